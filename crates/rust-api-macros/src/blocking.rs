@@ -0,0 +1,95 @@
+//! Blocking handler macro implementation
+//!
+//! Handles expansion of #[blocking] on a plain `fn` handler into an async
+//! handler that runs the original body on the app's `rust_api::cpu::CpuPool`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, FnArg, ItemFn, ReturnType};
+
+// move the body onto the CPU pool
+fn expand(mut func: ItemFn) -> syn::Result<TokenStream2> {
+    if let Some(asyncness) = func.sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "#[blocking] handlers must be plain `fn`; their body runs on a blocking thread",
+        ));
+    }
+    if let Some(FnArg::Receiver(receiver)) = func.sig.inputs.first() {
+        return Err(syn::Error::new_spanned(
+            receiver,
+            "#[blocking] applies to handler functions, not controller methods",
+        ));
+    }
+
+    let output = match &func.sig.output {
+        ReturnType::Type(_, ty) => quote!(#ty),
+        ReturnType::Default => quote!(()),
+    };
+    let pool: FnArg = syn::parse_quote! { __cpu_pool: ::rust_api::cpu::CpuPool };
+    func.sig.inputs.insert(0, pool);
+    func.sig.asyncness = Some(Default::default());
+    func.sig.output = syn::parse_quote! {
+        -> ::std::result::Result<#output, ::rust_api::ApiError>
+    };
+    let block = &func.block;
+    func.block = syn::parse_quote! {{
+        __cpu_pool.run(move || #block).await
+    }};
+
+    Ok(quote! {
+        //handler body running on the app's CPU pool
+        #func
+    })
+}
+
+/// Main expansion function for the blocking macro
+///
+/// This transforms:
+/// ```ignore
+/// #[blocking]
+/// fn render(Json(spec): Json<ReportSpec>) -> Vec<u8> { ... }
+/// ```
+///
+/// Into an async handler extracting the pool first:
+/// ```ignore
+/// async fn render(__cpu_pool: CpuPool, Json(spec): Json<ReportSpec>) -> Result<Vec<u8>, ApiError> {
+///     __cpu_pool.run(move || { ... }).await
+/// }
+/// ```
+pub fn expand_blocking(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = TokenStream2::from(args);
+        return syn::Error::new_spanned(args, "#[blocking] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let func = parse_macro_input!(input as ItemFn);
+    expand(func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_plain_fn() {
+        let func: ItemFn = syn::parse_quote! { async fn render() {} };
+        assert!(expand(func).is_err());
+        let func: ItemFn = syn::parse_quote! { fn render(&self) {} };
+        assert!(expand(func).is_err());
+
+        let func: ItemFn = syn::parse_quote! { fn render(body: String) -> usize { body.len() } };
+        let expanded = expand(func).unwrap().to_string();
+        assert!(
+            expanded.contains(
+                "async fn render (__cpu_pool : :: rust_api :: cpu :: CpuPool , body : String)"
+            ),
+            "{}",
+            expanded
+        );
+    }
+}
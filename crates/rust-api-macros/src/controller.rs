@@ -0,0 +1,253 @@
+//! Controller macro implementation
+//!
+//! Handles expansion of #[controller] on an impl block into a `router()`
+//! method serving every method annotated with a route macro.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Expr, FnArg, ImplItem, ImplItemFn, ItemImpl, LitStr, Type};
+
+use crate::{
+    guard::{guard_fn, take_guards},
+    pipe::take_pipes,
+    route::{validate_path, HttpMethod, RouteArgs},
+};
+
+/// A controller method served as a route
+struct ControllerRoute {
+    method: HttpMethod,
+    path: String,
+    handler: syn::Ident,
+    arg_types: Vec<Type>,
+    guard_list: Option<TokenStream2>,
+}
+
+// join the controller prefix and a route path
+fn join_path(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    match path {
+        "/" if !prefix.is_empty() => prefix.to_string(),
+        _ => format!("{}{}", prefix, path),
+    }
+}
+
+// take the route attribute off a method, if it has one
+fn take_route(
+    method: &mut ImplItemFn,
+    prefix: &str,
+    type_name: &str,
+    impl_guards: &[Expr],
+) -> syn::Result<Option<ControllerRoute>> {
+    let position = method.attrs.iter().position(|attr| {
+        attr.path()
+            .get_ident()
+            .and_then(|ident| HttpMethod::from_attribute(&ident.to_string()))
+            .is_some()
+    });
+    let Some(position) = position else {
+        return Ok(None);
+    };
+    let attr = method.attrs.remove(position);
+    let http_method = attr
+        .path()
+        .get_ident()
+        .and_then(|ident| HttpMethod::from_attribute(&ident.to_string()))
+        .expect("route attribute");
+    let args: RouteArgs = attr.parse_args()?;
+    let path = join_path(prefix, &args.path.value());
+    validate_path(&path).map_err(|msg| syn::Error::new(args.path.span(), msg))?;
+
+    take_pipes(&mut method.sig, &mut method.block)?;
+    let mut guards = impl_guards.to_vec();
+    guards.extend(take_guards(&mut method.attrs)?);
+    let guard_list = (!guards.is_empty()).then(|| {
+        let list = format_ident!("__{}_{}_guards", type_name, method.sig.ident);
+        guard_fn(&method.vis, &list, &mut method.sig, &guards)
+    });
+    let sig = &method.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "controller routes must be `async fn`",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "controller routes must not be generic",
+        ));
+    }
+    match sig.inputs.first() {
+        Some(FnArg::Receiver(receiver))
+            if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "controller routes must take `&self`",
+            ))
+        }
+    }
+    let arg_types = sig
+        .inputs
+        .iter()
+        .skip(1)
+        .filter_map(|input| match input {
+            FnArg::Typed(arg) => Some((*arg.ty).clone()),
+            FnArg::Receiver(_) => None,
+        })
+        .collect();
+
+    Ok(Some(ControllerRoute {
+        method: http_method,
+        path,
+        handler: sig.ident.clone(),
+        arg_types,
+        guard_list,
+    }))
+}
+
+/// Expand #[controller] on an impl block
+pub fn expand_controller(args: TokenStream, input: TokenStream) -> TokenStream {
+    let prefix = if args.is_empty() {
+        None
+    } else {
+        Some(parse_macro_input!(args as LitStr))
+    };
+    let mut item = parse_macro_input!(input as ItemImpl);
+    match expand(prefix, &mut item) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => {
+            let error = e.to_compile_error();
+            quote!(#item #error).into()
+        }
+    }
+}
+
+// strip the route attributes and generate the router
+fn expand(prefix: Option<LitStr>, item: &mut ItemImpl) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() || item.trait_.is_some() {
+        return Err(syn::Error::new_spanned(
+            &item.self_ty,
+            "#[controller] must be applied to a non-generic inherent impl block",
+        ));
+    }
+    let prefix = prefix.map(|p| p.value()).unwrap_or_default();
+    if !prefix.is_empty() && !prefix.starts_with('/') {
+        return Err(syn::Error::new_spanned(
+            &item.self_ty,
+            "controller prefix must start with `/`",
+        ));
+    }
+
+    // `#[guard]` below `#[controller]` guards every route of the controller
+    let impl_guards = take_guards(&mut item.attrs)?;
+    let self_ty = &item.self_ty;
+    let type_name = quote!(#self_ty).to_string().replace(' ', "");
+    let list_prefix: String = type_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let mut routes = Vec::new();
+    for impl_item in &mut item.items {
+        if let ImplItem::Fn(method) = impl_item {
+            if let Some(route) = take_route(method, &prefix, &list_prefix, &impl_guards)? {
+                routes.push(route);
+            }
+        }
+    }
+
+    // the table must be sorted the way `RouteTable::get` searches it
+    let mut table: Vec<_> = routes
+        .iter()
+        .map(|route| (route.path.as_str(), route.method.as_str(), &route.handler))
+        .collect();
+    table.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    if let Some(pair) = table
+        .windows(2)
+        .find(|pair| pair[0].0 == pair[1].0 && pair[0].1 == pair[1].1)
+    {
+        return Err(syn::Error::new_spanned(
+            pair[1].2,
+            format!("duplicate route {} {}", pair[1].1, pair[1].0),
+        ));
+    }
+
+    let self_ty = &item.self_ty;
+    let guard_lists = routes.iter().filter_map(|route| route.guard_list.as_ref());
+    let mut route_calls = Vec::new();
+    let mut registrations = Vec::new();
+    for route in &routes {
+        let path = &route.path;
+        let handler = &route.handler;
+        let axum_method = route.method.axum_method();
+        let method_name = route.method.as_str();
+        let handler_name = format!("{}::{}", type_name, handler);
+        let arg_types = &route.arg_types;
+        let args: Vec<_> = (0..arg_types.len())
+            .map(|i| format_ident!("arg{}", i))
+            .collect();
+
+        route_calls.push(quote! {
+            .route(#path, ::rust_api::routing::#axum_method({
+                let this = ::std::sync::Arc::clone(&self);
+                move |#(#args: #arg_types),*| async move { Self::#handler(&this, #(#args),*).await }
+            }))
+        });
+        registrations.push(quote! {
+            ::rust_api::registry::inventory::submit! {
+                ::rust_api::registry::RouteDef::new(
+                    #method_name,
+                    #path,
+                    concat!(module_path!(), "::", #handler_name),
+                )
+            }
+        });
+    }
+
+    let table_entries = table.iter().map(|(path, method, handler)| {
+        let handler_name = format!("{}::{}", type_name, handler);
+        quote! {
+            ::rust_api::registry::RouteDef::new(
+                #method,
+                #path,
+                concat!(module_path!(), "::", #handler_name),
+            )
+        }
+    });
+
+    Ok(quote! {
+        #item
+
+        impl #self_ty {
+            /// Routes of this controller, sorted by path and method
+            pub const ROUTES: ::rust_api::registry::RouteTable =
+                ::rust_api::registry::RouteTable::new(&[#(#table_entries),*]);
+
+            /// Router serving the routes of this controller
+            ///
+            /// Each handler holds its own `Arc` of the controller; a request
+            /// costs one reference-count increment and no state extraction.
+            pub fn router(self: ::std::sync::Arc<Self>) -> ::rust_api::Router {
+                ::rust_api::Router::new()
+                    #(#route_calls)*
+            }
+        }
+
+        #(#registrations)*
+        #(#guard_lists)*
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(join_path("", "/users"), "/users");
+        assert_eq!(join_path("/users", "/"), "/users");
+        assert_eq!(join_path("/users/", "/{id}"), "/users/{id}");
+        assert_eq!(join_path("", "/"), "/");
+    }
+}
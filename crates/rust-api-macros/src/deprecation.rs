@@ -0,0 +1,195 @@
+//! Route deprecation macro implementation
+//!
+//! Handles expansion of #[deprecated_route] into route registry metadata
+//! that makes the framework add `Deprecation` and `Sunset` headers.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse::Parser, parse_macro_input, ItemFn, LitStr};
+
+/// Options accepted by the deprecation macro
+#[derive(Default)]
+struct DeprecationArgs {
+    since: Option<i64>,
+    sunset: Option<String>,
+    link: Option<LitStr>,
+}
+
+impl DeprecationArgs {
+    // parse `since = "2025-01-01", sunset = "2026-01-01", link = "https://..."`
+    fn parse(args: proc_macro2::TokenStream) -> syn::Result<Self> {
+        let mut parsed = DeprecationArgs::default();
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("since") {
+                let date: LitStr = meta.value()?.parse()?;
+                let days =
+                    parse_date(&date.value()).map_err(|e| syn::Error::new(date.span(), e))?;
+                parsed.since = Some(days * 86_400);
+            } else if meta.path.is_ident("sunset") {
+                let date: LitStr = meta.value()?.parse()?;
+                let days =
+                    parse_date(&date.value()).map_err(|e| syn::Error::new(date.span(), e))?;
+                parsed.sunset = Some(http_date(days));
+            } else if meta.path.is_ident("link") {
+                parsed.link = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unsupported option; expected `since`, `sunset` or `link`"));
+            }
+            Ok(())
+        });
+        parser.parse2(args)?;
+        Ok(parsed)
+    }
+}
+
+// days since 1970-01-01 for a `YYYY-MM-DD` date
+fn parse_date(date: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid date `{}`; expected YYYY-MM-DD", date);
+    let mut parts = date.split('-');
+    let (Some(y), Some(m), Some(d), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let (y, m, d): (i64, i64, i64) = (
+        y.parse().map_err(|_| invalid())?,
+        m.parse().map_err(|_| invalid())?,
+        d.parse().map_err(|_| invalid())?,
+    );
+    let leap = (y % 4 == 0 && y % 100 != 0) || y % 400 == 0;
+    let month_days = [
+        31,
+        if leap { 29 } else { 28 },
+        31,
+        30,
+        31,
+        30,
+        31,
+        31,
+        30,
+        31,
+        30,
+        31,
+    ];
+    if !(1..=12).contains(&m) || d < 1 || d > month_days[(m - 1) as usize] {
+        return Err(invalid());
+    }
+
+    // days-from-civil (Howard Hinnant)
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok(era * 146_097 + doe - 719_468)
+}
+
+// IMF-fixdate for midnight UTC of a day since the epoch
+fn http_date(days: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    // civil-from-days (Howard Hinnant)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} 00:00:00 GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year
+    )
+}
+
+/// Main expansion function for the deprecation macro
+///
+/// This transforms:
+/// ```ignore
+/// #[deprecated_route(sunset = "2026-01-01", link = "https://example.com/v2")]
+/// #[get("/v1/users")]
+/// async fn list_users_v1() -> Json<Vec<User>> { ... }
+/// ```
+///
+/// Into the unchanged handler plus a route registry entry:
+/// ```ignore
+/// inventory::submit! {
+///     RouteMetadata::new("my_app::list_users_v1", |meta| {
+///         meta.insert(Deprecated::new().sunset("Thu, 01 Jan 2026 00:00:00 GMT").link("https://example.com/v2"))
+///     })
+/// }
+/// ```
+pub fn expand_deprecated_route(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = match DeprecationArgs::parse(args.into()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let func = parse_macro_input!(input as ItemFn);
+    let handler_name = func.sig.ident.to_string();
+
+    let mut deprecated = quote! { ::rust_api::deprecation::Deprecated::new() };
+    if let Some(since) = args.since {
+        deprecated = quote! { #deprecated.since(#since) };
+    }
+    if let Some(sunset) = args.sunset {
+        deprecated = quote! { #deprecated.sunset(#sunset) };
+    }
+    if let Some(link) = args.link {
+        deprecated = quote! { #deprecated.link(#link) };
+    }
+
+    let expanded = quote! {
+        #func
+
+        //route registry metadata - picked up by the deprecation middleware
+        ::rust_api::registry::inventory::submit! {
+            ::rust_api::registry::RouteMetadata::new(
+                concat!(module_path!(), "::", #handler_name),
+                |meta| meta.insert(#deprecated),
+            )
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2026-01-01"), Ok(20_454));
+        assert!(parse_date("2026-02-29").is_err());
+        assert!(parse_date("2024-02-29").is_ok());
+        assert!(parse_date("01-01-2026").is_err());
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(20_454), "Thu, 01 Jan 2026 00:00:00 GMT");
+        assert_eq!(
+            http_date(parse_date("2024-02-29").unwrap()),
+            "Thu, 29 Feb 2024 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args =
+            DeprecationArgs::parse(quote! { since = "1970-01-02", link = "https://x" }).unwrap();
+        assert_eq!(args.since, Some(86_400));
+        assert!(args.sunset.is_none());
+        assert!(DeprecationArgs::parse(quote! { until = "2026-01-01" }).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Entrypoint macro implementation
+//!
+//! Handles expansion of #[rust_api::main] into a synchronous `main` that
+//! starts the boot report, loads `.env` files, builds a tuned Tokio runtime
+//! and blocks on the async body.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse::Parser, parse_macro_input, ItemFn, LitBool, LitInt, LitStr};
+
+/// Runtime options accepted by the entrypoint macro
+#[derive(Default)]
+pub struct MainArgs {
+    workers: Option<LitInt>,
+    blocking_threads: Option<LitInt>,
+    thread_name: Option<LitStr>,
+    dotenv: Option<LitBool>,
+    console: Option<LitBool>,
+}
+
+impl MainArgs {
+    // parse `workers = 4, blocking_threads = 64, thread_name = "api"`
+    fn parse(args: proc_macro2::TokenStream) -> syn::Result<Self> {
+        let mut parsed = MainArgs::default();
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("workers") {
+                parsed.workers = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("blocking_threads") {
+                parsed.blocking_threads = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("thread_name") {
+                parsed.thread_name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("dotenv") {
+                parsed.dotenv = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("console") {
+                parsed.console = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error(
+                    "unsupported option; expected `workers`, `blocking_threads`, `thread_name`, \
+                     `dotenv` or `console`",
+                ));
+            }
+            Ok(())
+        });
+        parser.parse2(args)?;
+        Ok(parsed)
+    }
+
+    // generate the RuntimeConfig builder chain for the given options
+    fn runtime_config(&self) -> proc_macro2::TokenStream {
+        let mut config = quote! { ::rust_api::runtime::RuntimeConfig::new() };
+        if let Some(workers) = &self.workers {
+            config = quote! { #config.worker_threads(#workers) };
+        }
+        if let Some(count) = &self.blocking_threads {
+            config = quote! { #config.max_blocking_threads(#count) };
+        }
+        if let Some(name) = &self.thread_name {
+            config = quote! { #config.thread_name(#name) };
+        }
+        if let Some(console) = &self.console {
+            config = quote! { #config.console(#console) };
+        }
+        config
+    }
+
+    // load `.env` files unless disabled with `dotenv = false`
+    fn dotenv(&self) -> proc_macro2::TokenStream {
+        if self.dotenv.as_ref().is_some_and(|enabled| !enabled.value) {
+            return quote! {};
+        }
+        quote! {
+            ::rust_api::config::load_dotenv().expect("Failed to load .env files");
+        }
+    }
+}
+
+/// Main expansion function for the entrypoint macro
+///
+/// This transforms:
+/// ```ignore
+/// #[rust_api::main(workers = 4)]
+/// async fn main() { ... }
+/// ```
+///
+/// Into:
+/// ```ignore
+/// fn main() {
+///     boot::start();
+///     load_dotenv().expect("Failed to load .env files");
+///     RuntimeConfig::new().worker_threads(4).build().unwrap().block_on(async { ... })
+/// }
+/// ```
+pub fn expand_main_macro(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = match MainArgs::parse(args.into()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let mut func = parse_macro_input!(input as ItemFn);
+    if func.sig.asyncness.take().is_none() {
+        return syn::Error::new_spanned(func.sig.fn_token, "#[main] requires an async fn")
+            .to_compile_error()
+            .into();
+    }
+
+    let attrs = &func.attrs;
+    let vis = &func.vis;
+    let sig = &func.sig;
+    let body = &func.block;
+    let config = args.runtime_config();
+    let dotenv = args.dotenv();
+
+    let expanded = quote! {
+        #(#attrs)*
+        #vis #sig {
+            ::rust_api::boot::start();
+            #dotenv
+            #config
+                .build()
+                .expect("Failed to build Tokio runtime")
+                .block_on(async move #body)
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_main_args() {
+        let args = MainArgs::parse(quote! { workers = 4, thread_name = "api" }).unwrap();
+        assert!(!args.dotenv().is_empty());
+        assert_eq!(args.workers.unwrap().base10_digits(), "4");
+        assert_eq!(args.thread_name.unwrap().value(), "api");
+        assert!(args.blocking_threads.is_none());
+    }
+
+    #[test]
+    fn test_parse_main_args_with_console() {
+        let args = MainArgs::parse(quote! { console = true }).unwrap();
+        assert!(args.runtime_config().to_string().contains("console (true)"));
+    }
+
+    #[test]
+    fn test_parse_main_args_without_dotenv() {
+        let args = MainArgs::parse(quote! { dotenv = false }).unwrap();
+        assert!(args.dotenv().is_empty());
+    }
+
+    #[test]
+    fn test_parse_main_args_unknown_option() {
+        assert!(MainArgs::parse(quote! { flavor = "current_thread" }).is_err());
+    }
+}
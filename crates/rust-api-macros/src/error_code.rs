@@ -0,0 +1,228 @@
+//! Error code derive implementation
+//!
+//! Handles expansion of #[derive(ErrorCode)] into an `ErrorCode` impl, a
+//! `From` conversion into `ApiError` and one error catalog entry per
+//! variant.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr, Variant};
+
+/// Catalog settings of one enum variant
+struct CodeSpec {
+    code: String,
+    status: u16,
+    message: String,
+    docs: Option<String>,
+}
+
+// convert a variant name like `CardDeclined` to `card_declined`, keeping
+// acronyms together (`HTTPError` to `http_error`)
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let after_word = i > 0 && !chars[i - 1].is_ascii_uppercase();
+            let ends_acronym = i > 0
+                && chars[i - 1].is_ascii_uppercase()
+                && chars.get(i + 1).is_some_and(char::is_ascii_lowercase);
+            if after_word || ends_acronym {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// the variant's doc comment joined into one line
+fn doc_comment(variant: &Variant) -> Option<String> {
+    let lines: Vec<String> = variant
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(nv) => match &nv.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+// read the `#[error_code(...)]` attribute of a variant
+fn code_spec(variant: &Variant) -> syn::Result<CodeSpec> {
+    let mut code = None;
+    let mut status = None;
+    let mut message = None;
+    let mut docs = None;
+    for attr in variant
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("error_code"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("code") {
+                code = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("status") {
+                let lit: LitInt = meta.value()?.parse()?;
+                let value: u16 = lit.base10_parse()?;
+                if !(100..=599).contains(&value) {
+                    return Err(syn::Error::new_spanned(lit, "status must be 100-599"));
+                }
+                status = Some(value);
+            } else if meta.path.is_ident("message") {
+                message = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("docs") {
+                docs = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                return Err(meta
+                    .error("unsupported option; expected `code`, `status`, `message` or `docs`"));
+            }
+            Ok(())
+        })?;
+    }
+    let status = status.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &variant.ident,
+            "missing #[error_code(status = ...)] on variant",
+        )
+    })?;
+    let code = code.unwrap_or_else(|| snake_case(&variant.ident.to_string()));
+    let message = message
+        .or_else(|| doc_comment(variant))
+        .unwrap_or_else(|| code.clone());
+    Ok(CodeSpec {
+        code,
+        status,
+        message,
+        docs,
+    })
+}
+
+// generate the impls for a parsed enum
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(ErrorCode)] only supports enums",
+        ));
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut patterns = Vec::new();
+    let mut specs = Vec::new();
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        patterns.push(match &variant.fields {
+            Fields::Unit => quote! { Self::#ident },
+            Fields::Unnamed(_) => quote! { Self::#ident(..) },
+            Fields::Named(_) => quote! { Self::#ident { .. } },
+        });
+        specs.push(code_spec(variant)?);
+    }
+
+    let codes: Vec<_> = specs.iter().map(|s| &s.code).collect();
+    let statuses: Vec<_> = specs.iter().map(|s| s.status).collect();
+    let messages: Vec<_> = specs.iter().map(|s| &s.message).collect();
+    let docs: Vec<_> = specs
+        .iter()
+        .map(|s| match &s.docs {
+            Some(url) => quote! { ::core::option::Option::Some(#url) },
+            None => quote! { ::core::option::Option::None },
+        })
+        .collect();
+
+    Ok(quote! {
+        impl #impl_generics ::rust_api::codes::ErrorCode for #name #ty_generics #where_clause {
+            fn code(&self) -> &'static str {
+                match self {
+                    #(#patterns => #codes,)*
+                }
+            }
+
+            fn status(&self) -> ::rust_api::StatusCode {
+                let status = match self {
+                    #(#patterns => #statuses,)*
+                };
+                ::rust_api::StatusCode::from_u16(status).expect("status validated by the derive")
+            }
+
+            fn message(&self) -> &'static str {
+                match self {
+                    #(#patterns => #messages,)*
+                }
+            }
+
+            fn docs_url(&self) -> ::core::option::Option<&'static str> {
+                match self {
+                    #(#patterns => #docs,)*
+                }
+            }
+        }
+
+        impl #impl_generics ::core::convert::From<#name #ty_generics> for ::rust_api::ApiError #where_clause {
+            fn from(error: #name #ty_generics) -> Self {
+                ::rust_api::ApiError::from_code(&error)
+            }
+        }
+
+        #(
+            //error catalog entry - served by App::error_catalog and OpenAPI
+            ::rust_api::registry::inventory::submit! {
+                ::rust_api::codes::ErrorCodeInfo::new(#codes, #statuses, #messages, #docs)
+            }
+        )*
+    })
+}
+
+/// Main expansion function for the error code derive
+pub fn expand_error_code(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("CardDeclined"), "card_declined");
+        assert_eq!(snake_case("Timeout"), "timeout");
+        assert_eq!(snake_case("HTTPError"), "http_error");
+        assert_eq!(snake_case("UpstreamHTTP"), "upstream_http");
+        assert_eq!(snake_case("Oauth2Expired"), "oauth2_expired");
+    }
+
+    #[test]
+    fn test_code_spec() {
+        let variant: Variant = syn::parse_quote! {
+            /// The card was
+            /// declined
+            #[error_code(status = 402)]
+            CardDeclined
+        };
+        let spec = code_spec(&variant).unwrap();
+        assert_eq!(spec.code, "card_declined");
+        assert_eq!(spec.message, "The card was declined");
+
+        let variant: Variant = syn::parse_quote! { #[error_code(code = "x")] Missing };
+        assert!(code_spec(&variant).is_err());
+        let variant: Variant = syn::parse_quote! { #[error_code(status = 99)] Low };
+        assert!(code_spec(&variant).is_err());
+    }
+}
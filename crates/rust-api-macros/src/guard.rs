@@ -0,0 +1,167 @@
+//! Guard macro implementation
+//!
+//! Handles expansion of #[guard(...)] into a guard list type and a leading
+//! `rust_api::guard::Guarded` parameter that runs the guards before every
+//! other extractor of the handler.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse::Parser, punctuated::Punctuated, Attribute, Expr, FnArg, Ident, ItemFn, Signature, Token,
+    Visibility,
+};
+
+// parse `expr, expr, ...`
+fn parse_guards(tokens: TokenStream2) -> syn::Result<Vec<Expr>> {
+    let guards = Punctuated::<Expr, Token![,]>::parse_terminated.parse2(tokens)?;
+    Ok(guards.into_iter().collect())
+}
+
+/// Remove the `#[guard(...)]` attributes, returning their guards in order
+pub fn take_guards(attrs: &mut Vec<Attribute>) -> syn::Result<Vec<Expr>> {
+    let mut guards = Vec::new();
+    let mut error = None;
+    attrs.retain(|attr| {
+        if !attr.path().is_ident("guard") {
+            return true;
+        }
+        match attr
+            .meta
+            .require_list()
+            .and_then(|list| parse_guards(list.tokens.clone()))
+        {
+            Ok(parsed) => guards.extend(parsed),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+        false
+    });
+    match error {
+        Some(e) => Err(e),
+        None => Ok(guards),
+    }
+}
+
+/// Guard the function with `guards`
+///
+/// Adds the `Guarded<list>` parameter to `sig`, first after any receiver,
+/// and returns the list type to emit next to the function.
+pub fn guard_fn(
+    vis: &Visibility,
+    list: &Ident,
+    sig: &mut Signature,
+    guards: &[Expr],
+) -> TokenStream2 {
+    let param: FnArg = syn::parse_quote! { _: ::rust_api::guard::Guarded<#list> };
+    let position = match sig.inputs.first() {
+        Some(FnArg::Receiver(_)) => 1,
+        _ => 0,
+    };
+    sig.inputs.insert(position, param);
+
+    quote! {
+        //guard list - evaluated once, on the first request
+        #[doc(hidden)]
+        #[allow(non_camel_case_types)]
+        #vis struct #list;
+
+        impl ::rust_api::guard::GuardList for #list {
+            fn guards() -> &'static [::std::sync::Arc<dyn ::rust_api::guard::Guard>] {
+                static GUARDS: ::std::sync::OnceLock<
+                    ::std::vec::Vec<::std::sync::Arc<dyn ::rust_api::guard::Guard>>,
+                > = ::std::sync::OnceLock::new();
+                GUARDS.get_or_init(|| {
+                    ::std::vec![#(
+                        ::std::sync::Arc::new(#guards)
+                            as ::std::sync::Arc<dyn ::rust_api::guard::Guard>
+                    ),*]
+                })
+            }
+        }
+    }
+}
+
+// generate the guarded function
+fn expand(args: TokenStream2, mut func: ItemFn) -> syn::Result<TokenStream2> {
+    let mut guards = parse_guards(args)?;
+    guards.extend(take_guards(&mut func.attrs)?);
+    if guards.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &func.sig.ident,
+            "#[guard] needs at least one guard",
+        ));
+    }
+    let list = Ident::new(
+        &format!("__{}_guards", func.sig.ident),
+        func.sig.ident.span(),
+    );
+    let list_item = guard_fn(&func.vis, &list, &mut func.sig, &guards);
+    Ok(quote! {
+        #func
+        #list_item
+    })
+}
+
+/// Main expansion function for the guard macro
+///
+/// This transforms:
+/// ```ignore
+/// #[guard(require_api_key)]
+/// async fn list_users() -> Json<Vec<User>> { ... }
+/// ```
+///
+/// Into the handler with a leading guard extractor plus its guard list:
+/// ```ignore
+/// async fn list_users(_: Guarded<__list_users_guards>) -> Json<Vec<User>> { ... }
+/// struct __list_users_guards;
+/// impl GuardList for __list_users_guards { ... }
+/// ```
+pub fn expand_guard(args: TokenStream, input: TokenStream) -> TokenStream {
+    let func = match syn::parse::<ItemFn>(input) {
+        Ok(func) => func,
+        Err(e) => {
+            let message = "#[guard] applies to handler functions; \
+                           on a controller, place it below #[controller]";
+            let mut error = syn::Error::new(e.span(), message);
+            error.combine(e);
+            return error.to_compile_error().into();
+        }
+    };
+    expand(args.into(), func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_guards() {
+        let mut func: ItemFn = syn::parse_quote! {
+            #[guard(a, b::C)]
+            #[inline]
+            #[guard(D("x"))]
+            async fn handler() {}
+        };
+        let guards = take_guards(&mut func.attrs).unwrap();
+        assert_eq!(guards.len(), 3);
+        assert_eq!(func.attrs.len(), 1);
+
+        let mut func: ItemFn = syn::parse_quote! { #[guard = "a"] async fn handler() {} };
+        assert!(take_guards(&mut func.attrs).is_err());
+    }
+
+    #[test]
+    fn test_guard_param_follows_receiver() {
+        let mut sig: Signature = syn::parse_quote! { async fn show(&self, id: Path<u64>) };
+        let list = Ident::new("__show_guards", proc_macro2::Span::call_site());
+        guard_fn(&Visibility::Inherited, &list, &mut sig, &[]);
+        let expected: Signature = syn::parse_quote! {
+            async fn show(&self, _: ::rust_api::guard::Guarded<__show_guards>, id: Path<u64>)
+        };
+        assert_eq!(quote!(#sig).to_string(), quote!(#expected).to_string());
+    }
+}
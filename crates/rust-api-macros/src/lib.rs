@@ -1,23 +1,52 @@
 //! Procedural macros for rust-api framework
 //!
 //! Provides route macros like #[get], #[post], etc. for defining HTTP endpoints
-//! in a FastAPI-style syntax.
+//! in a FastAPI-style syntax, #[controller] impl blocks, the #[main]
+//! application entrypoint, the MapFrom derive for DTO conversions, the
+//! ErrorCode derive for error catalogs, #[mockable] test doubles, and
+//! #[retry] policies for service methods.
 
 use proc_macro::TokenStream;
 
+mod blocking;
+mod controller;
+mod deprecation;
+mod entry;
+mod error_code;
+mod guard;
+mod map_from;
+mod mock;
+mod pipe;
+mod priority;
+mod read_only;
+mod repository;
+mod retry;
 mod route;
+mod websocket;
 
 use route::HttpMethod;
 
 /// Define a GET route handler
 ///
+/// The path is validated at compile time. Captures use `{name}`; a final
+/// `{*name}` segment captures the rest of the path (see `rust_api::Rest`).
+///
+/// `Json`, `Query` and `Path` parameters of any route macro accept
+/// `#[pipe(...)]`, running the listed `rust_api::pipe::Pipe`s in order on
+/// the raw value before it is deserialized.
+///
 /// # Example
 ///
 /// ```ignore
-/// #[get("/users/:id")]
+/// #[get("/users/{id}")]
 /// async fn get_user(path: Path<String>) -> Json<User> {
 ///     // handler code
 /// }
+///
+/// #[get("/users/{id}/avatar")]
+/// async fn get_avatar(#[pipe(TrimStrings, ParseUuid)] Path(id): Path<String>) -> Bytes {
+///     // handler code
+/// }
 /// ```
 #[proc_macro_attribute]
 pub fn get(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -44,7 +73,7 @@ pub fn post(args: TokenStream, input: TokenStream) -> TokenStream {
 /// # Example
 ///
 /// ```ignore
-/// #[put("/users/:id")]
+/// #[put("/users/{id}")]
 /// async fn update_user(path: Path<String>, body: Json<User>) -> Json<User> {
 ///     // handler code
 /// }
@@ -59,7 +88,7 @@ pub fn put(args: TokenStream, input: TokenStream) -> TokenStream {
 /// # Example
 ///
 /// ```ignore
-/// #[delete("/users/:id")]
+/// #[delete("/users/{id}")]
 /// async fn delete_user(path: Path<String>) -> StatusCode {
 ///     // handler code
 /// }
@@ -74,7 +103,7 @@ pub fn delete(args: TokenStream, input: TokenStream) -> TokenStream {
 /// # Example
 ///
 /// ```ignore
-/// #[patch("/users/:id")]
+/// #[patch("/users/{id}")]
 /// async fn patch_user(path: Path<String>, body: Json<UserPatch>) -> Json<User> {
 ///     // handler code
 /// }
@@ -83,3 +112,319 @@ pub fn delete(args: TokenStream, input: TokenStream) -> TokenStream {
 pub fn patch(args: TokenStream, input: TokenStream) -> TokenStream {
     route::expand_route_macro(HttpMethod::Patch, args, input)
 }
+
+/// Define a WebSocket route
+///
+/// The function returns the `rust_api::ws::Gateway` serving the connection;
+/// its parameters are extracted from the upgrade request like those of any
+/// handler, and `#[guard(...)]` rejects the request before the upgrade.
+/// Requires the `ws` feature of `rust-api`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[guard(TokenAuth::new(verify_token))]
+/// #[websocket("/chat")]
+/// async fn chat(Inject(rooms): Inject<Rooms>) -> ChatGateway {
+///     ChatGateway::new(rooms)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn websocket(args: TokenStream, input: TokenStream) -> TokenStream {
+    websocket::expand_websocket(args, input)
+}
+
+/// Mark a route handler as deprecated
+///
+/// Responses from the route carry `Deprecation`, `Sunset` and `Link` headers
+/// and hits are counted in the app's metrics. Dates are `YYYY-MM-DD` (UTC);
+/// all options are optional.
+///
+/// # Example
+///
+/// ```ignore
+/// #[deprecated_route(since = "2025-06-01", sunset = "2026-01-01", link = "https://example.com/migrate")]
+/// #[get("/v1/users")]
+/// async fn list_users_v1() -> Json<Vec<User>> {
+///     // handler code
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn deprecated_route(args: TokenStream, input: TokenStream) -> TokenStream {
+    deprecation::expand_deprecated_route(args, input)
+}
+
+/// Guard a route handler
+///
+/// Each argument is an expression evaluating to a `rust_api::Guard`; the
+/// guards run in order, after all middleware and before the handler's
+/// extractors, and the first rejection is returned. On a controller, place
+/// `#[guard]` on a method or below `#[controller]` to guard every route.
+///
+/// # Example
+///
+/// ```ignore
+/// #[guard(require_api_key, RequireFeature("beta"))]
+/// #[get("/beta/reports")]
+/// async fn reports() -> Json<Vec<Report>> {
+///     // handler code
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn guard(args: TokenStream, input: TokenStream) -> TokenStream {
+    guard::expand_guard(args, input)
+}
+
+/// Serve the route methods of an impl block as a controller
+///
+/// Methods annotated with `#[get]`, `#[post]`, etc. must be `async fn`
+/// taking `&self` followed by any axum extractors. The macro adds
+/// `router(self: Arc<Self>) -> Router`; handlers capture the controller
+/// once, so its dependencies are plain fields rather than `State`
+/// extractions. An optional argument prefixes every path.
+///
+/// # Example
+///
+/// ```ignore
+/// struct UserController {
+///     users: Arc<UserService>,
+/// }
+///
+/// #[controller("/users")]
+/// impl UserController {
+///     #[get("/{id}")]
+///     async fn get(&self, Path(id): Path<u64>) -> Json<User> {
+///         Json(self.users.find(id))
+///     }
+/// }
+///
+/// let app = App::new().merge(Arc::new(UserController { users }).router());
+/// ```
+#[proc_macro_attribute]
+pub fn controller(args: TokenStream, input: TokenStream) -> TokenStream {
+    controller::expand_controller(args, input)
+}
+
+/// Define the application entrypoint with a tunable Tokio runtime
+///
+/// Accepts optional `workers`, `blocking_threads` and `thread_name` settings;
+/// omitted settings keep Tokio's defaults. `.env.local` and `.env` are loaded
+/// into the environment before the runtime starts unless `dotenv = false`.
+/// `console = true` enables tokio-console instrumentation (requires the
+/// `console` feature; see `RuntimeConfig::console`).
+///
+/// # Example
+///
+/// ```ignore
+/// #[rust_api::main(workers = 4, thread_name = "api")]
+/// async fn main() {
+///     RustAPI::new(app).serve().await.unwrap();
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, input: TokenStream) -> TokenStream {
+    entry::expand_main_macro(args, input)
+}
+
+/// Route a handler's database reads to replicas
+///
+/// While the handler runs, `Db::conn()` returns a replica connection instead
+/// of the primary.
+///
+/// # Example
+///
+/// ```ignore
+/// #[read_only]
+/// #[get("/reports")]
+/// async fn reports(Inject(db): Inject<Db<DatabaseConnection>>) -> Json<Report> {
+///     // db.conn() is a replica here
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn read_only(args: TokenStream, input: TokenStream) -> TokenStream {
+    read_only::expand_read_only(args, input)
+}
+
+/// Set how important a route is to keep serving under load
+///
+/// Takes `low`, `normal` (the default for unmarked routes), `high` or
+/// `critical`. With `App::load_shedding`, low priority routes are rejected
+/// with 503 as soon as the server is saturated and normal ones when it is
+/// badly saturated; high and critical routes are always served. Place it
+/// above the route macro.
+///
+/// # Example
+///
+/// ```ignore
+/// #[priority(low)]
+/// #[get("/reports/yearly")]
+/// async fn yearly_report() -> Json<Report> { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn priority(args: TokenStream, input: TokenStream) -> TokenStream {
+    priority::expand_priority(args, input)
+}
+
+/// Run a CPU-heavy handler on the app's `CpuPool`
+///
+/// The handler is written as a plain `fn`; its body runs on a blocking
+/// thread once the pool has room, so it does not stall other requests.
+/// The handler's response becomes `Result<T, ApiError>`, failing with 503
+/// while the pool's queue is full. Place it above the route macro.
+///
+/// # Example
+///
+/// ```ignore
+/// #[blocking]
+/// #[post("/reports/render")]
+/// fn render(Json(spec): Json<ReportSpec>) -> Vec<u8> {
+///     pdf::render(&spec)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn blocking(args: TokenStream, input: TokenStream) -> TokenStream {
+    blocking::expand_blocking(args, input)
+}
+
+/// Retry an async function or `&self` service method on transient errors
+///
+/// Accepts `attempts` (default 3, including the first), `backoff`
+/// (`"exponential"`, the default, or `"fixed"`), `delay_ms` before the first
+/// retry (default 100), `on`, the required list of transient error
+/// patterns to retry, `name`, the operation name in logs and metrics
+/// (default: the function's path), `jitter` (default true) and `metrics`,
+/// an expression such as `self.metrics` giving the `Metrics` to count
+/// retries in. The function must return a `Result`; its arguments are
+/// cloned for every attempt. See `rust_api::retry`.
+///
+/// # Example
+///
+/// ```ignore
+/// impl UserService {
+///     #[retry(attempts = 3, backoff = "exponential", on = [sqlx::Error::PoolTimedOut])]
+///     pub async fn find(&self, id: Uuid) -> Result<User, sqlx::Error> {
+///         sqlx::query_as("SELECT * FROM users WHERE id = $1").bind(id).fetch_one(&self.pool).await
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn retry(args: TokenStream, input: TokenStream) -> TokenStream {
+    retry::expand_retry(args, input)
+}
+
+/// Derive `From<Entity>` for a response DTO
+///
+/// Fields are moved from the same-named source field and converted with
+/// `Into`. Per field, `rename = "..."` reads a differently named source
+/// field, `with = "path::to::fn"` computes the value from `&Source`, and
+/// `skip` uses `Default::default()`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Serialize, MapFrom)]
+/// #[map_from(UserEntity)]
+/// struct UserResponse {
+///     id: String,
+///     #[map_from(rename = "email_address")]
+///     email: String,
+///     #[map_from(with = "display_name")]
+///     name: String,
+///     #[map_from(skip)]
+///     links: Vec<Link>,
+/// }
+///
+/// fn display_name(user: &UserEntity) -> String {
+///     format!("{} {}", user.first_name, user.last_name)
+/// }
+/// ```
+#[proc_macro_derive(MapFrom, attributes(map_from))]
+pub fn map_from(input: TokenStream) -> TokenStream {
+    map_from::expand_map_from(input)
+}
+
+/// Derive an `ErrorCode` impl and catalog entries for an error enum
+///
+/// Every variant needs `#[error_code(status = ...)]`. The code defaults to
+/// the variant name in snake case (override with `code = "..."`), the
+/// message to the variant's doc comment (override with `message = "..."`),
+/// and `docs = "..."` links to documentation. A `From` impl converts values
+/// into `ApiError`, and each code is listed in the catalog served by
+/// `App::error_catalog` and in the OpenAPI document.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Debug, ErrorCode)]
+/// enum BillingError {
+///     /// The card was declined by the issuer
+///     #[error_code(status = 402, docs = "https://docs.example.com/errors/card_declined")]
+///     CardDeclined,
+///     #[error_code(code = "plan_limit", status = 403, message = "Plan limit reached")]
+///     PlanLimitReached { limit: u32 },
+/// }
+/// ```
+#[proc_macro_derive(ErrorCode, attributes(error_code))]
+pub fn error_code(input: TokenStream) -> TokenStream {
+    error_code::expand_error_code(input)
+}
+
+/// Derive an sqlx-backed `Repository` for an entity
+///
+/// Generates `{Entity}Repository` (or `name = "..."`) wrapping an sqlx pool
+/// and implementing `rust_api::repository::Repository<Entity, Id>`. The
+/// entity must implement `sqlx::FromRow`; its columns are its field names.
+/// The id is the field named `id` or marked `#[repository(id)]`, and
+/// `#[repository(generated)]` fields are left to the database on insert and
+/// never updated. Requires the `sqlx-postgres` or `sqlx-sqlite` feature.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Clone, sqlx::FromRow, Repository)]
+/// #[repository(table = "users", backend = "postgres")]
+/// pub struct User {
+///     #[repository(id, generated)]
+///     pub id: i64,
+///     pub name: String,
+/// }
+///
+/// let users = UserRepository::new(pool);
+/// let page = users.list(Pagination::new(1, 20)).await?;
+/// ```
+#[proc_macro_derive(Repository, attributes(repository))]
+pub fn repository(input: TokenStream) -> TokenStream {
+    repository::expand_repository(input)
+}
+
+/// Generate a `Mock<Trait>` test double for a trait
+///
+/// The mock records every call in a `rust_api::testing::CallLog` and answers
+/// each method from a closure set with `on_<method>`. Methods must take
+/// `&self` (or `&mut self`) and must not be generic; `async fn` methods are
+/// supported. Register the mock behind the trait object with
+/// `TestContainer::with_mock`. Calls are recorded with their arguments, and
+/// `CallLog::called_with` checks them.
+///
+/// The mock is only compiled under `cfg(test)` or with a `testing` feature
+/// of the crate declaring the trait, which integration tests in `tests/`
+/// enable, so it never ships in production builds.
+///
+/// # Example
+///
+/// ```ignore
+/// #[mockable]
+/// pub trait UserRepo: Send + Sync {
+///     fn find(&self, id: u64) -> Option<User>;
+/// }
+/// impl Injectable for dyn UserRepo {}
+///
+/// let repo = Arc::new(MockUserRepo::new().on_find(|id| Some(User::fixture(id))));
+/// let container = TestContainer::new().with_mock::<dyn UserRepo>(repo.clone());
+/// // ... exercise the app ...
+/// assert!(repo.calls().called_with("find", &["7"]));
+/// ```
+#[proc_macro_attribute]
+pub fn mockable(_args: TokenStream, input: TokenStream) -> TokenStream {
+    mock::expand_mockable(input)
+}
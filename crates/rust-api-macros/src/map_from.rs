@@ -0,0 +1,192 @@
+//! DTO mapping derive implementation
+//!
+//! Handles expansion of #[derive(MapFrom)] into `From<Source>` impls that
+//! copy fields from a persistence entity into a response DTO.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, punctuated::Punctuated, Data, DeriveInput, Fields, Ident, LitStr, Path,
+    Token,
+};
+
+/// How a single DTO field is filled in
+enum FieldSource {
+    /// Moved from the source field with the given name, converted with `Into`
+    Field(Ident),
+    /// Computed by a function taking `&Source`
+    With(Path),
+    /// Filled with `Default::default()`
+    Skip,
+}
+
+// read the `#[map_from(...)]` attribute of a field
+fn field_source(field: &syn::Field) -> syn::Result<FieldSource> {
+    let ident = field.ident.clone().expect("named field");
+    let mut source = FieldSource::Field(ident);
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("map_from")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                source = FieldSource::Skip;
+            } else if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                source = FieldSource::Field(name.parse()?);
+            } else if meta.path.is_ident("with") {
+                let path: LitStr = meta.value()?.parse()?;
+                source = FieldSource::With(path.parse()?);
+            } else {
+                return Err(meta.error("unsupported option; expected `rename`, `with` or `skip`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(source)
+}
+
+// read the source types from the struct-level `#[map_from(A, B)]`
+fn source_types(input: &DeriveInput) -> syn::Result<Vec<Path>> {
+    let mut sources = Vec::new();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("map_from")) {
+        let paths = attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)?;
+        sources.extend(paths);
+    }
+    if sources.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(MapFrom)] requires #[map_from(SourceType)]",
+        ));
+    }
+    Ok(sources)
+}
+
+// generate the From impls for a parsed struct
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(MapFrom)] only supports structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(MapFrom)] requires named fields",
+        ));
+    };
+
+    let target = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    // computed fields run first, while the source is still borrowable
+    let mut computed = Vec::new();
+    let mut inits = Vec::new();
+    for field in &fields.named {
+        let name = field.ident.as_ref().expect("named field");
+        match field_source(field)? {
+            FieldSource::Field(from) => {
+                inits.push(quote! { #name: ::core::convert::Into::into(source.#from) });
+            }
+            FieldSource::With(func) => {
+                computed.push(quote! { let #name = #func(&source); });
+                inits.push(quote! { #name });
+            }
+            FieldSource::Skip => {
+                inits.push(quote! { #name: ::core::default::Default::default() });
+            }
+        }
+    }
+
+    let impls = source_types(input)?.into_iter().map(|source_type| {
+        quote! {
+            impl #impl_generics ::core::convert::From<#source_type> for #target #ty_generics #where_clause {
+                #[allow(unused_variables)]
+                fn from(source: #source_type) -> Self {
+                    #(#computed)*
+                    Self { #(#inits,)* }
+                }
+            }
+        }
+    });
+    Ok(quote! { #(#impls)* })
+}
+
+/// Main expansion function for the MapFrom derive
+///
+/// This transforms:
+/// ```ignore
+/// #[derive(MapFrom)]
+/// #[map_from(UserEntity)]
+/// struct UserResponse {
+///     id: String,
+///     #[map_from(rename = "email_address")]
+///     email: String,
+///     #[map_from(with = "display_name")]
+///     name: String,
+/// }
+/// ```
+///
+/// Into:
+/// ```ignore
+/// impl From<UserEntity> for UserResponse {
+///     fn from(source: UserEntity) -> Self {
+///         let name = display_name(&source);
+///         Self { id: source.id.into(), email: source.email_address.into(), name }
+///     }
+/// }
+/// ```
+pub fn expand_map_from(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn test_expand_field_options() {
+        let input: DeriveInput = parse_quote! {
+            #[map_from(UserEntity, AdminEntity)]
+            struct UserResponse {
+                id: String,
+                #[map_from(rename = "email_address")]
+                email: String,
+                #[map_from(with = "display_name")]
+                name: String,
+                #[map_from(skip)]
+                links: Vec<String>,
+            }
+        };
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("From < UserEntity > for UserResponse"));
+        assert!(tokens.contains("From < AdminEntity > for UserResponse"));
+        assert!(tokens.contains("source . email_address"));
+        assert!(tokens.contains("let name = display_name (& source)"));
+        assert!(tokens.contains("links : :: core :: default :: Default :: default ()"));
+    }
+
+    #[test]
+    fn test_expand_requires_source() {
+        let input: DeriveInput = parse_quote! {
+            struct UserResponse { id: String }
+        };
+        assert!(expand(&input).is_err());
+    }
+
+    #[test]
+    fn test_expand_rejects_unknown_option() {
+        let input: DeriveInput = parse_quote! {
+            #[map_from(UserEntity)]
+            struct UserResponse {
+                #[map_from(flatten)]
+                id: String,
+            }
+        };
+        assert!(expand(&input).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Mock generation implementation
+//!
+//! Handles expansion of #[mockable] on a trait into a `Mock<Trait>` struct
+//! that records calls and answers them from per-method stubs. The mock is
+//! only compiled for tests and with the using crate's `testing` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, FnArg, ItemTrait, ReturnType, Signature, TraitItem, TraitItemFn, Type,
+};
+
+/// A trait method the mock implements
+struct MockMethod {
+    sig: Signature,
+    arg_types: Vec<Type>,
+    output: TokenStream2,
+}
+
+// check a method can be stubbed and collect its argument types
+fn mock_method(method: &TraitItemFn) -> syn::Result<MockMethod> {
+    let sig = &method.sig;
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "#[mockable] does not support generic methods",
+        ));
+    }
+    match sig.inputs.first() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() => {}
+        _ => {
+            return Err(syn::Error::new_spanned(
+                sig,
+                "#[mockable] methods must take `&self` or `&mut self`",
+            ))
+        }
+    }
+
+    let mut arg_types = Vec::new();
+    for input in sig.inputs.iter().skip(1) {
+        if let FnArg::Typed(arg) = input {
+            if let Type::ImplTrait(_) = *arg.ty {
+                return Err(syn::Error::new_spanned(
+                    &arg.ty,
+                    "#[mockable] does not support `impl Trait` arguments",
+                ));
+            }
+            arg_types.push((*arg.ty).clone());
+        }
+    }
+
+    let output = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+
+    Ok(MockMethod {
+        sig: sig.clone(),
+        arg_types,
+        output,
+    })
+}
+
+/// Expand #[mockable] on a trait definition
+pub fn expand_mockable(input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as ItemTrait);
+    match expand(&item) {
+        Ok(mock) => quote!(#item #mock).into(),
+        Err(e) => {
+            let error = e.to_compile_error();
+            quote!(#item #error).into()
+        }
+    }
+}
+
+// generate the mock struct and its trait impl
+fn expand(item: &ItemTrait) -> syn::Result<TokenStream2> {
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &item.generics,
+            "#[mockable] does not support generic traits",
+        ));
+    }
+
+    let methods = item
+        .items
+        .iter()
+        .filter_map(|i| match i {
+            TraitItem::Fn(method) => Some(mock_method(method)),
+            _ => None,
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let vis = &item.vis;
+    let trait_name = &item.ident;
+    let mock_name = format_ident!("Mock{}", trait_name);
+    let doc = format!(
+        "Mock `{}` generated by `#[mockable]`\n\nConfigure answers with the `on_*` methods; calls without a stub panic.",
+        trait_name
+    );
+
+    let mut fields = Vec::new();
+    let mut inits = Vec::new();
+    let mut setters = Vec::new();
+    let mut impls = Vec::new();
+
+    for method in &methods {
+        let name = &method.sig.ident;
+        let name_str = name.to_string();
+        let stub = format_ident!("on_{}", name);
+        let arg_types = &method.arg_types;
+        let output = &method.output;
+        let args: Vec<_> = (0..arg_types.len())
+            .map(|i| format_ident!("arg{}", i))
+            .collect();
+        let setter_doc = format!("Answer calls to `{}` with `f`", name_str);
+        let missing = format!(
+            "{}::{} called without a stub; configure it with `{}`",
+            mock_name, name_str, stub
+        );
+
+        fields.push(quote! {
+            #stub: ::std::option::Option<
+                ::std::boxed::Box<dyn Fn(#(#arg_types),*) -> #output + Send + Sync>,
+            >
+        });
+        inits.push(quote!(#stub: ::std::option::Option::None));
+        setters.push(quote! {
+            #[doc = #setter_doc]
+            pub fn #stub(
+                mut self,
+                f: impl Fn(#(#arg_types),*) -> #output + Send + Sync + 'static,
+            ) -> Self {
+                self.#stub = ::std::option::Option::Some(::std::boxed::Box::new(f));
+                self
+            }
+        });
+
+        let mut sig = method.sig.clone();
+        for (input, arg) in sig.inputs.iter_mut().skip(1).zip(&args) {
+            if let FnArg::Typed(typed) = input {
+                typed.pat = syn::parse_quote!(#arg);
+            }
+        }
+        impls.push(quote! {
+            #sig {
+                {
+                    use ::rust_api::testing::{DescribeDebug as _, DescribeOpaque as _};
+                    let args = ::std::vec![
+                        #((&&::rust_api::testing::MockArg(&#args)).describe()),*
+                    ];
+                    self.calls.record(#name_str, args);
+                }
+                match &self.#stub {
+                    ::std::option::Option::Some(f) => f(#(#args),*),
+                    ::std::option::Option::None => panic!(#missing),
+                }
+            }
+        });
+    }
+
+    Ok(quote! {
+        #[cfg(any(test, feature = "testing"))]
+        #[doc = #doc]
+        #vis struct #mock_name {
+            calls: ::rust_api::testing::CallLog,
+            #(#fields,)*
+        }
+
+        #[cfg(any(test, feature = "testing"))]
+        impl #mock_name {
+            /// Create a mock with no stubs configured
+            pub fn new() -> Self {
+                Self {
+                    calls: ::rust_api::testing::CallLog::default(),
+                    #(#inits,)*
+                }
+            }
+
+            /// Calls received so far
+            pub fn calls(&self) -> &::rust_api::testing::CallLog {
+                &self.calls
+            }
+
+            #(#setters)*
+        }
+
+        #[cfg(any(test, feature = "testing"))]
+        impl ::std::default::Default for #mock_name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #[cfg(any(test, feature = "testing"))]
+        impl ::rust_api::Injectable for #mock_name {}
+
+        #[cfg(any(test, feature = "testing"))]
+        impl #trait_name for #mock_name {
+            #(#impls)*
+        }
+    })
+}
@@ -0,0 +1,93 @@
+//! Parameter pipe implementation
+//!
+//! Handles `#[pipe(...)]` on the parameters of route handlers by wrapping
+//! the parameter's extractor in `rust_api::pipe::Piped`.
+
+use quote::format_ident;
+use syn::{punctuated::Punctuated, Block, FnArg, Path, Signature, Token};
+
+/// Rewrite every parameter carrying `#[pipe(A, B)]`
+///
+/// `#[pipe(A, B)] pat: Ty` becomes `__pipe_N: Piped<Ty, (A, B,)>` and the
+/// body starts with `let pat: Ty = __pipe_N.0;`, so the handler sees the
+/// extractor it declared.
+pub fn take_pipes(sig: &mut Signature, block: &mut Block) -> syn::Result<()> {
+    let mut bindings = Vec::new();
+    for (index, input) in sig.inputs.iter_mut().enumerate() {
+        let FnArg::Typed(arg) = input else {
+            continue;
+        };
+        let Some(position) = arg.attrs.iter().position(|a| a.path().is_ident("pipe")) else {
+            continue;
+        };
+        let attr = arg.attrs.remove(position);
+        if let Some(extra) = arg.attrs.iter().find(|a| a.path().is_ident("pipe")) {
+            return Err(syn::Error::new_spanned(
+                extra,
+                "list every pipe in a single #[pipe(...)]",
+            ));
+        }
+        let pipes = attr.parse_args_with(Punctuated::<Path, Token![,]>::parse_terminated)?;
+        if pipes.is_empty() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "#[pipe] needs at least one pipe",
+            ));
+        }
+        if pipes.len() > 8 {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "#[pipe] takes at most 8 pipes",
+            ));
+        }
+
+        let pipes = pipes.iter();
+        let ident = format_ident!("__pipe_{}", index);
+        let pat = &arg.pat;
+        let ty = &arg.ty;
+        bindings.push(syn::parse_quote! { let #pat: #ty = #ident.0; });
+        let piped: syn::Type = syn::parse_quote! {
+            ::rust_api::pipe::Piped<#ty, (#(#pipes,)*)>
+        };
+        *arg.ty = piped;
+        *arg.pat = syn::parse_quote! { #ident };
+    }
+    if !bindings.is_empty() {
+        bindings.append(&mut block.stmts);
+        block.stmts = bindings;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::quote;
+
+    use super::*;
+
+    #[test]
+    fn test_take_pipes() {
+        let mut func: syn::ItemFn = syn::parse_quote! {
+            async fn add(
+                #[pipe(TrimStrings, pipes::ParseUuid)] Path(id): Path<String>,
+                state: State<S>,
+            ) -> String { id }
+        };
+        take_pipes(&mut func.sig, &mut func.block).unwrap();
+        let expected: syn::ItemFn = syn::parse_quote! {
+            async fn add(
+                __pipe_0: ::rust_api::pipe::Piped<Path<String>, (TrimStrings, pipes::ParseUuid,)>,
+                state: State<S>,
+            ) -> String {
+                let Path(id): Path<String> = __pipe_0.0;
+                id
+            }
+        };
+        assert_eq!(quote!(#func).to_string(), quote!(#expected).to_string());
+
+        let mut func: syn::ItemFn = syn::parse_quote! {
+            async fn add(#[pipe()] id: Path<String>) {}
+        };
+        assert!(take_pipes(&mut func.sig, &mut func.block).is_err());
+    }
+}
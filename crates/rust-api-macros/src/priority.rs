@@ -0,0 +1,77 @@
+//! Route priority macro implementation
+//!
+//! Handles expansion of #[priority(...)] into route registry metadata that
+//! tells the load shedder how important the route is.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Ident, ItemFn};
+
+// map the macro argument to a `Priority` variant
+fn variant(level: &Ident) -> syn::Result<Ident> {
+    let name = match level.to_string().as_str() {
+        "low" => "Low",
+        "normal" => "Normal",
+        "high" => "High",
+        "critical" => "Critical",
+        _ => {
+            return Err(syn::Error::new(
+                level.span(),
+                "unknown priority; expected `low`, `normal`, `high` or `critical`",
+            ))
+        }
+    };
+    Ok(Ident::new(name, level.span()))
+}
+
+/// Main expansion function for the priority macro
+///
+/// This transforms:
+/// ```ignore
+/// #[priority(low)]
+/// #[get("/reports/yearly")]
+/// async fn yearly_report() -> Json<Report> { ... }
+/// ```
+///
+/// Into the unchanged handler plus a route registry entry:
+/// ```ignore
+/// inventory::submit! {
+///     RouteMetadata::new("my_app::yearly_report", |meta| meta.insert(Priority::Low))
+/// }
+/// ```
+pub fn expand_priority(args: TokenStream, input: TokenStream) -> TokenStream {
+    let level = parse_macro_input!(args as Ident);
+    let variant = match variant(&level) {
+        Ok(variant) => variant,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let func = parse_macro_input!(input as ItemFn);
+    let handler_name = func.sig.ident.to_string();
+
+    let expanded = quote! {
+        #func
+
+        //route registry metadata - picked up by the load shedding middleware
+        ::rust_api::registry::inventory::submit! {
+            ::rust_api::registry::RouteMetadata::new(
+                concat!(module_path!(), "::", #handler_name),
+                |meta| meta.insert(::rust_api::shed::Priority::#variant),
+            )
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use proc_macro2::Span;
+
+    use super::*;
+
+    #[test]
+    fn test_variant() {
+        let level = Ident::new("critical", Span::call_site());
+        assert_eq!(variant(&level).unwrap(), "Critical");
+        assert!(variant(&Ident::new("urgent", Span::call_site())).is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Read-only route macro implementation
+//!
+//! Handles expansion of #[read_only] into route registry metadata that makes
+//! the framework route the handler's database reads to replicas.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, ItemFn};
+
+/// Main expansion function for the read-only macro
+///
+/// This transforms:
+/// ```ignore
+/// #[read_only]
+/// #[get("/reports")]
+/// async fn reports(Inject(db): Inject<Db<DatabaseConnection>>) -> Json<Report> { ... }
+/// ```
+///
+/// Into the unchanged handler plus a route registry entry:
+/// ```ignore
+/// inventory::submit! {
+///     RouteMetadata::new("my_app::reports", |meta| meta.insert(ReadOnly))
+/// }
+/// ```
+pub fn expand_read_only(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = proc_macro2::TokenStream::from(args);
+        return syn::Error::new_spanned(args, "#[read_only] takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let func = parse_macro_input!(input as ItemFn);
+    let handler_name = func.sig.ident.to_string();
+
+    let expanded = quote! {
+        #func
+
+        //route registry metadata - picked up by the read-only middleware
+        ::rust_api::registry::inventory::submit! {
+            ::rust_api::registry::RouteMetadata::new(
+                concat!(module_path!(), "::", #handler_name),
+                |meta| meta.insert(::rust_api::db::ReadOnly),
+            )
+        }
+    };
+    TokenStream::from(expanded)
+}
@@ -0,0 +1,469 @@
+//! Repository derive implementation
+//!
+//! Handles expansion of #[derive(Repository)] into an sqlx-backed
+//! `{Entity}Repository` implementing `rust_api::repository::Repository`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr, Type};
+
+/// SQL dialect of the generated queries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    Postgres,
+    Sqlite,
+}
+
+impl Backend {
+    // placeholder for the `n`th (1-based) bound parameter
+    fn placeholder(self, n: usize) -> String {
+        match self {
+            Backend::Postgres => format!("${}", n),
+            Backend::Sqlite => "?".to_string(),
+        }
+    }
+}
+
+/// A column of the entity table
+struct Column {
+    ident: Ident,
+    ty: Type,
+    id: bool,
+    generated: bool,
+}
+
+/// Queries of the generated repository
+#[derive(Debug, PartialEq, Eq)]
+struct Queries {
+    find: String,
+    list: String,
+    count: String,
+    create: String,
+    update: String,
+    delete: String,
+}
+
+// build the queries for `table`; `id` is the id column, `create` the columns
+// bound on insert and `update` the columns bound on update, in bind order
+fn queries(
+    backend: Backend,
+    table: &str,
+    id: &str,
+    create: &[String],
+    update: &[String],
+) -> Queries {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let table = quote(table);
+    let id = quote(id);
+    let p = |n| backend.placeholder(n);
+
+    let insert = if create.is_empty() {
+        format!("INSERT INTO {} DEFAULT VALUES RETURNING *", table)
+    } else {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({}) RETURNING *",
+            table,
+            create
+                .iter()
+                .map(|c| quote(c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            (1..=create.len()).map(p).collect::<Vec<_>>().join(", ")
+        )
+    };
+    let assignments = update
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("{} = {}", quote(c), p(i + 1)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Queries {
+        find: format!("SELECT * FROM {} WHERE {} = {}", table, id, p(1)),
+        list: format!(
+            "SELECT * FROM {} ORDER BY {} LIMIT {} OFFSET {}",
+            table,
+            id,
+            p(1),
+            p(2)
+        ),
+        count: format!("SELECT COUNT(*) FROM {}", table),
+        create: insert,
+        update: format!(
+            "UPDATE {} SET {} WHERE {} = {} RETURNING *",
+            table,
+            assignments,
+            id,
+            p(update.len() + 1)
+        ),
+        delete: format!("DELETE FROM {} WHERE {} = {}", table, id, p(1)),
+    }
+}
+
+// read the `#[repository(...)]` attributes of a field
+fn column(field: &syn::Field) -> syn::Result<Column> {
+    let ident = field.ident.clone().expect("named field");
+    let mut column = Column {
+        id: ident == "id",
+        ident,
+        ty: field.ty.clone(),
+        generated: false,
+    };
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("repository"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                column.id = true;
+            } else if meta.path.is_ident("generated") {
+                column.generated = true;
+            } else {
+                return Err(meta.error("unsupported option; expected `id` or `generated`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(column)
+}
+
+/// Struct-level `#[repository(...)]` options
+struct Options {
+    table: String,
+    backend: Backend,
+    name: Ident,
+}
+
+// read the struct-level `#[repository(...)]` options
+fn options(input: &DeriveInput) -> syn::Result<Options> {
+    let mut table = None;
+    let mut backend = Backend::Postgres;
+    let mut name = format_ident!("{}Repository", input.ident);
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("repository"))
+    {
+        attr.parse_nested_meta(|meta| {
+            let value: LitStr = meta.value()?.parse()?;
+            if meta.path.is_ident("table") {
+                table = Some(value.value());
+            } else if meta.path.is_ident("backend") {
+                backend = match value.value().as_str() {
+                    "postgres" => Backend::Postgres,
+                    "sqlite" => Backend::Sqlite,
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            value,
+                            "unsupported backend; expected \"postgres\" or \"sqlite\"",
+                        ))
+                    }
+                };
+            } else if meta.path.is_ident("name") {
+                name = value.parse()?;
+            } else {
+                return Err(meta.error("unsupported option; expected `table`, `backend` or `name`"));
+            }
+            Ok(())
+        })?;
+    }
+    let table = table.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(Repository)] requires #[repository(table = \"...\")]",
+        )
+    })?;
+    Ok(Options {
+        table,
+        backend,
+        name,
+    })
+}
+
+// generate the repository for a parsed struct
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(Repository)] only supports structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(Repository)] requires named fields",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "#[derive(Repository)] does not support generic entities",
+        ));
+    }
+
+    let options = options(input)?;
+    let columns = fields
+        .named
+        .iter()
+        .map(column)
+        .collect::<syn::Result<Vec<_>>>()?;
+    let mut ids = columns.iter().filter(|c| c.id);
+    let Some(id) = ids.next() else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[derive(Repository)] requires an `id` field or a #[repository(id)] field",
+        ));
+    };
+    if let Some(other) = ids.next() {
+        return Err(syn::Error::new_spanned(
+            &other.ident,
+            "#[derive(Repository)] supports a single id field",
+        ));
+    }
+
+    let create: Vec<&Column> = columns.iter().filter(|c| !c.generated).collect();
+    let update: Vec<&Column> = columns.iter().filter(|c| !c.generated && !c.id).collect();
+    let names = |columns: &[&Column]| {
+        columns
+            .iter()
+            .map(|c| c.ident.to_string())
+            .collect::<Vec<_>>()
+    };
+    let queries = queries(
+        options.backend,
+        &options.table,
+        &id.ident.to_string(),
+        &names(&create),
+        &names(&update),
+    );
+    let Queries {
+        find,
+        list,
+        count,
+        create: insert,
+        update: modify,
+        delete,
+    } = queries;
+
+    let entity = &input.ident;
+    let name = &options.name;
+    let vis = &input.vis;
+    let id_ty = &id.ty;
+    let create_fields = create.iter().map(|c| &c.ident);
+    let update_fields = update.iter().map(|c| &c.ident);
+    let db = match options.backend {
+        Backend::Postgres => quote! { ::rust_api::repository::sqlx::Postgres },
+        Backend::Sqlite => quote! { ::rust_api::repository::sqlx::Sqlite },
+    };
+    let doc = format!("sqlx-backed repository of [`{}`]", entity);
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Clone)]
+        #vis struct #name {
+            pool: ::rust_api::repository::sqlx::Pool<#db>,
+        }
+
+        impl #name {
+            /// Create the repository on a connection pool
+            #vis fn new(pool: ::rust_api::repository::sqlx::Pool<#db>) -> Self {
+                Self { pool }
+            }
+
+            /// The connection pool, for queries beyond CRUD
+            #vis fn pool(&self) -> &::rust_api::repository::sqlx::Pool<#db> {
+                &self.pool
+            }
+        }
+
+        impl ::rust_api::di::Injectable for #name {}
+
+        impl ::rust_api::repository::Repository<#entity, #id_ty> for #name {
+            fn find(
+                &self,
+                id: #id_ty,
+            ) -> ::rust_api::lifecycle::BoxFuture<
+                '_,
+                ::core::result::Result<::core::option::Option<#entity>, ::rust_api::repository::RepositoryError>,
+            > {
+                ::rust_api::repository::within_deadline(async move {
+                    ::core::result::Result::Ok(
+                        ::rust_api::repository::sqlx::query_as::<_, #entity>(#find)
+                            .bind(id)
+                            .fetch_optional(&self.pool)
+                            .await?,
+                    )
+                })
+            }
+
+            fn list(
+                &self,
+                pagination: ::rust_api::repository::Pagination,
+            ) -> ::rust_api::lifecycle::BoxFuture<
+                '_,
+                ::core::result::Result<::rust_api::repository::Page<#entity>, ::rust_api::repository::RepositoryError>,
+            > {
+                ::rust_api::repository::within_deadline(async move {
+                    let items = ::rust_api::repository::sqlx::query_as::<_, #entity>(#list)
+                        .bind(pagination.limit() as i64)
+                        .bind(pagination.offset() as i64)
+                        .fetch_all(&self.pool)
+                        .await?;
+                    let total: i64 = ::rust_api::repository::sqlx::query_scalar(#count)
+                        .fetch_one(&self.pool)
+                        .await?;
+                    ::core::result::Result::Ok(::rust_api::repository::Page::new(
+                        items,
+                        pagination,
+                        total as u64,
+                    ))
+                })
+            }
+
+            fn create(
+                &self,
+                item: #entity,
+            ) -> ::rust_api::lifecycle::BoxFuture<
+                '_,
+                ::core::result::Result<#entity, ::rust_api::repository::RepositoryError>,
+            > {
+                ::rust_api::repository::within_deadline(async move {
+                    ::core::result::Result::Ok(
+                        ::rust_api::repository::sqlx::query_as::<_, #entity>(#insert)
+                            #(.bind(item.#create_fields))*
+                            .fetch_one(&self.pool)
+                            .await?,
+                    )
+                })
+            }
+
+            fn update(
+                &self,
+                id: #id_ty,
+                item: #entity,
+            ) -> ::rust_api::lifecycle::BoxFuture<
+                '_,
+                ::core::result::Result<::core::option::Option<#entity>, ::rust_api::repository::RepositoryError>,
+            > {
+                ::rust_api::repository::within_deadline(async move {
+                    ::core::result::Result::Ok(
+                        ::rust_api::repository::sqlx::query_as::<_, #entity>(#modify)
+                            #(.bind(item.#update_fields))*
+                            .bind(id)
+                            .fetch_optional(&self.pool)
+                            .await?,
+                    )
+                })
+            }
+
+            fn delete(
+                &self,
+                id: #id_ty,
+            ) -> ::rust_api::lifecycle::BoxFuture<
+                '_,
+                ::core::result::Result<bool, ::rust_api::repository::RepositoryError>,
+            > {
+                ::rust_api::repository::within_deadline(async move {
+                    let result = ::rust_api::repository::sqlx::query(#delete)
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await?;
+                    ::core::result::Result::Ok(result.rows_affected() > 0)
+                })
+            }
+        }
+    })
+}
+
+/// Main expansion function for the Repository derive
+///
+/// This transforms:
+/// ```ignore
+/// #[derive(Repository)]
+/// #[repository(table = "users")]
+/// struct User {
+///     #[repository(id, generated)]
+///     id: i64,
+///     name: String,
+/// }
+/// ```
+///
+/// Into a `UserRepository` wrapping an sqlx pool, with
+/// `impl Repository<User, i64> for UserRepository` running queries such as
+/// `INSERT INTO "users" ("name") VALUES ($1) RETURNING *`.
+pub fn expand_repository(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn test_queries() {
+        let columns = ["name".to_string(), "email".to_string()];
+        let postgres = queries(Backend::Postgres, "users", "id", &columns, &columns);
+        assert_eq!(postgres.find, "SELECT * FROM \"users\" WHERE \"id\" = $1");
+        assert_eq!(
+            postgres.list,
+            "SELECT * FROM \"users\" ORDER BY \"id\" LIMIT $1 OFFSET $2"
+        );
+        assert_eq!(
+            postgres.create,
+            "INSERT INTO \"users\" (\"name\", \"email\") VALUES ($1, $2) RETURNING *"
+        );
+        assert_eq!(
+            postgres.update,
+            "UPDATE \"users\" SET \"name\" = $1, \"email\" = $2 WHERE \"id\" = $3 RETURNING *"
+        );
+        let sqlite = queries(Backend::Sqlite, "users", "id", &[], &columns);
+        assert_eq!(
+            sqlite.create,
+            "INSERT INTO \"users\" DEFAULT VALUES RETURNING *"
+        );
+        assert_eq!(sqlite.delete, "DELETE FROM \"users\" WHERE \"id\" = ?");
+    }
+
+    #[test]
+    fn test_expand_generates_repository() {
+        let input: DeriveInput = parse_quote! {
+            #[repository(table = "users", backend = "sqlite")]
+            pub struct User {
+                #[repository(id, generated)]
+                pub key: i64,
+                pub name: String,
+            }
+        };
+        let tokens = expand(&input).unwrap().to_string();
+        assert!(tokens.contains("pub struct UserRepository"));
+        assert!(tokens.contains("Repository < User , i64 > for UserRepository"));
+        assert!(tokens.contains("sqlx :: Sqlite"));
+        assert!(tokens.contains(". bind (item . name)"));
+        assert!(!tokens.contains(". bind (item . key)"));
+    }
+
+    #[test]
+    fn test_expand_rejects_invalid_input() {
+        let missing_table: DeriveInput = parse_quote! {
+            struct User { id: i64 }
+        };
+        assert!(expand(&missing_table).is_err());
+        let missing_id: DeriveInput = parse_quote! {
+            #[repository(table = "users")]
+            struct User { key: i64 }
+        };
+        assert!(expand(&missing_id).is_err());
+        let bad_backend: DeriveInput = parse_quote! {
+            #[repository(table = "users", backend = "oracle")]
+            struct User { id: i64 }
+        };
+        assert!(expand(&bad_backend).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+//! Retry macro implementation
+//!
+//! Handles expansion of #[retry(...)] on an async function or `&self`
+//! method into a call to `rust_api::retry::run` that re-runs the body on
+//! retryable errors.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse::Parser, parse_macro_input, punctuated::Punctuated, Expr, FnArg, ItemFn, LitInt, LitStr,
+    Pat, ReturnType, Token,
+};
+
+/// Options accepted by the retry macro
+struct RetryArgs {
+    attempts: u32,
+    backoff: TokenStream2,
+    delay_ms: Option<u64>,
+    on: Vec<Pat>,
+    name: Option<LitStr>,
+    jitter: bool,
+    metrics: Option<Expr>,
+}
+
+impl RetryArgs {
+    // parse `attempts = 3, backoff = "exponential", delay_ms = 100,
+    // on = [Error::A, Error::B], name = "users.find", jitter = false,
+    // metrics = self.metrics`
+    fn parse(args: TokenStream2) -> syn::Result<Self> {
+        let mut parsed = RetryArgs {
+            attempts: 3,
+            backoff: quote!(Exponential),
+            delay_ms: None,
+            on: Vec::new(),
+            name: None,
+            jitter: true,
+            metrics: None,
+        };
+        let parser = syn::meta::parser(|meta| {
+            if meta.path.is_ident("attempts") {
+                let attempts: LitInt = meta.value()?.parse()?;
+                parsed.attempts = attempts.base10_parse()?;
+                if parsed.attempts == 0 {
+                    return Err(syn::Error::new(
+                        attempts.span(),
+                        "attempts must be at least 1",
+                    ));
+                }
+            } else if meta.path.is_ident("backoff") {
+                let backoff: LitStr = meta.value()?.parse()?;
+                parsed.backoff = match backoff.value().as_str() {
+                    "fixed" => quote!(Fixed),
+                    "exponential" => quote!(Exponential),
+                    _ => {
+                        return Err(syn::Error::new(
+                            backoff.span(),
+                            "unknown backoff; expected \"fixed\" or \"exponential\"",
+                        ))
+                    }
+                };
+            } else if meta.path.is_ident("delay_ms") {
+                let delay: LitInt = meta.value()?.parse()?;
+                parsed.delay_ms = Some(delay.base10_parse()?);
+            } else if meta.path.is_ident("on") {
+                let input = meta.value()?;
+                let content;
+                syn::bracketed!(content in input);
+                let patterns = Punctuated::<Pat, Token![,]>::parse_terminated_with(
+                    &content,
+                    Pat::parse_multi,
+                )?;
+                parsed.on = patterns.into_iter().collect();
+            } else if meta.path.is_ident("name") {
+                parsed.name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("jitter") {
+                let jitter: syn::LitBool = meta.value()?.parse()?;
+                parsed.jitter = jitter.value;
+            } else if meta.path.is_ident("metrics") {
+                parsed.metrics = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error(
+                    "unsupported option; expected `attempts`, `backoff`, `delay_ms`, `on`, `name`, `jitter` or `metrics`",
+                ));
+            }
+            Ok(())
+        });
+        parser.parse2(args.clone())?;
+        // retrying every error would repeat non-transient failures and writes
+        if parsed.on.is_empty() {
+            return Err(syn::Error::new_spanned(
+                args,
+                "#[retry] needs `on = [...]`, the transient errors to retry",
+            ));
+        }
+        Ok(parsed)
+    }
+}
+
+// wrap the body in a retried closure
+fn expand(args: RetryArgs, mut func: ItemFn) -> syn::Result<TokenStream2> {
+    if func.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            func.sig.fn_token,
+            "#[retry] applies to async functions",
+        ));
+    }
+    let output = match &func.sig.output {
+        ReturnType::Type(_, ty) => ty.clone(),
+        ReturnType::Default => {
+            return Err(syn::Error::new_spanned(
+                &func.sig,
+                "#[retry] functions must return a `Result`",
+            ))
+        }
+    };
+
+    // every attempt gets its own copy of the arguments
+    let mut copies = Vec::new();
+    for (i, input) in func.sig.inputs.iter_mut().enumerate() {
+        match input {
+            FnArg::Receiver(receiver) => {
+                if receiver.reference.is_none() || receiver.mutability.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        receiver,
+                        "#[retry] methods must take `&self`",
+                    ));
+                }
+            }
+            FnArg::Typed(arg) => {
+                let pat = arg.pat.clone();
+                let ident = match &*arg.pat {
+                    Pat::Ident(ident) if ident.subpat.is_none() => ident.ident.clone(),
+                    _ => format_ident!("__arg{}", i),
+                };
+                *arg.pat = syn::parse_quote!(#ident);
+                copies.push(quote! { let #pat = ::core::clone::Clone::clone(&#ident); });
+            }
+        }
+    }
+
+    let name = match args.name {
+        Some(name) => quote!(#name),
+        None => {
+            let ident = func.sig.ident.to_string();
+            quote!(concat!(module_path!(), "::", #ident))
+        }
+    };
+    let attempts = args.attempts;
+    let backoff = args.backoff;
+    let mut policy = quote! {
+        ::rust_api::retry::RetryPolicy::new(#attempts)
+            .backoff(::rust_api::retry::Backoff::#backoff)
+    };
+    if let Some(delay) = args.delay_ms {
+        policy = quote!(#policy.delay(::std::time::Duration::from_millis(#delay)));
+    }
+    if !args.jitter {
+        policy = quote!(#policy.jitter(false));
+    }
+    if let Some(metrics) = &args.metrics {
+        policy = quote!(#policy.metrics(::core::clone::Clone::clone(&#metrics)));
+    }
+    let on = &args.on;
+    let retryable = quote!(|__error| matches!(__error, #(#on)|*));
+    let block = &func.block;
+    func.block = syn::parse_quote! {{
+        ::rust_api::retry::run(
+            #name,
+            #policy,
+            || {
+                #(#copies)*
+                async move {
+                    let __result: #output = async move #block.await;
+                    __result
+                }
+            },
+            #retryable,
+        )
+        .await
+    }};
+
+    Ok(quote! {
+        #func
+    })
+}
+
+/// Main expansion function for the retry macro
+///
+/// This transforms:
+/// ```ignore
+/// #[retry(attempts = 3, on = [sqlx::Error::PoolTimedOut])]
+/// async fn find(&self, id: String) -> Result<User, sqlx::Error> { ... }
+/// ```
+///
+/// Into a method running its body through `retry::run`:
+/// ```ignore
+/// async fn find(&self, id: String) -> Result<User, sqlx::Error> {
+///     ::rust_api::retry::run("my_app::find", RetryPolicy::new(3), || {
+///         let id = id.clone();
+///         async move { ... }
+///     }, |e| matches!(e, sqlx::Error::PoolTimedOut)).await
+/// }
+/// ```
+pub fn expand_retry(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = match RetryArgs::parse(args.into()) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let func = parse_macro_input!(input as ItemFn);
+    expand(args, func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = RetryArgs::parse(quote! {
+            attempts = 5, backoff = "fixed", delay_ms = 20, on = [Error::Busy, Error::Timeout(_)]
+        })
+        .unwrap();
+        assert_eq!(args.attempts, 5);
+        assert_eq!(args.backoff.to_string(), "Fixed");
+        assert_eq!(args.delay_ms, Some(20));
+        assert_eq!(args.on.len(), 2);
+
+        assert!(args.jitter);
+        assert!(args.metrics.is_none());
+
+        let args = RetryArgs::parse(quote!(
+            on = [Error::Busy],
+            jitter = false,
+            metrics = self.metrics
+        ))
+        .unwrap();
+        assert!(!args.jitter);
+        assert!(args.metrics.is_some());
+
+        assert!(RetryArgs::parse(quote!(attempts = 0, on = [Error::Busy])).is_err());
+        assert!(RetryArgs::parse(quote!(backoff = "linear", on = [Error::Busy])).is_err());
+        assert!(RetryArgs::parse(quote!(attempts = 3)).is_err());
+        assert!(RetryArgs::parse(quote!(on = [])).is_err());
+    }
+
+    #[test]
+    fn test_requires_async_shared_receiver() {
+        let args = || RetryArgs::parse(quote!(on = [E::Busy])).unwrap();
+        let func: ItemFn = syn::parse_quote! { fn find() -> Result<(), E> { Ok(()) } };
+        assert!(expand(args(), func).is_err());
+        let func: ItemFn =
+            syn::parse_quote! { async fn find(&mut self) -> Result<(), E> { Ok(()) } };
+        assert!(expand(args(), func).is_err());
+
+        let func: ItemFn = syn::parse_quote! {
+            async fn find(&self, (a, b): (u8, u8)) -> Result<u8, E> { Ok(a + b) }
+        };
+        let expanded = expand(args(), func).unwrap().to_string();
+        assert!(
+            expanded.contains("async fn find (& self , __arg1 : (u8 , u8))"),
+            "{}",
+            expanded
+        );
+    }
+}
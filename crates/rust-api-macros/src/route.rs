@@ -4,12 +4,15 @@
 //! handlers.
 
 use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input, ItemFn, LitStr,
+    parse_macro_input, FnArg, GenericArgument, ItemFn, LitStr, PathArguments, ReturnType, Type,
 };
 
+use crate::pipe::take_pipes;
+
 /// HTTP method for route
 #[derive(Debug, Clone, Copy)]
 pub enum HttpMethod {
@@ -21,9 +24,20 @@ pub enum HttpMethod {
 }
 
 impl HttpMethod {
+    /// Method for a route attribute name (`get`, `post`, ...)
+    pub fn from_attribute(name: &str) -> Option<Self> {
+        match name {
+            "get" => Some(HttpMethod::Get),
+            "post" => Some(HttpMethod::Post),
+            "put" => Some(HttpMethod::Put),
+            "delete" => Some(HttpMethod::Delete),
+            "patch" => Some(HttpMethod::Patch),
+            _ => None,
+        }
+    }
+
     // get the axum routing function name for this method
-    #[allow(dead_code)]
-    fn axum_method(&self) -> proc_macro2::TokenStream {
+    pub fn axum_method(&self) -> proc_macro2::TokenStream {
         match self {
             HttpMethod::Get => quote! { get },
             HttpMethod::Post => quote! { post },
@@ -34,8 +48,7 @@ impl HttpMethod {
     }
 
     // get the method name as a string
-    #[allow(dead_code)]
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             HttpMethod::Get => "GET",
             HttpMethod::Post => "POST",
@@ -48,16 +61,185 @@ impl HttpMethod {
 
 /// Arguments passed to route macro
 pub struct RouteArgs {
-    path: LitStr,
+    pub path: LitStr,
 }
 
 impl Parse for RouteArgs {
     fn parse(input: ParseStream) -> syn::Result<Self> {
         let path: LitStr = input.parse()?;
+        validate_path(&path.value()).map_err(|msg| syn::Error::new(path.span(), msg))?;
         Ok(RouteArgs { path })
     }
 }
 
+/// Validate a route path template at compile time
+///
+/// Catches mistakes that axum would otherwise only report with a panic at
+/// startup: missing leading slash, malformed `{param}` captures, legacy
+/// `:param` / `*rest` syntax, and catch-all `{*rest}` segments that are not
+/// last.
+pub fn validate_path(path: &str) -> Result<(), String> {
+    if !path.starts_with('/') {
+        return Err(format!("route path `{}` must start with `/`", path));
+    }
+
+    let segments: Vec<&str> = path[1..].split('/').collect();
+    for (index, segment) in segments.iter().enumerate() {
+        if segment.starts_with(':') || segment.starts_with('*') {
+            return Err(format!(
+                "route segment `{}` uses legacy syntax; write `{{{}}}` instead",
+                segment,
+                segment.trim_start_matches(':')
+            ));
+        }
+        for capture in captures(segment)? {
+            match capture.strip_prefix('*') {
+                Some(name) => {
+                    check_param_name(name)?;
+                    if index != segments.len() - 1 || *segment != format!("{{{}}}", capture) {
+                        return Err(format!(
+                            "catch-all `{{{}}}` must be the entire last segment",
+                            capture
+                        ));
+                    }
+                }
+                None => check_param_name(capture)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+// extract the `{...}` captures of a segment, skipping escaped `{{` and `}}`
+fn captures(segment: &str) -> Result<Vec<&str>, String> {
+    let bytes = segment.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'}' if bytes.get(i + 1) == Some(&bytes[i]) => i += 2,
+            b'{' => {
+                let close = segment[i + 1..]
+                    .find('}')
+                    .ok_or_else(|| format!("unclosed `{{` in route segment `{}`", segment))?;
+                found.push(&segment[i + 1..i + 1 + close]);
+                i += close + 2;
+            }
+            b'}' => return Err(format!("unmatched `}}` in route segment `{}`", segment)),
+            _ => i += 1,
+        }
+    }
+    Ok(found)
+}
+
+// route parameters must be valid Rust-style identifiers
+fn check_param_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid route parameter name `{}`", name))
+    }
+}
+
+// media type constant of a `Cbor`, `Proto` or `Xml` body type
+fn format_media_type(ty: &Type) -> Option<TokenStream2> {
+    let Type::Path(ty) = ty else {
+        return None;
+    };
+    match ty.path.segments.last()?.ident.to_string().as_str() {
+        "Cbor" => Some(quote! { ::rust_api::formats::CBOR_CONTENT_TYPE }),
+        "Proto" => Some(quote! { ::rust_api::formats::PROTOBUF_CONTENT_TYPE }),
+        "Xml" => Some(quote! { ::rust_api::formats::XML_CONTENT_TYPE }),
+        _ => None,
+    }
+}
+
+// body format of a return type, looking inside `Result<...>` and the like
+fn response_media_type(ty: &Type) -> Option<TokenStream2> {
+    if let Some(media_type) = format_media_type(ty) {
+        return Some(media_type);
+    }
+    let Type::Path(ty) = ty else {
+        return None;
+    };
+    let PathArguments::AngleBracketed(args) = &ty.path.segments.last()?.arguments else {
+        return None;
+    };
+    args.args.iter().find_map(|arg| match arg {
+        GenericArgument::Type(ty) => response_media_type(ty),
+        _ => None,
+    })
+}
+
+// `MediaTypes` metadata for handlers taking or returning non-JSON formats
+fn media_types(func: &ItemFn, handler_name: &str) -> Option<TokenStream2> {
+    let request = func.sig.inputs.iter().find_map(|input| match input {
+        FnArg::Typed(arg) => format_media_type(&arg.ty),
+        FnArg::Receiver(_) => None,
+    });
+    let response = match &func.sig.output {
+        ReturnType::Type(_, ty) => response_media_type(ty),
+        ReturnType::Default => None,
+    };
+    if request.is_none() && response.is_none() {
+        return None;
+    }
+    let request = request.map(|media_type| quote! { .request(#media_type) });
+    let response = response.map(|media_type| quote! { .response(#media_type) });
+    Some(quote! {
+        ::rust_api::registry::inventory::submit! {
+            ::rust_api::registry::RouteMetadata::new(
+                concat!(module_path!(), "::", #handler_name),
+                |meta| meta.insert(::rust_api::formats::MediaTypes::new() #request #response),
+            )
+        }
+    })
+}
+
+/// Route path constant and registry entry of a handler
+///
+/// The registry entry is submitted from inside the constant's initializer,
+/// so the expansion is valid both at module level and for an associated
+/// function in an `impl` block. Methods taking `self` are rejected: they
+/// are served through `#[controller]`. Handlers taking or returning `Cbor`,
+/// `Proto` or `Xml` bodies also get `MediaTypes` metadata.
+pub fn route_registration(method_name: &str, path: &LitStr, func: &ItemFn) -> TokenStream2 {
+    if let Some(receiver) = func.sig.receiver() {
+        return syn::Error::new_spanned(
+            receiver,
+            "route handlers cannot take `self`; put `#[controller]` on the impl block",
+        )
+        .to_compile_error();
+    }
+    let func_name = &func.sig.ident;
+    let func_vis = &func.vis;
+    let route_helper_name = syn::Ident::new(&format!("__{}_route", func_name), func_name.span());
+    let handler_name = func_name.to_string();
+    let media_types = media_types(func, &handler_name);
+
+    quote! {
+        //route path constant - stores just the path for registration
+        #[allow(non_upper_case_globals)]
+        #func_vis const #route_helper_name: &str = {
+            //route registry entry - lets other crates attach metadata to the route
+            ::rust_api::registry::inventory::submit! {
+                ::rust_api::registry::RouteDef::new(
+                    #method_name,
+                    #path,
+                    concat!(module_path!(), "::", #handler_name),
+                )
+            }
+            #media_types
+            #path
+        };
+    }
+}
+
 /// Main expansion function for route macros
 ///
 /// This transforms:
@@ -66,13 +248,18 @@ impl Parse for RouteArgs {
 /// async fn get_user(Path(id): Path<String>) -> Json<User> { ... }
 /// ```
 ///
-/// Into the original function plus a route path constant:
+/// Into the original function plus a route path constant, and submits the
+/// route to the route registry. Parameters marked `#[pipe(...)]` are
+/// rewritten to extract through `rust_api::pipe::Piped`:
 /// ```ignore
 /// async fn get_user(Path(id): Path<String>) -> Json<User> { ... }
-/// const __get_user_route: &str = "/users/{id}";
+/// const __get_user_route: &str = {
+///     inventory::submit! { RouteDef::new("GET", "/users/{id}", "my_app::get_user") }
+///     "/users/{id}"
+/// };
 /// ```
 pub fn expand_route_macro(
-    _method: HttpMethod,
+    method: HttpMethod,
     args: TokenStream,
     input: TokenStream,
 ) -> TokenStream {
@@ -81,20 +268,16 @@ pub fn expand_route_macro(
     let path = args.path;
 
     // parse the function
-    let func = parse_macro_input!(input as ItemFn);
-    let func_name = &func.sig.ident;
-    let func_vis = &func.vis;
-
-    // generate route registration helper
-    let route_helper_name = syn::Ident::new(&format!("__{}_route", func_name), func_name.span());
-
+    let mut func = parse_macro_input!(input as ItemFn);
+    if let Err(e) = take_pipes(&mut func.sig, &mut func.block) {
+        return e.to_compile_error().into();
+    }
+    let registration = route_registration(method.as_str(), &path, &func);
     let expanded = quote! {
         //original handler function
         #func
 
-        //route path constant - stores just the path for registration
-        #[allow(non_upper_case_globals)]
-        #func_vis const #route_helper_name: &str = #path;
+        #registration
     };
 
     TokenStream::from(expanded)
@@ -112,4 +295,43 @@ mod tests {
         assert_eq!(HttpMethod::Delete.as_str(), "DELETE");
         assert_eq!(HttpMethod::Patch.as_str(), "PATCH");
     }
+
+    #[test]
+    fn test_http_method_from_attribute() {
+        assert!(matches!(
+            HttpMethod::from_attribute("patch"),
+            Some(HttpMethod::Patch)
+        ));
+        assert!(HttpMethod::from_attribute("route").is_none());
+    }
+
+    #[test]
+    fn test_validate_path_accepts_valid_paths() {
+        for path in [
+            "/",
+            "/users",
+            "/users/{id}",
+            "/users/{user_id}/posts/{post_id}",
+            "/files/{*path}",
+            "/literal/{{braces}}",
+        ] {
+            assert!(validate_path(path).is_ok(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_validate_path_rejects_invalid_paths() {
+        for path in [
+            "users",
+            "/users/:id",
+            "/files/*path",
+            "/files/{*path}/tail",
+            "/users/{id",
+            "/users/id}",
+            "/users/{}",
+            "/users/{1id}",
+        ] {
+            assert!(validate_path(path).is_err(), "{}", path);
+        }
+    }
 }
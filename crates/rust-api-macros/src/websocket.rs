@@ -0,0 +1,92 @@
+//! WebSocket route macro implementation
+//!
+//! Handles expansion of #[websocket] on a function returning a gateway into
+//! a GET handler that accepts the upgrade and serves the connection.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, FnArg, ItemFn, ReturnType};
+
+use crate::{
+    pipe::take_pipes,
+    route::{route_registration, RouteArgs},
+};
+
+// turn the gateway factory into the upgrade handler
+fn expand(args: RouteArgs, mut func: ItemFn) -> syn::Result<TokenStream2> {
+    if func.sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            func.sig.fn_token,
+            "#[websocket] handlers must be `async fn`",
+        ));
+    }
+    let gateway = match &func.sig.output {
+        ReturnType::Type(_, ty) => (**ty).clone(),
+        ReturnType::Default => {
+            return Err(syn::Error::new_spanned(
+                &func.sig,
+                "#[websocket] handlers must return their `Gateway`",
+            ))
+        }
+    };
+    take_pipes(&mut func.sig, &mut func.block)?;
+
+    let upgrade: FnArg = syn::parse_quote! { __ws_upgrade: ::rust_api::ws::Upgrade };
+    func.sig.inputs.push(upgrade);
+    func.sig.output = syn::parse_quote! { -> ::rust_api::Response };
+    let block = &func.block;
+    func.block = syn::parse_quote! {{
+        let __ws_gateway: #gateway = async move #block.await;
+        __ws_upgrade.accept(__ws_gateway)
+    }};
+
+    let registration = route_registration("GET", &args.path, &func);
+    Ok(quote! {
+        //upgrade handler serving the returned gateway
+        #func
+
+        #registration
+    })
+}
+
+/// Main expansion function for the websocket macro
+///
+/// This transforms:
+/// ```ignore
+/// #[websocket("/chat")]
+/// async fn chat(Inject(rooms): Inject<Rooms>) -> ChatGateway { ChatGateway::new(rooms) }
+/// ```
+///
+/// Into a GET handler plus the usual route path constant and registry entry:
+/// ```ignore
+/// async fn chat(Inject(rooms): Inject<Rooms>, __ws_upgrade: Upgrade) -> Response {
+///     let __ws_gateway: ChatGateway = async move { ChatGateway::new(rooms) }.await;
+///     __ws_upgrade.accept(__ws_gateway)
+/// }
+/// const __chat_route: &str = "/chat";
+/// ```
+pub fn expand_websocket(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as RouteArgs);
+    let func = parse_macro_input!(input as ItemFn);
+    expand(args, func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requires_gateway_return() {
+        let args: RouteArgs = syn::parse_quote! { "/chat" };
+        let func: ItemFn = syn::parse_quote! { async fn chat() {} };
+        assert!(expand(args, func).is_err());
+
+        let args: RouteArgs = syn::parse_quote! { "/chat" };
+        let func: ItemFn = syn::parse_quote! { async fn chat() -> Chat { Chat } };
+        let expanded = expand(args, func).unwrap().to_string();
+        assert!(expanded.contains("__ws_upgrade . accept"), "{}", expanded);
+    }
+}
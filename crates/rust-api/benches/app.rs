@@ -0,0 +1,109 @@
+//! Request-path benchmarks
+//!
+//! Run with `cargo bench -p rust-api`; compare runs with criterion's saved
+//! baselines (`--save-baseline main`, then `--baseline main`).
+
+use std::sync::Arc;
+
+use axum::{body::Body, http::Request, Router};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_api::{
+    bench::{echo_app, EchoController, EchoService},
+    Container, RequestContext,
+};
+use tower::ServiceExt;
+
+// send one request through the router and drain the body
+async fn call(router: &Router, request: Request<Body>) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+}
+
+fn container(c: &mut Criterion) {
+    let mut container = Container::new();
+    container.register(Arc::new(EchoService::new("bench")));
+    c.bench_function("container/resolve", |b| {
+        b.iter(|| container.resolve::<EchoService>().unwrap())
+    });
+}
+
+fn requests(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let router = echo_app().build();
+    let mut group = c.benchmark_group("request");
+
+    group.bench_function("health", |b| {
+        b.to_async(&runtime).iter(|| {
+            call(
+                &router,
+                Request::get("/health").body(Body::empty()).unwrap(),
+            )
+        })
+    });
+    group.bench_function("path_and_inject", |b| {
+        b.to_async(&runtime).iter(|| {
+            call(
+                &router,
+                Request::get("/users/42").body(Body::empty()).unwrap(),
+            )
+        })
+    });
+    for tags in [1, 100, 5_000] {
+        let body = serde_json::json!({
+            "message": "hello",
+            "tags": vec!["tag"; tags],
+        })
+        .to_string();
+        group.bench_with_input(BenchmarkId::new("json_echo", tags), &body, |b, body| {
+            b.to_async(&runtime).iter(|| {
+                call(
+                    &router,
+                    Request::post("/echo")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.clone()))
+                        .unwrap(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn controllers(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let controller = Arc::new(EchoController::new(Arc::new(EchoService::new("bench"))));
+    let mut group = c.benchmark_group("controller");
+    for (name, router) in [
+        ("macro", controller.clone().router()),
+        ("state", controller.state_router()),
+    ] {
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| {
+                call(
+                    &router,
+                    Request::get("/users/42").body(Body::empty()).unwrap(),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn context(c: &mut Criterion) {
+    let mut group = c.benchmark_group("context");
+    RequestContext::new("bench").sync_scope(|| {
+        group.bench_function("current", |b| b.iter(RequestContext::current));
+        group.bench_function("with_current", |b| {
+            b.iter(|| RequestContext::with_current(|ctx| ctx.request_id().len()))
+        });
+    });
+    let ctx = RequestContext::new("bench");
+    ctx.set_principal("alice");
+    group.bench_function("principal", |b| b.iter(|| ctx.principal()));
+    group.finish();
+}
+
+criterion_group!(benches, container, requests, controllers, context);
+criterion_main!(benches);
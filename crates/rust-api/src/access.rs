@@ -0,0 +1,47 @@
+//! Access log for RustAPI framework
+//!
+//! With `App::access_log`, every request is logged once it is answered, as
+//! an INFO event with the `access` target. The event carries the request id
+//! that is also returned in `x-request-id`, recorded on the trace's
+//! `request` span, stamped on audit records and sent on outbound calls, so
+//! one id finds everything a request did. The client address is the one
+//! reported by the PROXY protocol header when it is enabled.
+
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::Response,
+};
+use tokio::time::Instant;
+
+use crate::context::RequestContext;
+
+// log the request once the response is known
+pub(crate) async fn log_access(req: Request, next: Next) -> Response {
+    let Some(ctx) = RequestContext::current() else {
+        return next.run(req).await;
+    };
+    let path = req.uri().path().to_string();
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let started = Instant::now();
+    let response = next.run(req).await;
+    tracing::info!(
+        target: "access",
+        method = %ctx.method(),
+        route = ctx.route().unwrap_or("unmatched"),
+        path = %path,
+        client = client.map(tracing::field::display),
+        status = response.status().as_u16(),
+        latency_ms = started.elapsed().as_secs_f64() * 1000.0,
+        request_id = ctx.request_id(),
+        principal = ctx.principal().as_deref(),
+        tenant = ctx.tenant().as_deref(),
+        "Request served"
+    );
+    response
+}
@@ -0,0 +1,436 @@
+//! Admin endpoints for RustAPI framework
+//!
+//! An optional group of debug endpoints (route list, registered services,
+//! redacted configuration, feature flags, log level, body capture) mounted
+//! with `App::enable_admin`. Every endpoint sits behind the admin guards, and
+//! the group is left out of release builds unless explicitly allowed.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    http::{header, request::Parts, HeaderMap},
+    routing::get,
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    capture::{BodyCapture, REDACTED},
+    di::Container,
+    error::ApiError,
+    group::RouteGroup,
+    guard::Guard,
+    metrics::{Metrics, OPENMETRICS_CONTENT_TYPE},
+    registry::ServedRoutes,
+    router::Router,
+};
+
+/// Default path prefix for the admin endpoints
+pub const DEFAULT_PREFIX: &str = "/._admin";
+
+// configuration keys containing any of these are always redacted
+const SECRET_KEYS: &[&str] = &["password", "secret", "token", "key", "credential"];
+
+// callback applying a new log level, e.g. through a tracing reload handle
+type LogLevelFn = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+
+/// Configuration of the admin endpoint group
+///
+/// The admin endpoints reject every request until at least one guard is
+/// added, and are only mounted in debug builds unless `allow_in_release` is
+/// set.
+///
+/// | Endpoint           | Description                                  |
+/// |--------------------|----------------------------------------------|
+/// | `GET /routes`      | Routes declared with route macros that the app serves, at their served paths |
+/// | `GET /services`    | DI graph: registered services and their dependencies |
+/// | `GET /config`      | Configuration, with secrets redacted         |
+/// | `GET /flags`       | Feature flags                                |
+/// | `GET, PUT /log-level` | Current log level / change it             |
+/// | `GET, PUT /capture`   | Body capture state / toggle it            |
+/// | `GET /metrics`     | Prometheus metrics, when `App::metrics` is set; OpenMetrics with exemplars when asked for |
+/// | `GET /pprof`       | CPU profile as a flamegraph or pprof, with the `profiling` feature (see `profiling`) |
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new()
+///     .capture_bodies(capture.clone())
+///     .enable_admin(
+///         "/._admin",
+///         Admin::new()
+///             .bearer_token(std::env::var("ADMIN_TOKEN")?)
+///             .config(serde_json::to_value(&settings)?)
+///             .flag("new_checkout", true)
+///             .log_level("info", move |level| reload.modify(|f| *f = EnvFilter::new(level)).map_err(|e| e.to_string()))
+///             .body_capture(capture),
+///     );
+/// ```
+pub struct Admin {
+    guards: Vec<Box<dyn FnOnce(RouteGroup) -> RouteGroup + Send>>,
+    config: Value,
+    redact: Vec<String>,
+    flags: BTreeMap<String, bool>,
+    log_level: Option<(String, Arc<LogLevelFn>)>,
+    body_capture: Option<BodyCapture>,
+    allow_in_release: bool,
+}
+
+impl Admin {
+    /// Create an admin group with no guards (every request is rejected)
+    pub fn new() -> Self {
+        Self {
+            guards: Vec::new(),
+            config: Value::Null,
+            redact: Vec::new(),
+            flags: BTreeMap::new(),
+            log_level: None,
+            body_capture: None,
+            allow_in_release: false,
+        }
+    }
+
+    /// Protect the admin endpoints with a guard
+    pub fn guard(mut self, guard: impl Guard) -> Self {
+        self.guards.push(Box::new(move |group| group.guard(guard)));
+        self
+    }
+
+    /// Require `Authorization: Bearer <token>` on every admin request
+    pub fn bearer_token(self, token: impl Into<String>) -> Self {
+        let expected = format!("Bearer {}", token.into());
+        self.guard(move |parts: &Parts| {
+            let provided = parts
+                .headers
+                .get(header::AUTHORIZATION)
+                .map_or(&[][..], |value| value.as_bytes());
+            // constant time, so the token cannot be guessed byte by byte
+            if bool::from(provided.ct_eq(expected.as_bytes())) {
+                Ok(())
+            } else {
+                Err(ApiError::unauthorized("Invalid admin credentials"))
+            }
+        })
+    }
+
+    /// Configuration to expose; keys that look like secrets are redacted
+    pub fn config(mut self, config: Value) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Additionally redact a configuration field by JSON pointer
+    pub fn redact(mut self, pointer: &str) -> Self {
+        self.redact.push(pointer.to_string());
+        self
+    }
+
+    /// Expose a feature flag
+    pub fn flag(mut self, name: impl Into<String>, enabled: bool) -> Self {
+        self.flags.insert(name.into(), enabled);
+        self
+    }
+
+    /// Allow changing the log level, starting from `current`
+    ///
+    /// `apply` receives the requested level (e.g. `debug` or an
+    /// `EnvFilter`-style directive) and applies it, typically through a
+    /// `tracing_subscriber::reload` handle.
+    pub fn log_level<F>(mut self, current: impl Into<String>, apply: F) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.log_level = Some((current.into(), Arc::new(apply)));
+        self
+    }
+
+    /// Allow toggling body capture at runtime
+    pub fn body_capture(mut self, capture: BodyCapture) -> Self {
+        self.body_capture = Some(capture);
+        self
+    }
+
+    /// Mount the admin endpoints in release builds too
+    pub fn allow_in_release(mut self, allow: bool) -> Self {
+        self.allow_in_release = allow;
+        self
+    }
+
+    /// Whether the endpoints should be mounted in this build
+    pub fn is_enabled(&self) -> bool {
+        cfg!(debug_assertions) || self.allow_in_release
+    }
+
+    /// Build the admin router for an application
+    pub(crate) fn into_router(
+        self,
+        container: &Container,
+        routes: &ServedRoutes,
+        metrics: Option<Metrics>,
+    ) -> Router {
+        let mut group = RouteGroup::new();
+
+        let routes: Vec<Value> = routes
+            .iter()
+            .map(|served| {
+                json!({
+                    "method": served.route.method,
+                    "path": served.path,
+                    "handler": served.route.handler,
+                })
+            })
+            .collect();
+        let routes = Json(routes);
+        group = group.route("/routes", get(move || async move { routes }));
+
+        let services: Vec<Value> = container
+            .graph()
+            .into_iter()
+            .map(|(service, dependencies)| json!({ "service": service, "dependencies": dependencies }))
+            .collect();
+        let services = Json(services);
+        group = group.route("/services", get(move || async move { services }));
+
+        let config = Json(redact_config(self.config, &self.redact));
+        group = group.route("/config", get(move || async move { config }));
+
+        let flags = Json(self.flags);
+        group = group.route("/flags", get(move || async move { flags }));
+
+        if let Some((current, apply)) = self.log_level {
+            let current = Arc::new(Mutex::new(current));
+            let read = current.clone();
+            group = group.route(
+                "/log-level",
+                get(move || async move {
+                    let level = read.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    Json(json!({ "level": level }))
+                })
+                .put(move |Json(body): Json<LogLevelBody>| async move {
+                    apply(&body.level).map_err(ApiError::bad_request)?;
+                    tracing::info!("Log level changed to {}", body.level);
+                    *current.lock().unwrap_or_else(|e| e.into_inner()) = body.level.clone();
+                    Ok::<_, ApiError>(Json(json!({ "level": body.level })))
+                }),
+            );
+        }
+
+        if let Some(capture) = self.body_capture {
+            let read = capture.clone();
+            group = group.route(
+                "/capture",
+                get(move || async move { Json(json!({ "enabled": read.is_enabled() })) }).put(
+                    move |Json(body): Json<CaptureBody>| async move {
+                        if body.enabled {
+                            capture.enable();
+                        } else {
+                            capture.disable();
+                        }
+                        tracing::info!(
+                            "Body capture {}",
+                            if body.enabled { "enabled" } else { "disabled" }
+                        );
+                        Json(json!({ "enabled": body.enabled }))
+                    },
+                ),
+            );
+        }
+
+        if let Some(metrics) = metrics {
+            group = group.route(
+                "/metrics",
+                get(move |headers: HeaderMap| async move {
+                    // OpenMetrics carries the latency exemplars
+                    let openmetrics = headers
+                        .get(header::ACCEPT)
+                        .and_then(|accept| accept.to_str().ok())
+                        .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+                    if openmetrics {
+                        (
+                            [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)],
+                            metrics.render_openmetrics(),
+                        )
+                    } else {
+                        (
+                            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                            metrics.render_prometheus(),
+                        )
+                    }
+                }),
+            );
+        }
+
+        #[cfg(all(feature = "profiling", unix))]
+        {
+            group = group.route("/pprof", get(crate::profiling::handler));
+        }
+
+        if self.guards.is_empty() {
+            group = group.guard(|_: &Parts| -> Result<(), ApiError> {
+                Err(ApiError::forbidden(
+                    "Admin endpoints have no guard configured",
+                ))
+            });
+        }
+        for add_guard in self.guards {
+            group = add_guard(group);
+        }
+        group.finish()
+    }
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Deserialize)]
+struct LogLevelBody {
+    level: String,
+}
+
+#[derive(Deserialize)]
+struct CaptureBody {
+    enabled: bool,
+}
+
+// replace secret-looking keys and the given pointers with a placeholder
+fn redact_config(mut config: Value, pointers: &[String]) -> Value {
+    redact_secret_keys(&mut config);
+    for pointer in pointers {
+        if let Some(value) = config.pointer_mut(pointer) {
+            *value = Value::String(REDACTED.to_string());
+        }
+    }
+    config
+}
+
+// walk a JSON value, redacting object entries whose key looks secret
+fn redact_secret_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, entry) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if SECRET_KEYS.iter().any(|secret| key.contains(secret)) {
+                    *entry = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secret_keys(entry);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_secret_keys),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn call(router: &Router, method: &str, path: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn test_redact_config() {
+        let config = json!({
+            "database": { "url": "postgres://db", "password": "hunter2" },
+            "api_key": "abc",
+            "region": "eu"
+        });
+        let redacted = redact_config(config, &["/region".to_string()]);
+        assert_eq!(redacted["database"]["password"], REDACTED);
+        assert_eq!(redacted["database"]["url"], "postgres://db");
+        assert_eq!(redacted["api_key"], REDACTED);
+        assert_eq!(redacted["region"], REDACTED);
+    }
+
+    #[tokio::test]
+    async fn test_requires_guard() {
+        let router = Admin::new().into_router(&Container::new(), &ServedRoutes::default(), None);
+        let (status, _) = call(&router, "GET", "/flags", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let router = Admin::new().bearer_token("other").into_router(
+            &Container::new(),
+            &ServedRoutes::default(),
+            None,
+        );
+        let (status, _) = call(&router, "GET", "/flags", "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints() {
+        let capture = BodyCapture::new();
+        let applied = Arc::new(Mutex::new(String::new()));
+        let applied_by_hook = applied.clone();
+        let router = Admin::new()
+            .bearer_token("secret")
+            .flag("beta", true)
+            .config(json!({ "token": "t" }))
+            .log_level("info", move |level| {
+                *applied_by_hook.lock().unwrap() = level.to_string();
+                Ok(())
+            })
+            .body_capture(capture.clone())
+            .into_router(&Container::new(), &ServedRoutes::default(), None);
+
+        assert_eq!(
+            call(&router, "GET", "/flags", "").await.1,
+            json!({ "beta": true })
+        );
+        assert_eq!(
+            call(&router, "GET", "/config", "").await.1["token"],
+            REDACTED
+        );
+        assert_eq!(call(&router, "GET", "/services", "").await.1, json!([]));
+
+        call(&router, "PUT", "/log-level", r#"{"level":"debug"}"#).await;
+        assert_eq!(*applied.lock().unwrap(), "debug");
+        assert_eq!(
+            call(&router, "GET", "/log-level", "").await.1["level"],
+            "debug"
+        );
+
+        call(&router, "PUT", "/capture", r#"{"enabled":true}"#).await;
+        assert!(capture.is_enabled());
+        let (status, _) = call(&router, "GET", "/metrics", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(all(feature = "profiling", unix))]
+    #[tokio::test]
+    async fn test_pprof_validates_query() {
+        let router = Admin::new().bearer_token("secret").into_router(
+            &Container::new(),
+            &ServedRoutes::default(),
+            None,
+        );
+        let (status, _) = call(&router, "GET", "/pprof?seconds=600", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(&router, "GET", "/pprof?format=jfr", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -3,11 +3,49 @@
 //! Provides an ergonomic API for constructing and configuring REST
 //! applications.
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use axum::Router;
+use axum::{
+    extract::Request,
+    http::StatusCode,
+    middleware,
+    response::Html,
+    routing::{any, get, MethodRouter},
+    Extension, Json, Router,
+};
+use tower_http::cors::CorsLayer;
 
-use crate::{di::Container, error::Result};
+use crate::{
+    access,
+    admin::Admin,
+    boot,
+    capture::{self, BodyCapture},
+    client::{Correlated, HttpClient},
+    codes,
+    compression::{self, Compression},
+    context, db, deprecation,
+    di::Container,
+    error::Result,
+    group::RouteGroup,
+    health::{DependencyWaits, HealthCheck},
+    host::{self, HostPattern},
+    ids::IdGenerator,
+    json::UnknownFields,
+    metrics::{self, Metrics},
+    mock,
+    openapi::OpenApi,
+    profile::{self, DocsAssets, LogFormat, Profile},
+    redirect,
+    registry::{RouteRegistry, ServedRoutes},
+    rejection::{self, Rejection, RejectionHandler},
+    router::{self, TrailingSlash},
+    sampling::Sampler,
+    seed::{SeedMarkers, Seeder, Seeds},
+    server::RustAPI,
+    shutdown,
+    tenant::{self, TenantResolver},
+    worker::{self, Worker, Workers},
+};
 
 /// Application builder for rust-api framework
 ///
@@ -28,6 +66,36 @@ use crate::{di::Container, error::Result};
 pub struct App {
     container: Container,
     router: Router,
+    lane: Router,
+    trailing_slash: TrailingSlash,
+    hosts: Vec<(HostPattern, Router)>,
+    mounts: Vec<String>,
+    request_timeout: Option<Duration>,
+    cancel_abandoned: bool,
+    id_generator: Option<Arc<dyn IdGenerator>>,
+    metrics: Option<Metrics>,
+    body_capture: Option<BodyCapture>,
+    admin: Option<(String, Admin)>,
+    openapi: OpenApi,
+    compression: Option<Compression>,
+    seeds: Seeds,
+    tenancy: Option<TenantResolver>,
+    profile: Option<Profile>,
+    log_format: Option<LogFormat>,
+    error_details: Option<bool>,
+    debug_errors: Option<bool>,
+    docs: Option<Option<String>>,
+    docs_assets: DocsAssets,
+    cors: Option<Option<CorsLayer>>,
+    rejections: Option<RejectionHandler>,
+    load_shedding: Option<crate::shed::LoadShedder>,
+    sampler: Option<Arc<Sampler>>,
+    access_log: bool,
+    proxy_protocol: bool,
+    mock_unimplemented: bool,
+    unknown_fields: Option<UnknownFields>,
+    workers: Workers,
+    waits: DependencyWaits,
 }
 
 impl App {
@@ -36,6 +104,36 @@ impl App {
         Self {
             container: Container::new(),
             router: Router::new(),
+            lane: Router::new(),
+            trailing_slash: TrailingSlash::default(),
+            hosts: Vec::new(),
+            mounts: Vec::new(),
+            request_timeout: None,
+            cancel_abandoned: false,
+            id_generator: None,
+            metrics: None,
+            body_capture: None,
+            admin: None,
+            openapi: OpenApi::default(),
+            compression: None,
+            seeds: Seeds::default(),
+            tenancy: None,
+            profile: None,
+            log_format: None,
+            error_details: None,
+            debug_errors: None,
+            docs: None,
+            docs_assets: DocsAssets::default(),
+            cors: None,
+            rejections: None,
+            load_shedding: None,
+            sampler: None,
+            access_log: false,
+            proxy_protocol: false,
+            mock_unimplemented: false,
+            unknown_fields: None,
+            workers: Workers::default(),
+            waits: DependencyWaits::new(),
         }
     }
 
@@ -54,13 +152,821 @@ impl App {
         &self.router
     }
 
+    /// Add a route to the application
+    pub fn route(mut self, path: &str, method_router: MethodRouter) -> Self {
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    /// Serve `router` on the priority lane
+    ///
+    /// Priority lane routes skip the app's middleware (load shedding,
+    /// timeouts, tenancy, compression, ...) and only get the container, so
+    /// probes and operational endpoints keep answering while the app is
+    /// overloaded. Keep their handlers cheap. The admin endpoints are served
+    /// on this lane.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .load_shedding(LoadShedder::new())
+    ///     .priority_lane(health::routes(readiness.clone()));
+    /// ```
+    pub fn priority_lane(mut self, router: Router) -> Self {
+        self.lane = self.lane.merge(router);
+        self
+    }
+
+    /// Redirect every request for `from` to `to` with the given status
+    ///
+    /// Useful for declaring URL migrations in bulk. The query string of the
+    /// original request is carried over.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `status` is not one of 301, 302, 303, 307 or 308.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .redirect("/old-users", "/users", 308)
+    ///     .redirect("/blog", "https://blog.example.com", 301);
+    /// ```
+    pub fn redirect(self, from: &str, to: &str, status: u16) -> Self {
+        let status = StatusCode::from_u16(status)
+            .ok()
+            .filter(|&status| redirect::Redirect::is_redirect_status(status))
+            .unwrap_or_else(|| panic!("{} is not a redirect status", status));
+
+        let target = to.to_string();
+        self.route(
+            from,
+            any(move |req: Request| redirect::redirect_handler(status, target.clone(), req)),
+        )
+    }
+
+    /// Merge an existing router into the application
+    pub fn merge(mut self, router: Router) -> Self {
+        self.router = self.router.merge(router);
+        self
+    }
+
+    /// Mount a child application under a path prefix, merging its services
+    ///
+    /// The child's services are added to this application's container.
+    /// Services already registered here take precedence over the child's.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let admin = App::new().route("/users", routing::get(list_users));
+    /// let app = App::new().nest("/admin", admin); // serves /admin/users
+    /// ```
+    pub fn nest(mut self, prefix: &str, child: App) -> Self {
+        self.container.inherit_from(&child.container);
+        self.add_mounts(prefix, &child.mounts);
+        self.router = Self::mount(self.router, prefix, child.router);
+        self
+    }
+
+    /// Register a module's services and routes with `build`
+    ///
+    /// The time `build` takes is recorded as the `module:<name>` phase of the
+    /// boot report.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .module("billing", |app| app.route("/invoices", get(list_invoices)))
+    ///     .module("search", search::register);
+    /// ```
+    pub fn module(self, name: &str, build: impl FnOnce(App) -> App) -> Self {
+        boot::time(format!("module:{}", name), || build(self))
+    }
+
+    /// Mount a child application under a path prefix, keeping its services
+    /// scoped to it
+    ///
+    /// The child's container inherits every service registered here so far,
+    /// with the child's own registrations taking precedence. Requests under
+    /// the prefix resolve services from the child's container; the rest of
+    /// the application never sees the child's registrations.
+    pub fn nest_scoped(mut self, prefix: &str, child: App) -> Self {
+        let mut scoped = child.container;
+        scoped.inherit_from(&self.container);
+
+        let child_router = child.router.layer(Extension(Arc::new(scoped)));
+        self.add_mounts(prefix, &child.mounts);
+        self.router = Self::mount(self.router, prefix, child_router);
+        self
+    }
+
+    /// Add a group of routes sharing a path prefix, layers and guards
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new().group("/api", |g| {
+    ///     g.layer(auth_layer)
+    ///         .route("/users", routing::get(list_users))
+    ///         .route("/users/{id}", routing::get(get_user))
+    /// });
+    /// ```
+    pub fn group(mut self, prefix: &str, build: impl FnOnce(RouteGroup) -> RouteGroup) -> Self {
+        let group = build(RouteGroup::new()).finish();
+        self.add_mounts(prefix, &[]);
+        self.router = Self::mount(self.router, prefix, group);
+        self
+    }
+
+    // remember a mount prefix, and those of the child mounted under it, for
+    // finding where registry routes are served
+    fn add_mounts(&mut self, prefix: &str, child: &[String]) {
+        let prefix = prefix.trim_end_matches('/');
+        let nested = child.iter().map(|mount| format!("{}{}", prefix, mount));
+        for mount in nested.chain((!prefix.is_empty()).then(|| prefix.to_string())) {
+            if !self.mounts.contains(&mount) {
+                self.mounts.push(mount);
+            }
+        }
+    }
+
+    // nest a router under a prefix, merging it when the prefix is the root
+    fn mount(router: Router, prefix: &str, child: Router) -> Router {
+        if prefix.is_empty() || prefix == "/" {
+            router.merge(child)
+        } else {
+            router.nest(prefix, child)
+        }
+    }
+
+    /// Serve a router for requests addressed to a specific host
+    ///
+    /// The pattern is either an exact host (`admin.example.com`) or a
+    /// wildcard subdomain (`*.example.com`). Host routers are tried in
+    /// registration order; requests matching none of them use the routes
+    /// added directly to the application.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .route("/", routing::get(home))
+    ///     .host("admin.example.com", admin_router);
+    /// ```
+    pub fn host(mut self, pattern: &str, router: Router) -> Self {
+        self.hosts.push((HostPattern::new(pattern), router));
+        self
+    }
+
+    /// Get the host patterns registered with `host()`, in matching order
+    pub fn hosts(&self) -> Vec<&HostPattern> {
+        self.hosts.iter().map(|(pattern, _)| pattern).collect()
+    }
+
+    /// Set how paths with a trailing slash are handled (default: strict)
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Abandon requests that take longer than `timeout` with 408
+    ///
+    /// The deadline is exposed to services through `RequestContext`.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Fire the `RequestContext` cancellation of abandoned requests
+    /// (default: false)
+    ///
+    /// A request is abandoned when its timeout passes or when the server
+    /// drops it because the client went away. The handler future is dropped
+    /// either way; with this enabled, spawned tasks and `CpuPool` jobs
+    /// working for the request see `RequestContext::is_cancelled` and can
+    /// stop, and jobs still waiting for the pool are not started.
+    pub fn cancel_abandoned_requests(mut self, enabled: bool) -> Self {
+        self.cancel_abandoned = enabled;
+        self
+    }
+
+    /// Generate request ids (and other framework ids) with `generator`
+    ///
+    /// The generator is also registered in the container as
+    /// `dyn IdGenerator`. Without this, ids are UUID v7.
+    pub fn id_generator(mut self, generator: Arc<dyn IdGenerator>) -> Self {
+        self.container
+            .register::<dyn IdGenerator>(generator.clone());
+        self.id_generator = Some(generator);
+        self
+    }
+
+    /// Record per-route latency histograms into `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Run `#[blocking]` handlers and `CpuPool` extractions on `pool`
+    pub fn cpu_pool(mut self, pool: crate::cpu::CpuPool) -> Self {
+        self.container.register(Arc::new(pool));
+        self
+    }
+
+    /// Register `bulkheads` in the container
+    pub fn bulkheads(mut self, bulkheads: crate::bulkhead::Bulkheads) -> Self {
+        self.container.register(Arc::new(bulkheads));
+        self
+    }
+
+    /// Reject low priority requests with 503 while the server is saturated
+    ///
+    /// See `LoadShedder` for how saturation is measured and `#[priority]`
+    /// for marking routes.
+    pub fn load_shedding(mut self, shedder: crate::shed::LoadShedder) -> Self {
+        self.container.register(Arc::new(shedder.clone()));
+        self.load_shedding = Some(shedder);
+        self
+    }
+
+    /// Sample the traces of requests with `sampler`
+    ///
+    /// Wrap the trace exporter layer in `sampling::layer` to keep unsampled
+    /// traces out of it.
+    pub fn trace_requests(mut self, sampler: Sampler) -> Self {
+        self.sampler = Some(Arc::new(sampler));
+        self
+    }
+
+    /// Log every request at INFO with the `access` target (default: false)
+    ///
+    /// Each line carries the method, route, status, latency, request id,
+    /// principal and tenant.
+    pub fn access_log(mut self, enabled: bool) -> Self {
+        self.access_log = enabled;
+        self
+    }
+
+    /// Require a PROXY protocol (v1 or v2) header on every connection
+    /// accepted by `serve` (default: false)
+    ///
+    /// Enable this behind HAProxy or a network load balancer in TCP mode, so
+    /// `ClientIp` and the access log report the original client address.
+    /// Connections without a valid header are dropped.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Apply `sockets`' heartbeat, idle and connection limit settings to the
+    /// app's WebSocket routes
+    #[cfg(feature = "ws")]
+    pub fn websockets(mut self, sockets: crate::ws::WebSockets) -> Self {
+        self.container.register(Arc::new(sockets));
+        self
+    }
+
+    /// Set the title, version and description of the OpenAPI document
+    pub fn openapi(mut self, info: OpenApi) -> Self {
+        self.openapi = info;
+        self
+    }
+
+    /// Set how `Json<T>` bodies treat fields `T` does not declare
+    ///
+    /// Routes and groups can override it with an
+    /// `Extension(UnknownFields::...)` layer.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new().unknown_json_fields(UnknownFields::Reject);
+    /// ```
+    pub fn unknown_json_fields(mut self, mode: UnknownFields) -> Self {
+        self.unknown_fields = Some(mode);
+        self
+    }
+
+    /// Answer requests for declared routes that have no handler with their
+    /// `Example`, for developing against the contract before the backend
+    /// exists
+    ///
+    /// Meant for the `Dev` profile: ignored with an error log in `Prod`, and
+    /// logged as a warning in any other profile. Requests matching no
+    /// declared route still reach the app's fallback. Routes are mocked at
+    /// their declared path and under each `nest` or `group` prefix. See
+    /// [`mock`](crate::mock).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .route("/orders", routing::get(list_orders))
+    ///     .mock_unimplemented();
+    /// ```
+    pub fn mock_unimplemented(mut self) -> Self {
+        self.mock_unimplemented = true;
+        self
+    }
+
+    // whether declared routes without a handler are mocked; never in Prod
+    fn mocking(&self) -> bool {
+        self.mock_unimplemented && self.profile != Some(Profile::Prod)
+    }
+
+    /// Get the routes declared with route macros that this application
+    /// serves, at the path and on the hosts it serves them
+    pub fn routes(&self) -> ServedRoutes {
+        ServedRoutes::discover(
+            RouteRegistry::global(),
+            &self.mounts,
+            &self.router,
+            &self.hosts,
+        )
+    }
+
+    /// Generate the OpenAPI document for the routes declared with route
+    /// macros that this application serves
+    ///
+    /// Routes are documented at the path they are served under; routes only
+    /// served on some hosts list those hosts as the operation's `servers`.
+    /// With `mock_unimplemented`, declared routes without a handler are
+    /// documented too. Error codes declared with `#[derive(ErrorCode)]` are
+    /// included as the `ErrorCode` schema.
+    pub fn openapi_spec(&self) -> serde_json::Value {
+        let mut routes = self.routes();
+        if self.mocking() {
+            routes.add_unserved(RouteRegistry::global());
+        }
+        let mut document = self.openapi.document_served(&routes);
+        codes::embed(&mut document, &codes::catalog());
+        document
+    }
+
+    /// Serve the catalog of declared error codes as JSON at `path`
+    pub fn error_catalog(mut self, path: &str) -> Self {
+        let catalog = Json(codes::catalog_json(&codes::catalog()));
+        self.router = self.router.route(path, get(move || async move { catalog }));
+        self
+    }
+
+    /// Log request and response bodies through `capture` while it is enabled
+    pub fn capture_bodies(mut self, capture: BodyCapture) -> Self {
+        self.body_capture = Some(capture);
+        self
+    }
+
+    /// Compress response bodies according to `compression`
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Resolve the tenant of every request with `resolver`
+    ///
+    /// The tenant is recorded in the `RequestContext`, and `Inject<T>` prefers
+    /// services registered with `Container::register_for_tenant`.
+    pub fn tenancy(mut self, resolver: TenantResolver) -> Self {
+        self.tenancy = Some(resolver);
+        self
+    }
+
+    /// Use the defaults of `profile` for logging, error detail, docs and CORS
+    ///
+    /// Settings made with `log_format`, `error_details`, `docs` and `cors`
+    /// take precedence, whether called before or after. See `Profile` for
+    /// the defaults.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new()
+    ///     .with_profile(Profile::from_env())
+    ///     .cors(CorsLayer::new().allow_origin(["https://app.example.com".parse()?]));
+    /// ```
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// The profile set with `with_profile`
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+
+    /// Use `format` for the log subscriber installed by `serve`
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
+    }
+
+    /// Include the message and details of 5xx `ApiError`s in responses
+    ///
+    /// When disabled, they are logged and the body only carries the status
+    /// reason and error code. Enabled unless a `Prod` profile is set.
+    pub fn error_details(mut self, enabled: bool) -> Self {
+        self.error_details = Some(enabled);
+        self
+    }
+
+    /// Include the cause chain and backtrace of 5xx `ApiError`s in responses
+    ///
+    /// They are added as `debug` to the error envelope, and backtraces are
+    /// captured by `ApiError::with_source` even without `RUST_BACKTRACE`.
+    /// Otherwise they are only logged. Enabled by the `Dev` profile; has no
+    /// effect when `error_details` is disabled.
+    pub fn debug_errors(mut self, enabled: bool) -> Self {
+        self.debug_errors = Some(enabled);
+        self
+    }
+
+    /// Serve a Swagger UI at `path` and the OpenAPI document at
+    /// `{path}/openapi.json`
+    pub fn docs(mut self, path: &str) -> Self {
+        self.docs = Some(Some(path.trim_end_matches('/').to_string()));
+        self
+    }
+
+    /// Do not serve the docs UI, even in the `Dev` profile
+    pub fn without_docs(mut self) -> Self {
+        self.docs = Some(None);
+        self
+    }
+
+    /// Load the docs UI's Swagger UI files from `assets` instead of the
+    /// pinned unpkg release
+    pub fn docs_assets(mut self, assets: DocsAssets) -> Self {
+        self.docs_assets = assets;
+        self
+    }
+
+    /// Answer cross-origin requests according to `cors`
+    pub fn cors(mut self, cors: CorsLayer) -> Self {
+        self.cors = Some(Some(cors));
+        self
+    }
+
+    /// Do not add CORS headers, even in the `Dev` profile
+    pub fn without_cors(mut self) -> Self {
+        self.cors = Some(None);
+        self
+    }
+
+    /// Render every extractor rejection with `render`
+    ///
+    /// Applies to 400, 413, 415 and 422 responses that are axum's plain-text
+    /// rejections (`Json`, `Query`, `Path`, ...) or `ApiError`s marked as
+    /// rejections by extractors, so all client errors share one envelope.
+    /// Handlers' own `ApiError`s are sent as they are, and the original
+    /// response's headers are kept.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = App::new().on_rejection(|rejection| {
+    ///     let body = json!({ "ok": false, "reason": rejection.code(), "detail": rejection.message() });
+    ///     (rejection.status(), Json(body)).into_response()
+    /// });
+    /// ```
+    pub fn on_rejection(
+        mut self,
+        render: impl Fn(Rejection) -> axum::response::Response + Send + Sync + 'static,
+    ) -> Self {
+        self.rejections = Some(RejectionHandler::new(render));
+        self
+    }
+
+    /// Install the log subscriber for the configured log format or profile
+    ///
+    /// Returns false if neither is set or a subscriber is already installed.
+    /// `serve` calls it; call it yourself when starting the server another
+    /// way.
+    pub fn init_logging(&self) -> bool {
+        self.log_format
+            .or(self.profile.map(Profile::log_format))
+            .is_some_and(profile::init_logging)
+    }
+
+    /// Register a background worker, run by `run_workers_only`
+    ///
+    /// `serve` leaves workers out, so web and worker deployments can share
+    /// one `App` setup. See `Worker`.
+    pub fn worker(mut self, name: &str, worker: impl Worker) -> Self {
+        self.workers.add(name, worker);
+        self
+    }
+
+    /// Run the registered workers without binding an HTTP port
+    ///
+    /// Runs until Ctrl+C or SIGTERM, until a worker fails or until every
+    /// worker returns; workers still running are then asked to stop and get
+    /// 30 seconds to finish. Fails if any worker failed, so the process can
+    /// exit with an error.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let app = wire_up(App::new());
+    /// match std::env::args().nth(1).as_deref() {
+    ///     Some("worker") => app.run_workers_only().await?,
+    ///     _ => app.serve("0.0.0.0:3000").await?,
+    /// }
+    /// ```
+    pub async fn run_workers_only(mut self) -> Result<()> {
+        self.init_logging();
+        self.wait_for_dependencies().await?;
+        let workers = std::mem::take(&mut self.workers);
+        tracing::info!("Running {} workers without HTTP listener", workers.len());
+        boot::emit();
+        workers
+            .run(
+                Arc::new(self.container),
+                shutdown::default_signal(),
+                worker::DEFAULT_STOP_TIMEOUT,
+            )
+            .await
+    }
+
+    /// Wait up to `timeout` for the dependency `name` to pass `check`
+    /// before starting
+    ///
+    /// The check is retried with exponential backoff, and startup fails if
+    /// it still fails after `timeout`, so an instance started before its
+    /// database waits for it instead of crash-looping. `serve` runs the
+    /// waits as a start hook once the listener is bound, so probes on the
+    /// priority lane answer meanwhile; `run_workers_only` runs them before
+    /// starting workers. Waits run concurrently.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let db = pool.clone();
+    /// let app = App::new().wait_for(
+    ///     "db",
+    ///     move || {
+    ///         let db = db.clone();
+    ///         async move { db.ping().await.map_err(|e| Error::other(e.to_string())) }
+    ///     },
+    ///     Duration::from_secs(60),
+    /// );
+    /// ```
+    pub fn wait_for(mut self, name: &str, check: impl HealthCheck, timeout: Duration) -> Self {
+        self.waits.add(name, check, timeout);
+        self
+    }
+
+    /// The dependencies registered with `wait_for`, for serving the router
+    /// with `RustAPI`: register them with `RustAPI::on_start`, so readiness
+    /// stays false until they are up
+    pub fn dependency_waits(&self) -> DependencyWaits {
+        self.waits.clone()
+    }
+
+    /// Wait for the dependencies registered with `wait_for`
+    ///
+    /// Timed as the `dependencies` phase of the boot report.
+    pub async fn wait_for_dependencies(&self) -> Result<()> {
+        self.waits.wait().await
+    }
+
+    /// Register a seeder to run on `seed`
+    pub fn seeder(mut self, seeder: impl Seeder) -> Self {
+        self.seeds.add(seeder);
+        self
+    }
+
+    /// Record applied seeders in `markers` instead of in memory
+    pub fn seed_markers(mut self, markers: Arc<dyn SeedMarkers>) -> Self {
+        self.seeds.set_markers(markers);
+        self
+    }
+
+    /// Seed for `environment` instead of the one named by `APP_ENV`
+    pub fn seed_environment(mut self, environment: impl Into<String>) -> Self {
+        self.seeds.set_environment(environment.into());
+        self
+    }
+
+    /// Run the pending seeders of the current environment
+    ///
+    /// Seeders run in registration order with access to the container;
+    /// returns the names of those that ran. Call it after migrations.
+    pub async fn seed(&self) -> Result<Vec<String>> {
+        self.seeds.run(&self.container).await
+    }
+
+    /// Mount the admin/debug endpoints under `prefix`
+    ///
+    /// Nothing is mounted in release builds unless the `Admin` configuration
+    /// allows it. See `Admin` for the available endpoints.
+    pub fn enable_admin(mut self, prefix: &str, admin: Admin) -> Self {
+        self.admin = Some((prefix.to_string(), admin));
+        self
+    }
+
     /// Build and return the configured router
+    ///
+    /// The container is attached to every request so that `Inject<T>` can
+    /// resolve services from it, and every request outside the priority
+    /// lane runs inside a `RequestContext`. Timed as the `router` phase of
+    /// the boot report.
     pub fn build(self) -> Router {
-        self.router
+        boot::set_routes(RouteRegistry::global().len());
+        boot::time("router", || self.assemble())
+    }
+
+    // apply the configured middleware and assemble the router
+    fn assemble(mut self) -> Router {
+        if let Some((prefix, admin)) = self.admin.take() {
+            if admin.is_enabled() {
+                let routes = self.routes();
+                let admin = admin.into_router(&self.container, &routes, self.metrics.clone());
+                self.lane = self.lane.nest(&prefix, admin);
+            }
+        }
+
+        let profile = self.profile;
+        if self.mock_unimplemented {
+            match profile {
+                Some(Profile::Prod) => tracing::error!(
+                    "App::mock_unimplemented is ignored in the Prod profile; \
+                     declared routes without a handler are not mocked"
+                ),
+                Some(Profile::Dev) => {
+                    tracing::info!("Mocking declared routes that have no handler")
+                }
+                _ => tracing::warn!(
+                    "Mocking declared routes that have no handler outside the Dev profile; \
+                     their requests get example responses"
+                ),
+            }
+        }
+        if self.mocking() {
+            let unserved =
+                mock::Unserved::find(RouteRegistry::global(), &self.routes(), &self.mounts);
+            self.router = mock::mock_unimplemented(self.router, unserved);
+        }
+
+        let docs = self
+            .docs
+            .take()
+            .unwrap_or_else(|| profile.and_then(Profile::docs_path).map(str::to_string));
+        if let Some(path) = docs {
+            let spec = format!("{}/openapi.json", path);
+            if profile == Some(Profile::Prod) && self.docs_assets.unverified_cdn() {
+                tracing::warn!(
+                    "Docs UI loads scripts from a CDN without integrity hashes; \
+                     set App::docs_assets"
+                );
+            }
+            let page = Html(profile::docs_page(&spec, &self.docs_assets));
+            let document = Json(self.openapi_spec());
+            self.router = self
+                .router
+                .route(&path, get(move || async move { page }))
+                .route(&spec, get(move || async move { document }));
+        }
+        let reporting = profile::ErrorReporting {
+            details: self
+                .error_details
+                .or(profile.map(Profile::error_details))
+                .unwrap_or(true),
+            debug: self
+                .debug_errors
+                .or(profile.map(Profile::debug_errors))
+                .unwrap_or(false),
+        };
+        let cors = self
+            .cors
+            .take()
+            .unwrap_or_else(|| profile.and_then(Profile::cors));
+
+        // lets middleware resolve routes mounted under a prefix
+        let served = Arc::new(self.routes());
+
+        let policy = self.trailing_slash;
+        let mut settings = context::ContextSettings {
+            timeout: self.request_timeout,
+            cancel_abandoned: self.cancel_abandoned,
+            sampler: self.sampler,
+            ..Default::default()
+        };
+        if let Some(ids) = self.id_generator {
+            settings.ids = ids;
+        }
+        // the context layer sits inside routing so the matched route is known
+        let metrics = self.metrics;
+        let body_capture = self.body_capture;
+        let tenancy = self.tenancy;
+        let deprecations = deprecation::any_deprecated();
+        let read_only = db::any_read_only();
+        let shedding = self.load_shedding;
+        let access_log = self.access_log;
+        let unknown_fields = self.unknown_fields;
+        let prepare = |mut r: Router| {
+            if let Some(mode) = unknown_fields {
+                r = r.layer(Extension(mode));
+            }
+            if read_only {
+                r = r.layer(middleware::from_fn(db::read_only_routes));
+            }
+            if deprecations {
+                r = r.layer(middleware::from_fn_with_state(
+                    metrics.clone(),
+                    deprecation::deprecation_headers,
+                ));
+            }
+            if let Some(capture) = &body_capture {
+                r = r.layer(middleware::from_fn_with_state(
+                    capture.clone(),
+                    capture::capture_bodies,
+                ));
+            }
+            if let Some(metrics) = &metrics {
+                r = r.layer(middleware::from_fn_with_state(
+                    metrics.clone(),
+                    metrics::record_latency,
+                ));
+            }
+            if let Some(resolver) = &tenancy {
+                r = r.layer(middleware::from_fn_with_state(
+                    resolver.clone(),
+                    tenant::resolve_tenant,
+                ));
+            }
+            if let Some(shedder) = &shedding {
+                r = r.layer(middleware::from_fn_with_state(
+                    shedder.clone(),
+                    crate::shed::shed_load,
+                ));
+            }
+            if access_log {
+                r = r.layer(middleware::from_fn(access::log_access));
+            }
+            let r = r.layer(middleware::from_fn_with_state(
+                settings.clone(),
+                context::scope_request,
+            ));
+            router::normalize_trailing_slash(r, policy)
+        };
+        let default = prepare(self.router);
+        let hosts = self
+            .hosts
+            .into_iter()
+            .map(|(pattern, r)| (pattern, prepare(r)))
+            .collect();
+
+        // one registry per app, drained by the server serving it
+        #[cfg(feature = "ws")]
+        if self.container.resolve::<crate::ws::WebSockets>().is_none() {
+            self.container
+                .register(Arc::new(crate::ws::WebSockets::new()));
+        }
+        // outbound calls carry the request id and give up at the deadline
+        if let Some(client) = self.container.resolve::<dyn HttpClient>() {
+            self.container
+                .register::<dyn HttpClient>(Arc::new(Correlated::new(client)));
+        }
+        let container = Arc::new(self.container);
+        let mut router = host::route_by_host(default, hosts)
+            .layer(Extension(container.clone()))
+            .layer(Extension(served));
+        router = router.layer(middleware::from_fn_with_state(
+            reporting,
+            profile::report_server_errors,
+        ));
+        if let Some(handler) = self.rejections {
+            router = router.layer(middleware::from_fn_with_state(
+                handler,
+                rejection::render_rejections,
+            ));
+        }
+        if let Some(compression) = self.compression {
+            router = router.layer(middleware::from_fn_with_state(
+                compression,
+                compression::compress,
+            ));
+        }
+        if let Some(cors) = cors {
+            router = router.layer(cors);
+        }
+        // merged last, so none of the layers above apply to it; merged into
+        // the lane so the app's fallback, layered above, is the one kept
+        self.lane.layer(Extension(container)).merge(router)
     }
 
     /// Start the HTTP server on the given address
     ///
+    /// Binds the listener, then waits for the dependencies registered with
+    /// `wait_for` as a start hook, failing if they do not come up. Shuts
+    /// down gracefully on Ctrl+C or SIGTERM, closing WebSocket connections
+    /// and waiting up to their drain timeout. Use `RustAPI` for shutdown
+    /// delays, deadlines and readiness.
+    ///
     /// # Example
     ///
     /// ```ignore
@@ -68,27 +974,25 @@ impl App {
     /// ```
     pub async fn serve(self, addr: impl Into<SocketAddr>) -> Result<()> {
         let addr = addr.into();
-        let listener = self.create_listener_at(addr).await?;
-        let router = self.router;
-        Self::run_server_on(listener, router).await
+        self.init_logging();
+        let listener = boot::time_async("listener", self.create_listener_at(addr)).await?;
+        let proxy_protocol = self.proxy_protocol;
+        let waits = self.dependency_waits();
+        let router = self.build();
+        RustAPI::new(router)
+            .proxy_protocol(proxy_protocol)
+            .on_start(waits)
+            .serve_with_listener(listener)
+            .await
     }
 
     // create a TCP listener on the given address
     async fn create_listener_at(&self, addr: SocketAddr) -> Result<tokio::net::TcpListener> {
         tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+            tracing::error!("Failed to bind to {}: {}", addr, e);
             crate::error::Error::server_error(format!("Failed to bind to {}: {}", addr, e))
         })
     }
-
-    // run the axum server with the given listener and router
-    async fn run_server_on(listener: tokio::net::TcpListener, router: Router) -> Result<()> {
-        let addr = listener.local_addr().unwrap();
-        tracing::info!("Server running on http://{}", addr);
-
-        axum::serve(listener, router)
-            .await
-            .map_err(|e| crate::error::Error::server_error(format!("Server error: {}", e)))
-    }
 }
 
 impl Default for App {
@@ -99,7 +1003,11 @@ impl Default for App {
 
 #[cfg(test)]
 mod tests {
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
     use super::*;
+    use crate::{context::RequestContext, extract::Inject};
 
     #[test]
     fn test_app_creation() {
@@ -112,4 +1020,459 @@ mod tests {
         let app = App::default();
         assert!(app.container().is_empty());
     }
+
+    struct Greeting(&'static str);
+
+    impl crate::di::Injectable for Greeting {}
+
+    async fn greet(Inject(greeting): Inject<Greeting>) -> &'static str {
+        greeting.0
+    }
+
+    async fn call(router: Router, path: &str) -> (StatusCode, String) {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_nest_merges_child_services() {
+        let mut child = App::new().route("/hello", get(greet));
+        child
+            .container_mut()
+            .register_factory(|| Greeting("from child"));
+
+        let app = App::new().nest("/admin", child);
+        assert!(app.container().contains::<Greeting>());
+
+        let (status, body) = call(app.build(), "/admin/hello").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "from child");
+    }
+
+    #[tokio::test]
+    async fn test_nest_scoped_isolates_child_services() {
+        let mut child = App::new().route("/hello", get(greet));
+        child
+            .container_mut()
+            .register_factory(|| Greeting("from child"));
+
+        let mut app = App::new();
+        app.container_mut()
+            .register_factory(|| Greeting("from parent"));
+        let app = app.nest_scoped("/admin", child).route("/hello", get(greet));
+        let router = app.build();
+
+        let (_, body) = call(router.clone(), "/admin/hello").await;
+        assert_eq!(body, "from child");
+        let (_, body) = call(router, "/hello").await;
+        assert_eq!(body, "from parent");
+    }
+
+    #[tokio::test]
+    async fn test_group_mounts_under_prefix() {
+        let app = App::new()
+            .group("/api", |g| g.route("/ping", get(|| async { "pong" })))
+            .group("/", |g| g.route("/root", get(|| async { "root" })));
+        let router = app.build();
+
+        let (status, body) = call(router.clone(), "/api/ping").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "pong");
+        let (status, _) = call(router, "/root").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_json_fields_per_app_and_route() {
+        #[derive(serde::Deserialize)]
+        struct Name {
+            #[allow(dead_code)]
+            name: String,
+        }
+
+        async fn create(crate::json::Json(_): crate::json::Json<Name>) -> &'static str {
+            "created"
+        }
+
+        let router = App::new()
+            .route("/strict", axum::routing::post(create))
+            .route(
+                "/lenient",
+                axum::routing::post(create).layer(Extension(UnknownFields::Ignore)),
+            )
+            .unknown_json_fields(UnknownFields::Reject)
+            .build();
+        let post = |path: &str| {
+            Request::post(path)
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"ada","nmae":"typo"}"#))
+                .unwrap()
+        };
+
+        let response = router.clone().oneshot(post("/strict")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = router.oneshot(post("/lenient")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_policy() {
+        let router = App::new()
+            .route("/hello", get(|| async { "hi" }))
+            .trailing_slash(TrailingSlash::Rewrite)
+            .build();
+
+        let (status, body) = call(router, "/hello/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "hi");
+    }
+
+    #[tokio::test]
+    async fn test_host_routing() {
+        let admin = Router::new().route("/hello", get(greet));
+        let mut app = App::new()
+            .route("/hello", get(|| async { "main" }))
+            .host("*.admin.example.com", admin);
+        app.container_mut().register_factory(|| Greeting("admin"));
+        assert_eq!(app.hosts()[0].as_str(), "*.admin.example.com");
+
+        let request = Request::builder()
+            .uri("/hello")
+            .header("host", "eu.admin.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.build().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "admin");
+    }
+
+    inventory::submit! {
+        crate::registry::RouteDef::new("GET", "/app-test/reports", "app_test::reports")
+    }
+
+    inventory::submit! {
+        crate::registry::RouteDef::new("GET", "/app-test/stats", "app_test::stats")
+    }
+
+    #[test]
+    fn test_routes_at_served_paths() {
+        let child = App::new().group("/v1", |g| {
+            g.route("/app-test/reports", get(|| async { "reports" }))
+        });
+        let stats = Router::new().route("/app-test/stats", get(|| async { "stats" }));
+        let app = App::new()
+            .nest("/api", child)
+            .host("admin.example.com", stats);
+
+        let routes = app.routes();
+        let reports = routes.get("GET", "/api/v1/app-test/reports").unwrap();
+        assert_eq!(reports.route.path, "/app-test/reports");
+        assert!(reports.hosts.is_empty());
+        let stats = routes.get("GET", "/app-test/stats").unwrap();
+        assert_eq!(stats.hosts[0].as_str(), "admin.example.com");
+
+        let spec = app.openapi_spec();
+        assert!(spec["paths"].get("/app-test/reports").is_none());
+        assert_eq!(
+            spec["paths"]["/app-test/stats"]["get"]["servers"][0]["url"],
+            "https://admin.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_admin_lists_served_routes() {
+        let child = App::new().route("/app-test/reports", get(|| async { "reports" }));
+        let router = App::new()
+            .nest("/api", child)
+            .enable_admin("/admin", crate::admin::Admin::new().bearer_token("secret"))
+            .build();
+        let request = Request::builder()
+            .uri("/admin/routes")
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        // `/app-test/stats` is declared but not mounted by this app
+        let routes: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            routes,
+            serde_json::json!([{
+                "method": "GET",
+                "path": "/api/app-test/reports",
+                "handler": "app_test::reports",
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_provides_request_context() {
+        let router = App::new()
+            .route(
+                "/users/{id}",
+                get(|ctx: RequestContext| async move { ctx.route().unwrap_or("-").to_string() }),
+            )
+            .request_timeout(Duration::from_secs(5))
+            .build();
+        let request = Request::builder()
+            .uri("/users/7")
+            .header("x-request-id", "req-1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "req-1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "/users/{id}");
+    }
+
+    #[tokio::test]
+    async fn test_id_generator() {
+        let app = App::new()
+            .route("/", get(|| async { "ok" }))
+            .id_generator(Arc::new(crate::ids::SequentialIds::new("req-")));
+        assert!(app.container().contains::<dyn crate::ids::IdGenerator>());
+
+        let router = app.build();
+        for expected in ["req-1", "req-2"] {
+            let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers()["x-request-id"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redirect_route() {
+        let router = App::new().redirect("/old", "/new", 301).build();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/old?x=1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()["location"], "/new?x=1");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a redirect status")]
+    fn test_redirect_route_rejects_bad_status() {
+        let _ = App::new().redirect("/old", "/new", 200);
+    }
+
+    #[tokio::test]
+    async fn test_nest_scoped_inherits_parent_services() {
+        let child = App::new().route("/hello", get(greet));
+        let mut app = App::new();
+        app.container_mut()
+            .register_factory(|| Greeting("from parent"));
+
+        let (_, body) = call(app.nest_scoped("/admin", child).build(), "/admin/hello").await;
+        assert_eq!(body, "from parent");
+    }
+
+    // request ids recorded on spans and events, by span name or event target
+    #[derive(Clone, Default)]
+    struct RequestIds(Arc<std::sync::Mutex<Vec<(String, String)>>>);
+
+    struct IdVisitor(Option<String>);
+
+    impl tracing::field::Visit for IdVisitor {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            if field.name() == "request_id" {
+                self.0 = Some(value.to_string());
+            }
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "request_id" {
+                self.0 = Some(format!("{:?}", value));
+            }
+        }
+    }
+
+    impl RequestIds {
+        fn push(&self, sink: &str, id: IdVisitor) {
+            if let Some(id) = id.0 {
+                self.0.lock().unwrap().push((sink.to_string(), id));
+            }
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for RequestIds {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut id = IdVisitor(None);
+            attrs.record(&mut id);
+            self.push(attrs.metadata().name(), id);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut id = IdVisitor(None);
+            event.record(&mut id);
+            self.push(event.metadata().target(), id);
+        }
+    }
+
+    // records the request id sent to the upstream
+    #[derive(Clone, Default)]
+    struct Upstream(Arc<std::sync::Mutex<Option<String>>>);
+
+    impl crate::client::HttpClient for Upstream {
+        fn send(
+            &self,
+            request: crate::client::ClientRequest,
+        ) -> crate::lifecycle::BoxFuture<
+            '_,
+            std::result::Result<crate::client::ClientResponse, crate::client::ClientError>,
+        > {
+            *self.0.lock().unwrap() = request
+                .headers()
+                .get("x-request-id")
+                .map(|id| id.to_str().unwrap().to_string());
+            Box::pin(async { Ok(crate::client::ClientResponse::new(Default::default())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_id_correlates_all_sinks() {
+        use tracing_subscriber::prelude::*;
+
+        use crate::{
+            audit::{Audit, LogSink},
+            client::HttpClient,
+            error::ApiError,
+        };
+
+        let ids = RequestIds::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(ids.clone()));
+        let upstream = Upstream::default();
+
+        let mut app = App::new().access_log(true).route(
+            "/orders",
+            axum::routing::post(
+                |Inject(audit): Inject<Audit>, Inject(client): Inject<dyn HttpClient>| async move {
+                    audit.record("order.create", "1").await?;
+                    let notify = axum::http::Request::post("http://upstream/notify")
+                        .body(Default::default())
+                        .unwrap();
+                    client.send(notify).await?;
+                    Err::<(), _>(ApiError::internal("Inventory unavailable"))
+                },
+            ),
+        );
+        app.container_mut()
+            .register(Arc::new(Audit::new(Arc::new(LogSink))));
+        app.container_mut()
+            .register::<dyn HttpClient>(Arc::new(upstream.clone()));
+
+        let request = Request::post("/orders").body(Body::empty()).unwrap();
+        let response = app.build().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        assert_eq!(upstream.0.lock().unwrap().as_deref(), Some(id.as_str()));
+        let ids = ids.0.lock().unwrap().clone();
+        let sinks: Vec<&str> = ids.iter().map(|(sink, _)| sink.as_str()).collect();
+        assert_eq!(sinks, ["request", "audit", "access", "rust_api::profile"]);
+        assert!(ids.iter().all(|(_, logged)| *logged == id), "{:?}", ids);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_dependencies() {
+        let up_at = tokio::time::Instant::now() + Duration::from_secs(3);
+        let db = move || async move {
+            match tokio::time::Instant::now() >= up_at {
+                true => Ok(()),
+                false => Err(crate::error::Error::other("connection refused")),
+            }
+        };
+        let app = App::new()
+            .wait_for("db", db, Duration::from_secs(60))
+            .wait_for("cache", || async { Ok(()) }, Duration::from_secs(1));
+        app.wait_for_dependencies().await.unwrap();
+        assert!(tokio::time::Instant::now() >= up_at);
+
+        let app = App::new().wait_for(
+            "db",
+            || async { Err(crate::error::Error::other("connection refused")) },
+            Duration::from_secs(2),
+        );
+        let error = app.wait_for_dependencies().await.unwrap_err();
+        assert!(error.to_string().contains("Dependency db unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_dependencies_are_waited_for_after_binding() {
+        let up = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let app = App::new().wait_for(
+            "db",
+            {
+                let up = up.clone();
+                move || {
+                    let up = up.load(std::sync::atomic::Ordering::SeqCst);
+                    async move {
+                        match up {
+                            true => Ok(()),
+                            false => Err(crate::error::Error::other("connection refused")),
+                        }
+                    }
+                }
+            },
+            Duration::from_secs(60),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = RustAPI::new(Router::new())
+            .health_probes(true)
+            .on_start(app.dependency_waits())
+            .shutdown_signal(std::future::pending());
+        let readiness = server.readiness();
+        let handle = tokio::spawn(server.serve_with_listener(listener));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!readiness.is_ready());
+        assert!(!handle.is_finished());
+        up.store(true, std::sync::atomic::Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !readiness.is_ready() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("dependency wait did not finish");
+        handle.abort();
+
+        let error = App::new()
+            .wait_for(
+                "db",
+                || async { Err(crate::error::Error::other("connection refused")) },
+                Duration::from_millis(300),
+            )
+            .serve(([127, 0, 0, 1], 0))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Dependency db unavailable"));
+    }
 }
@@ -0,0 +1,154 @@
+//! Audit records for RustAPI framework
+//!
+//! `Audit` records who did what to which resource. Every record carries the
+//! request id, principal and tenant of the request it was made in, taken
+//! from the `RequestContext`, so an audit entry can be matched with the
+//! request's access log line, error logs, trace and outbound calls.
+//! Records go to an `AuditSink`: `LogSink` writes them as tracing events
+//! with the `audit` target, `MemorySink` keeps them for tests.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{context::RequestContext, di::Injectable, error::Result, lifecycle::BoxFuture};
+
+/// An audited action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// What was done, e.g. `user.delete`
+    pub action: String,
+    /// What it was done to, e.g. the user's id
+    pub target: String,
+    /// Id of the request it was done in, if any
+    pub request_id: Option<String>,
+    /// Authenticated principal of the request
+    pub principal: Option<String>,
+    /// Tenant of the request
+    pub tenant: Option<String>,
+    /// When it was recorded
+    pub at: SystemTime,
+}
+
+/// Stores audit records
+pub trait AuditSink: Send + Sync + 'static {
+    /// Persist `record`
+    fn write<'a>(&'a self, record: &'a AuditRecord) -> BoxFuture<'a, Result<()>>;
+}
+
+impl Injectable for dyn AuditSink {}
+
+/// `AuditSink` writing records as INFO events with the `audit` target
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl AuditSink for LogSink {
+    fn write<'a>(&'a self, record: &'a AuditRecord) -> BoxFuture<'a, Result<()>> {
+        tracing::info!(
+            target: "audit",
+            action = %record.action,
+            target_id = %record.target,
+            request_id = record.request_id.as_deref(),
+            principal = record.principal.as_deref(),
+            tenant = record.tenant.as_deref(),
+            "Audit"
+        );
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// In-memory `AuditSink`, for tests
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    records: Arc<Mutex<Vec<AuditRecord>>>,
+}
+
+impl MemorySink {
+    /// Create an empty sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records written so far, oldest first
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl AuditSink for MemorySink {
+    fn write<'a>(&'a self, record: &'a AuditRecord) -> BoxFuture<'a, Result<()>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(record.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Audit service stamping records with the current request
+///
+/// Register it in the container and inject it into services.
+///
+/// # Example
+///
+/// ```ignore
+/// app.container_mut().register(Arc::new(Audit::new(Arc::new(LogSink))));
+///
+/// audit.record("user.delete", &id).await?;
+/// ```
+#[derive(Clone)]
+pub struct Audit {
+    sink: Arc<dyn AuditSink>,
+}
+
+impl Injectable for Audit {}
+
+impl Audit {
+    /// Write records to `sink`
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        Self { sink }
+    }
+
+    /// Record `action` on `target`
+    ///
+    /// Outside a request, the record has no request id, principal or tenant.
+    pub async fn record(&self, action: &str, target: &str) -> Result<()> {
+        let ctx = RequestContext::current();
+        let record = AuditRecord {
+            action: action.to_string(),
+            target: target.to_string(),
+            request_id: ctx.as_ref().map(|ctx| ctx.request_id().to_string()),
+            principal: ctx.as_ref().and_then(RequestContext::principal),
+            tenant: ctx.as_ref().and_then(RequestContext::tenant),
+            at: SystemTime::now(),
+        };
+        self.sink.write(&record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_is_stamped_with_the_request() {
+        let sink = MemorySink::new();
+        let audit = Audit::new(Arc::new(sink.clone()));
+
+        let ctx = RequestContext::new("req-1");
+        ctx.set_principal("alice");
+        ctx.scope(audit.record("user.delete", "42")).await.unwrap();
+        audit.record("cache.flush", "all").await.unwrap();
+
+        let records = sink.records();
+        assert_eq!(records[0].action, "user.delete");
+        assert_eq!(records[0].target, "42");
+        assert_eq!(records[0].request_id.as_deref(), Some("req-1"));
+        assert_eq!(records[0].principal.as_deref(), Some("alice"));
+        assert_eq!(records[1].request_id, None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Token authentication for RustAPI framework
+//!
+//! `TokenAuth` is a guard that finds a token on the request, verifies it
+//! and attaches the resulting `Principal` for handlers and gateways. Besides
+//! the `Authorization` header it reads a query parameter and a cookie,
+//! because browsers cannot set headers on WebSocket upgrade requests.
+//! Browsers send cookies along with cross-site requests too, so a
+//! cookie-authenticated request from another origin is only accepted when
+//! the origin is allowed.
+
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{header, request::Parts, Uri},
+};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+use crate::{
+    context::RequestContext, di::Container, error::ApiError, guard::Guard, lifecycle::BoxFuture,
+};
+
+/// Authenticated identity of a request
+///
+/// Attached by `TokenAuth` (or any guard inserting it into the request
+/// extensions) and extracted by handlers; extracting `Principal` from an
+/// unauthenticated request is a 401, extracting `Option<Principal>` is not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    subject: String,
+    claims: Value,
+}
+
+impl Principal {
+    /// Identity with the given subject and no claims
+    pub fn new(subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            claims: Value::Null,
+        }
+    }
+
+    /// Attach the verified claims of the token
+    pub fn with_claims(mut self, claims: Value) -> Self {
+        self.claims = claims;
+        self
+    }
+
+    /// Subject, e.g. the user id
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// All claims
+    pub fn claims(&self) -> &Value {
+        &self.claims
+    }
+
+    /// One claim, if present
+    pub fn claim(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| ApiError::unauthorized("Authentication required"))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Principal>().cloned())
+    }
+}
+
+// verifies a token, returning its principal
+type VerifyFn = dyn Fn(&str, &Container) -> Result<Principal, ApiError> + Send + Sync;
+
+/// Guard authenticating requests with a bearer token
+///
+/// The token is taken from the `Authorization: Bearer` header, then the
+/// `access_token` query parameter, then the `access_token` cookie. A
+/// request without a token is rejected with 401 `missing_token`; errors
+/// from the verifier are returned as they are.
+///
+/// A token read from the query is removed from the request's URI, so
+/// handlers and the logs after the guard do not see it. A request
+/// authenticated by the cookie whose `Origin` is neither its own host nor
+/// allowed with [`allow_origin`](Self::allow_origin) is rejected with 403
+/// `origin_not_allowed`, which stops cross-site WebSocket hijacking.
+///
+/// # Example
+///
+/// ```ignore
+/// let auth = TokenAuth::new(|token, container| {
+///     let sessions = container
+///         .resolve::<Sessions>()
+///         .ok_or_else(|| ApiError::internal("Sessions not registered"))?;
+///     sessions.principal(token)
+/// });
+///
+/// #[guard(auth())]
+/// #[websocket("/chat")]
+/// async fn chat() -> ChatGateway { ... }
+/// ```
+#[derive(Clone)]
+pub struct TokenAuth {
+    verify: Arc<VerifyFn>,
+    query_param: String,
+    cookie: String,
+    origins: Vec<String>,
+}
+
+// where a request's token was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Header,
+    Query,
+    Cookie,
+}
+
+impl TokenAuth {
+    /// Authenticate with `verify`
+    pub fn new(
+        verify: impl Fn(&str, &Container) -> Result<Principal, ApiError> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            verify: Arc::new(verify),
+            query_param: String::from("access_token"),
+            cookie: String::from("access_token"),
+            origins: Vec::new(),
+        }
+    }
+
+    /// Read the token from this query parameter instead of `access_token`
+    pub fn query_param(mut self, name: impl Into<String>) -> Self {
+        self.query_param = name.into();
+        self
+    }
+
+    /// Read the token from this cookie instead of `access_token`
+    pub fn cookie(mut self, name: impl Into<String>) -> Self {
+        self.cookie = name.into();
+        self
+    }
+
+    /// Accept cookie-authenticated requests from `origin`, e.g.
+    /// `https://app.example.com`, besides the request's own host
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Self {
+        let origin = origin.into();
+        self.origins
+            .push(origin.trim_end_matches('/').to_ascii_lowercase());
+        self
+    }
+
+    // whether a cookie-authenticated request comes from an allowed origin
+    fn origin_allowed(&self, parts: &Parts) -> bool {
+        let Some(origin) = parts.headers.get(header::ORIGIN) else {
+            // not sent by a browser on a cross-site WebSocket upgrade
+            return true;
+        };
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if self.origins.contains(&origin) {
+            return true;
+        }
+        let host = parts
+            .headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| parts.uri.authority().map(|authority| authority.as_str()));
+        let origin_host = origin.split_once("://").map(|(_, host)| host);
+        matches!((origin_host, host), (Some(origin), Some(host)) if origin.eq_ignore_ascii_case(host))
+    }
+
+    // the token of a request, from the first source that has one
+    fn token(&self, parts: &Parts) -> Option<(String, Source)> {
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let query = || {
+            parts.uri.query()?.split('&').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                (name == self.query_param)
+                    .then(|| percent_decode_str(value).decode_utf8_lossy().into_owned())
+            })
+        };
+        let cookie = || {
+            parts
+                .headers
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    (name == self.cookie).then(|| value.to_string())
+                })
+        };
+        bearer
+            .map(|token| (token, Source::Header))
+            .or_else(|| query().map(|token| (token, Source::Query)))
+            .or_else(|| cookie().map(|token| (token, Source::Cookie)))
+            .filter(|(token, _)| !token.is_empty())
+    }
+}
+
+// `uri` without the query parameter `name`
+fn without_param(uri: &Uri, name: &str) -> Option<Uri> {
+    let query = uri.query()?;
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| pair.split('=').next() != Some(name))
+        .collect();
+    let path_and_query = match kept.is_empty() {
+        true => uri.path().to_string(),
+        false => format!("{}?{}", uri.path(), kept.join("&")),
+    };
+    let mut uri_parts = uri.clone().into_parts();
+    uri_parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(uri_parts).ok()
+}
+
+impl fmt::Debug for TokenAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenAuth")
+            .field("query_param", &self.query_param)
+            .field("cookie", &self.cookie)
+            .field("origins", &self.origins)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Guard for TokenAuth {
+    fn check<'a>(
+        &'a self,
+        parts: &'a mut Parts,
+        container: &'a Container,
+    ) -> BoxFuture<'a, Result<(), ApiError>> {
+        let result = match self.token(parts) {
+            Some((_, Source::Cookie)) if !self.origin_allowed(parts) => Err(ApiError::forbidden(
+                "Cookie authentication is not allowed from this origin",
+            )
+            .with_code("origin_not_allowed")),
+            Some((token, source)) => (self.verify)(&token, container).map(|principal| {
+                if source == Source::Query {
+                    if let Some(uri) = without_param(&parts.uri, &self.query_param) {
+                        parts.uri = uri;
+                    }
+                }
+                // for code reading the principal from the request context
+                if let Some(ctx) = parts.extensions.get::<RequestContext>() {
+                    ctx.set_principal(principal.subject());
+                }
+                parts.extensions.insert(principal);
+            }),
+            None => Err(ApiError::unauthorized("Missing access token").with_code("missing_token")),
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::StatusCode, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::app::App;
+
+    fn auth() -> TokenAuth {
+        TokenAuth::new(|token, _| match token {
+            "secret" => {
+                Ok(Principal::new("ada").with_claims(serde_json::json!({ "role": "admin" })))
+            }
+            _ => Err(ApiError::unauthorized("Invalid token").with_code("invalid_token")),
+        })
+        .cookie("session")
+        .allow_origin("https://app.example.com/")
+    }
+
+    async fn call(request: axum::http::request::Builder) -> (StatusCode, String) {
+        let app = App::new().group("/me", |group| {
+            group.guard(auth()).route(
+                "/",
+                get(|principal: Principal| async move {
+                    format!(
+                        "{}:{}",
+                        principal.subject(),
+                        principal.claim("role").unwrap()
+                    )
+                }),
+            )
+        });
+        let request = request.body(Body::empty()).unwrap();
+        let response = app.build().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_token_sources() {
+        let ok = (StatusCode::OK, String::from(r#"ada:"admin""#));
+        assert_eq!(
+            call(Request::get("/me").header("authorization", "Bearer secret")).await,
+            ok
+        );
+        assert_eq!(call(Request::get("/me?access_token=secret")).await, ok);
+        assert_eq!(
+            call(Request::get("/me").header("cookie", "theme=dark; session=secret")).await,
+            ok
+        );
+
+        let (status, body) = call(Request::get("/me")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("missing_token"), "{}", body);
+        let (status, body) = call(Request::get("/me?access_token=nope")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("invalid_token"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_origins() {
+        let ok = (StatusCode::OK, String::from(r#"ada:"admin""#));
+        let cookie = |origin: &str| {
+            Request::get("/me")
+                .header("host", "api.example.com")
+                .header("origin", origin)
+                .header("cookie", "session=secret")
+        };
+        assert_eq!(call(cookie("https://api.example.com")).await, ok);
+        assert_eq!(call(cookie("https://APP.example.com")).await, ok);
+
+        let (status, body) = call(cookie("https://evil.example")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("origin_not_allowed"), "{}", body);
+
+        // a header token is not sent by the browser on its own
+        let request = cookie("https://evil.example").header("authorization", "Bearer secret");
+        assert_eq!(call(request).await, ok);
+    }
+
+    #[test]
+    fn test_query_token_is_removed() {
+        let uri: Uri = "/chat?room=1&access_token=secret&x".parse().unwrap();
+        assert_eq!(
+            without_param(&uri, "access_token").unwrap(),
+            "/chat?room=1&x"
+        );
+        let uri: Uri = "/chat?access_token=secret".parse().unwrap();
+        assert_eq!(without_param(&uri, "access_token").unwrap(), "/chat");
+    }
+}
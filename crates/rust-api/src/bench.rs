@@ -0,0 +1,190 @@
+//! Benchmark workloads for RustAPI framework
+//!
+//! A small but realistic application used by the crate's benchmarks, and
+//! available to applications that want to compare their own overhead
+//! against the framework baseline.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    routing, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{app::App, controller, di::Injectable, extract::Inject, json::Json, metrics::Metrics};
+
+/// Service resolved from the container on every benchmark request
+#[derive(Debug, Clone)]
+pub struct EchoService {
+    prefix: String,
+}
+
+impl EchoService {
+    /// Create the service with the prefix added to echoed messages
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Echo a message with the prefix
+    pub fn echo(&self, message: &str) -> String {
+        format!("{}{}", self.prefix, message)
+    }
+}
+
+impl Injectable for EchoService {}
+
+/// JSON payload of `POST /echo`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EchoPayload {
+    /// Message to echo
+    pub message: String,
+    /// Arbitrary tags, echoed unchanged
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Application exercising the common request path
+///
+/// Routes:
+///
+/// | Route              | Work done                                      |
+/// |--------------------|------------------------------------------------|
+/// | `GET /health`      | Static text, routing and middleware only       |
+/// | `GET /users/{id}`  | Path extraction, DI resolution, JSON response  |
+/// | `POST /echo`       | JSON body parsing, DI resolution, JSON response |
+///
+/// The middleware stack includes the request context, a request timeout
+/// and latency metrics.
+///
+/// # Example
+///
+/// ```ignore
+/// let router = rust_api::bench::echo_app().build();
+/// let response = router.oneshot(request).await.unwrap();
+/// ```
+pub fn echo_app() -> App {
+    let mut app = App::new()
+        .route("/health", routing::get(|| async { "ok" }))
+        .route("/users/{id}", routing::get(get_user))
+        .route("/echo", routing::post(echo))
+        .request_timeout(Duration::from_secs(30))
+        .metrics(Metrics::new());
+    app.container_mut()
+        .register(Arc::new(EchoService::new("echo: ")));
+    app
+}
+
+// GET /users/{id}
+async fn get_user(Inject(service): Inject<EchoService>, Path(id): Path<u64>) -> Json<EchoPayload> {
+    Json(EchoPayload {
+        message: service.echo(&id.to_string()),
+        tags: vec!["user".to_string()],
+    })
+}
+
+// POST /echo
+async fn echo(
+    Inject(service): Inject<EchoService>,
+    Json(payload): Json<EchoPayload>,
+) -> Json<EchoPayload> {
+    Json(EchoPayload {
+        message: service.echo(&payload.message),
+        tags: payload.tags,
+    })
+}
+
+/// Controller used to compare handler dispatch strategies
+#[derive(Debug, Clone)]
+pub struct EchoController {
+    service: Arc<EchoService>,
+}
+
+#[controller]
+impl EchoController {
+    #[get("/users/{id}")]
+    async fn get_user(&self, Path(id): Path<u64>) -> Json<EchoPayload> {
+        self.user(id)
+    }
+}
+
+impl EchoController {
+    /// Create the controller
+    pub fn new(service: Arc<EchoService>) -> Self {
+        Self { service }
+    }
+
+    // shared body of both dispatch strategies
+    fn user(&self, id: u64) -> Json<EchoPayload> {
+        Json(EchoPayload {
+            message: self.service.echo(&id.to_string()),
+            tags: vec!["user".to_string()],
+        })
+    }
+
+    /// The same route served through `State<Arc<EchoController>>`, the
+    /// pattern `#[controller]` replaces
+    pub fn state_router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route(
+                "/users/{id}",
+                routing::get(
+                    |State(this): State<Arc<EchoController>>, Path(id): Path<u64>| async move {
+                        this.user(id)
+                    },
+                ),
+            )
+            .with_state(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::testing::{Request, TestClient};
+
+    #[tokio::test]
+    async fn test_echo_app_routes() {
+        let client = TestClient::new(echo_app());
+        assert_eq!(client.send(Request::get("/health")).await.text(), "ok");
+
+        let user: EchoPayload = client.send(Request::get("/users/7")).await.json();
+        assert_eq!(user.message, "echo: 7");
+
+        let payload = EchoPayload {
+            message: "hi".to_string(),
+            tags: vec!["a".to_string()],
+        };
+        let response = client.send(Request::post("/echo").json(&payload)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<EchoPayload>().message, "echo: hi");
+    }
+
+    #[tokio::test]
+    async fn test_controller_routers_agree() {
+        let controller = Arc::new(EchoController::new(Arc::new(EchoService::new("> "))));
+        for router in [controller.clone().router(), controller.state_router()] {
+            let client = TestClient::from_router(router);
+            let user: EchoPayload = client.send(Request::get("/users/3")).await.json();
+            assert_eq!(user.message, "> 3");
+        }
+        assert!(crate::RouteRegistry::global()
+            .by_handler("rust_api::bench::EchoController::get_user")
+            .is_some());
+    }
+
+    #[test]
+    fn test_controller_route_table() {
+        const ROUTE: Option<&crate::registry::RouteDef> =
+            EchoController::ROUTES.get("GET", "/users/{id}");
+        assert_eq!(
+            ROUTE.map(|route| route.handler()),
+            Some("rust_api::bench::EchoController::get_user")
+        );
+        assert_eq!(EchoController::ROUTES.len(), 1);
+    }
+}
@@ -0,0 +1,584 @@
+//! Streaming request bodies for RustAPI framework
+//!
+//! The `BodyStream` extractor hands the request body to the handler chunk by
+//! chunk instead of buffering it, for upload proxies and chunked ingestion.
+//! `MultipartStream` does the same for `multipart/form-data` uploads, field
+//! by field.
+
+use std::{fmt, time::Duration};
+
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    extract::{FromRequest, Request},
+    http::{header, StatusCode},
+};
+use futures_util::StreamExt;
+
+use crate::error::ApiError;
+
+/// Default maximum total body size: 64 MiB
+pub const DEFAULT_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default time to wait for the next chunk
+pub const DEFAULT_CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest header block of a multipart field: 8 KiB
+pub const MAX_FIELD_HEADER_SIZE: usize = 8 * 1024;
+
+// progress callback, called with the total number of bytes read so far
+type ProgressFn = Box<dyn FnMut(u64) + Send>;
+
+/// Extractor yielding the request body chunk by chunk
+///
+/// Chunks are only read from the connection when `next_chunk` is called, so
+/// a slow consumer applies backpressure to the client. Reading fails with
+/// 413 Payload Too Large past the size limit and 408 Request Timeout when a
+/// chunk takes too long to arrive.
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/uploads")]
+/// async fn upload(body: BodyStream) -> Result<StatusCode, ApiError> {
+///     let mut body = body
+///         .max_size(1024 * 1024 * 1024)
+///         .on_progress(|read| tracing::debug!("received {} bytes", read));
+///     let mut file = tokio::fs::File::create("/tmp/upload").await?;
+///     while let Some(chunk) = body.next_chunk().await {
+///         file.write_all(&chunk?).await?;
+///     }
+///     Ok(StatusCode::CREATED)
+/// }
+/// ```
+pub struct BodyStream {
+    stream: BodyDataStream,
+    content_length: Option<u64>,
+    max_size: u64,
+    chunk_timeout: Duration,
+    read: u64,
+    progress: Option<ProgressFn>,
+    done: bool,
+}
+
+impl BodyStream {
+    // stream `body` with the default limits
+    pub(crate) fn from_body(body: Body, content_length: Option<u64>) -> Self {
+        Self {
+            stream: body.into_data_stream(),
+            content_length,
+            max_size: DEFAULT_MAX_SIZE,
+            chunk_timeout: DEFAULT_CHUNK_TIMEOUT,
+            read: 0,
+            progress: None,
+            done: false,
+        }
+    }
+
+    /// Set the maximum total body size
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set how long to wait for each chunk
+    pub fn chunk_timeout(mut self, timeout: Duration) -> Self {
+        self.chunk_timeout = timeout;
+        self
+    }
+
+    /// Call `progress` with the total bytes read after every chunk
+    pub fn on_progress(mut self, progress: impl FnMut(u64) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Length announced by the client in `Content-Length`, if any
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Number of bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// Read the next chunk, `None` at the end of the body
+    ///
+    /// After an error the stream is finished.
+    pub async fn next_chunk(&mut self) -> Option<Result<Bytes, ApiError>> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_chunk().await;
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+
+    // read one chunk, enforcing the limits
+    async fn read_chunk(&mut self) -> Option<Result<Bytes, ApiError>> {
+        // refuse announced oversized bodies before reading anything
+        if self.read == 0 && self.content_length.is_some_and(|len| len > self.max_size) {
+            return Some(Err(too_large(self.max_size)));
+        }
+
+        let chunk = match tokio::time::timeout(self.chunk_timeout, self.stream.next()).await {
+            Err(_) => {
+                return Some(Err(ApiError::new(
+                    StatusCode::REQUEST_TIMEOUT,
+                    "Timed out waiting for request body",
+                )))
+            }
+            Ok(None) => return None,
+            Ok(Some(Err(e))) => {
+                return Some(Err(ApiError::bad_request(format!(
+                    "Failed to read request body: {}",
+                    e
+                ))))
+            }
+            Ok(Some(Ok(chunk))) => chunk,
+        };
+
+        self.read += chunk.len() as u64;
+        if self.read > self.max_size {
+            return Some(Err(too_large(self.max_size)));
+        }
+        if let Some(progress) = &mut self.progress {
+            progress(self.read);
+        }
+        Some(Ok(chunk))
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("content_length", &self.content_length)
+            .field("max_size", &self.max_size)
+            .field("chunk_timeout", &self.chunk_timeout)
+            .field("read", &self.read)
+            .finish()
+    }
+}
+
+// 413 error for a body over the limit
+fn too_large(max_size: u64) -> ApiError {
+    ApiError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("Request body exceeds {} bytes", max_size),
+    )
+}
+
+impl<S: Send + Sync> FromRequest<S> for BodyStream {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        let content_length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        Ok(Self::from_body(req.into_body(), content_length))
+    }
+}
+
+/// A field of a `multipart/form-data` body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartField {
+    /// Form field name
+    pub name: String,
+    /// File name sent by the client, for file fields
+    pub file_name: Option<String>,
+    /// Content type sent by the client
+    pub content_type: Option<String>,
+}
+
+// where a `MultipartStream` is in the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    // before the first delimiter
+    Preamble,
+    // right after a delimiter
+    Delimiter,
+    // inside a field's contents
+    Contents,
+    // after the closing delimiter
+    Done,
+}
+
+/// Extractor reading a `multipart/form-data` body field by field
+///
+/// Field contents are read chunk by chunk, so file uploads are never
+/// buffered whole; unread contents are skipped by the next `next_field`.
+/// The size limit and chunk timeout apply to the whole body, as with
+/// `BodyStream`. Requests without a multipart content type are rejected
+/// with 415 Unsupported Media Type, malformed bodies with 400.
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/uploads")]
+/// async fn upload(mut form: MultipartStream) -> Result<StatusCode, ApiError> {
+///     while let Some(field) = form.next_field().await? {
+///         if field.file_name.is_none() {
+///             tracing::debug!("{} = {}", field.name, form.text().await?);
+///             continue;
+///         }
+///         while let Some(chunk) = form.next_chunk().await {
+///             scanner.feed(&chunk?);
+///         }
+///     }
+///     Ok(StatusCode::CREATED)
+/// }
+/// ```
+pub struct MultipartStream {
+    body: BodyStream,
+    // CRLF, `--` and the boundary
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    part: Part,
+}
+
+impl MultipartStream {
+    /// Set the maximum total body size
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.body = self.body.max_size(max_size);
+        self
+    }
+
+    /// Set how long to wait for each chunk
+    pub fn chunk_timeout(mut self, timeout: Duration) -> Self {
+        self.body = self.body.chunk_timeout(timeout);
+        self
+    }
+
+    /// Number of body bytes read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.body.bytes_read()
+    }
+
+    /// Move to the next field, `None` after the last one
+    pub async fn next_field(&mut self) -> Result<Option<MultipartField>, ApiError> {
+        while let Some(chunk) = self.next_chunk().await {
+            chunk?;
+        }
+        if self.part == Part::Preamble {
+            // the first delimiter may open the body without a CRLF
+            let opening = self.delimiter[2..].to_vec();
+            loop {
+                if let Some(at) = find(&self.buffer, &opening) {
+                    self.buffer.drain(..at + opening.len());
+                    break;
+                }
+                let skipped = self.buffer.len().saturating_sub(opening.len() - 1);
+                self.buffer.drain(..skipped);
+                self.fill().await?;
+            }
+            self.part = Part::Delimiter;
+        }
+        if self.part == Part::Done {
+            return Ok(None);
+        }
+
+        while self.buffer.len() < 2 {
+            self.fill().await?;
+        }
+        if self.buffer.starts_with(b"--") {
+            self.part = Part::Done;
+            return Ok(None);
+        }
+        if !self.buffer.starts_with(b"\r\n") {
+            return Err(malformed("expected a line break after the boundary"));
+        }
+        let end = loop {
+            if let Some(at) = find(&self.buffer, b"\r\n\r\n") {
+                break at;
+            }
+            if self.buffer.len() > MAX_FIELD_HEADER_SIZE {
+                return Err(malformed("field headers are too large"));
+            }
+            self.fill().await?;
+        };
+        let headers = std::str::from_utf8(&self.buffer[2.min(end)..end])
+            .map_err(|_| malformed("field headers are not valid UTF-8"))?;
+        let field = parse_field(headers)?;
+        self.buffer.drain(..end + 4);
+        self.part = Part::Contents;
+        Ok(Some(field))
+    }
+
+    /// Read the next chunk of the current field, `None` at its end
+    pub async fn next_chunk(&mut self) -> Option<Result<Bytes, ApiError>> {
+        if self.part != Part::Contents {
+            return None;
+        }
+        loop {
+            if let Some(at) = find(&self.buffer, &self.delimiter) {
+                let chunk = Bytes::copy_from_slice(&self.buffer[..at]);
+                self.buffer.drain(..at + self.delimiter.len());
+                self.part = Part::Delimiter;
+                return (!chunk.is_empty()).then_some(Ok(chunk));
+            }
+            // the end may hold the start of a delimiter
+            let complete = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if complete > 0 {
+                let chunk = Bytes::copy_from_slice(&self.buffer[..complete]);
+                self.buffer.drain(..complete);
+                return Some(Ok(chunk));
+            }
+            if let Err(e) = self.fill().await {
+                self.part = Part::Done;
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Read the rest of the current field as UTF-8 text
+    pub async fn text(&mut self) -> Result<String, ApiError> {
+        let mut contents = Vec::new();
+        while let Some(chunk) = self.next_chunk().await {
+            contents.extend_from_slice(&chunk?);
+        }
+        String::from_utf8(contents).map_err(|_| malformed("field is not valid UTF-8"))
+    }
+
+    // append the next body chunk to the buffer
+    async fn fill(&mut self) -> Result<(), ApiError> {
+        match self.body.next_chunk().await {
+            Some(chunk) => {
+                self.buffer.extend_from_slice(&chunk?);
+                Ok(())
+            }
+            None => Err(malformed("body ended before the closing boundary")),
+        }
+    }
+}
+
+impl fmt::Debug for MultipartStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultipartStream")
+            .field("body", &self.body)
+            .field("part", &self.part)
+            .finish()
+    }
+}
+
+impl<S: Send + Sync> FromRequest<S> for MultipartStream {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mut params = content_type.split(';').map(str::trim);
+        let is_multipart = params
+            .next()
+            .is_some_and(|essence| essence.eq_ignore_ascii_case("multipart/form-data"));
+        let boundary = params
+            .find_map(|param| parameter(param, "boundary"))
+            .filter(|boundary| !boundary.is_empty() && is_multipart);
+        let Some(boundary) = boundary else {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a multipart/form-data body with a boundary",
+            )
+            .rejection());
+        };
+        Ok(Self {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            body: BodyStream::from_request(req, state).await?,
+            buffer: Vec::new(),
+            part: Part::Preamble,
+        })
+    }
+}
+
+// 400 error for a malformed multipart body
+fn malformed(reason: &str) -> ApiError {
+    ApiError::bad_request(format!("Malformed multipart body: {}", reason))
+}
+
+// position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// value of `name=value` or `name="value"`
+fn parameter(param: &str, name: &str) -> Option<String> {
+    let (key, value) = param.split_once('=')?;
+    key.trim()
+        .eq_ignore_ascii_case(name)
+        .then(|| value.trim().trim_matches('"').to_string())
+}
+
+// the field described by a part's header block
+fn parse_field(headers: &str) -> Result<MultipartField, ApiError> {
+    let mut name = None;
+    let mut file_name = None;
+    let mut content_type = None;
+    for line in headers.split("\r\n").filter(|line| !line.is_empty()) {
+        let Some((header, value)) = line.split_once(':') else {
+            return Err(malformed("invalid field header"));
+        };
+        let value = value.trim();
+        if header.trim().eq_ignore_ascii_case("content-disposition") {
+            for param in value.split(';').map(str::trim) {
+                name = name.or_else(|| parameter(param, "name"));
+                file_name = file_name.or_else(|| parameter(param, "filename"));
+            }
+        } else if header.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.to_string());
+        }
+    }
+    let name = name.ok_or_else(|| malformed("field has no name"))?;
+    Ok(MultipartField {
+        name,
+        file_name,
+        content_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use axum::body::Body;
+    use futures_util::stream;
+
+    use super::*;
+
+    async fn body_stream(chunks: Vec<&'static str>) -> BodyStream {
+        let chunks = stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+        let request = Request::post("/").body(Body::from_stream(chunks)).unwrap();
+        BodyStream::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reads_chunks_with_progress() {
+        let seen = Arc::new(AtomicU64::new(0));
+        let progress = seen.clone();
+        let mut body = body_stream(vec!["ab", "cde"])
+            .await
+            .on_progress(move |read| progress.store(read, Ordering::Relaxed));
+
+        let mut collected = Vec::new();
+        while let Some(chunk) = body.next_chunk().await {
+            collected.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(collected, b"abcde");
+        assert_eq!(body.bytes_read(), 5);
+        assert_eq!(seen.load(Ordering::Relaxed), 5);
+    }
+
+    #[tokio::test]
+    async fn test_enforces_max_size() {
+        let mut body = body_stream(vec!["ab", "cde"]).await.max_size(4);
+        assert!(body.next_chunk().await.unwrap().is_ok());
+        let err = body.next_chunk().await.unwrap().unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(body.next_chunk().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_announced_oversized_body() {
+        let request = Request::post("/")
+            .header(header::CONTENT_LENGTH, "100")
+            .body(Body::from(vec![0u8; 100]))
+            .unwrap();
+        let mut body = BodyStream::from_request(request, &())
+            .await
+            .unwrap()
+            .max_size(10);
+        assert_eq!(body.content_length(), Some(100));
+        let err = body.next_chunk().await.unwrap().unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body.bytes_read(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chunk_timeout() {
+        let chunks = stream::pending::<Result<Bytes, std::io::Error>>();
+        let request = Request::post("/").body(Body::from_stream(chunks)).unwrap();
+        let mut body = BodyStream::from_request(request, &())
+            .await
+            .unwrap()
+            .chunk_timeout(Duration::from_secs(1));
+        let err = body.next_chunk().await.unwrap().unwrap_err();
+        assert_eq!(err.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_fields() {
+        let form = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Holiday\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\r\n\
+            \r\n--Xy not yet\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"skipped\"\r\n\r\n\
+            unread\r\n--XyZ--\r\n";
+        // split into small chunks, so delimiters straddle them
+        let chunks: Vec<&'static str> = (0..form.len())
+            .step_by(3)
+            .map(|at| &form[at..(at + 3).min(form.len())])
+            .collect();
+        let chunks = stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+        let request = Request::post("/")
+            .header(
+                header::CONTENT_TYPE,
+                "multipart/form-data; boundary=\"XyZ\"",
+            )
+            .body(Body::from_stream(chunks))
+            .unwrap();
+        let mut form = MultipartStream::from_request(request, &()).await.unwrap();
+
+        let title = form.next_field().await.unwrap().unwrap();
+        assert_eq!(title.name, "title");
+        assert_eq!(title.file_name, None);
+        assert_eq!(form.text().await.unwrap(), "Holiday");
+
+        let photo = form.next_field().await.unwrap().unwrap();
+        assert_eq!(photo.file_name.as_deref(), Some("beach.jpg"));
+        assert_eq!(photo.content_type.as_deref(), Some("image/jpeg"));
+        let mut contents = Vec::new();
+        while let Some(chunk) = form.next_chunk().await {
+            contents.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(contents, b"\r\n--Xy not yet");
+
+        assert_eq!(form.next_field().await.unwrap().unwrap().name, "skipped");
+        assert!(form.next_field().await.unwrap().is_none());
+        assert!(form.next_field().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_rejections() {
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let err = MultipartStream::from_request(request, &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+            .body(Body::from(
+                "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ncut",
+            ))
+            .unwrap();
+        let mut form = MultipartStream::from_request(request, &()).await.unwrap();
+        form.next_field().await.unwrap().unwrap();
+        let err = form.text().await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+}
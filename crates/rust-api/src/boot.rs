@@ -0,0 +1,170 @@
+//! Startup timing for RustAPI framework
+//!
+//! Slow cold starts, in serverless deployments especially, are hard to
+//! diagnose from the outside. The framework times the phases of its own
+//! startup (configuration loading, module registration, router building,
+//! listener binding, start hooks such as migrations) into a process-wide
+//! boot report, and logs it as one structured `Boot report` line once the
+//! server is ready. Applications can time their own phases with `time` and
+//! `time_async`.
+//!
+//! Durations are measured from `start`, called by `#[rust_api::main]`
+//! before anything else; without it, from the first recorded phase.
+
+use std::{
+    future::Future,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Serialize, Serializer};
+
+// phases kept; apps built over and over (tests) drop the oldest
+const MAX_PHASES: usize = 256;
+
+/// One timed phase of the startup
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BootPhase {
+    /// Phase name, e.g. `config` or `module:billing`
+    pub name: String,
+    /// Time spent in the phase
+    #[serde(rename = "duration_ms", serialize_with = "millis")]
+    pub duration: Duration,
+}
+
+/// Startup timings of the process
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BootReport {
+    /// Timed phases, in the order they finished
+    pub phases: Vec<BootPhase>,
+    /// Number of routes declared with route macros, once the app is built
+    pub routes: Option<usize>,
+    /// Time since startup began
+    #[serde(rename = "elapsed_ms", serialize_with = "millis")]
+    pub elapsed: Duration,
+}
+
+// report under construction
+struct Boot {
+    started: Instant,
+    phases: Vec<BootPhase>,
+    routes: Option<usize>,
+    emitted: bool,
+}
+
+// the process-wide report, started on first use
+fn boot() -> &'static Mutex<Boot> {
+    static BOOT: OnceLock<Mutex<Boot>> = OnceLock::new();
+    BOOT.get_or_init(|| {
+        Mutex::new(Boot {
+            started: Instant::now(),
+            phases: Vec::new(),
+            routes: None,
+            emitted: false,
+        })
+    })
+}
+
+/// Mark the start of the process's startup
+pub fn start() {
+    boot();
+}
+
+/// Add a phase that took `duration` to the report
+pub fn record(name: impl Into<String>, duration: Duration) {
+    let name = name.into();
+    tracing::debug!(phase = %name, duration_ms = duration.as_millis() as u64, "Boot phase done");
+    let mut boot = boot().lock().unwrap_or_else(|e| e.into_inner());
+    if boot.phases.len() == MAX_PHASES {
+        boot.phases.remove(0);
+    }
+    boot.phases.push(BootPhase { name, duration });
+}
+
+/// Run `f`, recording how long it took as phase `name`
+///
+/// # Example
+///
+/// ```ignore
+/// let catalog = boot::time("catalog", || Catalog::load(&settings))?;
+/// ```
+pub fn time<T>(name: impl Into<String>, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let value = f();
+    record(name, started.elapsed());
+    value
+}
+
+/// Await `future`, recording how long it took as phase `name`
+pub async fn time_async<T>(name: impl Into<String>, future: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let value = future.await;
+    record(name, started.elapsed());
+    value
+}
+
+/// The report so far
+pub fn report() -> BootReport {
+    let boot = boot().lock().unwrap_or_else(|e| e.into_inner());
+    BootReport {
+        phases: boot.phases.clone(),
+        routes: boot.routes,
+        elapsed: boot.started.elapsed(),
+    }
+}
+
+// record the number of declared routes
+pub(crate) fn set_routes(routes: usize) {
+    boot().lock().unwrap_or_else(|e| e.into_inner()).routes = Some(routes);
+}
+
+// log the report, once per process
+pub(crate) fn emit() {
+    {
+        let mut boot = boot().lock().unwrap_or_else(|e| e.into_inner());
+        if boot.emitted {
+            return;
+        }
+        boot.emitted = true;
+    }
+    let report = report();
+    let phases = serde_json::to_string(&report.phases).unwrap_or_default();
+    tracing::info!(
+        elapsed_ms = report.elapsed.as_millis() as u64,
+        routes = report.routes.unwrap_or(0),
+        phases = %phases,
+        "Boot report"
+    );
+}
+
+// serialize a duration as fractional milliseconds
+fn millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_phases_are_recorded() {
+        start();
+        let value = time("boot-test:sync", || 7);
+        assert_eq!(value, 7);
+        time_async(
+            "boot-test:async",
+            tokio::time::sleep(Duration::from_millis(5)),
+        )
+        .await;
+
+        let report = report();
+        let phase = |name: &str| report.phases.iter().find(|phase| phase.name == name);
+        assert!(phase("boot-test:sync").is_some());
+        assert!(phase("boot-test:async").unwrap().duration >= Duration::from_millis(5));
+        assert!(report.elapsed >= Duration::from_millis(5));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert!(json["elapsed_ms"].as_f64().unwrap() >= 5.0);
+        assert!(json["phases"][0]["duration_ms"].is_number());
+    }
+}
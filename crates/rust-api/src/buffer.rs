@@ -0,0 +1,451 @@
+//! Response buffer pooling for RustAPI framework
+//!
+//! Serialized response bodies are written into buffers taken from a pool of
+//! size buckets. A buffer goes back to its bucket when the response body is
+//! dropped after being sent, so steady traffic reuses a fixed set of
+//! allocations instead of allocating per response. Each bucket is split into
+//! shards picked by thread, so concurrent responses rarely share a lock.
+
+use std::{
+    cell::RefCell,
+    io,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
+};
+
+use axum::body::Bytes;
+
+use crate::metrics::{Counter, Metrics};
+
+/// Default bucket sizes in bytes
+pub const DEFAULT_BUCKETS: &[usize] = &[1024, 4 * 1024, 16 * 1024, 64 * 1024];
+
+/// Default number of idle buffers kept per bucket
+pub const DEFAULT_BUFFERS_PER_BUCKET: usize = 64;
+
+// shards per bucket
+const SHARDS: usize = 16;
+
+/// Pool of reusable response buffers
+///
+/// Clones share the same buffers. Buffers that grew beyond four times the
+/// largest bucket are freed instead of pooled.
+///
+/// # Example
+///
+/// ```ignore
+/// let pool = BufferPool::new()
+///     .buckets(&[512, 8 * 1024, 128 * 1024])
+///     .buffers_per_bucket(256)
+///     .metrics(metrics.clone());
+///
+/// RustAPI::new(app).buffer_pool(pool).serve().await?;
+/// ```
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    buckets: Vec<Bucket>,
+    per_bucket: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    // running average of the bodies written, in bytes
+    typical: AtomicUsize,
+    metrics: Option<(Metrics, Counter, Counter)>,
+}
+
+// idle buffers with at least `size` bytes of capacity
+struct Bucket {
+    size: usize,
+    per_shard: usize,
+    shards: Vec<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Bucket {
+    // take an idle buffer, from this thread's shard first
+    fn pop(&self) -> Option<Vec<u8>> {
+        let own = shard();
+        let buffer = self.shards[own]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop();
+        buffer.or_else(|| {
+            // other shards only when they are free
+            (1..SHARDS).find_map(|offset| {
+                let mut free = self.shards[(own + offset) % SHARDS].try_lock().ok()?;
+                free.pop()
+            })
+        })
+    }
+
+    // keep an idle buffer in this thread's shard, unless it is full
+    fn push(&self, buffer: Vec<u8>) {
+        let mut free = self.shards[shard()]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.per_shard {
+            free.push(buffer);
+        }
+    }
+}
+
+// shard of the current thread, assigned round-robin
+fn shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+    SHARD.with(|shard| *shard)
+}
+
+impl BufferPool {
+    /// Create a pool with the default buckets
+    pub fn new() -> Self {
+        Self::build(DEFAULT_BUCKETS, DEFAULT_BUFFERS_PER_BUCKET, None)
+    }
+
+    /// A pool that never keeps buffers, i.e. plain allocation
+    pub fn disabled() -> Self {
+        Self::build(&[], 0, None)
+    }
+
+    // assemble a pool from its settings
+    fn build(sizes: &[usize], per_bucket: usize, metrics: Option<Metrics>) -> Self {
+        let mut sizes = sizes.to_vec();
+        sizes.sort_unstable();
+        sizes.dedup();
+        let metrics = metrics.map(|metrics| {
+            let hit = metrics.counter_handle("response_buffer_pool_total", &[("outcome", "hit")]);
+            let miss = metrics.counter_handle("response_buffer_pool_total", &[("outcome", "miss")]);
+            (metrics, hit, miss)
+        });
+        Self {
+            inner: Arc::new(Inner {
+                buckets: sizes
+                    .into_iter()
+                    .map(|size| Bucket {
+                        size,
+                        per_shard: per_bucket.div_ceil(SHARDS),
+                        shards: (0..SHARDS).map(|_| Mutex::new(Vec::new())).collect(),
+                    })
+                    .collect(),
+                per_bucket,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                typical: AtomicUsize::new(0),
+                metrics,
+            }),
+        }
+    }
+
+    // the metrics registry, if any
+    fn registry(&self) -> Option<Metrics> {
+        self.inner
+            .metrics
+            .as_ref()
+            .map(|(metrics, ..)| metrics.clone())
+    }
+
+    /// Use these bucket sizes (bytes) instead of `DEFAULT_BUCKETS`
+    pub fn buckets(self, sizes: &[usize]) -> Self {
+        Self::build(sizes, self.inner.per_bucket, self.registry())
+    }
+
+    /// Keep about `count` idle buffers per bucket
+    ///
+    /// The count is split across the bucket's shards, each keeping at
+    /// least one buffer.
+    pub fn buffers_per_bucket(self, count: usize) -> Self {
+        let sizes = self.bucket_sizes();
+        Self::build(&sizes, count, self.registry())
+    }
+
+    /// Count hits and misses as `response_buffer_pool_total{outcome}`
+    pub fn metrics(self, metrics: Metrics) -> Self {
+        let sizes = self.bucket_sizes();
+        Self::build(&sizes, self.inner.per_bucket, Some(metrics))
+    }
+
+    /// Configured bucket sizes, ascending
+    pub fn bucket_sizes(&self) -> Vec<usize> {
+        self.inner.buckets.iter().map(|b| b.size).collect()
+    }
+
+    /// Running average size of the bodies written into pooled buffers
+    ///
+    /// The size hint for bodies whose length is not known before they are
+    /// serialized.
+    pub fn typical_size(&self) -> usize {
+        self.inner.typical.load(Ordering::Relaxed)
+    }
+
+    /// Take a buffer with room for at least `size_hint` bytes
+    pub fn acquire(&self, size_hint: usize) -> PooledBuffer {
+        let inner = &self.inner;
+        let bucket = inner.buckets.iter().find(|bucket| bucket.size >= size_hint);
+        let reused = bucket.and_then(Bucket::pop);
+        let (counter, handle) = match (&reused, &inner.metrics) {
+            (Some(_), metrics) => (&inner.hits, metrics.as_ref().map(|(_, hit, _)| hit)),
+            (None, metrics) => (&inner.misses, metrics.as_ref().map(|(_, _, miss)| miss)),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(handle) = handle {
+            handle.increment();
+        }
+
+        let buffer = reused
+            .unwrap_or_else(|| Vec::with_capacity(bucket.map_or(size_hint, |bucket| bucket.size)));
+        PooledBuffer {
+            buffer,
+            pool: Some(self.clone()),
+        }
+    }
+
+    // put a buffer back in the largest bucket it can serve
+    fn release(&self, mut buffer: Vec<u8>) {
+        let inner = &self.inner;
+        // an estimate, so concurrent updates may overwrite each other
+        let typical = inner.typical.load(Ordering::Relaxed);
+        let typical = if typical == 0 {
+            buffer.len()
+        } else {
+            (typical * 7 + buffer.len()) / 8
+        };
+        inner.typical.store(typical, Ordering::Relaxed);
+
+        let Some(largest) = inner.buckets.last() else {
+            return;
+        };
+        if buffer.capacity() > largest.size * 4 {
+            return;
+        }
+        if let Some(bucket) = inner
+            .buckets
+            .iter()
+            .rev()
+            .find(|bucket| bucket.size <= buffer.capacity())
+        {
+            buffer.clear();
+            bucket.push(buffer);
+        }
+    }
+
+    /// Buffers served from the pool
+    pub fn hits(&self) -> u64 {
+        self.inner.hits.load(Ordering::Relaxed)
+    }
+
+    /// Buffers that had to be allocated
+    pub fn misses(&self) -> u64 {
+        self.inner.misses.load(Ordering::Relaxed)
+    }
+
+    /// Share of acquisitions served from the pool, 0.0 to 1.0
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.hits();
+        let total = hits + self.misses();
+        if total == 0 {
+            0.0
+        } else {
+            hits as f64 / total as f64
+        }
+    }
+
+    /// The pool used for framework responses
+    ///
+    /// Each thread keeps its own handle, refreshed after `set_global`.
+    pub fn global() -> BufferPool {
+        thread_local! {
+            static LOCAL: RefCell<Option<(u64, BufferPool)>> = const { RefCell::new(None) };
+        }
+        let generation = GENERATION.load(Ordering::Acquire);
+        LOCAL.with(|local| {
+            let mut local = local.borrow_mut();
+            match &*local {
+                Some((seen, pool)) if *seen == generation => pool.clone(),
+                _ => {
+                    let pool = global_slot()
+                        .read()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .clone();
+                    *local = Some((generation, pool.clone()));
+                    pool
+                }
+            }
+        })
+    }
+
+    /// Replace the pool used for framework responses
+    pub fn set_global(pool: BufferPool) {
+        *global_slot().write().unwrap_or_else(|e| e.into_inner()) = pool;
+        GENERATION.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// bumped by `set_global`, telling threads to refresh their handle
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// storage of the global pool
+fn global_slot() -> &'static RwLock<BufferPool> {
+    static GLOBAL: OnceLock<RwLock<BufferPool>> = OnceLock::new();
+    GLOBAL.get_or_init(|| RwLock::new(BufferPool::new()))
+}
+
+/// A buffer that returns to its pool when dropped
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Option<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Freeze into `Bytes`; the buffer is returned once the bytes are dropped
+    pub fn into_bytes(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl io::Write for PooledBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Write for PooledBuffer {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.buffer.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.release(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_buffers_are_reused() {
+        let pool = BufferPool::new().buckets(&[64, 1024]);
+        let mut buffer = pool.acquire(10);
+        assert_eq!(buffer.capacity(), 64);
+        buffer.write_all(b"hello").unwrap();
+        let bytes = buffer.into_bytes();
+        assert_eq!(bytes, "hello");
+        assert_eq!(pool.misses(), 1);
+
+        drop(bytes);
+        let buffer = pool.acquire(10);
+        assert!(buffer.is_empty());
+        assert_eq!(pool.hits(), 1);
+        assert_eq!(pool.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_grown_buffers_move_up_a_bucket() {
+        let pool = BufferPool::new().buckets(&[64, 1024]);
+        let mut buffer = pool.acquire(10);
+        buffer.extend_from_slice(&[0; 2000]);
+        drop(buffer);
+
+        // the grown buffer now serves the large bucket
+        assert!(pool.acquire(1000).capacity() >= 2000);
+        assert_eq!(pool.hits(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let pool = BufferPool::new().buckets(&[64]).buffers_per_bucket(1);
+        let (a, b) = (pool.acquire(1), pool.acquire(1));
+        drop((a, b));
+        // only one of the two buffers was kept
+        let _reused = pool.acquire(1);
+        assert_eq!(pool.hits(), 1);
+        let _allocated = pool.acquire(1);
+        assert_eq!(pool.hits(), 1);
+
+        let mut huge = pool.acquire(1);
+        huge.reserve(64 * 5);
+        drop(huge);
+        assert_eq!(pool.acquire(1).capacity(), 64);
+
+        let disabled = BufferPool::disabled();
+        drop(disabled.acquire(100));
+        disabled.acquire(100);
+        assert_eq!(disabled.hits(), 0);
+    }
+
+    #[test]
+    fn test_typical_size_and_global_refresh() {
+        let pool = BufferPool::new().buckets(&[64, 1024]);
+        let mut buffer = pool.acquire(pool.typical_size());
+        assert_eq!(buffer.capacity(), 64);
+        buffer.extend_from_slice(&[0; 800]);
+        drop(buffer);
+        assert_eq!(pool.typical_size(), 800);
+        assert_eq!(pool.acquire(pool.typical_size()).capacity(), 1024);
+
+        let before = BufferPool::global();
+        BufferPool::set_global(pool.clone());
+        assert!(Arc::ptr_eq(&BufferPool::global().inner, &pool.inner));
+        BufferPool::set_global(before);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::new();
+        let pool = BufferPool::new().metrics(metrics.clone());
+        drop(pool.acquire(1));
+        drop(pool.acquire(1));
+        assert_eq!(
+            metrics.counter("response_buffer_pool_total", &[("outcome", "miss")]),
+            1
+        );
+        assert_eq!(
+            metrics.counter("response_buffer_pool_total", &[("outcome", "hit")]),
+            1
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Bulk operations for RustAPI framework
+//!
+//! Import endpoints take a JSON array and should not fail as a whole
+//! because one item is bad. `Bulk<T>` deserializes every item on its own,
+//! `Bulk::process` runs the valid ones with bounded concurrency, and the
+//! resulting `BulkResponse` reports each item's outcome by index with
+//! 207 Multi-Status.
+//!
+//! Items failing with a 5xx are logged, and redacted to their status and
+//! code like top-level errors when the app does not send error details.
+//!
+//! Response body:
+//!
+//! ```json
+//! {
+//!   "results": [
+//!     { "index": 0, "status": 201, "data": { "id": 1 } },
+//!     { "index": 1, "status": 422, "error": { "status": 422, "code": "invalid_json", "message": "..." } }
+//!   ],
+//!   "succeeded": 1,
+//!   "failed": 1
+//! }
+//! ```
+
+use std::future::Future;
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    error::ApiError,
+    json::{json_error, Json},
+    profile::{self, REPORTING},
+};
+
+/// Default largest number of items a `Bulk` request may hold
+pub const DEFAULT_MAX_ITEMS: usize = 1000;
+
+/// Default number of items processed at once
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Limits of the `Bulk` extractor
+///
+/// Read from the request extensions, so add it as a layer on the routes
+/// or groups that need other limits.
+///
+/// # Example
+///
+/// ```ignore
+/// let imports = Router::new()
+///     .route("/users/import", routing::post(import_users))
+///     .layer(Extension(BulkConfig::new().max_items(10_000)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BulkConfig {
+    max_items: usize,
+}
+
+impl BulkConfig {
+    /// Accept up to `DEFAULT_MAX_ITEMS` items
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept up to `max_items` items
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items;
+        self
+    }
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        Self {
+            max_items: DEFAULT_MAX_ITEMS,
+        }
+    }
+}
+
+/// Extractor for a JSON array of items checked one by one
+///
+/// A body that is not a JSON array is rejected like `Json`. More items
+/// than `BulkConfig` allows, `DEFAULT_MAX_ITEMS` by default, are rejected
+/// with 413 (`too_many_items`). Items that do not deserialize into `T` are
+/// kept as failed items.
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/users/import")]
+/// async fn import_users(Inject(repo): Inject<UserRepo>, users: Bulk<NewUser>) -> BulkResponse<User> {
+///     users
+///         .process(DEFAULT_CONCURRENCY, |user| repo.create(user))
+///         .await
+///         .success_status(StatusCode::CREATED)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Bulk<T> {
+    items: Vec<Result<T, ApiError>>,
+}
+
+impl<T: DeserializeOwned> Bulk<T> {
+    /// Deserialize each of `values` into `T`
+    pub fn from_values(values: Vec<Value>) -> Self {
+        Self {
+            items: values
+                .into_iter()
+                .map(|value| serde_json::from_value(value).map_err(json_error))
+                .collect(),
+        }
+    }
+}
+
+impl<T> Bulk<T> {
+    /// Number of items, valid or not
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Check whether the request had no items
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Every item, in request order
+    pub fn items(&self) -> &[Result<T, ApiError>] {
+        &self.items
+    }
+
+    /// Fail the valid items `check` rejects
+    pub fn validate_with(mut self, check: impl Fn(&T) -> Result<(), ApiError>) -> Self {
+        for item in &mut self.items {
+            if let Ok(value) = item {
+                if let Err(error) = check(value) {
+                    *item = Err(error);
+                }
+            }
+        }
+        self
+    }
+
+    /// Fail the valid items that break `T`'s validation rules
+    #[cfg(feature = "validator")]
+    pub fn validated(self) -> Self
+    where
+        T: validator::Validate,
+    {
+        self.validate_with(|value| value.validate().map_err(ApiError::from))
+    }
+
+    /// Run `handler` on every valid item, at most `concurrency` at once
+    ///
+    /// Results keep the request order.
+    pub async fn process<R, F, Fut>(self, concurrency: usize, handler: F) -> BulkResponse<R>
+    where
+        F: Fn(T) -> Fut,
+        Fut: Future<Output = Result<R, ApiError>>,
+    {
+        let handler = &handler;
+        let results = stream::iter(self.items)
+            .map(|item| async move {
+                match item {
+                    Ok(value) => handler(value).await,
+                    Err(error) => Err(error),
+                }
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await;
+        BulkResponse::new(results)
+    }
+}
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Bulk<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let config = req
+            .extensions()
+            .get::<BulkConfig>()
+            .copied()
+            .unwrap_or_default();
+        let Json(values) = Json::<Vec<Value>>::from_request(req, state).await?;
+        if values.len() > config.max_items {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "{} items sent, at most {} are accepted",
+                    values.len(),
+                    config.max_items
+                ),
+            )
+            .with_code("too_many_items")
+            .rejection());
+        }
+        Ok(Self::from_values(values))
+    }
+}
+
+/// Per-item outcome of a bulk operation, sent as 207 Multi-Status
+#[derive(Debug)]
+pub struct BulkResponse<R> {
+    results: Vec<Result<R, ApiError>>,
+    success: StatusCode,
+}
+
+impl<R> BulkResponse<R> {
+    /// Outcomes in item order
+    pub fn new(results: Vec<Result<R, ApiError>>) -> Self {
+        Self {
+            results,
+            success: StatusCode::OK,
+        }
+    }
+
+    /// Report succeeded items with `status` instead of 200
+    pub fn success_status(mut self, status: StatusCode) -> Self {
+        self.success = status;
+        self
+    }
+
+    /// Outcomes in item order
+    pub fn results(&self) -> &[Result<R, ApiError>] {
+        &self.results
+    }
+
+    /// Number of items that succeeded
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.is_ok()).count()
+    }
+
+    /// Number of items that failed
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+}
+
+impl<R: Serialize> BulkResponse<R> {
+    /// The response body
+    ///
+    /// 5xx item errors are redacted when the app handling the request does
+    /// not send error details.
+    pub fn to_json(&self) -> Result<Value, ApiError> {
+        let details = REPORTING
+            .try_with(|reporting| reporting.details)
+            .unwrap_or(true);
+        let mut results = Vec::with_capacity(self.results.len());
+        for (index, result) in self.results.iter().enumerate() {
+            let item = match result {
+                Ok(data) => json!({
+                    "index": index,
+                    "status": self.success.as_u16(),
+                    "data": serde_json::to_value(data).map_err(|e| {
+                        ApiError::internal(format!("Failed to serialize item {}: {}", index, e))
+                    })?,
+                }),
+                Err(error) => {
+                    let mut item = match error.status().is_server_error() && !details {
+                        true => profile::redacted(error).to_json(),
+                        false => error.to_json(),
+                    };
+                    item["index"] = json!(index);
+                    item["status"] = json!(error.status().as_u16());
+                    item
+                }
+            };
+            results.push(item);
+        }
+        Ok(json!({
+            "results": results,
+            "succeeded": self.succeeded(),
+            "failed": self.failed(),
+        }))
+    }
+}
+
+// 5xx item errors are logged here, as `report_server_errors` only sees the
+// 207 around them
+impl<R: Serialize> IntoResponse for BulkResponse<R> {
+    fn into_response(self) -> Response {
+        for (index, result) in self.results.iter().enumerate() {
+            if let Err(error) = result {
+                if error.status().is_server_error() {
+                    tracing::error!(
+                        index,
+                        status = error.status().as_u16(),
+                        code = error.code(),
+                        details = ?error.details(),
+                        chain = ?error.chain(),
+                        "Bulk item failed: {}",
+                        error.message()
+                    );
+                }
+            }
+        }
+        match self.to_json() {
+            Ok(body) => (StatusCode::MULTI_STATUS, Json(body)).into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct NewUser {
+        name: String,
+    }
+
+    fn request(body: String) -> Request {
+        Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_extractor_checks_items_one_by_one() {
+        let body = r#"[{"name":"ada"},{"nom":"grace"},{"name":""}]"#.to_string();
+        let users = Bulk::<NewUser>::from_request(request(body), &())
+            .await
+            .unwrap()
+            .validate_with(|user| match user.name.is_empty() {
+                true => Err(ApiError::unprocessable("name is empty").with_code("empty_name")),
+                false => Ok(()),
+            });
+        assert_eq!(users.len(), 3);
+        assert_eq!(users.items()[0].as_ref().unwrap().name, "ada");
+        assert_eq!(
+            users.items()[1].as_ref().unwrap_err().code(),
+            "invalid_json"
+        );
+        assert_eq!(users.items()[2].as_ref().unwrap_err().code(), "empty_name");
+
+        let error = Bulk::<NewUser>::from_request(request("{}".to_string()), &())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = serde_json::to_string(&vec![json!({}); DEFAULT_MAX_ITEMS + 1]).unwrap();
+        let error = Bulk::<NewUser>::from_request(request(body), &())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "too_many_items");
+
+        let mut limited = request(r#"[{"name":"ada"},{"name":"grace"}]"#.to_string());
+        limited
+            .extensions_mut()
+            .insert(BulkConfig::new().max_items(1));
+        let error = Bulk::<NewUser>::from_request(limited, &())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_process_bounds_concurrency_and_keeps_order() {
+        let values = (0..10).map(|i| json!(i)).collect();
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let response = Bulk::<u32>::from_values(values)
+            .process(3, |n| {
+                let running = &running;
+                let peak = &peak;
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(u64::from(10 - n))).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    match n % 4 {
+                        3 => Err(ApiError::conflict(format!("{} exists", n))),
+                        _ => Ok(n * 10),
+                    }
+                }
+            })
+            .await;
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(response.succeeded(), 8);
+        assert_eq!(response.failed(), 2);
+        assert_eq!(*response.results()[2].as_ref().unwrap(), 20);
+        assert!(response.results()[7].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_response_is_multi_status() {
+        let response = BulkResponse::new(vec![
+            Ok(json!({ "id": 1 })),
+            Err(ApiError::unprocessable("bad").with_code("invalid_json")),
+        ])
+        .success_status(StatusCode::CREATED);
+        assert_eq!(
+            response.to_json().unwrap(),
+            json!({
+                "results": [
+                    { "index": 0, "status": 201, "data": { "id": 1 } },
+                    {
+                        "index": 1,
+                        "status": 422,
+                        "error": { "status": 422, "code": "invalid_json", "message": "bad" }
+                    }
+                ],
+                "succeeded": 1,
+                "failed": 1
+            })
+        );
+        assert_eq!(response.into_response().status(), StatusCode::MULTI_STATUS);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_redacted_without_details() {
+        let response = || {
+            BulkResponse::<Value>::new(vec![
+                Err(ApiError::internal("connection to 10.0.0.7 refused")),
+                Err(ApiError::conflict("ada exists")),
+            ])
+        };
+        let body = response().to_json().unwrap();
+        assert_eq!(
+            body["results"][0]["error"]["message"],
+            "connection to 10.0.0.7 refused"
+        );
+
+        let reporting = profile::ErrorReporting {
+            details: false,
+            debug: false,
+        };
+        let body = REPORTING
+            .scope(reporting, async { response().to_json().unwrap() })
+            .await;
+        assert_eq!(body["results"][0]["status"], 500);
+        assert_eq!(
+            body["results"][0]["error"]["message"],
+            "Internal Server Error"
+        );
+        assert_eq!(body["results"][1]["error"]["message"], "ada exists");
+    }
+}
@@ -0,0 +1,340 @@
+//! Bulkhead isolation for RustAPI framework
+//!
+//! When a dependency slows down, every request calling it waits, and soon
+//! all of the server's capacity is stuck on that one dependency. A
+//! `Bulkhead` caps how many calls to a dependency run at once and how long
+//! callers wait for a turn, so the excess fails fast with 503 and requests
+//! that do not need the dependency keep being served.
+//!
+//! `Bulkheads` keeps them by name in the container. `BulkheadClient` puts
+//! an `HttpClient` behind one, `Db::bulkhead` a database pool, and
+//! `LoadShedder::watch` sheds low priority routes while one is saturated.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::http::StatusCode;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    client::{ClientError, ClientRequest, ClientResponse, HttpClient},
+    context,
+    di::Injectable,
+    error::ApiError,
+    lifecycle::BoxFuture,
+    metrics::Metrics,
+};
+
+/// Default longest wait for a turn before a call is rejected
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Concurrency limit for calls to one dependency
+///
+/// Cheap to clone; clones share the limit.
+///
+/// # Example
+///
+/// ```ignore
+/// let payments = Bulkhead::new("payments", 16).max_wait(Duration::from_millis(50));
+///
+/// let receipt = payments.run(gateway.charge(&order)).await??;
+/// ```
+#[derive(Clone)]
+pub struct Bulkhead {
+    name: Arc<str>,
+    max_concurrent: usize,
+    max_wait: Duration,
+    metrics: Option<Metrics>,
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+}
+
+/// A turn in a bulkhead, given back on drop
+pub struct BulkheadPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Bulkhead {
+    /// Let at most `max_concurrent` calls to `name` run at once
+    pub fn new(name: &str, max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            name: name.into(),
+            max_concurrent,
+            max_wait: DEFAULT_MAX_WAIT,
+            metrics: None,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            waiting: Arc::default(),
+        }
+    }
+
+    /// Reject calls that waited `max_wait` for a turn (default: 100ms)
+    ///
+    /// The wait is also capped to the request's remaining deadline.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Report `bulkhead_in_use` and `bulkhead_waiting` gauges and the
+    /// `bulkhead_rejected_total` counter, labelled with the name, to
+    /// `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        let labels = [("name", &*self.name)];
+        let bulkhead = self.clone();
+        metrics.gauge("bulkhead_in_use", &labels, move || bulkhead.in_use() as f64);
+        let waiting = Arc::clone(&self.waiting);
+        metrics.gauge("bulkhead_waiting", &labels, move || {
+            waiting.load(Ordering::Relaxed) as f64
+        });
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Name of the dependency
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Calls running now
+    pub fn in_use(&self) -> usize {
+        self.max_concurrent - self.permits.available_permits()
+    }
+
+    /// Calls waiting for a turn
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Calls running and waiting relative to the limit; 1.0 or more is
+    /// saturated
+    pub fn saturation(&self) -> f64 {
+        (self.in_use() + self.waiting()) as f64 / self.max_concurrent as f64
+    }
+
+    /// Wait for a turn
+    ///
+    /// Fails with 503 `bulkhead_full` when none frees up in time.
+    pub async fn acquire(&self) -> Result<BulkheadPermit, ApiError> {
+        let waiting = Waiting::new(&self.waiting);
+        let wait = context::cap_timeout(self.max_wait);
+        let permit = tokio::time::timeout(wait, Arc::clone(&self.permits).acquire_owned()).await;
+        drop(waiting);
+        match permit {
+            Ok(Ok(permit)) => Ok(BulkheadPermit { _permit: permit }),
+            Ok(Err(_)) => Err(ApiError::internal("Bulkhead closed")),
+            Err(_) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.increment("bulkhead_rejected_total", &[("name", &self.name)]);
+                }
+                tracing::warn!(bulkhead = %self.name, "Bulkhead full, rejecting call");
+                Err(ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("{} is overloaded, try again later", self.name),
+                )
+                .with_code("bulkhead_full"))
+            }
+        }
+    }
+
+    /// Run `fut` once it gets a turn
+    pub async fn run<F: Future>(&self, fut: F) -> Result<F::Output, ApiError> {
+        let _permit = self.acquire().await?;
+        Ok(fut.await)
+    }
+}
+
+// a caller waiting for a turn, counted until dropped, so an abandoned wait
+// isn't counted forever
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    // count a caller in `waiting`
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Bulkheads by dependency name
+///
+/// Cheap to clone. Install with `App::bulkheads` and inject it into
+/// services.
+///
+/// # Example
+///
+/// ```ignore
+/// let bulkheads = Bulkheads::new()
+///     .bulkhead(Bulkhead::new("payments", 16).metrics(metrics.clone()))
+///     .bulkhead(Bulkhead::new("search", 32).metrics(metrics.clone()));
+/// let app = App::new().bulkheads(bulkheads.clone());
+///
+/// let results = bulkheads.run("search", index.query(&q)).await??;
+/// ```
+#[derive(Clone, Default)]
+pub struct Bulkheads {
+    bulkheads: Arc<HashMap<String, Bulkhead>>,
+}
+
+impl Injectable for Bulkheads {}
+
+impl Bulkheads {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `bulkhead`, replacing any with the same name
+    pub fn bulkhead(mut self, bulkhead: Bulkhead) -> Self {
+        Arc::make_mut(&mut self.bulkheads).insert(bulkhead.name().to_string(), bulkhead);
+        self
+    }
+
+    /// The bulkhead of `name`
+    pub fn get(&self, name: &str) -> Option<&Bulkhead> {
+        self.bulkheads.get(name)
+    }
+
+    /// Every bulkhead, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Bulkhead> {
+        self.bulkheads.values()
+    }
+
+    /// Run `fut` in the bulkhead of `name`
+    ///
+    /// Fails with 500 when there is no such bulkhead.
+    pub async fn run<F: Future>(&self, name: &str, fut: F) -> Result<F::Output, ApiError> {
+        match self.get(name) {
+            Some(bulkhead) => bulkhead.run(fut).await,
+            None => Err(ApiError::internal(format!("No bulkhead named {}", name))),
+        }
+    }
+}
+
+/// `HttpClient` sending requests through a bulkhead
+///
+/// Rejected requests fail with `ClientError::Saturated`, which becomes a
+/// 503.
+///
+/// # Example
+///
+/// ```ignore
+/// let client = BulkheadClient::new(Arc::new(payments_client), bulkheads.get("payments").unwrap().clone());
+/// app.container_mut().register::<dyn HttpClient>(Arc::new(client));
+/// ```
+pub struct BulkheadClient {
+    inner: Arc<dyn HttpClient>,
+    bulkhead: Bulkhead,
+}
+
+impl BulkheadClient {
+    /// Send requests through `inner`, at most as many at once as `bulkhead`
+    /// allows
+    pub fn new(inner: Arc<dyn HttpClient>, bulkhead: Bulkhead) -> Self {
+        Self { inner, bulkhead }
+    }
+}
+
+impl HttpClient for BulkheadClient {
+    fn send(&self, request: ClientRequest) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+        Box::pin(async move {
+            let _permit = self
+                .bulkhead
+                .acquire()
+                .await
+                .map_err(|_| ClientError::Saturated(self.bulkhead.name().to_string()))?;
+            self.inner.send(request).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limits_and_metrics() {
+        let metrics = Metrics::new();
+        let bulkhead = Bulkhead::new("payments", 1)
+            .max_wait(Duration::from_millis(10))
+            .metrics(metrics.clone());
+        let labels = [("name", "payments")];
+
+        let held = bulkhead.acquire().await.unwrap();
+        assert_eq!(bulkhead.in_use(), 1);
+        assert_eq!(metrics.gauge_value("bulkhead_in_use", &labels), Some(1.0));
+        assert_eq!(bulkhead.saturation(), 1.0);
+
+        let error = bulkhead.run(async { 1 }).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.code(), "bulkhead_full");
+        assert_eq!(metrics.counter("bulkhead_rejected_total", &labels), 1);
+
+        let waiter = tokio::spawn({
+            let bulkhead = bulkhead.clone();
+            async move { bulkhead.run(async { 2 }).await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(bulkhead.waiting(), 1);
+        drop(held);
+        assert_eq!(waiter.await.unwrap().unwrap(), 2);
+        assert_eq!((bulkhead.in_use(), bulkhead.waiting()), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_abandoned_waits_are_not_counted() {
+        let bulkhead = Bulkhead::new("payments", 1).max_wait(Duration::from_secs(1));
+        let _held = bulkhead.acquire().await.unwrap();
+
+        let waiter = tokio::spawn({
+            let bulkhead = bulkhead.clone();
+            async move { bulkhead.acquire().await.map(drop) }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(bulkhead.waiting(), 1);
+        waiter.abort();
+        assert!(waiter.await.unwrap_err().is_cancelled());
+        assert_eq!(bulkhead.waiting(), 0);
+        assert_eq!(bulkhead.saturation(), 1.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_client_and_registry() {
+        struct Ok200;
+
+        impl HttpClient for Ok200 {
+            fn send(&self, _: ClientRequest) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+                Box::pin(async { Ok(ClientResponse::new(Default::default())) })
+            }
+        }
+
+        let bulkheads =
+            Bulkheads::new().bulkhead(Bulkhead::new("search", 1).max_wait(Duration::ZERO));
+        let search = bulkheads.get("search").unwrap().clone();
+        let client = BulkheadClient::new(Arc::new(Ok200), search.clone());
+        let request = || ClientRequest::new(Default::default());
+        assert!(client.send(request()).await.is_ok());
+
+        let _held = search.acquire().await.unwrap();
+        let error = client.send(request()).await.unwrap_err();
+        assert!(matches!(error, ClientError::Saturated(ref name) if name == "search"));
+        assert_eq!(
+            ApiError::from(error).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert!(bulkheads.run("billing", async {}).await.is_err());
+    }
+}
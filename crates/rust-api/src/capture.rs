@@ -0,0 +1,457 @@
+//! Body capture for RustAPI framework
+//!
+//! Opt-in debug middleware logging request and response bodies, with
+//! redaction of sensitive headers, query parameters and JSON fields so
+//! secrets never reach the logs. Capture can be switched on and off while
+//! the server runs.
+//!
+//! At most `max_bytes` of a body are held back for the log; the rest is
+//! streamed through untouched. Bodies of unknown length, such as SSE and
+//! other streaming responses, are passed through without being captured.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, Uri},
+    middleware::Next,
+    response::Response,
+};
+use futures_util::{stream, StreamExt};
+
+/// Replacement for redacted values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Default maximum number of body bytes written to the log
+pub const DEFAULT_MAX_BYTES: usize = 4096;
+
+/// What to hide from captured requests and responses
+///
+/// By default the `authorization`, `cookie`, `set-cookie` and `x-api-key`
+/// headers and the `access_token` and `api_key` query parameters are
+/// redacted.
+///
+/// # Example
+///
+/// ```ignore
+/// let redaction = Redaction::default()
+///     .header("x-session-token")
+///     .json_pointer("/password")
+///     .json_pointer("/card/number");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    headers: Vec<String>,
+    query_params: Vec<String>,
+    json_pointers: Vec<String>,
+}
+
+impl Redaction {
+    /// Redact nothing
+    pub fn none() -> Self {
+        Self {
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            json_pointers: Vec::new(),
+        }
+    }
+
+    /// Redact a header (case-insensitive)
+    pub fn header(mut self, name: &str) -> Self {
+        self.headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Redact the value of a query parameter
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.query_params.push(name.to_string());
+        self
+    }
+
+    /// Render a URI for the log, redacting sensitive query parameters
+    pub fn uri_to_string(&self, uri: &Uri) -> String {
+        match uri.query() {
+            Some(query) => format!(
+                "{}?{}",
+                uri.path(),
+                redact_query(query, |name| self.query_params.iter().any(|p| p == name))
+            ),
+            None => uri.path().to_string(),
+        }
+    }
+
+    /// Redact a JSON field by JSON pointer (RFC 6901), e.g. `/user/password`
+    pub fn json_pointer(mut self, pointer: &str) -> Self {
+        self.json_pointers.push(pointer.to_string());
+        self
+    }
+
+    /// Render headers as `name: value` lines, redacting sensitive ones
+    pub fn headers_to_string(&self, headers: &HeaderMap) -> String {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.headers.iter().any(|h| h == name.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("<binary>")
+                };
+                format!("{}: {}", name, value)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Render a body for the log, redacting JSON fields and truncating
+    pub fn body_to_string(&self, body: &[u8], max_bytes: usize) -> String {
+        if let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(body) {
+            for pointer in &self.json_pointers {
+                if let Some(value) = json.pointer_mut(pointer) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                }
+            }
+            return truncate(&json.to_string(), max_bytes);
+        }
+        truncate(&String::from_utf8_lossy(body), max_bytes)
+    }
+
+    // render the first bytes of a body too large to capture whole; JSON
+    // redaction needs the whole document, so nothing is shown when it applies
+    fn head_to_string(&self, head: &[u8], max_bytes: usize) -> String {
+        if !self.json_pointers.is_empty() {
+            return format!("[over {} bytes, not logged]", max_bytes);
+        }
+        let text = String::from_utf8_lossy(head);
+        let mut end = max_bytes.min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}... (over {} bytes)", &text[..end], max_bytes)
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::none()
+            .header(header::AUTHORIZATION.as_str())
+            .header(header::COOKIE.as_str())
+            .header(header::SET_COOKIE.as_str())
+            .header("x-api-key")
+            .query_param("access_token")
+            .query_param("api_key")
+    }
+}
+
+// query string with the values of `sensitive` parameters replaced by
+// `REDACTED`
+pub(crate) fn redact_query(query: &str, sensitive: impl Fn(&str) -> bool) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if sensitive(name) => format!("{}={}", name, REDACTED),
+            None if sensitive(pair) => format!("{}={}", pair, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// cut a string to at most max_bytes, on a character boundary
+fn truncate(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes total)", &text[..end], text.len())
+}
+
+/// Handle controlling body capture
+///
+/// Disabled until `enable()` is called. Cheap to clone; clones share the
+/// on/off switch, so a handle kept elsewhere (e.g. by an admin endpoint) can
+/// toggle capture at runtime.
+///
+/// Up to `max_bytes` of each body are held back until they can be logged,
+/// so only enable this while debugging.
+///
+/// # Example
+///
+/// ```ignore
+/// let capture = BodyCapture::new().redaction(Redaction::default().json_pointer("/password"));
+/// let app = App::new().capture_bodies(capture.clone());
+/// capture.enable();
+/// ```
+#[derive(Clone)]
+pub struct BodyCapture {
+    enabled: Arc<AtomicBool>,
+    max_bytes: usize,
+    redaction: Arc<Redaction>,
+}
+
+impl BodyCapture {
+    /// Create a disabled capture with the default redaction
+    pub fn new() -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(false)),
+            max_bytes: DEFAULT_MAX_BYTES,
+            redaction: Arc::new(Redaction::default()),
+        }
+    }
+
+    /// Maximum number of body bytes logged per message
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Set what to redact
+    pub fn redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Arc::new(redaction);
+        self
+    }
+
+    /// Start capturing
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop capturing
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+
+    /// Check whether capture is on
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for BodyCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware logging request and response bodies at DEBUG when enabled
+pub(crate) async fn capture_bodies(
+    State(capture): State<BodyCapture>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if !capture.is_enabled() {
+        return next.run(req).await;
+    }
+
+    let (parts, body) = req.into_parts();
+    let (logged, body) = capture.peek(body).await;
+    tracing::debug!(
+        method = %parts.method,
+        uri = %capture.redaction.uri_to_string(&parts.uri),
+        headers = %capture.redaction.headers_to_string(&parts.headers),
+        body = %logged,
+        "Captured request"
+    );
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    let (logged, body) = capture.peek(body).await;
+    tracing::debug!(
+        status = parts.status.as_u16(),
+        headers = %capture.redaction.headers_to_string(&parts.headers),
+        body = %logged,
+        "Captured response"
+    );
+    Response::from_parts(parts, body)
+}
+
+impl BodyCapture {
+    // read up to `max_bytes` of a body for the log, returning what to log
+    // and a body yielding the same data, and the same read error, as the
+    // original
+    async fn peek(&self, body: Body) -> (String, Body) {
+        if body.size_hint().exact().is_none() {
+            return ("[streamed body, not captured]".to_string(), body);
+        }
+        let mut data = body.into_data_stream();
+        let mut head: Vec<Bytes> = Vec::new();
+        let mut len = 0;
+        while len <= self.max_bytes {
+            match data.next().await {
+                Some(Ok(chunk)) => {
+                    len += chunk.len();
+                    head.push(chunk);
+                }
+                Some(Err(e)) => {
+                    let logged = format!("[body read failed: {}]", e);
+                    let replay = stream::iter(head.into_iter().map(Ok))
+                        .chain(stream::once(async move { Err(e) }))
+                        .chain(data);
+                    return (logged, Body::from_stream(replay));
+                }
+                None => {
+                    let bytes = Bytes::from(head.concat());
+                    let logged = self.redaction.body_to_string(&bytes, self.max_bytes);
+                    return (logged, Body::from(bytes));
+                }
+            }
+        }
+        let logged = self
+            .redaction
+            .head_to_string(&head.concat(), self.max_bytes);
+        let replay = stream::iter(head.into_iter().map(Ok)).chain(data);
+        (logged, Body::from_stream(replay))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::HeaderValue, middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[test]
+    fn test_redact_query() {
+        let sensitive = |name: &str| name == "access_token";
+        assert_eq!(
+            redact_query("q=rust&access_token=abc&page=2", sensitive),
+            "q=rust&access_token=[REDACTED]&page=2"
+        );
+        assert_eq!(
+            redact_query("access_token", sensitive),
+            "access_token=[REDACTED]"
+        );
+        assert_eq!(redact_query("a=1&b", |_| true), "a=[REDACTED]&b=[REDACTED]");
+        assert_eq!(redact_query("", |_| true), "");
+    }
+
+    #[test]
+    fn test_redacts_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("*/*"));
+        let text = Redaction::default().headers_to_string(&headers);
+        assert!(text.contains("authorization: [REDACTED]"));
+        assert!(text.contains("accept: */*"));
+    }
+
+    #[test]
+    fn test_redacts_json_pointers() {
+        let redaction = Redaction::none()
+            .json_pointer("/password")
+            .json_pointer("/card/number");
+        let body = br#"{"user":"a","password":"hunter2","card":{"number":"4111"}}"#;
+        let text = redaction.body_to_string(body, 1024);
+        assert!(!text.contains("hunter2"));
+        assert!(!text.contains("4111"));
+        assert!(text.contains("\"user\":\"a\""));
+    }
+
+    #[test]
+    fn test_truncates_on_char_boundary() {
+        let text = Redaction::none().body_to_string("héllo".as_bytes(), 2);
+        assert_eq!(text, "h... (6 bytes total)");
+    }
+
+    #[tokio::test]
+    async fn test_capture_passes_bodies_through() {
+        let capture = BodyCapture::new();
+        let router = Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .layer(middleware::from_fn_with_state(
+                capture.clone(),
+                capture_bodies,
+            ));
+
+        for enabled in [false, true] {
+            if enabled {
+                capture.enable();
+            }
+            let request = Request::post("/echo").body(Body::from("ping")).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, "ping");
+        }
+        assert!(capture.is_enabled());
+        capture.disable();
+        assert!(!capture.is_enabled());
+    }
+
+    // records the fields of every event as `name=value`
+    #[derive(Clone, Default)]
+    struct Fields(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl tracing::field::Visit for Fields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            let entry = format!("{}={:?}", field.name(), value);
+            self.0.lock().unwrap().push(entry);
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Fields {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            event.record(&mut self.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_caps_and_streams_bodies() {
+        use tracing_subscriber::prelude::*;
+
+        let fields = Fields::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(fields.clone()));
+        let capture = BodyCapture::new().max_bytes(8);
+        capture.enable();
+        let router = Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .route(
+                "/events",
+                axum::routing::get(|| async {
+                    let chunks = ["data: 1\n\n", "data: 2\n\n"].map(Ok::<_, std::io::Error>);
+                    Body::from_stream(futures_util::stream::iter(chunks))
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                capture.clone(),
+                capture_bodies,
+            ));
+
+        let request = Request::post("/echo?q=1&access_token=secret")
+            .body(Body::from("0123456789abcdef"))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "0123456789abcdef");
+
+        let request = Request::get("/events").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "data: 1\n\ndata: 2\n\n");
+
+        let logged = fields.0.lock().unwrap().join("\n");
+        assert!(logged.contains("uri=/echo?q=1&access_token=[REDACTED]"));
+        assert!(!logged.contains("secret"));
+        assert!(logged.contains("body=01234567... (over 8 bytes)"));
+        assert!(logged.contains("body=[streamed body, not captured]"));
+    }
+}
@@ -0,0 +1,464 @@
+//! API changelog generation for RustAPI framework
+//!
+//! Compares two OpenAPI documents, typically a snapshot committed with the
+//! last release and `App::openapi_spec()`, and lists what consumers of the
+//! API will notice: added and removed operations, new or dropped
+//! parameters and responses, deprecations and error codes. Every change is
+//! classified as breaking or not, and the whole renders as Markdown.
+//!
+//! `cli` wraps this for a subcommand of the application binary, so the
+//! changelog can be produced in CI:
+//!
+//! ```ignore
+//! let app = wire_up(App::new());
+//! let mut args = std::env::args().skip(1);
+//! match args.next().as_deref() {
+//!     Some("api-changelog") => std::process::exit(changelog::cli(&app, args)),
+//!     _ => app.serve("0.0.0.0:3000").await?,
+//! }
+//! ```
+
+use std::{collections::BTreeSet, fmt, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    app::App,
+    error::{Error, Result},
+};
+
+/// What happened to a part of the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChangeKind {
+    /// Something new is available
+    Added,
+    /// Something is gone
+    Removed,
+    /// Something was marked deprecated
+    Deprecated,
+    /// Something behaves differently
+    Changed,
+}
+
+/// One consumer-visible difference between two documents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// What happened
+    pub kind: ChangeKind,
+    /// Operation it applies to, such as `GET /users/{id}`; empty for
+    /// document-wide changes
+    pub operation: String,
+    /// Human-readable description
+    pub description: String,
+    /// Whether existing clients can break
+    pub breaking: bool,
+}
+
+/// Differences between two OpenAPI documents
+///
+/// # Example
+///
+/// ```ignore
+/// let changes = ApiChangelog::from_files("openapi/v1.3.json", "openapi/v1.4.json")?;
+/// assert!(!changes.is_breaking(), "{}", changes);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiChangelog {
+    changes: Vec<Change>,
+}
+
+impl ApiChangelog {
+    /// Compare `old` and `new`
+    pub fn between(old: &Value, new: &Value) -> Self {
+        let mut log = Self::default();
+        let (old_ops, new_ops) = (operations(old), operations(new));
+        for (key, op) in &old_ops {
+            match new_ops.iter().find(|(other, _)| other == key) {
+                Some((_, new_op)) => log.compare_operation(key, op, new_op),
+                None => log.push(ChangeKind::Removed, key, "Operation removed", true),
+            }
+        }
+        for (key, _) in &new_ops {
+            if !old_ops.iter().any(|(other, _)| other == key) {
+                log.push(ChangeKind::Added, key, "Operation added", false);
+            }
+        }
+
+        let (old_codes, new_codes) = (error_codes(old), error_codes(new));
+        for code in new_codes.difference(&old_codes) {
+            let description = format!("Error code `{}` added", code);
+            log.push(ChangeKind::Added, "", &description, false);
+        }
+        for code in old_codes.difference(&new_codes) {
+            let description = format!("Error code `{}` removed", code);
+            log.push(ChangeKind::Removed, "", &description, false);
+        }
+        log
+    }
+
+    /// Compare the JSON documents at `old` and `new`
+    pub fn from_files(old: impl AsRef<Path>, new: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::between(
+            &read_spec(old.as_ref())?,
+            &read_spec(new.as_ref())?,
+        ))
+    }
+
+    /// Every change, by operation, then error code changes
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// The breaking changes
+    pub fn breaking(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(|change| change.breaking)
+    }
+
+    /// Check whether any change can break existing clients
+    pub fn is_breaking(&self) -> bool {
+        self.breaking().next().is_some()
+    }
+
+    /// Check whether the documents are equivalent for consumers
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // differences within one operation present in both documents
+    fn compare_operation(&mut self, key: &str, old: &Value, new: &Value) {
+        let (was, is) = (is_deprecated(old), is_deprecated(new));
+        if !was && is {
+            self.push(ChangeKind::Deprecated, key, "Operation deprecated", false);
+        } else if was && !is {
+            self.push(ChangeKind::Changed, key, "No longer deprecated", false);
+        }
+
+        let (old_params, new_params) = (parameters(old), parameters(new));
+        for (name, required) in &old_params {
+            match new_params.iter().find(|(other, _)| other == name) {
+                None => {
+                    let description = format!("Parameter `{}` removed", name);
+                    self.push(ChangeKind::Removed, key, &description, true);
+                }
+                Some((_, true)) if !required => {
+                    let description = format!("Parameter `{}` is now required", name);
+                    self.push(ChangeKind::Changed, key, &description, true);
+                }
+                Some((_, false)) if *required => {
+                    let description = format!("Parameter `{}` is now optional", name);
+                    self.push(ChangeKind::Changed, key, &description, false);
+                }
+                _ => {}
+            }
+        }
+        for (name, required) in &new_params {
+            if !old_params.iter().any(|(other, _)| other == name) {
+                let description = if *required {
+                    format!("Required parameter `{}` added", name)
+                } else {
+                    format!("Optional parameter `{}` added", name)
+                };
+                self.push(ChangeKind::Added, key, &description, *required);
+            }
+        }
+
+        let (old_statuses, new_statuses) = (responses(old), responses(new));
+        for status in old_statuses.difference(&new_statuses) {
+            let description = format!("Response {} removed", status);
+            self.push(ChangeKind::Removed, key, &description, true);
+        }
+        for status in new_statuses.difference(&old_statuses) {
+            let description = format!("Response {} added", status);
+            self.push(ChangeKind::Added, key, &description, false);
+        }
+    }
+
+    // record a change
+    fn push(&mut self, kind: ChangeKind, operation: &str, description: &str, breaking: bool) {
+        self.changes.push(Change {
+            kind,
+            operation: operation.to_string(),
+            description: description.to_string(),
+            breaking,
+        });
+    }
+
+    // write one section of the Markdown changelog
+    fn section(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        title: &str,
+        include: impl Fn(&Change) -> bool,
+    ) -> fmt::Result {
+        let changes: Vec<&Change> = self.changes.iter().filter(|c| include(c)).collect();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "\n### {}\n", title)?;
+        for change in changes {
+            if change.operation.is_empty() {
+                writeln!(f, "- {}", change.description)?;
+            } else {
+                writeln!(f, "- `{}`: {}", change.operation, change.description)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Markdown changelog, breaking changes first
+impl fmt::Display for ApiChangelog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "## API changes")?;
+        if self.is_empty() {
+            return writeln!(f, "\nNo changes.");
+        }
+        self.section(f, "Breaking", |c| c.breaking)?;
+        self.section(f, "Added", |c| !c.breaking && c.kind == ChangeKind::Added)?;
+        self.section(f, "Deprecated", |c| {
+            !c.breaking && c.kind == ChangeKind::Deprecated
+        })?;
+        self.section(f, "Changed", |c| {
+            !c.breaking && matches!(c.kind, ChangeKind::Changed | ChangeKind::Removed)
+        })
+    }
+}
+
+/// Print the changelog between a snapshot and `app`'s current document
+///
+/// `args` are the subcommand's arguments: the old snapshot's path, then
+/// optionally the new one's (default: `app.openapi_spec()`), and
+/// `--fail-on-breaking`. Returns the process exit code: 1 on errors and,
+/// with `--fail-on-breaking`, on breaking changes.
+pub fn cli(app: &App, args: impl IntoIterator<Item = String>) -> i32 {
+    let mut fail_on_breaking = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--fail-on-breaking" => fail_on_breaking = true,
+            _ => paths.push(arg),
+        }
+    }
+    let changelog = match paths.as_slice() {
+        [old] => {
+            read_spec(Path::new(old)).map(|old| ApiChangelog::between(&old, &app.openapi_spec()))
+        }
+        [old, new] => ApiChangelog::from_files(old, new),
+        _ => Err(Error::other(
+            "usage: api-changelog <old.json> [new.json] [--fail-on-breaking]",
+        )),
+    };
+    match changelog {
+        Ok(changelog) => {
+            print!("{}", changelog);
+            i32::from(fail_on_breaking && changelog.is_breaking())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+// parse the JSON document at `path`
+fn read_spec(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::other(format!("Cannot read {}: {}", path.display(), e)))?;
+    serde_json::from_str(&text).map_err(|e| {
+        Error::other(format!(
+            "Invalid OpenAPI document {}: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+// `METHOD /path` and operation object of every operation, sorted
+pub(crate) fn operations(document: &Value) -> Vec<(String, &Value)> {
+    const METHODS: [&str; 8] = [
+        "get", "put", "post", "delete", "options", "head", "patch", "trace",
+    ];
+    let Some(paths) = document["paths"].as_object() else {
+        return Vec::new();
+    };
+    let mut operations: Vec<_> = paths
+        .iter()
+        .flat_map(|(path, item)| {
+            METHODS.iter().filter_map(move |method| {
+                let operation = item.get(*method)?;
+                Some((
+                    format!("{} {}", method.to_ascii_uppercase(), path),
+                    operation,
+                ))
+            })
+        })
+        .collect();
+    operations.sort_by(|a, b| a.0.cmp(&b.0));
+    operations
+}
+
+// name and requiredness of an operation's parameters
+fn parameters(operation: &Value) -> Vec<(String, bool)> {
+    let Some(params) = operation["parameters"].as_array() else {
+        return Vec::new();
+    };
+    params
+        .iter()
+        .filter_map(|param| {
+            let name = param["name"].as_str()?;
+            let location = param["in"].as_str().unwrap_or("query");
+            let required = param["required"].as_bool().unwrap_or(location == "path");
+            Some((format!("{} ({})", name, location), required))
+        })
+        .collect()
+}
+
+// documented response statuses of an operation
+fn responses(operation: &Value) -> BTreeSet<String> {
+    operation["responses"]
+        .as_object()
+        .map(|responses| responses.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+// whether an operation is marked deprecated
+fn is_deprecated(operation: &Value) -> bool {
+    operation["deprecated"].as_bool().unwrap_or(false)
+}
+
+// codes of the `ErrorCode` schema
+fn error_codes(document: &Value) -> BTreeSet<String> {
+    document["components"]["schemas"]["ErrorCode"]["enum"]
+        .as_array()
+        .map(|codes| {
+            codes
+                .iter()
+                .filter_map(|code| code.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{deprecation::Deprecated, openapi::OpenApi, registry::RouteRegistry};
+
+    fn spec(build: impl FnOnce(&mut RouteRegistry)) -> Value {
+        let mut registry = RouteRegistry::default();
+        build(&mut registry);
+        OpenApi::default().document(&registry)
+    }
+
+    #[test]
+    fn test_classifies_changes() {
+        let old = spec(|r| {
+            r.register("GET", "/users", "app::list_users");
+            r.register("GET", "/users/{id}", "app::get_user");
+            r.register("DELETE", "/users/{id}", "app::delete_user");
+        });
+        let mut new = spec(|r| {
+            r.register("GET", "/users", "app::list_users")
+                .metadata
+                .insert(Deprecated::new());
+            r.register("GET", "/users/{id}", "app::get_user");
+            r.register("POST", "/users", "app::create_user");
+        });
+        new["paths"]["/users/{id}"]["get"]["parameters"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!({ "name": "fields", "in": "query" }));
+        new["components"]["schemas"]["ErrorCode"] = json!({ "enum": ["user_locked"] });
+
+        let changelog = ApiChangelog::between(&old, &new);
+        let summary: Vec<_> = changelog
+            .changes()
+            .iter()
+            .map(|c| (c.kind, c.operation.as_str(), c.breaking))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ChangeKind::Removed, "DELETE /users/{id}", true),
+                (ChangeKind::Deprecated, "GET /users", false),
+                (ChangeKind::Added, "GET /users/{id}", false),
+                (ChangeKind::Added, "POST /users", false),
+                (ChangeKind::Added, "", false),
+            ]
+        );
+        assert!(changelog.is_breaking());
+
+        let markdown = changelog.to_string();
+        assert!(markdown.contains("### Breaking\n\n- `DELETE /users/{id}`: Operation removed"));
+        assert!(markdown.contains("- `GET /users/{id}`: Optional parameter `fields (query)` added"));
+        assert!(markdown.contains("- Error code `user_locked` added"));
+        assert!(markdown.contains("### Deprecated\n\n- `GET /users`: Operation deprecated"));
+
+        assert!(ApiChangelog::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_parameter_and_response_changes() {
+        let old = json!({ "paths": { "/orders": { "get": {
+            "parameters": [
+                { "name": "page", "in": "query" },
+                { "name": "sort", "in": "query", "required": true },
+            ],
+            "responses": { "200": {}, "404": {} },
+        }}}});
+        let new = json!({ "paths": { "/orders": { "get": {
+            "parameters": [
+                { "name": "page", "in": "query", "required": true },
+                { "name": "sort", "in": "query" },
+                { "name": "tenant", "in": "header", "required": true },
+            ],
+            "responses": { "200": {} },
+        }}}});
+
+        let changelog = ApiChangelog::between(&old, &new);
+        let breaking: Vec<_> = changelog
+            .breaking()
+            .map(|c| c.description.as_str())
+            .collect();
+        assert_eq!(
+            breaking,
+            vec![
+                "Parameter `page (query)` is now required",
+                "Required parameter `tenant (header)` added",
+                "Response 404 removed",
+            ]
+        );
+        assert_eq!(changelog.changes().len(), 4);
+    }
+
+    #[test]
+    fn test_cli() {
+        let dir = std::env::temp_dir().join(format!("rust-api-changelog-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.json");
+        let new = dir.join("new.json");
+        std::fs::write(
+            &old,
+            json!({ "paths": { "/a": { "get": {} } } }).to_string(),
+        )
+        .unwrap();
+        std::fs::write(&new, json!({ "paths": {} }).to_string()).unwrap();
+        let args = |list: &[&std::path::Path], flag: bool| {
+            let mut args: Vec<String> = list.iter().map(|p| p.display().to_string()).collect();
+            if flag {
+                args.push("--fail-on-breaking".to_string());
+            }
+            args
+        };
+
+        let app = App::new();
+        assert_eq!(cli(&app, args(&[&old, &new], false)), 0);
+        assert_eq!(cli(&app, args(&[&old, &new], true)), 1);
+        assert_eq!(cli(&app, args(&[&new, &old], true)), 0);
+        assert_eq!(cli(&app, args(&[&dir.join("missing.json")], false)), 1);
+        assert_eq!(cli(&app, Vec::new()), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
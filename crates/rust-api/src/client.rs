@@ -0,0 +1,193 @@
+//! Outbound HTTP client abstraction for RustAPI framework
+//!
+//! Services that call third-party APIs depend on `dyn HttpClient` from the
+//! container instead of a concrete client, so the transport (reqwest, hyper,
+//! a test fake) can be chosen when the application is wired up. The
+//! container's client is wrapped in `Correlated` when the app is built, to
+//! send the current request's id along, so the remote service's logs can be
+//! matched with ours, and to give up on calls still running when the
+//! request deadline passes.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderValue, Request, Response},
+};
+use thiserror::Error;
+
+use crate::{
+    context::{self, RequestContext, REQUEST_ID_HEADER},
+    di::Injectable,
+    lifecycle::BoxFuture,
+};
+
+/// Outbound request with a buffered body
+pub type ClientRequest = Request<Bytes>;
+
+/// Response to an outbound request with a buffered body
+pub type ClientResponse = Response<Bytes>;
+
+/// Failure to get a response from the remote service
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The connection could not be established or broke down
+    #[error("Connection failed: {0}")]
+    Connect(String),
+
+    /// No response within the configured timeout
+    #[error("Request timed out")]
+    Timeout,
+
+    /// Not sent because the dependency's bulkhead is full
+    #[error("Bulkhead {0} is full")]
+    Saturated(String),
+
+    /// The response body exceeded the request's `ResponseLimit`, in bytes
+    #[error("Response larger than {0} bytes")]
+    TooLarge(usize),
+
+    /// Any other transport failure
+    #[error("HTTP client error: {0}")]
+    Other(String),
+}
+
+/// Largest response body to buffer for a request, in bytes
+///
+/// Set as a request extension; clients stop reading a larger response and
+/// fail with `ClientError::TooLarge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseLimit(pub usize);
+
+/// Sends outbound HTTP requests
+///
+/// Implement it over the HTTP client of your choice and register it as
+/// `dyn HttpClient`; non-2xx responses are returned as `Ok`. Honour the
+/// request's `ResponseLimit` extension, if any, while buffering the body.
+///
+/// # Example
+///
+/// ```ignore
+/// struct Reqwest(reqwest::Client);
+///
+/// impl HttpClient for Reqwest {
+///     fn send(&self, request: ClientRequest) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+///         Box::pin(async move { /* convert and send */ })
+///     }
+/// }
+///
+/// app.container_mut().register::<dyn HttpClient>(Arc::new(Reqwest(reqwest::Client::new())));
+/// ```
+pub trait HttpClient: Send + Sync + 'static {
+    /// Send a request and buffer the response
+    fn send(&self, request: ClientRequest) -> BoxFuture<'_, Result<ClientResponse, ClientError>>;
+}
+
+impl Injectable for dyn HttpClient {}
+
+// sends through reqwest, e.g. as the default client of `storage::StorageConfig`
+#[cfg(feature = "reqwest")]
+impl HttpClient for reqwest::Client {
+    fn send(&self, request: ClientRequest) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+        Box::pin(async move {
+            let limit = request
+                .extensions()
+                .get::<ResponseLimit>()
+                .map(|limit| limit.0);
+            let request = reqwest::Request::try_from(request)
+                .map_err(|e| ClientError::Other(e.to_string()))?;
+            let mut response = self.execute(request).await.map_err(|e| {
+                if e.is_timeout() {
+                    ClientError::Timeout
+                } else if e.is_connect() {
+                    ClientError::Connect(e.to_string())
+                } else {
+                    ClientError::Other(e.to_string())
+                }
+            })?;
+            let mut builder = Response::builder()
+                .status(response.status())
+                .version(response.version());
+            if let Some(headers) = builder.headers_mut() {
+                *headers = response.headers().clone();
+            }
+            let Some(limit) = limit else {
+                let body = response
+                    .bytes()
+                    .await
+                    .map_err(|e| ClientError::Other(e.to_string()))?;
+                return builder
+                    .body(body)
+                    .map_err(|e| ClientError::Other(e.to_string()));
+            };
+            if response
+                .content_length()
+                .is_some_and(|length| length > limit as u64)
+            {
+                return Err(ClientError::TooLarge(limit));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response
+                .chunk()
+                .await
+                .map_err(|e| ClientError::Other(e.to_string()))?
+            {
+                if body.len() + chunk.len() > limit {
+                    return Err(ClientError::TooLarge(limit));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            builder
+                .body(Bytes::from(body))
+                .map_err(|e| ClientError::Other(e.to_string()))
+        })
+    }
+}
+
+/// `HttpClient` sending the current request's id in `x-request-id`
+///
+/// Calls made during a request with a deadline (`App::request_timeout`)
+/// fail with `ClientError::Timeout` when the deadline passes. The
+/// `dyn HttpClient` registered in the app's container is wrapped in one
+/// when the app is built; wrap clients used outside the container yourself.
+///
+/// # Example
+///
+/// ```ignore
+/// let client = Correlated::new(Arc::new(Reqwest(reqwest::Client::new())));
+/// let store = S3Store::new(config, Arc::new(client))?;
+/// ```
+pub struct Correlated {
+    inner: Arc<dyn HttpClient>,
+}
+
+impl Correlated {
+    /// Send requests through `inner`
+    pub fn new(inner: Arc<dyn HttpClient>) -> Self {
+        Self { inner }
+    }
+}
+
+impl HttpClient for Correlated {
+    fn send(
+        &self,
+        mut request: ClientRequest,
+    ) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+        propagate_request_id(request.headers_mut());
+        Box::pin(async move {
+            context::within_deadline(self.inner.send(request))
+                .await
+                .unwrap_or(Err(ClientError::Timeout))
+        })
+    }
+}
+
+/// Set `x-request-id` in outbound `headers` to the current request's id
+///
+/// Does nothing outside a request.
+pub fn propagate_request_id(headers: &mut HeaderMap) {
+    let id = RequestContext::with_current(|ctx| HeaderValue::from_str(ctx.request_id()).ok());
+    if let Some(id) = id.flatten() {
+        headers.insert(REQUEST_ID_HEADER, id);
+    }
+}
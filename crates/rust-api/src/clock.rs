@@ -0,0 +1,156 @@
+//! Time source for RustAPI framework
+//!
+//! Components that depend on the current time read it through a `Clock`
+//! instead of calling `Instant::now()` directly, so tests can swap in a
+//! `TestClock` and move time forward by hand.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use tokio::time::Instant;
+
+use crate::di::Injectable;
+
+/// Source of the current time
+///
+/// Register one in the container to make it available to handlers:
+///
+/// ```ignore
+/// app.container_mut().register::<dyn Clock>(Arc::new(SystemClock));
+///
+/// async fn create_session(Inject(clock): Inject<dyn Clock>) -> Json<Session> {
+///     Json(Session::new(clock.system_time() + Duration::from_secs(3600)))
+/// }
+/// ```
+pub trait Clock: Send + Sync + 'static {
+    /// Monotonic time, for measuring intervals
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for timestamps
+    fn system_time(&self) -> SystemTime;
+}
+
+impl Injectable for dyn Clock {}
+
+/// The real clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Clones share the same time, so a test can keep one handle and give
+/// another to the component under test.
+///
+/// # Example
+///
+/// ```ignore
+/// let clock = TestClock::new();
+/// let quotas = Quotas::builder(QuotaTier::new("free", 1, Duration::from_secs(60)))
+///     .clock(Arc::new(clock.clone()))
+///     .build();
+///
+/// assert!(quotas.check("alice").await.allowed);
+/// assert!(!quotas.check("alice").await.allowed);
+/// clock.advance(Duration::from_secs(60));
+/// assert!(quotas.check("alice").await.allowed);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    inner: Arc<Mutex<TestTime>>,
+}
+
+// starting points and the time elapsed since
+#[derive(Debug)]
+struct TestTime {
+    instant: Instant,
+    system: SystemTime,
+    elapsed: Duration,
+}
+
+impl TestClock {
+    /// Create a clock frozen at the current time
+    pub fn new() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    /// Create a clock frozen at the given wall-clock time
+    pub fn at(system_time: SystemTime) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(TestTime {
+                instant: Instant::now(),
+                system: system_time,
+                elapsed: Duration::ZERO,
+            })),
+        }
+    }
+
+    /// Move time forward
+    pub fn advance(&self, by: Duration) {
+        self.time().elapsed += by;
+    }
+
+    /// Time elapsed since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        self.time().elapsed
+    }
+
+    // lock the shared time
+    fn time(&self) -> std::sync::MutexGuard<'_, TestTime> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        let time = self.time();
+        time.instant + time.elapsed
+    }
+
+    fn system_time(&self) -> SystemTime {
+        let time = self.time();
+        time.system + time.elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_test_clock_advances() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::at(start);
+        let before = clock.now();
+        assert_eq!(clock.now(), before);
+
+        clock.clone().advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - before, Duration::from_secs(90));
+        assert_eq!(clock.system_time(), start + Duration::from_secs(90));
+        assert_eq!(clock.elapsed(), Duration::from_secs(90));
+    }
+
+    #[test]
+    fn test_clock_as_service() {
+        let mut container = crate::di::Container::new();
+        container.register::<dyn Clock>(Arc::new(TestClock::new()));
+        assert!(container.resolve::<dyn Clock>().is_some());
+    }
+}
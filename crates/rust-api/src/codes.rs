@@ -0,0 +1,246 @@
+//! Error code catalog for RustAPI framework
+//!
+//! Application error codes declared with `#[derive(ErrorCode)]` are
+//! collected into a process-wide catalog. The catalog is embedded in the
+//! OpenAPI document and can be served with `App::error_catalog`, so clients
+//! can program against stable codes instead of message strings.
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use crate::{error::ApiError, registry::inventory};
+
+/// An application error with a stable, documented code
+///
+/// Usually derived; converting a value into `ApiError` renders the code,
+/// default status and message in the standard envelope.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Debug, ErrorCode)]
+/// enum BillingError {
+///     /// The card was declined by the issuer
+///     #[error_code(status = 402, docs = "https://docs.example.com/errors/card_declined")]
+///     CardDeclined,
+///     #[error_code(code = "plan_limit", status = 403, message = "Plan limit reached")]
+///     PlanLimitReached { limit: u32 },
+/// }
+///
+/// async fn charge() -> Result<Json<Receipt>, ApiError> {
+///     Err(BillingError::CardDeclined)?
+/// }
+/// ```
+pub trait ErrorCode {
+    /// Machine-readable code, e.g. `card_declined`
+    fn code(&self) -> &'static str;
+
+    /// Default HTTP status
+    fn status(&self) -> StatusCode;
+
+    /// Human-readable message
+    fn message(&self) -> &'static str;
+
+    /// Link to documentation of the error
+    fn docs_url(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+impl ApiError {
+    /// Create an error from a declared error code
+    pub fn from_code(error: &impl ErrorCode) -> Self {
+        let api_error = ApiError::new(error.status(), error.message()).with_code(error.code());
+        match error.docs_url() {
+            Some(url) => api_error.with_docs(url),
+            None => api_error,
+        }
+    }
+}
+
+/// Catalog entry of a declared error code
+///
+/// Submitted automatically by `#[derive(ErrorCode)]`; not usually built by
+/// hand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCodeInfo {
+    code: &'static str,
+    status: u16,
+    message: &'static str,
+    docs: Option<&'static str>,
+}
+
+impl ErrorCodeInfo {
+    /// Declare an error code
+    pub const fn new(
+        code: &'static str,
+        status: u16,
+        message: &'static str,
+        docs: Option<&'static str>,
+    ) -> Self {
+        Self {
+            code,
+            status,
+            message,
+            docs,
+        }
+    }
+
+    /// Machine-readable code
+    pub const fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Default HTTP status
+    pub const fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Human-readable message
+    pub const fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// Link to documentation of the error
+    pub const fn docs(&self) -> Option<&'static str> {
+        self.docs
+    }
+
+    /// JSON representation used by the catalog endpoint and OpenAPI
+    pub fn to_json(&self) -> Value {
+        let mut entry = json!({
+            "code": self.code,
+            "status": self.status,
+            "message": self.message,
+        });
+        if let Some(docs) = self.docs {
+            entry["docs"] = json!(docs);
+        }
+        entry
+    }
+}
+
+inventory::collect!(ErrorCodeInfo);
+
+/// Every declared error code, sorted by code
+///
+/// A code may be declared more than once with the same status; the entry
+/// with the smallest message is kept, so the result does not depend on
+/// link order.
+///
+/// # Panics
+///
+/// Panics when a code is declared with two different statuses.
+pub fn catalog() -> Vec<ErrorCodeInfo> {
+    collect_codes(inventory::iter::<ErrorCodeInfo>.into_iter().copied())
+}
+
+// sort and deduplicate declarations, rejecting conflicting statuses
+fn collect_codes(declared: impl IntoIterator<Item = ErrorCodeInfo>) -> Vec<ErrorCodeInfo> {
+    let mut codes: Vec<ErrorCodeInfo> = declared.into_iter().collect();
+    codes.sort_by_key(|info| (info.code, info.status, info.message, info.docs));
+    for pair in codes.windows(2) {
+        if pair[0].code == pair[1].code && pair[0].status != pair[1].status {
+            panic!(
+                "error code {:?} is declared with statuses {} and {}",
+                pair[0].code, pair[0].status, pair[1].status
+            );
+        }
+    }
+    codes.dedup_by_key(|info| info.code);
+    codes
+}
+
+/// JSON body of the catalog endpoint
+pub fn catalog_json(codes: &[ErrorCodeInfo]) -> Value {
+    json!({ "errors": codes.iter().map(ErrorCodeInfo::to_json).collect::<Vec<_>>() })
+}
+
+// add the catalog to an OpenAPI document as the `ErrorCode` schema
+pub(crate) fn embed(document: &mut Value, codes: &[ErrorCodeInfo]) {
+    if codes.is_empty() {
+        return;
+    }
+    let schema = json!({
+        "type": "string",
+        "description": "Machine-readable code of an error response",
+        "enum": codes.iter().map(|info| info.code).collect::<Vec<_>>(),
+        "x-error-codes": codes.iter().map(ErrorCodeInfo::to_json).collect::<Vec<_>>(),
+    });
+    document["components"]["schemas"]["ErrorCode"] = schema;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorCode;
+
+    #[allow(dead_code)]
+    #[derive(Debug, ErrorCode)]
+    enum BillingError {
+        /// The card was declined by the issuer
+        #[error_code(status = 402, docs = "https://docs.example.com/errors/card_declined")]
+        CardDeclined,
+        #[error_code(code = "plan_limit", status = 403, message = "Plan limit reached")]
+        PlanLimitReached { limit: u32 },
+        #[error_code(status = 503)]
+        GatewayDown(String),
+    }
+
+    #[test]
+    fn test_derived_error_codes() {
+        let error = BillingError::PlanLimitReached { limit: 3 };
+        assert_eq!(error.code(), "plan_limit");
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            BillingError::CardDeclined.message(),
+            "The card was declined by the issuer"
+        );
+        assert_eq!(BillingError::GatewayDown("x".into()).code(), "gateway_down");
+        assert_eq!(
+            BillingError::GatewayDown("x".into()).message(),
+            "gateway_down"
+        );
+
+        let api_error = ApiError::from(BillingError::CardDeclined);
+        assert_eq!(api_error.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            api_error.to_json()["error"]["docs"],
+            "https://docs.example.com/errors/card_declined"
+        );
+    }
+
+    #[test]
+    fn test_catalog_is_sorted_and_embedded() {
+        let names: Vec<_> = catalog().iter().map(ErrorCodeInfo::code).collect();
+        for code in ["card_declined", "gateway_down", "plan_limit"] {
+            assert!(names.contains(&code), "{} is not in the catalog", code);
+        }
+
+        let codes = collect_codes([
+            ErrorCodeInfo::new("plan_limit", 403, "Plan limit reached", None),
+            ErrorCodeInfo::new("card_declined", 402, "Declined", None),
+            ErrorCodeInfo::new("plan_limit", 403, "Limit reached", None),
+        ]);
+        let names: Vec<_> = codes.iter().map(ErrorCodeInfo::code).collect();
+        assert_eq!(names, ["card_declined", "plan_limit"]);
+        assert_eq!(codes[1].message(), "Limit reached");
+        assert_eq!(catalog_json(&codes)["errors"][1]["status"], 403);
+
+        let mut document = json!({ "openapi": "3.1.0" });
+        embed(&mut document, &codes);
+        assert_eq!(
+            document["components"]["schemas"]["ErrorCode"]["enum"][0],
+            "card_declined"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "declared with statuses 400 and 422")]
+    fn test_conflicting_statuses_panic() {
+        collect_codes([
+            ErrorCodeInfo::new("unknown_fields", 422, "Unknown fields", None),
+            ErrorCodeInfo::new("unknown_fields", 400, "Unknown fields", None),
+        ]);
+    }
+}
@@ -0,0 +1,787 @@
+//! Response compression for RustAPI framework
+//!
+//! Compresses response bodies with the encoding the client prefers, but only
+//! when it pays off: small bodies and content types that are already
+//! compressed (images, video, archives) are sent as they are, and a CPU budget
+//! stops compression when the server is spending too long on it. Bytes saved
+//! are counted so the trade-off can be watched in production.
+//!
+//! A strong `ETag` on a compressed response gets the encoding as a suffix
+//! (`"v1"` becomes `"v1-gzip"`), keeping it strong but distinct from the
+//! uncompressed representation. The suffix is stripped from the request's
+//! `If-Match` and `If-None-Match` tags, so handlers compare against the tags
+//! they issued, and a 304 answering a suffixed tag gets the suffix back.
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{conditional::ETag, metrics::Metrics};
+
+/// Default smallest body worth compressing, in bytes
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Default largest body compressed, in bytes; larger bodies are streamed as is
+pub const DEFAULT_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// Content types skipped by default, matched as prefixes
+pub const DEFAULT_SKIPPED_CONTENT_TYPES: &[&str] = &[
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/octet-stream",
+    "application/grpc",
+    "text/event-stream",
+];
+
+// compressible exceptions to the skipped prefixes
+const COMPRESSIBLE_CONTENT_TYPES: &[&str] = &["image/svg+xml"];
+
+/// A content coding the framework can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// `br`
+    Brotli,
+    /// `gzip`
+    Gzip,
+    /// `deflate` (zlib)
+    Deflate,
+}
+
+impl Encoding {
+    /// Token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    // compress `input` at `level`
+    fn encode(&self, input: &[u8], level: CompressionLevel) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() / 2);
+        match self {
+            Encoding::Brotli => {
+                let quality = match level {
+                    CompressionLevel::Fastest => 1,
+                    CompressionLevel::Default => 4,
+                    CompressionLevel::Best => 11,
+                };
+                let mut writer = brotli::CompressorWriter::new(&mut out, 4096, quality, 22);
+                writer.write_all(input)?;
+                writer.flush()?;
+            }
+            Encoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(&mut out, level.flate2());
+                encoder.write_all(input)?;
+                encoder.finish()?;
+            }
+            Encoding::Deflate => {
+                let mut encoder = flate2::write::ZlibEncoder::new(&mut out, level.flate2());
+                encoder.write_all(input)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Trade-off between CPU time and compression ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    /// Cheapest setting of each encoding
+    Fastest,
+    /// Balanced setting (gzip 6, brotli 4)
+    #[default]
+    Default,
+    /// Smallest output, most CPU
+    Best,
+}
+
+impl CompressionLevel {
+    // the matching flate2 level
+    fn flate2(self) -> flate2::Compression {
+        match self {
+            CompressionLevel::Fastest => flate2::Compression::fast(),
+            CompressionLevel::Default => flate2::Compression::default(),
+            CompressionLevel::Best => flate2::Compression::best(),
+        }
+    }
+}
+
+/// Response compression settings
+///
+/// The encoding is the one with the highest `q` value in the request's
+/// `Accept-Encoding`; ties go to the first in [`encodings`](Self::encodings).
+/// Only bodies of known size between `min_size` and `max_size` are
+/// compressed, so streaming responses pass through untouched.
+///
+/// Clones share their statistics and CPU budget.
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new()
+///     .route("/users", get(list_users))
+///     .compression(
+///         Compression::new()
+///             .min_size(2048)
+///             .level(CompressionLevel::Fastest)
+///             .cpu_budget(Duration::from_millis(200))
+///             .metrics(metrics.clone()),
+///     );
+/// ```
+#[derive(Clone)]
+pub struct Compression {
+    encodings: Vec<Encoding>,
+    level: CompressionLevel,
+    min_size: usize,
+    max_size: usize,
+    skipped: Vec<String>,
+    cpu_budget: Option<Duration>,
+    metrics: Option<Metrics>,
+    state: Arc<SharedState>,
+}
+
+struct SharedState {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    budget: Mutex<BudgetWindow>,
+}
+
+// CPU time spent compressing in the current one-second window
+struct BudgetWindow {
+    started: Instant,
+    spent: Duration,
+}
+
+/// Why a response was or was not compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Compressed,
+    TooSmall,
+    TooLarge,
+    ContentType,
+    OverBudget,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Compressed => "compressed",
+            Outcome::TooSmall => "too_small",
+            Outcome::TooLarge => "too_large",
+            Outcome::ContentType => "content_type",
+            Outcome::OverBudget => "over_budget",
+        }
+    }
+}
+
+impl Compression {
+    /// Compress with brotli, gzip or deflate using the default thresholds
+    pub fn new() -> Self {
+        Self {
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+            level: CompressionLevel::default(),
+            min_size: DEFAULT_MIN_SIZE,
+            max_size: DEFAULT_MAX_SIZE,
+            skipped: DEFAULT_SKIPPED_CONTENT_TYPES
+                .iter()
+                .map(|prefix| prefix.to_string())
+                .collect(),
+            cpu_budget: None,
+            metrics: None,
+            state: Arc::new(SharedState {
+                bytes_in: AtomicU64::new(0),
+                bytes_out: AtomicU64::new(0),
+                budget: Mutex::new(BudgetWindow {
+                    started: Instant::now(),
+                    spent: Duration::ZERO,
+                }),
+            }),
+        }
+    }
+
+    /// Encodings offered, in server preference order for equal `q` values
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    /// Trade CPU time for compression ratio
+    pub fn level(mut self, level: CompressionLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Leave bodies smaller than `bytes` uncompressed
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Leave bodies larger than `bytes` uncompressed
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Also skip content types starting with `prefix`
+    pub fn skip_content_type(mut self, prefix: &str) -> Self {
+        self.skipped.push(prefix.to_ascii_lowercase());
+        self
+    }
+
+    /// Spend at most `budget` of CPU time per second compressing
+    ///
+    /// Once the budget is used up, responses are sent uncompressed until the
+    /// next second starts.
+    pub fn cpu_budget(mut self, budget: Duration) -> Self {
+        self.cpu_budget = Some(budget);
+        self
+    }
+
+    /// Count outcomes in `response_compression_total{outcome}` and savings in
+    /// `response_compression_bytes_saved_total{encoding}`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Total size of the bodies that were compressed, before compression
+    pub fn bytes_in(&self) -> u64 {
+        self.state.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Total size of the bodies that were compressed, after compression
+    pub fn bytes_out(&self) -> u64 {
+        self.state.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Bytes not sent thanks to compression
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_in().saturating_sub(self.bytes_out())
+    }
+
+    /// Pick the encoding for an `Accept-Encoding` header value
+    ///
+    /// Returns `None` when the client accepts none of the offered encodings.
+    pub fn negotiate(&self, accept_encoding: &str) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+        for &encoding in &self.encodings {
+            let q = quality(accept_encoding, encoding.as_str());
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    // whether the content type is compressed already or not worth compressing
+    fn skips_content_type(&self, headers: &HeaderMap) -> bool {
+        let Some(content_type) = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let content_type = content_type.to_ascii_lowercase();
+        if COMPRESSIBLE_CONTENT_TYPES
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix))
+        {
+            return false;
+        }
+        self.skipped
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix.as_str()))
+    }
+
+    // whether the CPU budget allows another compression right now
+    fn within_budget(&self) -> bool {
+        let Some(budget) = self.cpu_budget else {
+            return true;
+        };
+        let mut window = self.state.budget.lock().unwrap_or_else(|e| e.into_inner());
+        if window.started.elapsed() >= Duration::from_secs(1) {
+            window.started = Instant::now();
+            window.spent = Duration::ZERO;
+        }
+        window.spent < budget
+    }
+
+    // charge compression time against the budget
+    fn spend(&self, elapsed: Duration) {
+        if self.cpu_budget.is_some() {
+            let mut window = self.state.budget.lock().unwrap_or_else(|e| e.into_inner());
+            window.spent += elapsed;
+        }
+    }
+
+    // count an outcome, and the bytes saved for compressed responses
+    fn record(&self, outcome: Outcome, encoding: Option<Encoding>, before: usize, after: usize) {
+        if outcome == Outcome::Compressed {
+            self.state
+                .bytes_in
+                .fetch_add(before as u64, Ordering::Relaxed);
+            self.state
+                .bytes_out
+                .fetch_add(after as u64, Ordering::Relaxed);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(
+                "response_compression_total",
+                &[("outcome", outcome.as_str())],
+            );
+            if let Some(encoding) = encoding {
+                metrics.add(
+                    "response_compression_bytes_saved_total",
+                    &[("encoding", encoding.as_str())],
+                    before.saturating_sub(after) as u64,
+                );
+            }
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// q value of `coding` in an Accept-Encoding header, falling back to `*`
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse::<f32>().ok())
+                    .flatten()
+            })
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+// remove encoding suffixes added by `compress` from a tag list
+fn strip_encoding_suffixes(value: &str) -> Option<String> {
+    let mut changed = false;
+    let tags: Vec<String> = value
+        .split(',')
+        .map(|tag| {
+            let tag = tag.trim();
+            let stripped = split_encoding_suffix(tag).map(|(body, _)| body);
+            changed |= stripped.is_some();
+            stripped.unwrap_or_else(|| tag.to_string())
+        })
+        .collect();
+    changed.then(|| tags.join(", "))
+}
+
+/// Split a strong tag such as `"v1-gzip"` into `"v1"` and its encoding
+fn split_encoding_suffix(tag: &str) -> Option<(String, Encoding)> {
+    [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate]
+        .into_iter()
+        .find_map(|encoding| {
+            let body = tag.strip_suffix(&format!("-{}\"", encoding.as_str()))?;
+            (body.starts_with('"') && body.len() > 1).then(|| (format!("{}\"", body), encoding))
+        })
+}
+
+/// Middleware compressing response bodies according to `compression`
+pub(crate) async fn compress(
+    State(compression): State<Compression>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // a 304 confirms the client's copy, so it gets back the tag it sent
+    let suffixed: Vec<(String, Encoding)> = req
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|tag| split_encoding_suffix(tag.trim()))
+        .collect();
+    for name in [header::IF_MATCH, header::IF_NONE_MATCH] {
+        let stripped: Vec<HeaderValue> = req
+            .headers()
+            .get_all(&name)
+            .iter()
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(strip_encoding_suffixes)
+                    .and_then(|tags| HeaderValue::from_str(&tags).ok())
+                    .unwrap_or_else(|| value.clone())
+            })
+            .collect();
+        if !stripped.is_empty() {
+            req.headers_mut().remove(&name);
+            for value in stripped {
+                req.headers_mut().append(&name, value);
+            }
+        }
+    }
+    let encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| compression.negotiate(value));
+    let mut response = next.run(req).await;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        let tagged = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let tag = ETag::parse(value).filter(|tag| !tag.is_weak())?;
+                suffixed
+                    .iter()
+                    .find(|(body, _)| ETag::parse(body).is_some_and(|sent| sent.strong_eq(&tag)))
+                    .map(|(_, encoding)| {
+                        ETag::strong(format!("{}-{}", tag.tag(), encoding.as_str()))
+                    })
+            });
+        if let Some(tagged) = tagged {
+            response
+                .headers_mut()
+                .insert(header::ETAG, tagged.to_header_value());
+        }
+        return response;
+    }
+    let Some(encoding) = encoding else {
+        return response;
+    };
+
+    let headers = response.headers();
+    let status = response.status();
+    let no_transform = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.to_ascii_lowercase().contains("no-transform"));
+    if headers.contains_key(header::CONTENT_ENCODING)
+        || headers.contains_key(header::CONTENT_RANGE)
+        || no_transform
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+    {
+        return response;
+    }
+    // streaming bodies have no exact size and are never buffered
+    let Some(size) = response.body().size_hint().exact() else {
+        return response;
+    };
+    let size = size as usize;
+    let skip = if size < compression.min_size {
+        Some(Outcome::TooSmall)
+    } else if size > compression.max_size {
+        Some(Outcome::TooLarge)
+    } else if compression.skips_content_type(headers) {
+        Some(Outcome::ContentType)
+    } else if !compression.within_budget() {
+        Some(Outcome::OverBudget)
+    } else {
+        None
+    };
+    if let Some(outcome) = skip {
+        compression.record(outcome, None, size, size);
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read response body for compression: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    // compressing is CPU-bound, so keep it off the runtime threads
+    let level = compression.level;
+    let input = bytes.clone();
+    let encoded = tokio::task::spawn_blocking(move || {
+        let started = Instant::now();
+        let encoded = encoding.encode(&input, level);
+        (encoded, started.elapsed())
+    })
+    .await;
+    let encoded = match encoded {
+        Ok((encoded, elapsed)) => {
+            compression.spend(elapsed);
+            encoded
+        }
+        Err(e) => Err(std::io::Error::other(e)),
+    };
+    let encoded = match encoded {
+        // compressing can grow tiny or random bodies; send those as they are
+        Ok(encoded) if encoded.len() < bytes.len() => encoded,
+        Ok(_) => {
+            compression.record(Outcome::TooSmall, None, bytes.len(), bytes.len());
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+        Err(e) => {
+            tracing::error!("Failed to compress response body: {}", e);
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+    };
+    compression.record(
+        Outcome::Compressed,
+        Some(encoding),
+        bytes.len(),
+        encoded.len(),
+    );
+
+    let headers = &mut parts.headers;
+    headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(encoded.len()));
+    headers.remove(header::ACCEPT_RANGES);
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    // the compressed bytes differ, so a strong tag names the encoding
+    if let Some(tag) = headers
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .and_then(ETag::parse)
+    {
+        if !tag.is_weak() {
+            let tagged = ETag::strong(format!("{}-{}", tag.tag(), encoding.as_str()));
+            headers.insert(header::ETAG, tagged.to_header_value());
+        }
+    }
+    Response::from_parts(parts, Body::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use axum::{response::IntoResponse, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::conditional::{IfMatch, IfNoneMatch};
+
+    fn text(len: usize) -> String {
+        "hello compression ".repeat(len / 18 + 1)[..len].to_string()
+    }
+
+    fn router(compression: Compression) -> Router {
+        Router::new()
+            .route("/small", get(|| async { "tiny" }))
+            .route("/large", get(|| async { text(8192) }))
+            .route(
+                "/png",
+                get(|| async { ([(header::CONTENT_TYPE, "image/png")], text(8192)) }),
+            )
+            .route(
+                "/tagged",
+                get(|| async { ([(header::ETAG, "\"v1\"")], text(8192)) }),
+            )
+            .layer(axum::middleware::from_fn_with_state(compression, compress))
+    }
+
+    async fn call(router: &Router, path: &str, accept: &str) -> Response {
+        let request = Request::get(path)
+            .header(header::ACCEPT_ENCODING, accept)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn body(response: Response) -> Vec<u8> {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let compression = Compression::new();
+        assert_eq!(compression.negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(
+            compression.negotiate("gzip;q=1.0, br;q=0.5"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(compression.negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(compression.negotiate("identity"), None);
+        assert_eq!(compression.negotiate("*;q=0"), None);
+        let gzip_first = Compression::new().encodings(&[Encoding::Gzip, Encoding::Brotli]);
+        assert_eq!(gzip_first.negotiate("br, gzip"), Some(Encoding::Gzip));
+    }
+
+    #[tokio::test]
+    async fn test_compresses_by_client_priority() {
+        let compression = Compression::new();
+        let router = router(compression.clone());
+
+        let response = call(&router, "/large", "br;q=0.8, gzip").await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        let compressed = body(response).await;
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text(8192));
+        assert_eq!(compression.bytes_in(), 8192);
+        assert_eq!(compression.bytes_saved(), 8192 - compressed.len() as u64);
+
+        let response = call(&router, "/large", "br").await;
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "br");
+        let compressed = body(response).await;
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text(8192));
+    }
+
+    #[tokio::test]
+    async fn test_skips_small_and_compressed_bodies() {
+        let metrics = Metrics::new();
+        let router = router(Compression::new().metrics(metrics.clone()));
+
+        for path in ["/small", "/png"] {
+            let response = call(&router, path, "gzip").await;
+            assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        }
+        let response = call(&router, "/large", "identity").await;
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(body(response).await.len(), 8192);
+
+        assert_eq!(
+            metrics.counter("response_compression_total", &[("outcome", "too_small")]),
+            1
+        );
+        assert_eq!(
+            metrics.counter("response_compression_total", &[("outcome", "content_type")]),
+            1
+        );
+        call(&router, "/large", "gzip").await;
+        assert!(
+            metrics.counter(
+                "response_compression_bytes_saved_total",
+                &[("encoding", "gzip")]
+            ) > 0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suffixes_strong_etag() {
+        let router = router(Compression::new());
+        let response = call(&router, "/tagged", "gzip").await;
+        assert_eq!(response.headers()[header::ETAG], "\"v1-gzip\"");
+        let response = call(&router, "/tagged", "br").await;
+        assert_eq!(response.headers()[header::ETAG], "\"v1-br\"");
+
+        // preconditions reach the handler with the tag it issued
+        let router = Router::new()
+            .route(
+                "/guarded",
+                get(|condition: IfMatch| async move {
+                    match condition.check(&ETag::strong("v1")) {
+                        Ok(()) => text(8192).into_response(),
+                        Err(e) => e.into_response(),
+                    }
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Compression::new(),
+                compress,
+            ));
+        let request = Request::get("/guarded")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .header(header::IF_MATCH, "\"v0\", \"v1-gzip\"")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // weak tags are never suffixed
+        assert_eq!(
+            strip_encoding_suffixes("W/\"a-br\", \"b-br\""),
+            Some("W/\"a-br\", \"b\"".to_string())
+        );
+        assert_eq!(strip_encoding_suffixes("\"-gzip\""), None);
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_keeps_suffixed_etag() {
+        let router = Router::new()
+            .route(
+                "/cached",
+                get(|condition: IfNoneMatch| async move {
+                    let etag = ETag::strong("v1");
+                    let status = if condition.matches(&etag) {
+                        StatusCode::NOT_MODIFIED
+                    } else {
+                        StatusCode::OK
+                    };
+                    (status, [(header::ETAG, etag.to_header_value())])
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Compression::new(),
+                compress,
+            ));
+        let request = Request::get("/cached")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .header(header::IF_NONE_MATCH, "\"v1-gzip\"")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], "\"v1-gzip\"");
+    }
+
+    #[tokio::test]
+    async fn test_cpu_budget() {
+        let compression = Compression::new().cpu_budget(Duration::from_nanos(1));
+        let router = router(compression.clone());
+        let first = call(&router, "/large", "gzip").await;
+        assert!(first.headers().contains_key(header::CONTENT_ENCODING));
+        let second = call(&router, "/large", "gzip").await;
+        assert!(!second.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(compression.bytes_in(), 8192);
+    }
+}
@@ -0,0 +1,486 @@
+//! Conditional requests for RustAPI framework
+//!
+//! Entity tags, the `If-None-Match` handling that lets clients skip
+//! re-downloading unchanged resources, and `If-Match` preconditions for
+//! optimistic concurrency on updates.
+
+use std::fmt;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{error::ApiError, json::Json};
+
+/// An entity tag, as sent in `ETag`, `If-Match` and `If-None-Match`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// Strong tag: the representation is byte-for-byte identical
+    ///
+    /// A tag with characters an entity tag cannot carry (quotes, spaces,
+    /// control or non-ASCII characters) is replaced by a hash of it.
+    pub fn strong(tag: impl Into<String>) -> Self {
+        Self::new(tag.into(), false)
+    }
+
+    /// Weak tag: the representation is semantically equivalent
+    ///
+    /// Invalid tags are hashed, as with `strong`.
+    pub fn weak(tag: impl Into<String>) -> Self {
+        Self::new(tag.into(), true)
+    }
+
+    // keep the tag valid in a header, hashing it if it is not
+    fn new(tag: String, weak: bool) -> Self {
+        let tag = if tag.bytes().all(is_etagc) {
+            tag
+        } else {
+            format!("{:016x}", fnv1a(tag.as_bytes()))
+        };
+        Self { tag, weak }
+    }
+
+    /// Strong tag derived from a hash of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self::strong(format!("{:016x}", fnv1a(bytes)))
+    }
+
+    /// Strong tag for a version number or revision id
+    pub fn from_version(version: impl fmt::Display) -> Self {
+        Self::strong(version.to_string())
+    }
+
+    /// The opaque tag, without quotes or weak prefix
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Check whether this is a weak tag
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Strong comparison (RFC 9110): both tags strong and equal
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Weak comparison (RFC 9110): tags equal regardless of weakness
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Parse a single tag, e.g. `"abc"` or `W/"abc"`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        if !tag.bytes().all(is_etagc) {
+            return None;
+        }
+        Some(Self {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+
+    /// Header value for the `ETag` response header
+    pub fn to_header_value(&self) -> HeaderValue {
+        // the constructors only keep valid tags; a hash is valid regardless
+        HeaderValue::from_str(&self.to_string()).unwrap_or_else(|_| {
+            let hashed = format!("{:016x}", fnv1a(self.tag.as_bytes()));
+            Self::new(hashed, self.weak).to_header_value()
+        })
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+// characters allowed inside an entity tag (RFC 9110 `etagc`, ASCII only)
+fn is_etagc(byte: u8) -> bool {
+    byte == 0x21 || (0x23..=0x7e).contains(&byte)
+}
+
+// 64-bit FNV-1a: stable across processes and releases, unlike std's hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Precondition from an `If-Match` or `If-None-Match` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagCondition {
+    /// `*`: any current representation
+    Any,
+    /// A list of entity tags
+    Tags(Vec<ETag>),
+}
+
+impl TagCondition {
+    /// Parse every occurrence of a header, `None` if it is absent
+    pub fn from_headers(headers: &HeaderMap, name: header::HeaderName) -> Option<Self> {
+        let mut tags = Vec::new();
+        for value in headers.get_all(name) {
+            let value = value.to_str().ok()?;
+            if value.trim() == "*" {
+                return Some(TagCondition::Any);
+            }
+            tags.extend(split_tags(value).filter_map(ETag::parse));
+        }
+        (!tags.is_empty()).then_some(TagCondition::Tags(tags))
+    }
+}
+
+// split a comma-separated tag list, keeping commas inside quotes
+fn split_tags(value: &str) -> impl Iterator<Item = &str> {
+    let mut in_quotes = false;
+    value
+        .split(move |c| {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+            c == ',' && !in_quotes
+        })
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+}
+
+/// Extractor for the `If-None-Match` request header
+///
+/// Holds `None` when the header is absent or unparseable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IfNoneMatch(pub Option<TagCondition>);
+
+impl IfNoneMatch {
+    /// Check whether a resource with `etag` is unchanged for the client
+    ///
+    /// Uses weak comparison, as required for `If-None-Match`.
+    pub fn matches(&self, etag: &ETag) -> bool {
+        match &self.0 {
+            Some(TagCondition::Any) => true,
+            Some(TagCondition::Tags(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            None => false,
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(TagCondition::from_headers(
+            &parts.headers,
+            header::IF_NONE_MATCH,
+        )))
+    }
+}
+
+/// Extractor for the `If-Match` request header
+///
+/// Guards updates against lost writes: the client sends back the ETag it
+/// last read, and the update is refused with 412 Precondition Failed when
+/// the resource changed in the meantime.
+///
+/// # Example
+///
+/// ```ignore
+/// #[put("/documents/{id}")]
+/// async fn update(Path(id): Path<u64>, if_match: IfMatch, Json(doc): Json<Doc>) -> Result<ETagged<Doc>, ApiError> {
+///     let current = store.get(id)?;
+///     if_match.check_required(&ETag::from_version(current.version))?;
+///     Ok(ETagged::new(store.save(id, doc)?))
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IfMatch(pub Option<TagCondition>);
+
+impl IfMatch {
+    /// Check whether the current representation satisfies the precondition
+    ///
+    /// Uses strong comparison, as required for `If-Match`. An absent header
+    /// always matches.
+    pub fn matches(&self, current: &ETag) -> bool {
+        match &self.0 {
+            Some(TagCondition::Any) | None => true,
+            Some(TagCondition::Tags(tags)) => tags.iter().any(|tag| tag.strong_eq(current)),
+        }
+    }
+
+    /// Fail with 412 Precondition Failed unless the precondition holds
+    pub fn check(&self, current: &ETag) -> Result<(), ApiError> {
+        if self.matches(current) {
+            Ok(())
+        } else {
+            Err(ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                "Resource was modified by another request",
+            ))
+        }
+    }
+
+    /// Like `check`, but fail with 428 Precondition Required when the client
+    /// sent no `If-Match` header
+    pub fn check_required(&self, current: &ETag) -> Result<(), ApiError> {
+        if self.0.is_none() {
+            return Err(ApiError::new(
+                StatusCode::PRECONDITION_REQUIRED,
+                "This request requires an If-Match header",
+            ));
+        }
+        self.check(current)
+    }
+
+    /// Fail with 412 when the resource does not exist but the client
+    /// required some current representation (`If-Match: *` or any tag)
+    pub fn check_missing(&self) -> Result<(), ApiError> {
+        match self.0 {
+            None => Ok(()),
+            Some(_) => Err(ApiError::new(
+                StatusCode::PRECONDITION_FAILED,
+                "Resource does not exist",
+            )),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(IfMatch(TagCondition::from_headers(
+            &parts.headers,
+            header::IF_MATCH,
+        )))
+    }
+}
+
+/// JSON response carrying an `ETag` derived from the serialized body
+///
+/// Answers 304 Not Modified when the client's `If-None-Match` matches.
+/// Other response headers such as `Cache-Control` can be added by wrapping
+/// it in a tuple, as with any response.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users/{id}")]
+/// async fn get_user(Path(id): Path<String>, inm: IfNoneMatch) -> ETagged<User> {
+///     ETagged::new(service.find(&id)).if_none_match(inm)
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ETagged<T> {
+    value: T,
+    weak: bool,
+    condition: IfNoneMatch,
+}
+
+impl<T: Serialize> ETagged<T> {
+    /// Wrap a value with a strong ETag
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            weak: false,
+            condition: IfNoneMatch::default(),
+        }
+    }
+
+    /// Use a weak ETag, e.g. when serialization is not byte-stable
+    pub fn weak(mut self) -> Self {
+        self.weak = true;
+        self
+    }
+
+    /// Answer 304 when the request's `If-None-Match` matches
+    pub fn if_none_match(mut self, condition: IfNoneMatch) -> Self {
+        self.condition = condition;
+        self
+    }
+}
+
+impl<T: Serialize> IntoResponse for ETagged<T> {
+    fn into_response(self) -> Response {
+        let body = match serde_json::to_vec(&self.value) {
+            Ok(body) => body,
+            Err(e) => {
+                return ApiError::internal(format!("Failed to serialize response: {}", e))
+                    .into_response()
+            }
+        };
+        let mut etag = ETag::from_bytes(&body);
+        etag.weak = self.weak;
+
+        let etag_header = (header::ETAG, etag.to_header_value());
+        if self.condition.matches(&etag) {
+            return (StatusCode::NOT_MODIFIED, [etag_header]).into_response();
+        }
+        (
+            [
+                etag_header,
+                (
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                ),
+            ],
+            body,
+        )
+            .into_response()
+    }
+}
+
+// ETagged is a drop-in replacement for Json in handlers
+impl<T: Serialize> From<Json<T>> for ETagged<T> {
+    fn from(Json(value): Json<T>) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: Serialize> From<axum::Json<T>> for ETagged<T> {
+    fn from(axum::Json(value): axum::Json<T>) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_etag_parse_and_display() {
+        let strong = ETag::parse("\"abc\"").unwrap();
+        let weak = ETag::parse("W/\"abc\"").unwrap();
+        assert!(!strong.is_weak());
+        assert!(weak.is_weak());
+        assert_eq!(weak.to_string(), "W/\"abc\"");
+        assert!(strong.weak_eq(&weak));
+        assert!(!strong.strong_eq(&weak));
+        assert!(ETag::parse("abc").is_none());
+    }
+
+    #[test]
+    fn test_etag_from_bytes_is_stable() {
+        assert_eq!(ETag::from_bytes(b"hello"), ETag::from_bytes(b"hello"));
+        assert_ne!(ETag::from_bytes(b"hello"), ETag::from_bytes(b"world"));
+        assert_eq!(ETag::from_bytes(b"").tag(), "cbf29ce484222325");
+    }
+
+    #[test]
+    fn test_invalid_tags_are_hashed() {
+        for tag in ["a\"b", "caf\u{e9}", "line\nbreak", "two words"] {
+            let etag = ETag::weak(tag);
+            assert_eq!(etag.tag(), format!("{:016x}", fnv1a(tag.as_bytes())));
+            assert!(etag.is_weak());
+            assert_eq!(
+                ETag::parse(etag.to_header_value().to_str().unwrap()),
+                Some(etag)
+            );
+        }
+        assert_eq!(ETag::strong("v1-gzip").tag(), "v1-gzip");
+        assert!(ETag::parse("\"two words\"").is_none());
+    }
+
+    #[test]
+    fn test_tag_condition_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"a\", W/\"b,c\""),
+        );
+        headers.append(header::IF_NONE_MATCH, HeaderValue::from_static("\"d\""));
+        let Some(TagCondition::Tags(tags)) =
+            TagCondition::from_headers(&headers, header::IF_NONE_MATCH)
+        else {
+            panic!("expected tags");
+        };
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[1], ETag::weak("b,c"));
+
+        headers.insert(header::IF_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(
+            TagCondition::from_headers(&headers, header::IF_MATCH),
+            Some(TagCondition::Any)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_etagged_not_modified() {
+        let response = ETagged::new(json!({ "id": 1 })).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+
+        let request = Request::builder()
+            .header(header::IF_NONE_MATCH, etag)
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        let inm = IfNoneMatch::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        let response = ETagged::new(json!({ "id": 1 }))
+            .if_none_match(inm.clone())
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = ETagged::new(json!({ "id": 2 }))
+            .if_none_match(inm)
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_if_match() {
+        let current = ETag::from_version(7);
+        let absent = IfMatch(None);
+        assert!(absent.check(&current).is_ok());
+        assert_eq!(
+            absent.check_required(&current).unwrap_err().status(),
+            StatusCode::PRECONDITION_REQUIRED
+        );
+
+        let stale = IfMatch(Some(TagCondition::Tags(vec![ETag::strong("6")])));
+        assert_eq!(
+            stale.check(&current).unwrap_err().status(),
+            StatusCode::PRECONDITION_FAILED
+        );
+        let fresh = IfMatch(Some(TagCondition::Tags(vec![ETag::strong("7")])));
+        assert!(fresh.check_required(&current).is_ok());
+
+        // weak tags never satisfy If-Match
+        let weak = IfMatch(Some(TagCondition::Tags(vec![ETag::weak("7")])));
+        assert!(weak.check(&current).is_err());
+
+        let any = IfMatch(Some(TagCondition::Any));
+        assert!(any.check(&current).is_ok());
+        assert!(any.check_missing().is_err());
+        assert!(absent.check_missing().is_ok());
+    }
+
+    #[test]
+    fn test_etagged_weak() {
+        let response = ETagged::new(json!([1, 2])).weak().into_response();
+        let etag = response.headers()[header::ETAG].to_str().unwrap();
+        assert!(etag.starts_with("W/\""));
+    }
+}
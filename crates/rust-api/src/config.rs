@@ -0,0 +1,713 @@
+//! Configuration loading for RustAPI framework
+//!
+//! `ConfigLoader` merges defaults, TOML or JSON files and environment
+//! variables, later sources overriding earlier ones key by key, and
+//! deserializes the result into the application's settings type.
+//!
+//! String values of the form `enc:v1:...` are encrypted with AES-256-GCM and
+//! decrypted while loading, so credentials for shared environments can be
+//! committed without being readable. Encrypting and decrypting needs the
+//! `config-encryption` feature and a `ConfigKey`, by default read from
+//! `APP_CONFIG_KEY`.
+//!
+//! Precedence, highest first: process environment, `.env.local`, `.env`,
+//! config files (later files first), defaults. Dotenv files only fill in
+//! variables the environment does not already set; `#[rust_api::main]` loads
+//! them before the runtime starts. Each source used is logged at startup with
+//! the keys it set, never their values.
+
+use std::path::{Path, PathBuf};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    boot,
+    error::{Error, Result},
+};
+
+/// Prefix of encrypted config values
+pub const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+/// Environment variable holding the base64 decryption key
+pub const CONFIG_KEY_VAR: &str = "APP_CONFIG_KEY";
+
+/// Dotenv files loaded by `load_dotenv`, highest precedence first
+pub const DOTENV_FILES: [&str; 2] = [".env.local", ".env"];
+
+// a source of settings, lowest precedence first
+enum Layer {
+    Defaults(Value),
+    File { path: PathBuf, required: bool },
+}
+
+/// Layered configuration loader
+///
+/// Sources apply in the order they are added, then environment variables
+/// with the configured prefix: `APP_DATABASE__URL` sets `database.url` for
+/// prefix `APP` (`__` separates nested keys). Environment values that parse
+/// as numbers or booleans are used as such.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Settings {
+///     port: u16,
+///     database: DatabaseSettings,
+/// }
+///
+/// let settings: Settings = ConfigLoader::new()
+///     .defaults(&json!({ "port": 3000 }))
+///     .file("config/default.toml")
+///     .optional_file("config/local.toml")
+///     .dotenv()
+///     .env_prefix("APP")
+///     .load()?;
+/// ```
+#[derive(Default)]
+pub struct ConfigLoader {
+    layers: Vec<Layer>,
+    env_prefix: Option<String>,
+    dotenv: Option<PathBuf>,
+    #[cfg(feature = "config-encryption")]
+    key: Option<ConfigKey>,
+}
+
+impl ConfigLoader {
+    /// Create a loader with no sources
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `defaults` for settings no other source provides
+    ///
+    /// # Panics
+    ///
+    /// Panics if `defaults` cannot be represented as JSON.
+    pub fn defaults<T: Serialize>(mut self, defaults: &T) -> Self {
+        let value = serde_json::to_value(defaults).expect("config defaults must serialize to JSON");
+        self.layers.push(Layer::Defaults(value));
+        self
+    }
+
+    /// Read settings from a `.toml` or `.json` file that must exist
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File {
+            path: path.into(),
+            required: true,
+        });
+        self
+    }
+
+    /// Read settings from a `.toml` or `.json` file if it exists
+    pub fn optional_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.layers.push(Layer::File {
+            path: path.into(),
+            required: false,
+        });
+        self
+    }
+
+    /// Override settings from environment variables starting with
+    /// `{prefix}_`
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Read `.env.local` and `.env` from the working directory as
+    /// environment variables
+    ///
+    /// Their variables join the process environment for this loader only;
+    /// the process environment itself is left untouched.
+    pub fn dotenv(self) -> Self {
+        self.dotenv_in(".")
+    }
+
+    /// Read `.env.local` and `.env` from `dir` as environment variables
+    pub fn dotenv_in(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dotenv = Some(dir.into());
+        self
+    }
+
+    /// Decrypt `enc:v1:` values with `key` instead of the one in
+    /// `APP_CONFIG_KEY`
+    #[cfg(feature = "config-encryption")]
+    pub fn decryption_key(mut self, key: ConfigKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Load and merge all sources into a deserialized settings value
+    pub fn load<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.load_value()?)
+            .map_err(|e| Error::other(format!("Invalid configuration: {}", e)))
+    }
+
+    /// Load and merge all sources into a JSON value
+    ///
+    /// Timed as the `config` phase of the boot report.
+    pub fn load_value(&self) -> Result<Value> {
+        boot::time("config", || self.merge_sources())
+    }
+
+    // read every source and merge them in order
+    fn merge_sources(&self) -> Result<Value> {
+        self.merge_with_env(std::env::vars().collect())
+    }
+
+    // merge the sources, with `environment` as the process environment
+    fn merge_with_env(&self, mut environment: Vec<(String, String)>) -> Result<Value> {
+        let mut merged = Value::Object(Map::new());
+        for layer in &self.layers {
+            let (source, value) = match layer {
+                Layer::Defaults(value) => ("defaults".to_string(), value.clone()),
+                Layer::File { path, required } => {
+                    if !*required && !path.exists() {
+                        continue;
+                    }
+                    (path.display().to_string(), read_file(path)?)
+                }
+            };
+            log_source(&source, leaf_keys(&value));
+            merge(&mut merged, value);
+        }
+        if let Some(dir) = &self.dotenv {
+            for (path, vars) in read_dotenv(dir)? {
+                // the environment and earlier files win
+                let vars: Vec<_> = vars
+                    .into_iter()
+                    .filter(|(key, _)| environment.iter().all(|(set, _)| set != key))
+                    .collect();
+                log_source(
+                    &path.display().to_string(),
+                    vars.iter().map(|(key, _)| key.clone()).collect(),
+                );
+                environment.extend(vars);
+            }
+        }
+        if let Some(prefix) = &self.env_prefix {
+            let overrides = env_overrides(prefix, environment.into_iter());
+            log_source("environment", leaf_keys(&overrides));
+            merge(&mut merged, overrides);
+        }
+        self.decrypt(&mut merged)?;
+        Ok(merged)
+    }
+
+    // decrypt every encrypted string in place
+    #[cfg(feature = "config-encryption")]
+    fn decrypt(&self, value: &mut Value) -> Result<()> {
+        let mut key = self.key.clone();
+        visit_encrypted(value, "", &mut |path, encrypted| {
+            if key.is_none() {
+                key = Some(ConfigKey::from_env(CONFIG_KEY_VAR)?);
+            }
+            let key = key.as_ref().expect("key loaded above");
+            key.decrypt(encrypted)
+                .map_err(|e| Error::other(format!("Cannot decrypt config value {}: {}", path, e)))
+        })
+    }
+
+    // without the feature, encrypted values are an error
+    #[cfg(not(feature = "config-encryption"))]
+    fn decrypt(&self, value: &mut Value) -> Result<()> {
+        visit_encrypted(value, "", &mut |path, _| {
+            Err(Error::other(format!(
+                "Config value {} is encrypted; enable the `config-encryption` feature",
+                path
+            )))
+        })
+    }
+}
+
+// report a config source and the keys it set, without their values
+fn log_source(source: &str, keys: Vec<String>) {
+    if keys.is_empty() {
+        return;
+    }
+    tracing::info!(source, keys = %keys.join(", "), "Loaded configuration");
+}
+
+// dotted paths of the scalar settings in `value`
+fn leaf_keys(value: &Value) -> Vec<String> {
+    fn walk(value: &Value, path: String, keys: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map {
+                    let path = match path.as_str() {
+                        "" => key.clone(),
+                        _ => format!("{}.{}", path, key),
+                    };
+                    walk(child, path, keys);
+                }
+            }
+            _ => keys.push(path),
+        }
+    }
+    let mut keys = Vec::new();
+    walk(value, String::new(), &mut keys);
+    keys
+}
+
+/// Load `.env.local` and `.env` from the working directory into the process
+/// environment
+///
+/// Variables that are already set keep their value, so the environment
+/// overrides `.env.local`, which overrides `.env`. Returns the files that
+/// were found. Changing the environment races with other threads reading
+/// it, so call it before any start, as `#[rust_api::main]` does; within a
+/// running application use `ConfigLoader::dotenv` instead.
+pub fn load_dotenv() -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for (path, vars) in read_dotenv(Path::new("."))? {
+        for (key, value) in vars {
+            if std::env::var_os(&key).is_none() {
+                std::env::set_var(&key, value);
+            }
+        }
+        found.push(path);
+    }
+    Ok(found)
+}
+
+// variables of a dotenv file, in file order
+type DotenvVars = Vec<(String, String)>;
+
+// read the dotenv files in `dir`, highest precedence first, with their
+// variables
+fn read_dotenv(dir: &Path) -> Result<Vec<(PathBuf, DotenvVars)>> {
+    let mut loaded = Vec::new();
+    for name in DOTENV_FILES {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| Error::other(format!("Cannot read {}: {}", path.display(), e)))?;
+        let vars = parse_dotenv(&text)
+            .map_err(|e| Error::other(format!("Invalid {}: {}", path.display(), e)))?;
+        loaded.push((path, vars));
+    }
+    Ok(loaded)
+}
+
+// parse `KEY=value` lines; supports comments, `export` and quoted values
+fn parse_dotenv(text: &str) -> std::result::Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=value", index + 1))?;
+        let key = key.trim();
+        if key.is_empty() || !key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+            return Err(format!("line {}: invalid variable name", index + 1));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let inner = quoted
+                .strip_suffix('"')
+                .ok_or_else(|| format!("line {}: unterminated quote", index + 1))?;
+            unescape(inner)
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            quoted
+                .strip_suffix('\'')
+                .ok_or_else(|| format!("line {}: unterminated quote", index + 1))?
+                .to_string()
+        } else {
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+// resolve `\n`, `\"` and `\\` in a double-quoted dotenv value
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                out.push('\n');
+                chars.next();
+            }
+            ('\\', Some(escaped @ ('"' | '\\'))) => {
+                out.push(escaped);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// replace every `enc:v1:` string in `value` with the result of `decrypt`
+fn visit_encrypted(
+    value: &mut Value,
+    path: &str,
+    decrypt: &mut dyn FnMut(&str, &str) -> Result<String>,
+) -> Result<()> {
+    match value {
+        Value::String(s) if s.starts_with(ENCRYPTED_PREFIX) => {
+            *s = decrypt(path, s)?;
+        }
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                let path = match path {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", path, key),
+                };
+                visit_encrypted(child, &path, decrypt)?;
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                visit_encrypted(child, &format!("{}[{}]", path, index), decrypt)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// parse a `.toml` or `.json` settings file
+fn read_file(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::other(format!("Cannot read {}: {}", path.display(), e)))?;
+    let invalid =
+        |e: &dyn std::fmt::Display| Error::other(format!("Invalid {}: {}", path.display(), e));
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => {
+            let table: toml::Table = toml::from_str(&text).map_err(|e| invalid(&e))?;
+            serde_json::to_value(table).map_err(|e| invalid(&e))
+        }
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(&e)),
+        _ => Err(Error::other(format!(
+            "Unsupported config file {}: expected .toml or .json",
+            path.display()
+        ))),
+    }
+}
+
+// nested settings from `{prefix}_A__B=value` variables
+fn env_overrides(prefix: &str, vars: impl Iterator<Item = (String, String)>) -> Value {
+    let prefix = format!("{}_", prefix);
+    let mut overrides = Value::Object(Map::new());
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let value = match serde_json::from_str::<Value>(&raw) {
+            Ok(parsed @ (Value::Number(_) | Value::Bool(_))) => parsed,
+            _ => Value::String(raw),
+        };
+        let mut node = &mut overrides;
+        let keys: Vec<String> = path.split("__").map(str::to_ascii_lowercase).collect();
+        for key in &keys[..keys.len() - 1] {
+            node = node
+                .as_object_mut()
+                .expect("override nodes are objects")
+                .entry(key.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+        }
+        if let Some(map) = node.as_object_mut() {
+            map.insert(keys[keys.len() - 1].clone(), value);
+        }
+    }
+    overrides
+}
+
+// deep-merge `overlay` into `base`; objects merge key by key, anything else
+// replaces
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(feature = "config-encryption")]
+pub use self::encryption::ConfigKey;
+
+#[cfg(feature = "config-encryption")]
+mod encryption {
+    use std::fmt;
+
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng},
+        Aes256Gcm, Key, Nonce,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use super::ENCRYPTED_PREFIX;
+    use crate::error::{Error, Result};
+
+    // AES-GCM nonce length in bytes
+    const NONCE_LEN: usize = 12;
+
+    /// AES-256-GCM key for encrypted config values
+    ///
+    /// Keep it out of the repository: in an environment variable, a secret
+    /// store, or as a data key unwrapped from a KMS at startup and passed to
+    /// `ConfigKey::new`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// // once, to create a key and encrypt a value for the config file
+    /// let key = ConfigKey::generate();
+    /// println!("APP_CONFIG_KEY={}", key.to_base64());
+    /// println!("password = \"{}\"", key.encrypt("s3cret"));
+    /// ```
+    #[derive(Clone)]
+    pub struct ConfigKey {
+        key: Key<Aes256Gcm>,
+    }
+
+    impl ConfigKey {
+        /// Use the 32 bytes of `key`
+        pub fn new(key: [u8; 32]) -> Self {
+            Self { key: key.into() }
+        }
+
+        /// Create a random key
+        pub fn generate() -> Self {
+            Self {
+                key: Aes256Gcm::generate_key(OsRng),
+            }
+        }
+
+        /// Decode a base64 key
+        pub fn from_base64(encoded: &str) -> Result<Self> {
+            let bytes = STANDARD
+                .decode(encoded.trim())
+                .map_err(|e| Error::other(format!("Invalid config key: {}", e)))?;
+            let key: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| Error::other("Invalid config key: expected 32 bytes"))?;
+            Ok(Self::new(key))
+        }
+
+        /// Read a base64 key from the environment variable `var`
+        pub fn from_env(var: &str) -> Result<Self> {
+            let encoded = std::env::var(var).map_err(|_| {
+                Error::other(format!("{} is not set; cannot decrypt config values", var))
+            })?;
+            Self::from_base64(&encoded)
+        }
+
+        /// The key in base64, for storing it
+        pub fn to_base64(&self) -> String {
+            STANDARD.encode(self.key)
+        }
+
+        /// Encrypt `plaintext` into an `enc:v1:` config value
+        pub fn encrypt(&self, plaintext: &str) -> String {
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = Aes256Gcm::new(&self.key)
+                .encrypt(&nonce, plaintext.as_bytes())
+                .expect("AES-GCM encryption of an in-memory value cannot fail");
+            let mut sealed = nonce.to_vec();
+            sealed.extend_from_slice(&ciphertext);
+            format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(sealed))
+        }
+
+        /// Decrypt an `enc:v1:` config value
+        pub fn decrypt(&self, value: &str) -> Result<String> {
+            let encoded = value
+                .strip_prefix(ENCRYPTED_PREFIX)
+                .ok_or_else(|| Error::other("not an encrypted value"))?;
+            let sealed = STANDARD
+                .decode(encoded)
+                .map_err(|e| Error::other(format!("invalid base64: {}", e)))?;
+            if sealed.len() < NONCE_LEN {
+                return Err(Error::other("truncated value"));
+            }
+            let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+            let plaintext = Aes256Gcm::new(&self.key)
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| Error::other("wrong key or tampered value"))?;
+            String::from_utf8(plaintext).map_err(|_| Error::other("value is not UTF-8"))
+        }
+    }
+
+    impl fmt::Debug for ConfigKey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("ConfigKey(***)")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    // write `contents` to a fresh temporary file named `name`
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-api-config-{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Settings {
+        port: u16,
+        debug: bool,
+        database: Database,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Database {
+        url: String,
+        pool: u32,
+    }
+
+    #[test]
+    fn test_layers_merge_in_order() {
+        let file = temp_file(
+            "app.toml",
+            "debug = true\n[database]\nurl = \"postgres://file\"\n",
+        );
+        let local = temp_file("local.json", r#"{"database": {"pool": 8}}"#);
+        let settings: Settings = ConfigLoader::new()
+            .defaults(&json!({ "port": 3000, "debug": false, "database": { "pool": 4 } }))
+            .file(&file)
+            .optional_file(&local)
+            .optional_file("/nonexistent/config.toml")
+            .load()
+            .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                port: 3000,
+                debug: true,
+                database: Database {
+                    url: "postgres://file".to_string(),
+                    pool: 8,
+                },
+            }
+        );
+        assert!(ConfigLoader::new()
+            .file("/nonexistent/config.toml")
+            .load_value()
+            .is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let vars = [
+            ("APP_PORT", "8080"),
+            ("APP_DATABASE__URL", "postgres://env"),
+            ("APP_DEBUG", "true"),
+            ("APP_NAME", "0123"),
+            ("OTHER_PORT", "1"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let overrides = env_overrides("APP", vars.into_iter());
+        assert_eq!(
+            overrides,
+            json!({ "port": 8080, "debug": true, "name": "0123", "database": { "url": "postgres://env" } })
+        );
+    }
+
+    #[test]
+    fn test_parse_dotenv() {
+        let vars = parse_dotenv(
+            "# comment\n\nexport A=1\nB = two # note\nC=\"line\\nbreak \\\"q\\\"\"\nD='raw # kept'\n",
+        )
+        .unwrap();
+        let expected = [
+            ("A", "1"),
+            ("B", "two"),
+            ("C", "line\nbreak \"q\""),
+            ("D", "raw # kept"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        assert_eq!(vars, expected);
+        assert!(parse_dotenv("NOVALUE").unwrap_err().contains("line 1"));
+        assert!(parse_dotenv("A=\"open").is_err());
+    }
+
+    #[test]
+    fn test_dotenv_precedence() {
+        let dir = temp_file(".env", "DOTENV_TEST__PORT=1\nDOTENV_TEST__HOST=dotenv\n")
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        std::fs::write(dir.join(".env.local"), "DOTENV_TEST__HOST=local\n").unwrap();
+        let environment = vec![("DOTENV_TEST__PORT".to_string(), "2".to_string())];
+
+        let value = ConfigLoader::new()
+            .defaults(&json!({ "port": 0, "host": "default", "name": "app" }))
+            .dotenv_in(&dir)
+            .env_prefix("DOTENV_TEST_")
+            .merge_with_env(environment)
+            .unwrap();
+        assert_eq!(value, json!({ "port": 2, "host": "local", "name": "app" }));
+        // the process environment is left alone
+        assert!(std::env::var_os("DOTENV_TEST__HOST").is_none());
+    }
+
+    #[cfg(not(feature = "config-encryption"))]
+    #[test]
+    fn test_encrypted_values_need_the_feature() {
+        let error = ConfigLoader::new()
+            .defaults(&json!({ "database": { "password": "enc:v1:AAAA" } }))
+            .load_value()
+            .unwrap_err();
+        assert!(error.to_string().contains("database.password"));
+    }
+
+    #[cfg(feature = "config-encryption")]
+    #[test]
+    fn test_encrypted_values_are_decrypted() {
+        let key = ConfigKey::generate();
+        let encrypted = key.encrypt("s3cret");
+        assert!(encrypted.starts_with(ENCRYPTED_PREFIX));
+        assert_ne!(encrypted, key.encrypt("s3cret"));
+
+        let loader = || {
+            ConfigLoader::new()
+                .defaults(&json!({ "database": { "passwords": [encrypted.clone()] } }))
+        };
+        let value = loader().decryption_key(key.clone()).load_value().unwrap();
+        assert_eq!(value["database"]["passwords"][0], "s3cret");
+
+        let error = loader()
+            .decryption_key(ConfigKey::generate())
+            .load_value()
+            .unwrap_err();
+        assert!(error.to_string().contains("database.passwords[0]"));
+
+        let restored = ConfigKey::from_base64(&key.to_base64()).unwrap();
+        assert_eq!(restored.decrypt(&encrypted).unwrap(), "s3cret");
+        assert_eq!(format!("{:?}", restored), "ConfigKey(***)");
+    }
+}
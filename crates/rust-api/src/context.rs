@@ -0,0 +1,880 @@
+//! Request context for RustAPI framework
+//!
+//! Per-request information (request id, route, principal, locale, deadline)
+//! stored in a tokio task-local, so services deep in the call stack can log
+//! and trace without threading parameters through every call.
+//!
+//! The context is an immutable snapshot behind an `Arc`; reading its fields
+//! never takes a lock, and the values set after creation, such as the
+//! principal, are write-once cells.
+//!
+//! `RequestContext::local` keeps values for the rest of one request, such
+//! as the caches of `DataLoader`s, without any global state. Those live in a
+//! map behind a mutex, locked briefly on every call to look the value up.
+//!
+//! With `App::cancel_abandoned_requests`, the context also carries a
+//! `Cancellation` that fires when the request is abandoned: its deadline
+//! passed, or the client went away and the server dropped the request.
+//! Work that outlives the handler future, such as spawned tasks and
+//! `CpuPool` jobs, checks it to stop early.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{header, request::Parts, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::{sync::Notify, time::Instant};
+use tracing::Instrument;
+
+use crate::{
+    error::ApiError,
+    ids::{IdGenerator, UuidV7},
+    sampling::{self, Sampler},
+};
+
+/// Header carrying the request id, read from requests and echoed on responses
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static CURRENT: RequestContext;
+}
+
+/// Information about the request currently being handled
+///
+/// Set up for every request by `App::build()`. Cheap to clone (one reference
+/// count increment); clones share the same principal. Use
+/// [`with_current`](Self::with_current) to read it without cloning at all.
+///
+/// # Example
+///
+/// ```ignore
+/// impl UserService {
+///     pub fn delete(&self, id: &str) {
+///         if let Some(ctx) = RequestContext::current() {
+///             tracing::info!(request_id = ctx.request_id(), principal = ?ctx.principal(), "deleting user {}", id);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RequestContext {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    request_id: String,
+    method: Method,
+    route: Option<String>,
+    locale: Option<String>,
+    deadline: Option<Instant>,
+    principal: OnceLock<String>,
+    tenant: OnceLock<String>,
+    sampled: OnceLock<bool>,
+    cancellation: Cancellation,
+    locals: Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl RequestContext {
+    /// Create a context by hand, e.g. for background jobs or tests
+    pub fn new(request_id: impl Into<String>) -> Self {
+        Self::build(request_id.into(), Method::GET, None, None, None)
+    }
+
+    // assemble a context from its parts
+    pub(crate) fn build(
+        request_id: String,
+        method: Method,
+        route: Option<String>,
+        locale: Option<String>,
+        deadline: Option<Instant>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                request_id,
+                method,
+                route,
+                locale,
+                deadline,
+                principal: OnceLock::new(),
+                tenant: OnceLock::new(),
+                sampled: OnceLock::new(),
+                cancellation: Cancellation::new(),
+                locals: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Build the context for an incoming request
+    pub(crate) fn from_parts(parts: &Parts, settings: &ContextSettings) -> Self {
+        let request_id = parts
+            .headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 128)
+            .map_or_else(|| settings.ids.generate(), str::to_string);
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|matched| matched.as_str().to_string());
+        let locale = parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(preferred_locale);
+
+        Self::build(
+            request_id,
+            parts.method.clone(),
+            route,
+            locale,
+            settings.timeout.map(|t| Instant::now() + t),
+        )
+    }
+
+    /// The context of the request being handled by the current task
+    ///
+    /// Returns `None` outside a request, or in a task spawned without
+    /// `RequestContext::scope`.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    /// Call `f` with the current context, without cloning it
+    ///
+    /// Returns `None` when there is no current context.
+    pub fn with_current<R>(f: impl FnOnce(&RequestContext) -> R) -> Option<R> {
+        CURRENT.try_with(f).ok()
+    }
+
+    /// Run a future with this context as the current one
+    ///
+    /// Use this to carry the context into spawned tasks.
+    pub async fn scope<F: std::future::Future>(self, fut: F) -> F::Output {
+        CURRENT.scope(self, fut).await
+    }
+
+    /// Run a closure with this context as the current one
+    ///
+    /// Use this to carry the context into `spawn_blocking` work.
+    pub fn sync_scope<R>(self, f: impl FnOnce() -> R) -> R {
+        CURRENT.sync_scope(self, f)
+    }
+
+    /// Request id, taken from the `x-request-id` header or generated
+    pub fn request_id(&self) -> &str {
+        &self.inner.request_id
+    }
+
+    /// HTTP method of the request
+    pub fn method(&self) -> &Method {
+        &self.inner.method
+    }
+
+    /// Route template that matched, e.g. `/users/{id}`
+    pub fn route(&self) -> Option<&str> {
+        self.inner.route.as_deref()
+    }
+
+    /// Preferred locale from the `Accept-Language` header, by q value
+    pub fn locale(&self) -> Option<&str> {
+        self.inner.locale.as_deref()
+    }
+
+    /// Point in time after which the request is abandoned
+    ///
+    /// Measured on the tokio clock, so it follows paused time in tests.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// Time left before the deadline, zero once it has passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.inner
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Check whether the deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_some_and(|left| left.is_zero())
+    }
+
+    /// Signal fired when the request is abandoned
+    ///
+    /// Only fires with `App::cancel_abandoned_requests`; otherwise, and for
+    /// contexts created by hand, it fires when `Cancellation::cancel` is
+    /// called.
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.inner.cancellation
+    }
+
+    /// Check whether the request was abandoned
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancellation.is_cancelled()
+    }
+
+    /// Authenticated principal, once set by authentication middleware
+    pub fn principal(&self) -> Option<String> {
+        self.inner.principal.get().cloned()
+    }
+
+    /// Record the authenticated principal for the rest of the request
+    ///
+    /// The principal can only be set once per request; later calls are
+    /// ignored and logged at WARN.
+    pub fn set_principal(&self, principal: impl Into<String>) {
+        if let Err(rejected) = self.inner.principal.set(principal.into()) {
+            tracing::warn!(
+                request_id = %self.inner.request_id,
+                "Ignoring principal {:?}: already set for this request",
+                rejected
+            );
+        }
+    }
+
+    /// Tenant of the request, once resolved by the `TenantResolver`
+    pub fn tenant(&self) -> Option<String> {
+        self.inner.tenant.get().cloned()
+    }
+
+    // record the tenant; resolved once per request by the tenancy middleware
+    pub(crate) fn set_tenant(&self, tenant: String) {
+        let _ = self.inner.tenant.set(tenant);
+    }
+
+    /// Whether the request's trace is sampled
+    ///
+    /// Decided by the `Sampler` installed with `App::trace_requests`; true
+    /// when there is none.
+    pub fn is_sampled(&self) -> bool {
+        self.inner.sampled.get().copied().unwrap_or(true)
+    }
+
+    // the head sampling decision, if a sampler made one
+    pub(crate) fn sampling_decision(&self) -> Option<bool> {
+        self.inner.sampled.get().copied()
+    }
+
+    // record the head sampling decision, made once per request
+    pub(crate) fn set_sampled(&self, sampled: bool) {
+        let _ = self.inner.sampled.set(sampled);
+    }
+
+    /// The request's value of type `T`, created with `init` on first use
+    ///
+    /// One value per type is kept until the last clone of the context is
+    /// dropped, normally when the response is sent. Each call locks the
+    /// request's map of locals for the lookup; `init` runs without the lock,
+    /// so it may read other locals, and if two callers race the first value
+    /// stored wins.
+    pub fn local<T: Send + Sync + 'static>(&self, init: impl FnOnce() -> T) -> Arc<T> {
+        let key = TypeId::of::<T>();
+        let stored = self.lock_locals().get(&key).cloned();
+        let value = match stored {
+            Some(value) => value,
+            None => {
+                let fresh: Arc<dyn Any + Send + Sync> = Arc::new(init());
+                Arc::clone(self.lock_locals().entry(key).or_insert(fresh))
+            }
+        };
+        value
+            .downcast()
+            .expect("request local stored under its own type")
+    }
+
+    fn lock_locals(&self) -> MutexGuard<'_, HashMap<TypeId, Arc<dyn Any + Send + Sync>>> {
+        self.inner.locals.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestContext")
+            .field("request_id", &self.inner.request_id)
+            .field("method", &self.inner.method)
+            .field("route", &self.inner.route)
+            .field("locale", &self.inner.locale)
+            .field("deadline", &self.inner.deadline)
+            .field("principal", &self.inner.principal.get())
+            .field("tenant", &self.inner.tenant.get())
+            .field("sampled", &self.is_sampled())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RequestContext {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<RequestContext>()
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::internal(
+                    "Request context unavailable: router was not built with App::build()",
+                )
+            })
+    }
+}
+
+/// Cancellation signal of a request
+///
+/// Cheap to clone; clones share the signal, which fires at most once.
+///
+/// # Example
+///
+/// ```ignore
+/// let cancellation = ctx.cancellation().clone();
+/// pool.run(move || {
+///     for page in pages {
+///         if cancellation.is_cancelled() {
+///             return Err(ApiError::internal("render abandoned"));
+///         }
+///         render(page);
+///     }
+///     Ok(())
+/// })
+/// .await??;
+/// ```
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    inner: Arc<CancellationInner>,
+}
+
+#[derive(Default)]
+struct CancellationInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl Cancellation {
+    /// Create a signal that has not fired
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fire the signal, waking everyone waiting in `cancelled`
+    pub fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            self.inner.notify.notify_waiters();
+        }
+    }
+
+    /// Check whether the signal fired
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait until the signal fires
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cancellation")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Run work on behalf of the current request, giving up once it is abandoned
+///
+/// Fails with 503 `request_cancelled` if the request's cancellation fires
+/// first. Outside a request the future simply runs to completion. Use it in
+/// tasks spawned with `RequestContext::scope`, whose work would otherwise
+/// continue after the client is gone.
+///
+/// # Example
+///
+/// ```ignore
+/// let ctx = RequestContext::current().unwrap();
+/// tokio::spawn(ctx.scope(async move {
+///     context::until_cancelled(index.rebuild(&tenant)).await
+/// }));
+/// ```
+pub async fn until_cancelled<F: std::future::Future>(fut: F) -> Result<F::Output, ApiError> {
+    match RequestContext::with_current(|ctx| ctx.cancellation().clone()) {
+        Some(cancellation) => tokio::select! {
+            output = fut => Ok(output),
+            _ = cancellation.cancelled() => Err(request_cancelled()),
+        },
+        None => Ok(fut.await),
+    }
+}
+
+/// Cap a downstream timeout to the current request's remaining deadline
+///
+/// Outside a request, or when no deadline is set, `timeout` is returned
+/// unchanged. Clients for downstream services (HTTP, databases) should pass
+/// their own timeouts through this so no work outlives the caller.
+///
+/// # Example
+///
+/// ```ignore
+/// let response = client
+///     .get(url)
+///     .timeout(context::cap_timeout(Duration::from_secs(10)))
+///     .send()
+///     .await?;
+/// ```
+pub fn cap_timeout(timeout: Duration) -> Duration {
+    RequestContext::with_current(RequestContext::remaining)
+        .flatten()
+        .map_or(timeout, |left| left.min(timeout))
+}
+
+/// Run a downstream call, giving up when the request deadline passes
+///
+/// Fails with 504 Gateway Timeout if the deadline is reached first. Without a
+/// deadline the future simply runs to completion.
+///
+/// # Example
+///
+/// ```ignore
+/// let user = context::within_deadline(repo.find_user(id)).await??;
+/// ```
+pub async fn within_deadline<F: std::future::Future>(fut: F) -> Result<F::Output, ApiError> {
+    match RequestContext::with_current(RequestContext::deadline).flatten() {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut)
+            .await
+            .map_err(|_| deadline_exceeded()),
+        None => Ok(fut.await),
+    }
+}
+
+// 504 error for downstream work cut short by the request deadline
+pub(crate) fn deadline_exceeded() -> ApiError {
+    ApiError::new(StatusCode::GATEWAY_TIMEOUT, "Request deadline exceeded")
+        .with_code("deadline_exceeded")
+}
+
+// 503 error for work given up because its request was abandoned
+pub(crate) fn request_cancelled() -> ApiError {
+    ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Request was abandoned")
+        .with_code("request_cancelled")
+}
+
+/// Settings of the request context middleware
+#[derive(Clone)]
+pub(crate) struct ContextSettings {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) ids: Arc<dyn IdGenerator>,
+    pub(crate) cancel_abandoned: bool,
+    pub(crate) sampler: Option<Arc<Sampler>>,
+}
+
+impl Default for ContextSettings {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            ids: Arc::new(UuidV7),
+            cancel_abandoned: false,
+            sampler: None,
+        }
+    }
+}
+
+// fires the cancellation of a request dropped before it completed
+struct CancelOnDrop(Option<Cancellation>);
+
+impl CancelOnDrop {
+    // the request completed
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(cancellation) = self.0.take() {
+            tracing::debug!("Request abandoned before completion, cancelling");
+            cancellation.cancel();
+        }
+    }
+}
+
+/// Middleware establishing the request context, running the request in its
+/// `request` span and enforcing the timeout
+pub(crate) async fn scope_request(
+    State(settings): State<ContextSettings>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let ctx = RequestContext::from_parts(&parts, &settings);
+    parts.extensions.insert(ctx.clone());
+    let started = Instant::now();
+    // opened within the context, which carries the sampling decision
+    let span = CURRENT.sync_scope(ctx.clone(), || {
+        sampling::request_span(&ctx, &parts.headers, settings.sampler.as_deref())
+    });
+    req = Request::from_parts(parts, body);
+
+    let request_id = HeaderValue::from_str(ctx.request_id()).ok();
+    let abandoned = CancelOnDrop(
+        settings
+            .cancel_abandoned
+            .then(|| ctx.cancellation().clone()),
+    );
+    let run = CURRENT.scope(ctx.clone(), next.run(req).instrument(span.clone()));
+    let mut response = match ctx.deadline() {
+        Some(deadline) => tokio::time::timeout_at(deadline, run)
+            .await
+            .unwrap_or_else(|_| {
+                if settings.cancel_abandoned {
+                    ctx.cancellation().cancel();
+                }
+                ApiError::new(StatusCode::REQUEST_TIMEOUT, "Request timed out").into_response()
+            }),
+        None => run.await,
+    };
+    abandoned.disarm();
+    sampling::finish(&span, &response, started);
+
+    if let Some(request_id) = request_id {
+        response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+    response
+}
+
+// language tag with the highest q value in an Accept-Language header;
+// equal weights keep the listed order, and `q=0` tags are refused
+fn preferred_locale(header: &str) -> Option<String> {
+    let mut best: Option<(&str, f32)> = None;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let tag = parts.next().unwrap_or("").trim();
+        let q = parts
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse::<f32>().ok())
+                    .flatten()
+            })
+            .unwrap_or(1.0);
+        if tag.is_empty() || tag == "*" || q <= 0.0 {
+            continue;
+        }
+        if best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((tag, q));
+        }
+    }
+    best.map(|(tag, _)| tag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    // stands in for a service that never sees the request
+    fn describe_current() -> String {
+        let ctx = RequestContext::current().expect("inside a request");
+        format!(
+            "{} {} {}",
+            ctx.request_id(),
+            ctx.route().unwrap_or("-"),
+            ctx.locale().unwrap_or("-")
+        )
+    }
+
+    fn router(timeout: Option<Duration>) -> Router {
+        router_with(ContextSettings {
+            timeout,
+            ..ContextSettings::default()
+        })
+    }
+
+    fn router_with(settings: ContextSettings) -> Router {
+        Router::new()
+            .route("/users/{id}", get(|| async { describe_current() }))
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(settings, scope_request))
+    }
+
+    #[tokio::test]
+    async fn test_current_context_in_handler() {
+        let request = Request::builder()
+            .uri("/users/7")
+            .header(REQUEST_ID_HEADER, "abc123")
+            .header(header::ACCEPT_LANGUAGE, "fr-CH, fr;q=0.9, en;q=0.8")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(None).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc123");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "abc123 /users/{id} fr-CH");
+    }
+
+    #[tokio::test]
+    async fn test_generates_request_id() {
+        let request = Request::builder()
+            .uri("/users/7")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(None).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER].len(), 36);
+
+        let settings = ContextSettings {
+            ids: Arc::new(crate::ids::SequentialIds::new("req-")),
+            ..ContextSettings::default()
+        };
+        let request = Request::builder()
+            .uri("/users/7")
+            .body(Body::empty())
+            .unwrap();
+        let response = router_with(settings).oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "req-1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let request = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let response = router(Some(Duration::from_millis(100)))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_scope_and_principal() {
+        assert!(RequestContext::current().is_none());
+        let ctx = RequestContext::new("job-1");
+        let principal = ctx
+            .clone()
+            .scope(async {
+                RequestContext::current().unwrap().set_principal("alice");
+                RequestContext::current().unwrap().principal()
+            })
+            .await;
+        assert_eq!(principal.as_deref(), Some("alice"));
+        assert_eq!(ctx.principal().as_deref(), Some("alice"));
+
+        ctx.set_principal("mallory");
+        assert_eq!(ctx.principal().as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_sync_scope_and_with_current() {
+        assert_eq!(
+            RequestContext::with_current(|ctx| ctx.request_id().len()),
+            None
+        );
+        let len = RequestContext::new("job-2")
+            .sync_scope(|| RequestContext::with_current(|ctx| ctx.request_id().len()));
+        assert_eq!(len, Some(5));
+    }
+
+    #[test]
+    fn test_locals() {
+        let ctx = RequestContext::new("req-1");
+        // init may read other locals of the same request
+        let outer = ctx.local(|| u64::from(*ctx.local(|| 2u32)) + 1);
+        assert_eq!(*outer, 3);
+        assert_eq!(*ctx.local(|| 0u32), 2);
+        assert!(Arc::ptr_eq(&outer, &ctx.clone().local(|| 0u64)));
+        assert_eq!(*RequestContext::new("req-2").local(|| 7u32), 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_caps_downstream_calls() {
+        assert_eq!(cap_timeout(Duration::from_secs(3)), Duration::from_secs(3));
+
+        let ctx = RequestContext::build(
+            "req".to_string(),
+            Method::GET,
+            None,
+            None,
+            Some(Instant::now() + Duration::from_secs(1)),
+        );
+        ctx.scope(async {
+            assert!(cap_timeout(Duration::from_secs(3)) <= Duration::from_secs(1));
+            assert_eq!(
+                cap_timeout(Duration::from_millis(10)),
+                Duration::from_millis(10)
+            );
+
+            let fast = within_deadline(async { 1 }).await.unwrap();
+            assert_eq!(fast, 1);
+            let slow = within_deadline(tokio::time::sleep(Duration::from_secs(5))).await;
+            assert_eq!(slow.unwrap_err().status(), StatusCode::GATEWAY_TIMEOUT);
+            assert!(RequestContext::current().unwrap().is_expired());
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_bounds_client_and_repository_calls() {
+        use crate::{
+            client::{ClientError, ClientRequest, ClientResponse, Correlated, HttpClient},
+            lifecycle::BoxFuture,
+            repository::{self, RepositoryError},
+        };
+
+        struct Hanging;
+
+        impl HttpClient for Hanging {
+            fn send(
+                &self,
+                _request: ClientRequest,
+            ) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+                Box::pin(std::future::pending())
+            }
+        }
+
+        let ctx = RequestContext::build(
+            "req".to_string(),
+            Method::GET,
+            None,
+            None,
+            Some(Instant::now() + Duration::from_secs(1)),
+        );
+        ctx.scope(async {
+            let client = Correlated::new(Arc::new(Hanging));
+            let request = Request::get("http://upstream/")
+                .body(Default::default())
+                .unwrap();
+            let sent = client.send(request).await;
+            assert!(matches!(sent, Err(ClientError::Timeout)));
+
+            let found: Result<(), _> = repository::within_deadline(std::future::pending()).await;
+            let err = found.unwrap_err();
+            assert!(matches!(err, RepositoryError::Timeout));
+            assert_eq!(ApiError::from(err).status(), StatusCode::GATEWAY_TIMEOUT);
+        })
+        .await;
+    }
+
+    // router whose `/spawn` handler starts a task in the request's scope that
+    // reports whether it was cancelled
+    fn spawning_router(
+        cancel_abandoned: bool,
+        started: tokio::sync::mpsc::UnboundedSender<()>,
+        outcome: tokio::sync::mpsc::UnboundedSender<bool>,
+    ) -> Router {
+        let settings = ContextSettings {
+            timeout: Some(Duration::from_millis(50)),
+            cancel_abandoned,
+            ..ContextSettings::default()
+        };
+        let handler = move || async move {
+            let ctx = RequestContext::current().unwrap();
+            tokio::spawn(ctx.scope(async move {
+                let result = until_cancelled(tokio::time::sleep(Duration::from_secs(1))).await;
+                let _ = outcome.send(result.is_err());
+            }));
+            let _ = started.send(());
+            std::future::pending::<()>().await
+        };
+        Router::new()
+            .route("/spawn", get(handler))
+            .layer(middleware::from_fn_with_state(settings, scope_request))
+    }
+
+    fn spawn_request() -> Request {
+        Request::builder()
+            .uri("/spawn")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_cancels_spawned_work() {
+        let (started, _) = tokio::sync::mpsc::unbounded_channel();
+        let (outcome, mut outcomes) = tokio::sync::mpsc::unbounded_channel();
+        let response = spawning_router(true, started, outcome)
+            .oneshot(spawn_request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(outcomes.recv().await, Some(true));
+
+        // not opted in: the spawned work runs to completion
+        let (started, _) = tokio::sync::mpsc::unbounded_channel();
+        let (outcome, mut outcomes) = tokio::sync::mpsc::unbounded_channel();
+        let response = spawning_router(false, started, outcome)
+            .oneshot(spawn_request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(outcomes.recv().await, Some(false));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_request_cancels_spawned_work() {
+        let (started, mut starts) = tokio::sync::mpsc::unbounded_channel();
+        let (outcome, mut outcomes) = tokio::sync::mpsc::unbounded_channel();
+        let request =
+            tokio::spawn(spawning_router(true, started, outcome).oneshot(spawn_request()));
+        starts.recv().await.unwrap();
+
+        // what the server does when the client disconnects
+        request.abort();
+        assert_eq!(outcomes.recv().await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_wakes_waiters() {
+        let cancellation = Cancellation::new();
+        let waiter = tokio::spawn({
+            let cancellation = cancellation.clone();
+            async move { cancellation.cancelled().await }
+        });
+        assert!(!cancellation.is_cancelled());
+        cancellation.cancel();
+        waiter.await.unwrap();
+        assert!(cancellation.is_cancelled());
+        cancellation.cancelled().await;
+    }
+
+    #[test]
+    fn test_preferred_locale() {
+        assert_eq!(preferred_locale("de;q=0.5, en").as_deref(), Some("en"));
+        assert_eq!(
+            preferred_locale("fr-CH, fr;q=0.9, en;q=0.8").as_deref(),
+            Some("fr-CH")
+        );
+        assert_eq!(
+            preferred_locale("en;q=0.8, de;q=0.8").as_deref(),
+            Some("en")
+        );
+        assert_eq!(preferred_locale("en;q=0, de;q=0.1").as_deref(), Some("de"));
+        assert_eq!(preferred_locale("*"), None);
+    }
+}
@@ -0,0 +1,326 @@
+//! CPU-bound work for RustAPI framework
+//!
+//! Work that keeps a thread busy for more than a few hundred microseconds,
+//! such as image processing or report generation, stalls every request
+//! sharing the async worker thread. `CpuPool` runs such work on Tokio's
+//! blocking threads with a cap on how much runs at once and how much may
+//! wait, so a burst of heavy requests is rejected with 503 instead of
+//! silently exhausting the blocking pool, and reports its load as metrics.
+//!
+//! `#[blocking]` turns a plain `fn` handler into one whose body runs on the
+//! app's pool.
+
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    context::{self, RequestContext},
+    di::{Container, Injectable},
+    error::ApiError,
+    metrics::Metrics,
+};
+
+/// Default number of jobs waiting for a thread before new ones are rejected
+pub const DEFAULT_MAX_QUEUED: usize = 256;
+
+/// Bounded runner for CPU-bound work
+///
+/// Cheap to clone; clones share the limits. Install with `App::cpu_pool`;
+/// apps without one share a pool running one job per CPU. Handlers get the
+/// app's pool by extracting `CpuPool`.
+///
+/// # Example
+///
+/// ```ignore
+/// let pool = CpuPool::new(4).max_queued(32).metrics(metrics.clone());
+/// let app = App::new().cpu_pool(pool);
+///
+/// #[post("/thumbnails")]
+/// async fn thumbnail(pool: CpuPool, body: Bytes) -> Result<Vec<u8>, ApiError> {
+///     pool.run(move || resize(&body, 128)).await
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CpuPool {
+    max_concurrent: usize,
+    max_queued: usize,
+    metrics: Option<Metrics>,
+    permits: Arc<Semaphore>,
+    load: Arc<Load>,
+}
+
+// jobs waiting for and holding a thread
+#[derive(Debug, Default)]
+struct Load {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+}
+
+impl CpuPool {
+    /// Run at most `max_concurrent` jobs at once
+    pub fn new(max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            max_concurrent,
+            max_queued: DEFAULT_MAX_QUEUED,
+            metrics: None,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            load: Arc::default(),
+        }
+    }
+
+    /// Reject jobs with 503 `cpu_pool_saturated` while `max` are waiting
+    pub fn max_queued(mut self, max: usize) -> Self {
+        self.max_queued = max;
+        self
+    }
+
+    /// Report `cpu_pool_running` and `cpu_pool_queued` gauges and the
+    /// `cpu_pool_jobs_total`, `cpu_pool_rejected_total` and
+    /// `cpu_pool_wait_ms_total` counters to `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        let load = Arc::clone(&self.load);
+        metrics.gauge("cpu_pool_running", &[], move || {
+            load.running.load(Ordering::Relaxed) as f64
+        });
+        let load = Arc::clone(&self.load);
+        metrics.gauge("cpu_pool_queued", &[], move || {
+            load.queued.load(Ordering::Relaxed) as f64
+        });
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Pool used when the app has none, running one job per CPU
+    pub fn global() -> Self {
+        static GLOBAL: OnceLock<CpuPool> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| {
+                let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+                CpuPool::new(cpus)
+            })
+            .clone()
+    }
+
+    /// Most jobs running at once
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// Jobs running now
+    pub fn running(&self) -> usize {
+        self.load.running.load(Ordering::Relaxed)
+    }
+
+    /// Jobs waiting for a thread
+    pub fn queued(&self) -> usize {
+        self.load.queued.load(Ordering::Relaxed)
+    }
+
+    /// Run `job` on a blocking thread once the pool has room
+    ///
+    /// Fails with 503 `cpu_pool_saturated` when the queue is full, and with
+    /// 500 when `job` panics. A job keeps running when the caller stops
+    /// waiting for it, and counts against the limit until it returns; it
+    /// runs in the caller's `RequestContext`, so long jobs can check
+    /// `is_cancelled` to stop early. Jobs of a request cancelled while they
+    /// waited are not started.
+    pub async fn run<F, T>(&self, job: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let waiting = Counted::new(&self.load.queued);
+        if waiting.previous >= self.max_queued {
+            drop(waiting);
+            self.count("cpu_pool_rejected_total", 1);
+            tracing::warn!(
+                queued = self.max_queued,
+                "CPU pool saturated, rejecting job"
+            );
+            return Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server is too busy, try again later",
+            )
+            .with_code("cpu_pool_saturated"));
+        }
+
+        let started = Instant::now();
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| ApiError::internal("CPU pool closed"))?;
+        drop(waiting);
+        let ctx = RequestContext::current();
+        if ctx.as_ref().is_some_and(RequestContext::is_cancelled) {
+            return Err(context::request_cancelled());
+        }
+        self.count("cpu_pool_jobs_total", 1);
+        self.count(
+            "cpu_pool_wait_ms_total",
+            started.elapsed().as_millis() as u64,
+        );
+
+        let load = Arc::clone(&self.load);
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _running = Counted::new(&load.running);
+            match ctx {
+                Some(ctx) => ctx.sync_scope(job),
+                None => job(),
+            }
+        })
+        .await
+        .map_err(|e| ApiError::internal(format!("CPU-bound job failed: {}", e)))
+    }
+
+    // add to a counter when metrics are enabled
+    fn count(&self, name: &str, value: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add(name, &[], value);
+        }
+    }
+}
+
+impl Default for CpuPool {
+    fn default() -> Self {
+        Self::global()
+    }
+}
+
+impl Injectable for CpuPool {}
+
+impl<S: Send + Sync> FromRequestParts<S> for CpuPool {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(parts
+            .extensions
+            .get::<Arc<Container>>()
+            .and_then(|container| container.resolve::<CpuPool>())
+            .map_or_else(CpuPool::global, |pool| (*pool).clone()))
+    }
+}
+
+// one unit of a load counter, released on drop
+struct Counted<'a> {
+    counter: &'a AtomicUsize,
+    previous: usize,
+}
+
+impl<'a> Counted<'a> {
+    // take a unit of `counter`
+    fn new(counter: &'a AtomicUsize) -> Self {
+        let previous = counter.fetch_add(1, Ordering::Relaxed);
+        Self { counter, previous }
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use axum::{body::Body, extract::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app::App, routing::get};
+
+    #[tokio::test]
+    async fn test_limits_and_metrics() {
+        let metrics = Metrics::new();
+        let pool = CpuPool::new(1).max_queued(1).metrics(metrics.clone());
+        let (release, released) = mpsc::channel::<()>();
+
+        let first = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || released.recv().map(|_| 1)).await }
+        });
+        while pool.running() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 2).await }
+        });
+        while pool.queued() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(metrics.gauge_value("cpu_pool_queued", &[]), Some(1.0));
+
+        let error = pool.run(|| 3).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.code(), "cpu_pool_saturated");
+
+        release.send(()).unwrap();
+        assert_eq!(first.await.unwrap().unwrap(), Ok(1));
+        assert_eq!(second.await.unwrap().unwrap(), 2);
+        assert_eq!(metrics.counter("cpu_pool_jobs_total", &[]), 2);
+        assert_eq!(metrics.counter("cpu_pool_rejected_total", &[]), 1);
+        assert_eq!((pool.running(), pool.queued()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_panics_become_errors() {
+        let pool = CpuPool::new(1);
+        let error = pool.run(|| panic!("boom")).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(pool.run(|| 1).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_run_in_request_context() {
+        let pool = CpuPool::new(1);
+        let ctx = RequestContext::new("req-1");
+        let id = ctx
+            .clone()
+            .scope(pool.run(|| RequestContext::current().map(|ctx| ctx.request_id().to_string())))
+            .await
+            .unwrap();
+        assert_eq!(id.as_deref(), Some("req-1"));
+
+        ctx.cancellation().cancel();
+        let error = ctx.scope(pool.run(|| 1)).await.unwrap_err();
+        assert_eq!(error.code(), "request_cancelled");
+    }
+
+    #[crate::blocking]
+    fn checksum(body: String) -> String {
+        let sum: u32 = body.bytes().map(u32::from).sum();
+        sum.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_blocking_handler_uses_app_pool() {
+        let pool = CpuPool::new(2).metrics(Metrics::new());
+        let app = App::new()
+            .cpu_pool(pool.clone())
+            .route("/checksum", get(checksum))
+            .build();
+        let request = Request::get("/checksum").body(Body::from("ab")).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "195");
+        assert_eq!(pool.metrics.unwrap().counter("cpu_pool_jobs_total", &[]), 1);
+    }
+}
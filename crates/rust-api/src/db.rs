@@ -0,0 +1,475 @@
+//! Read/write database routing for RustAPI framework
+//!
+//! `Db` holds a primary connection pool and any number of replica pools.
+//! Writes go to the primary; reads go to a healthy replica in turn, falling
+//! back to the primary when no replica is usable or a replica query fails.
+//! Replicas are marked unhealthy when a `ReplicaLag` probe fails or reports
+//! more lag than allowed. Handlers marked `#[read_only]` (or code run in
+//! `read_only`) get replica connections from `Db::conn`. With a `Bulkhead`,
+//! `Db::isolated` caps how many queries wait on the database at once.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::Request, middleware::Next, response::Response};
+
+use crate::{
+    bulkhead::Bulkhead,
+    di::Injectable,
+    error::{ApiError, Result},
+    lifecycle::BoxFuture,
+    registry::RouteRegistry,
+};
+
+/// Replication lag allowed before a replica stops serving reads
+pub const DEFAULT_MAX_LAG: Duration = Duration::from_secs(5);
+
+tokio::task_local! {
+    static READ_ONLY: ();
+}
+
+/// Route metadata attached by `#[read_only]`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadOnly;
+
+/// Run `fut` with `Db::conn` routed to replicas
+pub async fn read_only<F: Future>(fut: F) -> F::Output {
+    READ_ONLY.scope((), fut).await
+}
+
+/// Check whether the current task runs in a `read_only` scope
+pub fn is_read_only() -> bool {
+    READ_ONLY.try_with(|_| ()).is_ok()
+}
+
+pub(crate) fn any_read_only() -> bool {
+    RouteRegistry::global().with::<ReadOnly>().next().is_some()
+}
+
+// run requests to `#[read_only]` routes in a read-only scope
+pub(crate) async fn read_only_routes(req: Request, next: Next) -> Response {
+    let (parts, body) = req.into_parts();
+    let read_only = RouteRegistry::global()
+        .for_request(&parts)
+        .is_some_and(|route| route.metadata.contains::<ReadOnly>());
+    let req = Request::from_parts(parts, body);
+    match read_only {
+        true => READ_ONLY.scope((), next.run(req)).await,
+        false => next.run(req).await,
+    }
+}
+
+/// Probe measuring how far a replica lags behind the primary
+///
+/// Implemented for closures taking the replica connection, e.g.
+/// `|conn| async move { query_lag(&conn).await }`.
+pub trait ReplicaLag<C>: Send + Sync + 'static {
+    /// Current replication lag of `replica`
+    fn lag<'a>(&'a self, replica: &'a C) -> BoxFuture<'a, Result<Duration>>;
+}
+
+impl<C, F, Fut> ReplicaLag<C> for F
+where
+    C: Clone + Send + Sync + 'static,
+    F: Fn(C) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Duration>> + Send + 'static,
+{
+    fn lag<'a>(&'a self, replica: &'a C) -> BoxFuture<'a, Result<Duration>> {
+        Box::pin(self(replica.clone()))
+    }
+}
+
+/// Health of one replica, as of the last check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicaStatus {
+    /// Name given at registration
+    pub name: String,
+    /// Whether the replica serves reads
+    pub healthy: bool,
+    /// Lag measured by the last successful probe
+    pub lag: Option<Duration>,
+}
+
+// a replica pool and its health
+struct Replica<C> {
+    name: String,
+    conn: C,
+    healthy: AtomicBool,
+    // lag in milliseconds, u64::MAX when never measured
+    lag_ms: AtomicU64,
+}
+
+/// Primary and replica connection pools behind one handle
+///
+/// Cheap to clone; clones share replica health. Register it in the container
+/// and inject it into services.
+///
+/// # Example
+///
+/// ```ignore
+/// let db = Db::new(primary)
+///     .replica("replica-1", replica_1)
+///     .replica("replica-2", replica_2)
+///     .lag_probe(orm::PostgresReplicaLag)
+///     .max_lag(Duration::from_secs(2));
+/// db.spawn_health_checks(Duration::from_secs(10));
+///
+/// let user = db.read(|conn| async move { User::find_by_id(id).one(&conn).await }).await?;
+/// User::insert(model).exec(db.writer()).await?;
+/// ```
+pub struct Db<C> {
+    primary: C,
+    replicas: Vec<Arc<Replica<C>>>,
+    next: Arc<AtomicUsize>,
+    max_lag: Duration,
+    probe: Option<Arc<dyn ReplicaLag<C>>>,
+    bulkhead: Option<Bulkhead>,
+}
+
+impl<C: Clone> Clone for Db<C> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            replicas: self.replicas.clone(),
+            next: self.next.clone(),
+            max_lag: self.max_lag,
+            probe: self.probe.clone(),
+            bulkhead: self.bulkhead.clone(),
+        }
+    }
+}
+
+impl<C: Send + Sync + 'static> Injectable for Db<C> {}
+
+impl<C: Send + Sync + 'static> Db<C> {
+    /// Route everything to `primary` until replicas are added
+    pub fn new(primary: C) -> Self {
+        Self {
+            primary,
+            replicas: Vec::new(),
+            next: Arc::new(AtomicUsize::new(0)),
+            max_lag: DEFAULT_MAX_LAG,
+            probe: None,
+            bulkhead: None,
+        }
+    }
+
+    /// Add a replica pool serving reads, healthy until a check says otherwise
+    pub fn replica(mut self, name: impl Into<String>, conn: C) -> Self {
+        self.replicas.push(Arc::new(Replica {
+            name: name.into(),
+            conn,
+            healthy: AtomicBool::new(true),
+            lag_ms: AtomicU64::new(u64::MAX),
+        }));
+        self
+    }
+
+    /// Replication lag allowed before a replica stops serving reads
+    pub fn max_lag(mut self, max_lag: Duration) -> Self {
+        self.max_lag = max_lag;
+        self
+    }
+
+    /// Measure replica lag with `probe` on every health check
+    pub fn lag_probe(mut self, probe: impl ReplicaLag<C>) -> Self {
+        self.probe = Some(Arc::new(probe));
+        self
+    }
+
+    /// Run `isolated` queries at most as many at once as `bulkhead` allows
+    pub fn bulkhead(mut self, bulkhead: Bulkhead) -> Self {
+        self.bulkhead = Some(bulkhead);
+        self
+    }
+
+    /// The primary pool, for writes
+    pub fn writer(&self) -> &C {
+        &self.primary
+    }
+
+    /// A healthy replica pool in turn, or the primary if there is none
+    pub fn reader(&self) -> &C {
+        self.healthy_replica()
+            .map_or(&self.primary, |replica| &replica.conn)
+    }
+
+    /// The reader inside a `read_only` scope, the writer otherwise
+    pub fn conn(&self) -> &C {
+        match is_read_only() {
+            true => self.reader(),
+            false => self.writer(),
+        }
+    }
+
+    /// Run a read query on a replica, retrying it on the primary if it fails
+    ///
+    /// The failing replica is marked unhealthy until the next health check.
+    pub async fn read<T, E, F, Fut>(&self, query: F) -> std::result::Result<T, E>
+    where
+        C: Clone,
+        E: std::fmt::Display,
+        F: Fn(C) -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+    {
+        if let Some(replica) = self.healthy_replica() {
+            match query(replica.conn.clone()).await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    tracing::warn!(
+                        "Read on replica {} failed, retrying on primary: {}",
+                        replica.name,
+                        e
+                    );
+                    replica.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+        query(self.primary.clone()).await
+    }
+
+    /// Run `query` in the pool's bulkhead, if it has one
+    ///
+    /// Fails with 503 `bulkhead_full` when the database is saturated.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let user = db.isolated(User::find_by_id(id).one(db.conn())).await??;
+    /// ```
+    pub async fn isolated<F: Future>(&self, query: F) -> std::result::Result<F::Output, ApiError> {
+        match &self.bulkhead {
+            Some(bulkhead) => bulkhead.run(query).await,
+            None => Ok(query.await),
+        }
+    }
+
+    /// Health of every replica, as of the last check
+    pub fn replica_status(&self) -> Vec<ReplicaStatus> {
+        self.replicas
+            .iter()
+            .map(|replica| ReplicaStatus {
+                name: replica.name.clone(),
+                healthy: replica.healthy.load(Ordering::Relaxed),
+                lag: match replica.lag_ms.load(Ordering::Relaxed) {
+                    u64::MAX => None,
+                    ms => Some(Duration::from_millis(ms)),
+                },
+            })
+            .collect()
+    }
+
+    /// Probe every replica, including unhealthy ones, and update its health
+    ///
+    /// A replica is healthy when the probe succeeds and reports no more than
+    /// `max_lag`. Without a lag probe every replica is restored, so replicas
+    /// taken out by a failed `read` are tried again.
+    pub async fn check_replicas(&self) {
+        for replica in &self.replicas {
+            let probed = match &self.probe {
+                Some(probe) => Some(probe.lag(&replica.conn).await),
+                None => None,
+            };
+            let healthy = match probed {
+                None => true,
+                Some(Ok(lag)) => {
+                    let ms = u64::try_from(lag.as_millis()).unwrap_or(u64::MAX - 1);
+                    replica.lag_ms.store(ms, Ordering::Relaxed);
+                    lag <= self.max_lag
+                }
+                Some(Err(e)) => {
+                    tracing::warn!("Lag probe on replica {} failed: {}", replica.name, e);
+                    false
+                }
+            };
+            let was_healthy = replica.healthy.swap(healthy, Ordering::Relaxed);
+            if was_healthy != healthy {
+                match healthy {
+                    true => tracing::info!("Replica {} is serving reads again", replica.name),
+                    false => tracing::warn!("Replica {} stopped serving reads", replica.name),
+                }
+            }
+        }
+    }
+
+    /// Run `check_replicas` every `interval` in the background
+    pub fn spawn_health_checks(&self, interval: Duration) -> tokio::task::JoinHandle<()>
+    where
+        C: Clone,
+    {
+        let db = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                db.check_replicas().await;
+            }
+        })
+    }
+
+    // next healthy replica, round robin
+    fn healthy_replica(&self) -> Option<&Replica<C>> {
+        let count = self.replicas.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..count)
+            .map(|offset| &*self.replicas[(start + offset) % count])
+            .find(|replica| replica.healthy.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        error::Error,
+        registry::{RouteDef, RouteMetadata},
+    };
+
+    fn db() -> Db<&'static str> {
+        Db::new("primary").replica("a", "a").replica("b", "b")
+    }
+
+    #[test]
+    fn test_reads_rotate_over_replicas_and_writes_go_to_primary() {
+        let db = db();
+        let readers: Vec<_> = (0..4).map(|_| *db.reader()).collect();
+        assert_eq!(readers, ["a", "b", "a", "b"]);
+        assert_eq!(*db.writer(), "primary");
+        assert_eq!(*db.conn(), "primary");
+        assert_eq!(*Db::new("primary").reader(), "primary");
+    }
+
+    #[tokio::test]
+    async fn test_read_only_scope_routes_conn_to_replicas() {
+        let db = db();
+        assert!(!is_read_only());
+        let conn = read_only(async { *db.conn() }).await;
+        assert!(conn == "a" || conn == "b");
+    }
+
+    #[tokio::test]
+    async fn test_lag_checks_mark_replicas_unhealthy() {
+        let lags = Arc::new(Mutex::new(HashMap::from([
+            ("a", Ok(Duration::from_millis(100))),
+            ("b", Ok(Duration::from_secs(30))),
+        ])));
+        let probe_lags = lags.clone();
+        let db = db().max_lag(Duration::from_secs(1)).lag_probe(move |conn| {
+            let lag = probe_lags.lock().unwrap()[conn].clone();
+            async move { lag.map_err(|e: String| Error::other(e)) }
+        });
+
+        db.check_replicas().await;
+        assert_eq!((0..3).map(|_| *db.reader()).collect::<Vec<_>>(), ["a"; 3]);
+        let status = db.replica_status();
+        assert!(status[0].healthy && !status[1].healthy);
+        assert_eq!(status[1].lag, Some(Duration::from_secs(30)));
+
+        lags.lock()
+            .unwrap()
+            .insert("a", Err("connection refused".to_string()));
+        db.check_replicas().await;
+        assert_eq!(*db.reader(), "primary");
+
+        // recovered replicas serve reads again
+        lags.lock().unwrap().extend([
+            ("a", Ok(Duration::from_millis(10))),
+            ("b", Ok(Duration::from_millis(10))),
+        ]);
+        db.check_replicas().await;
+        assert!(db.replica_status().iter().all(|status| status.healthy));
+    }
+
+    #[tokio::test]
+    async fn test_read_fails_over_to_primary() {
+        let db = Db::new("primary").replica("a", "a");
+        let result: std::result::Result<&str, String> = db
+            .read(|conn| async move {
+                match conn {
+                    "primary" => Ok(conn),
+                    _ => Err("replica down".to_string()),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "primary");
+        assert!(!db.replica_status()[0].healthy);
+
+        // without a lag probe the next check restores it
+        db.check_replicas().await;
+        assert!(db.replica_status()[0].healthy);
+    }
+
+    #[tokio::test]
+    async fn test_isolated_queries_respect_bulkhead() {
+        let bulkhead = Bulkhead::new("db", 1).max_wait(Duration::ZERO);
+        let db = Db::new("primary").bulkhead(bulkhead.clone());
+        assert_eq!(db.isolated(async { db.writer().len() }).await.unwrap(), 7);
+
+        let _held = bulkhead.acquire().await.unwrap();
+        let error = db.isolated(async {}).await.unwrap_err();
+        assert_eq!(error.code(), "bulkhead_full");
+    }
+
+    inventory::submit! {
+        RouteDef::new("GET", "/db-test/report", concat!(module_path!(), "::", "report"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "report"), |meta| meta.insert(ReadOnly))
+    }
+
+    #[tokio::test]
+    async fn test_read_only_routes() {
+        assert!(any_read_only());
+        let router = Router::new()
+            .route(
+                "/db-test/report",
+                get(|| async { is_read_only().to_string() }),
+            )
+            .route(
+                "/db-test/write",
+                get(|| async { is_read_only().to_string() }),
+            )
+            .layer(middleware::from_fn(read_only_routes));
+
+        for (path, expected) in [("/db-test/report", "true"), ("/db-test/write", "false")] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_only_routes_under_a_prefix() {
+        let router = crate::App::new()
+            .group("/v1", |g| {
+                g.route(
+                    "/db-test/report",
+                    get(|| async { is_read_only().to_string() }),
+                )
+            })
+            .build();
+        let request = Request::get("/v1/db-test/report")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "true");
+    }
+}
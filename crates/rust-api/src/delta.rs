@@ -0,0 +1,472 @@
+//! Delta queries for RustAPI framework
+//!
+//! Offline-capable clients keep a copy of a collection and only fetch what
+//! changed since their last sync. A `DeltaRepository` returns those changes
+//! as a `Delta`: the items created or updated, the ids of the items deleted
+//! (their tombstones), and a `SyncToken` to send back next time.
+//!
+//! Conventions:
+//!
+//! - The first sync has no parameters and returns every live item, without
+//!   tombstones; later syncs pass `?sync_token=` from the previous response.
+//!   `?updated_since=` with an RFC 3339 timestamp is accepted for clients
+//!   that only track time.
+//! - While `has_more` is true, the client requests again with the new token
+//!   right away.
+//! - A client removes the items listed in `deleted` from its copy.
+//! - When tombstones older than the token have been purged, the query fails
+//!   with 410 Gone (`sync_token_expired`) and the client syncs from the
+//!   start.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    error::ApiError,
+    lifecycle::BoxFuture,
+    repository::{Repository, RepositoryError, DEFAULT_PER_PAGE, MAX_PER_PAGE},
+};
+
+/// Opaque position in a collection's changes
+///
+/// Sent to clients as a string, which they should store without parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SyncToken(u64);
+
+impl SyncToken {
+    /// Token for the changes after change number `version`
+    pub fn new(version: u64) -> Self {
+        Self(version)
+    }
+
+    /// Number of the last change the token covers
+    pub fn version(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for SyncToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for SyncToken {
+    type Err = ApiError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        token.parse().map(Self).map_err(|_| {
+            ApiError::bad_request(format!("Invalid sync token {:?}", token))
+                .with_code("invalid_sync_token")
+        })
+    }
+}
+
+impl Serialize for SyncToken {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Where a delta query starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    /// A first sync: every live item, no tombstones
+    Start,
+    /// The changes after a token from an earlier sync
+    Token(SyncToken),
+    /// The changes made at or after a time
+    Time(SystemTime),
+}
+
+/// Changes to a collection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Delta<T, Id> {
+    /// Items created or updated, oldest change first
+    pub items: Vec<T>,
+    /// Ids of the items deleted
+    pub deleted: Vec<Id>,
+    /// Token for the next sync
+    pub sync_token: SyncToken,
+    /// Whether more changes follow the token
+    pub has_more: bool,
+}
+
+impl<T, Id> Delta<T, Id> {
+    /// No changes, up to `sync_token`
+    pub fn new(sync_token: SyncToken) -> Self {
+        Self {
+            items: Vec::new(),
+            deleted: Vec::new(),
+            sync_token,
+            has_more: false,
+        }
+    }
+
+    /// Mark whether more changes follow
+    pub fn has_more(mut self, has_more: bool) -> Self {
+        self.has_more = has_more;
+        self
+    }
+
+    /// Convert the items, e.g. entities into response DTOs
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Delta<U, Id> {
+        Delta {
+            items: self.items.into_iter().map(f).collect(),
+            deleted: self.deleted,
+            sync_token: self.sync_token,
+            has_more: self.has_more,
+        }
+    }
+}
+
+/// A `Repository` that can list the changes made since a point
+///
+/// Implementations record a change number and time for each write and keep
+/// a tombstone for each delete, e.g. `updated_at` and `deleted_at` columns
+/// with soft deletes, plus a sequence.
+pub trait DeltaRepository<T, Id>: Repository<T, Id> {
+    /// Up to `limit` changes after `since`, oldest first
+    ///
+    /// Fails with `RepositoryError::SyncExpired` when tombstones after
+    /// `since` may have been purged.
+    fn changes(
+        &self,
+        since: Since,
+        limit: u32,
+    ) -> BoxFuture<'_, Result<Delta<T, Id>, RepositoryError>>;
+}
+
+/// Extractor for `?sync_token=` or `?updated_since=`, and `?per_page=`
+///
+/// Passing both starting points, or an invalid one, is rejected with 400
+/// Bad Request. Like `Pagination::new`, `per_page` defaults to
+/// `DEFAULT_PER_PAGE` and is clamped to 1..=`MAX_PER_PAGE`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/notes/sync")]
+/// async fn sync_notes(Inject(notes): Inject<NoteRepository>, query: DeltaQuery) -> ApiResult<Json<Delta<Note, i64>>> {
+///     Ok(Json(notes.changes(query.since(), query.limit()).await?))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaQuery {
+    since: Since,
+    limit: u32,
+}
+
+// query parameters read by the `DeltaQuery` extractor
+#[derive(Deserialize)]
+struct DeltaParams {
+    sync_token: Option<String>,
+    updated_since: Option<String>,
+    per_page: Option<u32>,
+}
+
+impl DeltaQuery {
+    /// Query `limit` changes after `since`
+    pub fn new(since: Since, limit: u32) -> Self {
+        Self {
+            since,
+            limit: limit.clamp(1, MAX_PER_PAGE),
+        }
+    }
+
+    /// Where the query starts
+    pub fn since(&self) -> Since {
+        self.since
+    }
+
+    /// Largest number of changes to return
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for DeltaQuery {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let invalid = |message: String| {
+            ApiError::new(StatusCode::BAD_REQUEST, message)
+                .with_code("invalid_delta_query")
+                .rejection()
+        };
+        let Query(params) = Query::<DeltaParams>::try_from_uri(&parts.uri)
+            .map_err(|e| invalid(format!("Invalid delta query: {}", e)))?;
+        let since = match (params.sync_token, params.updated_since) {
+            (Some(_), Some(_)) => {
+                return Err(invalid(
+                    "Pass either sync_token or updated_since, not both".to_string(),
+                ))
+            }
+            (Some(token), None) => Since::Token(token.parse().map_err(ApiError::rejection)?),
+            (None, Some(time)) => Since::Time(parse_rfc3339(&time).ok_or_else(|| {
+                invalid(format!(
+                    "updated_since {:?} is not an RFC 3339 timestamp",
+                    time
+                ))
+            })?),
+            (None, None) => Since::Start,
+        };
+        Ok(Self::new(
+            since,
+            params.per_page.unwrap_or(DEFAULT_PER_PAGE),
+        ))
+    }
+}
+
+/// Parse an RFC 3339 timestamp, such as `2024-05-01T12:30:00.250Z`
+pub fn parse_rfc3339(timestamp: &str) -> Option<SystemTime> {
+    let number = |s: &str| -> Option<i64> {
+        s.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| s.parse().ok())
+            .flatten()
+    };
+    let bytes = timestamp.as_bytes();
+    if !timestamp.is_ascii()
+        || bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return None;
+    }
+    let (year, month, day) = (
+        number(&timestamp[0..4])?,
+        number(&timestamp[5..7])?,
+        number(&timestamp[8..10])?,
+    );
+    let (hour, minute, second) = (
+        number(&timestamp[11..13])?,
+        number(&timestamp[14..16])?,
+        number(&timestamp[17..19])?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // leap seconds are folded into the next second
+    if second > 60 {
+        return None;
+    }
+
+    let mut rest = &timestamp[19..];
+    let mut nanos = 0u32;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        let padded = format!("{:0<9}", &fraction[..digits.min(9)]);
+        nanos = padded.parse().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let minutes = number(&rest[1..3])? * 60 + number(&rest[4..6])?;
+            if *sign == b'+' {
+                minutes * 60
+            } else {
+                -minutes * 60
+            }
+        }
+        _ => return None,
+    };
+
+    // days since the epoch of a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::new(seconds, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::Request;
+
+    use super::*;
+    use crate::{clock::Clock, repository::MemoryRepository};
+
+    #[test]
+    fn test_parse_rfc3339() {
+        let at = |secs: u64, nanos: u32| Some(UNIX_EPOCH + Duration::new(secs, nanos));
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), at(0, 0));
+        assert_eq!(
+            parse_rfc3339("2024-02-29T12:30:15.25Z"),
+            at(1_709_209_815, 250_000_000)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-02-29T14:30:15+02:00"),
+            at(1_709_209_815, 0)
+        );
+        for invalid in [
+            "2024-02-29",
+            "2024-13-01T00:00:00Z",
+            "2024-02-29T12:30:15",
+            "2024-02-29T12:30:15.Z",
+            "1969-12-31T23:59:59Z",
+            "2024-0é-29T12:30:15Z",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+        }
+    }
+
+    async fn extract(uri: &str) -> Result<DeltaQuery, ApiError> {
+        let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+        DeltaQuery::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_delta_query_extractor() {
+        let query = extract("/sync").await.unwrap();
+        assert_eq!(
+            (query.since(), query.limit()),
+            (Since::Start, DEFAULT_PER_PAGE)
+        );
+        let query = extract("/sync?sync_token=42&per_page=5").await.unwrap();
+        assert_eq!(query, DeltaQuery::new(Since::Token(SyncToken::new(42)), 5));
+        assert_eq!(extract("/sync?per_page=0").await.unwrap().limit(), 1);
+        assert_eq!(
+            extract("/sync?per_page=5000").await.unwrap().limit(),
+            MAX_PER_PAGE
+        );
+        let query = extract("/sync?updated_since=1970-01-01T00:01:00Z")
+            .await
+            .unwrap();
+        assert_eq!(
+            query.since(),
+            Since::Time(UNIX_EPOCH + Duration::from_secs(60))
+        );
+
+        assert_eq!(
+            extract("/sync?sync_token=abc").await.unwrap_err().code(),
+            "invalid_sync_token"
+        );
+        for uri in [
+            "/sync?sync_token=1&updated_since=1970-01-01T00:00:00Z",
+            "/sync?updated_since=yesterday",
+            "/sync?per_page=many",
+        ] {
+            assert_eq!(
+                extract(uri).await.unwrap_err().code(),
+                "invalid_delta_query"
+            );
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize)]
+    struct Note {
+        id: u32,
+        text: &'static str,
+    }
+
+    #[tokio::test]
+    async fn test_memory_repository_deltas() {
+        let notes = MemoryRepository::new(|note: &Note| note.id);
+        let note = |id, text| Note { id, text };
+        for id in 1..=3 {
+            notes.create(note(id, "new")).await.unwrap();
+        }
+        notes.delete(3).await.unwrap();
+
+        // first sync, in two pages, without tombstones
+        let first = notes.changes(Since::Start, 1).await.unwrap();
+        assert_eq!(first.items, [note(1, "new")]);
+        assert!(first.has_more);
+        let rest = notes
+            .changes(Since::Token(first.sync_token), 10)
+            .await
+            .unwrap();
+        assert_eq!(rest.items, [note(2, "new")]);
+        assert_eq!(rest.deleted, [3]);
+        assert!(!rest.has_more);
+
+        let before_update = SystemTime::now();
+        notes.update(1, note(1, "edited")).await.unwrap();
+        notes.delete(2).await.unwrap();
+        let delta = notes
+            .changes(Since::Token(rest.sync_token), 10)
+            .await
+            .unwrap();
+        assert_eq!(delta.items, [note(1, "edited")]);
+        assert_eq!(delta.deleted, [2]);
+        let by_time = notes.changes(Since::Time(before_update), 10).await.unwrap();
+        assert_eq!(
+            (by_time.items, by_time.deleted),
+            (delta.items, delta.deleted)
+        );
+
+        let unchanged = notes
+            .changes(Since::Token(delta.sync_token), 10)
+            .await
+            .unwrap();
+        assert!(unchanged.items.is_empty() && unchanged.deleted.is_empty());
+        assert_eq!(unchanged.sync_token, delta.sync_token);
+        assert_eq!(
+            serde_json::to_value(&unchanged).unwrap()["sync_token"],
+            delta.sync_token.to_string()
+        );
+
+        notes.purge_tombstones();
+        let expired = notes.changes(Since::Token(rest.sync_token), 10).await;
+        let error = ApiError::from(expired.unwrap_err());
+        assert_eq!(error.status(), StatusCode::GONE);
+        assert_eq!(error.code(), "sync_token_expired");
+        assert_eq!(notes.len(), 1);
+        assert!(notes.changes(Since::Start, 10).await.is_ok());
+    }
+
+    // a wall clock set by hand
+    struct WallClock(Mutex<SystemTime>);
+
+    impl Clock for WallClock {
+        fn now(&self) -> tokio::time::Instant {
+            tokio::time::Instant::now()
+        }
+
+        fn system_time(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_changes_survive_the_clock_stepping_back() {
+        let noon = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = Arc::new(WallClock(Mutex::new(noon)));
+        let notes = MemoryRepository::new(|note: &Note| note.id).clock(clock.clone());
+        notes.create(Note { id: 1, text: "new" }).await.unwrap();
+
+        // NTP steps the clock back a minute
+        *clock.0.lock().unwrap() = noon - Duration::from_secs(60);
+        notes.create(Note { id: 2, text: "new" }).await.unwrap();
+        let delta = notes.changes(Since::Time(noon), 10).await.unwrap();
+        assert_eq!(delta.items.len(), 2);
+    }
+}
@@ -0,0 +1,389 @@
+//! Route deprecation for RustAPI framework
+//!
+//! Routes marked with `#[deprecated_route]` answer with `Deprecation`,
+//! `Sunset` and `Link` headers (RFC 9745, RFC 8594) so clients learn about
+//! the deprecation, and hits are counted so owners know who still calls them.
+//!
+//! `DeprecationPolicy` enforces the lifecycle in tests: comparing the
+//! OpenAPI snapshot of the last release with the current document, it fails
+//! when a route disappears without having been deprecated long enough.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use serde_json::Value;
+
+use crate::{changelog, metrics::Metrics, registry::RouteRegistry};
+
+/// Route metadata recording a deprecation
+///
+/// Attached by `#[deprecated_route]`; documentation generators can find
+/// deprecated operations with `RouteRegistry::global().with::<Deprecated>()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Deprecated {
+    since: Option<i64>,
+    sunset: Option<&'static str>,
+    link: Option<&'static str>,
+}
+
+impl Deprecated {
+    /// Deprecated without further details
+    pub const fn new() -> Self {
+        Self {
+            since: None,
+            sunset: None,
+            link: None,
+        }
+    }
+
+    /// Unix timestamp at which the route was deprecated
+    pub const fn since(mut self, timestamp: i64) -> Self {
+        self.since = Some(timestamp);
+        self
+    }
+
+    /// HTTP-date after which the route stops working
+    pub const fn sunset(mut self, http_date: &'static str) -> Self {
+        self.sunset = Some(http_date);
+        self
+    }
+
+    /// URL of the migration guide
+    pub const fn link(mut self, url: &'static str) -> Self {
+        self.link = Some(url);
+        self
+    }
+
+    /// Unix timestamp of the deprecation, if known
+    pub fn deprecated_since(&self) -> Option<i64> {
+        self.since
+    }
+
+    /// Sunset HTTP-date, if any
+    pub fn sunset_date(&self) -> Option<&'static str> {
+        self.sunset
+    }
+
+    /// Migration guide URL, if any
+    pub fn link_url(&self) -> Option<&'static str> {
+        self.link
+    }
+
+    // add the deprecation headers to a response
+    fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        let deprecation = match self.since {
+            Some(timestamp) => format!("@{}", timestamp),
+            None => "true".to_string(),
+        };
+        if let Ok(value) = HeaderValue::from_str(&deprecation) {
+            headers.insert("deprecation", value);
+        }
+        if let Some(sunset) = self.sunset.and_then(|s| HeaderValue::from_str(s).ok()) {
+            headers.insert("sunset", sunset);
+        }
+        if let Some(link) = self
+            .link
+            .and_then(|url| HeaderValue::from_str(&format!("<{}>; rel=\"deprecation\"", url)).ok())
+        {
+            headers.append(header::LINK, link);
+        }
+    }
+}
+
+/// Check whether any registered route is deprecated
+pub(crate) fn any_deprecated() -> bool {
+    RouteRegistry::global()
+        .with::<Deprecated>()
+        .next()
+        .is_some()
+}
+
+/// Middleware adding deprecation headers and counting hits
+///
+/// Must run after routing so the matched route is known.
+pub(crate) async fn deprecation_headers(
+    State(metrics): State<Option<Metrics>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let route = RouteRegistry::global().for_request(&parts);
+    let deprecated = route.and_then(|route| route.metadata.get::<Deprecated>().copied());
+
+    if let (Some(route), Some(metrics), Some(_)) = (route, &metrics, deprecated) {
+        metrics.increment(
+            "deprecated_route_requests_total",
+            &[("method", route.method), ("route", route.path)],
+        );
+    }
+
+    let mut response = next.run(Request::from_parts(parts, body)).await;
+    if let Some(deprecated) = deprecated {
+        deprecated.apply(&mut response);
+    }
+    response
+}
+
+/// Rule for removing routes: only after they were deprecated for a while
+///
+/// A route of the previous document may be missing from the current one
+/// once it was deprecated at least `min_period` ago and its sunset date, if
+/// any, has passed. The dates come from the `x-deprecated-since` and
+/// `x-sunset` fields that `#[deprecated_route]` adds to the OpenAPI
+/// document; a route deprecated with neither can never be removed.
+///
+/// # Example
+///
+/// ```ignore
+/// #[test]
+/// fn removals_follow_the_deprecation_policy() {
+///     let released: serde_json::Value = serde_json::from_str(include_str!("../openapi/v1.4.json")).unwrap();
+///     DeprecationPolicy::new(Duration::from_secs(90 * 86_400))
+///         .check(&released, &build_app().openapi_spec())
+///         .assert_success();
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeprecationPolicy {
+    min_period: Duration,
+}
+
+/// Route removed against the policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    /// Operation removed, such as `GET /users/{id}`
+    pub operation: String,
+    /// Why the removal is not allowed yet
+    pub reason: String,
+}
+
+/// Outcome of checking a document against a `DeprecationPolicy`
+#[derive(Debug, Clone, Default)]
+pub struct PolicyReport {
+    /// Number of operations removed since the previous document
+    pub removed: usize,
+    /// Removals the policy does not allow
+    pub violations: Vec<PolicyViolation>,
+}
+
+impl PolicyReport {
+    /// Whether every removal followed the policy
+    pub fn is_success(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// Fail the test with every violation listed
+    ///
+    /// # Panics
+    ///
+    /// Panics if any route was removed against the policy.
+    pub fn assert_success(&self) {
+        let lines: Vec<String> = self
+            .violations
+            .iter()
+            .map(|v| format!("- {}: {}", v.operation, v.reason))
+            .collect();
+        assert!(
+            lines.is_empty(),
+            "{} of {} removed route(s) violate the deprecation policy:\n{}",
+            lines.len(),
+            self.removed,
+            lines.join("\n")
+        );
+    }
+}
+
+impl DeprecationPolicy {
+    /// Require routes to be deprecated for `min_period` before removal
+    pub const fn new(min_period: Duration) -> Self {
+        Self { min_period }
+    }
+
+    /// Check the routes removed between `previous` and `current` OpenAPI
+    /// documents, as of now
+    pub fn check(&self, previous: &Value, current: &Value) -> PolicyReport {
+        self.check_at(previous, current, SystemTime::now())
+    }
+
+    /// Check the routes removed between `previous` and `current` as of `now`
+    pub fn check_at(&self, previous: &Value, current: &Value, now: SystemTime) -> PolicyReport {
+        let current: Vec<String> = changelog::operations(current)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        let mut report = PolicyReport::default();
+        for (key, operation) in changelog::operations(previous) {
+            if current.contains(&key) {
+                continue;
+            }
+            report.removed += 1;
+            if let Err(reason) = self.removable(operation, now) {
+                report.violations.push(PolicyViolation {
+                    operation: key,
+                    reason,
+                });
+            }
+        }
+        report
+    }
+
+    // check that a removed operation was deprecated long enough
+    fn removable(&self, operation: &Value, now: SystemTime) -> Result<(), String> {
+        if !operation["deprecated"].as_bool().unwrap_or(false) {
+            return Err("removed without being deprecated first".to_string());
+        }
+        let since = operation["x-deprecated-since"]
+            .as_u64()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs) + self.min_period);
+        let sunset = operation["x-sunset"]
+            .as_str()
+            .map(|date| {
+                httpdate::parse_http_date(date)
+                    .map_err(|_| format!("invalid sunset date {:?}", date))
+            })
+            .transpose()?;
+        let Some(earliest) = since.max(sunset) else {
+            return Err("deprecated without a `since` or `sunset` date".to_string());
+        };
+        if now < earliest {
+            return Err(format!(
+                "removed before {}, the end of its deprecation period",
+                httpdate::fmt_http_date(earliest)
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::registry::{RouteDef, RouteMetadata};
+
+    inventory::submit! {
+        RouteDef::new("GET", "/deprecation-test/old", concat!(module_path!(), "::", "old_handler"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "old_handler"), |meta| {
+            meta.insert(
+                Deprecated::new()
+                    .since(1_735_689_600)
+                    .sunset("Thu, 01 Jan 2026 00:00:00 GMT")
+                    .link("https://example.com/migrate"),
+            )
+        })
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_headers_and_metric() {
+        assert!(any_deprecated());
+        let metrics = Metrics::new();
+        let router = Router::new()
+            .route("/deprecation-test/old", get(|| async { "old" }))
+            .route("/deprecation-test/new", get(|| async { "new" }))
+            .layer(middleware::from_fn_with_state(
+                Some(metrics.clone()),
+                deprecation_headers,
+            ));
+
+        let request = Request::get("/deprecation-test/old")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["deprecation"], "@1735689600");
+        assert_eq!(
+            response.headers()["sunset"],
+            "Thu, 01 Jan 2026 00:00:00 GMT"
+        );
+        assert_eq!(
+            response.headers()[header::LINK],
+            "<https://example.com/migrate>; rel=\"deprecation\""
+        );
+
+        let request = Request::get("/deprecation-test/new")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert!(!response.headers().contains_key("deprecation"));
+
+        let labels = [("method", "GET"), ("route", "/deprecation-test/old")];
+        assert_eq!(
+            metrics.counter("deprecated_route_requests_total", &labels),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_headers_under_a_prefix() {
+        let router = crate::App::new()
+            .group("/v1", |g| {
+                g.route("/deprecation-test/old", get(|| async { "old" }))
+            })
+            .build();
+        let request = Request::get("/v1/deprecation-test/old")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["deprecation"], "@1735689600");
+    }
+
+    #[test]
+    fn test_deprecation_policy() {
+        let day = 86_400;
+        let previous = serde_json::json!({ "paths": {
+            "/v1/users": { "get": {} },
+            "/v1/orders": { "get": { "deprecated": true, "x-deprecated-since": 100 * day } },
+            "/v1/files": { "get": { "deprecated": true, "x-sunset": "Sat, 01 Mar 1980 00:00:00 GMT" } },
+            "/v1/tags": { "get": { "deprecated": true } },
+            "/v2/users": { "get": {} },
+        }});
+        let current = serde_json::json!({ "paths": { "/v2/users": { "get": {} } } });
+        let policy = DeprecationPolicy::new(Duration::from_secs(30 * day));
+        let at = |days: u64| UNIX_EPOCH + Duration::from_secs(days * day);
+
+        let report = policy.check_at(&previous, &current, at(120));
+        assert_eq!(report.removed, 4);
+        let violations: Vec<_> = report
+            .violations
+            .iter()
+            .map(|v| v.operation.as_str())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "GET /v1/files",
+                "GET /v1/orders",
+                "GET /v1/tags",
+                "GET /v1/users"
+            ]
+        );
+        assert!(report.violations[1].reason.contains("Mon, 11 May 1970"));
+
+        let report = policy.check_at(&previous, &current, at(4000));
+        let violations: Vec<_> = report
+            .violations
+            .iter()
+            .map(|v| v.reason.as_str())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "deprecated without a `since` or `sunset` date",
+                "removed without being deprecated first",
+            ]
+        );
+        assert!(!report.is_success());
+        assert!(policy.check(&previous, &previous).is_success());
+    }
+}
@@ -3,19 +3,50 @@
 //! A simple, type-safe DI container that stores services as Arc-wrapped trait
 //! objects. Services can be registered and retrieved by type, with automatic
 //! Arc wrapping.
+//!
+//! Resolutions can be counted and traced per service type, to find services
+//! resolved on every request that could be resolved once at startup.
+//!
+//! Services built with `register_with` record the services their factory
+//! resolves as dependencies; `graph` lists them, e.g. for the admin
+//! `/services` endpoint.
 
 use std::{
     any::{Any, TypeId},
+    cell::RefCell,
     collections::HashMap,
     sync::Arc,
 };
 
+use crate::{context::RequestContext, metrics::Metrics};
+
 /// Trait that all injectable services must implement
+///
+/// Implement it for a trait object to register services behind an
+/// interface, then resolve them as `Arc<dyn Trait>`:
+///
+/// ```ignore
+/// trait UserRepo: Send + Sync {
+///     fn find(&self, id: u64) -> Option<User>;
+/// }
+/// impl Injectable for dyn UserRepo {}
+///
+/// container.register::<dyn UserRepo>(Arc::new(PgUserRepo::new(pool)));
+/// let repo: Arc<dyn UserRepo> = container.resolve().unwrap();
+/// ```
 pub trait Injectable: Send + Sync + 'static {}
 
 /// Type-erased service storage using Any
+///
+/// Holds an `Arc<T>` behind the `Any`, so unsized services (trait objects)
+/// can be stored and downcast like concrete ones.
 type ServiceBox = Arc<dyn Any + Send + Sync>;
 
+thread_local! {
+    // services resolved by the `register_with` factory running on this thread
+    static RESOLVED: RefCell<Option<Vec<TypeId>>> = const { RefCell::new(None) };
+}
+
 /// Dependency injection container
 ///
 /// Stores services as Arc-wrapped values and provides type-safe retrieval.
@@ -32,6 +63,11 @@ type ServiceBox = Arc<dyn Any + Send + Sync>;
 #[derive(Clone, Default)]
 pub struct Container {
     services: HashMap<TypeId, ServiceBox>,
+    names: HashMap<TypeId, &'static str>,
+    dependencies: HashMap<TypeId, Vec<TypeId>>,
+    tenants: HashMap<(TypeId, String), ServiceBox>,
+    metrics: Option<Metrics>,
+    trace: bool,
 }
 
 impl Container {
@@ -39,32 +75,57 @@ impl Container {
     pub fn new() -> Self {
         Self {
             services: HashMap::new(),
+            names: HashMap::new(),
+            dependencies: HashMap::new(),
+            tenants: HashMap::new(),
+            metrics: None,
+            trace: false,
         }
     }
 
+    /// Count resolutions in `metrics`
+    ///
+    /// Increments `container_resolutions_total`, labelled with the service
+    /// type, the `phase` (`request` inside a request, `startup` otherwise),
+    /// the `scope` (`shared` or `tenant`) and the `outcome` (`hit` or
+    /// `miss`). Services with many `request` resolutions are candidates for
+    /// resolving once and keeping.
+    pub fn record_resolutions(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Open a DEBUG `resolve` span for every resolution (default: false)
+    pub fn trace_resolutions(&mut self, enabled: bool) {
+        self.trace = enabled;
+    }
+
     /// Register a service in the container
     ///
     /// The service must be wrapped in an Arc. If a service of this type
-    /// already exists, it will be replaced.
+    /// already exists, it will be replaced. `T` may be a trait object that
+    /// implements `Injectable`.
     ///
     /// # Example
     ///
     /// ```ignore
     /// container.register(Arc::new(MyService::new()));
+    /// container.register::<dyn UserRepo>(Arc::new(PgUserRepo::new(pool)));
     /// ```
-    pub fn register<T: Injectable>(&mut self, service: Arc<T>) {
+    pub fn register<T: Injectable + ?Sized>(&mut self, service: Arc<T>) {
         let type_id = self.get_type_id::<T>();
         self.insert_service(type_id, service);
     }
 
     // get the TypeId for a given type T
-    fn get_type_id<T: Injectable>(&self) -> TypeId {
+    fn get_type_id<T: Injectable + ?Sized>(&self) -> TypeId {
         TypeId::of::<T>()
     }
 
     // insert a service into the storage map
-    fn insert_service<T: Injectable>(&mut self, type_id: TypeId, service: Arc<T>) {
-        self.services.insert(type_id, service as ServiceBox);
+    fn insert_service<T: Injectable + ?Sized>(&mut self, type_id: TypeId, service: Arc<T>) {
+        self.services
+            .insert(type_id, Arc::new(service) as ServiceBox);
+        self.names.insert(type_id, std::any::type_name::<T>());
     }
 
     /// Register a service from a constructor function
@@ -84,6 +145,44 @@ impl Container {
         self.register(service);
     }
 
+    /// Register a service built from services already in the container
+    ///
+    /// Every service the factory resolves is recorded as a dependency of
+    /// `T` in the `graph`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// container.register_with(|c| UserService::new(c.resolve_or_panic::<dyn UserRepo>()));
+    /// ```
+    pub fn register_with<T: Injectable, F>(&mut self, factory: F)
+    where
+        F: FnOnce(&Container) -> T,
+    {
+        let outer = RESOLVED.with(|resolved| resolved.borrow_mut().replace(Vec::new()));
+        let service = factory(self);
+        let dependencies =
+            RESOLVED.with(|resolved| std::mem::replace(&mut *resolved.borrow_mut(), outer));
+        self.register(Arc::new(service));
+        self.add_dependencies(TypeId::of::<T>(), dependencies.unwrap_or_default());
+    }
+
+    /// Record that `T` depends on `D`, for services registered with
+    /// `register`
+    pub fn depends_on<T: Injectable + ?Sized, D: Injectable + ?Sized>(&mut self) {
+        self.add_dependencies(TypeId::of::<T>(), vec![TypeId::of::<D>()]);
+    }
+
+    // add dependency edges of a service, keeping each once
+    fn add_dependencies(&mut self, service: TypeId, dependencies: Vec<TypeId>) {
+        let edges = self.dependencies.entry(service).or_default();
+        for dependency in dependencies {
+            if dependency != service && !edges.contains(&dependency) {
+                edges.push(dependency);
+            }
+        }
+    }
+
     // create a service instance from a factory function
     fn create_service<T: Injectable, F>(&self, factory: F) -> Arc<T>
     where
@@ -101,21 +200,92 @@ impl Container {
     /// ```ignore
     /// let service: Arc<MyService> = container.resolve().unwrap();
     /// ```
-    pub fn resolve<T: Injectable>(&self) -> Option<Arc<T>> {
-        let type_id = self.get_type_id::<T>();
-        self.lookup_service(type_id)
+    pub fn resolve<T: Injectable + ?Sized>(&self) -> Option<Arc<T>> {
+        let _span = self.resolve_span::<T>();
+        let service = self.lookup_service(self.get_type_id::<T>());
+        self.record::<T>("shared", service.is_some());
+        service
     }
 
     // lookup a service by TypeId and downcast it
-    fn lookup_service<T: Injectable>(&self, type_id: TypeId) -> Option<Arc<T>> {
+    fn lookup_service<T: Injectable + ?Sized>(&self, type_id: TypeId) -> Option<Arc<T>> {
+        RESOLVED.with(|resolved| {
+            if let Some(resolved) = resolved.borrow_mut().as_mut() {
+                resolved.push(type_id);
+            }
+        });
         self.services
             .get(&type_id)
             .and_then(|boxed| self.downcast_service(boxed))
     }
 
+    // span around a resolution, when tracing is enabled
+    fn resolve_span<T: ?Sized>(&self) -> Option<tracing::span::EnteredSpan> {
+        self.trace.then(|| {
+            tracing::debug_span!("resolve", service = std::any::type_name::<T>()).entered()
+        })
+    }
+
+    // count a resolution, when metrics are enabled
+    fn record<T: ?Sized>(&self, scope: &str, found: bool) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        let phase = match RequestContext::current() {
+            Some(_) => "request",
+            None => "startup",
+        };
+        metrics.increment(
+            "container_resolutions_total",
+            &[
+                ("service", std::any::type_name::<T>()),
+                ("phase", phase),
+                ("scope", scope),
+                ("outcome", if found { "hit" } else { "miss" }),
+            ],
+        );
+    }
+
     // downcast a type-erased service to the concrete type
-    fn downcast_service<T: Injectable>(&self, boxed: &ServiceBox) -> Option<Arc<T>> {
-        boxed.clone().downcast::<T>().ok()
+    fn downcast_service<T: Injectable + ?Sized>(&self, boxed: &ServiceBox) -> Option<Arc<T>> {
+        boxed.downcast_ref::<Arc<T>>().cloned()
+    }
+
+    /// Register a service for one tenant
+    ///
+    /// While a request of `tenant` is handled, `Inject<T>` resolves this
+    /// instance instead of the one registered with `register`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// container.register(Arc::new(shared_pool));
+    /// container.register_for_tenant("acme", Arc::new(acme_pool));
+    /// ```
+    pub fn register_for_tenant<T: Injectable + ?Sized>(&mut self, tenant: &str, service: Arc<T>) {
+        let key = (self.get_type_id::<T>(), tenant.to_string());
+        self.tenants.insert(key, Arc::new(service) as ServiceBox);
+        self.names
+            .entry(self.get_type_id::<T>())
+            .or_insert(std::any::type_name::<T>());
+    }
+
+    /// Resolve the service registered for `tenant`, falling back to the
+    /// shared one
+    pub fn resolve_for_tenant<T: Injectable + ?Sized>(&self, tenant: &str) -> Option<Arc<T>> {
+        let _span = self.resolve_span::<T>();
+        let key = (self.get_type_id::<T>(), tenant.to_string());
+        if let Some(service) = self
+            .tenants
+            .get(&key)
+            .and_then(|boxed| self.downcast_service(boxed))
+        {
+            self.record::<T>("tenant", true);
+            return Some(service);
+        }
+        let service = self.lookup_service(self.get_type_id::<T>());
+        self.record::<T>("shared", service.is_some());
+        service
     }
 
     /// Resolve a service or panic if not found
@@ -123,13 +293,13 @@ impl Container {
     /// # Panics
     ///
     /// Panics if the service hasn't been registered.
-    pub fn resolve_or_panic<T: Injectable>(&self) -> Arc<T> {
+    pub fn resolve_or_panic<T: Injectable + ?Sized>(&self) -> Arc<T> {
         self.resolve()
             .unwrap_or_else(|| panic!("Service {} not registered", std::any::type_name::<T>()))
     }
 
     /// Check if a service is registered
-    pub fn contains<T: Injectable>(&self) -> bool {
+    pub fn contains<T: Injectable + ?Sized>(&self) -> bool {
         let type_id = TypeId::of::<T>();
         self.services.contains_key(&type_id)
     }
@@ -144,9 +314,74 @@ impl Container {
         self.services.is_empty()
     }
 
+    /// Type names of the registered services, sorted
+    pub fn service_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.names.values().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Registered services with the type names of their dependencies, sorted
+    ///
+    /// Dependencies are known for services built with `register_with` or
+    /// declared with `depends_on`.
+    pub fn graph(&self) -> Vec<(&'static str, Vec<&'static str>)> {
+        let name = |type_id: &TypeId| self.names.get(type_id).copied().unwrap_or("<unregistered>");
+        let mut graph: Vec<_> = self
+            .names
+            .iter()
+            .map(|(type_id, service)| {
+                let mut dependencies: Vec<_> = self
+                    .dependencies
+                    .get(type_id)
+                    .into_iter()
+                    .flatten()
+                    .map(name)
+                    .collect();
+                dependencies.sort_unstable();
+                (*service, dependencies)
+            })
+            .collect();
+        graph.sort_unstable();
+        graph
+    }
+
+    /// Copy every service from another container that is not registered here
+    ///
+    /// Services already registered in this container are kept as-is. The
+    /// copied services are shared (the same `Arc`), not re-created.
+    /// Resolution metrics and tracing are inherited unless set here.
+    pub fn inherit_from(&mut self, other: &Container) {
+        if self.metrics.is_none() {
+            self.metrics = other.metrics.clone();
+        }
+        self.trace |= other.trace;
+        for (type_id, service) in &other.services {
+            self.services
+                .entry(*type_id)
+                .or_insert_with(|| service.clone());
+        }
+        for (type_id, name) in &other.names {
+            self.names.entry(*type_id).or_insert(name);
+        }
+        for (type_id, dependencies) in &other.dependencies {
+            self.dependencies
+                .entry(*type_id)
+                .or_insert_with(|| dependencies.clone());
+        }
+        for (key, service) in &other.tenants {
+            self.tenants
+                .entry(key.clone())
+                .or_insert_with(|| service.clone());
+        }
+    }
+
     /// Clear all services from the container
     pub fn clear(&mut self) {
         self.services.clear();
+        self.names.clear();
+        self.dependencies.clear();
+        self.tenants.clear();
     }
 }
 
@@ -191,6 +426,29 @@ mod tests {
         assert_eq!(resolved.connection_string, "postgres://localhost");
     }
 
+    trait Repository: Send + Sync {
+        fn name(&self) -> &str;
+    }
+
+    impl Injectable for dyn Repository {}
+
+    impl Repository for MockDatabase {
+        fn name(&self) -> &str {
+            &self.connection_string
+        }
+    }
+
+    #[test]
+    fn test_register_trait_object() {
+        let mut container = Container::new();
+        container.register::<dyn Repository>(Arc::new(MockDatabase::new("users")));
+
+        let repo: Arc<dyn Repository> = container.resolve().unwrap();
+        assert_eq!(repo.name(), "users");
+        assert!(container.contains::<dyn Repository>());
+        assert!(!container.contains::<MockDatabase>());
+    }
+
     #[test]
     fn test_register_factory() {
         let mut container = Container::new();
@@ -247,6 +505,67 @@ mod tests {
         assert!(container.contains::<MockDatabase>());
     }
 
+    #[test]
+    fn test_service_names() {
+        let mut container = Container::new();
+        container.register(Arc::new(MockDatabase::new("db")));
+        assert_eq!(container.service_names().len(), 1);
+        assert!(container.service_names()[0].ends_with("MockDatabase"));
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let mut container = Container::new();
+        container.register(Arc::new(MockDatabase::new("db")));
+        container.register_with(|c| MockUserService::new(c.resolve_or_panic()));
+        assert_eq!(
+            container
+                .resolve::<MockUserService>()
+                .unwrap()
+                .db
+                .connection_string,
+            "db"
+        );
+
+        let graph = container.graph();
+        assert_eq!(graph.len(), 2);
+        let (name, dependencies) = graph
+            .iter()
+            .find(|(name, _)| name.ends_with("MockUserService"))
+            .unwrap();
+        assert!(name.ends_with("MockUserService"));
+        assert_eq!(dependencies.len(), 1);
+        assert!(dependencies[0].ends_with("MockDatabase"));
+        // resolutions outside a factory record nothing
+        let _ = container.resolve::<MockDatabase>();
+        let (_, dependencies) = graph
+            .iter()
+            .find(|(name, _)| name.ends_with("MockDatabase"))
+            .unwrap();
+        assert!(dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_inherit_from() {
+        let mut parent = Container::new();
+        parent.register_factory(|| MockDatabase::new("parent"));
+
+        let mut child = Container::new();
+        child.register_factory(|| MockDatabase::new("child"));
+        child.inherit_from(&parent);
+        assert_eq!(
+            child.resolve::<MockDatabase>().unwrap().connection_string,
+            "child"
+        );
+
+        let mut empty = Container::new();
+        empty.inherit_from(&parent);
+        assert_eq!(
+            empty.resolve::<MockDatabase>().unwrap().connection_string,
+            "parent"
+        );
+    }
+
     #[test]
     fn test_len_and_clear() {
         let mut container = Container::new();
@@ -261,4 +580,69 @@ mod tests {
         assert_eq!(container.len(), 0);
         assert!(container.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_resolution_metrics() {
+        let metrics = Metrics::new();
+        let mut container = Container::new();
+        container.record_resolutions(metrics.clone());
+        container.trace_resolutions(true);
+        container.register(Arc::new(MockDatabase::new("shared")));
+        container.register_for_tenant("acme", Arc::new(MockDatabase::new("acme")));
+
+        container.resolve::<MockDatabase>();
+        container.resolve::<MockUserService>();
+        RequestContext::new("req-1")
+            .scope(async {
+                container.resolve::<MockDatabase>();
+                container.resolve_for_tenant::<MockDatabase>("acme");
+                container.resolve_for_tenant::<MockDatabase>("other");
+            })
+            .await;
+
+        let database = std::any::type_name::<MockDatabase>();
+        let count = |phase, scope, outcome| {
+            metrics.counter(
+                "container_resolutions_total",
+                &[
+                    ("service", database),
+                    ("phase", phase),
+                    ("scope", scope),
+                    ("outcome", outcome),
+                ],
+            )
+        };
+        assert_eq!(count("startup", "shared", "hit"), 1);
+        assert_eq!(count("request", "shared", "hit"), 2);
+        assert_eq!(count("request", "tenant", "hit"), 1);
+        let labels = [
+            ("service", std::any::type_name::<MockUserService>()),
+            ("phase", "startup"),
+            ("scope", "shared"),
+            ("outcome", "miss"),
+        ];
+        assert_eq!(metrics.counter("container_resolutions_total", &labels), 1);
+    }
+
+    #[test]
+    fn test_tenant_services_override_shared() {
+        let mut container = Container::new();
+        container.register(Arc::new(MockDatabase::new("shared")));
+        container.register_for_tenant("acme", Arc::new(MockDatabase::new("acme")));
+
+        let resolve = |tenant| {
+            container
+                .resolve_for_tenant::<MockDatabase>(tenant)
+                .unwrap()
+        };
+        assert_eq!(resolve("acme").connection_string, "acme");
+        assert_eq!(resolve("globex").connection_string, "shared");
+        assert_eq!(
+            container
+                .resolve::<MockDatabase>()
+                .unwrap()
+                .connection_string,
+            "shared"
+        );
+    }
 }
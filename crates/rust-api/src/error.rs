@@ -1,11 +1,42 @@
 //! Error types for rust-api framework
 
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    error::Error as StdError,
+    fmt,
+    sync::Arc,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
 use thiserror::Error;
 
 /// Result type alias for rust-api operations
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Result type alias for handlers, e.g. `ApiResult<Json<User>>`
+///
+/// Anything convertible into `ApiError` (the core `Error`, declared error
+/// codes, enabled library errors) can be propagated with `?`.
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
 /// Main error type for the rust-api framework
+///
+/// Can be returned from handlers: it renders as a 500 `ApiError` whose
+/// message is the generic status reason. The error itself is kept as the
+/// cause, so it is logged, or shown in the body with `App::debug_errors`.
+///
+/// # Example
+///
+/// ```ignore
+/// async fn report(Inject(reports): Inject<Reports>) -> rust_api::Result<Json<Report>> {
+///     Ok(Json(reports.latest().await?))
+/// }
+/// ```
 #[derive(Error, Debug)]
 pub enum Error {
     /// Service not found in the DI container
@@ -55,3 +86,571 @@ impl Error {
         Self::Other(msg.into())
     }
 }
+
+tokio::task_local! {
+    // capture backtraces even when RUST_BACKTRACE / RUST_LIB_BACKTRACE are
+    // unset; set for requests of apps built with `debug_errors`
+    pub(crate) static FORCE_BACKTRACES: bool;
+}
+
+/// HTTP error returned from handlers, guards and extractors
+///
+/// Renders as a JSON envelope with the status, a machine-readable code and a
+/// human-readable message:
+///
+/// ```json
+/// { "error": { "status": 404, "code": "not_found", "message": "User not found" } }
+/// ```
+///
+/// # Example
+///
+/// ```ignore
+/// async fn get_user(Path(id): Path<String>) -> Result<Json<User>, ApiError> {
+///     let user = repo.find(&id).ok_or_else(|| ApiError::not_found("User not found"))?;
+///     Ok(Json(user))
+/// }
+/// ```
+#[derive(Debug, Clone, Error)]
+#[error("{status}: {message}")]
+pub struct ApiError {
+    status: StatusCode,
+    code: String,
+    message: String,
+    details: Option<Value>,
+    docs: Option<String>,
+    #[source]
+    cause: Option<Arc<Cause>>,
+    rejection: bool,
+}
+
+// underlying error of an `ApiError` and where it was recorded; shared to keep
+// `ApiError` small and cheap to clone
+#[derive(Debug)]
+struct Cause {
+    error: Box<dyn StdError + Send + Sync>,
+    trace: Option<Backtrace>,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl StdError for Cause {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+impl ApiError {
+    /// Create an error with the given status and message
+    ///
+    /// The code defaults to the status reason in snake case, e.g.
+    /// `not_found`.
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: Self::default_code(status),
+            message: message.into(),
+            details: None,
+            docs: None,
+            cause: None,
+            rejection: false,
+        }
+    }
+
+    /// Create a 400 Bad Request error
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    /// Create a 401 Unauthorized error
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    /// Create a 403 Forbidden error
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    /// Create a 404 Not Found error
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    /// Create a 409 Conflict error
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, message)
+    }
+
+    /// Create a 422 Unprocessable Entity error
+    pub fn unprocessable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, message)
+    }
+
+    /// Create a 500 Internal Server Error
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// Replace the machine-readable error code
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = code.into();
+        self
+    }
+
+    /// Attach structured details to the error body
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Mark the error as an extractor rejection
+    ///
+    /// Only rejections are passed to the handler installed with
+    /// `App::on_rejection`; the framework's extractors mark theirs, and
+    /// custom extractors can do the same.
+    pub fn rejection(mut self) -> Self {
+        self.rejection = true;
+        self
+    }
+
+    /// Whether the error was raised by an extractor
+    pub fn is_rejection(&self) -> bool {
+        self.rejection
+    }
+
+    /// Link to documentation of the error, rendered as `docs`
+    pub fn with_docs(mut self, url: impl Into<String>) -> Self {
+        self.docs = Some(url.into());
+        self
+    }
+
+    /// Record the error that caused this one and capture a backtrace
+    ///
+    /// The cause and its `source()` chain are never part of the standard
+    /// envelope: they are logged for 5xx responses, or included in the body
+    /// with `App::debug_errors`. The backtrace is captured when enabled by
+    /// `RUST_BACKTRACE` / `RUST_LIB_BACKTRACE` or `App::debug_errors`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let user = repo.find(id).await.map_err(|e| ApiError::internal("Lookup failed").with_source(e))?;
+    /// ```
+    pub fn with_source(mut self, source: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        let trace = if FORCE_BACKTRACES.try_with(|force| *force).unwrap_or(false) {
+            Backtrace::force_capture()
+        } else {
+            Backtrace::capture()
+        };
+        self.cause = Some(Arc::new(Cause {
+            error: source.into(),
+            trace: (trace.status() == BacktraceStatus::Captured).then_some(trace),
+        }));
+        self
+    }
+
+    /// Messages of the cause and its `source()` chain, outermost first
+    pub fn chain(&self) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut next = self
+            .cause
+            .as_deref()
+            .map(|e| e as &(dyn StdError + 'static));
+        while let Some(error) = next {
+            chain.push(error.to_string());
+            next = error.source();
+        }
+        chain
+    }
+
+    /// Backtrace captured by `with_source`, if any
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.cause.as_deref()?.trace.as_ref()
+    }
+
+    /// HTTP status of the error
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Machine-readable error code
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Human-readable error message
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Structured details, if any
+    pub fn details(&self) -> Option<&Value> {
+        self.details.as_ref()
+    }
+
+    /// JSON envelope rendered as the response body
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "status": self.status.as_u16(),
+            "code": self.code,
+            "message": self.message,
+        });
+        if let Some(details) = &self.details {
+            error["details"] = details.clone();
+        }
+        if let Some(docs) = &self.docs {
+            error["docs"] = json!(docs);
+        }
+        json!({ "error": error })
+    }
+
+    // derive a snake_case code from the status reason phrase
+    fn default_code(status: StatusCode) -> String {
+        status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace([' ', '-'], "_")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.to_json())).into_response();
+        // kept for middleware that rewrites error bodies
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Turn a missing value into a 404 `ApiError`
+///
+/// # Example
+///
+/// ```ignore
+/// async fn get_user(Path(id): Path<u64>) -> ApiResult<Json<User>> {
+///     let user = repo.find(id).await?.or_not_found("user")?;
+///     Ok(Json(user))
+/// }
+/// ```
+pub trait OptionExt<T> {
+    /// The value, or 404 "{Resource} not found" with code `not_found`
+    fn or_not_found(self, resource: &str) -> ApiResult<T>;
+}
+
+impl<T> OptionExt<T> for Option<T> {
+    fn or_not_found(self, resource: &str) -> ApiResult<T> {
+        self.ok_or_else(|| {
+            let mut chars = resource.chars();
+            let resource = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::from("Resource"),
+            };
+            ApiError::not_found(format!("{} not found", resource))
+        })
+    }
+}
+
+/// Turn any error into a 500 `ApiError` that keeps it as the cause
+///
+/// # Example
+///
+/// ```ignore
+/// let bytes = tokio::fs::read(&path).await.or_internal("Cannot read report")?;
+/// ```
+pub trait ResultExt<T> {
+    /// The value, or 500 with `message` and the error as its cause
+    fn or_internal(self, message: &str) -> ApiResult<T>;
+}
+
+impl<T, E> ResultExt<T> for std::result::Result<T, E>
+where
+    E: Into<Box<dyn StdError + Send + Sync>>,
+{
+    fn or_internal(self, message: &str) -> ApiResult<T> {
+        self.map_err(|e| ApiError::internal(message).with_source(e))
+    }
+}
+
+// 500 with the message redacted; the error is kept as the cause
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        ApiError::internal("Internal Server Error").with_source(error)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        ApiError::from(self).into_response()
+    }
+}
+
+// Conversions from common library errors, so `?` works in handlers. Server
+// side failures keep the library error as the cause, which is logged rather
+// than rendered.
+
+// 404 for missing rows, 409 for constraint violations, 503 when the pool is
+// exhausted
+#[cfg(feature = "sqlx")]
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        use sqlx::error::ErrorKind;
+
+        let api_error = match &error {
+            sqlx::Error::RowNotFound => ApiError::not_found("Resource not found"),
+            sqlx::Error::PoolTimedOut => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable")
+            }
+            sqlx::Error::Database(db) => match db.kind() {
+                ErrorKind::UniqueViolation => {
+                    ApiError::conflict("Conflicts with an existing resource")
+                }
+                ErrorKind::ForeignKeyViolation => {
+                    ApiError::conflict("References a missing or in-use resource")
+                }
+                ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                    ApiError::unprocessable("Violates a data constraint")
+                }
+                _ => ApiError::internal("Database error"),
+            },
+            _ => ApiError::internal("Database error"),
+        };
+        api_error.with_source(error)
+    }
+}
+
+// 422 `validation_failed` with the messages of each field, by dotted path
+#[cfg(feature = "validator")]
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(errors: validator::ValidationErrors) -> Self {
+        fn collect(
+            errors: &validator::ValidationErrors,
+            prefix: &str,
+            fields: &mut serde_json::Map<String, Value>,
+        ) {
+            use validator::ValidationErrorsKind;
+
+            for (field, kind) in errors.errors() {
+                let path = match prefix {
+                    "" => field.to_string(),
+                    _ => format!("{}.{}", prefix, field),
+                };
+                match kind {
+                    ValidationErrorsKind::Field(list) => {
+                        let messages: Vec<String> = list
+                            .iter()
+                            .map(|e| e.message.as_deref().unwrap_or(&e.code).to_string())
+                            .collect();
+                        fields.insert(path, json!(messages));
+                    }
+                    ValidationErrorsKind::Struct(nested) => collect(nested, &path, fields),
+                    ValidationErrorsKind::List(items) => {
+                        for (index, nested) in items {
+                            collect(nested, &format!("{}[{}]", path, index), fields);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut fields = serde_json::Map::new();
+        collect(&errors, "", &mut fields);
+        ApiError::unprocessable("Validation failed")
+            .with_code("validation_failed")
+            .with_details(json!({ "fields": fields }))
+    }
+}
+
+// failures of upstream calls: 504 on timeouts, 502 otherwise
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for ApiError {
+    fn from(error: reqwest::Error) -> Self {
+        let api_error = if error.is_timeout() {
+            ApiError::new(StatusCode::GATEWAY_TIMEOUT, "Upstream service timed out")
+        } else if error.is_builder() {
+            ApiError::internal("Invalid upstream request")
+        } else {
+            ApiError::new(StatusCode::BAD_GATEWAY, "Upstream service failed")
+        };
+        api_error.with_source(error)
+    }
+}
+
+// failures of `HttpClient` calls: 504 on timeouts, 502 otherwise
+impl From<crate::client::ClientError> for ApiError {
+    fn from(error: crate::client::ClientError) -> Self {
+        let api_error = match error {
+            crate::client::ClientError::Timeout => {
+                ApiError::new(StatusCode::GATEWAY_TIMEOUT, "Upstream service timed out")
+            }
+            crate::client::ClientError::Saturated(_) => ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Upstream service is overloaded, try again later",
+            )
+            .with_code("bulkhead_full"),
+            crate::client::ClientError::TooLarge(_) => {
+                ApiError::new(StatusCode::BAD_GATEWAY, "Upstream response too large")
+                    .with_code("upstream_response_too_large")
+            }
+            _ => ApiError::new(StatusCode::BAD_GATEWAY, "Upstream service failed"),
+        };
+        api_error.with_source(error)
+    }
+}
+
+// 401 for tokens that fail verification, 500 for key and crypto problems
+#[cfg(feature = "jsonwebtoken")]
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(error: jsonwebtoken::errors::Error) -> Self {
+        use jsonwebtoken::errors::ErrorKind;
+
+        match error.kind() {
+            ErrorKind::ExpiredSignature => {
+                ApiError::unauthorized("Token expired").with_code("token_expired")
+            }
+            ErrorKind::InvalidEcdsaKey
+            | ErrorKind::InvalidRsaKey(_)
+            | ErrorKind::RsaFailedSigning
+            | ErrorKind::InvalidKeyFormat
+            | ErrorKind::InvalidAlgorithmName
+            | ErrorKind::Crypto(_) => {
+                ApiError::internal("Token processing failed").with_source(error)
+            }
+            _ => ApiError::unauthorized("Invalid token").with_code("invalid_token"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_api_error_default_code() {
+        let err = ApiError::not_found("User not found");
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+        assert_eq!(err.code(), "not_found");
+        assert_eq!(err.message(), "User not found");
+    }
+
+    #[test]
+    fn test_api_error_source_chain() {
+        #[derive(Debug, Error)]
+        #[error("query failed")]
+        struct QueryError(#[source] std::io::Error);
+
+        let err = ApiError::internal("Lookup failed")
+            .with_source(QueryError(std::io::Error::other("connection reset")));
+        assert_eq!(err.chain(), ["query failed", "connection reset"]);
+        assert_eq!(err.source().unwrap().to_string(), "query failed");
+        assert!(err.to_json()["error"].get("debug").is_none());
+        assert!(ApiError::internal("x").chain().is_empty());
+    }
+
+    #[test]
+    fn test_core_error_into_response() {
+        let err = ApiError::from(Error::service_not_found("Mailer"));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.message(), "Internal Server Error");
+        assert_eq!(err.chain(), ["Service not found: Mailer"]);
+
+        let response = Error::other("boom").into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.extensions().get::<ApiError>().is_some());
+    }
+
+    #[test]
+    fn test_result_combinators() {
+        let err = None::<u32>.or_not_found("user").unwrap_err();
+        assert_eq!(
+            (err.status(), err.message()),
+            (StatusCode::NOT_FOUND, "User not found")
+        );
+        assert_eq!(Some(7).or_not_found("user").unwrap(), 7);
+
+        let failed: std::result::Result<(), _> = Err(std::io::Error::other("disk full"));
+        let err = failed.or_internal("Cannot save").unwrap_err();
+        assert_eq!(err.message(), "Cannot save");
+        assert_eq!(err.chain(), ["disk full"]);
+    }
+
+    #[cfg(feature = "validator")]
+    #[test]
+    fn test_from_validation_errors() {
+        let mut address = validator::ValidationErrors::new();
+        address.add("zip", validator::ValidationError::new("length"));
+        let mut errors = validator::ValidationErrors::new();
+        errors.add(
+            "email",
+            validator::ValidationError::new("email").with_message("must be an email".into()),
+        );
+        errors.errors_mut().insert(
+            "address".into(),
+            validator::ValidationErrorsKind::Struct(Box::new(address)),
+        );
+
+        let err = ApiError::from(errors);
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            err.details().unwrap(),
+            &json!({ "fields": { "email": ["must be an email"], "address.zip": ["length"] } })
+        );
+    }
+
+    #[cfg(feature = "jsonwebtoken")]
+    #[test]
+    fn test_from_jwt_errors() {
+        use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+
+        let err = ApiError::from(JwtError::from(ErrorKind::ExpiredSignature));
+        assert_eq!(
+            (err.status(), err.code()),
+            (StatusCode::UNAUTHORIZED, "token_expired")
+        );
+        let err = ApiError::from(JwtError::from(ErrorKind::InvalidSignature));
+        assert_eq!(err.code(), "invalid_token");
+        let err = ApiError::from(JwtError::from(ErrorKind::InvalidKeyFormat));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[cfg(feature = "sqlx")]
+    #[test]
+    fn test_from_sqlx_errors() {
+        assert_eq!(
+            ApiError::from(sqlx::Error::RowNotFound).status(),
+            StatusCode::NOT_FOUND
+        );
+        let err = ApiError::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(err.chain()[0].contains("pool timed out"));
+    }
+
+    #[test]
+    fn test_api_error_json_envelope() {
+        let err = ApiError::unprocessable("Invalid body")
+            .with_code("validation_failed")
+            .with_details(json!({ "field": "email" }));
+
+        assert_eq!(
+            err.to_json(),
+            json!({
+                "error": {
+                    "status": 422,
+                    "code": "validation_failed",
+                    "message": "Invalid body",
+                    "details": { "field": "email" },
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_api_error_into_response() {
+        let response = ApiError::forbidden("nope").into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,629 @@
+//! Event bus for RustAPI framework
+//!
+//! `EventBus` delivers typed events to every subscriber of their topic.
+//! Events are serialized with serde (JSON), so the same events can travel
+//! between app instances through a `Transport`; with the `redis` feature,
+//! `RedisTransport` fans them out over a Redis stream.
+//!
+//! # Delivery
+//!
+//! Within one instance, delivery is at-most-once: a subscriber that falls
+//! more than the channel capacity behind skips the oldest events, with a
+//! warning. Across instances, the Redis transport is at-least-once while the
+//! stream retains the events: a subscriber that loses its connection resumes
+//! after the last event it saw, and an event may be seen twice if delivery
+//! is interrupted, so handlers must be idempotent. Events published with a
+//! message id, such as the outbox's, carry it to subscribers of
+//! `Subscription::recv_delivery`, so they can drop the ones already handled.
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{broadcast, oneshot};
+
+use crate::{
+    di::Injectable,
+    error::{Error, Result},
+    ids::{IdGenerator, UuidV7},
+    lifecycle::BoxFuture,
+};
+
+/// Events buffered per topic for slow subscribers
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Longest wait between transport reconnection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// An event published on the bus
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Clone, Serialize, Deserialize)]
+/// struct UserCreated {
+///     id: String,
+/// }
+///
+/// impl Event for UserCreated {
+///     const TOPIC: &'static str = "users.created";
+/// }
+/// ```
+pub trait Event: Serialize + DeserializeOwned + Send + 'static {
+    /// Topic the event is published on, shared by all instances
+    const TOPIC: &'static str;
+}
+
+/// An event in transit between instances
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// Topic of the event
+    pub topic: String,
+    /// Id of the instance that published it
+    pub origin: String,
+    /// Id of the message, for events published with one
+    pub id: Option<String>,
+    /// The event, serialized as JSON
+    pub payload: Vec<u8>,
+}
+
+/// An event received with its message id
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery<E> {
+    /// Id of the message, for events published with one; the same for every
+    /// redelivery of the message
+    pub id: Option<String>,
+    /// The event
+    pub event: E,
+}
+
+// a serialized event handed to local subscribers
+#[derive(Clone)]
+struct Message {
+    id: Option<Arc<str>>,
+    payload: Arc<[u8]>,
+}
+
+/// Carries events between app instances
+pub trait Transport: Send + Sync + 'static {
+    /// Send an event to all instances
+    fn publish<'a>(&'a self, envelope: &'a Envelope) -> BoxFuture<'a, Result<()>>;
+
+    /// Receive events from all instances, including this one, until the
+    /// connection fails
+    ///
+    /// Called again after an error, with backoff; implementations should
+    /// resume after the last event they delivered.
+    fn listen<'a>(
+        &'a self,
+        deliver: &'a (dyn Fn(Envelope) + Send + Sync),
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+// state shared by the clones of a bus
+struct Inner {
+    instance: Mutex<String>,
+    capacity: usize,
+    topics: Mutex<HashMap<String, broadcast::Sender<Message>>>,
+    transport: Option<Arc<dyn Transport>>,
+    // dropped with the bus, stopping the listener task
+    _stop: Option<oneshot::Sender<()>>,
+}
+
+impl Inner {
+    // id tagging the events this instance sends
+    fn instance(&self) -> String {
+        self.instance
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // the channel of `topic`, created on first use
+    fn channel(&self, topic: &str) -> broadcast::Sender<Message> {
+        self.topics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(topic.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .clone()
+    }
+
+    // hand a serialized event to the local subscribers
+    fn deliver(&self, topic: &str, message: Message) {
+        let sender = self
+            .topics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(topic)
+            .cloned();
+        if let Some(sender) = sender {
+            // no receivers is fine
+            let _ = sender.send(message);
+        }
+    }
+}
+
+/// Publish/subscribe bus for typed events
+///
+/// Cheap to clone; clones share subscribers. Register it in the container
+/// and inject it into services.
+///
+/// # Example
+///
+/// ```ignore
+/// let bus = EventBus::with_transport(Arc::new(RedisTransport::connect("redis://redis:6379", "events").await?));
+///
+/// let mut created = bus.subscribe::<UserCreated>();
+/// tokio::spawn(async move {
+///     while let Some(event) = created.recv().await {
+///         send_welcome_mail(&event.id).await;
+///     }
+/// });
+///
+/// bus.publish(&UserCreated { id: user.id.clone() }).await?;
+/// ```
+#[derive(Clone)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+impl Injectable for EventBus {}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// Create a bus delivering events within this process
+    pub fn new() -> Self {
+        Self::build(None, None, DEFAULT_CAPACITY)
+    }
+
+    /// Create a bus delivering events to every instance through `transport`
+    ///
+    /// Spawns the task receiving events from other instances, so it must be
+    /// called within a Tokio runtime. The task reconnects with backoff when
+    /// the transport fails and stops when the last clone of the bus is
+    /// dropped.
+    pub fn with_transport(transport: Arc<dyn Transport>) -> Self {
+        let (stop, stopped) = oneshot::channel();
+        let bus = Self::build(Some(transport.clone()), Some(stop), DEFAULT_CAPACITY);
+        let listener = listen(Arc::downgrade(&bus.inner), transport);
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = stopped => {}
+                _ = listener => {}
+            }
+        });
+        bus
+    }
+
+    // create a bus with its shared state
+    fn build(
+        transport: Option<Arc<dyn Transport>>,
+        stop: Option<oneshot::Sender<()>>,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                instance: Mutex::new(UuidV7.generate()),
+                capacity,
+                topics: Mutex::new(HashMap::new()),
+                transport,
+                _stop: stop,
+            }),
+        }
+    }
+
+    /// Name this instance with an id from `generator` instead of a UUID v7
+    ///
+    /// The id tags the events sent through the transport, so the bus can
+    /// skip its own when they come back; pass the app's `IdGenerator`.
+    pub fn id_generator(self, generator: Arc<dyn IdGenerator>) -> Self {
+        *self
+            .inner
+            .instance
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = generator.generate();
+        self
+    }
+
+    /// Publish `event` to the subscribers of its topic
+    ///
+    /// Local subscribers receive it immediately; an error means it could not
+    /// be handed to the transport for the other instances.
+    pub async fn publish<E: Event>(&self, event: &E) -> Result<()> {
+        let payload = serde_json::to_vec(event)
+            .map_err(|e| Error::other(format!("Failed to serialize {}: {}", E::TOPIC, e)))?;
+        self.publish_raw(E::TOPIC, None, payload).await
+    }
+
+    /// Publish an event already serialized as JSON on `topic`, with the
+    /// message `id` if it has one
+    ///
+    /// For events stored before publishing, such as the outbox's, whose id
+    /// lets subscribers drop redeliveries.
+    pub async fn publish_raw(&self, topic: &str, id: Option<&str>, payload: Vec<u8>) -> Result<()> {
+        let message = Message {
+            id: id.map(Arc::from),
+            payload: payload.as_slice().into(),
+        };
+        self.inner.deliver(topic, message);
+        if let Some(transport) = &self.inner.transport {
+            let envelope = Envelope {
+                topic: topic.to_string(),
+                origin: self.inner.instance(),
+                id: id.map(str::to_string),
+                payload,
+            };
+            transport.publish(&envelope).await?;
+        }
+        Ok(())
+    }
+
+    /// Receive the events of type `E` published from now on
+    pub fn subscribe<E: Event>(&self) -> Subscription<E> {
+        Subscription {
+            receiver: self.inner.channel(E::TOPIC).subscribe(),
+            event: PhantomData,
+        }
+    }
+}
+
+// receive events from other instances while the bus is alive
+async fn listen(bus: Weak<Inner>, transport: Arc<dyn Transport>) {
+    let deliver = move |envelope: Envelope| {
+        if let Some(bus) = bus.upgrade() {
+            // events from this instance were delivered on publish
+            if envelope.origin != bus.instance() {
+                let message = Message {
+                    id: envelope.id.map(Arc::from),
+                    payload: envelope.payload.into(),
+                };
+                bus.deliver(&envelope.topic, message);
+            }
+        }
+    };
+    let mut delay = Duration::from_millis(100);
+    loop {
+        match transport.listen(&deliver).await {
+            Ok(()) => {
+                tracing::debug!("Event transport closed, reconnecting");
+                delay = Duration::from_millis(100);
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                tracing::warn!("Event transport failed, reconnecting in {:?}: {}", delay, e);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+}
+
+/// Stream of the events of one type
+pub struct Subscription<E> {
+    receiver: broadcast::Receiver<Message>,
+    event: PhantomData<fn() -> E>,
+}
+
+impl<E: Event> Subscription<E> {
+    /// The next event, `None` once the bus is gone
+    ///
+    /// Events that fail to deserialize, e.g. from an instance running a
+    /// different version, are skipped with a warning.
+    pub async fn recv(&mut self) -> Option<E> {
+        self.recv_delivery().await.map(|delivery| delivery.event)
+    }
+
+    /// The next event with its message id, `None` once the bus is gone
+    ///
+    /// Like `recv`; use the id to skip messages delivered again.
+    pub async fn recv_delivery(&mut self) -> Option<Delivery<E>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(message) => match serde_json::from_slice(&message.payload) {
+                    Ok(event) => {
+                        return Some(Delivery {
+                            id: message.id.as_deref().map(str::to_string),
+                            event,
+                        })
+                    }
+                    Err(e) => tracing::warn!("Skipping malformed {} event: {}", E::TOPIC, e),
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Subscriber of {} skipped {} events", E::TOPIC, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_transport::RedisTransport;
+
+#[cfg(feature = "redis")]
+mod redis_transport {
+    use std::sync::Mutex;
+
+    use redis::{
+        aio::ConnectionManager,
+        streams::{StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply},
+        AsyncCommands,
+    };
+
+    use super::{Envelope, Transport};
+    use crate::{
+        error::{Error, Result},
+        lifecycle::BoxFuture,
+    };
+
+    /// Events retained in the stream for reconnecting instances
+    pub const DEFAULT_MAX_LEN: usize = 10_000;
+
+    // how long one XREAD waits for new events, in milliseconds
+    const BLOCK_MS: usize = 5_000;
+
+    /// `Transport` over a Redis stream
+    ///
+    /// Every instance appends events to the stream with `XADD` and reads all
+    /// of them with a blocking `XREAD` on a dedicated connection, resuming
+    /// after the last entry it saw when it reconnects. The stream is capped
+    /// at about `max_len` entries.
+    pub struct RedisTransport {
+        client: redis::Client,
+        conn: ConnectionManager,
+        stream: String,
+        max_len: usize,
+        last_id: Mutex<Option<String>>,
+    }
+
+    impl RedisTransport {
+        /// Connect to the Redis server at `url` and use the stream `stream`
+        pub async fn connect(url: &str, stream: impl Into<String>) -> Result<Self> {
+            let client = redis::Client::open(url).map_err(redis_error)?;
+            let conn = client.get_connection_manager().await.map_err(redis_error)?;
+            Ok(Self {
+                client,
+                conn,
+                stream: stream.into(),
+                max_len: DEFAULT_MAX_LEN,
+                last_id: Mutex::new(None),
+            })
+        }
+
+        /// Retain about `max_len` events in the stream
+        pub fn max_len(mut self, max_len: usize) -> Self {
+            self.max_len = max_len;
+            self
+        }
+
+        // last delivered entry id
+        fn last_id(&self) -> Option<String> {
+            self.last_id
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        }
+
+        // remember the last delivered entry id
+        fn set_last_id(&self, id: String) {
+            *self.last_id.lock().unwrap_or_else(|e| e.into_inner()) = Some(id);
+        }
+    }
+
+    // failure talking to Redis
+    fn redis_error(error: redis::RedisError) -> Error {
+        Error::server_error(format!("Redis event transport failed: {}", error))
+    }
+
+    impl Transport for RedisTransport {
+        fn publish<'a>(&'a self, envelope: &'a Envelope) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                let mut fields: Vec<(&str, &[u8])> = vec![
+                    ("topic", envelope.topic.as_bytes()),
+                    ("origin", envelope.origin.as_bytes()),
+                    ("payload", &envelope.payload),
+                ];
+                if let Some(id) = &envelope.id {
+                    fields.push(("id", id.as_bytes()));
+                }
+                let _: Option<String> = self
+                    .conn
+                    .clone()
+                    .xadd_maxlen(
+                        &self.stream,
+                        StreamMaxlen::Approx(self.max_len),
+                        "*",
+                        &fields,
+                    )
+                    .await
+                    .map_err(redis_error)?;
+                Ok(())
+            })
+        }
+
+        fn listen<'a>(
+            &'a self,
+            deliver: &'a (dyn Fn(Envelope) + Send + Sync),
+        ) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                let mut conn = self
+                    .client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .map_err(redis_error)?;
+                // start after the newest entry on first connect
+                let mut last_id = match self.last_id() {
+                    Some(id) => id,
+                    None => {
+                        let newest: StreamRangeReply = conn
+                            .xrevrange_count(&self.stream, "+", "-", 1)
+                            .await
+                            .map_err(redis_error)?;
+                        let id = newest
+                            .ids
+                            .first()
+                            .map_or_else(|| "0-0".to_string(), |entry| entry.id.clone());
+                        self.set_last_id(id.clone());
+                        id
+                    }
+                };
+                let options = StreamReadOptions::default().block(BLOCK_MS).count(100);
+                loop {
+                    let reply: Option<StreamReadReply> = conn
+                        .xread_options(&[&self.stream], &[&last_id], &options)
+                        .await
+                        .map_err(redis_error)?;
+                    for entry in reply.into_iter().flat_map(|r| r.keys).flat_map(|k| k.ids) {
+                        let envelope = match (
+                            entry.get::<String>("topic"),
+                            entry.get::<String>("origin"),
+                            entry.get::<Vec<u8>>("payload"),
+                        ) {
+                            (Some(topic), Some(origin), Some(payload)) => Envelope {
+                                topic,
+                                origin,
+                                id: entry.get("id"),
+                                payload,
+                            },
+                            _ => {
+                                tracing::warn!("Skipping malformed stream entry {}", entry.id);
+                                continue;
+                            }
+                        };
+                        deliver(envelope);
+                        last_id = entry.id;
+                        self.set_last_id(last_id.clone());
+                    }
+                }
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct UserCreated {
+        id: String,
+    }
+
+    impl Event for UserCreated {
+        const TOPIC: &'static str = "users.created";
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct OrderPlaced {
+        total: u32,
+    }
+
+    impl Event for OrderPlaced {
+        const TOPIC: &'static str = "orders.placed";
+    }
+
+    #[tokio::test]
+    async fn test_local_publish_and_subscribe() {
+        let bus = EventBus::new();
+        let mut users = bus.subscribe::<UserCreated>();
+        let mut orders = bus.subscribe::<OrderPlaced>();
+
+        bus.publish(&UserCreated { id: "1".into() }).await.unwrap();
+        bus.publish(&OrderPlaced { total: 5 }).await.unwrap();
+        assert_eq!(users.recv().await.unwrap().id, "1");
+        assert_eq!(orders.recv().await.unwrap().total, 5);
+        drop(bus);
+        assert!(users.recv().await.is_none());
+    }
+
+    // broker shared by the transports of several instances; fails the first
+    // `failures` listen calls to exercise reconnection
+    #[derive(Clone)]
+    struct Broker {
+        events: broadcast::Sender<Envelope>,
+        failures: Arc<AtomicUsize>,
+    }
+
+    impl Transport for Broker {
+        fn publish<'a>(&'a self, envelope: &'a Envelope) -> BoxFuture<'a, Result<()>> {
+            let _ = self.events.send(envelope.clone());
+            Box::pin(async { Ok(()) })
+        }
+
+        fn listen<'a>(
+            &'a self,
+            deliver: &'a (dyn Fn(Envelope) + Send + Sync),
+        ) -> BoxFuture<'a, Result<()>> {
+            let mut events = self.events.subscribe();
+            Box::pin(async move {
+                if self.failures.load(Ordering::SeqCst) > 0 {
+                    self.failures.fetch_sub(1, Ordering::SeqCst);
+                    return Err(Error::other("connection refused"));
+                }
+                while let Ok(envelope) = events.recv().await {
+                    deliver(envelope);
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transport_fans_out_across_instances() {
+        let broker = Broker {
+            events: broadcast::channel(16).0,
+            failures: Arc::new(AtomicUsize::new(1)),
+        };
+        let first = EventBus::with_transport(Arc::new(broker.clone()));
+        let second = EventBus::with_transport(Arc::new(broker.clone()));
+        let mut on_first = first.subscribe::<UserCreated>();
+        let mut on_second = second.subscribe::<UserCreated>();
+
+        // both listeners connect after one failed attempt each
+        while broker.events.receiver_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        first
+            .publish(&UserCreated { id: "7".into() })
+            .await
+            .unwrap();
+        assert_eq!(on_second.recv().await.unwrap().id, "7");
+        assert_eq!(on_first.recv().await.unwrap().id, "7");
+        // the publisher's own subscriber sees the event once
+        second
+            .publish(&UserCreated { id: "8".into() })
+            .await
+            .unwrap();
+        assert_eq!(on_first.recv().await.unwrap().id, "8");
+        assert_eq!(on_second.recv().await.unwrap().id, "8");
+        assert!(on_second.receiver.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_events_are_skipped() {
+        let bus = EventBus::new();
+        let mut users = bus.subscribe::<UserCreated>();
+        let malformed = Message {
+            id: None,
+            payload: b"{\"name\":1}".as_slice().into(),
+        };
+        bus.inner.deliver(UserCreated::TOPIC, malformed);
+        bus.publish(&UserCreated { id: "2".into() }).await.unwrap();
+        assert_eq!(users.recv().await.unwrap().id, "2");
+    }
+}
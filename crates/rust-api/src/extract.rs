@@ -0,0 +1,217 @@
+//! Request extractors for RustAPI framework
+//!
+//! Extractors provided by the framework on top of the ones re-exported from
+//! Axum.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, RawPathParams},
+    http::{request::Parts, StatusCode},
+};
+
+use crate::{
+    context::RequestContext,
+    di::{Container, Injectable},
+    error::ApiError,
+    paths,
+};
+
+/// Extractor for the IP address of the client that made the request
+///
+/// When the server runs with PROXY protocol enabled, this is the original
+/// client address reported by the load balancer rather than the address of
+/// the load balancer itself.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/whoami")]
+/// async fn whoami(ClientIp(ip): ClientIp) -> String {
+///     ip.to_string()
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| ClientIp(addr.ip()))
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Client address unavailable: server was not started with connect info",
+            ))
+    }
+}
+
+/// Extractor that resolves a service from the application's DI container
+///
+/// Requires the router to be built with `App::build()`, which attaches the
+/// container to every request. Routes mounted with `App::nest_scoped` resolve
+/// from their own scoped container.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users")]
+/// async fn list_users(Inject(users): Inject<UserService>) -> Json<Vec<User>> {
+///     Json(users.list())
+/// }
+///
+/// // services registered behind a trait object
+/// #[get("/users/{id}")]
+/// async fn get_user(Inject(repo): Inject<dyn UserRepo>, Path(id): Path<u64>) -> Json<User> {
+///     Json(repo.find(id).unwrap())
+/// }
+/// ```
+pub struct Inject<T: Injectable + ?Sized>(pub Arc<T>);
+
+impl<T: Injectable + ?Sized, S: Send + Sync> FromRequestParts<S> for Inject<T> {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let container = parts.extensions.get::<Arc<Container>>().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "DI container unavailable: router was not built with App::build()".to_string(),
+        ))?;
+
+        let tenant = RequestContext::with_current(RequestContext::tenant).flatten();
+        let service = match tenant {
+            Some(tenant) => container.resolve_for_tenant::<T>(&tenant),
+            None => container.resolve::<T>(),
+        };
+        service.map(Inject).ok_or_else(|| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Service not registered: {}", std::any::type_name::<T>()),
+            )
+        })
+    }
+}
+
+/// Extractor for the catch-all (`{*name}`) segment of the matched route
+///
+/// Holds the percent-decoded remainder of the path, without a leading slash.
+/// The value is untrusted: use `sanitized()` before touching the filesystem.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/files/{*path}")]
+/// async fn download(rest: Rest) -> Result<Vec<u8>, ApiError> {
+///     let path = paths::safe_join("/srv/files", rest.as_str())?;
+///     // ...
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest(pub String);
+
+impl Rest {
+    /// The raw remainder of the path
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The remainder as a relative path, rejecting traversal attempts
+    pub fn sanitized(&self) -> Result<PathBuf, ApiError> {
+        paths::sanitize_path(&self.0)
+    }
+
+    // find the name of the catch-all parameter in a route template
+    fn catch_all_name(template: &str) -> Option<&str> {
+        let start = template.rfind("{*")? + 2;
+        let end = start + template[start..].find('}')?;
+        Some(&template[start..end])
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Rest {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let name = parts
+            .extensions
+            .get::<MatchedPath>()
+            .and_then(|matched| Self::catch_all_name(matched.as_str()))
+            .map(str::to_string)
+            .ok_or_else(|| ApiError::internal("Rest used on a route without a {*name} segment"))?;
+
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::bad_request(e.body_text()).rejection())?;
+
+        let value = params
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default();
+        Ok(Rest(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_ip_from_connect_info() {
+        let addr: SocketAddr = "203.0.113.7:8080".parse().unwrap();
+        let mut req = Request::new(());
+        req.extensions_mut().insert(ConnectInfo(addr));
+        let (mut parts, _) = req.into_parts();
+
+        let ClientIp(ip) = ClientIp::from_request_parts(&mut parts, &()).await.unwrap();
+        assert_eq!(ip, addr.ip());
+    }
+
+    #[tokio::test]
+    async fn test_rest_extracts_catch_all() {
+        use axum::{body::Body, routing::get, Router};
+        use tower::ServiceExt;
+
+        let router = Router::new().route(
+            "/files/{*path}",
+            get(|rest: Rest| async move { rest.sanitized().map(|p| p.display().to_string()) }),
+        );
+
+        let request = Request::builder()
+            .uri("/files/docs/a%20b.txt")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "docs/a b.txt");
+
+        let request = Request::builder()
+            .uri("/files/a/%2E%2E/%2E%2E/secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_catch_all_name() {
+        assert_eq!(Rest::catch_all_name("/files/{*path}"), Some("path"));
+        assert_eq!(Rest::catch_all_name("/users/{id}"), None);
+    }
+
+    #[tokio::test]
+    async fn test_client_ip_missing() {
+        let (mut parts, _) = Request::new(()).into_parts();
+        assert!(ClientIp::from_request_parts(&mut parts, &()).await.is_err());
+    }
+}
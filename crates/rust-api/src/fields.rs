@@ -0,0 +1,332 @@
+//! Sparse fieldsets for RustAPI framework
+//!
+//! `?fields=id,name,profile.avatar` lets a client ask for the parts of a
+//! resource it needs. The `Fields` extractor parses the list and `Sparse`
+//! filters a response through it after serialization, so one model serves
+//! every view without writing a DTO per client.
+//!
+//! Dotted paths select nested members, and a path through an array selects
+//! the member in every element. Requested fields that the resource does not
+//! have are left out; `Fields::allow` restricts the fields a client may ask
+//! for and rejects the others.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::ApiError, json::Json};
+
+/// Extractor for the `?fields=` query parameter
+///
+/// Without the parameter every field is kept. An empty name, as in
+/// `fields=id,,name` or `fields=profile.`, is rejected with 400 Bad Request.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users/{id}")]
+/// async fn get_user(Path(id): Path<u64>, fields: Fields) -> ApiResult<Sparse<User>> {
+///     let fields = fields.allow(&["id", "name", "email", "profile", "profile.avatar"])?;
+///     Ok(Sparse::new(repo.find(id).await?, fields))
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fields {
+    // `None` keeps everything
+    selected: Option<Vec<String>>,
+}
+
+// query parameter read by the `Fields` extractor
+#[derive(Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+impl Fields {
+    /// Keep every field
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Parse a comma-separated list of dotted paths
+    pub fn parse(list: &str) -> Result<Self, ApiError> {
+        let mut selected = Vec::new();
+        for path in list.split(',').map(str::trim) {
+            if path.split('.').any(str::is_empty) {
+                return Err(
+                    ApiError::bad_request(format!("Invalid field {:?} in fields", path))
+                        .with_code("invalid_fields"),
+                );
+            }
+            if !selected.iter().any(|p| p == path) {
+                selected.push(path.to_string());
+            }
+        }
+        Ok(Self {
+            selected: Some(selected),
+        })
+    }
+
+    /// Check whether every field is kept
+    pub fn is_all(&self) -> bool {
+        self.selected.is_none()
+    }
+
+    /// The requested paths, or `None` when every field is kept
+    pub fn paths(&self) -> Option<&[String]> {
+        self.selected.as_deref()
+    }
+
+    /// Reject the request when it asks for a field outside `allowed`
+    ///
+    /// Fails with 400 (`unknown_fieldset`) listing the fields in `details`.
+    pub fn allow(self, allowed: &[&str]) -> Result<Self, ApiError> {
+        let unknown: Vec<&String> = self
+            .paths()
+            .unwrap_or_default()
+            .iter()
+            .filter(|path| !allowed.contains(&path.as_str()))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(self);
+        }
+        Err(ApiError::bad_request(format!(
+            "Unknown fields: {}",
+            unknown
+                .iter()
+                .map(|path| path.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .with_code("unknown_fieldset")
+        .with_details(serde_json::json!({ "fields": unknown })))
+    }
+
+    /// Filter `value` in place, keeping the requested fields
+    pub fn filter(&self, value: &mut Value) {
+        if let Some(paths) = &self.selected {
+            let mut tree = Tree::default();
+            for path in paths {
+                tree.insert(path);
+            }
+            tree.filter(value);
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Fields {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FieldsQuery>::try_from_uri(&parts.uri).map_err(|e| {
+            ApiError::bad_request(format!("Invalid fields: {}", e))
+                .with_code("invalid_fields")
+                .rejection()
+        })?;
+        match query.fields {
+            Some(list) => Self::parse(&list).map_err(ApiError::rejection),
+            None => Ok(Self::all()),
+        }
+    }
+}
+
+// requested paths by segment; a leaf keeps the whole member
+#[derive(Default)]
+struct Tree {
+    children: BTreeMap<String, Tree>,
+    leaf: bool,
+}
+
+impl Tree {
+    fn insert(&mut self, path: &str) {
+        let mut node = self;
+        for segment in path.split('.') {
+            node = node.children.entry(segment.to_string()).or_default();
+        }
+        node.leaf = true;
+    }
+
+    fn filter(&self, value: &mut Value) {
+        if self.leaf {
+            return;
+        }
+        match value {
+            Value::Object(members) => {
+                members.retain(|name, _| self.children.contains_key(name));
+                for (name, member) in members.iter_mut() {
+                    self.children[name].filter(member);
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|item| self.filter(item)),
+            _ => {}
+        }
+    }
+}
+
+/// JSON response keeping only the requested fields
+///
+/// Arrays are filtered element by element. For a wrapper such as `Page<T>`,
+/// `under` applies the fieldset to the items and keeps the wrapper's own
+/// members.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users")]
+/// async fn list_users(page: Pagination, fields: Fields) -> ApiResult<Sparse<Page<User>>> {
+///     Ok(Sparse::new(repo.list(page).await?, fields).under("items"))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sparse<T> {
+    body: T,
+    fields: Fields,
+    under: Option<String>,
+}
+
+impl<T: Serialize> Sparse<T> {
+    /// Respond with `body`, filtered through `fields`
+    pub fn new(body: T, fields: Fields) -> Self {
+        Self {
+            body,
+            fields,
+            under: None,
+        }
+    }
+
+    /// Filter only the top-level member `name` of the body
+    pub fn under(mut self, name: impl Into<String>) -> Self {
+        self.under = Some(name.into());
+        self
+    }
+
+    /// The filtered body
+    pub fn to_value(&self) -> Result<Value, ApiError> {
+        let mut value = serde_json::to_value(&self.body)
+            .map_err(|e| ApiError::internal(format!("Failed to serialize the response: {}", e)))?;
+        let target = match &self.under {
+            Some(name) => value.get_mut(name.as_str()),
+            None => Some(&mut value),
+        };
+        if let Some(target) = target {
+            self.fields.filter(target);
+        }
+        Ok(value)
+    }
+}
+
+impl<T: Serialize> IntoResponse for Sparse<T> {
+    fn into_response(self) -> Response {
+        match self.to_value() {
+            Ok(value) => (StatusCode::OK, Json(value)).into_response(),
+            Err(error) => error.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use serde_json::json;
+
+    use super::*;
+
+    fn user() -> Value {
+        json!({
+            "id": 7,
+            "name": "ada",
+            "email": "ada@example.com",
+            "profile": { "avatar": "a.png", "bio": "..." },
+            "roles": [{ "name": "admin", "since": 2020 }, { "name": "ops", "since": 2021 }],
+        })
+    }
+
+    async fn extract(uri: &str) -> Result<Fields, ApiError> {
+        let (mut parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        Fields::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_extractor() {
+        assert!(extract("/users").await.unwrap().is_all());
+        let fields = extract("/users?fields=id,%20name,id").await.unwrap();
+        assert_eq!(fields.paths().unwrap(), ["id", "name"]);
+        for uri in [
+            "/users?fields=id,,name",
+            "/users?fields=profile.",
+            "/users?fields=",
+        ] {
+            assert_eq!(extract(uri).await.unwrap_err().code(), "invalid_fields");
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut value = user();
+        Fields::parse("id,profile.avatar,roles.name,missing")
+            .unwrap()
+            .filter(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "id": 7,
+                "profile": { "avatar": "a.png" },
+                "roles": [{ "name": "admin" }, { "name": "ops" }],
+            })
+        );
+
+        let mut value = user();
+        Fields::parse("profile,profile.avatar")
+            .unwrap()
+            .filter(&mut value);
+        assert_eq!(value, json!({ "profile": user()["profile"] }));
+
+        let mut value = json!([user(), user()]);
+        Fields::parse("name").unwrap().filter(&mut value);
+        assert_eq!(value, json!([{ "name": "ada" }, { "name": "ada" }]));
+
+        let mut value = user();
+        Fields::all().filter(&mut value);
+        assert_eq!(value, user());
+    }
+
+    #[test]
+    fn test_allow() {
+        let allowed = ["id", "name", "profile.avatar"];
+        assert!(Fields::parse("id,profile.avatar")
+            .unwrap()
+            .allow(&allowed)
+            .is_ok());
+        assert!(Fields::all().allow(&allowed).is_ok());
+        let error = Fields::parse("id,email")
+            .unwrap()
+            .allow(&allowed)
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), "unknown_fieldset");
+        assert_eq!(error.details(), Some(&json!({ "fields": ["email"] })));
+    }
+
+    #[tokio::test]
+    async fn test_sparse_response() {
+        let page = json!({ "items": [user()], "page": 1, "total": 1 });
+        let sparse = Sparse::new(page, Fields::parse("id").unwrap()).under("items");
+        assert_eq!(
+            sparse.to_value().unwrap(),
+            json!({ "items": [{ "id": 7 }], "page": 1, "total": 1 })
+        );
+
+        let response = Sparse::new(user(), Fields::parse("name").unwrap()).into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"name":"ada"}"#);
+    }
+}
@@ -0,0 +1,514 @@
+//! Non-JSON body formats for RustAPI framework
+//!
+//! `Cbor<T>` (feature `cbor`), `Proto<T>` (feature `protobuf`) and `Xml<T>`
+//! (feature `xml`) work like `Json<T>`: as extractors they enforce the
+//! content type and decode the body, as responses they encode the value and
+//! set the content type, writing into buffers from the global `BufferPool`.
+//! Route macros record the formats a handler takes and returns as
+//! `MediaTypes`, which the OpenAPI document lists.
+
+#[cfg(any(feature = "cbor", feature = "protobuf", feature = "xml"))]
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+#[cfg(any(feature = "cbor", feature = "protobuf", feature = "xml"))]
+use crate::{buffer::BufferPool, error::ApiError};
+
+/// Media type of CBOR bodies
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Media type of Protobuf bodies
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Media type of XML bodies
+pub const XML_CONTENT_TYPE: &str = "application/xml";
+
+/// Route metadata naming the non-JSON body formats of a handler
+///
+/// Attached by the route macros when a handler takes or returns `Cbor<T>`,
+/// `Proto<T>` or `Xml<T>`; the types are recognised by name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MediaTypes {
+    request: Option<&'static str>,
+    response: Option<&'static str>,
+}
+
+impl MediaTypes {
+    /// No known formats
+    pub const fn new() -> Self {
+        Self {
+            request: None,
+            response: None,
+        }
+    }
+
+    /// The request body is sent as `media_type`
+    pub const fn request(mut self, media_type: &'static str) -> Self {
+        self.request = Some(media_type);
+        self
+    }
+
+    /// The response body is sent as `media_type`
+    pub const fn response(mut self, media_type: &'static str) -> Self {
+        self.response = Some(media_type);
+        self
+    }
+
+    /// Media type of the request body, if known
+    pub const fn request_type(&self) -> Option<&'static str> {
+        self.request
+    }
+
+    /// Media type of the response body, if known
+    pub const fn response_type(&self) -> Option<&'static str> {
+        self.response
+    }
+}
+
+// check the request's content type against the accepted media types
+#[cfg(any(feature = "cbor", feature = "protobuf", feature = "xml"))]
+fn require_content_type(headers: &HeaderMap, accepted: &[&str]) -> Result<(), ApiError> {
+    let media_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase());
+    match media_type {
+        Some(media_type) if accepted.contains(&media_type.as_str()) => Ok(()),
+        _ => Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Expected request with `Content-Type: {}`", accepted[0]),
+        )
+        .rejection()),
+    }
+}
+
+// read the body of a request whose content type was checked
+#[cfg(any(feature = "cbor", feature = "protobuf", feature = "xml"))]
+async fn read_body<S: Send + Sync>(req: Request, state: &S) -> Result<Bytes, ApiError> {
+    Bytes::from_request(req, state)
+        .await
+        .map_err(|e| ApiError::new(e.status(), e.body_text()).rejection())
+}
+
+// response with an encoded body and its content type
+#[cfg(any(feature = "cbor", feature = "protobuf", feature = "xml"))]
+fn encoded(content_type: &'static str, body: Bytes) -> Response {
+    (
+        [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+        body,
+    )
+        .into_response()
+}
+
+/// CBOR extractor and response (feature `cbor`)
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/readings")]
+/// async fn ingest(Cbor(reading): Cbor<Reading>) -> Cbor<Ack> {
+///     Cbor(store.save(reading))
+/// }
+/// ```
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor<T>(pub T);
+
+#[cfg(feature = "cbor")]
+impl<T, S> FromRequest<S> for Cbor<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        require_content_type(req.headers(), &[CBOR_CONTENT_TYPE])?;
+        let body = read_body(req, state).await?;
+        ciborium::from_reader(body.as_ref())
+            .map(Cbor)
+            .map_err(|e| ApiError::bad_request(format!("Invalid CBOR body: {}", e)).rejection())
+    }
+}
+
+#[cfg(feature = "cbor")]
+impl<T: serde::Serialize> IntoResponse for Cbor<T> {
+    fn into_response(self) -> Response {
+        let pool = BufferPool::global();
+        let mut body = pool.acquire(pool.typical_size());
+        match ciborium::into_writer(&self.0, &mut body) {
+            Ok(()) => encoded(CBOR_CONTENT_TYPE, body.into_bytes()),
+            Err(e) => ApiError::internal(format!("Failed to encode CBOR: {}", e)).into_response(),
+        }
+    }
+}
+
+/// Protobuf extractor and response for `prost` messages (feature `protobuf`)
+///
+/// Accepts `application/x-protobuf` and `application/protobuf`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/rpc/get-user")]
+/// async fn get_user(Proto(req): Proto<pb::GetUserRequest>) -> Proto<pb::User> {
+///     Proto(service.find(req.id))
+/// }
+/// ```
+#[cfg(feature = "protobuf")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Proto<T>(pub T);
+
+#[cfg(feature = "protobuf")]
+impl<T, S> FromRequest<S> for Proto<T>
+where
+    T: prost::Message + Default,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        require_content_type(
+            req.headers(),
+            &[PROTOBUF_CONTENT_TYPE, "application/protobuf"],
+        )?;
+        let body = read_body(req, state).await?;
+        T::decode(body)
+            .map(Proto)
+            .map_err(|e| ApiError::bad_request(format!("Invalid Protobuf body: {}", e)).rejection())
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<T: prost::Message> IntoResponse for Proto<T> {
+    fn into_response(self) -> Response {
+        let mut body = BufferPool::global().acquire(self.0.encoded_len());
+        match self.0.encode(&mut *body) {
+            Ok(()) => encoded(PROTOBUF_CONTENT_TYPE, body.into_bytes()),
+            Err(e) => {
+                ApiError::internal(format!("Failed to encode Protobuf: {}", e)).into_response()
+            }
+        }
+    }
+}
+
+/// How `Xml<T>` treats the document's root element (feature `xml`)
+///
+/// Add it to a router as an extension to change the defaults, which accept
+/// any root element and do not unwrap SOAP envelopes.
+///
+/// # Example
+///
+/// ```ignore
+/// let partner_api = Router::new()
+///     .route("/orders", routing::post(receive_order))
+///     .layer(Extension(XmlConfig::new().unwrap_soap_body(true).expect_root("Order")));
+/// ```
+#[cfg(feature = "xml")]
+#[derive(Debug, Clone, Default)]
+pub struct XmlConfig {
+    root: Option<String>,
+    unwrap_soap_body: bool,
+}
+
+#[cfg(feature = "xml")]
+impl XmlConfig {
+    /// Accept any root element
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject documents whose root element (ignoring namespace prefix) is
+    /// not `name`; checked after SOAP unwrapping
+    pub fn expect_root(mut self, name: impl Into<String>) -> Self {
+        self.root = Some(name.into());
+        self
+    }
+
+    /// Decode the content of `Envelope/Body` rather than the whole document
+    pub fn unwrap_soap_body(mut self, unwrap: bool) -> Self {
+        self.unwrap_soap_body = unwrap;
+        self
+    }
+}
+
+/// XML extractor and response (feature `xml`)
+///
+/// Accepts `application/xml`, `text/xml` and `application/soap+xml`.
+/// Malformed documents are rejected with a 400 whose code is `malformed_xml`;
+/// see `XmlConfig` for root element handling.
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/partners/orders")]
+/// async fn receive_order(Xml(order): Xml<PurchaseOrder>) -> StatusCode {
+///     orders.import(order);
+///     StatusCode::ACCEPTED
+/// }
+/// ```
+#[cfg(feature = "xml")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xml<T>(pub T);
+
+#[cfg(feature = "xml")]
+impl<T, S> FromRequest<S> for Xml<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        require_content_type(
+            req.headers(),
+            &[XML_CONTENT_TYPE, "text/xml", "application/soap+xml"],
+        )?;
+        let config = req
+            .extensions()
+            .get::<XmlConfig>()
+            .cloned()
+            .unwrap_or_default();
+        let body = read_body(req, state).await?;
+        let text = std::str::from_utf8(&body)
+            .map_err(|_| malformed_xml("body is not valid UTF-8".to_string()))?;
+        decode_xml(text, &config).map(Xml)
+    }
+}
+
+#[cfg(feature = "xml")]
+impl<T: serde::Serialize> IntoResponse for Xml<T> {
+    fn into_response(self) -> Response {
+        let pool = BufferPool::global();
+        let mut body = pool.acquire(pool.typical_size());
+        match quick_xml::se::to_writer(&mut body, &self.0) {
+            Ok(_) => encoded(XML_CONTENT_TYPE, body.into_bytes()),
+            Err(e) => ApiError::internal(format!("Failed to encode XML: {}", e)).into_response(),
+        }
+    }
+}
+
+// apply the root element handling and deserialize
+#[cfg(feature = "xml")]
+fn decode_xml<T: serde::de::DeserializeOwned>(
+    text: &str,
+    config: &XmlConfig,
+) -> Result<T, ApiError> {
+    let document = if config.unwrap_soap_body {
+        soap_body(text)?
+    } else {
+        text
+    };
+    if let Some(expected) = &config.root {
+        let root = root_element(document)?;
+        if root != *expected {
+            return Err(malformed_xml(format!(
+                "expected root element `{}`, found `{}`",
+                expected, root
+            )));
+        }
+    }
+    quick_xml::de::from_str(document).map_err(|e| malformed_xml(e.to_string()))
+}
+
+// local name of the first element in a document
+#[cfg(feature = "xml")]
+fn root_element(text: &str) -> Result<String, ApiError> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(text);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                return Ok(String::from_utf8_lossy(e.local_name().as_ref()).into_owned())
+            }
+            Ok(Event::Eof) => {
+                return Err(malformed_xml("document has no root element".to_string()))
+            }
+            Ok(_) => {}
+            Err(e) => return Err(malformed_xml(e.to_string())),
+        }
+    }
+}
+
+// inner XML of the SOAP `Envelope/Body` element
+#[cfg(feature = "xml")]
+fn soap_body(text: &str) -> Result<&str, ApiError> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(text);
+    let mut depth = 0;
+    let mut body_start = None;
+    loop {
+        let before = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                depth += 1;
+                let name = e.local_name();
+                if depth == 1 && name.as_ref() != b"Envelope" {
+                    return Err(malformed_xml("expected a SOAP Envelope".to_string()));
+                }
+                if depth == 2 && name.as_ref() == b"Body" {
+                    body_start = Some(reader.buffer_position() as usize);
+                }
+            }
+            Ok(Event::End(_)) => {
+                if depth == 2 {
+                    if let Some(start) = body_start {
+                        return Ok(&text[start..before]);
+                    }
+                }
+                depth -= 1;
+            }
+            Ok(Event::Eof) => return Err(malformed_xml("SOAP Envelope has no Body".to_string())),
+            Ok(_) => {}
+            Err(e) => return Err(malformed_xml(e.to_string())),
+        }
+    }
+}
+
+// structured 400 for a document that could not be decoded
+#[cfg(feature = "xml")]
+fn malformed_xml(reason: String) -> ApiError {
+    ApiError::bad_request("Malformed XML body")
+        .with_code("malformed_xml")
+        .with_details(serde_json::json!({ "reason": reason }))
+        .rejection()
+}
+
+#[cfg(all(test, any(feature = "cbor", feature = "protobuf", feature = "xml")))]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    #[cfg(any(feature = "cbor", feature = "protobuf"))]
+    async fn body_bytes(response: Response) -> Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    fn request(content_type: &str, body: Vec<u8>) -> Request {
+        Request::post("/")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[cfg(feature = "cbor")]
+    #[crate::post("/formats-test/readings")]
+    async fn ingest(Cbor(reading): Cbor<u32>) -> Result<Cbor<u32>, ApiError> {
+        Ok(Cbor(reading))
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn test_route_media_types_are_documented() {
+        assert_eq!(ingest(Cbor(7)).await.unwrap().0, 7);
+        let registry = crate::RouteRegistry::global();
+        let route = registry.get("POST", "/formats-test/readings").unwrap();
+        let media_types = route.metadata.get::<MediaTypes>().unwrap();
+        assert_eq!(media_types.request_type(), Some(CBOR_CONTENT_TYPE));
+        assert_eq!(media_types.response_type(), Some(CBOR_CONTENT_TYPE));
+
+        let doc = crate::OpenApi::new("Readings", "1.0.0").document(registry);
+        let operation = &doc["paths"]["/formats-test/readings"]["post"];
+        assert!(operation["requestBody"]["content"][CBOR_CONTENT_TYPE].is_object());
+        assert!(operation["responses"]["200"]["content"][CBOR_CONTENT_TYPE].is_object());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn test_cbor_round_trip() {
+        let response = Cbor(vec![1u8, 2, 3]).into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], CBOR_CONTENT_TYPE);
+        let body = body_bytes(response).await.to_vec();
+
+        let Cbor(value) =
+            Cbor::<Vec<u8>>::from_request(request(CBOR_CONTENT_TYPE, body.clone()), &())
+                .await
+                .unwrap();
+        assert_eq!(value, vec![1, 2, 3]);
+
+        let err = Cbor::<Vec<u8>>::from_request(request("application/json", body), &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let err = Cbor::<Vec<u8>>::from_request(request(CBOR_CONTENT_TYPE, vec![0xff]), &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[tokio::test]
+    async fn test_proto_round_trip() {
+        let response = Proto(String::from("hello")).into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROTOBUF_CONTENT_TYPE
+        );
+        let body = body_bytes(response).await.to_vec();
+
+        let Proto(value) =
+            Proto::<String>::from_request(request("application/protobuf", body), &())
+                .await
+                .unwrap();
+        assert_eq!(value, "hello");
+
+        let err = Proto::<String>::from_request(request("text/plain", Vec::new()), &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[cfg(feature = "xml")]
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Order {
+        id: u32,
+        item: String,
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_xml_root_handling() {
+        let plain = "<Order><id>7</id><item>bolt</item></Order>";
+        let order: Order = decode_xml(plain, &XmlConfig::new().expect_root("Order")).unwrap();
+        assert_eq!(
+            order,
+            Order {
+                id: 7,
+                item: "bolt".to_string()
+            }
+        );
+
+        let err = decode_xml::<Order>(plain, &XmlConfig::new().expect_root("Invoice")).unwrap_err();
+        assert_eq!(err.code(), "malformed_xml");
+
+        let soap = r#"<soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope">
+            <soap:Header/>
+            <soap:Body><Order><id>8</id><item>nut</item></Order></soap:Body>
+        </soap:Envelope>"#;
+        let config = XmlConfig::new().unwrap_soap_body(true).expect_root("Order");
+        let order: Order = decode_xml(soap, &config).unwrap();
+        assert_eq!(order.id, 8);
+    }
+
+    #[cfg(feature = "xml")]
+    #[tokio::test]
+    async fn test_xml_malformed_is_structured_400() {
+        let err = Xml::<Order>::from_request(request("text/xml", b"<Order><id>".to_vec()), &())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.code(), "malformed_xml");
+        assert!(err.details().unwrap()["reason"].is_string());
+    }
+}
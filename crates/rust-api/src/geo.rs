@@ -0,0 +1,588 @@
+//! GeoJSON types for RustAPI framework
+//!
+//! `Point`, `Polygon` and `Geometry` are GeoJSON geometries (RFC 7946) to
+//! use in DTOs. They check their coordinates when built or deserialized:
+//! latitudes within -90..=90, longitudes within -180..=180, closed polygon
+//! rings. A bad geometry in a `Json<T>` body is therefore rejected with 422
+//! like any other invalid field. `GeoJson<T>` sends a `Feature` or
+//! `FeatureCollection` as `application/geo+json`.
+//!
+//! Positions are written longitude first, as GeoJSON requires.
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::{error::ApiError, json::Json};
+
+/// Media type of GeoJSON bodies
+pub const GEOJSON_CONTENT_TYPE: &str = "application/geo+json";
+
+/// Invalid coordinates or geometry
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum GeoError {
+    /// Latitude outside -90..=90
+    #[error("latitude {0} is outside -90..=90")]
+    Latitude(f64),
+
+    /// Longitude outside -180..=180
+    #[error("longitude {0} is outside -180..=180")]
+    Longitude(f64),
+
+    /// A position without 2 or 3 coordinates
+    #[error("a position has 2 or 3 coordinates, not {0}")]
+    Dimensions(usize),
+
+    /// A polygon without an exterior ring
+    #[error("a polygon needs an exterior ring")]
+    NoRing,
+
+    /// A polygon ring with fewer than 4 positions
+    #[error("a polygon ring needs at least 4 positions, not {0}")]
+    RingTooShort(usize),
+
+    /// A polygon ring that does not end where it starts
+    #[error("a polygon ring must end at its first position")]
+    RingNotClosed,
+}
+
+// 422 like other invalid bodies
+impl From<GeoError> for ApiError {
+    fn from(error: GeoError) -> Self {
+        ApiError::unprocessable(error.to_string()).with_code("invalid_geometry")
+    }
+}
+
+/// Check that `lat` is a latitude
+pub fn check_latitude(lat: f64) -> Result<(), GeoError> {
+    match (-90.0..=90.0).contains(&lat) {
+        true => Ok(()),
+        false => Err(GeoError::Latitude(lat)),
+    }
+}
+
+/// Check that `lon` is a longitude
+pub fn check_longitude(lon: f64) -> Result<(), GeoError> {
+    match (-180.0..=180.0).contains(&lon) {
+        true => Ok(()),
+        false => Err(GeoError::Longitude(lon)),
+    }
+}
+
+/// Custom validator for latitude fields of plain DTOs
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Deserialize, Validate)]
+/// struct NearbyQuery {
+///     #[validate(custom(function = "rust_api::geo::latitude"))]
+///     lat: f64,
+///     #[validate(custom(function = "rust_api::geo::longitude"))]
+///     lon: f64,
+/// }
+/// ```
+#[cfg(feature = "validator")]
+pub fn latitude(value: &f64) -> Result<(), validator::ValidationError> {
+    check_latitude(*value)
+        .map_err(|e| validator::ValidationError::new("latitude").with_message(e.to_string().into()))
+}
+
+/// Custom validator for longitude fields of plain DTOs
+#[cfg(feature = "validator")]
+pub fn longitude(value: &f64) -> Result<(), validator::ValidationError> {
+    check_longitude(*value).map_err(|e| {
+        validator::ValidationError::new("longitude").with_message(e.to_string().into())
+    })
+}
+
+/// A checked longitude, latitude and optional altitude
+///
+/// Serialized as the GeoJSON array `[lon, lat]` or `[lon, lat, alt]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    lon: f64,
+    lat: f64,
+    alt: Option<f64>,
+}
+
+impl Position {
+    /// The position at `lon`, `lat`
+    pub fn new(lon: f64, lat: f64) -> Result<Self, GeoError> {
+        check_longitude(lon)?;
+        check_latitude(lat)?;
+        Ok(Self {
+            lon,
+            lat,
+            alt: None,
+        })
+    }
+
+    /// Add an altitude, in meters
+    pub fn with_altitude(mut self, alt: f64) -> Self {
+        self.alt = Some(alt);
+        self
+    }
+
+    /// Longitude, in degrees
+    pub fn lon(&self) -> f64 {
+        self.lon
+    }
+
+    /// Latitude, in degrees
+    pub fn lat(&self) -> f64 {
+        self.lat
+    }
+
+    /// Altitude, in meters
+    pub fn alt(&self) -> Option<f64> {
+        self.alt
+    }
+}
+
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.alt {
+            Some(alt) => [self.lon, self.lat, alt].serialize(serializer),
+            None => [self.lon, self.lat].serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let coordinates = Vec::<f64>::deserialize(deserializer)?;
+        let position = match coordinates[..] {
+            [lon, lat] => Position::new(lon, lat),
+            [lon, lat, alt] => Position::new(lon, lat).map(|p| p.with_altitude(alt)),
+            _ => Err(GeoError::Dimensions(coordinates.len())),
+        };
+        position.map_err(serde::de::Error::custom)
+    }
+}
+
+/// A GeoJSON `Point`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Geometry", try_from = "Geometry")]
+pub struct Point(pub Position);
+
+impl Point {
+    /// The point at `lon`, `lat`
+    pub fn new(lon: f64, lat: f64) -> Result<Self, GeoError> {
+        Position::new(lon, lat).map(Self)
+    }
+
+    /// Longitude, in degrees
+    pub fn lon(&self) -> f64 {
+        self.0.lon
+    }
+
+    /// Latitude, in degrees
+    pub fn lat(&self) -> f64 {
+        self.0.lat
+    }
+
+    /// JSON Schema of a GeoJSON `Point`, for DTO documentation
+    pub fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["type", "coordinates"],
+            "properties": {
+                "type": { "const": "Point" },
+                "coordinates": position_schema(),
+            },
+        })
+    }
+}
+
+/// A GeoJSON `Polygon`: an exterior ring and optional holes
+///
+/// Rings are closed, so their last position repeats the first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "Geometry", try_from = "Geometry")]
+pub struct Polygon {
+    rings: Vec<Vec<Position>>,
+}
+
+impl Polygon {
+    /// The polygon with the exterior ring `rings[0]` and the holes after it
+    pub fn new(rings: Vec<Vec<Position>>) -> Result<Self, GeoError> {
+        if rings.is_empty() {
+            return Err(GeoError::NoRing);
+        }
+        for ring in &rings {
+            if ring.len() < 4 {
+                return Err(GeoError::RingTooShort(ring.len()));
+            }
+            if ring.first() != ring.last() {
+                return Err(GeoError::RingNotClosed);
+            }
+        }
+        Ok(Self { rings })
+    }
+
+    /// The outer boundary
+    pub fn exterior(&self) -> &[Position] {
+        &self.rings[0]
+    }
+
+    /// The holes
+    pub fn holes(&self) -> &[Vec<Position>] {
+        &self.rings[1..]
+    }
+
+    /// Check whether `position` is inside the polygon and outside its holes
+    ///
+    /// Treats coordinates as planar, which is accurate enough for areas of
+    /// city size away from the poles and the antimeridian.
+    pub fn contains(&self, position: &Position) -> bool {
+        ring_contains(self.exterior(), position)
+            && !self
+                .holes()
+                .iter()
+                .any(|hole| ring_contains(hole, position))
+    }
+
+    /// JSON Schema of a GeoJSON `Polygon`, for DTO documentation
+    pub fn schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["type", "coordinates"],
+            "properties": {
+                "type": { "const": "Polygon" },
+                "coordinates": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": { "type": "array", "minItems": 4, "items": position_schema() },
+                },
+            },
+        })
+    }
+}
+
+// even-odd rule: count the edges a ray going east crosses
+fn ring_contains(ring: &[Position], position: &Position) -> bool {
+    let (x, y) = (position.lon, position.lat);
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let (a, b) = (&edge[0], &edge[1]);
+        if (a.lat > y) != (b.lat > y) {
+            let crossing = a.lon + (y - a.lat) / (b.lat - a.lat) * (b.lon - a.lon);
+            if x < crossing {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+// JSON Schema of a `[lon, lat]` or `[lon, lat, alt]` array
+fn position_schema() -> Value {
+    json!({
+        "type": "array",
+        "minItems": 2,
+        "maxItems": 3,
+        "prefixItems": [
+            { "type": "number", "minimum": -180, "maximum": 180 },
+            { "type": "number", "minimum": -90, "maximum": 90 },
+            { "type": "number" },
+        ],
+    })
+}
+
+/// Any supported GeoJSON geometry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "RawGeometry", try_from = "RawGeometry")]
+pub enum Geometry {
+    /// A single position
+    Point(Point),
+    /// An area
+    Polygon(Polygon),
+}
+
+// the tagged GeoJSON shape, checked on conversion
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum RawGeometry {
+    Point { coordinates: Position },
+    Polygon { coordinates: Vec<Vec<Position>> },
+}
+
+impl From<Geometry> for RawGeometry {
+    fn from(geometry: Geometry) -> Self {
+        match geometry {
+            Geometry::Point(point) => RawGeometry::Point {
+                coordinates: point.0,
+            },
+            Geometry::Polygon(polygon) => RawGeometry::Polygon {
+                coordinates: polygon.rings,
+            },
+        }
+    }
+}
+
+impl TryFrom<RawGeometry> for Geometry {
+    type Error = GeoError;
+
+    fn try_from(raw: RawGeometry) -> Result<Self, Self::Error> {
+        match raw {
+            RawGeometry::Point { coordinates } => Ok(Geometry::Point(Point(coordinates))),
+            RawGeometry::Polygon { coordinates } => {
+                Polygon::new(coordinates).map(Geometry::Polygon)
+            }
+        }
+    }
+}
+
+impl From<Point> for Geometry {
+    fn from(point: Point) -> Self {
+        Geometry::Point(point)
+    }
+}
+
+impl From<Polygon> for Geometry {
+    fn from(polygon: Polygon) -> Self {
+        Geometry::Polygon(polygon)
+    }
+}
+
+impl TryFrom<Geometry> for Point {
+    type Error = String;
+
+    fn try_from(geometry: Geometry) -> Result<Self, Self::Error> {
+        match geometry {
+            Geometry::Point(point) => Ok(point),
+            Geometry::Polygon(_) => Err("expected a Point, found a Polygon".to_string()),
+        }
+    }
+}
+
+impl TryFrom<Geometry> for Polygon {
+    type Error = String;
+
+    fn try_from(geometry: Geometry) -> Result<Self, Self::Error> {
+        match geometry {
+            Geometry::Polygon(polygon) => Ok(polygon),
+            Geometry::Point(_) => Err("expected a Polygon, found a Point".to_string()),
+        }
+    }
+}
+
+/// A GeoJSON `Feature`: a geometry with properties
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "Feature")]
+pub struct Feature<P> {
+    /// Identifier of the feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    /// Where the feature is, if anywhere
+    pub geometry: Option<Geometry>,
+    /// What the feature is
+    pub properties: P,
+}
+
+impl<P> Feature<P> {
+    /// A feature at `geometry`
+    pub fn new(geometry: impl Into<Geometry>, properties: P) -> Self {
+        Self {
+            id: None,
+            geometry: Some(geometry.into()),
+            properties,
+        }
+    }
+
+    /// Set the identifier
+    pub fn id(mut self, id: impl Into<Value>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+}
+
+/// A GeoJSON `FeatureCollection`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "FeatureCollection")]
+pub struct FeatureCollection<P> {
+    /// The features
+    pub features: Vec<Feature<P>>,
+}
+
+impl<P> FromIterator<Feature<P>> for FeatureCollection<P> {
+    fn from_iter<I: IntoIterator<Item = Feature<P>>>(features: I) -> Self {
+        Self {
+            features: features.into_iter().collect(),
+        }
+    }
+}
+
+/// GeoJSON body, sent as `application/geo+json`
+///
+/// As an extractor it works like `Json<T>`, which already accepts
+/// `application/geo+json`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/stores")]
+/// async fn stores(Inject(repo): Inject<StoreRepo>) -> ApiResult<GeoJson<FeatureCollection<Store>>> {
+///     let stores = repo.all().await?;
+///     Ok(GeoJson(stores.into_iter().map(|s| Feature::new(s.location, s)).collect()))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeoJson<T>(pub T);
+
+impl<T: Serialize> IntoResponse for GeoJson<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        if response.status() == StatusCode::OK {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(GEOJSON_CONTENT_TYPE),
+            );
+        }
+        response
+    }
+}
+
+impl<T, S> FromRequest<S> for GeoJson<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(GeoJson(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    fn square() -> Polygon {
+        let p = |lon, lat| Position::new(lon, lat).unwrap();
+        Polygon::new(vec![
+            vec![
+                p(0.0, 0.0),
+                p(10.0, 0.0),
+                p(10.0, 10.0),
+                p(0.0, 10.0),
+                p(0.0, 0.0),
+            ],
+            vec![p(4.0, 4.0), p(6.0, 4.0), p(6.0, 6.0), p(4.0, 4.0)],
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_positions_are_checked() {
+        assert!(Point::new(-180.0, 90.0).is_ok());
+        assert_eq!(Point::new(0.0, 91.0), Err(GeoError::Latitude(91.0)));
+        assert_eq!(Point::new(181.0, 0.0), Err(GeoError::Longitude(181.0)));
+        assert!(Point::new(f64::NAN, 0.0).is_err());
+
+        let point: Point =
+            serde_json::from_value(json!({ "type": "Point", "coordinates": [2.35, 48.85] }))
+                .unwrap();
+        assert_eq!((point.lon(), point.lat()), (2.35, 48.85));
+        assert_eq!(
+            serde_json::to_value(point).unwrap(),
+            json!({ "type": "Point", "coordinates": [2.35, 48.85] })
+        );
+        for invalid in [
+            json!({ "type": "Point", "coordinates": [48.85, 200.0] }),
+            json!({ "type": "Point", "coordinates": [1.0] }),
+            json!({ "type": "Polygon", "coordinates": [] }),
+            json!({ "coordinates": [2.35, 48.85] }),
+        ] {
+            assert!(
+                serde_json::from_value::<Point>(invalid.clone()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_polygons() {
+        let polygon = square();
+        let at = |lon, lat| Position::new(lon, lat).unwrap();
+        assert!(polygon.contains(&at(2.0, 2.0)));
+        assert!(!polygon.contains(&at(5.0, 5.0)));
+        assert!(!polygon.contains(&at(12.0, 2.0)));
+
+        let value = serde_json::to_value(&polygon).unwrap();
+        assert_eq!(value["type"], "Polygon");
+        assert_eq!(serde_json::from_value::<Polygon>(value).unwrap(), polygon);
+
+        let open = json!({ "type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 1]]] });
+        let error = serde_json::from_value::<Geometry>(open).unwrap_err();
+        assert!(error.to_string().contains("must end at its first position"));
+        assert_eq!(Polygon::new(vec![]), Err(GeoError::NoRing));
+    }
+
+    #[tokio::test]
+    async fn test_geojson_body() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Store {
+            name: String,
+        }
+
+        let stores: FeatureCollection<Store> = [Feature::new(
+            Point::new(2.35, 48.85).unwrap(),
+            Store {
+                name: "Paris".to_string(),
+            },
+        )
+        .id(1)]
+        .into_iter()
+        .collect();
+        let response = GeoJson(&stores).into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            GEOJSON_CONTENT_TYPE
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["type"], "FeatureCollection");
+        assert_eq!(value["features"][0]["type"], "Feature");
+        assert_eq!(value["features"][0]["geometry"]["type"], "Point");
+
+        let request = Request::builder()
+            .header("content-type", GEOJSON_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap();
+        let GeoJson(parsed) = GeoJson::<FeatureCollection<Store>>::from_request(request, &())
+            .await
+            .unwrap();
+        assert_eq!(parsed, stores);
+
+        let bad = r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[0,100]},"properties":{"name":"x"}}"#;
+        let request = Request::builder()
+            .header("content-type", GEOJSON_CONTENT_TYPE)
+            .body(Body::from(bad))
+            .unwrap();
+        let error = GeoJson::<Feature<Store>>::from_request(request, &())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[cfg(feature = "validator")]
+    #[test]
+    fn test_validators() {
+        assert!(latitude(&45.0).is_ok());
+        assert_eq!(longitude(&-200.0).unwrap_err().code, "longitude");
+    }
+}
@@ -0,0 +1,184 @@
+//! Route groups for RustAPI framework
+//!
+//! A route group is a set of routes sharing a path prefix, a middleware stack
+//! and default guards, built with `App::group`.
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::Request,
+    middleware,
+    response::IntoResponse,
+    routing::{MethodRouter, Route},
+};
+use tower::{Layer, Service};
+
+use crate::{
+    guard::{self, Guard, Guards},
+    interceptor::{self, Interceptor, Interceptors},
+    router::Router,
+};
+
+// deferred layer application, so layers cover routes added after them
+type LayerFn = Box<dyn FnOnce(Router) -> Router + Send>;
+
+/// Builder for a set of routes sharing a prefix, layers and guards
+///
+/// Layers and guards apply to every route in the group, regardless of the
+/// order in which `route`, `layer` and `guard` are called. Guards run inside
+/// the group's layers in registration order, before any `#[guard]` of the
+/// handler itself; interceptors run on the handler's response, inside the
+/// guards. See `rust_api::interceptor` for the complete execution order.
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new().group("/api", |g| {
+///     g.layer(TraceLayer::new_for_http())
+///         .guard(require_api_key)
+///         .route("/users", routing::get(list_users))
+///         .route("/users/{id}", routing::get(get_user))
+/// });
+/// ```
+pub struct RouteGroup {
+    router: Router,
+    layers: Vec<LayerFn>,
+    guards: Vec<Arc<dyn Guard>>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+}
+
+impl RouteGroup {
+    /// Create an empty group
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            layers: Vec::new(),
+            guards: Vec::new(),
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Add a route to the group, relative to the group's prefix
+    pub fn route(mut self, path: &str, method_router: MethodRouter) -> Self {
+        self.router = self.router.route(path, method_router);
+        self
+    }
+
+    /// Add a middleware layer to every route in the group
+    ///
+    /// Layers added later wrap layers added earlier, as with `Router::layer`.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.layers
+            .push(Box::new(move |router| router.layer(layer)));
+        self
+    }
+
+    /// Add a guard to every route in the group
+    pub fn guard(mut self, guard: impl Guard) -> Self {
+        self.guards.push(Arc::new(guard));
+        self
+    }
+
+    /// Add an interceptor to every route in the group
+    pub fn intercept(mut self, interceptor: impl Interceptor) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Apply interceptors, guards and layers and return the group's router
+    pub fn finish(self) -> Router {
+        let mut router = self.router;
+
+        if !self.interceptors.is_empty() {
+            let interceptors: Interceptors = Arc::from(self.interceptors);
+            router = router.layer(middleware::from_fn_with_state(
+                interceptors,
+                interceptor::run_interceptors,
+            ));
+        }
+        if !self.guards.is_empty() {
+            let guards: Guards = Arc::from(self.guards);
+            router = router.layer(middleware::from_fn_with_state(guards, guard::run_guards));
+        }
+        for apply in self.layers {
+            router = apply(router);
+        }
+
+        router
+    }
+}
+
+impl Default for RouteGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{request::Parts, HeaderValue, StatusCode},
+        routing::get,
+    };
+    use tower::ServiceExt;
+    use tower_http::set_header::SetResponseHeaderLayer;
+
+    use super::*;
+    use crate::error::ApiError;
+
+    fn deny_all(_: &Parts) -> Result<(), ApiError> {
+        Err(ApiError::forbidden("denied"))
+    }
+
+    async fn status_and_header(router: Router) -> (StatusCode, Option<HeaderValue>) {
+        let request = Request::builder()
+            .uri("/users")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        (
+            response.status(),
+            response.headers().get("x-group").cloned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_layer_applies_to_later_routes() {
+        let router = RouteGroup::new()
+            .layer(SetResponseHeaderLayer::overriding(
+                axum::http::HeaderName::from_static("x-group"),
+                HeaderValue::from_static("api"),
+            ))
+            .route("/users", get(|| async { "users" }))
+            .finish();
+
+        let (status, header) = status_and_header(router).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(header, Some(HeaderValue::from_static("api")));
+    }
+
+    #[tokio::test]
+    async fn test_guard_runs_inside_layers() {
+        let router = RouteGroup::new()
+            .route("/users", get(|| async { "users" }))
+            .guard(deny_all)
+            .layer(SetResponseHeaderLayer::overriding(
+                axum::http::HeaderName::from_static("x-group"),
+                HeaderValue::from_static("api"),
+            ))
+            .finish();
+
+        // the rejection still passes back through the group's layers
+        let (status, header) = status_and_header(router).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(header, Some(HeaderValue::from_static("api")));
+    }
+}
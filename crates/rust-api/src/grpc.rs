@@ -0,0 +1,369 @@
+//! gRPC client configuration for RustAPI framework
+//!
+//! `GrpcClients` holds the settings of every downstream gRPC service by
+//! name, loaded with `ConfigLoader` from a `grpc` section and registered in
+//! the container so services inject one place to look them up.
+//!
+//! With the `grpc` feature, `GrpcClients::channel` gives the tonic
+//! `Channel` of a service, built on first use with its endpoint, TLS and
+//! timeout settings and shared afterwards: a channel connects lazily,
+//! multiplexes calls over its connection and reconnects on its own, so one
+//! per service is all an app needs. Without the feature, build channels
+//! from `GrpcClientConfig::endpoint` with the app's own tonic version.
+//! Either way, `GrpcClientConfig::metadata` gives what every call needs:
+//! the configured auth metadata, the current request id and a
+//! `grpc-timeout` capped to the request's remaining deadline, ready to copy
+//! into a tonic interceptor.
+
+use std::{collections::BTreeMap, time::Duration};
+#[cfg(feature = "grpc")]
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+
+use crate::{
+    client::propagate_request_id,
+    context,
+    di::Injectable,
+    error::{Error, Result},
+};
+
+/// Default deadline of a call made outside a request
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings of one downstream gRPC service
+///
+/// # Example
+///
+/// ```toml
+/// [grpc.inventory]
+/// endpoint = "https://inventory.internal:443"
+/// tls = true
+/// timeout_ms = 2000
+/// metadata = { authorization = "Bearer enc:v1:..." }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GrpcClientConfig {
+    /// URI of the service, such as `http://localhost:50051`
+    pub endpoint: String,
+    /// Connect with TLS
+    #[serde(default)]
+    pub tls: bool,
+    /// Name checked against the server certificate, when it differs from
+    /// the endpoint's host
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Deadline of a call made outside a request, in milliseconds
+    /// (default: 10s)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Metadata sent with every call, such as `authorization`
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl GrpcClientConfig {
+    /// Call the service at `endpoint`
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            tls: false,
+            domain: None,
+            timeout_ms: None,
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    // deadline of a call made outside a request
+    fn configured_timeout(&self) -> Duration {
+        self.timeout_ms
+            .map_or(DEFAULT_TIMEOUT, Duration::from_millis)
+    }
+
+    /// Deadline of a call made now: the configured timeout, capped to the
+    /// current request's remaining deadline
+    pub fn timeout(&self) -> Duration {
+        context::cap_timeout(self.configured_timeout())
+    }
+
+    /// Metadata of a call made now
+    ///
+    /// Holds the configured metadata, `x-request-id` inside a request and
+    /// `grpc-timeout` set to `timeout`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let config = clients.get("inventory")?.clone();
+    /// let client = InventoryClient::with_interceptor(channel, move |mut req: tonic::Request<()>| {
+    ///     for (name, value) in &config.metadata().unwrap() {
+    ///         req.metadata_mut().insert(name.as_str().parse().unwrap(), value.to_str().unwrap().parse().unwrap());
+    ///     }
+    ///     Ok(req)
+    /// });
+    /// ```
+    pub fn metadata(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.metadata {
+            let name = HeaderName::try_from(name.as_str())
+                .map_err(|e| Error::other(format!("Invalid gRPC metadata key {}: {}", name, e)))?;
+            let value = HeaderValue::try_from(value.as_str()).map_err(|e| {
+                Error::other(format!("Invalid gRPC metadata value for {}: {}", name, e))
+            })?;
+            headers.insert(name, value);
+        }
+        propagate_request_id(&mut headers);
+        let timeout =
+            HeaderValue::try_from(grpc_timeout(self.timeout())).expect("grpc-timeout is ASCII");
+        headers.insert("grpc-timeout", timeout);
+        Ok(headers)
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl GrpcClientConfig {
+    // a lazily connecting channel to the service
+    fn connect_lazy(&self) -> Result<tonic::transport::Channel> {
+        use tonic::transport::{ClientTlsConfig, Endpoint};
+
+        let invalid = |e: tonic::transport::Error| {
+            Error::other(format!("Invalid gRPC endpoint {}: {}", self.endpoint, e))
+        };
+        let mut endpoint = Endpoint::from_shared(self.endpoint.clone())
+            .map_err(invalid)?
+            .timeout(self.configured_timeout());
+        if self.tls {
+            let mut tls = ClientTlsConfig::new().with_native_roots();
+            if let Some(domain) = &self.domain {
+                tls = tls.domain_name(domain.clone());
+            }
+            endpoint = endpoint.tls_config(tls).map_err(invalid)?;
+        }
+        Ok(endpoint.connect_lazy())
+    }
+}
+
+/// Downstream gRPC services by name
+///
+/// Deserializes from a table of `GrpcClientConfig`s. Cheap to clone;
+/// clones share the channels built with `channel`. Register it in the
+/// container and inject it into services.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Settings {
+///     grpc: GrpcClients,
+/// }
+///
+/// let settings: Settings = ConfigLoader::new().file("config.toml").load()?;
+/// app.container_mut().register(Arc::new(settings.grpc));
+///
+/// // with the `grpc` feature
+/// let client = InventoryClient::new(clients.channel("inventory")?);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct GrpcClients {
+    clients: BTreeMap<String, GrpcClientConfig>,
+    #[cfg(feature = "grpc")]
+    #[serde(skip)]
+    channels: Channels,
+}
+
+// channels built so far, by service name
+#[cfg(feature = "grpc")]
+#[derive(Clone, Default)]
+struct Channels(Arc<Mutex<HashMap<String, tonic::transport::Channel>>>);
+
+#[cfg(feature = "grpc")]
+impl fmt::Debug for Channels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Channels")
+    }
+}
+
+// a cache of the configuration, so it doesn't make sets unequal
+#[cfg(feature = "grpc")]
+impl PartialEq for Channels {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Injectable for GrpcClients {}
+
+impl GrpcClients {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the service `name`, replacing any with the same name
+    pub fn client(mut self, name: impl Into<String>, config: GrpcClientConfig) -> Self {
+        let name = name.into();
+        #[cfg(feature = "grpc")]
+        self.channels
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&name);
+        self.clients.insert(name, config);
+        self
+    }
+
+    /// Settings of the service `name`
+    ///
+    /// Fails when it is not configured, so a missing section is reported by
+    /// name instead of as a failed connection.
+    pub fn get(&self, name: &str) -> Result<&GrpcClientConfig> {
+        self.clients
+            .get(name)
+            .ok_or_else(|| Error::other(format!("No gRPC client named {} is configured", name)))
+    }
+
+    /// The channel to the service `name`, built on first use and shared
+    /// afterwards
+    ///
+    /// The channel connects on its first call and reconnects when the
+    /// connection drops, so this only fails when `name` is not configured
+    /// or its settings are invalid. Call it within a Tokio runtime.
+    #[cfg(feature = "grpc")]
+    pub fn channel(&self, name: &str) -> Result<tonic::transport::Channel> {
+        let mut channels = self.channels.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(channel) = channels.get(name) {
+            return Ok(channel.clone());
+        }
+        let channel = self.get(name)?.connect_lazy()?;
+        channels.insert(name.to_string(), channel.clone());
+        Ok(channel)
+    }
+
+    /// Every configured service, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &GrpcClientConfig)> {
+        self.clients
+            .iter()
+            .map(|(name, config)| (name.as_str(), config))
+    }
+}
+
+/// Encode `timeout` as a `grpc-timeout` value
+///
+/// Uses the finest unit that fits the protocol's 8 digits, rounding up so
+/// the server never sees a longer deadline than the caller has.
+pub fn grpc_timeout(timeout: Duration) -> String {
+    const MAX: u128 = 99_999_999;
+    let nanos = timeout.as_nanos();
+    for (unit, per) in [
+        ("n", 1),
+        ("u", 1_000),
+        ("m", 1_000_000),
+        ("S", 1_000_000_000),
+        ("M", 60_000_000_000),
+    ] {
+        let value = nanos.div_ceil(per);
+        if value <= MAX {
+            return format!("{}{}", value, unit);
+        }
+    }
+    format!("{}H", nanos.div_ceil(3_600_000_000_000).min(MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::context::RequestContext;
+
+    #[test]
+    fn test_grpc_timeout() {
+        assert_eq!(grpc_timeout(Duration::from_millis(50)), "50000000n");
+        assert_eq!(grpc_timeout(Duration::from_secs(2)), "2000000u");
+        assert_eq!(grpc_timeout(Duration::from_nanos(100_000_001)), "100001u");
+        assert_eq!(grpc_timeout(Duration::from_secs(3600)), "3600000m");
+        assert_eq!(grpc_timeout(Duration::MAX), "99999999H");
+    }
+
+    #[test]
+    fn test_clients_from_config() {
+        let clients: GrpcClients = serde_json::from_value(serde_json::json!({
+            "inventory": {
+                "endpoint": "https://inventory:443",
+                "tls": true,
+                "timeout_ms": 2000,
+                "metadata": { "authorization": "Bearer token" }
+            },
+            "search": { "endpoint": "http://search:50051" }
+        }))
+        .unwrap();
+        let inventory = clients.get("inventory").unwrap();
+        assert!(inventory.tls);
+        assert_eq!(inventory.timeout(), Duration::from_secs(2));
+        assert_eq!(clients.get("search").unwrap().timeout(), DEFAULT_TIMEOUT);
+        assert!(clients.get("billing").is_err());
+        assert_eq!(clients.iter().count(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metadata_carries_request_deadline() {
+        let mut config = GrpcClientConfig::new("http://search:50051");
+        config
+            .metadata
+            .insert("authorization".to_string(), "Bearer token".to_string());
+
+        let headers = config.metadata().unwrap();
+        assert_eq!(headers["authorization"], "Bearer token");
+        assert_eq!(headers["grpc-timeout"], "10000000u");
+        assert!(headers.get("x-request-id").is_none());
+
+        let ctx = RequestContext::build(
+            "req-1".to_string(),
+            Method::GET,
+            None,
+            None,
+            Some(Instant::now() + Duration::from_millis(300)),
+        );
+        ctx.scope(async {
+            let headers = config.metadata().unwrap();
+            assert_eq!(headers["x-request-id"], "req-1");
+            assert_eq!(headers["grpc-timeout"], "300000u");
+        })
+        .await;
+
+        config
+            .metadata
+            .insert("bad key".to_string(), "x".to_string());
+        assert!(config.metadata().is_err());
+    }
+
+    #[cfg(feature = "grpc")]
+    #[tokio::test]
+    async fn test_channels_are_shared() {
+        let mut secure = GrpcClientConfig::new("https://inventory:443");
+        secure.tls = true;
+        secure.domain = Some("inventory.internal".to_string());
+        let clients = GrpcClients::new()
+            .client("inventory", secure)
+            .client("search", GrpcClientConfig::new("http://search:50051"))
+            .client("broken", GrpcClientConfig::new("not a uri"));
+
+        let shared = clients.clone();
+        clients.channel("search").unwrap();
+        shared.channel("search").unwrap();
+        clients.channel("inventory").unwrap();
+        assert_eq!(clients.channels.0.lock().unwrap().len(), 2);
+        assert!(clients.channel("broken").is_err());
+        assert!(clients.channel("billing").is_err());
+
+        let clients = clients.client("search", GrpcClientConfig::new("http://search:50052"));
+        assert_eq!(clients.channels.0.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,334 @@
+//! Route guards for RustAPI framework
+//!
+//! A guard inspects an incoming request before it reaches the handler and
+//! either lets it through or rejects it with a response. Guards see the
+//! request parts and the application's DI container, so authorization,
+//! feature flags and tenant checks all share one mechanism.
+//!
+//! Guards are attached to a group with `RouteGroup::guard` or to a single
+//! handler with `#[guard(...)]`. Group guards run inside the group's layers;
+//! handler guards run after them, before any of the handler's extractors.
+//! Within a list, guards run in order and the first rejection wins.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{di::Container, error::ApiError, lifecycle::BoxFuture};
+
+/// Check that runs before a handler and may reject the request
+///
+/// Implemented for plain closures taking the request parts, which covers
+/// most header- and path-based checks; closures that also need services use
+/// `guard::from_fn`.
+///
+/// # Example
+///
+/// ```ignore
+/// struct RequireFeature(&'static str);
+///
+/// impl Guard for RequireFeature {
+///     fn check<'a>(
+///         &'a self,
+///         _parts: &'a mut Parts,
+///         container: &'a Container,
+///     ) -> BoxFuture<'a, Result<(), ApiError>> {
+///         Box::pin(async move {
+///             let flags = container
+///                 .resolve::<Flags>()
+///                 .ok_or_else(|| ApiError::internal("Flags not registered"))?;
+///             match flags.enabled(self.0).await {
+///                 true => Ok(()),
+///                 false => Err(ApiError::not_found("Not found")),
+///             }
+///         })
+///     }
+/// }
+///
+/// #[guard(require_api_key, RequireFeature("beta"))]
+/// #[get("/beta/reports")]
+/// async fn reports() -> Json<Vec<Report>> { ... }
+/// ```
+pub trait Guard: Send + Sync + 'static {
+    /// Allow the request with `Ok(())`, or reject it with an error
+    ///
+    /// A guard may add extensions to the request, e.g. the authenticated
+    /// `Principal`, for the extractors and guards after it.
+    fn check<'a>(
+        &'a self,
+        parts: &'a mut Parts,
+        container: &'a Container,
+    ) -> BoxFuture<'a, Result<(), ApiError>>;
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&Parts) -> Result<(), ApiError> + Send + Sync + 'static,
+{
+    fn check<'a>(
+        &'a self,
+        parts: &'a mut Parts,
+        _container: &'a Container,
+    ) -> BoxFuture<'a, Result<(), ApiError>> {
+        let result = self(parts);
+        Box::pin(async move { result })
+    }
+}
+
+/// Guard built from a closure taking the request parts and the container
+pub struct FnGuard<F>(F);
+
+/// Create a guard from a closure that also needs the DI container
+///
+/// # Example
+///
+/// ```ignore
+/// let same_tenant = guard::from_fn(|parts: &Parts, container: &Container| {
+///     let sessions = container
+///         .resolve::<Sessions>()
+///         .ok_or_else(|| ApiError::internal("Sessions not registered"))?;
+///     sessions.check_tenant(parts)
+/// });
+/// ```
+pub fn from_fn<F>(check: F) -> FnGuard<F>
+where
+    F: Fn(&Parts, &Container) -> Result<(), ApiError> + Send + Sync + 'static,
+{
+    FnGuard(check)
+}
+
+impl<F> Guard for FnGuard<F>
+where
+    F: Fn(&Parts, &Container) -> Result<(), ApiError> + Send + Sync + 'static,
+{
+    fn check<'a>(
+        &'a self,
+        parts: &'a mut Parts,
+        container: &'a Container,
+    ) -> BoxFuture<'a, Result<(), ApiError>> {
+        let result = (self.0)(parts, container);
+        Box::pin(async move { result })
+    }
+}
+
+/// Ordered list of guards shared by a set of routes
+pub(crate) type Guards = Arc<[Arc<dyn Guard>]>;
+
+// run guards in order, stopping at the first rejection
+async fn check_all(guards: &[Arc<dyn Guard>], parts: &mut Parts) -> Result<(), ApiError> {
+    let container = match parts.extensions.get::<Arc<Container>>() {
+        Some(container) => Arc::clone(container),
+        None => Arc::new(Container::new()),
+    };
+    for guard in guards {
+        guard.check(parts, &container).await?;
+    }
+    Ok(())
+}
+
+/// Middleware running each guard in order, stopping at the first rejection
+pub(crate) async fn run_guards(State(guards): State<Guards>, req: Request, next: Next) -> Response {
+    let (mut parts, body) = req.into_parts();
+    if let Err(rejection) = check_all(&guards, &mut parts).await {
+        return rejection.into_response();
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Guards of one handler, generated by `#[guard(...)]`
+pub trait GuardList: 'static {
+    /// The guards, in the order they were listed
+    fn guards() -> &'static [Arc<dyn Guard>];
+}
+
+/// Extractor running the guards of `G`
+///
+/// Inserted as the first parameter of a handler by `#[guard(...)]`, so the
+/// guards run before every other extractor.
+pub struct Guarded<G>(PhantomData<fn() -> G>);
+
+impl<G, S> FromRequestParts<S> for Guarded<G>
+where
+    G: GuardList,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        check_all(G::guards(), parts).await?;
+        Ok(Guarded(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::router::Router;
+
+    fn require_header(parts: &Parts) -> Result<(), ApiError> {
+        if parts.headers.contains_key("x-api-key") {
+            Ok(())
+        } else {
+            Err(ApiError::unauthorized("Missing API key"))
+        }
+    }
+
+    fn guarded_router() -> Router {
+        let guards: Guards = Arc::from(vec![Arc::new(require_header) as Arc<dyn Guard>]);
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(guards, run_guards))
+    }
+
+    #[tokio::test]
+    async fn test_guard_rejects() {
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let response = guarded_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_guard_allows() {
+        let request = Request::builder()
+            .uri("/")
+            .header("x-api-key", "secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = guarded_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    mod attribute {
+        use super::*;
+        use crate::{app::App, controller, di::Injectable, get, guard};
+
+        struct Flags(Vec<&'static str>);
+
+        impl Injectable for Flags {}
+
+        struct RequireFlag(&'static str);
+
+        impl Guard for RequireFlag {
+            fn check<'a>(
+                &'a self,
+                _parts: &'a mut Parts,
+                container: &'a Container,
+            ) -> BoxFuture<'a, Result<(), ApiError>> {
+                Box::pin(async move {
+                    let flags = container
+                        .resolve::<Flags>()
+                        .ok_or_else(|| ApiError::internal("Flags not registered"))?;
+                    match flags.0.contains(&self.0) {
+                        true => Ok(()),
+                        false => Err(ApiError::not_found("Not found")),
+                    }
+                })
+            }
+        }
+
+        #[guard(require_header, RequireFlag("beta"))]
+        #[get("/reports")]
+        async fn reports() -> &'static str {
+            "reports"
+        }
+
+        struct Admin;
+
+        #[controller("/admin")]
+        #[guard(require_header)]
+        impl Admin {
+            #[get("/stats")]
+            async fn stats(&self) -> &'static str {
+                "stats"
+            }
+
+            #[guard(RequireFlag("audit"))]
+            #[get("/audit")]
+            async fn audit(&self) -> &'static str {
+                "audit"
+            }
+        }
+
+        async fn status(app: App, uri: &str, key: bool) -> StatusCode {
+            let mut request = Request::get(uri);
+            if key {
+                request = request.header("x-api-key", "secret");
+            }
+            let request = request.body(Body::empty()).unwrap();
+            app.build().oneshot(request).await.unwrap().status()
+        }
+
+        fn app(flags: Vec<&'static str>) -> App {
+            let mut app = App::new()
+                .route(__reports_route, get(reports))
+                .merge(Arc::new(Admin).router());
+            app.container_mut().register(Arc::new(Flags(flags)));
+            app
+        }
+
+        #[tokio::test]
+        async fn test_guards_see_request_and_container() {
+            assert_eq!(
+                status(app(vec!["beta"]), "/reports", false).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                status(app(vec![]), "/reports", true).await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                status(app(vec!["beta"]), "/reports", true).await,
+                StatusCode::OK
+            );
+        }
+
+        #[tokio::test]
+        async fn test_controller_guards() {
+            assert_eq!(
+                status(app(vec![]), "/admin/stats", false).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                status(app(vec![]), "/admin/stats", true).await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status(app(vec![]), "/admin/audit", true).await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                status(app(vec!["audit"]), "/admin/audit", true).await,
+                StatusCode::OK
+            );
+        }
+
+        #[tokio::test]
+        async fn test_from_fn_guard_in_group() {
+            let app = || {
+                App::new().group("/beta", |group| {
+                    group
+                        .guard(from_fn(|_: &Parts, container: &Container| match container
+                            .contains::<Flags>()
+                        {
+                            true => Ok(()),
+                            false => Err(ApiError::forbidden("No flags")),
+                        }))
+                        .route("/", get(|| async { "ok" }))
+                })
+            };
+            assert_eq!(status(app(), "/beta", false).await, StatusCode::FORBIDDEN);
+
+            let mut app = app();
+            app.container_mut().register(Arc::new(Flags(vec![])));
+            assert_eq!(status(app, "/beta", false).await, StatusCode::OK);
+        }
+    }
+}
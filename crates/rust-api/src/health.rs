@@ -0,0 +1,690 @@
+//! Health and readiness probes for RustAPI framework
+//!
+//! Provides `/healthz` (liveness) and `/readyz` (readiness) endpoints backed by
+//! a shared `Readiness` flag that the server flips during startup and
+//! shutdown.
+//!
+//! `HealthChecks` adds dependency checks to the probes. Each check has a
+//! `Severity`, and each probe a threshold: by default `/readyz` fails when a
+//! critical check fails, while `/healthz` ignores dependencies, so a
+//! database outage takes instances out of rotation instead of restarting
+//! them. Results are cached for a few seconds, and concurrent probes share
+//! one run, so probe storms do not reach the dependencies.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json};
+use futures_util::future::join_all;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{
+    boot,
+    clock::{Clock, SystemClock},
+    error::{Error, Result},
+    lifecycle::{BoxFuture, OnStart},
+    retry::RetryPolicy,
+    router::Router,
+};
+
+/// Path of the liveness probe
+pub const LIVENESS_PATH: &str = "/healthz";
+
+/// Path of the readiness probe
+pub const READINESS_PATH: &str = "/readyz";
+
+/// Default time a check result is reused for
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Default time a check may take before it counts as failed
+pub const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Shared readiness flag
+///
+/// Starts as not ready. `RustAPI::serve` marks it ready once all start hooks
+/// have finished and not ready again as soon as graceful shutdown begins.
+/// Cheap to clone; all clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness {
+    ready: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    /// Create a flag in the not-ready state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether the application is ready to receive traffic
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Mark the application as ready or not ready
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+}
+
+/// How much a failing check matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Reported, but the application works without it
+    Informational,
+    /// The application cannot serve requests without it
+    Critical,
+}
+
+/// Check of one dependency, such as a database or a downstream service
+///
+/// Implemented for async closures and for `Arc`-wrapped services.
+///
+/// # Example
+///
+/// ```ignore
+/// let db = pool.clone();
+/// let checks = HealthChecks::new().check("db", Severity::Critical, move || {
+///     let db = db.clone();
+///     async move {
+///         sqlx::query("SELECT 1").execute(&db).await.map_err(|e| Error::other(e.to_string()))?;
+///         Ok(())
+///     }
+/// });
+/// ```
+pub trait HealthCheck: Send + Sync + 'static {
+    /// Check the dependency; an error marks it as failing
+    fn check(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+impl<F, Fut> HealthCheck for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn check(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self())
+    }
+}
+
+impl<T: HealthCheck> HealthCheck for Arc<T> {
+    fn check(&self) -> BoxFuture<'_, Result<()>> {
+        (**self).check()
+    }
+}
+
+// a registered check and its last result
+struct Check {
+    name: String,
+    severity: Severity,
+    check: Box<dyn HealthCheck>,
+    last: Mutex<Option<(Instant, std::result::Result<(), String>)>>,
+}
+
+/// Dependency checks reported by the probes
+///
+/// Cheap to clone; clones share the cached results.
+///
+/// # Example
+///
+/// ```ignore
+/// let checks = HealthChecks::new()
+///     .check("db", Severity::Critical, db_check)
+///     .check("search", Severity::Informational, search_check)
+///     .cache_for(Duration::from_secs(10));
+///
+/// RustAPI::new(app.build()).health_checks(checks).serve().await?;
+/// ```
+#[derive(Clone)]
+pub struct HealthChecks {
+    checks: Vec<Arc<Check>>,
+    clock: Arc<dyn Clock>,
+    cache_ttl: Duration,
+    timeout: Duration,
+    liveness: Option<Severity>,
+    readiness: Option<Severity>,
+}
+
+impl HealthChecks {
+    /// Create an empty set
+    ///
+    /// `/readyz` fails on critical checks and `/healthz` on none.
+    pub fn new() -> Self {
+        Self {
+            checks: Vec::new(),
+            clock: Arc::new(SystemClock),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            timeout: DEFAULT_CHECK_TIMEOUT,
+            liveness: None,
+            readiness: Some(Severity::Critical),
+        }
+    }
+
+    /// Add the check `name`
+    pub fn check(mut self, name: &str, severity: Severity, check: impl HealthCheck) -> Self {
+        self.checks.push(Arc::new(Check {
+            name: name.to_string(),
+            severity,
+            check: Box::new(check),
+            last: Mutex::new(None),
+        }));
+        self
+    }
+
+    /// Reuse results for `ttl` (default: 5s); zero runs checks every time
+    pub fn cache_for(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// Age cached results by `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Fail checks that take longer than `timeout` (default: 2s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fail `/healthz` when a check of at least `severity` fails
+    ///
+    /// `None` (the default) keeps liveness independent of dependencies.
+    pub fn liveness_threshold(mut self, severity: Option<Severity>) -> Self {
+        self.liveness = severity;
+        self
+    }
+
+    /// Fail `/readyz` when a check of at least `severity` fails
+    ///
+    /// Defaults to `Severity::Critical`; `None` ignores dependencies.
+    pub fn readiness_threshold(mut self, severity: Option<Severity>) -> Self {
+        self.readiness = severity;
+        self
+    }
+
+    /// Number of checks
+    pub fn len(&self) -> usize {
+        self.checks.len()
+    }
+
+    /// Check whether there are no checks
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Run every check, reusing results younger than the cache period
+    pub async fn run(&self) -> HealthReport {
+        let checks = join_all(self.checks.iter().map(|check| self.run_one(check))).await;
+        HealthReport { checks }
+    }
+
+    // run one check, or reuse its last result
+    async fn run_one(&self, check: &Check) -> CheckStatus {
+        // held while checking, so concurrent probes wait for this result
+        let mut last = check.last.lock().await;
+        let (result, cached) = match &*last {
+            Some((at, result)) if self.clock.now().duration_since(*at) < self.cache_ttl => {
+                (result.clone(), true)
+            }
+            _ => {
+                let result = match tokio::time::timeout(self.timeout, check.check.check()).await {
+                    Ok(result) => result.map_err(|e| e.to_string()),
+                    Err(_) => Err(format!("timed out after {:?}", self.timeout)),
+                };
+                if let Err(error) = &result {
+                    tracing::warn!(check = %check.name, "Health check failing: {}", error);
+                }
+                *last = Some((self.clock.now(), result.clone()));
+                (result, false)
+            }
+        };
+        CheckStatus {
+            name: check.name.clone(),
+            severity: check.severity,
+            error: result.err(),
+            cached,
+        }
+    }
+
+    // report of the checks a probe with `threshold` depends on, if any
+    async fn probe(&self, threshold: Option<Severity>) -> Option<HealthReport> {
+        match threshold {
+            Some(_) if !self.is_empty() => Some(self.run().await),
+            _ => None,
+        }
+    }
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Result of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckStatus {
+    /// Name of the check
+    pub name: String,
+    /// Severity of the check
+    pub severity: Severity,
+    /// Why the check failed, if it did
+    pub error: Option<String>,
+    /// Whether the result was reused from an earlier run
+    pub cached: bool,
+}
+
+impl CheckStatus {
+    /// Check whether the dependency is healthy
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Results of every check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Results in registration order
+    pub checks: Vec<CheckStatus>,
+}
+
+impl HealthReport {
+    /// Check whether every check of at least `threshold` is healthy
+    pub fn passes(&self, threshold: Severity) -> bool {
+        self.checks
+            .iter()
+            .filter(|check| check.severity >= threshold)
+            .all(CheckStatus::is_healthy)
+    }
+
+    /// The status and severity of each check as JSON, by check name
+    ///
+    /// Error text is left out, as it can name hosts and users and the
+    /// probes answer anyone; failing checks log it instead.
+    pub fn to_json(&self) -> Value {
+        let checks: BTreeMap<&str, Value> = self
+            .checks
+            .iter()
+            .map(|check| {
+                let status = json!({
+                    "status": if check.is_healthy() { "ok" } else { "failing" },
+                    "severity": check.severity,
+                });
+                (check.name.as_str(), status)
+            })
+            .collect();
+        json!(checks)
+    }
+}
+
+/// Delay before the first retry of `wait_for`
+pub const WAIT_INITIAL_DELAY: Duration = Duration::from_millis(250);
+
+/// Run `check` until it succeeds, retrying with exponential backoff, for at
+/// most `timeout`
+///
+/// For dependencies that may come up after the application, such as a
+/// database starting alongside it. The last retry is made at `timeout`,
+/// with `DEFAULT_CHECK_TIMEOUT` to answer; if it fails too, fails with its
+/// error.
+pub async fn wait_for(name: &str, check: &dyn HealthCheck, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let policy = RetryPolicy::new(u32::MAX).delay(WAIT_INITIAL_DELAY);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let limit = match remaining.is_zero() {
+            true => DEFAULT_CHECK_TIMEOUT,
+            false => remaining,
+        };
+        let error = match tokio::time::timeout(limit, check.check()).await {
+            Ok(Ok(())) => {
+                tracing::info!(dependency = %name, attempt, "Dependency available");
+                return Ok(());
+            }
+            Ok(Err(error)) => error.to_string(),
+            Err(_) => "check timed out".to_string(),
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::other(format!(
+                "Dependency {} unavailable after {:?} ({} attempts): {}",
+                name, timeout, attempt, error
+            )));
+        }
+        // the last sleep ends at the deadline, for one final attempt
+        let delay = policy.delay_for(attempt).min(remaining);
+        tracing::warn!(
+            dependency = %name,
+            attempt,
+            "Dependency unavailable, retrying in {:?}: {}",
+            delay,
+            error
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Dependencies to wait for at startup, each with its own timeout
+///
+/// Registered with `App::wait_for`. As an `OnStart` hook it runs after the
+/// listener is bound, so the probes answer, with readiness false, while
+/// dependencies come up. Cheap to clone.
+///
+/// # Example
+///
+/// ```ignore
+/// let waits = app.dependency_waits();
+/// RustAPI::new(app.build())
+///     .health_probes(true)
+///     .on_start(waits)
+///     .serve()
+///     .await?;
+/// ```
+#[derive(Clone, Default)]
+pub struct DependencyWaits {
+    waits: Vec<(String, Arc<dyn HealthCheck>, Duration)>,
+}
+
+impl DependencyWaits {
+    /// Create an empty set of waits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait up to `timeout` for the dependency `name` to pass `check`
+    pub fn add(&mut self, name: &str, check: impl HealthCheck, timeout: Duration) {
+        self.waits
+            .push((name.to_string(), Arc::new(check), timeout));
+    }
+
+    /// Number of dependencies waited for
+    pub fn len(&self) -> usize {
+        self.waits.len()
+    }
+
+    /// Check whether there is nothing to wait for
+    pub fn is_empty(&self) -> bool {
+        self.waits.is_empty()
+    }
+
+    /// Wait for every dependency at once, failing with the first error
+    ///
+    /// Timed as the `dependencies` phase of the boot report.
+    pub async fn wait(&self) -> Result<()> {
+        if self.waits.is_empty() {
+            return Ok(());
+        }
+        let waits = self
+            .waits
+            .iter()
+            .map(|(name, check, timeout)| wait_for(name, check.as_ref(), *timeout));
+        boot::time_async("dependencies", join_all(waits))
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+impl OnStart for DependencyWaits {
+    fn on_start(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.wait())
+    }
+}
+
+// state of the probe handlers
+#[derive(Clone)]
+struct Probes {
+    readiness: Readiness,
+    checks: HealthChecks,
+}
+
+/// Build a router serving the liveness and readiness probes
+pub fn routes(readiness: Readiness) -> Router {
+    routes_with(readiness, HealthChecks::new())
+}
+
+/// Build a router serving the probes, reporting `checks`
+pub fn routes_with(readiness: Readiness, checks: HealthChecks) -> Router {
+    Router::new()
+        .route(LIVENESS_PATH, get(liveness))
+        .route(READINESS_PATH, get(readiness_probe))
+        .with_state(Probes { readiness, checks })
+}
+
+// liveness: the process is up and serving requests
+async fn liveness(State(probes): State<Probes>) -> (StatusCode, Json<Value>) {
+    let threshold = probes.checks.liveness;
+    match (probes.checks.probe(threshold).await, threshold) {
+        (Some(report), Some(threshold)) => {
+            let (status, label) = match report.passes(threshold) {
+                true => (StatusCode::OK, "ok"),
+                false => (StatusCode::SERVICE_UNAVAILABLE, "failing"),
+            };
+            (
+                status,
+                Json(json!({ "status": label, "checks": report.to_json() })),
+            )
+        }
+        _ => (StatusCode::OK, Json(json!({ "status": "ok" }))),
+    }
+}
+
+// readiness: the process should receive traffic
+async fn readiness_probe(State(probes): State<Probes>) -> (StatusCode, Json<Value>) {
+    if !probes.readiness.is_ready() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "not ready" })),
+        );
+    }
+    let threshold = probes.checks.readiness;
+    match (probes.checks.probe(threshold).await, threshold) {
+        (Some(report), Some(threshold)) => {
+            let (status, label) = match report.passes(threshold) {
+                true => (StatusCode::OK, "ready"),
+                false => (StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+            };
+            (
+                status,
+                Json(json!({ "status": label, "checks": report.to_json() })),
+            )
+        }
+        _ => (StatusCode::OK, Json(json!({ "status": "ready" }))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn probe(readiness: &Readiness, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        routes(readiness.clone())
+            .oneshot(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_readiness_starts_not_ready() {
+        let readiness = Readiness::new();
+        assert!(!readiness.is_ready());
+        assert_eq!(
+            probe(&readiness, READINESS_PATH).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(probe(&readiness, LIVENESS_PATH).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_flips() {
+        let readiness = Readiness::new();
+        readiness.set_ready(true);
+        assert_eq!(probe(&readiness, READINESS_PATH).await, StatusCode::OK);
+
+        readiness.set_ready(false);
+        assert_eq!(
+            probe(&readiness, READINESS_PATH).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    // a check whose outcome the test controls, counting its runs
+    #[derive(Default)]
+    struct Dependency {
+        down: AtomicBool,
+        runs: AtomicUsize,
+    }
+
+    impl HealthCheck for Dependency {
+        fn check(&self) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move {
+                self.runs.fetch_add(1, Ordering::SeqCst);
+                match self.down.load(Ordering::SeqCst) {
+                    true => Err(Error::other("connection refused")),
+                    false => Ok(()),
+                }
+            })
+        }
+    }
+
+    async fn get(router: &Router, path: &str) -> (StatusCode, Value) {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_probe_thresholds() {
+        let db = Arc::new(Dependency::default());
+        let search = Arc::new(Dependency::default());
+        let checks = HealthChecks::new()
+            .check("db", Severity::Critical, db.clone())
+            .check("search", Severity::Informational, search.clone())
+            .cache_for(Duration::ZERO);
+        let readiness = Readiness::new();
+        readiness.set_ready(true);
+        let router = routes_with(readiness, checks.clone());
+
+        search.down.store(true, Ordering::SeqCst);
+        let (status, body) = get(&router, READINESS_PATH).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["checks"]["search"],
+            json!({ "status": "failing", "severity": "informational" })
+        );
+
+        db.down.store(true, Ordering::SeqCst);
+        let (status, body) = get(&router, READINESS_PATH).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["db"]["severity"], "critical");
+
+        // liveness ignores dependencies unless told otherwise
+        let runs = db.runs.load(Ordering::SeqCst);
+        assert_eq!(get(&router, LIVENESS_PATH).await.0, StatusCode::OK);
+        assert_eq!(db.runs.load(Ordering::SeqCst), runs);
+
+        let strict = routes_with(
+            Readiness::new(),
+            checks.liveness_threshold(Some(Severity::Critical)),
+        );
+        db.down.store(false, Ordering::SeqCst);
+        assert_eq!(get(&strict, LIVENESS_PATH).await.0, StatusCode::OK);
+        db.down.store(true, Ordering::SeqCst);
+        assert_eq!(
+            get(&strict, LIVENESS_PATH).await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_results_are_cached_and_shared() {
+        let clock = crate::clock::TestClock::new();
+        let db = Arc::new(Dependency::default());
+        let checks = HealthChecks::new()
+            .check("db", Severity::Critical, db.clone())
+            .clock(Arc::new(clock.clone()))
+            .cache_for(Duration::from_secs(5));
+
+        let reports = join_all((0..10).map(|_| checks.run())).await;
+        assert_eq!(db.runs.load(Ordering::SeqCst), 1);
+        assert_eq!(reports.iter().filter(|r| !r.checks[0].cached).count(), 1);
+
+        clock.advance(Duration::from_secs(6));
+        db.down.store(true, Ordering::SeqCst);
+        let report = checks.run().await;
+        assert!(!report.passes(Severity::Informational));
+        assert_eq!(db.runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_checks_time_out() {
+        let checks = HealthChecks::new()
+            .check("slow", Severity::Critical, || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .timeout(Duration::from_millis(100));
+        let report = checks.run().await;
+        assert_eq!(
+            report.checks[0].error.as_deref(),
+            Some("timed out after 100ms")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_retries_with_backoff() {
+        let db = Arc::new(Dependency::default());
+        db.down.store(true, Ordering::SeqCst);
+        let started = Instant::now();
+        let recover = {
+            let db = db.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                db.down.store(false, Ordering::SeqCst);
+            }
+        };
+        let (result, ()) = tokio::join!(wait_for("db", &db, Duration::from_secs(60)), recover);
+        result.unwrap();
+        // checks at 0, 0.25, 0.75, 1.75 and 3.75 seconds
+        assert_eq!(db.runs.load(Ordering::SeqCst), 5);
+        assert_eq!(started.elapsed(), Duration::from_millis(3750));
+
+        db.down.store(true, Ordering::SeqCst);
+        let started = Instant::now();
+        let runs = db.runs.load(Ordering::SeqCst);
+        let error = wait_for("db", &db, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Dependency db unavailable after 5s (6 attempts)"));
+        // checks at 0, 0.25, 0.75, 1.75, 3.75 and, finally, 5 seconds
+        assert_eq!(db.runs.load(Ordering::SeqCst), runs + 6);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,145 @@
+//! Host-based routing for RustAPI framework
+//!
+//! Dispatches requests to different routers depending on the `Host` header,
+//! so several sites (e.g. an admin subdomain) can be served from one process.
+
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::Request,
+    http::{header, request::Parts},
+};
+use tower::ServiceExt;
+
+use crate::router::Router;
+
+/// Pattern matched against the request host
+///
+/// Either an exact host (`admin.example.com`) or a wildcard covering any
+/// subdomain (`*.example.com` matches `a.example.com` and `a.b.example.com`,
+/// but not `example.com` itself). Matching ignores case and port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostPattern {
+    pattern: String,
+}
+
+impl HostPattern {
+    /// Parse a host pattern
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into().to_ascii_lowercase(),
+        }
+    }
+
+    /// The pattern as written (lowercased)
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Check whether a host (optionally with a port) matches the pattern
+    pub fn matches(&self, host: &str) -> bool {
+        let host = strip_port(host).to_ascii_lowercase();
+        match self.pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == self.pattern,
+        }
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+// remove a trailing `:port` from a host, leaving IPv6 literals intact
+pub(crate) fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split(']').next().map_or(host, |h| &host[..=h.len()]);
+    }
+    host.rsplit_once(':').map_or(host, |(name, _)| name)
+}
+
+/// Host the request was sent to, from the `Host` header or the URI authority
+pub fn request_host(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| parts.uri.host())
+}
+
+/// Build a router that dispatches on the request host
+///
+/// Patterns are tried in order; requests matching none of them go to the
+/// default router. `App::host` does this automatically.
+pub fn route_by_host(default: Router, hosts: Vec<(HostPattern, Router)>) -> Router {
+    if hosts.is_empty() {
+        return default;
+    }
+
+    let hosts: Arc<[(HostPattern, Router)]> = Arc::from(hosts);
+    Router::new().fallback_service(tower::service_fn(move |req: Request| {
+        let (parts, body) = req.into_parts();
+        let router = request_host(&parts)
+            .and_then(|host| hosts.iter().find(|(pattern, _)| pattern.matches(host)))
+            .map_or_else(|| default.clone(), |(_, router)| router.clone());
+        router.oneshot(Request::from_parts(parts, body))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode, routing::get};
+
+    use super::*;
+
+    #[test]
+    fn test_exact_host_pattern() {
+        let pattern = HostPattern::new("Admin.Example.com");
+        assert!(pattern.matches("admin.example.com"));
+        assert!(pattern.matches("ADMIN.example.com:8080"));
+        assert!(!pattern.matches("www.example.com"));
+    }
+
+    #[test]
+    fn test_wildcard_host_pattern() {
+        let pattern = HostPattern::new("*.example.com");
+        assert!(pattern.matches("tenant.example.com"));
+        assert!(pattern.matches("a.b.example.com:443"));
+        assert!(!pattern.matches("example.com"));
+        assert!(!pattern.matches("badexample.com"));
+    }
+
+    #[test]
+    fn test_strip_port_ipv6() {
+        assert_eq!(strip_port("[::1]:3000"), "[::1]");
+        assert_eq!(strip_port("localhost:3000"), "localhost");
+    }
+
+    #[tokio::test]
+    async fn test_route_by_host() {
+        let default = Router::new().route("/", get(|| async { "main" }));
+        let admin = Router::new().route("/", get(|| async { "admin" }));
+        let router = route_by_host(
+            default,
+            vec![(HostPattern::new("admin.example.com"), admin)],
+        );
+
+        for (host, expected) in [("admin.example.com", "admin"), ("example.com", "main")] {
+            let request = Request::builder()
+                .uri("/")
+                .header(header::HOST, host)
+                .body(Body::empty())
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+}
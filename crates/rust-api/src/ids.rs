@@ -0,0 +1,100 @@
+//! Identifier generation for RustAPI framework
+//!
+//! Components that mint identifiers (request ids, ...) take them from an
+//! `IdGenerator`, so tests can swap random UUIDs for predictable ones and
+//! snapshots stay stable.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use uuid::Uuid;
+
+use crate::di::Injectable;
+
+/// Source of unique identifiers
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new().id_generator(Arc::new(SequentialIds::new("id-")));
+///
+/// async fn create_order(Inject(ids): Inject<dyn IdGenerator>) -> Json<Order> {
+///     Json(Order::new(ids.generate()))
+/// }
+/// ```
+pub trait IdGenerator: Send + Sync + 'static {
+    /// Produce a new identifier
+    fn generate(&self) -> String;
+}
+
+impl Injectable for dyn IdGenerator {}
+
+/// Time-ordered UUIDs (version 7), the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7;
+
+impl IdGenerator for UuidV7 {
+    fn generate(&self) -> String {
+        Uuid::now_v7().to_string()
+    }
+}
+
+/// Predictable identifiers for tests: `prefix` followed by 1, 2, 3, ...
+///
+/// Clones share the same counter.
+#[derive(Debug, Clone)]
+pub struct SequentialIds {
+    prefix: String,
+    next: Arc<AtomicU64>,
+}
+
+impl SequentialIds {
+    /// Count from 1 with the given prefix
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            next: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl Default for SequentialIds {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn generate(&self) -> String {
+        format!(
+            "{}{}",
+            self.prefix,
+            self.next.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_v7() {
+        let first = UuidV7.generate();
+        let second = UuidV7.generate();
+        assert_eq!(first.len(), 36);
+        assert_eq!(&first[14..15], "7");
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_sequential_ids() {
+        let ids = SequentialIds::new("req-");
+        let shared = ids.clone();
+        assert_eq!(ids.generate(), "req-1");
+        assert_eq!(shared.generate(), "req-2");
+        assert_eq!(SequentialIds::default().generate(), "1");
+    }
+}
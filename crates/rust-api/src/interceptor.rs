@@ -0,0 +1,241 @@
+//! Response interceptors for RustAPI framework
+//!
+//! An interceptor sees the response of a route after the handler produced
+//! it and may replace it, e.g. to add headers or wrap bodies. Interceptors
+//! are added to a group with `RouteGroup::intercept`.
+//!
+//! # Execution order
+//!
+//! For every request, the cross-cutting stages run in this order:
+//!
+//! 1. **Middleware**: App-level layers (tenancy, request context, metrics,
+//!    ...), then the group's layers, outermost first.
+//! 2. **Guards**: the group's guards, then the handler's `#[guard(...)]`, each
+//!    in the order listed. The first rejection ends the request.
+//! 3. **Pipes**: the handler's extractors in parameter order, with
+//!    `#[pipe(...)]` running on its parameter's raw value.
+//! 4. **Handler**
+//! 5. **Interceptors**: the group's interceptors in registration order, on any
+//!    response produced after the group's guards passed.
+//! 6. **Filters**: App-level error rendering; server error reporting, then the
+//!    `App::on_rejection` handler.
+//!
+//! A rejection at any stage skips the later request stages, but its
+//! response still travels back through the response side of the stages
+//! already entered, so a guard rejection reaches the filters but not the
+//! interceptors.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::request::Parts,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::lifecycle::BoxFuture;
+
+/// Transformation of a route's response
+///
+/// Implemented for plain closures taking the request parts and the
+/// response.
+///
+/// # Example
+///
+/// ```ignore
+/// let no_store = |_: &Parts, mut response: Response| {
+///     response
+///         .headers_mut()
+///         .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+///     response
+/// };
+/// let app = App::new().group("/account", |g| g.intercept(no_store).route("/", get(me)));
+/// ```
+pub trait Interceptor: Send + Sync + 'static {
+    /// The response to send in place of `response`
+    fn intercept<'a>(&'a self, parts: &'a Parts, response: Response) -> BoxFuture<'a, Response>;
+}
+
+impl<F> Interceptor for F
+where
+    F: Fn(&Parts, Response) -> Response + Send + Sync + 'static,
+{
+    fn intercept<'a>(&'a self, parts: &'a Parts, response: Response) -> BoxFuture<'a, Response> {
+        let response = self(parts, response);
+        Box::pin(async move { response })
+    }
+}
+
+/// Ordered list of interceptors shared by a set of routes
+pub(crate) type Interceptors = Arc<[Arc<dyn Interceptor>]>;
+
+/// Middleware passing the response through each interceptor in order
+pub(crate) async fn run_interceptors(
+    State(interceptors): State<Interceptors>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let mut response = next.run(Request::from_parts(parts.clone(), body)).await;
+    for interceptor in interceptors.iter() {
+        response = interceptor.intercept(&parts, response).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{
+        body::Body,
+        http::{HeaderValue, StatusCode},
+        middleware,
+        response::IntoResponse,
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app::App,
+        error::ApiError,
+        json::Json,
+        pipe::{Pipe, PipeError},
+        routing::post,
+    };
+
+    // stages in the order they ran; only `test_execution_order` records here
+    static STAGES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+    fn record(stage: &'static str) {
+        STAGES.lock().unwrap_or_else(|e| e.into_inner()).push(stage);
+    }
+
+    fn take_stages() -> Vec<&'static str> {
+        std::mem::take(&mut *STAGES.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    #[derive(Default)]
+    struct RecordPipe;
+
+    impl Pipe for RecordPipe {
+        fn apply(&self, value: Value) -> Result<Value, Vec<PipeError>> {
+            record("pipe");
+            Ok(value)
+        }
+    }
+
+    fn group_guard(parts: &Parts) -> Result<(), ApiError> {
+        record("group guard");
+        match parts.headers.contains_key("x-deny") {
+            true => Err(ApiError::forbidden("Denied")),
+            false => Ok(()),
+        }
+    }
+
+    fn route_guard(_: &Parts) -> Result<(), ApiError> {
+        record("route guard");
+        Ok(())
+    }
+
+    #[crate::guard(route_guard)]
+    #[crate::post("/")]
+    async fn create(#[pipe(RecordPipe)] Json(body): Json<Value>) -> Result<&'static str, ApiError> {
+        record("handler");
+        match body["valid"].as_bool() {
+            Some(true) => Ok("created"),
+            _ => Err(ApiError::unprocessable("Invalid")),
+        }
+    }
+
+    fn app() -> App {
+        App::new()
+            .group("/items", |group| {
+                group
+                    .intercept(|_: &Parts, mut response: Response| {
+                        record("interceptor");
+                        response
+                            .headers_mut()
+                            .insert("x-intercepted", HeaderValue::from_static("1"));
+                        response
+                    })
+                    .guard(group_guard)
+                    .layer(middleware::from_fn(|req: Request, next: Next| async move {
+                        record("middleware");
+                        next.run(req).await
+                    }))
+                    .route(__create_route, post(create))
+            })
+            .on_rejection(|rejection| {
+                record("filter");
+                rejection.into_api_error().into_response()
+            })
+    }
+
+    async fn call(deny: bool, body: &'static str) -> (StatusCode, bool, Vec<&'static str>) {
+        let mut request = Request::post("/items").header("content-type", "application/json");
+        if deny {
+            request = request.header("x-deny", "1");
+        }
+        let request = request.body(Body::from(body)).unwrap();
+        let response = app().build().oneshot(request).await.unwrap();
+        let intercepted = response.headers().contains_key("x-intercepted");
+        (response.status(), intercepted, take_stages())
+    }
+
+    #[tokio::test]
+    async fn test_execution_order() {
+        let (status, intercepted, stages) = call(false, r#"{"valid": true}"#).await;
+        assert_eq!((status, intercepted), (StatusCode::OK, true));
+        assert_eq!(
+            stages,
+            [
+                "middleware",
+                "group guard",
+                "route guard",
+                "pipe",
+                "handler",
+                "interceptor"
+            ]
+        );
+
+        // the handler's own error is not a rejection, so the filter skips it
+        let (status, intercepted, stages) = call(false, r#"{"valid": false}"#).await;
+        assert_eq!(
+            (status, intercepted),
+            (StatusCode::UNPROCESSABLE_ENTITY, true)
+        );
+        assert_eq!(
+            stages,
+            [
+                "middleware",
+                "group guard",
+                "route guard",
+                "pipe",
+                "handler",
+                "interceptor"
+            ]
+        );
+
+        // a rejected body goes through the filter, keeping the interceptor's
+        // header
+        let (status, intercepted, stages) = call(false, "{").await;
+        assert_eq!((status, intercepted), (StatusCode::BAD_REQUEST, true));
+        assert_eq!(
+            stages,
+            [
+                "middleware",
+                "group guard",
+                "route guard",
+                "interceptor",
+                "filter"
+            ]
+        );
+
+        let (status, intercepted, stages) = call(true, "{}").await;
+        assert_eq!((status, intercepted), (StatusCode::FORBIDDEN, false));
+        assert_eq!(stages, ["middleware", "group guard"]);
+    }
+}
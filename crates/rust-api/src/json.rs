@@ -0,0 +1,511 @@
+//! JSON bodies for RustAPI framework
+//!
+//! `Json<T>` is the framework's JSON extractor and response. It accepts the
+//! same requests as axum's `Json` (`application/json` or any `+json` media
+//! type) and rejects with `ApiError`s: 415 for other content types, 400 for
+//! malformed JSON and 422 for JSON that does not fit `T`.
+//!
+//! Fields `T` does not declare are ignored, as serde does by default. With
+//! `UnknownFields::Reject`, set for the app with `App::unknown_json_fields`
+//! or for a route as a layer, they are rejected instead, with a 422 listing
+//! them, so client bugs such as misspelled fields surface early.
+//!
+//! With the `simd` feature, bodies of at least `SIMD_THRESHOLD` bytes are
+//! parsed with simd-json on x86_64 and aarch64; smaller bodies and other
+//! targets use serde_json.
+//!
+//! `JsonStream<S>` streams a JSON array from an async stream without
+//! collecting it first.
+
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    task::Poll,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, Stream};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+use crate::{buffer::BufferPool, error::ApiError};
+
+/// Body size from which the `simd` feature switches to simd-json
+///
+/// Below it, copying the body for the in-place SIMD parser costs more than
+/// the faster parse saves.
+pub const SIMD_THRESHOLD: usize = 16 * 1024;
+
+/// Default number of buffered bytes after which `JsonStream` sends a chunk
+pub const DEFAULT_FLUSH_BYTES: usize = 8 * 1024;
+
+/// How `Json<T>` treats fields that `T` does not declare
+///
+/// Read from the request extensions, so it applies to the whole app with
+/// `App::unknown_json_fields` and to single routes or groups by adding it
+/// as a layer; the innermost setting wins.
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new()
+///     .unknown_json_fields(UnknownFields::Reject)
+///     // a legacy client still sends fields we dropped
+///     .route("/v1/users", post(create_user).layer(Extension(UnknownFields::Ignore)));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownFields {
+    /// Skip unknown fields, like serde does by default
+    #[default]
+    Ignore,
+    /// Reject the body with 422 `unknown_fields`
+    Reject,
+}
+
+/// JSON extractor and response
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/users")]
+/// async fn create_user(Json(user): Json<NewUser>) -> Json<User> {
+///     Json(service.create(user))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(ApiError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected request with `Content-Type: application/json`",
+            )
+            .rejection());
+        }
+        let unknown = req
+            .extensions()
+            .get::<UnknownFields>()
+            .copied()
+            .unwrap_or_default();
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::new(e.status(), e.body_text()).rejection())?;
+        let value = match unknown {
+            UnknownFields::Ignore => from_slice(&body),
+            UnknownFields::Reject => from_slice_strict(&body),
+        };
+        value.map(Json).map_err(ApiError::rejection)
+    }
+}
+
+// bodies are serialized into buffers from the global `BufferPool`, sized
+// for the typical body
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        let pool = BufferPool::global();
+        let mut buffer = pool.acquire(pool.typical_size());
+        match serde_json::to_writer(&mut *buffer, &self.0) {
+            Ok(()) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                )],
+                buffer.into_bytes(),
+            )
+                .into_response(),
+            Err(e) => {
+                ApiError::internal(format!("Failed to serialize JSON: {}", e)).into_response()
+            }
+        }
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Json<T> {
+    fn from(value: T) -> Self {
+        Json(value)
+    }
+}
+
+impl<T> From<axum::Json<T>> for Json<T> {
+    fn from(axum::Json(value): axum::Json<T>) -> Self {
+        Json(value)
+    }
+}
+
+/// JSON array response streamed from an async stream
+///
+/// Writes `[`, then each item as it arrives, separated by commas, then `]`.
+/// Serialized items are sent in chunks of about `flush_bytes`, and whatever
+/// is buffered is flushed as soon as the stream has to wait for its next
+/// item, so slow producers still reach the client promptly. Memory use is
+/// bounded by the chunk size, not by the number of items.
+///
+/// The status and headers are sent before the first item, so an item that
+/// fails to serialize can only abort the response; the client sees a
+/// truncated body. Handle source errors before the items reach the stream.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/exports/users")]
+/// async fn export_users(Inject(repo): Inject<UserRepo>) -> JsonStream<impl Stream<Item = User>> {
+///     JsonStream::new(repo.stream_all())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct JsonStream<S> {
+    stream: S,
+    flush_bytes: usize,
+}
+
+impl<S> JsonStream<S> {
+    /// Stream the items of `stream` as a JSON array
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            flush_bytes: DEFAULT_FLUSH_BYTES,
+        }
+    }
+
+    /// Send a chunk once `bytes` of serialized items are buffered
+    pub fn flush_bytes(mut self, bytes: usize) -> Self {
+        self.flush_bytes = bytes.max(1);
+        self
+    }
+}
+
+impl<S> IntoResponse for JsonStream<S>
+where
+    S: Stream + Send + 'static,
+    S::Item: Serialize,
+{
+    fn into_response(self) -> Response {
+        let flush_bytes = self.flush_bytes;
+        let mut items = Box::pin(self.stream);
+        let mut buffer = b"[".to_vec();
+        let mut first = true;
+        let mut done = false;
+        let chunks = stream::poll_fn(move |cx| {
+            if done {
+                return Poll::Ready(None);
+            }
+            loop {
+                match items.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => {
+                        if !first {
+                            buffer.push(b',');
+                        }
+                        first = false;
+                        if let Err(e) = serde_json::to_writer(&mut buffer, &item) {
+                            tracing::error!("Aborting JSON stream: {}", e);
+                            done = true;
+                            return Poll::Ready(Some(Err(io::Error::other(e))));
+                        }
+                        if buffer.len() >= flush_bytes {
+                            return Poll::Ready(Some(Ok(Bytes::from(std::mem::take(&mut buffer)))));
+                        }
+                    }
+                    Poll::Ready(None) => {
+                        buffer.push(b']');
+                        done = true;
+                        return Poll::Ready(Some(Ok(Bytes::from(std::mem::take(&mut buffer)))));
+                    }
+                    Poll::Pending if buffer.is_empty() => return Poll::Pending,
+                    Poll::Pending => {
+                        return Poll::Ready(Some(Ok(Bytes::from(std::mem::take(&mut buffer)))))
+                    }
+                }
+            }
+        });
+        (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            )],
+            Body::from_stream(chunks),
+        )
+            .into_response()
+    }
+}
+
+// `application/json` or `application/<anything>+json`
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(media_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+    else {
+        return false;
+    };
+    media_type == "application/json"
+        || media_type
+            .strip_prefix("application/")
+            .is_some_and(|subtype| subtype.ends_with("+json"))
+}
+
+/// Parse a JSON body, choosing the parser by size and build features
+///
+/// Errors are 400 (`malformed_json`) for invalid JSON and 422
+/// (`invalid_json`) for valid JSON that does not match `T`.
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ApiError> {
+    #[cfg(all(feature = "simd", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    if bytes.len() >= SIMD_THRESHOLD {
+        let mut buffer = bytes.to_vec();
+        if let Ok(value) = simd_json::serde::from_slice(&mut buffer) {
+            return Ok(value);
+        }
+        // serde_json's errors are the ones clients see
+    }
+    serde_json::from_slice(bytes).map_err(json_error)
+}
+
+/// Parse a JSON body, rejecting fields `T` does not declare
+///
+/// Fails like `from_slice`, and with 422 (`unknown_fields`) when the body
+/// has unknown fields; their paths, such as `profile.nickname`, are listed
+/// in the message and under `fields` in the details.
+pub fn from_slice_strict<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ApiError> {
+    let mut unknown = Vec::new();
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    let value =
+        serde_ignored::deserialize(&mut deserializer, |path| unknown.push(path.to_string()))
+            .map_err(json_error)?;
+    deserializer.end().map_err(json_error)?;
+    if !unknown.is_empty() {
+        return Err(unknown_fields(unknown));
+    }
+    Ok(value)
+}
+
+// deserialize `value`, along with the paths of the fields `T` ignored
+pub(crate) fn from_value_ignored<T: DeserializeOwned>(
+    value: serde_json::Value,
+) -> Result<(T, Vec<String>), ApiError> {
+    let mut ignored = Vec::new();
+    let value = serde_ignored::deserialize(value, |path| ignored.push(path.to_string()))
+        .map_err(json_error)?;
+    Ok((value, ignored))
+}
+
+// rejection for a body with fields its type does not declare
+pub(crate) fn unknown_fields(fields: Vec<String>) -> ApiError {
+    ApiError::unprocessable(format!(
+        "Unknown fields in the JSON body: {}",
+        fields.join(", ")
+    ))
+    .with_code("unknown_fields")
+    .with_details(json!({ "fields": fields }))
+}
+
+// rejection for a serde_json error
+pub(crate) fn json_error(e: serde_json::Error) -> ApiError {
+    use serde_json::error::Category;
+
+    match e.classify() {
+        Category::Data => ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to deserialize the JSON body: {}", e),
+        )
+        .with_code("invalid_json"),
+        _ => ApiError::bad_request(format!("Failed to parse the JSON body: {}", e))
+            .with_code("malformed_json"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Payload {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    async fn extract(content_type: &str, body: String) -> Result<Json<Payload>, ApiError> {
+        let request = Request::builder()
+            .header("content-type", content_type)
+            .body(Body::from(body))
+            .unwrap();
+        Json::<Payload>::from_request(request, &()).await
+    }
+
+    fn status(result: Result<Json<Payload>, ApiError>) -> StatusCode {
+        result.unwrap_err().into_response().status()
+    }
+
+    #[tokio::test]
+    async fn test_extracts_json() {
+        let body = r#"{"name":"ada","tags":["x"]}"#.to_string();
+        let Json(payload) = extract("application/json; charset=utf-8", body.clone())
+            .await
+            .unwrap();
+        assert_eq!(payload.name, "ada");
+        assert!(extract("application/merge-patch+json", body).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejections() {
+        let body = r#"{"name":"ada","tags":[]}"#.to_string();
+        assert_eq!(
+            status(extract("text/plain", body).await),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            status(extract("application/json", "{".to_string()).await),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(extract("application/json", r#"{"name":1}"#.to_string()).await),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[test]
+    fn test_large_bodies() {
+        let tags: Vec<String> = (0..SIMD_THRESHOLD / 4).map(|i| i.to_string()).collect();
+        let body = serde_json::json!({ "name": "bulk", "tags": tags }).to_string();
+        assert!(body.len() >= SIMD_THRESHOLD);
+        let payload: Payload = from_slice(body.as_bytes()).unwrap();
+        assert_eq!(payload.tags.len(), tags.len());
+
+        // a large body with a type error still gets serde_json's diagnosis
+        let invalid = body.replacen("\"bulk\"", "7", 1);
+        let error = from_slice::<Payload>(invalid.as_bytes()).unwrap_err();
+        assert_eq!(
+            error.into_response().status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Profile {
+        name: String,
+        avatar: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct User {
+        id: u32,
+        profile: Profile,
+    }
+
+    #[tokio::test]
+    async fn test_unknown_fields() {
+        let body = r#"{"id":1,"role":"admin","profile":{"name":"ada","nick":"a"}}"#;
+        let request = |mode: Option<UnknownFields>| {
+            let mut request = Request::builder()
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            if let Some(mode) = mode {
+                request.extensions_mut().insert(mode);
+            }
+            Json::<User>::from_request(request, &())
+        };
+
+        assert!(request(None).await.is_ok());
+        assert!(request(Some(UnknownFields::Ignore)).await.is_ok());
+        let error = request(Some(UnknownFields::Reject)).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "unknown_fields");
+        assert_eq!(
+            error.details().unwrap()["fields"],
+            serde_json::json!(["role", "profile.nick"])
+        );
+
+        let user: User = from_slice_strict(br#"{"id":1,"profile":{"name":"ada"}}"#).unwrap();
+        assert_eq!(user.id, 1);
+        let error =
+            from_slice_strict::<User>(br#"{"id":1,"profile":{"name":"ada"}} x"#).unwrap_err();
+        assert_eq!(error.code(), "malformed_json");
+    }
+
+    #[test]
+    fn test_into_response() {
+        let response = Json(serde_json::json!({ "ok": true })).into_response();
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+
+    async fn collect_chunks(response: Response) -> Vec<Bytes> {
+        use futures_util::StreamExt;
+
+        response
+            .into_body()
+            .into_data_stream()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_json_stream() {
+        let items = stream::iter((0..1000).map(|i| serde_json::json!({ "id": i })));
+        let response = JsonStream::new(items).flush_bytes(1024).into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let chunks = collect_chunks(response).await;
+        assert!(chunks.len() > 10);
+        assert!(chunks.iter().all(|chunk| chunk.len() < 1024 + 32));
+        let body: Vec<u8> = chunks.concat();
+        let values: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(values.len(), 1000);
+        assert_eq!(values[999]["id"], 999);
+
+        let empty = JsonStream::new(stream::empty::<u32>()).into_response();
+        assert_eq!(collect_chunks(empty).await.concat(), b"[]");
+    }
+
+    #[tokio::test]
+    async fn test_json_stream_flushes_while_waiting() {
+        use futures_util::StreamExt;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let items = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|item| (item, rx))
+        });
+        let mut body = JsonStream::new(items)
+            .into_response()
+            .into_body()
+            .into_data_stream();
+        tx.send(1).unwrap();
+        assert_eq!(body.next().await.unwrap().unwrap(), "[1");
+        tx.send(2).unwrap();
+        assert_eq!(body.next().await.unwrap().unwrap(), ",2");
+        drop(tx);
+        assert_eq!(body.next().await.unwrap().unwrap(), "]");
+        assert!(body.next().await.is_none());
+    }
+}
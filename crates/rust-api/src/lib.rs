@@ -41,18 +41,168 @@
 //! - `basic-api`: Complete example with controllers, services, and DI
 
 // Core modules
+// lets `::rust_api` paths emitted by the macros resolve inside this crate
+extern crate self as rust_api;
+
+pub mod access;
+pub mod admin;
 pub mod app;
+pub mod audit;
+pub mod auth;
+pub mod bench;
+pub mod body;
+pub mod boot;
+pub mod buffer;
+pub mod bulk;
+pub mod bulkhead;
+pub mod capture;
+pub mod changelog;
+pub mod client;
+pub mod clock;
+pub mod codes;
+pub mod compression;
+pub mod conditional;
+pub mod config;
+pub mod context;
+pub mod cpu;
+pub mod db;
+pub mod delta;
+pub mod deprecation;
 pub mod di;
 pub mod error;
+pub mod events;
+pub mod extract;
+pub mod fields;
+pub mod formats;
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod group;
+pub mod grpc;
+pub mod guard;
+pub mod health;
+pub mod host;
+pub mod ids;
+pub mod interceptor;
+pub mod json;
+pub mod lifecycle;
+pub mod links;
+pub mod loader;
+pub mod lock;
+pub mod metrics;
+pub mod mock;
+pub mod openapi;
+#[cfg(feature = "sea-orm")]
+pub mod orm;
+pub mod outbox;
+#[cfg(feature = "pact")]
+pub mod pact;
+pub mod patch;
+pub mod paths;
+pub mod pipe;
+pub mod platform;
+pub mod profile;
+#[cfg(all(feature = "profiling", unix))]
+pub mod profiling;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod quota;
+pub mod range;
+pub mod raw;
+pub mod redirect;
+pub mod registry;
+pub mod rejection;
+pub mod repository;
+pub mod retry;
 pub mod router;
+pub mod runtime;
+pub mod sampling;
+pub mod seed;
 pub mod server;
+pub mod shed;
+pub mod shutdown;
+pub mod sse;
+#[cfg(feature = "storage")]
+pub mod storage;
+pub mod tasks;
+pub mod tenant;
+pub mod testing;
+pub mod worker;
+#[cfg(feature = "ws")]
+pub mod ws;
 
 // Re-export core types
+pub use admin::Admin;
 pub use app::App;
+pub use audit::{Audit, AuditSink};
+pub use auth::{Principal, TokenAuth};
+pub use body::{BodyStream, MultipartField, MultipartStream};
+pub use buffer::BufferPool;
+pub use bulk::{Bulk, BulkConfig, BulkResponse};
+pub use bulkhead::{Bulkhead, BulkheadClient, Bulkheads};
+pub use capture::{BodyCapture, Redaction};
+pub use changelog::ApiChangelog;
+pub use client::{ClientError, Correlated, HttpClient, ResponseLimit};
+pub use clock::{Clock, SystemClock, TestClock};
+pub use codes::ErrorCode;
+pub use compression::{Compression, CompressionLevel, Encoding};
+pub use conditional::{ETag, ETagged, IfMatch, IfNoneMatch};
+pub use config::ConfigLoader;
+pub use context::{Cancellation, RequestContext};
+pub use cpu::CpuPool;
+pub use db::Db;
+pub use delta::{Delta, DeltaQuery, DeltaRepository, SyncToken};
+pub use deprecation::DeprecationPolicy;
 pub use di::{Container, Injectable};
-pub use error::{Error, Result};
-pub use router::{Router, RouterExt};
+pub use error::{ApiError, ApiResult, Error, OptionExt, Result, ResultExt};
+pub use events::{Event, EventBus};
+pub use extract::{ClientIp, Inject, Rest};
+pub use fields::{Fields, Sparse};
+#[cfg(feature = "cbor")]
+pub use formats::Cbor;
+#[cfg(feature = "protobuf")]
+pub use formats::Proto;
+#[cfg(feature = "xml")]
+pub use formats::{Xml, XmlConfig};
+#[cfg(feature = "geo")]
+pub use geo::{Feature, FeatureCollection, GeoJson, Geometry, Point, Polygon};
+pub use group::RouteGroup;
+pub use grpc::{GrpcClientConfig, GrpcClients};
+pub use guard::Guard;
+pub use health::{DependencyWaits, HealthCheck, HealthChecks, Readiness, Severity};
+pub use ids::{IdGenerator, SequentialIds, UuidV7};
+pub use interceptor::Interceptor;
+pub use json::{Json, JsonStream, UnknownFields};
+pub use lifecycle::OnStart;
+pub use links::{Hal, Link, Links};
+pub use loader::{BatchLoad, DataLoader, Loader};
+pub use lock::{DistributedLock, Locks};
+pub use metrics::Metrics;
+pub use mock::Example;
+pub use openapi::OpenApi;
+pub use outbox::{OutboxRelay, OutboxStore};
+pub use patch::{JsonPatch, MergePatch};
+pub use platform::Platform;
+pub use profile::{DocsAssets, Profile};
+pub use proxy::Proxy;
+pub use quota::{QuotaTier, Quotas, QuotasBuilder};
+pub use range::RangeBody;
+pub use raw::Raw;
+pub use redirect::Redirect;
+pub use registry::{Metadata, RouteInfo, RouteRegistry, ServedRoute, ServedRoutes};
+pub use repository::{Page, Pagination, Repository};
+pub use retry::{Backoff, RetryPolicy};
+pub use router::{url_for, Router, RouterExt, TrailingSlash};
+pub use runtime::RuntimeConfig;
+pub use sampling::Sampler;
+pub use seed::Seeder;
 pub use server::RustAPI;
+pub use shed::{LoadShedder, Priority};
+pub use sse::{LastEventId, SseBroadcaster, SseResponse};
+#[cfg(feature = "storage")]
+pub use storage::{BlobStore, MemoryStore, S3Config, S3Store};
+pub use tasks::{TaskAccepted, TaskHandle, TaskTracker};
+pub use tenant::{Tenant, TenantResolver};
+pub use worker::{StopSignal, Worker};
 
 // Re-export routing methods from Axum
 // These are used to define route handlers (get, post, put, delete, etc.)
@@ -66,10 +216,14 @@ pub use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
 };
+#[cfg(feature = "ws")]
+pub use rust_api_macros::websocket;
 // Re-export macros
-pub use rust_api_macros::{delete, get, patch, post, put};
+pub use rust_api_macros::{
+    blocking, controller, delete, deprecated_route, get, guard, main, mockable, patch, post,
+    priority, put, read_only, retry, ErrorCode, MapFrom, Repository,
+};
 // Re-export serde for user convenience
 pub use serde::{Deserialize, Serialize};
 pub use tower_http::{cors::CorsLayer, trace::TraceLayer};
@@ -85,32 +239,50 @@ pub mod prelude {
     pub use tokio;
 
     pub use super::{
+        blocking,
+        controller,
         delete,
+        deprecated_route,
         // Macros
         get,
+        guard,
         patch,
 
         post,
+        priority,
         put,
+        retry,
         router,
         routing,
 
+        ApiError,
+        ApiResult,
         App,
+        // Extractors
+        ClientIp,
         // Core
         Container,
         // Middleware
         CorsLayer,
         Deserialize,
         Error,
+        Inject,
         Injectable,
         IntoResponse,
         // Axum
         Json,
+        MapFrom,
+        OptionExt,
         Path,
         Query,
+        Redirect,
+        RequestContext,
         Response,
+        Rest,
 
         Result,
+        ResultExt,
+        RouteGroup,
         Router,
         RouterExt,
         RustAPI,
@@ -119,5 +291,6 @@ pub mod prelude {
         State,
         StatusCode,
         TraceLayer,
+        TrailingSlash,
     };
 }
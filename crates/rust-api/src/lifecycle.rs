@@ -0,0 +1,106 @@
+//! Application lifecycle hooks for RustAPI framework
+//!
+//! Hooks registered with `RustAPI::on_start` run after the listener is bound
+//! and before the application reports itself ready.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::error::Result;
+
+/// Boxed future returned by lifecycle hooks
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Hook that runs once while the application starts up
+///
+/// Implemented for async closures and for `Arc`-wrapped services, so a
+/// service resolved from the container can be registered directly.
+///
+/// # Example
+///
+/// ```ignore
+/// impl OnStart for CacheService {
+///     fn on_start(&self) -> BoxFuture<'_, Result<()>> {
+///         Box::pin(async move { self.warm_up().await })
+///     }
+/// }
+///
+/// RustAPI::new(app)
+///     .on_start(container.resolve::<CacheService>().unwrap())
+///     .on_start(|| async { Ok(()) });
+/// ```
+pub trait OnStart: Send + Sync + 'static {
+    /// Run the hook; an error aborts startup
+    fn on_start(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+impl<F, Fut> OnStart for F
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn on_start(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self())
+    }
+}
+
+impl<T: OnStart> OnStart for Arc<T> {
+    fn on_start(&self) -> BoxFuture<'_, Result<()>> {
+        (**self).on_start()
+    }
+}
+
+/// Run start hooks in registration order, stopping at the first failure
+pub(crate) async fn run_start_hooks(hooks: &[Box<dyn OnStart>]) -> Result<()> {
+    for (index, hook) in hooks.iter().enumerate() {
+        tracing::debug!("Running start hook {} of {}", index + 1, hooks.len());
+        hook.on_start().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::error::Error;
+
+    struct CountingService {
+        calls: AtomicUsize,
+    }
+
+    impl OnStart for CountingService {
+        fn on_start(&self) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_start_hooks_in_order() {
+        let service = Arc::new(CountingService {
+            calls: AtomicUsize::new(0),
+        });
+        let hooks: Vec<Box<dyn OnStart>> =
+            vec![Box::new(service.clone()), Box::new(|| async { Ok(()) })];
+
+        run_start_hooks(&hooks).await.unwrap();
+        assert_eq!(service.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_start_hooks_stops_on_error() {
+        let service = Arc::new(CountingService {
+            calls: AtomicUsize::new(0),
+        });
+        let hooks: Vec<Box<dyn OnStart>> = vec![
+            Box::new(|| async { Err(Error::other("boom")) }),
+            Box::new(service.clone()),
+        ];
+
+        assert!(run_start_hooks(&hooks).await.is_err());
+        assert_eq!(service.calls.load(Ordering::SeqCst), 0);
+    }
+}
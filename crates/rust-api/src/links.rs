@@ -0,0 +1,258 @@
+//! Hypermedia links for RustAPI framework
+//!
+//! Builds `self`/`next`/related links from route templates with `url_for`,
+//! serialized in the HAL `_links` shape or sent as a `Link` header.
+
+use std::{collections::BTreeMap, convert::Infallible};
+
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+    Json,
+};
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::{error::ApiError, router::url_for};
+
+/// Media type of HAL documents
+pub const HAL_CONTENT_TYPE: &str = "application/hal+json";
+
+/// A single link
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Link {
+    /// Target URL
+    pub href: String,
+    /// Human-readable title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Whether `href` is a URI template the client must expand
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub templated: bool,
+}
+
+impl Link {
+    /// Link to `href`
+    pub fn new(href: impl Into<String>) -> Self {
+        Self {
+            href: href.into(),
+            title: None,
+            templated: false,
+        }
+    }
+
+    /// Set the title
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Mark `href` as a URI template
+    pub fn templated(mut self) -> Self {
+        self.templated = true;
+        self
+    }
+}
+
+/// Set of links keyed by relation
+///
+/// Serializes to the HAL `_links` object: one link per relation becomes an
+/// object, several become an array. Used in a response tuple it sets the
+/// `Link` header instead.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users")]
+/// async fn list_users(Query(page): Query<Page>) -> Result<Hal<UserList>, ApiError> {
+///     let links = Links::new()
+///         .route("self", __list_users_route, &[])?
+///         .route("item", __get_user_route, &[("id", "1")])?
+///         .add("next", format!("/users?page={}", page.number + 1));
+///     Ok(Hal::new(users, links))
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Links {
+    links: BTreeMap<String, Vec<Link>>,
+}
+
+impl Links {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a link to `href` under `rel`
+    pub fn add(self, rel: &str, href: impl Into<String>) -> Self {
+        self.link(rel, Link::new(href))
+    }
+
+    /// Add a fully built link under `rel`
+    pub fn link(mut self, rel: &str, link: Link) -> Self {
+        self.links.entry(rel.to_string()).or_default().push(link);
+        self
+    }
+
+    /// Add the `self` link
+    pub fn self_link(self, href: impl Into<String>) -> Self {
+        self.add("self", href)
+    }
+
+    /// Add a link built from a route template and its parameters
+    pub fn route(
+        self,
+        rel: &str,
+        template: &str,
+        params: &[(&str, &str)],
+    ) -> Result<Self, ApiError> {
+        Ok(self.add(rel, url_for(template, params)?))
+    }
+
+    /// Links under `rel`
+    pub fn get(&self, rel: &str) -> &[Link] {
+        self.links.get(rel).map_or(&[], Vec::as_slice)
+    }
+
+    /// Check whether there are no links
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Render as a `Link` header value (RFC 8288)
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        let value = self
+            .links
+            .iter()
+            .flat_map(|(rel, links)| {
+                links.iter().map(move |link| match &link.title {
+                    Some(title) => format!(
+                        "<{}>; rel=\"{}\"; title=\"{}\"",
+                        link.href,
+                        rel,
+                        title.replace('"', "'")
+                    ),
+                    None => format!("<{}>; rel=\"{}\"", link.href, rel),
+                })
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        if value.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+impl Serialize for Links {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.links.len()))?;
+        for (rel, links) in &self.links {
+            match links.as_slice() {
+                [link] => map.serialize_entry(rel, link)?,
+                links => map.serialize_entry(rel, links)?,
+            }
+        }
+        map.end()
+    }
+}
+
+impl IntoResponseParts for Links {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(value) = self.to_header_value() {
+            res.headers_mut().append(header::LINK, value);
+        }
+        Ok(res)
+    }
+}
+
+/// JSON resource with embedded HAL `_links`
+///
+/// The resource's fields are flattened next to `_links`, so `T` must
+/// serialize to a JSON object.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hal<T> {
+    #[serde(flatten)]
+    resource: T,
+    #[serde(rename = "_links", skip_serializing_if = "Links::is_empty")]
+    links: Links,
+}
+
+impl<T: Serialize> Hal<T> {
+    /// Wrap a resource with its links
+    pub fn new(resource: T, links: Links) -> Self {
+        Self { resource, links }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Hal<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self).into_response();
+        if response.status().is_success() {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(HAL_CONTENT_TYPE),
+            );
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn links() -> Links {
+        Links::new()
+            .route("self", "/users/{id}", &[("id", "7")])
+            .unwrap()
+            .add("orders", "/users/7/orders?page=1")
+            .add("orders", "/users/7/orders?page=2")
+            .link(
+                "search",
+                Link::new("/users{?q}").templated().title("Search"),
+            )
+    }
+
+    #[test]
+    fn test_hal_shape() {
+        let value = serde_json::to_value(Hal::new(json!({ "id": 7 }), links())).unwrap();
+        assert_eq!(value["id"], 7);
+        assert_eq!(value["_links"]["self"], json!({ "href": "/users/7" }));
+        assert_eq!(value["_links"]["orders"].as_array().unwrap().len(), 2);
+        assert_eq!(value["_links"]["search"]["templated"], true);
+
+        let bare = serde_json::to_value(Hal::new(json!({ "id": 1 }), Links::new())).unwrap();
+        assert!(bare.get("_links").is_none());
+    }
+
+    #[test]
+    fn test_link_header() {
+        let header = Links::new()
+            .self_link("/users/7")
+            .add("next", "/users/8")
+            .to_header_value()
+            .unwrap();
+        assert_eq!(header, "</users/8>; rel=\"next\", </users/7>; rel=\"self\"");
+        assert!(Links::new().to_header_value().is_none());
+    }
+
+    #[test]
+    fn test_route_link_requires_params() {
+        assert!(Links::new().route("self", "/users/{id}", &[]).is_err());
+        assert_eq!(links().get("self")[0].href, "/users/7");
+        assert!(links().get("missing").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_links_in_response_tuple() {
+        let response = (Links::new().self_link("/a"), "body").into_response();
+        assert_eq!(response.headers()[header::LINK], "</a>; rel=\"self\"");
+
+        let response = Hal::new(json!({}), Links::new()).into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], HAL_CONTENT_TYPE);
+    }
+}
@@ -0,0 +1,365 @@
+//! Request-scoped data loaders for RustAPI framework
+//!
+//! Resolving a list item by item, e.g. the author of every post, makes one
+//! query per item. A `DataLoader` collects the keys that concurrent loads
+//! ask for in the same tick and fetches them with a single `BatchLoad`
+//! call, caching the results.
+//!
+//! Lifetime rules:
+//!
+//! - The batch function (`BatchLoad`) is a normal container service,
+//!   shared by every request.
+//! - Its `DataLoader` and cache belong to one request: the `Loader`
+//!   extractor and `DataLoader::scoped` keep them in the request's
+//!   `RequestContext`, so nothing is shared between requests or users and
+//!   nothing outlives the request. Outside a request, `scoped` returns a
+//!   fresh loader each time.
+//! - Values are cached for the rest of the request, so a write made in the
+//!   same request is not seen by later loads; call `clear` after writing.
+//! - Only keys loaded concurrently are batched: collect the loads first
+//!   (`load_many`, or `join_all` over `load` calls) instead of awaiting
+//!   them one by one.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+};
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use futures_util::future::join_all;
+use tokio::sync::Notify;
+
+use crate::{
+    context::RequestContext, di::Injectable, error::ApiError, extract::Inject, lifecycle::BoxFuture,
+};
+
+/// Values returned by one `BatchLoad` call, by key
+pub type Loaded<L> = HashMap<<L as BatchLoad>::Key, <L as BatchLoad>::Value>;
+
+/// Fetches many values by key in one call
+///
+/// # Example
+///
+/// ```ignore
+/// struct UsersById {
+///     db: Db,
+/// }
+///
+/// impl Injectable for UsersById {}
+///
+/// impl BatchLoad for UsersById {
+///     type Key = i64;
+///     type Value = User;
+///
+///     fn load<'a>(&'a self, ids: &'a [i64]) -> BoxFuture<'a, Result<Loaded<Self>, ApiError>> {
+///         Box::pin(async move {
+///             let users = self.db.find_users(ids).await?;
+///             Ok(users.into_iter().map(|user| (user.id, user)).collect())
+///         })
+///     }
+/// }
+/// ```
+pub trait BatchLoad: Injectable {
+    /// Key values are looked up by
+    type Key: Clone + Eq + Hash + Send + Sync + 'static;
+    /// Value loaded for a key
+    type Value: Clone + Send + Sync + 'static;
+
+    /// Fetch the values of `keys`; keys missing from the result have none
+    fn load<'a>(&'a self, keys: &'a [Self::Key]) -> BoxFuture<'a, Result<Loaded<Self>, ApiError>>;
+}
+
+/// Batching, caching front of a `BatchLoad`
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/posts")]
+/// async fn posts(Inject(repo): Inject<PostRepo>, Loader(users): Loader<UsersById>) -> ApiResult<Json<Vec<PostView>>> {
+///     let posts = repo.recent().await?;
+///     let authors = users.load_many(posts.iter().map(|post| post.author_id)).await?;
+///     // ...
+/// }
+/// ```
+pub struct DataLoader<L: BatchLoad> {
+    source: Arc<L>,
+    state: Mutex<State<L>>,
+}
+
+// cached values and the batch being collected
+struct State<L: BatchLoad> {
+    cache: HashMap<L::Key, Option<L::Value>>,
+    pending: Vec<L::Key>,
+    batch: Option<Arc<Batch>>,
+}
+
+// one call to the batch function, awaited by every load in it
+#[derive(Default)]
+struct Batch {
+    done: OnceLock<Result<(), ApiError>>,
+    notify: Notify,
+}
+
+impl Batch {
+    // record the outcome and wake the loads waiting for it
+    fn finish(&self, outcome: Result<(), ApiError>) {
+        let _ = self.done.set(outcome);
+        self.notify.notify_waiters();
+    }
+}
+
+// fails the batch if the load running it is dropped halfway, so its keys
+// go to the next batch instead of being waited on forever
+struct Dispatch<'a, L: BatchLoad> {
+    loader: &'a DataLoader<L>,
+    batch: &'a Arc<Batch>,
+}
+
+impl<L: BatchLoad> Drop for Dispatch<'_, L> {
+    fn drop(&mut self) {
+        if self.batch.done.get().is_some() {
+            return;
+        }
+        let mut state = self.loader.state();
+        if state
+            .batch
+            .as_ref()
+            .is_some_and(|b| Arc::ptr_eq(b, self.batch))
+        {
+            state.batch = None;
+        }
+        drop(state);
+        self.batch
+            .finish(Err(ApiError::internal("Batch load was cancelled")));
+    }
+}
+
+impl<L: BatchLoad> DataLoader<L> {
+    /// Create a loader with an empty cache
+    ///
+    /// Prefer `scoped` or the `Loader` extractor, which share one loader
+    /// per request.
+    pub fn new(source: Arc<L>) -> Self {
+        Self {
+            source,
+            state: Mutex::new(State {
+                cache: HashMap::new(),
+                pending: Vec::new(),
+                batch: None,
+            }),
+        }
+    }
+
+    /// The current request's loader for `source`
+    pub fn scoped(source: Arc<L>) -> Arc<Self> {
+        match RequestContext::current() {
+            Some(ctx) => ctx.local(|| Self::new(source)),
+            None => Arc::new(Self::new(source)),
+        }
+    }
+
+    /// Load the value of `key`, batched with concurrent loads
+    ///
+    /// A failed batch fails every load in it and caches nothing.
+    pub async fn load(&self, key: L::Key) -> Result<Option<L::Value>, ApiError> {
+        let (batch, leader) = {
+            let mut state = self.state();
+            if let Some(value) = state.cache.get(&key) {
+                return Ok(value.clone());
+            }
+            if !state.pending.contains(&key) {
+                state.pending.push(key.clone());
+            }
+            match &state.batch {
+                Some(batch) => (Arc::clone(batch), false),
+                None => {
+                    let batch = Arc::new(Batch::default());
+                    state.batch = Some(Arc::clone(&batch));
+                    (batch, true)
+                }
+            }
+        };
+
+        if leader {
+            let _dispatch = Dispatch {
+                loader: self,
+                batch: &batch,
+            };
+            // let the other loads of this tick add their keys first
+            tokio::task::yield_now().await;
+            self.dispatch(&batch).await;
+        } else {
+            let notified = batch.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if batch.done.get().is_none() {
+                notified.await;
+            }
+        }
+
+        match batch.done.get() {
+            Some(Err(error)) => Err(error.clone()),
+            _ => Ok(self.state().cache.get(&key).cloned().flatten()),
+        }
+    }
+
+    /// Load the values of `keys` in one batch, in order
+    pub async fn load_many(
+        &self,
+        keys: impl IntoIterator<Item = L::Key>,
+    ) -> Result<Vec<Option<L::Value>>, ApiError> {
+        join_all(keys.into_iter().map(|key| self.load(key)))
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Cache `value` for `key` without loading it
+    pub fn prime(&self, key: L::Key, value: L::Value) {
+        self.state().cache.insert(key, Some(value));
+    }
+
+    /// Forget the cached value of `key`, e.g. after updating it
+    pub fn clear(&self, key: &L::Key) {
+        self.state().cache.remove(key);
+    }
+
+    // call the batch function with the keys collected so far
+    async fn dispatch(&self, batch: &Batch) {
+        let keys = {
+            let mut state = self.state();
+            state.batch = None;
+            std::mem::take(&mut state.pending)
+        };
+        let outcome = match self.source.load(&keys).await {
+            Ok(mut values) => {
+                let mut state = self.state();
+                for key in keys {
+                    let value = values.remove(&key);
+                    state.cache.insert(key, value);
+                }
+                Ok(())
+            }
+            Err(error) => Err(error),
+        };
+        batch.finish(outcome);
+    }
+
+    // lock the state, shrugging off poisoning
+    fn state(&self) -> MutexGuard<'_, State<L>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Extractor for the current request's `DataLoader` of a `BatchLoad`
+/// service registered in the container
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/posts/{id}/author")]
+/// async fn author(Path(id): Path<i64>, Loader(users): Loader<UsersById>) -> ApiResult<Json<User>> {
+///     // ...
+/// }
+/// ```
+pub struct Loader<L: BatchLoad>(pub Arc<DataLoader<L>>);
+
+impl<L: BatchLoad, S: Send + Sync> FromRequestParts<S> for Loader<L> {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Inject(source) = Inject::<L>::from_request_parts(parts, state).await?;
+        Ok(Loader(DataLoader::scoped(source)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Squares {
+        calls: Mutex<Vec<Vec<u32>>>,
+        fail: AtomicBool,
+    }
+
+    impl Injectable for Squares {}
+
+    impl BatchLoad for Squares {
+        type Key = u32;
+        type Value = u32;
+
+        fn load<'a>(&'a self, keys: &'a [u32]) -> BoxFuture<'a, Result<Loaded<Self>, ApiError>> {
+            Box::pin(async move {
+                self.calls.lock().unwrap().push(keys.to_vec());
+                if self.fail.load(Ordering::Relaxed) {
+                    return Err(ApiError::internal("database down"));
+                }
+                Ok(keys
+                    .iter()
+                    .filter(|&&k| k != 0)
+                    .map(|&k| (k, k * k))
+                    .collect())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_and_caches_concurrent_loads() {
+        let source = Arc::new(Squares::default());
+        let loader = DataLoader::new(Arc::clone(&source));
+
+        let values = loader.load_many([3, 2, 3, 0]).await.unwrap();
+        assert_eq!(values, vec![Some(9), Some(4), Some(9), None]);
+        assert_eq!(*source.calls.lock().unwrap(), vec![vec![3, 2, 0]]);
+
+        assert_eq!(loader.load(2).await.unwrap(), Some(4));
+        assert_eq!(loader.load(0).await.unwrap(), None);
+        assert_eq!(source.calls.lock().unwrap().len(), 1);
+
+        loader.clear(&2);
+        loader.prime(5, 0);
+        let (a, b) = tokio::join!(loader.load(2), loader.load(5));
+        assert_eq!((a.unwrap(), b.unwrap()), (Some(4), Some(0)));
+        assert_eq!(source.calls.lock().unwrap()[1], vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_batch_fails_every_load_and_is_not_cached() {
+        let source = Arc::new(Squares::default());
+        source.fail.store(true, Ordering::Relaxed);
+        let loader = DataLoader::new(Arc::clone(&source));
+
+        let (a, b) = tokio::join!(loader.load(1), loader.load(2));
+        assert_eq!(a.unwrap_err().message(), "database down");
+        assert!(b.is_err());
+
+        source.fail.store(false, Ordering::Relaxed);
+        assert_eq!(loader.load(1).await.unwrap(), Some(1));
+        assert_eq!(source.calls.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_one_loader_per_request() {
+        let source = Arc::new(Squares::default());
+        let scoped = || DataLoader::scoped(Arc::clone(&source));
+
+        let first = RequestContext::new("req-1");
+        let (a, b) = first.scope(async { (scoped(), scoped()) }).await;
+        assert!(Arc::ptr_eq(&a, &b));
+        a.load(4).await.unwrap();
+
+        let other = RequestContext::new("req-2").scope(async { scoped() }).await;
+        assert!(!Arc::ptr_eq(&a, &other));
+        other.load(4).await.unwrap();
+        assert_eq!(source.calls.lock().unwrap().len(), 2);
+
+        assert!(!Arc::ptr_eq(&scoped(), &scoped()));
+    }
+}
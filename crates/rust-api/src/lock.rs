@@ -0,0 +1,559 @@
+//! Distributed locks for RustAPI framework
+//!
+//! A `DistributedLock` hands out time-limited leases on a key so that cron
+//! jobs and consumers running on several replicas execute only once. Every
+//! lease carries a fencing token that increases with each acquisition, for
+//! storage to reject writes from a holder whose lease already expired.
+//! `Locks` wraps a backend with automatic lease renewal and contention
+//! metrics. `MemoryLock` serves a single process and tests; with the `redis`
+//! feature, `RedisLock` uses `SET NX` across replicas.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    di::Injectable,
+    error::{Error, Result},
+    lifecycle::BoxFuture,
+    metrics::Metrics,
+};
+
+/// A held lock on a key
+#[derive(Debug, Clone)]
+pub struct Lease {
+    key: String,
+    token: String,
+    fence: u64,
+    lost: Arc<AtomicBool>,
+}
+
+impl Lease {
+    /// Create a lease on `key` held by `token` with fencing token `fence`
+    ///
+    /// For `DistributedLock` implementations.
+    pub fn new(key: impl Into<String>, token: impl Into<String>, fence: u64) -> Self {
+        Self {
+            key: key.into(),
+            token: token.into(),
+            fence,
+            lost: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Locked key
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Unique value identifying this holder to the backend
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Fencing token, greater than that of every earlier lease on the key
+    pub fn fence(&self) -> u64 {
+        self.fence
+    }
+
+    /// Check whether renewal failed and another holder may own the key
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+/// Lock service shared by all replicas
+///
+/// # Example
+///
+/// ```ignore
+/// impl DistributedLock for EtcdLock {
+///     fn try_acquire<'a>(&'a self, key: &'a str, ttl: Duration) -> BoxFuture<'a, Result<Option<Lease>>> {
+///         Box::pin(async move { self.grant(key, ttl).await })
+///     }
+///     // renew, release
+/// }
+/// ```
+pub trait DistributedLock: Send + Sync + 'static {
+    /// Take the lock on `key` for `ttl`, `None` if someone else holds it
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<Option<Lease>>>;
+
+    /// Extend `lease` to `ttl` from now, `false` if it is no longer held
+    fn renew<'a>(&'a self, lease: &'a Lease, ttl: Duration) -> BoxFuture<'a, Result<bool>>;
+
+    /// Give up `lease`; does nothing if it is no longer held
+    fn release<'a>(&'a self, lease: &'a Lease) -> BoxFuture<'a, Result<()>>;
+}
+
+impl Injectable for dyn DistributedLock {}
+
+/// In-process `DistributedLock`, for a single replica and for tests
+///
+/// Leases expire by the time of its `Clock`.
+#[derive(Clone)]
+pub struct MemoryLock {
+    state: Arc<Mutex<MemoryState>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryLock {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+// held leases and the last fencing token per key
+#[derive(Default)]
+struct MemoryState {
+    held: HashMap<String, (String, Instant)>,
+    fences: HashMap<String, u64>,
+    tokens: u64,
+}
+
+impl MemoryLock {
+    /// Create a lock with no keys held
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // lock the state
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl DistributedLock for MemoryLock {
+    fn try_acquire<'a>(
+        &'a self,
+        key: &'a str,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<Option<Lease>>> {
+        let now = self.clock.now();
+        let mut state = self.state();
+        let lease = match state.held.get(key) {
+            Some((_, expires)) if *expires > now => None,
+            _ => {
+                state.tokens += 1;
+                let token = state.tokens.to_string();
+                let fence = state.fences.entry(key.to_string()).or_insert(0);
+                *fence += 1;
+                let lease = Lease::new(key, token.clone(), *fence);
+                state.held.insert(key.to_string(), (token, now + ttl));
+                Some(lease)
+            }
+        };
+        Box::pin(async move { Ok(lease) })
+    }
+
+    fn renew<'a>(&'a self, lease: &'a Lease, ttl: Duration) -> BoxFuture<'a, Result<bool>> {
+        let now = self.clock.now();
+        let renewed = match self.state().held.get_mut(&lease.key) {
+            Some((token, expires)) if *token == lease.token && *expires > now => {
+                *expires = now + ttl;
+                true
+            }
+            _ => false,
+        };
+        Box::pin(async move { Ok(renewed) })
+    }
+
+    fn release<'a>(&'a self, lease: &'a Lease) -> BoxFuture<'a, Result<()>> {
+        let mut state = self.state();
+        if state
+            .held
+            .get(&lease.key)
+            .is_some_and(|(token, _)| *token == lease.token)
+        {
+            state.held.remove(&lease.key);
+        }
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+/// Lock service with lease renewal and contention metrics
+///
+/// Register it in the container and inject it into jobs.
+///
+/// # Example
+///
+/// ```ignore
+/// let locks = Locks::new(Arc::new(RedisLock::connect("redis://redis:6379").await?))
+///     .metrics(metrics.clone());
+///
+/// let ran = locks
+///     .run_exclusive("jobs:nightly-report", Duration::from_secs(30), |lease| async move {
+///         reports.generate(lease.fence()).await
+///     })
+///     .await?;
+/// if ran.is_none() {
+///     tracing::info!("Nightly report already running on another replica");
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Locks {
+    backend: Arc<dyn DistributedLock>,
+    metrics: Option<Metrics>,
+}
+
+impl Injectable for Locks {}
+
+impl Locks {
+    /// Take locks from `backend`
+    pub fn new(backend: Arc<dyn DistributedLock>) -> Self {
+        Self {
+            backend,
+            metrics: None,
+        }
+    }
+
+    /// Count acquisitions in `lock_acquire_total{key,outcome}` and lost
+    /// leases in `lock_lost_total{key}`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Take the lock on `key` for `ttl`, `None` if someone else holds it
+    ///
+    /// The lease is not renewed; release it with `release`. Fails when `ttl`
+    /// is zero.
+    pub async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<Lease>> {
+        if ttl.is_zero() {
+            return Err(Error::other(format!("Lock {} needs a non-zero ttl", key)));
+        }
+        let lease = self.backend.try_acquire(key, ttl).await?;
+        let outcome = match lease {
+            Some(_) => "acquired",
+            None => "contended",
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.increment("lock_acquire_total", &[("key", key), ("outcome", outcome)]);
+        }
+        Ok(lease)
+    }
+
+    /// Give up `lease`
+    pub async fn release(&self, lease: &Lease) -> Result<()> {
+        self.backend.release(lease).await
+    }
+
+    /// Run `job` while holding the lock on `key`, `None` if someone else
+    /// holds it
+    ///
+    /// The lease is renewed every third of `ttl` while the job runs and
+    /// released afterwards, also when the job panics or the returned future
+    /// is dropped. If a renewal fails the lease is marked lost (see
+    /// `Lease::is_lost`) but the job is not interrupted.
+    pub async fn run_exclusive<T, F, Fut>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        job: F,
+    ) -> Result<Option<T>>
+    where
+        F: FnOnce(Lease) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let Some(lease) = self.try_acquire(key, ttl).await? else {
+            return Ok(None);
+        };
+        let mut held = Held {
+            renewal: tokio::spawn(renew(
+                self.backend.clone(),
+                self.metrics.clone(),
+                lease.clone(),
+                ttl,
+            )),
+            backend: self.backend.clone(),
+            lease: Some(lease.clone()),
+        };
+        let output = job(lease.clone()).await;
+        held.renewal.abort();
+        if let Some(lease) = held.lease.take() {
+            if let Err(e) = self.backend.release(&lease).await {
+                tracing::warn!("Failed to release lock {}: {}", lease.key, e);
+            }
+        }
+        Ok(Some(output))
+    }
+}
+
+// stops renewing a lease when dropped, releasing it unless already released
+struct Held {
+    renewal: tokio::task::JoinHandle<()>,
+    backend: Arc<dyn DistributedLock>,
+    lease: Option<Lease>,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.renewal.abort();
+        let Some(lease) = self.lease.take() else {
+            return;
+        };
+        let backend = self.backend.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                if let Err(e) = backend.release(&lease).await {
+                    tracing::warn!("Failed to release lock {}: {}", lease.key, e);
+                }
+            });
+        }
+    }
+}
+
+// renew `lease` until it is lost or the task is aborted
+async fn renew(
+    backend: Arc<dyn DistributedLock>,
+    metrics: Option<Metrics>,
+    lease: Lease,
+    ttl: Duration,
+) {
+    // `interval` panics on a zero period
+    let mut ticks = tokio::time::interval((ttl / 3).max(Duration::from_millis(1)));
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        match backend.renew(&lease, ttl).await {
+            Ok(true) => continue,
+            Ok(false) => tracing::warn!("Lost lock {}: held by another owner", lease.key),
+            Err(e) => tracing::warn!("Lost lock {}: renewal failed: {}", lease.key, e),
+        }
+        lease.lost.store(true, Ordering::Relaxed);
+        if let Some(metrics) = &metrics {
+            metrics.increment("lock_lost_total", &[("key", &lease.key)]);
+        }
+        return;
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_lock::RedisLock;
+
+#[cfg(feature = "redis")]
+mod redis_lock {
+    use std::time::Duration;
+
+    use redis::{aio::ConnectionManager, Script};
+
+    use super::{DistributedLock, Lease};
+    use crate::{
+        error::{Error, Result},
+        ids::{IdGenerator, UuidV7},
+        lifecycle::BoxFuture,
+    };
+
+    // take the lock and bump the fencing counter in one step
+    const ACQUIRE: &str = r"
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return redis.call('INCR', KEYS[2])
+end
+return 0";
+
+    // extend the lock if the caller still holds it
+    const RENEW: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0";
+
+    // delete the lock if the caller still holds it
+    const RELEASE: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0";
+
+    /// `DistributedLock` on Redis
+    ///
+    /// Locks are `SET key token NX PX ttl`; the fencing token is a counter
+    /// stored at `key:fence`. Renewal and release only touch the key while it
+    /// still holds the caller's token.
+    #[derive(Clone)]
+    pub struct RedisLock {
+        conn: ConnectionManager,
+    }
+
+    impl RedisLock {
+        /// Use an existing connection
+        pub fn new(conn: ConnectionManager) -> Self {
+            Self { conn }
+        }
+
+        /// Connect to the Redis server at `url`, e.g. `redis://redis:6379`
+        pub async fn connect(url: &str) -> Result<Self> {
+            let client = redis::Client::open(url).map_err(redis_error)?;
+            let conn = client.get_connection_manager().await.map_err(redis_error)?;
+            Ok(Self::new(conn))
+        }
+    }
+
+    // failure talking to Redis
+    fn redis_error(error: redis::RedisError) -> Error {
+        Error::server_error(format!("Redis lock failed: {}", error))
+    }
+
+    impl DistributedLock for RedisLock {
+        fn try_acquire<'a>(
+            &'a self,
+            key: &'a str,
+            ttl: Duration,
+        ) -> BoxFuture<'a, Result<Option<Lease>>> {
+            Box::pin(async move {
+                let token = UuidV7.generate();
+                let fence: u64 = Script::new(ACQUIRE)
+                    .key(key)
+                    .key(format!("{}:fence", key))
+                    .arg(&token)
+                    .arg(ttl.as_millis() as u64)
+                    .invoke_async(&mut self.conn.clone())
+                    .await
+                    .map_err(redis_error)?;
+                Ok((fence > 0).then(|| Lease::new(key, token, fence)))
+            })
+        }
+
+        fn renew<'a>(&'a self, lease: &'a Lease, ttl: Duration) -> BoxFuture<'a, Result<bool>> {
+            Box::pin(async move {
+                let renewed: u64 = Script::new(RENEW)
+                    .key(lease.key())
+                    .arg(lease.token())
+                    .arg(ttl.as_millis() as u64)
+                    .invoke_async(&mut self.conn.clone())
+                    .await
+                    .map_err(redis_error)?;
+                Ok(renewed == 1)
+            })
+        }
+
+        fn release<'a>(&'a self, lease: &'a Lease) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                let _: u64 = Script::new(RELEASE)
+                    .key(lease.key())
+                    .arg(lease.token())
+                    .invoke_async(&mut self.conn.clone())
+                    .await
+                    .map_err(redis_error)?;
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_lock_excludes_and_fences() {
+        let lock = MemoryLock::new();
+        let ttl = Duration::from_secs(10);
+        let first = lock.try_acquire("job", ttl).await.unwrap().unwrap();
+        assert!(lock.try_acquire("job", ttl).await.unwrap().is_none());
+        assert!(lock.try_acquire("other", ttl).await.unwrap().is_some());
+
+        lock.release(&first).await.unwrap();
+        let second = lock.try_acquire("job", ttl).await.unwrap().unwrap();
+        assert!(second.fence() > first.fence());
+        assert!(!lock.renew(&first, ttl).await.unwrap());
+        assert!(lock.renew(&second, ttl).await.unwrap());
+
+        // a stale holder cannot release the new lease
+        lock.release(&first).await.unwrap();
+        assert!(lock.try_acquire("job", ttl).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_lock_expires() {
+        let clock = crate::clock::TestClock::new();
+        let lock = MemoryLock::new().clock(Arc::new(clock.clone()));
+        let lease = lock
+            .try_acquire("job", Duration::from_millis(10))
+            .await
+            .unwrap()
+            .unwrap();
+        clock.advance(Duration::from_millis(20));
+        assert!(!lock.renew(&lease, Duration::from_secs(1)).await.unwrap());
+        assert!(lock
+            .try_acquire("job", Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_run_exclusive_renews_and_counts_contention() {
+        let metrics = Metrics::new();
+        let locks = Locks::new(Arc::new(MemoryLock::new())).metrics(metrics.clone());
+        let ttl = Duration::from_millis(60);
+
+        let inner = locks.clone();
+        let ran = locks
+            .run_exclusive("report", ttl, |lease| async move {
+                // outlive the ttl; renewal keeps the lease
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                let contended = inner.run_exclusive("report", ttl, |_| async {}).await;
+                (lease.is_lost(), contended.unwrap())
+            })
+            .await
+            .unwrap();
+        assert_eq!(ran, Some((false, None)));
+        assert!(locks
+            .run_exclusive("report", ttl, |_| async {})
+            .await
+            .unwrap()
+            .is_some());
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("lock_acquire_total{key=\"report\",outcome=\"acquired\"} 2"));
+        assert!(text.contains("lock_acquire_total{key=\"report\",outcome=\"contended\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_run_exclusive_releases_when_dropped_or_panicking() {
+        let backend = Arc::new(MemoryLock::new());
+        let locks = Locks::new(backend.clone());
+        let ttl = Duration::from_secs(60);
+
+        let pending = locks.run_exclusive("job", ttl, |_| std::future::pending::<()>());
+        assert!(tokio::time::timeout(Duration::from_millis(10), pending)
+            .await
+            .is_err());
+        tokio::task::yield_now().await;
+        assert!(locks.try_acquire("job", ttl).await.unwrap().is_some());
+
+        let panicking = locks.clone();
+        let task = tokio::spawn(async move {
+            panicking
+                .run_exclusive("panics", ttl, |_| async { panic!("job failed") })
+                .await
+        });
+        assert!(task.await.unwrap_err().is_panic());
+        tokio::task::yield_now().await;
+        assert!(locks.try_acquire("panics", ttl).await.unwrap().is_some());
+
+        assert!(locks.try_acquire("zero", Duration::ZERO).await.is_err());
+    }
+}
@@ -0,0 +1,921 @@
+//! Metrics for RustAPI framework
+//!
+//! Per-route latency histograms keyed by route template, counters, gauges
+//! sampled at scrape time, a slow request log and a Prometheus text
+//! exposition of the collected data.
+//!
+//! Histograms and counters are atomics: recording takes a shared read lock
+//! to find the series, and a write lock only the first time a series is
+//! seen. Hot paths can keep a `Counter` handle and skip the lookup.
+//!
+//! Sampled requests leave their trace id as an exemplar on the latency
+//! bucket they fell into. With the `otel` feature the id is that of the
+//! current OpenTelemetry span, so requests starting a trace get one too;
+//! without it, that of the W3C `traceparent` header sent by instrumented
+//! callers and proxies. The `Sampler` decision of `App::trace_requests`
+//! applies in both cases. The OpenMetrics exposition includes the
+//! exemplars, so dashboards can jump from a slow bucket to an example trace.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{FromRequestParts, MatchedPath, RawPathParams, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use tokio::time::Instant;
+
+use crate::{capture, context::RequestContext};
+
+/// Default latency bucket upper bounds, in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type of the OpenMetrics exposition
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Example observation linking a histogram bucket to a trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    /// Trace id of the request, 32 hex digits
+    pub trace_id: String,
+    /// Observed value, in seconds
+    pub value: f64,
+    /// Unix time of the observation, in seconds
+    pub timestamp: f64,
+}
+
+impl Exemplar {
+    // exemplar for an observation made now
+    fn now(trace_id: &str, value: Duration) -> Self {
+        Self {
+            trace_id: trace_id.to_string(),
+            value: value.as_secs_f64(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |now| now.as_secs_f64()),
+        }
+    }
+}
+
+// index of the bucket holding `secs` (bounds.len() for +Inf)
+fn bucket(bounds: &[f64], secs: f64) -> usize {
+    bounds
+        .iter()
+        .position(|bound| secs <= *bound)
+        .unwrap_or(bounds.len())
+}
+
+/// Cumulative latency histogram
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: Arc<[f64]>,
+    counts: Vec<u64>,
+    exemplars: Vec<Option<Exemplar>>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the given bucket upper bounds (seconds)
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            counts: vec![0; bounds.len()],
+            exemplars: vec![None; bounds.len() + 1],
+            bounds: Arc::from(bounds),
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    /// Record one observation
+    pub fn observe(&mut self, value: Duration) {
+        self.record(value);
+    }
+
+    /// Record one observation made while serving trace `trace_id`
+    ///
+    /// The observation becomes the exemplar of its bucket, replacing the
+    /// previous one.
+    pub fn observe_with_exemplar(&mut self, value: Duration, trace_id: &str) {
+        let slot = self.record(value);
+        self.exemplars[slot] = Some(Exemplar::now(trace_id, value));
+    }
+
+    // count an observation, returning its bucket (bounds.len() for +Inf)
+    fn record(&mut self, value: Duration) -> usize {
+        let secs = value.as_secs_f64();
+        let slot = bucket(&self.bounds, secs);
+        if let Some(count) = self.counts.get_mut(slot) {
+            *count += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+        slot
+    }
+
+    /// Bucket upper bounds with the cumulative number of observations in each
+    pub fn buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (*bound, total)
+            })
+            .collect()
+    }
+
+    /// Latest exemplar of each bucket, followed by that of the `+Inf` bucket
+    pub fn exemplars(&self) -> &[Option<Exemplar>] {
+        &self.exemplars
+    }
+
+    /// Total number of observations
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of all observations, in seconds
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+}
+
+/// Shared metrics registry
+///
+/// Cheap to clone; clones record into the same registry.
+///
+/// # Example
+///
+/// ```ignore
+/// let metrics = Metrics::new().slow_request_threshold(Duration::from_millis(500));
+/// let app = App::new()
+///     .route("/users/{id}", routing::get(get_user))
+///     .metrics(metrics.clone());
+///
+/// // later, e.g. from a /metrics handler
+/// let text = metrics.render_prometheus();
+/// ```
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+// gauge sampled when metrics are rendered
+type GaugeFn = Arc<dyn Fn() -> f64 + Send + Sync>;
+
+// slow threshold value meaning "no slow log"
+const NO_THRESHOLD: u64 = u64::MAX;
+
+struct Inner {
+    bounds: RwLock<Arc<[f64]>>,
+    slow_threshold_nanos: AtomicU64,
+    // method -> route template -> histogram
+    routes: RwLock<BTreeMap<String, BTreeMap<String, Arc<LatencySeries>>>>,
+    counters: RwLock<BTreeMap<(String, String), Counter>>,
+    gauges: Mutex<BTreeMap<(String, String), GaugeFn>>,
+}
+
+// latency histogram of one route, recorded with atomics
+struct LatencySeries {
+    bounds: Arc<[f64]>,
+    // one per bound, then +Inf; not cumulative
+    counts: Box<[AtomicU64]>,
+    count: AtomicU64,
+    sum_bits: AtomicU64,
+    exemplars: Box<[Mutex<Option<Exemplar>>]>,
+}
+
+impl LatencySeries {
+    fn new(bounds: Arc<[f64]>) -> Self {
+        Self {
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            exemplars: (0..=bounds.len()).map(|_| Mutex::new(None)).collect(),
+            bounds,
+            count: AtomicU64::new(0),
+            sum_bits: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    fn observe(&self, value: Duration, trace_id: Option<&str>) {
+        let secs = value.as_secs_f64();
+        let slot = bucket(&self.bounds, secs);
+        self.counts[slot].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum_bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + secs).to_bits())
+            });
+        if let Some(trace_id) = trace_id {
+            *self.exemplars[slot]
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) = Some(Exemplar::now(trace_id, value));
+        }
+    }
+
+    // point-in-time copy; concurrent observations may be partly included
+    fn snapshot(&self) -> Histogram {
+        let bounds = self.bounds.len();
+        Histogram {
+            bounds: self.bounds.clone(),
+            counts: self.counts[..bounds]
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            exemplars: self
+                .exemplars
+                .iter()
+                .map(|exemplar| exemplar.lock().unwrap_or_else(|e| e.into_inner()).clone())
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: f64::from_bits(self.sum_bits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Handle on one counter series
+///
+/// Returned by `Metrics::counter_handle`; incrementing it is a single atomic
+/// add, without looking the series up by name and labels.
+#[derive(Debug, Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    /// Add one
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    /// Add `value`
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Metrics {
+    /// Create a registry with the default latency buckets
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                bounds: RwLock::new(Arc::from(DEFAULT_BUCKETS)),
+                slow_threshold_nanos: AtomicU64::new(NO_THRESHOLD),
+                routes: RwLock::new(BTreeMap::new()),
+                counters: RwLock::new(BTreeMap::new()),
+                gauges: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Use custom latency bucket upper bounds, in seconds
+    ///
+    /// Applies to routes first seen afterwards; set it before serving
+    /// requests.
+    pub fn buckets(self, bounds: &[f64]) -> Self {
+        *self.inner.bounds.write().unwrap_or_else(|e| e.into_inner()) = Arc::from(bounds);
+        self
+    }
+
+    /// Log requests slower than `threshold` at WARN
+    pub fn slow_request_threshold(self, threshold: Duration) -> Self {
+        let nanos = u64::try_from(threshold.as_nanos()).unwrap_or(NO_THRESHOLD - 1);
+        self.inner
+            .slow_threshold_nanos
+            .store(nanos.min(NO_THRESHOLD - 1), Ordering::Relaxed);
+        self
+    }
+
+    // threshold of the slow request log, if enabled
+    fn slow_threshold(&self) -> Option<Duration> {
+        match self.inner.slow_threshold_nanos.load(Ordering::Relaxed) {
+            NO_THRESHOLD => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    // latency series of a route, created on first use
+    fn series(&self, method: &str, route: &str) -> Arc<LatencySeries> {
+        let routes = self.inner.routes.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(series) = routes.get(method).and_then(|routes| routes.get(route)) {
+            return series.clone();
+        }
+        drop(routes);
+        let bounds = self
+            .inner
+            .bounds
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        self.inner
+            .routes
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(method.to_string())
+            .or_default()
+            .entry(route.to_string())
+            .or_insert_with(|| Arc::new(LatencySeries::new(bounds)))
+            .clone()
+    }
+
+    /// Record the latency of a request to a route template
+    pub fn observe(&self, method: &str, route: &str, latency: Duration) {
+        self.observe_traced(method, route, latency, None);
+    }
+
+    /// Record the latency of a request, keeping `trace_id` as the exemplar
+    /// of its bucket when given
+    pub fn observe_traced(
+        &self,
+        method: &str,
+        route: &str,
+        latency: Duration,
+        trace_id: Option<&str>,
+    ) {
+        self.series(method, route).observe(latency, trace_id);
+    }
+
+    /// Latency histogram for a route template, if it has seen any requests
+    pub fn histogram(&self, method: &str, route: &str) -> Option<Histogram> {
+        let routes = self.inner.routes.read().unwrap_or_else(|e| e.into_inner());
+        routes
+            .get(method)
+            .and_then(|routes| routes.get(route))
+            .map(|series| series.snapshot())
+    }
+
+    /// Increment a counter identified by name and labels
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// metrics.increment("jobs_processed_total", &[("queue", "emails")]);
+    /// ```
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    /// Add `value` to a counter identified by name and labels
+    pub fn add(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.counter_handle(name, labels).add(value);
+    }
+
+    /// Handle on the counter identified by name and labels, created at zero
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let sent = metrics.counter_handle("emails_sent_total", &[]);
+    /// // per email, without a registry lookup
+    /// sent.increment();
+    /// ```
+    pub fn counter_handle(&self, name: &str, labels: &[(&str, &str)]) -> Counter {
+        let key = (name.to_string(), render_labels(labels));
+        let counters = self
+            .inner
+            .counters
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(counter) = counters.get(&key) {
+            return counter.clone();
+        }
+        drop(counters);
+        self.inner
+            .counters
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    /// Current value of a counter, zero if it was never incremented
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let labels = render_labels(labels);
+        let counters = self
+            .inner
+            .counters
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        counters
+            .get(&(name.to_string(), labels))
+            .map_or(0, Counter::get)
+    }
+
+    /// Report a gauge whose value is read from `sample` on every render
+    ///
+    /// Registering the same name and labels again replaces the sampler.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let queue = jobs.clone();
+    /// metrics.gauge("job_queue_depth", &[("queue", "emails")], move || queue.len() as f64);
+    /// ```
+    pub fn gauge(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        sample: impl Fn() -> f64 + Send + Sync + 'static,
+    ) {
+        let labels = render_labels(labels);
+        self.inner
+            .gauges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((name.to_string(), labels), Arc::new(sample));
+    }
+
+    /// Current value of a gauge, `None` if it was never registered
+    pub fn gauge_value(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let labels = render_labels(labels);
+        let sample = self
+            .inner
+            .gauges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&(name.to_string(), labels))
+            .cloned();
+        sample.map(|sample| sample())
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        self.render(false)
+    }
+
+    /// Render all metrics in the OpenMetrics text format, with exemplars
+    ///
+    /// Serve it with `OPENMETRICS_CONTENT_TYPE` to scrapers asking for it.
+    pub fn render_openmetrics(&self) -> String {
+        let mut out = self.render(true);
+        out.push_str("# EOF\n");
+        out
+    }
+
+    // render in either format; OpenMetrics names counter families without
+    // `_total` and appends exemplars to histogram buckets
+    fn render(&self, openmetrics: bool) -> String {
+        let mut out = String::new();
+        let counters = self
+            .inner
+            .counters
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut last_name = None;
+        for ((name, labels), counter) in counters.iter() {
+            let (family, sample) = if openmetrics {
+                let family = name.strip_suffix("_total").unwrap_or(name);
+                (family, format!("{}_total", family))
+            } else {
+                (name.as_str(), name.clone())
+            };
+            if last_name != Some(family) {
+                let _ = writeln!(out, "# TYPE {} counter", family);
+                last_name = Some(family);
+            }
+            let _ = writeln!(out, "{}{{{}}} {}", sample, labels, counter.get());
+        }
+        drop(counters);
+
+        // samplers run without the lock so they may use the registry too
+        let gauges: Vec<_> = self
+            .inner
+            .gauges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(key, sample)| (key.clone(), sample.clone()))
+            .collect();
+        let mut last_name = None;
+        for ((name, labels), sample) in &gauges {
+            if last_name != Some(name) {
+                let _ = writeln!(out, "# TYPE {} gauge", name);
+                last_name = Some(name);
+            }
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, sample());
+        }
+
+        let routes: Vec<_> = self
+            .inner
+            .routes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .flat_map(|(method, routes)| {
+                routes
+                    .iter()
+                    .map(move |(route, series)| (method.clone(), route.clone(), series.snapshot()))
+            })
+            .collect();
+        out.push_str("# HELP http_request_duration_seconds Request latency by route\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (method, route, histogram) in &routes {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape_label(route));
+            let buckets = histogram
+                .buckets()
+                .into_iter()
+                .map(|(bound, count)| (bound.to_string(), count))
+                .chain([("+Inf".to_string(), histogram.count())]);
+            for ((bound, count), exemplar) in buckets.zip(histogram.exemplars()) {
+                let _ = write!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+                if let Some(exemplar) = exemplar.as_ref().filter(|_| openmetrics) {
+                    let _ = write!(
+                        out,
+                        " # {{trace_id=\"{}\"}} {} {:.3}",
+                        exemplar.trace_id, exemplar.value, exemplar.timestamp
+                    );
+                }
+                out.push('\n');
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels,
+                histogram.sum()
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels,
+                histogram.count()
+            );
+        }
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// trace id of the current request for its exemplar, if its trace is
+// sampled: from the current OpenTelemetry span with the `otel` feature, or
+// from the `traceparent` header
+fn exemplar_trace_id(headers: &HeaderMap) -> Option<String> {
+    let decision = RequestContext::with_current(RequestContext::sampling_decision).flatten();
+    #[cfg(feature = "otel")]
+    if let Some((trace_id, sampled)) = span_trace_id() {
+        return (sampled && decision.unwrap_or(true)).then_some(trace_id);
+    }
+    let (trace_id, sampled) = traceparent(headers)?;
+    decision.unwrap_or(sampled).then(|| trace_id.to_string())
+}
+
+// trace id and sampled flag of the current OpenTelemetry span, if it has a
+// valid context
+#[cfg(feature = "otel")]
+fn span_trace_id() -> Option<(String, bool)> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context.is_valid().then(|| {
+        (
+            span_context.trace_id().to_string(),
+            span_context.is_sampled(),
+        )
+    })
+}
+
+// trace id and sampled flag of a valid W3C `traceparent` header
+pub(crate) fn traceparent(headers: &HeaderMap) -> Option<(&str, bool)> {
+    let value = headers.get("traceparent")?.to_str().ok()?;
+    let mut fields = value.trim().split('-');
+    let (Some(version), Some(trace_id), Some(parent_id), Some(flags)) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return None;
+    };
+    let hex = |field: &str, len: usize| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let valid = hex(version, 2)
+        && version != "ff"
+        && hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && hex(parent_id, 16)
+        && hex(flags, 2);
+    let sampled = u8::from_str_radix(flags, 16).is_ok_and(|flags| flags & 1 == 1);
+    valid.then_some((trace_id, sampled))
+}
+
+// render labels as `key="value",...`
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware recording per-route latency and logging slow requests
+///
+/// Must run after routing (added with `Router::layer`) so the matched route
+/// template is known; unmatched requests are not recorded.
+pub(crate) async fn record_latency(
+    State(metrics): State<Metrics>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = req.into_parts();
+    let method = parts.method.to_string();
+    let route = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string());
+    let slow_threshold = metrics.slow_threshold();
+    // key parameters are only needed for the slow log
+    let params = match slow_threshold {
+        Some(_) => RawPathParams::from_request_parts(&mut parts, &())
+            .await
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default(),
+        None => String::new(),
+    };
+    // only the names of query parameters are logged; values may hold tokens
+    let query = slow_threshold
+        .map(|_| capture::redact_query(parts.uri.query().unwrap_or(""), |_| true))
+        .unwrap_or_default();
+    let trace_id = exemplar_trace_id(&parts.headers);
+
+    let start = Instant::now();
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let latency = start.elapsed();
+
+    let Some(route) = route else {
+        return response;
+    };
+    metrics.observe_traced(&method, &route, latency, trace_id.as_deref());
+
+    if slow_threshold.is_some_and(|threshold| latency >= threshold) {
+        let request_id = RequestContext::current()
+            .map(|ctx| ctx.request_id().to_string())
+            .unwrap_or_default();
+        tracing::warn!(
+            method = %method,
+            route = %route,
+            duration_ms = latency.as_millis() as u64,
+            params = %params,
+            query = %query,
+            request_id = %request_id,
+            status = response.status().as_u16(),
+            "Slow request"
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(Duration::from_millis(50));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(5));
+        assert_eq!(histogram.buckets(), vec![(0.1, 1), (1.0, 2)]);
+        assert_eq!(histogram.count(), 3);
+        assert!((histogram.sum() - 5.55).abs() < 1e-9);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_records_by_route_template() {
+        let metrics = Metrics::new().slow_request_threshold(Duration::from_millis(100));
+        let router = Router::new()
+            .route(
+                "/users/{id}",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    "user"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                metrics.clone(),
+                record_latency,
+            ));
+
+        for id in ["1", "2"] {
+            let request = Request::builder()
+                .uri(format!("/users/{}", id))
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+        let request = Request::builder()
+            .uri("/missing")
+            .body(Body::empty())
+            .unwrap();
+        router.oneshot(request).await.unwrap();
+
+        let histogram = metrics.histogram("GET", "/users/{id}").unwrap();
+        assert_eq!(histogram.count(), 2);
+        assert!(metrics.histogram("GET", "/missing").is_none());
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/users/{id}\",le=\"0.25\"} 2"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/users/{id}\"} 2"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_exemplars_from_traceparent() {
+        let metrics = Metrics::new();
+        metrics.increment("jobs_total", &[]);
+        let router = Router::new()
+            .route("/orders", get(|| async { "orders" }))
+            .layer(middleware::from_fn_with_state(
+                metrics.clone(),
+                record_latency,
+            ));
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        for flags in ["01", "00"] {
+            let request = Request::builder()
+                .uri("/orders")
+                .header(
+                    "traceparent",
+                    format!("00-{}-00f067aa0ba902b7-{}", trace_id, flags),
+                )
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let histogram = metrics.histogram("GET", "/orders").unwrap();
+        let exemplars: Vec<_> = histogram.exemplars().iter().flatten().collect();
+        assert_eq!(exemplars.len(), 1);
+        assert_eq!(exemplars[0].trace_id, trace_id);
+
+        let text = metrics.render_openmetrics();
+        let exemplar = format!("# {{trace_id=\"{}\"}}", trace_id);
+        assert_eq!(text.matches(&exemplar).count(), 1);
+        assert!(text.contains("# TYPE jobs counter\njobs_total{} 1\n"));
+        assert!(text.ends_with("# EOF\n"));
+        assert!(!metrics.render_prometheus().contains("trace_id"));
+    }
+
+    #[test]
+    fn test_exemplar_trace_id() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("traceparent", value.parse().unwrap());
+            headers
+        };
+        let valid = headers("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01");
+        assert_eq!(
+            exemplar_trace_id(&valid).as_deref(),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        let zero = headers("00-00000000000000000000000000000000-b7ad6b7169203331-01");
+        assert_eq!(exemplar_trace_id(&zero), None);
+        let short = headers("00-0af7651916cd43dd-b7ad6b7169203331-01");
+        assert_eq!(exemplar_trace_id(&short), None);
+        assert_eq!(exemplar_trace_id(&HeaderMap::new()), None);
+
+        // the sampler's decision overrides the caller's
+        let ctx = RequestContext::new("req-1");
+        ctx.set_sampled(false);
+        assert_eq!(ctx.sync_scope(|| exemplar_trace_id(&valid)), None);
+        let unsampled = headers("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00");
+        let ctx = RequestContext::new("req-2");
+        ctx.set_sampled(true);
+        assert!(ctx.sync_scope(|| exemplar_trace_id(&unsampled)).is_some());
+    }
+
+    #[cfg(feature = "otel")]
+    #[tokio::test]
+    async fn test_exemplars_from_the_current_span() {
+        use opentelemetry::trace::{TraceContextExt, TracerProvider};
+        use tracing::Instrument;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let metrics = Metrics::new();
+        let router = Router::new()
+            .route("/orders", get(|| async { "orders" }))
+            .layer(middleware::from_fn_with_state(
+                metrics.clone(),
+                record_latency,
+            ));
+        // this service starts the trace: no traceparent
+        let span = tracing::info_span!("request");
+        let trace_id = span.context().span().span_context().trace_id().to_string();
+        let request = Request::get("/orders").body(Body::empty()).unwrap();
+        router.oneshot(request).instrument(span).await.unwrap();
+
+        let histogram = metrics.histogram("GET", "/orders").unwrap();
+        let exemplars: Vec<_> = histogram.exemplars().iter().flatten().collect();
+        assert_eq!(exemplars.len(), 1);
+        assert_eq!(exemplars[0].trace_id, trace_id);
+    }
+
+    #[test]
+    fn test_counters() {
+        let metrics = Metrics::new();
+        metrics.increment("jobs_total", &[("queue", "a")]);
+        metrics.increment("jobs_total", &[("queue", "a")]);
+        metrics.increment("jobs_total", &[("queue", "b")]);
+        assert_eq!(metrics.counter("jobs_total", &[("queue", "a")]), 2);
+        assert_eq!(metrics.counter("jobs_total", &[("queue", "c")]), 0);
+
+        let text = metrics.render_prometheus();
+        assert_eq!(text.matches("# TYPE jobs_total counter").count(), 1);
+        assert!(text.contains("jobs_total{queue=\"b\"} 1"));
+    }
+
+    #[test]
+    fn test_counter_handles_and_configuration() {
+        let metrics = Metrics::new();
+        let sent = metrics.counter_handle("emails_sent_total", &[]);
+        sent.increment();
+        sent.add(2);
+
+        // configuring keeps what was recorded and handed out
+        let metrics = metrics
+            .buckets(&[0.5])
+            .slow_request_threshold(Duration::from_secs(1));
+        sent.increment();
+        assert_eq!(metrics.counter("emails_sent_total", &[]), 4);
+        assert_eq!(metrics.slow_threshold(), Some(Duration::from_secs(1)));
+        metrics.observe("GET", "/health", Duration::from_millis(100));
+        let histogram = metrics.histogram("GET", "/health").unwrap();
+        assert_eq!(histogram.buckets(), vec![(0.5, 1)]);
+    }
+
+    #[test]
+    fn test_gauges_are_sampled_on_render() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let depth = Arc::new(AtomicU64::new(3));
+        let metrics = Metrics::new();
+        let sampled = depth.clone();
+        metrics.gauge("queue_depth", &[("queue", "a")], move || {
+            sampled.load(Ordering::Relaxed) as f64
+        });
+        assert_eq!(
+            metrics.gauge_value("queue_depth", &[("queue", "a")]),
+            Some(3.0)
+        );
+        assert_eq!(metrics.gauge_value("queue_depth", &[("queue", "b")]), None);
+
+        depth.store(7, Ordering::Relaxed);
+        let text = metrics.render_prometheus();
+        assert!(text.contains("# TYPE queue_depth gauge"));
+        assert!(text.contains("queue_depth{queue=\"a\"} 7"));
+    }
+}
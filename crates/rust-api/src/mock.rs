@@ -0,0 +1,326 @@
+//! Contract mocking for RustAPI framework
+//!
+//! With `App::mock_unimplemented`, requests for routes that are declared in
+//! the route registry, and so in the OpenAPI document, but not served by any
+//! handler are answered with the route's `Example` instead of 404 or 405.
+//! Frontend teams can then develop against the contract before the backend
+//! exists. Routes can be declared without a handler by submitting a
+//! `RouteDef` by hand.
+//!
+//! Mocked responses carry `x-mock: true`. Routes without an example answer
+//! 501 Not Implemented. Mocking is meant for the `Dev` profile and is never
+//! enabled in `Prod`.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+
+use crate::{
+    error::ApiError,
+    registry::{self, RouteInfo, RouteRegistry, ServedRoutes},
+};
+
+/// Header marking a mocked response
+pub const MOCK_HEADER: &str = "x-mock";
+
+/// Route metadata holding an example response
+///
+/// Shown as the response example in the OpenAPI document and returned by
+/// the mock mode of `App::mock_unimplemented`.
+///
+/// # Example
+///
+/// ```ignore
+/// // a route of the contract, not implemented yet
+/// inventory::submit! {
+///     RouteDef::new("GET", "/orders/{id}", "shop::orders::get_order")
+/// }
+///
+/// inventory::submit! {
+///     RouteMetadata::new("shop::orders::get_order", |meta| {
+///         meta.insert(Example::new(r#"{"id": "o-1", "total": 1250}"#))
+///     })
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Example {
+    status: u16,
+    body: &'static str,
+}
+
+impl Example {
+    /// A 200 response with the JSON `body`
+    pub const fn new(body: &'static str) -> Self {
+        Self { status: 200, body }
+    }
+
+    /// Respond with `status` instead of 200
+    pub const fn status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Status code of the example
+    pub const fn status_code(&self) -> u16 {
+        self.status
+    }
+
+    /// JSON body of the example, as written
+    pub const fn body(&self) -> &'static str {
+        self.body
+    }
+
+    /// The body as a JSON value; a body that is not JSON becomes a string
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(self.body)
+            .unwrap_or_else(|_| serde_json::Value::String(self.body.to_string()))
+    }
+}
+
+/// Declared routes without a handler, by the paths they may be served at
+///
+/// A route is tried at its declared path and under each mount prefix, as
+/// its handler would be served once it exists.
+#[derive(Debug, Default)]
+pub(crate) struct Unserved {
+    routes: Vec<(String, RouteInfo)>,
+}
+
+impl Unserved {
+    /// Find the routes of `registry` that `served` does not serve
+    pub(crate) fn find(
+        registry: &RouteRegistry,
+        served: &ServedRoutes,
+        prefixes: &[String],
+    ) -> Self {
+        let mut routes = Vec::new();
+        for route in registry.iter() {
+            let is_served = served.iter().any(|served| {
+                served.route.handler == route.handler && served.route.method == route.method
+            });
+            if is_served {
+                continue;
+            }
+            for prefix in std::iter::once("").chain(prefixes.iter().map(String::as_str)) {
+                routes.push((registry::join(prefix, route.path), route.clone()));
+            }
+        }
+        Self { routes }
+    }
+
+    // the route declared for `method` whose template matches `path`
+    fn get(&self, method: &str, path: &str) -> Option<&RouteInfo> {
+        self.routes
+            .iter()
+            .filter(|(_, route)| route.method.eq_ignore_ascii_case(method))
+            .find(|(template, _)| matches_template(template, path))
+            .map(|(_, route)| route)
+    }
+}
+
+// answer requests for unserved routes from the registry; requests matching
+// no route still reach the app's fallback, and 405s of other methods stay
+pub(crate) fn mock_unimplemented(router: Router, unserved: Unserved) -> Router {
+    router.layer(middleware::from_fn_with_state(Arc::new(unserved), answer))
+}
+
+// mock a request no route matched, or one whose method is not allowed
+async fn answer(State(unserved): State<Arc<Unserved>>, req: Request, next: Next) -> Response {
+    let route = unserved.get(req.method().as_str(), req.uri().path());
+    match route {
+        Some(route) if req.extensions().get::<MatchedPath>().is_none() => mock(route),
+        Some(route) => {
+            let route = route.clone();
+            let response = next.run(req).await;
+            if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+                mock(&route)
+            } else {
+                response
+            }
+        }
+        None => next.run(req).await,
+    }
+}
+
+// mocked response of a declared route
+fn mock(route: &RouteInfo) -> Response {
+    let mut response = match route.metadata.get::<Example>() {
+        Some(example) => {
+            let status = StatusCode::from_u16(example.status).unwrap_or(StatusCode::OK);
+            let mut response = (status, example.body).into_response();
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+            response
+        }
+        None => ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            format!("{} {} has no example to mock", route.method, route.path),
+        )
+        .with_code("not_implemented")
+        .into_response(),
+    };
+    response
+        .headers_mut()
+        .insert(MOCK_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+// check whether `path` fits a `/users/{id}` or `/files/{*rest}` template
+fn matches_template(template: &str, path: &str) -> bool {
+    let mut segments = path.trim_start_matches('/').split('/');
+    for part in template.trim_start_matches('/').split('/') {
+        if part.starts_with("{*") {
+            return segments.next().is_some_and(|s| !s.is_empty());
+        }
+        match segments.next() {
+            Some(segment) if part.starts_with('{') => {
+                if segment.is_empty() {
+                    return false;
+                }
+            }
+            Some(segment) if segment == part => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        registry::{RouteDef, RouteMetadata},
+        App, Profile,
+    };
+
+    inventory::submit! {
+        RouteDef::new("GET", "/mock-test/orders/{id}", concat!(module_path!(), "::", "get_order"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "get_order"), |meta| {
+            meta.insert(Example::new(r#"{"id":"o-1","total":1250}"#))
+        })
+    }
+
+    inventory::submit! {
+        RouteDef::new("POST", "/mock-test/orders", concat!(module_path!(), "::", "create_order"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "create_order"), |meta| {
+            meta.insert(Example::new(r#"{"id":"o-2"}"#).status(201))
+        })
+    }
+
+    inventory::submit! {
+        RouteDef::new("DELETE", "/mock-test/orders/{id}", concat!(module_path!(), "::", "delete_order"))
+    }
+
+    #[test]
+    fn test_matches_template() {
+        assert!(matches_template("/users/{id}", "/users/42"));
+        assert!(!matches_template("/users/{id}", "/users"));
+        assert!(!matches_template("/users/{id}", "/users/42/posts"));
+        assert!(matches_template("/files/{*path}", "/files/a/b.txt"));
+        assert!(!matches_template("/files/{*path}", "/files/"));
+        assert!(matches_template("/", "/"));
+    }
+
+    #[tokio::test]
+    async fn test_mocks_declared_routes_without_handlers() {
+        let router = App::new()
+            .route("/mock-test/orders", get(|| async { "implemented" }))
+            .mock_unimplemented()
+            .build();
+        let send = |method: &str, uri: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let response = send("GET", "/mock-test/orders").await.unwrap();
+        assert!(!response.headers().contains_key(MOCK_HEADER));
+
+        let response = send("GET", "/mock-test/orders/o-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[MOCK_HEADER], "true");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"{"id":"o-1","total":1250}"#);
+
+        let response = send("POST", "/mock-test/orders").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = send("DELETE", "/mock-test/orders/o-1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let response = send("PUT", "/mock-test/orders").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = send("GET", "/mock-test/unknown").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn status(router: &Router, method: &str, uri: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_mocks_compose_with_fallbacks_and_prefixes() {
+        let router = App::new()
+            .nest("/api", App::new().route("/health", get(|| async { "ok" })))
+            .merge(Router::new().fallback(|| async { StatusCode::IM_A_TEAPOT }))
+            .mock_unimplemented()
+            .build();
+
+        assert_eq!(
+            status(&router, "GET", "/api/mock-test/orders/o-1").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, "GET", "/mock-test/orders/o-1").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, "GET", "/other/mock-test/orders/o-1").await,
+            StatusCode::IM_A_TEAPOT
+        );
+        assert_eq!(
+            status(&router, "GET", "/unknown").await,
+            StatusCode::IM_A_TEAPOT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mocks_are_refused_in_prod() {
+        let router = App::new()
+            .with_profile(Profile::Prod)
+            .mock_unimplemented()
+            .build();
+        assert_eq!(
+            status(&router, "GET", "/mock-test/orders/o-1").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
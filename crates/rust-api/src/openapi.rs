@@ -0,0 +1,292 @@
+//! OpenAPI document generation for RustAPI framework
+//!
+//! Builds an OpenAPI 3.1 document from the routes declared with route
+//! macros: one operation per method and path, with path parameters and
+//! deprecation and example responses taken from the route registry. Routes added with plain
+//! `App::route` are not known to the registry and are not documented.
+//!
+//! `App::openapi_spec` documents the routes the app serves, at the path it
+//! serves them under; operations only served on some hosts list them as
+//! `servers`.
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    deprecation::Deprecated,
+    formats::MediaTypes,
+    host::HostPattern,
+    mock::Example,
+    registry::{RouteInfo, RouteRegistry, ServedRoutes},
+};
+
+/// OpenAPI version of the generated documents
+pub const OPENAPI_VERSION: &str = "3.1.0";
+
+/// Document-level information of the generated OpenAPI spec
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new().openapi(OpenApi::new("Users API", "1.4.0").description("User accounts"));
+/// let spec = app.openapi_spec();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenApi {
+    title: String,
+    version: String,
+    description: Option<String>,
+}
+
+impl OpenApi {
+    /// Create document info with a title and API version
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            version: version.into(),
+            description: None,
+        }
+    }
+
+    /// Set the API description
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Generate the document for the routes in `registry`, at the paths
+    /// they declare
+    ///
+    /// The output is deterministic: paths and methods are sorted, so the
+    /// document can be committed and diffed.
+    pub fn document(&self, registry: &RouteRegistry) -> Value {
+        self.render(registry.iter().map(|route| (route.path, route, &[][..])))
+    }
+
+    /// Generate the document for the routes an application serves
+    pub fn document_served(&self, routes: &ServedRoutes) -> Value {
+        self.render(
+            routes
+                .iter()
+                .map(|served| (served.path.as_str(), &served.route, &served.hosts[..])),
+        )
+    }
+
+    // document for (path, route, hosts) entries
+    fn render<'a>(
+        &self,
+        routes: impl Iterator<Item = (&'a str, &'a RouteInfo, &'a [HostPattern])>,
+    ) -> Value {
+        let mut info = json!({ "title": self.title, "version": self.version });
+        if let Some(description) = &self.description {
+            info["description"] = json!(description);
+        }
+
+        let mut paths = Map::new();
+        for (path, route, hosts) in routes {
+            let item = paths
+                .entry(openapi_path(path))
+                .or_insert_with(|| Value::Object(Map::new()));
+            let mut operation = operation(path, route);
+            if !hosts.is_empty() {
+                operation["servers"] = hosts.iter().map(server).collect();
+            }
+            item[route.method.to_ascii_lowercase()] = operation;
+        }
+
+        json!({
+            "openapi": OPENAPI_VERSION,
+            "info": info,
+            "paths": paths,
+        })
+    }
+}
+
+impl Default for OpenApi {
+    fn default() -> Self {
+        Self::new("RustAPI", "0.1.0")
+    }
+}
+
+// axum's catch-all `{*rest}` is a plain `{rest}` parameter in OpenAPI
+fn openapi_path(path: &str) -> String {
+    path.replace("{*", "{")
+}
+
+// names of the `{name}` / `{*name}` captures in a path template
+pub(crate) fn path_params(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| name.trim_start_matches('*'))
+        .collect()
+}
+
+// server object for a host pattern; a wildcard becomes a variable
+fn server(host: &HostPattern) -> Value {
+    match host.as_str().strip_prefix("*.") {
+        Some(domain) => json!({
+            "url": format!("https://{{subdomain}}.{}", domain),
+            "variables": { "subdomain": { "default": "www" } },
+        }),
+        None => json!({ "url": format!("https://{}", host) }),
+    }
+}
+
+// operation object for one route served at `path`
+fn operation(path: &str, route: &RouteInfo) -> Value {
+    let handler = route.handler;
+    let operation_id = handler.rsplit("::").next().unwrap_or(handler);
+    let mut operation = json!({ "operationId": operation_id });
+    let params: Vec<Value> = path_params(path)
+        .into_iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect();
+    if !params.is_empty() {
+        operation["parameters"] = Value::Array(params);
+    }
+    if let Some(deprecated) = route.metadata.get::<Deprecated>() {
+        operation["deprecated"] = json!(true);
+        // kept so `DeprecationPolicy` can check removals against snapshots
+        if let Some(since) = deprecated.deprecated_since() {
+            operation["x-deprecated-since"] = json!(since);
+        }
+        if let Some(sunset) = deprecated.sunset_date() {
+            operation["x-sunset"] = json!(sunset);
+        }
+    }
+    if let Some(example) = route.metadata.get::<Example>() {
+        operation["responses"] = json!({
+            example.status_code().to_string(): {
+                "description": "Example response",
+                "content": { "application/json": { "example": example.json() } },
+            }
+        });
+    }
+    if let Some(media_types) = route.metadata.get::<MediaTypes>() {
+        if let Some(request) = media_types.request_type() {
+            operation["requestBody"] = json!({ "required": true, "content": { request: {} } });
+        }
+        if let Some(response) = media_types.response_type() {
+            let responses = &mut operation["responses"];
+            if responses.is_null() {
+                *responses = json!({ "200": { "description": "Response" } });
+            }
+            for status in responses
+                .as_object_mut()
+                .into_iter()
+                .flat_map(|r| r.values_mut())
+            {
+                status["content"][response] = json!({});
+            }
+        }
+    }
+    operation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let mut registry = RouteRegistry::default();
+        registry.register("GET", "/users/{id}", "app::users::get_user");
+        registry.register("DELETE", "/users/{id}", "app::users::delete_user");
+        registry
+            .register("POST", "/users", "app::users::create_user")
+            .metadata
+            .insert(Example::new(r#"{"id": "u-1"}"#).status(201));
+        registry
+            .register("GET", "/v1/files/{*path}", "app::files::download")
+            .metadata
+            .insert(Deprecated::new().sunset("Thu, 01 Jan 2026 00:00:00 GMT"));
+
+        let doc = OpenApi::new("Users", "2.0.0")
+            .description("User accounts")
+            .document(&registry);
+        assert_eq!(doc["openapi"], "3.1.0");
+        assert_eq!(doc["info"]["title"], "Users");
+        assert_eq!(doc["info"]["description"], "User accounts");
+
+        let user = &doc["paths"]["/users/{id}"];
+        assert_eq!(user["get"]["operationId"], "get_user");
+        assert_eq!(user["delete"]["operationId"], "delete_user");
+        assert_eq!(user["get"]["parameters"][0]["name"], "id");
+        assert!(user["get"].get("deprecated").is_none());
+        assert!(user["get"].get("responses").is_none());
+
+        let created = &doc["paths"]["/users"]["post"]["responses"]["201"];
+        assert_eq!(
+            created["content"]["application/json"]["example"]["id"],
+            "u-1"
+        );
+
+        let files = &doc["paths"]["/v1/files/{path}"]["get"];
+        assert_eq!(files["parameters"][0]["name"], "path");
+        assert_eq!(files["deprecated"], true);
+        assert_eq!(files["x-sunset"], "Thu, 01 Jan 2026 00:00:00 GMT");
+        assert!(files.get("x-deprecated-since").is_none());
+    }
+
+    #[test]
+    fn test_document_served() {
+        use axum::routing::get;
+
+        use crate::router::Router;
+
+        let mut registry = RouteRegistry::default();
+        registry.register("GET", "/users/{id}", "app::users::get_user");
+        registry.register("GET", "/stats", "app::admin::stats");
+        registry.register("GET", "/unmounted", "app::unmounted");
+        let default = Router::new().nest(
+            "/api",
+            Router::new().route("/users/{id}", get(|| async { "user" })),
+        );
+        let hosts = [
+            (
+                HostPattern::new("admin.example.com"),
+                Router::new().route("/stats", get(|| async { "stats" })),
+            ),
+            (
+                HostPattern::new("*.example.com"),
+                Router::new().route("/stats", get(|| async { "stats" })),
+            ),
+        ];
+        let served = ServedRoutes::discover(&registry, &["/api".to_string()], &default, &hosts);
+        assert_eq!(served.len(), 2);
+        assert_eq!(
+            served.get("get", "/api/users/{id}").unwrap().route.path,
+            "/users/{id}"
+        );
+
+        let doc = OpenApi::default().document_served(&served);
+        let user = &doc["paths"]["/api/users/{id}"]["get"];
+        assert_eq!(user["parameters"][0]["name"], "id");
+        assert!(user.get("servers").is_none());
+        assert_eq!(
+            doc["paths"]["/stats"]["get"]["servers"],
+            json!([
+                { "url": "https://admin.example.com" },
+                {
+                    "url": "https://{subdomain}.example.com",
+                    "variables": { "subdomain": { "default": "www" } },
+                },
+            ])
+        );
+        assert!(doc["paths"].get("/unmounted").is_none());
+    }
+
+    #[test]
+    fn test_default_info() {
+        let doc = OpenApi::default().document(&RouteRegistry::default());
+        assert_eq!(doc["info"]["title"], "RustAPI");
+        assert!(doc.get("info").unwrap().get("description").is_none());
+        assert_eq!(doc["paths"], json!({}));
+    }
+}
@@ -0,0 +1,652 @@
+//! Transactional outbox for RustAPI framework
+//!
+//! Publishing an event after committing a transaction loses the event when
+//! the process dies in between; publishing before commits to an event for
+//! changes that may roll back. With an outbox, the event is written to an
+//! outbox table in the same transaction as the changes, so both are stored
+//! or neither is, and an `OutboxRelay` worker publishes stored events on
+//! the `EventBus` afterwards.
+//!
+//! # Delivery
+//!
+//! The relay marks each message delivered once it is published; marking is
+//! idempotent, and with `OutboxRelay::locks` only one replica relays at a
+//! time. A relay that dies between publishing and marking publishes the
+//! message again on its next run, so delivery is at-least-once. Every
+//! message is published with its outbox id, the same on each redelivery,
+//! which subscribers read with `Subscription::recv_delivery` to drop the
+//! messages they already handled.
+//!
+//! Delivered messages are kept for `OutboxRelay::retention`, then deleted
+//! by the relay.
+//!
+//! `MemoryOutbox` serves tests; with the `sqlx-postgres` and `sqlx-sqlite`
+//! features, `PgOutbox` and `SqliteOutbox` store messages in a table.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::Instant;
+
+use crate::{
+    di::{Container, Injectable},
+    error::{Error, Result},
+    events::{Event, EventBus},
+    ids::{IdGenerator, UuidV7},
+    lifecycle::BoxFuture,
+    lock::Locks,
+    metrics::Metrics,
+    worker::{StopSignal, Worker},
+};
+
+/// Default name of the outbox table
+pub const DEFAULT_TABLE: &str = "outbox";
+
+/// Default number of messages published per relay run
+pub const DEFAULT_BATCH: usize = 100;
+
+/// Default wait between relay runs that found nothing to publish
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default time delivered messages are kept before they are deleted
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Interval between deletions of expired delivered messages
+const SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Lock held by the relaying replica
+const RELAY_LOCK: &str = "outbox:relay";
+
+/// How long a relay holds its lock without renewing it
+const RELAY_LOCK_TTL: Duration = Duration::from_secs(30);
+
+/// An event stored in the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxMessage {
+    /// Unique id from the store's `IdGenerator`
+    pub id: String,
+    /// Topic the event is published on
+    pub topic: String,
+    /// The event, serialized as JSON
+    pub payload: Vec<u8>,
+}
+
+impl OutboxMessage {
+    /// Serialize `event` into a new message with an id from `ids`
+    pub fn new<E: Event>(ids: &dyn IdGenerator, event: &E) -> Result<Self> {
+        let payload = serde_json::to_vec(event)
+            .map_err(|e| Error::other(format!("Failed to serialize {}: {}", E::TOPIC, e)))?;
+        Ok(Self {
+            id: ids.generate(),
+            topic: E::TOPIC.to_string(),
+            payload,
+        })
+    }
+}
+
+/// Storage of outbox messages read by the relay
+///
+/// Staging messages is specific to each store, since it has to join the
+/// caller's transaction.
+pub trait OutboxStore: Send + Sync + 'static {
+    /// Up to `limit` undelivered messages, oldest first
+    fn pending(&self, limit: usize) -> BoxFuture<'_, Result<Vec<OutboxMessage>>>;
+
+    /// Mark the message `id` delivered; does nothing if it already is
+    fn mark_delivered<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// Delete the messages delivered before `before`, returning how many
+    fn purge_delivered(&self, before: SystemTime) -> BoxFuture<'_, Result<usize>>;
+}
+
+impl Injectable for dyn OutboxStore {}
+
+// a staged message with its delivery time
+type Stored = (OutboxMessage, Option<SystemTime>);
+
+/// In-process `OutboxStore`, for tests
+#[derive(Clone)]
+pub struct MemoryOutbox {
+    // in staging order
+    messages: Arc<Mutex<Vec<Stored>>>,
+    ids: Arc<dyn IdGenerator>,
+}
+
+impl Default for MemoryOutbox {
+    fn default() -> Self {
+        Self {
+            messages: Arc::default(),
+            ids: Arc::new(UuidV7),
+        }
+    }
+}
+
+impl MemoryOutbox {
+    /// Create an empty outbox
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate message ids with `generator` instead of UUID v7
+    pub fn id_generator(mut self, generator: Arc<dyn IdGenerator>) -> Self {
+        self.ids = generator;
+        self
+    }
+
+    /// Store `event` for the relay
+    pub fn stage<E: Event>(&self, event: &E) -> Result<()> {
+        let message = OutboxMessage::new(self.ids.as_ref(), event)?;
+        self.lock().push((message, None));
+        Ok(())
+    }
+
+    /// Messages not delivered yet
+    pub fn undelivered(&self) -> usize {
+        self.lock()
+            .iter()
+            .filter(|(_, delivered)| delivered.is_none())
+            .count()
+    }
+
+    // the stored messages
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Stored>> {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl OutboxStore for MemoryOutbox {
+    fn pending(&self, limit: usize) -> BoxFuture<'_, Result<Vec<OutboxMessage>>> {
+        let pending = self
+            .lock()
+            .iter()
+            .filter(|(_, delivered)| delivered.is_none())
+            .take(limit)
+            .map(|(message, _)| message.clone())
+            .collect();
+        Box::pin(async move { Ok(pending) })
+    }
+
+    fn mark_delivered<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<()>> {
+        if let Some((_, delivered)) = self.lock().iter_mut().find(|(message, _)| message.id == id) {
+            delivered.get_or_insert_with(SystemTime::now);
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn purge_delivered(&self, before: SystemTime) -> BoxFuture<'_, Result<usize>> {
+        let mut messages = self.lock();
+        let stored = messages.len();
+        messages.retain(|(_, delivered)| !delivered.is_some_and(|at| at < before));
+        let purged = stored - messages.len();
+        Box::pin(async move { Ok(purged) })
+    }
+}
+
+/// Worker publishing outbox messages on the event bus
+///
+/// Register it with `App::worker`.
+///
+/// # Example
+///
+/// ```ignore
+/// let outbox = PgOutbox::new(pool.clone());
+/// outbox.create_table().await?;
+///
+/// let mut tx = pool.begin().await?;
+/// sqlx::query("INSERT INTO orders (id) VALUES ($1)").bind(&id).execute(&mut *tx).await?;
+/// outbox.stage(&mut tx, &OrderPlaced { id }).await?;
+/// tx.commit().await?;
+///
+/// app.worker("outbox", OutboxRelay::new(Arc::new(outbox), bus).locks(locks));
+/// ```
+pub struct OutboxRelay {
+    store: Arc<dyn OutboxStore>,
+    bus: EventBus,
+    batch: usize,
+    interval: Duration,
+    locks: Option<Locks>,
+    metrics: Option<Metrics>,
+    retention: Duration,
+    // when delivered messages were last purged
+    swept: Mutex<Option<Instant>>,
+}
+
+impl OutboxRelay {
+    /// Publish the messages of `store` on `bus`
+    pub fn new(store: Arc<dyn OutboxStore>, bus: EventBus) -> Self {
+        Self {
+            store,
+            bus,
+            batch: DEFAULT_BATCH,
+            interval: DEFAULT_POLL_INTERVAL,
+            locks: None,
+            metrics: None,
+            retention: DEFAULT_RETENTION,
+            swept: Mutex::new(None),
+        }
+    }
+
+    /// Publish at most `batch` messages per run (default: 100)
+    pub fn batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// Wait `interval` between runs that found nothing (default: 1 second)
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Relay from one replica at a time, holding a lock from `locks`
+    pub fn locks(mut self, locks: Locks) -> Self {
+        self.locks = Some(locks);
+        self
+    }
+
+    /// Delete delivered messages once they are `retention` old (default:
+    /// 7 days)
+    ///
+    /// Subscribers deduplicating by message id only need ids as old as the
+    /// redeliveries they may see.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Count published messages in `outbox_published_total{topic}`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Publish one batch of pending messages
+    ///
+    /// Returns how many were published; zero when another replica holds the
+    /// relay lock.
+    pub async fn relay_once(&self) -> Result<usize> {
+        match &self.locks {
+            Some(locks) => locks
+                .run_exclusive(RELAY_LOCK, RELAY_LOCK_TTL, |_| self.deliver())
+                .await?
+                .unwrap_or(Ok(0)),
+            None => self.deliver().await,
+        }
+    }
+
+    // publish pending messages in order, stopping at the first failure
+    async fn deliver(&self) -> Result<usize> {
+        let messages = self.store.pending(self.batch).await?;
+        for message in &messages {
+            self.bus
+                .publish_raw(&message.topic, Some(&message.id), message.payload.clone())
+                .await?;
+            self.store.mark_delivered(&message.id).await?;
+            if let Some(metrics) = &self.metrics {
+                metrics.increment("outbox_published_total", &[("topic", &message.topic)]);
+            }
+        }
+        self.sweep().await;
+        Ok(messages.len())
+    }
+
+    // delete expired delivered messages, at most once per sweep interval
+    async fn sweep(&self) {
+        let now = Instant::now();
+        {
+            let mut swept = self.swept.lock().unwrap_or_else(|e| e.into_inner());
+            if swept.is_some_and(|swept| now.duration_since(swept) < SWEEP_INTERVAL) {
+                return;
+            }
+            *swept = Some(now);
+        }
+        let before = SystemTime::now()
+            .checked_sub(self.retention)
+            .unwrap_or(UNIX_EPOCH);
+        match self.store.purge_delivered(before).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::debug!("Purged {} delivered outbox messages", purged),
+            Err(e) => tracing::warn!("Purging delivered outbox messages failed: {}", e),
+        }
+    }
+}
+
+impl Worker for OutboxRelay {
+    fn run(&self, _: Arc<Container>, mut stop: StopSignal) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            loop {
+                match self.relay_once().await {
+                    // more may be waiting
+                    Ok(published) if published == self.batch && !stop.is_stopped() => continue,
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Outbox relay failed: {}", e),
+                }
+                tokio::select! {
+                    _ = stop.stopped() => return Ok(()),
+                    _ = tokio::time::sleep(self.interval) => {}
+                }
+            }
+        })
+    }
+}
+
+// milliseconds since the Unix epoch
+#[cfg_attr(
+    not(any(feature = "sqlx-postgres", feature = "sqlx-sqlite")),
+    allow(dead_code)
+)]
+fn now_ms() -> i64 {
+    epoch_ms(SystemTime::now())
+}
+
+// milliseconds from the Unix epoch to `time`
+#[cfg_attr(
+    not(any(feature = "sqlx-postgres", feature = "sqlx-sqlite")),
+    allow(dead_code)
+)]
+fn epoch_ms(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64)
+}
+
+#[cfg(feature = "sqlx-postgres")]
+pub use self::sqlx_outbox::PgOutbox;
+#[cfg(feature = "sqlx-sqlite")]
+pub use self::sqlx_outbox::SqliteOutbox;
+
+#[cfg(any(feature = "sqlx-postgres", feature = "sqlx-sqlite"))]
+mod sqlx_outbox {
+    use super::*;
+
+    // quote an SQL identifier
+    fn quote(name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+
+    // failure talking to the database
+    fn sqlx_error(error: sqlx::Error) -> Error {
+        Error::server_error(format!("Outbox query failed: {}", error))
+    }
+
+    // an `OutboxStore` on a table of one sqlx backend
+    macro_rules! sqlx_outbox {
+        ($(#[$meta:meta])* $name:ident, $pool:ty, $conn:ty, $blob:literal, $p:expr) => {
+            $(#[$meta])*
+            #[derive(Clone)]
+            pub struct $name {
+                pool: $pool,
+                table: String,
+                ids: Arc<dyn IdGenerator>,
+            }
+
+            impl $name {
+                /// Store messages in the `outbox` table of `pool`
+                pub fn new(pool: $pool) -> Self {
+                    Self {
+                        pool,
+                        table: DEFAULT_TABLE.to_string(),
+                        ids: Arc::new(UuidV7),
+                    }
+                }
+
+                /// Generate message ids with `generator` instead of UUID v7
+                pub fn id_generator(mut self, generator: Arc<dyn IdGenerator>) -> Self {
+                    self.ids = generator;
+                    self
+                }
+
+                /// Store messages in `table` instead
+                pub fn table(mut self, table: &str) -> Self {
+                    self.table = table.to_string();
+                    self
+                }
+
+                /// Create the outbox table unless it exists
+                pub async fn create_table(&self) -> Result<()> {
+                    let sql = format!(
+                        "CREATE TABLE IF NOT EXISTS {} (id TEXT PRIMARY KEY, topic TEXT NOT NULL, payload {} NOT NULL, created_at BIGINT NOT NULL, delivered_at BIGINT)",
+                        quote(&self.table),
+                        $blob
+                    );
+                    sqlx::query(&sql)
+                        .execute(&self.pool)
+                        .await
+                        .map_err(sqlx_error)?;
+                    Ok(())
+                }
+
+                /// Store `event` through `conn`, as part of its transaction
+                ///
+                /// Pass the transaction the event's changes are written in,
+                /// as `&mut tx`.
+                pub async fn stage<E: Event>(&self, conn: &mut $conn, event: &E) -> Result<()> {
+                    let message = OutboxMessage::new(self.ids.as_ref(), event)?;
+                    let p = $p;
+                    let sql = format!(
+                        "INSERT INTO {} (id, topic, payload, created_at) VALUES ({}, {}, {}, {})",
+                        quote(&self.table),
+                        p(1),
+                        p(2),
+                        p(3),
+                        p(4)
+                    );
+                    sqlx::query(&sql)
+                        .bind(message.id)
+                        .bind(message.topic)
+                        .bind(message.payload)
+                        .bind(now_ms())
+                        .execute(conn)
+                        .await
+                        .map_err(sqlx_error)?;
+                    Ok(())
+                }
+            }
+
+            impl Injectable for $name {}
+
+            impl OutboxStore for $name {
+                fn pending(&self, limit: usize) -> BoxFuture<'_, Result<Vec<OutboxMessage>>> {
+                    Box::pin(async move {
+                        let sql = format!(
+                            "SELECT id, topic, payload FROM {} WHERE delivered_at IS NULL ORDER BY created_at, id LIMIT {}",
+                            quote(&self.table),
+                            ($p)(1)
+                        );
+                        let rows: Vec<(String, String, Vec<u8>)> = sqlx::query_as(&sql)
+                            .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+                            .fetch_all(&self.pool)
+                            .await
+                            .map_err(sqlx_error)?;
+                        Ok(rows
+                            .into_iter()
+                            .map(|(id, topic, payload)| OutboxMessage { id, topic, payload })
+                            .collect())
+                    })
+                }
+
+                fn mark_delivered<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<()>> {
+                    Box::pin(async move {
+                        let p = $p;
+                        let sql = format!(
+                            "UPDATE {} SET delivered_at = {} WHERE id = {} AND delivered_at IS NULL",
+                            quote(&self.table),
+                            p(1),
+                            p(2)
+                        );
+                        sqlx::query(&sql)
+                            .bind(now_ms())
+                            .bind(id)
+                            .execute(&self.pool)
+                            .await
+                            .map_err(sqlx_error)?;
+                        Ok(())
+                    })
+                }
+
+                fn purge_delivered(&self, before: SystemTime) -> BoxFuture<'_, Result<usize>> {
+                    Box::pin(async move {
+                        let sql = format!(
+                            "DELETE FROM {} WHERE delivered_at < {}",
+                            quote(&self.table),
+                            ($p)(1)
+                        );
+                        let result = sqlx::query(&sql)
+                            .bind(epoch_ms(before))
+                            .execute(&self.pool)
+                            .await
+                            .map_err(sqlx_error)?;
+                        Ok(result.rows_affected() as usize)
+                    })
+                }
+            }
+        };
+    }
+
+    #[cfg(feature = "sqlx-postgres")]
+    sqlx_outbox!(
+        /// `OutboxStore` on a Postgres table
+        PgOutbox,
+        sqlx::PgPool,
+        sqlx::PgConnection,
+        "BYTEA",
+        |n: usize| format!("${}", n)
+    );
+
+    #[cfg(feature = "sqlx-sqlite")]
+    sqlx_outbox!(
+        /// `OutboxStore` on an SQLite table
+        SqliteOutbox,
+        sqlx::SqlitePool,
+        sqlx::SqliteConnection,
+        "BLOB",
+        |_: usize| "?".to_string()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::lock::MemoryLock;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct OrderPlaced {
+        id: u32,
+    }
+
+    impl Event for OrderPlaced {
+        const TOPIC: &'static str = "orders.placed";
+    }
+
+    #[tokio::test]
+    async fn test_message_ids_come_from_the_generator() {
+        let ids = crate::ids::SequentialIds::new("msg-");
+        let outbox = MemoryOutbox::new().id_generator(Arc::new(ids));
+        outbox.stage(&OrderPlaced { id: 1 }).unwrap();
+        outbox.stage(&OrderPlaced { id: 2 }).unwrap();
+        let pending = outbox.pending(10).await.unwrap();
+        let ids: Vec<_> = pending.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, ["msg-1", "msg-2"]);
+    }
+
+    #[tokio::test]
+    async fn test_relay_publishes_in_order_once() {
+        let ids = crate::ids::SequentialIds::new("msg-");
+        let outbox = MemoryOutbox::new().id_generator(Arc::new(ids));
+        let bus = EventBus::new();
+        let mut placed = bus.subscribe::<OrderPlaced>();
+        let metrics = Metrics::new();
+        let relay = OutboxRelay::new(Arc::new(outbox.clone()), bus)
+            .batch(2)
+            .metrics(metrics.clone());
+
+        for id in 1..=3 {
+            outbox.stage(&OrderPlaced { id }).unwrap();
+        }
+        assert_eq!(relay.relay_once().await.unwrap(), 2);
+        assert_eq!(relay.relay_once().await.unwrap(), 1);
+        assert_eq!(relay.relay_once().await.unwrap(), 0);
+        assert_eq!(outbox.undelivered(), 0);
+        for id in 1..=3 {
+            let delivery = placed.recv_delivery().await.unwrap();
+            assert_eq!(delivery.event, OrderPlaced { id });
+            assert_eq!(delivery.id, Some(format!("msg-{}", id)));
+        }
+        assert_eq!(
+            metrics.counter("outbox_published_total", &[("topic", "orders.placed")]),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_skips_runs_while_locked_elsewhere() {
+        let outbox = MemoryOutbox::new();
+        outbox.stage(&OrderPlaced { id: 1 }).unwrap();
+        let locks = Locks::new(Arc::new(MemoryLock::new()));
+        let relay =
+            OutboxRelay::new(Arc::new(outbox.clone()), EventBus::new()).locks(locks.clone());
+
+        let held = locks
+            .try_acquire(RELAY_LOCK, Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(relay.relay_once().await.unwrap(), 0);
+        locks.release(&held).await.unwrap();
+        assert_eq!(relay.relay_once().await.unwrap(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_relay_purges_expired_messages() {
+        let outbox = MemoryOutbox::new();
+        let relay = OutboxRelay::new(Arc::new(outbox.clone()), EventBus::new())
+            .retention(Duration::from_millis(1));
+        outbox.stage(&OrderPlaced { id: 1 }).unwrap();
+        assert_eq!(relay.relay_once().await.unwrap(), 1);
+        outbox.stage(&OrderPlaced { id: 2 }).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+
+        // swept once per interval, keeping undelivered messages
+        assert_eq!(relay.relay_once().await.unwrap(), 1);
+        assert_eq!(outbox.lock().len(), 2);
+        tokio::time::advance(SWEEP_INTERVAL).await;
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(relay.relay_once().await.unwrap(), 0);
+        assert!(outbox.lock().is_empty());
+
+        outbox.stage(&OrderPlaced { id: 3 }).unwrap();
+        let before = SystemTime::now() + Duration::from_secs(1);
+        assert_eq!(outbox.purge_delivered(before).await.unwrap(), 0);
+    }
+
+    #[cfg(feature = "sqlx-sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_outbox_joins_transaction() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let outbox = SqliteOutbox::new(pool.clone());
+        outbox.create_table().await.unwrap();
+
+        let mut tx = pool.begin().await.unwrap();
+        outbox.stage(&mut tx, &OrderPlaced { id: 1 }).await.unwrap();
+        tx.rollback().await.unwrap();
+        let mut tx = pool.begin().await.unwrap();
+        outbox.stage(&mut tx, &OrderPlaced { id: 2 }).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let bus = EventBus::new();
+        let mut placed = bus.subscribe::<OrderPlaced>();
+        let relay = OutboxRelay::new(Arc::new(outbox.clone()), bus);
+        assert_eq!(relay.relay_once().await.unwrap(), 1);
+        assert_eq!(placed.recv().await, Some(OrderPlaced { id: 2 }));
+        assert!(outbox.pending(10).await.unwrap().is_empty());
+        let later = SystemTime::now() + Duration::from_secs(1);
+        assert_eq!(outbox.purge_delivered(later).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,802 @@
+//! Pact provider verification for RustAPI framework
+//!
+//! Verifies consumer-driven contracts (Pact files, specification v2 and v3)
+//! against the real application: the app is booted with `testing::spawn`,
+//! provider states are set up through registered hooks, and every recorded
+//! interaction is replayed and compared with the expected response.
+//!
+//! Bodies are compared the Pact way: response objects may carry extra keys,
+//! everything else must be equal unless the response's `matchingRules` say
+//! otherwise. The `type` (with `min`/`max` for arrays) and `regex` matchers
+//! are supported, on body paths and headers, in the v2 and v3 layouts; other
+//! matchers fail the interaction.
+
+use std::{collections::BTreeMap, future::Future, path::Path, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{Method, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::{
+    app::App,
+    error::{Error, Result},
+    lifecycle::BoxFuture,
+    testing::{self, Request, TestResponse},
+};
+
+/// Path of the provider state change endpoint mounted by
+/// `ProviderStates::router`
+pub const PROVIDER_STATES_PATH: &str = "/_pact/provider-states";
+
+type StateHook = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Hooks putting the provider into the states named by the consumer
+///
+/// # Example
+///
+/// ```ignore
+/// let states = ProviderStates::new()
+///     .state("user 1 exists", move |_params| {
+///         let repo = repo.clone();
+///         async move { repo.insert(User::fixture(1)).await.map_err(Error::other) }
+///     })
+///     .state("no users", |_| async { Ok(()) });
+/// ```
+#[derive(Clone, Default)]
+pub struct ProviderStates {
+    hooks: BTreeMap<String, StateHook>,
+}
+
+impl ProviderStates {
+    /// Create an empty set of hooks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the setup hook for a state; it receives the state's params
+    pub fn state<F, Fut>(mut self, name: impl Into<String>, hook: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.hooks
+            .insert(name.into(), Arc::new(move |params| Box::pin(hook(params))));
+        self
+    }
+
+    /// Run the setup hook of a state
+    pub async fn setup(&self, name: &str, params: Value) -> Result<()> {
+        let hook = self
+            .hooks
+            .get(name)
+            .ok_or_else(|| Error::other(format!("unknown provider state: {}", name)))?;
+        hook(params).await
+    }
+
+    /// State change endpoint for external verifiers (`pact_verifier_cli`)
+    ///
+    /// Accepts `{"state": "...", "params": {...}, "action": "setup"}`;
+    /// teardown requests are acknowledged without running anything.
+    pub fn router(self) -> Router {
+        Router::new()
+            .route(PROVIDER_STATES_PATH, post(change_state))
+            .with_state(self)
+    }
+}
+
+// handler of the state change endpoint
+async fn change_state(
+    State(states): State<ProviderStates>,
+    Json(body): Json<Value>,
+) -> (StatusCode, Json<Value>) {
+    if body["action"] == "teardown" {
+        return (StatusCode::OK, Json(json!({})));
+    }
+    let name = body["state"].as_str().unwrap_or_default();
+    let params = body.get("params").cloned().unwrap_or(Value::Null);
+    match states.setup(name, params).await {
+        Ok(()) => (StatusCode::OK, Json(json!({}))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// One interaction that did not behave as the contract says
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Description of the interaction from the Pact file
+    pub interaction: String,
+    /// What differed
+    pub reason: String,
+}
+
+/// Outcome of verifying a Pact file
+#[derive(Debug, Clone, Default)]
+pub struct VerificationReport {
+    /// Number of interactions replayed
+    pub interactions: usize,
+    /// Interactions that failed
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerificationReport {
+    /// Whether every interaction matched
+    pub fn is_success(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Fail the test with every mismatch listed
+    ///
+    /// # Panics
+    ///
+    /// Panics if any interaction failed.
+    pub fn assert_success(&self) {
+        let lines: Vec<String> = self
+            .mismatches
+            .iter()
+            .map(|m| format!("- {}: {}", m.interaction, m.reason))
+            .collect();
+        assert!(
+            lines.is_empty(),
+            "{} of {} Pact interaction(s) failed:\n{}",
+            lines.len(),
+            self.interactions,
+            lines.join("\n")
+        );
+    }
+}
+
+/// Verify a Pact file against the application
+///
+/// Boots `app` with `testing::spawn` and replays each interaction after
+/// setting up its provider states.
+///
+/// # Example
+///
+/// ```ignore
+/// #[tokio::test]
+/// async fn honours_web_frontend_contract() {
+///     pact::verify(build_app(), provider_states(), "pacts/web-users.json")
+///         .await
+///         .unwrap()
+///         .assert_success();
+/// }
+/// ```
+pub async fn verify(
+    app: App,
+    states: ProviderStates,
+    pact_file: impl AsRef<Path>,
+) -> Result<VerificationReport> {
+    let path = pact_file.as_ref();
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::other(format!("cannot read {}: {}", path.display(), e)))?;
+    let pact: Value = serde_json::from_str(&text)
+        .map_err(|e| Error::other(format!("invalid Pact file {}: {}", path.display(), e)))?;
+    let interactions = pact["interactions"]
+        .as_array()
+        .ok_or_else(|| Error::other("Pact file has no interactions"))?;
+
+    let server = testing::spawn(app).await;
+    let mut report = VerificationReport::default();
+    for interaction in interactions {
+        report.interactions += 1;
+        let description = interaction["description"]
+            .as_str()
+            .unwrap_or("(no description)")
+            .to_string();
+        if let Err(reason) = verify_interaction(&server, &states, interaction).await {
+            report.mismatches.push(Mismatch {
+                interaction: description,
+                reason,
+            });
+        }
+    }
+    server.shutdown().await;
+    Ok(report)
+}
+
+// set up states, replay one interaction and compare the response
+async fn verify_interaction(
+    server: &testing::TestServer,
+    states: &ProviderStates,
+    interaction: &Value,
+) -> std::result::Result<(), String> {
+    for (name, params) in provider_states(interaction) {
+        states
+            .setup(&name, params)
+            .await
+            .map_err(|e| format!("provider state \"{}\": {}", name, e))?;
+    }
+    let request = build_request(&interaction["request"])?;
+    let response = server.send(request).await;
+    compare_response(&interaction["response"], &response)
+}
+
+// provider states of an interaction, v3 `providerStates` or v2 `providerState`
+fn provider_states(interaction: &Value) -> Vec<(String, Value)> {
+    if let Some(states) = interaction["providerStates"].as_array() {
+        return states
+            .iter()
+            .filter_map(|s| {
+                let name = s["name"].as_str()?.to_string();
+                Some((name, s.get("params").cloned().unwrap_or(Value::Null)))
+            })
+            .collect();
+    }
+    interaction["providerState"]
+        .as_str()
+        .map(|name| vec![(name.to_string(), Value::Null)])
+        .unwrap_or_default()
+}
+
+// test request for the `request` part of an interaction
+fn build_request(expected: &Value) -> std::result::Result<Request, String> {
+    let method = expected["method"].as_str().unwrap_or("GET").to_uppercase();
+    let method = Method::from_bytes(method.as_bytes()).map_err(|e| e.to_string())?;
+    let mut uri = expected["path"].as_str().unwrap_or("/").to_string();
+    match &expected["query"] {
+        Value::String(query) if !query.is_empty() => uri = format!("{}?{}", uri, query),
+        Value::Object(params) => {
+            let pairs: Vec<String> = params
+                .iter()
+                .flat_map(|(key, values)| {
+                    let values = match values {
+                        Value::Array(values) => values.clone(),
+                        other => vec![other.clone()],
+                    };
+                    values.into_iter().map(move |v| {
+                        format!(
+                            "{}={}",
+                            key,
+                            v.as_str().map_or(v.to_string(), str::to_string)
+                        )
+                    })
+                })
+                .collect();
+            if !pairs.is_empty() {
+                uri = format!("{}?{}", uri, pairs.join("&"));
+            }
+        }
+        _ => {}
+    }
+
+    let mut request = Request::new(method, uri);
+    if let Some(headers) = expected["headers"].as_object() {
+        for (name, value) in headers {
+            request = request.header(name, value.as_str().unwrap_or_default());
+        }
+    }
+    match expected.get("body") {
+        None | Some(Value::Null) => {}
+        Some(Value::String(text)) => request = request.body(text.clone()),
+        Some(body) => request = request.json(body),
+    }
+    Ok(request)
+}
+
+// a Pact matcher
+enum Matcher {
+    // same JSON type; arrays within the length bounds
+    Type {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    // string form of the value matches entirely
+    Regex(Regex),
+}
+
+impl Matcher {
+    // read a matcher, e.g. `{"match": "regex", "regex": "\\d+"}`
+    fn parse(matcher: &Value) -> std::result::Result<Self, String> {
+        let bound = |key: &str| matcher[key].as_u64().map(|n| n as usize);
+        match (matcher["match"].as_str(), matcher["regex"].as_str()) {
+            (Some("type") | None, None) => Ok(Matcher::Type {
+                min: bound("min"),
+                max: bound("max"),
+            }),
+            (Some("regex") | None, Some(pattern)) => Regex::new(&format!("^(?:{})$", pattern))
+                .map(Matcher::Regex)
+                .map_err(|e| format!("invalid regex {:?}: {}", pattern, e)),
+            (Some(other), _) => Err(format!("unsupported matcher {:?}", other)),
+        }
+    }
+
+    // check `actual` at `at` against the matcher
+    fn check(&self, expected: &Value, actual: &Value, at: &str) -> std::result::Result<(), String> {
+        match self {
+            Matcher::Type { min, max } => {
+                if !same_type(expected, actual) {
+                    return Err(format!(
+                        "expected {} at {}, got {}",
+                        kind(expected),
+                        at,
+                        actual
+                    ));
+                }
+                let len = actual.as_array().map_or(0, Vec::len);
+                match (min, max) {
+                    (Some(min), _) if len < *min => Err(format!(
+                        "expected at least {} element(s) at {}, got {}",
+                        min, at, len
+                    )),
+                    (_, Some(max)) if len > *max => Err(format!(
+                        "expected at most {} element(s) at {}, got {}",
+                        max, at, len
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            Matcher::Regex(regex) => {
+                let text = actual.as_str().map_or(actual.to_string(), str::to_string);
+                if regex.is_match(&text) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "expected {} to match {}, got {}",
+                        at,
+                        regex.as_str(),
+                        actual
+                    ))
+                }
+            }
+        }
+    }
+}
+
+// matchers of one path, all of which must pass unless combined with OR
+struct Rule {
+    matchers: Vec<Matcher>,
+    any: bool,
+}
+
+impl Rule {
+    // v3 `{"matchers": [...], "combine": "OR"}` or a single v2 matcher
+    fn parse(rule: &Value) -> std::result::Result<Self, String> {
+        let matchers = match rule["matchers"].as_array() {
+            Some(matchers) => matchers
+                .iter()
+                .map(Matcher::parse)
+                .collect::<std::result::Result<_, _>>()?,
+            None => vec![Matcher::parse(rule)?],
+        };
+        Ok(Self {
+            matchers,
+            any: rule["combine"] == "OR",
+        })
+    }
+
+    // check the matchers; `Ok(true)` when a passing `type` matcher lets
+    // the children be compared by type too
+    fn check(
+        &self,
+        expected: &Value,
+        actual: &Value,
+        at: &str,
+    ) -> std::result::Result<bool, String> {
+        let outcomes: Vec<_> = self
+            .matchers
+            .iter()
+            .map(|matcher| matcher.check(expected, actual, at))
+            .collect();
+        let passed = if self.any {
+            outcomes.iter().any(std::result::Result::is_ok)
+        } else {
+            outcomes.iter().all(std::result::Result::is_ok)
+        };
+        if !passed {
+            return Err(outcomes
+                .into_iter()
+                .find_map(std::result::Result::err)
+                .unwrap_or_default());
+        }
+        Ok(self
+            .matchers
+            .iter()
+            .zip(&outcomes)
+            .any(|(matcher, outcome)| matches!(matcher, Matcher::Type { .. }) && outcome.is_ok()))
+    }
+}
+
+// matching rules of a response, by body path and lowercase header name
+#[derive(Default)]
+struct MatchingRules {
+    body: Vec<(Vec<String>, Rule)>,
+    headers: BTreeMap<String, Rule>,
+}
+
+impl MatchingRules {
+    // read v3 rules grouped by category, or v2 rules keyed by `$.body...`
+    // and `$.headers...`
+    fn parse(rules: &Value) -> std::result::Result<Self, String> {
+        let mut parsed = Self::default();
+        for (key, rule) in rules.as_object().into_iter().flatten() {
+            match key.as_str() {
+                "body" => {
+                    for (path, rule) in rule.as_object().into_iter().flatten() {
+                        parsed.body.push((segments(path), Rule::parse(rule)?));
+                    }
+                }
+                "header" | "headers" => {
+                    for (name, rule) in rule.as_object().into_iter().flatten() {
+                        parsed
+                            .headers
+                            .insert(name.to_ascii_lowercase(), Rule::parse(rule)?);
+                    }
+                }
+                key => {
+                    if let Some(path) = key.strip_prefix("$.body") {
+                        let path = format!("${}", path);
+                        parsed.body.push((segments(&path), Rule::parse(rule)?));
+                    } else if let Some(name) = key.strip_prefix("$.headers.") {
+                        parsed
+                            .headers
+                            .insert(name.to_ascii_lowercase(), Rule::parse(rule)?);
+                    }
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    // the most specific rule whose path matches `path`
+    fn body_rule(&self, path: &[String]) -> Option<&Rule> {
+        self.body
+            .iter()
+            .filter(|(pattern, _)| {
+                pattern.len() == path.len()
+                    && pattern
+                        .iter()
+                        .zip(path)
+                        .all(|(want, got)| want == "*" || want == got)
+            })
+            .max_by_key(|(pattern, _)| pattern.iter().filter(|s| *s != "*").count())
+            .map(|(_, rule)| rule)
+    }
+}
+
+// segments of a JSON path such as `$.items[*].name` or `$['a b']`
+fn segments(path: &str) -> Vec<String> {
+    let mut segments = vec!["$".to_string()];
+    let mut rest = path.strip_prefix('$').unwrap_or(path);
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("['") {
+            let end = after.find("']").unwrap_or(after.len());
+            segments.push(after[..end].to_string());
+            rest = after.get(end + 2..).unwrap_or_default();
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').unwrap_or(after.len());
+            segments.push(after[..end].to_string());
+            rest = after.get(end + 1..).unwrap_or_default();
+        } else {
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            segments.push(after[..end].to_string());
+            rest = &after[end..];
+        }
+    }
+    segments
+}
+
+// whether two values have the same JSON type
+fn same_type(expected: &Value, actual: &Value) -> bool {
+    kind(expected) == kind(actual)
+}
+
+// JSON type name of a value
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+// compare the actual response with the `response` part of an interaction
+fn compare_response(expected: &Value, actual: &TestResponse) -> std::result::Result<(), String> {
+    let rules = MatchingRules::parse(&expected["matchingRules"])?;
+    let status = expected["status"].as_u64().unwrap_or(200);
+    if u64::from(actual.status().as_u16()) != status {
+        return Err(format!(
+            "expected status {}, got {}",
+            status,
+            actual.status().as_u16()
+        ));
+    }
+    if let Some(headers) = expected["headers"].as_object() {
+        for (name, value) in headers {
+            let want = value.as_str().unwrap_or_default();
+            if let Some(rule) = rules.headers.get(&name.to_ascii_lowercase()) {
+                let got = actual
+                    .header(name)
+                    .ok_or_else(|| format!("missing header {}", name))?;
+                rule.check(value, &Value::String(got.to_string()), name)?;
+                continue;
+            }
+            match actual.header(name) {
+                Some(got) if got == want => {}
+                got => {
+                    return Err(format!(
+                        "expected header {}: {}, got {}",
+                        name,
+                        want,
+                        got.unwrap_or("(missing)")
+                    ))
+                }
+            }
+        }
+    }
+    match expected.get("body") {
+        None | Some(Value::Null) => Ok(()),
+        Some(Value::String(text)) if serde_json::from_slice::<Value>(actual.bytes()).is_err() => {
+            if actual.text() == *text {
+                Ok(())
+            } else {
+                Err(format!("expected body {:?}, got {:?}", text, actual.text()))
+            }
+        }
+        Some(body) => {
+            let got: Value = serde_json::from_slice(actual.bytes())
+                .map_err(|_| format!("expected JSON body, got {:?}", actual.text()))?;
+            body_matches(body, &got, &["$".to_string()], &rules, false)
+        }
+    }
+}
+
+// Pact body comparison: extra keys in objects are allowed, and values under
+// a `type` rule only need the expected JSON type
+fn body_matches(
+    expected: &Value,
+    actual: &Value,
+    path: &[String],
+    rules: &MatchingRules,
+    by_type: bool,
+) -> std::result::Result<(), String> {
+    let at = display_path(path);
+    let mut by_type = by_type;
+    if let Some(rule) = rules.body_rule(path) {
+        if !rule.check(expected, actual, &at)? {
+            return Ok(());
+        }
+        by_type = true;
+    }
+    let child = |segment: String| {
+        let mut child = path.to_vec();
+        child.push(segment);
+        child
+    };
+    match (expected, actual) {
+        (Value::Object(want), Value::Object(got)) => {
+            for (key, value) in want {
+                let path = child(key.clone());
+                match got.get(key) {
+                    Some(actual) => body_matches(value, actual, &path, rules, by_type)?,
+                    None => return Err(format!("missing {} in body", display_path(&path))),
+                }
+            }
+            Ok(())
+        }
+        // every element is like the first expected one
+        (Value::Array(want), Value::Array(got)) if by_type => {
+            if let Some(template) = want.first() {
+                for (i, got) in got.iter().enumerate() {
+                    body_matches(template, got, &child(i.to_string()), rules, by_type)?;
+                }
+            }
+            Ok(())
+        }
+        (Value::Array(want), Value::Array(got)) => {
+            if want.len() != got.len() {
+                return Err(format!(
+                    "expected {} element(s) at {}, got {}",
+                    want.len(),
+                    at,
+                    got.len()
+                ));
+            }
+            for (i, (want, got)) in want.iter().zip(got).enumerate() {
+                body_matches(want, got, &child(i.to_string()), rules, by_type)?;
+            }
+            Ok(())
+        }
+        _ if by_type && same_type(expected, actual) => Ok(()),
+        _ if by_type => Err(format!(
+            "expected {} at {}, got {}",
+            kind(expected),
+            at,
+            actual
+        )),
+        _ if expected == actual => Ok(()),
+        _ => Err(format!("expected {} at {}, got {}", expected, at, actual)),
+    }
+}
+
+// `$.items[0].name` form of path segments
+fn display_path(path: &[String]) -> String {
+    let mut display = String::from("$");
+    for segment in &path[1..] {
+        if segment.parse::<usize>().is_ok() {
+            display.push_str(&format!("[{}]", segment));
+        } else {
+            display.push('.');
+            display.push_str(segment);
+        }
+    }
+    display
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::{extract::Path as UrlPath, routing::get};
+
+    use super::*;
+
+    // compare bodies under `rules`
+    fn matches(expected: Value, actual: Value, rules: Value) -> std::result::Result<(), String> {
+        let rules = MatchingRules::parse(&rules)?;
+        body_matches(&expected, &actual, &["$".to_string()], &rules, false)
+    }
+
+    #[test]
+    fn test_body_matches() {
+        let expected = json!({ "id": 1, "tags": ["a"] });
+        let exact = |actual| matches(expected.clone(), actual, Value::Null);
+        assert!(exact(json!({ "id": 1, "tags": ["a"], "x": 0 })).is_ok());
+        assert_eq!(
+            exact(json!({ "id": 2, "tags": ["a"] })).unwrap_err(),
+            "expected 1 at $.id, got 2"
+        );
+        assert_eq!(
+            exact(json!({ "id": 1, "tags": [] })).unwrap_err(),
+            "expected 1 element(s) at $.tags, got 0"
+        );
+    }
+
+    #[test]
+    fn test_matching_rules() {
+        let expected = json!({ "id": 1, "email": "ada@example.com", "items": [{ "sku": "A-1" }] });
+        let v3 = json!({ "body": {
+            "$.id": { "matchers": [{ "match": "type" }] },
+            "$.email": { "matchers": [{ "match": "regex", "regex": "[^@]+@[^@]+" }] },
+            "$.items": { "matchers": [{ "match": "type", "min": 1 }] },
+            "$.items[*].sku": { "matchers": [{ "match": "regex", "regex": "[A-Z]-\\d+" }] }
+        }});
+        let actual = json!({ "id": 7, "email": "bob@example.org", "items": [{ "sku": "B-2" }, { "sku": "C-3" }] });
+        assert!(matches(expected.clone(), actual, v3.clone()).is_ok());
+
+        let wrong_type = json!({ "id": "7", "email": "a@b", "items": [{ "sku": "B-2" }] });
+        assert_eq!(
+            matches(expected.clone(), wrong_type, v3.clone()).unwrap_err(),
+            "expected a number at $.id, got \"7\""
+        );
+        let bad_sku = json!({ "id": 7, "email": "a@b", "items": [{ "sku": "b2" }] });
+        assert!(matches(expected.clone(), bad_sku, v3.clone())
+            .unwrap_err()
+            .contains("$.items[0].sku to match"));
+        let empty = json!({ "id": 7, "email": "a@b", "items": [] });
+        assert_eq!(
+            matches(expected.clone(), empty, v3).unwrap_err(),
+            "expected at least 1 element(s) at $.items, got 0"
+        );
+
+        // v2 layout, with a type rule covering the children
+        let v2 = json!({ "$.body": { "match": "type" } });
+        let actual = json!({ "id": 2, "email": "x", "items": [{ "sku": "Z" }] });
+        assert!(matches(expected.clone(), actual, v2).is_ok());
+
+        let unsupported = json!({ "$.body.id": { "match": "semver" } });
+        assert!(matches(expected, json!({}), unsupported)
+            .unwrap_err()
+            .contains("unsupported matcher"));
+    }
+
+    #[test]
+    fn test_build_request() {
+        let request = build_request(&json!({
+            "method": "post",
+            "path": "/users",
+            "query": { "notify": ["yes"] },
+            "headers": { "x-tenant": "acme" },
+            "body": { "name": "Ada" }
+        }))
+        .unwrap()
+        .into_http();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "/users?notify=yes");
+        assert_eq!(request.headers()["x-tenant"], "acme");
+        assert_eq!(request.headers()["content-type"], "application/json");
+    }
+
+    #[tokio::test]
+    async fn test_verify_pact_file() {
+        let seeded = Arc::new(AtomicBool::new(false));
+        let flag = seeded.clone();
+        let app = App::new().route(
+            "/users/{id}",
+            get(move |UrlPath(id): UrlPath<u64>| {
+                let seeded = flag.load(Ordering::SeqCst);
+                async move {
+                    if seeded && id == 1 {
+                        Ok(Json(json!({ "id": 1, "name": "Ada", "admin": false })))
+                    } else {
+                        Err(StatusCode::NOT_FOUND)
+                    }
+                }
+            }),
+        );
+        let seed = seeded.clone();
+        let states = ProviderStates::new().state("user 1 exists", move |params| {
+            let seed = seed.clone();
+            async move {
+                assert_eq!(params["id"], 1);
+                seed.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        let pact = json!({
+            "consumer": { "name": "web" },
+            "provider": { "name": "users" },
+            "interactions": [
+                {
+                    "description": "get user 1",
+                    "providerStates": [{ "name": "user 1 exists", "params": { "id": 1 } }],
+                    "request": { "method": "GET", "path": "/users/1" },
+                    "response": {
+                        "status": 200,
+                        "headers": { "content-type": "application/hal+json" },
+                        "body": { "id": 1, "name": "Grace" },
+                        "matchingRules": {
+                            "header": { "Content-Type": { "matchers": [{ "match": "regex", "regex": "application/.*json" }] } },
+                            "body": { "$.name": { "matchers": [{ "match": "type" }] } }
+                        }
+                    }
+                },
+                {
+                    "description": "get user 2",
+                    "request": { "method": "GET", "path": "/users/2" },
+                    "response": { "status": 200 }
+                }
+            ]
+        });
+        let path = std::env::temp_dir().join(format!("rust-api-pact-{}.json", std::process::id()));
+        std::fs::write(&path, pact.to_string()).unwrap();
+
+        let report = verify(app, states, &path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(seeded.load(Ordering::SeqCst));
+        assert_eq!(report.interactions, 2);
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                interaction: "get user 2".to_string(),
+                reason: "expected status 200, got 404".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_change_endpoint() {
+        let states = ProviderStates::new().state("empty", |_| async { Ok(()) });
+        let client = testing::TestClient::from_router(states.router());
+
+        let ok = Request::post(PROVIDER_STATES_PATH).json(&json!({ "state": "empty" }));
+        assert_eq!(client.send(ok).await.status(), StatusCode::OK);
+
+        let unknown = Request::post(PROVIDER_STATES_PATH).json(&json!({ "state": "nope" }));
+        let response = client.send(unknown).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.text().contains("unknown provider state"));
+    }
+}
@@ -0,0 +1,716 @@
+//! Partial updates for RustAPI framework
+//!
+//! `MergePatch<T>` (RFC 7386) and `JsonPatch` (RFC 6902) extract PATCH
+//! bodies and apply them onto the current state of a model: the model is
+//! serialized to JSON, patched, and deserialized back, so the result is a
+//! complete `T` checked by serde like any request body. With the
+//! `validator` feature, `apply_validated` also runs the model's validation
+//! rules on the result.
+//!
+//! Any serialized field can be patched, including ones such as `id` or
+//! `role` that clients must not change, so restrict patches with `allow`,
+//! which rejects a patch changing other paths with 422 (`unpatchable_fields`).
+//! Fields of the patch that `T` does not declare are dropped, unless
+//! `UnknownFields::Reject` is set for the route, as for `Json<T>`; then they
+//! fail with 422 (`unknown_fields`).
+//!
+//! A patch that does not apply fails with 422 (`invalid_patch`), a failed
+//! JSON Patch `test` operation with 409 (`patch_test_failed`), and a result
+//! that is not a valid `T` with 422 (`invalid_json`). Patches are applied to
+//! a copy, so a failure never leaves a half-patched value.
+
+use std::marker::PhantomData;
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    error::ApiError,
+    json::{self, json_error, Json, UnknownFields},
+};
+
+/// JSON Merge Patch extractor (RFC 7386)
+///
+/// Members of the patch replace the model's, `null` removes them and
+/// nested objects are merged recursively.
+///
+/// # Example
+///
+/// ```ignore
+/// #[patch("/users/{id}")]
+/// async fn update_user(Path(id): Path<u64>, patch: MergePatch<User>) -> ApiResult<Json<User>> {
+///     let patch = patch.allow(&["/name", "/email", "/tags"])?;
+///     let user = repo.find(id).await?;
+///     let user = patch.apply(&user)?;
+///     Ok(Json(repo.save(user).await?))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MergePatch<T> {
+    patch: Value,
+    unknown: UnknownFields,
+    _model: PhantomData<fn() -> T>,
+}
+
+impl<T> MergePatch<T> {
+    /// Wrap a merge patch document
+    pub fn new(patch: Value) -> Self {
+        Self {
+            patch,
+            unknown: UnknownFields::default(),
+            _model: PhantomData,
+        }
+    }
+
+    /// Set how fields `T` does not declare are treated; the extractor reads
+    /// it from the request
+    pub fn unknown_fields(mut self, mode: UnknownFields) -> Self {
+        self.unknown = mode;
+        self
+    }
+
+    /// The patch document
+    pub fn document(&self) -> &Value {
+        &self.patch
+    }
+
+    /// Reject the patch when it sets or removes a member outside `allowed`
+    ///
+    /// `allowed` holds JSON Pointers such as `/name`; a pointer allows its
+    /// children too. Fails with 422 (`unpatchable_fields`) listing the
+    /// paths in `details`.
+    pub fn allow(self, allowed: &[&str]) -> Result<Self, ApiError> {
+        let mut changed = Vec::new();
+        touched(&self.patch, String::new(), &mut changed);
+        check_allowed(changed, allowed)?;
+        Ok(self)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> MergePatch<T> {
+    /// The result of patching `current`
+    pub fn apply(&self, current: &T) -> Result<T, ApiError> {
+        let current = to_value(current)?;
+        let mut value = current.clone();
+        merge_patch(&mut value, &self.patch);
+        from_patched(&current, value, self.unknown)
+    }
+
+    /// The result of patching `current`, checked against `T`'s validation
+    /// rules
+    #[cfg(feature = "validator")]
+    pub fn apply_validated(&self, current: &T) -> Result<T, ApiError>
+    where
+        T: validator::Validate,
+    {
+        let patched = self.apply(current)?;
+        patched.validate()?;
+        Ok(patched)
+    }
+}
+
+impl<T, S: Send + Sync> FromRequest<S> for MergePatch<T> {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let unknown = unknown_fields(&req);
+        let Json(patch) = Json::<Value>::from_request(req, state).await?;
+        Ok(Self::new(patch).unknown_fields(unknown))
+    }
+}
+
+/// Apply the merge patch `patch` to `target` in place (RFC 7386)
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(members) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let object = target.as_object_mut().expect("target was made an object");
+    for (name, value) in members {
+        if value.is_null() {
+            object.remove(name);
+        } else {
+            merge_patch(object.entry(name.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// One JSON Patch operation
+///
+/// Paths are JSON Pointers (RFC 6901), such as `/tags/0` or `/name`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    /// Add a member or insert an array element (`-` appends)
+    Add {
+        /// Where to add
+        path: String,
+        /// Value added
+        value: Value,
+    },
+    /// Remove an existing value
+    Remove {
+        /// What to remove
+        path: String,
+    },
+    /// Replace an existing value
+    Replace {
+        /// What to replace
+        path: String,
+        /// New value
+        value: Value,
+    },
+    /// Remove a value and add it elsewhere
+    Move {
+        /// What to move
+        from: String,
+        /// Where to move it
+        path: String,
+    },
+    /// Add a copy of a value elsewhere
+    Copy {
+        /// What to copy
+        from: String,
+        /// Where to add the copy
+        path: String,
+    },
+    /// Check that a value is as expected before going on
+    Test {
+        /// What to check
+        path: String,
+        /// Expected value
+        value: Value,
+    },
+}
+
+/// JSON Patch extractor (RFC 6902)
+///
+/// # Example
+///
+/// ```ignore
+/// #[patch("/users/{id}")]
+/// async fn update_user(Path(id): Path<u64>, patch: JsonPatch) -> ApiResult<Json<User>> {
+///     let patch = patch.allow(&["/name", "/email", "/tags"])?;
+///     let user = repo.find(id).await?;
+///     let user = patch.apply(&user)?;
+///     Ok(Json(repo.save(user).await?))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JsonPatch {
+    operations: Vec<PatchOperation>,
+    unknown: UnknownFields,
+}
+
+impl JsonPatch {
+    /// Wrap a list of operations
+    pub fn new(operations: Vec<PatchOperation>) -> Self {
+        Self {
+            operations,
+            unknown: UnknownFields::default(),
+        }
+    }
+
+    /// Set how fields the patched type does not declare are treated; the
+    /// extractor reads it from the request
+    pub fn unknown_fields(mut self, mode: UnknownFields) -> Self {
+        self.unknown = mode;
+        self
+    }
+
+    /// The operations, in order
+    pub fn operations(&self) -> &[PatchOperation] {
+        &self.operations
+    }
+
+    /// Reject the patch when an operation changes a path outside `allowed`
+    ///
+    /// `allowed` holds JSON Pointers such as `/name`; a pointer allows its
+    /// children too. `test` operations and the source of `copy` only read,
+    /// so they may use any path. Fails with 422 (`unpatchable_fields`)
+    /// listing the paths in `details`.
+    pub fn allow(self, allowed: &[&str]) -> Result<Self, ApiError> {
+        let changed = self
+            .operations
+            .iter()
+            .flat_map(|operation| match operation {
+                PatchOperation::Add { path, .. }
+                | PatchOperation::Remove { path }
+                | PatchOperation::Replace { path, .. }
+                | PatchOperation::Copy { path, .. } => vec![path.clone()],
+                PatchOperation::Move { from, path } => vec![from.clone(), path.clone()],
+                PatchOperation::Test { .. } => Vec::new(),
+            })
+            .collect();
+        check_allowed(changed, allowed)?;
+        Ok(self)
+    }
+
+    /// The result of patching `current`
+    pub fn apply<T: Serialize + DeserializeOwned>(&self, current: &T) -> Result<T, ApiError> {
+        let current = to_value(current)?;
+        let mut value = current.clone();
+        self.apply_to(&mut value)?;
+        from_patched(&current, value, self.unknown)
+    }
+
+    /// The result of patching `current`, checked against `T`'s validation
+    /// rules
+    #[cfg(feature = "validator")]
+    pub fn apply_validated<T>(&self, current: &T) -> Result<T, ApiError>
+    where
+        T: Serialize + DeserializeOwned + validator::Validate,
+    {
+        let patched = self.apply(current)?;
+        patched.validate()?;
+        Ok(patched)
+    }
+
+    /// Apply the operations to `target` in order
+    ///
+    /// `target` is only changed if every operation succeeds.
+    pub fn apply_to(&self, target: &mut Value) -> Result<(), ApiError> {
+        let mut patched = target.clone();
+        for (index, operation) in self.operations.iter().enumerate() {
+            apply_operation(&mut patched, operation).map_err(|error| match error {
+                OpError::Invalid(reason) => ApiError::unprocessable(format!(
+                    "Patch operation {} cannot be applied: {}",
+                    index, reason
+                ))
+                .with_code("invalid_patch"),
+                OpError::TestFailed(path) => ApiError::new(
+                    StatusCode::CONFLICT,
+                    format!("Patch test failed at {}", path),
+                )
+                .with_code("patch_test_failed"),
+            })?;
+        }
+        *target = patched;
+        Ok(())
+    }
+}
+
+impl<S: Send + Sync> FromRequest<S> for JsonPatch {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let unknown = unknown_fields(&req);
+        let Json(operations) = Json::<Vec<PatchOperation>>::from_request(req, state).await?;
+        Ok(JsonPatch::new(operations).unknown_fields(unknown))
+    }
+}
+
+// why an operation did not apply
+enum OpError {
+    Invalid(String),
+    TestFailed(String),
+}
+
+// serialize the model being patched
+fn to_value<T: Serialize>(current: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(current)
+        .map_err(|e| ApiError::internal(format!("Failed to serialize the patched model: {}", e)))
+}
+
+// how the route treats unknown fields, as `Json<T>` reads it
+fn unknown_fields(req: &Request) -> UnknownFields {
+    req.extensions()
+        .get::<UnknownFields>()
+        .copied()
+        .unwrap_or_default()
+}
+
+// deserialize the patched model; with `UnknownFields::Reject`, fields the
+// patch added that `T` does not declare are rejected, while ones the model
+// serializes without reading back are not the client's doing
+fn from_patched<T: DeserializeOwned>(
+    current: &Value,
+    patched: Value,
+    unknown: UnknownFields,
+) -> Result<T, ApiError> {
+    if unknown == UnknownFields::Ignore {
+        return serde_json::from_value(patched).map_err(json_error);
+    }
+    let (value, ignored) = json::from_value_ignored::<T>(patched)?;
+    let (_, before) = json::from_value_ignored::<T>(current.clone())?;
+    let added: Vec<String> = ignored
+        .into_iter()
+        .filter(|path| !before.contains(path))
+        .collect();
+    if !added.is_empty() {
+        return Err(json::unknown_fields(added));
+    }
+    Ok(value)
+}
+
+// JSON Pointers of the members a merge patch sets or removes
+fn touched(patch: &Value, pointer: String, changed: &mut Vec<String>) {
+    match patch {
+        Value::Object(members) => {
+            for (name, value) in members {
+                let name = name.replace('~', "~0").replace('/', "~1");
+                touched(value, format!("{}/{}", pointer, name), changed);
+            }
+        }
+        _ => changed.push(pointer),
+    }
+}
+
+// reject changes to paths that are not, or not under, an allowed pointer
+fn check_allowed(changed: Vec<String>, allowed: &[&str]) -> Result<(), ApiError> {
+    let mut forbidden: Vec<String> = Vec::new();
+    for path in changed {
+        let permitted = allowed.iter().any(|pointer| {
+            path.strip_prefix(pointer)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        if !permitted && !forbidden.contains(&path) {
+            forbidden.push(path);
+        }
+    }
+    if forbidden.is_empty() {
+        return Ok(());
+    }
+    Err(ApiError::unprocessable(format!(
+        "Fields that cannot be patched: {}",
+        forbidden.join(", ")
+    ))
+    .with_code("unpatchable_fields")
+    .with_details(json!({ "fields": forbidden })))
+}
+
+// apply one operation in place
+fn apply_operation(target: &mut Value, operation: &PatchOperation) -> Result<(), OpError> {
+    match operation {
+        PatchOperation::Add { path, value } => add(target, path, value.clone()),
+        PatchOperation::Remove { path } => remove(target, path).map(drop),
+        PatchOperation::Replace { path, value } => {
+            *lookup(target, path)? = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(OpError::Invalid(format!(
+                    "cannot move {} into its own child {}",
+                    from, path
+                )));
+            }
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = lookup(target, from)?.clone();
+            add(target, path, value)
+        }
+        PatchOperation::Test { path, value } => {
+            if *lookup(target, path)? == *value {
+                Ok(())
+            } else {
+                Err(OpError::TestFailed(path.clone()))
+            }
+        }
+    }
+}
+
+// decode the reference tokens of a JSON Pointer
+fn tokens(pointer: &str) -> Result<Vec<String>, OpError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = pointer.strip_prefix('/') else {
+        return Err(OpError::Invalid(format!(
+            "{:?} is not a JSON Pointer",
+            pointer
+        )));
+    };
+    Ok(rest
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+// the existing value at `pointer`
+fn lookup<'a>(target: &'a mut Value, pointer: &str) -> Result<&'a mut Value, OpError> {
+    let mut current = target;
+    for token in tokens(pointer)? {
+        current = match current {
+            Value::Object(members) => members.get_mut(&token),
+            Value::Array(items) => index(&token, items.len())
+                .ok()
+                .and_then(|i| items.get_mut(i)),
+            _ => None,
+        }
+        .ok_or_else(|| OpError::Invalid(format!("{} does not exist", pointer)))?;
+    }
+    Ok(current)
+}
+
+// split a pointer into its parent container and last token
+fn parent<'a>(target: &'a mut Value, pointer: &str) -> Result<(&'a mut Value, String), OpError> {
+    let Some((parent_pointer, _)) = pointer.rsplit_once('/') else {
+        return Err(OpError::Invalid(format!(
+            "{:?} is not a JSON Pointer",
+            pointer
+        )));
+    };
+    let last = tokens(pointer)?
+        .pop()
+        .expect("a pointer with a slash has a token");
+    Ok((lookup(target, parent_pointer)?, last))
+}
+
+// array index of a token, which must be at most `len`
+fn index(token: &str, len: usize) -> Result<usize, OpError> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    match token.parse::<usize>() {
+        Ok(i) if valid && i <= len => Ok(i),
+        _ => Err(OpError::Invalid(format!(
+            "{:?} is not a valid array index",
+            token
+        ))),
+    }
+}
+
+// add `value` at `pointer`
+fn add(target: &mut Value, pointer: &str, value: Value) -> Result<(), OpError> {
+    if pointer.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+    let (container, token) = parent(target, pointer)?;
+    match container {
+        Value::Object(members) => {
+            members.insert(token, value);
+        }
+        Value::Array(items) => {
+            let at = if token == "-" {
+                items.len()
+            } else {
+                index(&token, items.len())?
+            };
+            items.insert(at, value);
+        }
+        _ => return Err(OpError::Invalid(format!("cannot add to {}", pointer))),
+    }
+    Ok(())
+}
+
+// remove and return the value at `pointer`
+fn remove(target: &mut Value, pointer: &str) -> Result<Value, OpError> {
+    if pointer.is_empty() {
+        return Ok(std::mem::take(target));
+    }
+    let missing = || OpError::Invalid(format!("{} does not exist", pointer));
+    let (container, token) = parent(target, pointer)?;
+    match container {
+        Value::Object(members) => members.remove(&token).ok_or_else(missing),
+        Value::Array(items) => {
+            let at = index(&token, items.len())?;
+            if at == items.len() {
+                return Err(missing());
+            }
+            Ok(items.remove(at))
+        }
+        _ => Err(missing()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        email: Option<String>,
+        tags: Vec<String>,
+    }
+
+    fn ada() -> User {
+        User {
+            name: "ada".to_string(),
+            email: Some("ada@example.com".to_string()),
+            tags: vec!["admin".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // examples of RFC 7386, appendix A
+        let mut target = json!({ "a": "b", "c": { "d": "e", "f": "g" } });
+        merge_patch(&mut target, &json!({ "a": "z", "c": { "f": null } }));
+        assert_eq!(target, json!({ "a": "z", "c": { "d": "e" } }));
+        let mut target = json!({ "a": "foo" });
+        merge_patch(&mut target, &json!({ "b": { "c": null } }));
+        assert_eq!(target, json!({ "a": "foo", "b": {} }));
+        let mut target = json!(["a", "b"]);
+        merge_patch(&mut target, &json!({ "a": "c" }));
+        assert_eq!(target, json!({ "a": "c" }));
+
+        let patch = MergePatch::<User>::new(json!({ "email": null, "tags": ["ops"] }));
+        let user = patch.apply(&ada()).unwrap();
+        assert_eq!(user.email, None);
+        assert_eq!(user.tags, vec!["ops"]);
+        assert_eq!(user.name, "ada");
+
+        let error = MergePatch::<User>::new(json!({ "name": null }))
+            .apply(&ada())
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "invalid_json");
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let patch: Vec<PatchOperation> = serde_json::from_value(json!([
+            { "op": "test", "path": "/name", "value": "ada" },
+            { "op": "replace", "path": "/name", "value": "grace" },
+            { "op": "add", "path": "/tags/-", "value": "ops" },
+            { "op": "add", "path": "/tags/0", "value": "dev" },
+            { "op": "copy", "from": "/tags/0", "path": "/tags/-" },
+            { "op": "remove", "path": "/email" },
+        ]))
+        .unwrap();
+        let user = JsonPatch::new(patch).apply(&ada()).unwrap();
+        assert_eq!(user.name, "grace");
+        assert_eq!(user.tags, vec!["dev", "admin", "ops", "dev"]);
+        assert_eq!(user.email, None);
+
+        let mut value = json!({ "a/b": { "c~d": 1 }, "list": [1, 2] });
+        let moved = JsonPatch::new(vec![PatchOperation::Move {
+            from: "/a~1b/c~0d".to_string(),
+            path: "/list/1".to_string(),
+        }]);
+        moved.apply_to(&mut value).unwrap();
+        assert_eq!(value, json!({ "a/b": {}, "list": [1, 1, 2] }));
+    }
+
+    #[test]
+    fn test_json_patch_failures_leave_target_unchanged() {
+        let mut value = json!({ "name": "ada", "tags": [] });
+        let failing = |operations: Value| {
+            let patch: Vec<PatchOperation> = serde_json::from_value(operations).unwrap();
+            JsonPatch::new(patch)
+        };
+
+        let error = failing(json!([
+            { "op": "replace", "path": "/name", "value": "grace" },
+            { "op": "test", "path": "/name", "value": "ada" },
+        ]))
+        .apply_to(&mut value)
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::CONFLICT);
+        assert_eq!(error.code(), "patch_test_failed");
+        assert_eq!(value["name"], "ada");
+
+        for operations in [
+            json!([{ "op": "remove", "path": "/missing" }]),
+            json!([{ "op": "add", "path": "/tags/01", "value": 1 }]),
+            json!([{ "op": "add", "path": "/tags/5", "value": 1 }]),
+            json!([{ "op": "replace", "path": "name", "value": 1 }]),
+            json!([{ "op": "move", "from": "/tags", "path": "/tags/0" }]),
+        ] {
+            let error = failing(operations.clone())
+                .apply_to(&mut value)
+                .unwrap_err();
+            assert_eq!(error.code(), "invalid_patch", "{}", operations);
+        }
+        assert_eq!(value, json!({ "name": "ada", "tags": [] }));
+    }
+
+    #[tokio::test]
+    async fn test_extractors() {
+        let request = |content_type: &str, body: &str| {
+            Request::builder()
+                .header("content-type", content_type)
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let body = r#"{"name":"grace"}"#;
+        let patch =
+            MergePatch::<User>::from_request(request("application/merge-patch+json", body), &())
+                .await
+                .unwrap();
+        assert_eq!(patch.apply(&ada()).unwrap().name, "grace");
+
+        let body = r#"[{"op":"remove","path":"/tags/0"}]"#;
+        let patch = JsonPatch::from_request(request("application/json-patch+json", body), &())
+            .await
+            .unwrap();
+        assert!(patch.apply(&ada()).unwrap().tags.is_empty());
+
+        let body = r#"[{"op":"frobnicate","path":"/tags"}]"#;
+        let error = JsonPatch::from_request(request("application/json-patch+json", body), &())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let mut strict = request("application/merge-patch+json", r#"{"nmae":"grace"}"#);
+        strict.extensions_mut().insert(UnknownFields::Reject);
+        let patch = MergePatch::<User>::from_request(strict, &()).await.unwrap();
+        assert_eq!(patch.apply(&ada()).unwrap_err().code(), "unknown_fields");
+    }
+
+    #[test]
+    fn test_allow() {
+        let allowed = ["/email", "/tags"];
+        let patch = MergePatch::<User>::new(json!({ "email": null, "tags": ["ops"] }));
+        assert!(patch.allow(&allowed).is_ok());
+        let error = MergePatch::<User>::new(json!({ "name": "eve", "tags": [] }))
+            .allow(&allowed)
+            .unwrap_err();
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "unpatchable_fields");
+        assert_eq!(error.details(), Some(&json!({ "fields": ["/name"] })));
+
+        let patch = |operations: Value| {
+            JsonPatch::new(serde_json::from_value(operations).unwrap()).allow(&allowed)
+        };
+        assert!(patch(json!([
+            { "op": "test", "path": "/name", "value": "ada" },
+            { "op": "add", "path": "/tags/-", "value": "ops" },
+            { "op": "copy", "from": "/name", "path": "/email" },
+        ]))
+        .is_ok());
+        for operations in [
+            json!([{ "op": "replace", "path": "/name", "value": "eve" }]),
+            json!([{ "op": "move", "from": "/name", "path": "/email" }]),
+            json!([{ "op": "replace", "path": "", "value": {} }]),
+        ] {
+            let error = patch(operations.clone()).unwrap_err();
+            assert_eq!(error.code(), "unpatchable_fields", "{}", operations);
+        }
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let patch = MergePatch::<User>::new(json!({ "nmae": "grace" }));
+        assert_eq!(patch.apply(&ada()).unwrap(), ada());
+        let error = patch
+            .unknown_fields(UnknownFields::Reject)
+            .apply(&ada())
+            .unwrap_err();
+        assert_eq!(error.code(), "unknown_fields");
+        assert_eq!(error.details(), Some(&json!({ "fields": ["nmae"] })));
+
+        let patch = JsonPatch::new(vec![PatchOperation::Add {
+            path: "/nmae".to_string(),
+            value: json!("grace"),
+        }])
+        .unknown_fields(UnknownFields::Reject);
+        assert_eq!(patch.apply(&ada()).unwrap_err().code(), "unknown_fields");
+    }
+}
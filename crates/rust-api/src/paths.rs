@@ -0,0 +1,95 @@
+//! Path sanitization helpers for RustAPI framework
+//!
+//! Helpers for turning untrusted, request-supplied paths (typically a `Rest`
+//! catch-all segment) into relative paths that cannot escape a base
+//! directory.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::ApiError;
+
+/// Normalize an untrusted relative path, rejecting traversal attempts
+///
+/// Empty and `.` segments are dropped. Any `..` segment, absolute path,
+/// backslash, drive prefix or NUL byte is rejected with 400 Bad Request.
+///
+/// # Example
+///
+/// ```ignore
+/// assert_eq!(sanitize_path("docs//./guide.md")?, PathBuf::from("docs/guide.md"));
+/// assert!(sanitize_path("../etc/passwd").is_err());
+/// ```
+pub fn sanitize_path(path: &str) -> Result<PathBuf, ApiError> {
+    if path.starts_with('/') {
+        return Err(traversal("absolute paths are not allowed"));
+    }
+    if path.contains('\0') {
+        return Err(traversal("NUL bytes are not allowed"));
+    }
+    // backslashes are separators on Windows, so treat them as hostile everywhere
+    if path.contains('\\') {
+        return Err(traversal("backslashes are not allowed"));
+    }
+
+    let mut clean = PathBuf::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(traversal("parent directory segments are not allowed")),
+            s if s.contains(':') => return Err(traversal("drive prefixes are not allowed")),
+            s => clean.push(s),
+        }
+    }
+    Ok(clean)
+}
+
+/// Join an untrusted relative path onto a trusted base directory
+///
+/// The result is guaranteed to stay inside `base` (lexically; symlinks inside
+/// `base` are not resolved).
+pub fn safe_join(base: impl AsRef<Path>, path: &str) -> Result<PathBuf, ApiError> {
+    Ok(base.as_ref().join(sanitize_path(path)?))
+}
+
+// 400 error for a rejected path
+fn traversal(reason: &str) -> ApiError {
+    ApiError::bad_request(format!("Invalid path: {}", reason)).with_code("invalid_path")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_normalizes_segments() {
+        assert_eq!(
+            sanitize_path("docs//./guide.md").unwrap(),
+            PathBuf::from("docs/guide.md")
+        );
+        assert_eq!(sanitize_path("").unwrap(), PathBuf::new());
+    }
+
+    #[test]
+    fn test_sanitize_rejects_traversal() {
+        for path in [
+            "../etc/passwd",
+            "a/../../b",
+            "/etc/passwd",
+            "a\\..\\b",
+            "C:/windows",
+            "a\0b",
+        ] {
+            let err = sanitize_path(path).unwrap_err();
+            assert_eq!(err.code(), "invalid_path", "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_safe_join() {
+        assert_eq!(
+            safe_join("/srv/files", "img/logo.png").unwrap(),
+            PathBuf::from("/srv/files/img/logo.png")
+        );
+        assert!(safe_join("/srv/files", "../secret").is_err());
+    }
+}
@@ -0,0 +1,528 @@
+//! Parameter pipes for RustAPI framework
+//!
+//! A pipe transforms or validates the raw value of a handler parameter
+//! before it is deserialized into the parameter's type: the JSON body of a
+//! `Json<T>`, the query parameters of a `Query<T>`, or the path parameters
+//! of a `Path<T>`. Pipes are attached with `#[pipe(...)]` on a parameter of
+//! a route handler and run in order; the errors of all of them are reported
+//! together as one 422 response.
+//!
+//! ```ignore
+//! #[post("/users/{id}/notes")]
+//! async fn add_note(
+//!     #[pipe(TrimStrings, ParseUuid)] Path(id): Path<Uuid>,
+//!     #[pipe(TrimStrings)] Json(note): Json<NewNote>,
+//! ) -> Json<Note> { ... }
+//! ```
+
+use std::marker::PhantomData;
+
+use axum::{
+    extract::{FromRequest, FromRequestParts, Path, Query, RawPathParams, Request},
+    http::{request::Parts, StatusCode, Uri},
+};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::{error::ApiError, json};
+
+/// Problem a pipe found with one field of a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeError {
+    /// Dotted path of the field, empty for the value itself
+    pub field: String,
+    /// What is wrong with it
+    pub message: String,
+}
+
+impl PipeError {
+    /// Report `message` about the field at `field`
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Transformation or validation of a parameter's raw value
+///
+/// Pipes used with `#[pipe(...)]` are created with `Default`, so they are
+/// usually unit structs.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Default)]
+/// struct Lowercase;
+///
+/// impl Pipe for Lowercase {
+///     fn apply(&self, value: Value) -> Result<Value, Vec<PipeError>> {
+///         map_strings(value, |_, s| Ok(s.to_lowercase()))
+///     }
+/// }
+/// ```
+pub trait Pipe: Send + Sync + 'static {
+    /// The transformed value, or every problem found in it
+    fn apply(&self, value: Value) -> Result<Value, Vec<PipeError>>;
+}
+
+/// Apply `f` to every string in `value`, collecting the errors
+///
+/// `f` receives the dotted path of each string (`address.zip`,
+/// `tags[0]`) and the string itself.
+pub fn map_strings(
+    value: Value,
+    mut f: impl FnMut(&str, String) -> Result<String, String>,
+) -> Result<Value, Vec<PipeError>> {
+    // rebuild `value` with `f` applied at every string leaf
+    fn walk(
+        value: Value,
+        path: &str,
+        f: &mut dyn FnMut(&str, String) -> Result<String, String>,
+        errors: &mut Vec<PipeError>,
+    ) -> Value {
+        match value {
+            Value::String(s) => match f(path, s.clone()) {
+                Ok(s) => Value::String(s),
+                Err(message) => {
+                    errors.push(PipeError::new(path, message));
+                    Value::String(s)
+                }
+            },
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, item)| walk(item, &format!("{}[{}]", path, i), f, errors))
+                    .collect(),
+            ),
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, item)| {
+                        let path = match path {
+                            "" => key.clone(),
+                            _ => format!("{}.{}", path, key),
+                        };
+                        let item = walk(item, &path, f, errors);
+                        (key, item)
+                    })
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    let mut errors = Vec::new();
+    let value = walk(value, "", &mut f, &mut errors);
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Trims leading and trailing whitespace from every string
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrimStrings;
+
+impl Pipe for TrimStrings {
+    fn apply(&self, value: Value) -> Result<Value, Vec<PipeError>> {
+        map_strings(value, |_, s| Ok(s.trim().to_string()))
+    }
+}
+
+/// Requires every string to be a UUID, normalized to lowercase hyphenated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseUuid;
+
+impl Pipe for ParseUuid {
+    fn apply(&self, value: Value) -> Result<Value, Vec<PipeError>> {
+        map_strings(value, |_, s| {
+            uuid::Uuid::parse_str(&s)
+                .map(|id| id.hyphenated().to_string())
+                .map_err(|_| "must be a UUID".to_string())
+        })
+    }
+}
+
+/// Ordered list of pipes, as written in `#[pipe(A, B)]`
+///
+/// Implemented for tuples of up to eight `Pipe + Default` types.
+pub trait Pipes: Send + Sync + 'static {
+    /// Run the pipes in order; a failing pipe leaves the value unchanged
+    /// and its errors are collected with those of the others
+    fn run(value: Value) -> Result<Value, Vec<PipeError>>;
+}
+
+macro_rules! impl_pipes {
+    ($($pipe:ident),+) => {
+        impl<$($pipe: Pipe + Default),+> Pipes for ($($pipe,)+) {
+            fn run(mut value: Value) -> Result<Value, Vec<PipeError>> {
+                let mut errors = Vec::new();
+                $(
+                    match $pipe::default().apply(value.clone()) {
+                        Ok(piped) => value = piped,
+                        Err(found) => errors.extend(found),
+                    }
+                )+
+                if errors.is_empty() {
+                    Ok(value)
+                } else {
+                    Err(errors)
+                }
+            }
+        }
+    };
+}
+
+impl_pipes!(A);
+impl_pipes!(A, B);
+impl_pipes!(A, B, C);
+impl_pipes!(A, B, C, D);
+impl_pipes!(A, B, C, D, E);
+impl_pipes!(A, B, C, D, E, F);
+impl_pipes!(A, B, C, D, E, F, G);
+impl_pipes!(A, B, C, D, E, F, G, H);
+
+/// Extractor `E` whose raw value first went through the pipes `P`
+///
+/// Generated by `#[pipe(...)]` on a handler parameter; the handler itself
+/// sees the plain `E`. Supported for `Json<T>`, `Query<T>` and `Path<T>`.
+/// Pipes see query parameters as an object of strings, with an array of
+/// strings for a repeated key.
+pub struct Piped<E, P>(pub E, PhantomData<fn() -> P>);
+
+impl<E, P> Piped<E, P> {
+    /// The piped extractor
+    pub fn into_inner(self) -> E {
+        self.0
+    }
+}
+
+// 422 `validation_failed` listing the messages of each field
+fn rejection(errors: Vec<PipeError>, param: &str) -> ApiError {
+    let mut fields = Map::new();
+    for error in errors {
+        let field = if error.field.is_empty() {
+            param.to_string()
+        } else {
+            error.field
+        };
+        let messages = fields.entry(field).or_insert_with(|| json!([]));
+        if let Value::Array(list) = messages {
+            list.push(Value::String(error.message));
+        }
+    }
+    ApiError::unprocessable("Validation failed")
+        .with_code("validation_failed")
+        .with_details(json!({ "fields": fields }))
+        .rejection()
+}
+
+// scalar JSON value for a path or query string: numbers and booleans as such
+fn coerce(raw: &str) -> Value {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+// the string form of a piped scalar, for re-encoding
+fn scalar_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl<T, P, S> FromRequest<S> for Piped<json::Json<T>, P>
+where
+    T: DeserializeOwned,
+    P: Pipes,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let json::Json(value) = json::Json::<Value>::from_request(req, state).await?;
+        let value = P::run(value).map_err(|errors| rejection(errors, "body"))?;
+        let parsed = serde_json::from_value(value).map_err(|e| {
+            ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to deserialize the JSON body: {}", e),
+            )
+            .with_code("invalid_json")
+            .rejection()
+        })?;
+        Ok(Piped(json::Json(parsed), PhantomData))
+    }
+}
+
+impl<T, P, S> FromRequestParts<S> for Piped<Query<T>, P>
+where
+    T: DeserializeOwned,
+    P: Pipes,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Query(pairs) = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+            .map_err(|e| ApiError::bad_request(e.body_text()).rejection())?;
+        // a repeated key (`?tag=a&tag=b`) becomes an array of its values
+        let mut raw = Map::new();
+        for (key, value) in pairs {
+            match raw.get_mut(&key) {
+                Some(Value::Array(values)) => values.push(Value::String(value)),
+                Some(first) => *first = json!([first.take(), value]),
+                None => {
+                    raw.insert(key, Value::String(value));
+                }
+            }
+        }
+        let piped = P::run(Value::Object(raw)).map_err(|errors| rejection(errors, "query"))?;
+
+        // re-encode so `Query` parses the piped strings into `T` as usual
+        let pair = |key: &str, value: &Value| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(key, NON_ALPHANUMERIC),
+                utf8_percent_encode(&scalar_string(value), NON_ALPHANUMERIC)
+            )
+        };
+        let query = piped
+            .as_object()
+            .into_iter()
+            .flatten()
+            .flat_map(|(key, value)| match value {
+                Value::Array(values) => values.iter().map(|value| pair(key, value)).collect(),
+                value => vec![pair(key, value)],
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        let uri: Uri = format!("/?{}", query)
+            .parse()
+            .map_err(|_| ApiError::bad_request("Invalid query string").rejection())?;
+        let query = Query::try_from_uri(&uri)
+            .map_err(|e| ApiError::bad_request(e.body_text()).rejection())?;
+        Ok(Piped(query, PhantomData))
+    }
+}
+
+impl<T, P, S> FromRequestParts<S> for Piped<Path<T>, P>
+where
+    T: DeserializeOwned + Send,
+    P: Pipes,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|e| ApiError::bad_request(e.body_text()).rejection())?;
+        let names: Vec<String> = params.iter().map(|(name, _)| name.to_string()).collect();
+        let raw: Map<String, Value> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+            .collect();
+        let piped = P::run(Value::Object(raw)).map_err(|errors| rejection(errors, "path"))?;
+
+        // a single parameter, a tuple in path order, or a struct by name
+        let values: Vec<Value> = names
+            .iter()
+            .map(|name| coerce(&scalar_string(&piped[name.as_str()])))
+            .collect();
+        let strings: Vec<Value> = names
+            .iter()
+            .map(|name| Value::String(scalar_string(&piped[name.as_str()])))
+            .collect();
+        let object: Map<String, Value> =
+            names.iter().cloned().zip(values.iter().cloned()).collect();
+        let mut candidates = Vec::new();
+        if let [single] = strings.as_slice() {
+            candidates.push(single.clone());
+            candidates.push(values[0].clone());
+        }
+        candidates.push(Value::Array(values));
+        candidates.push(Value::Object(object));
+        // the first shape's error is the one that describes `T`
+        let mut first_error = None;
+        for candidate in candidates {
+            match serde_json::from_value(candidate) {
+                Ok(value) => return Ok(Piped(Path(value), PhantomData)),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        let reason = first_error.map(|e| e.to_string()).unwrap_or_default();
+        Err(
+            ApiError::bad_request(format!("Invalid path parameters: {}", reason))
+                .with_code("invalid_path")
+                .rejection(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn call(router: Router, request: Request) -> (StatusCode, String) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_map_strings_paths() {
+        let value = json!({ "name": " Ada ", "tags": [" a", "b "], "age": 36 });
+        assert_eq!(
+            TrimStrings.apply(value).unwrap(),
+            json!({ "name": "Ada", "tags": ["a", "b"], "age": 36 })
+        );
+        let errors = ParseUuid.apply(json!({ "ids": ["nope"] })).unwrap_err();
+        assert_eq!(errors, [PipeError::new("ids[0]", "must be a UUID")]);
+    }
+
+    #[derive(Deserialize)]
+    struct Note {
+        text: String,
+    }
+
+    #[tokio::test]
+    async fn test_piped_path_and_json() {
+        async fn add_note(
+            Piped(Path(id), _): Piped<Path<String>, (TrimStrings, ParseUuid)>,
+            Piped(json::Json(note), _): Piped<json::Json<Note>, (TrimStrings,)>,
+        ) -> String {
+            format!("{}:{}", id, note.text)
+        }
+        let router = Router::new().route("/users/{id}/notes", post(add_note));
+        let request = |id: &str| {
+            Request::post(format!("/users/{}/notes", id))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"text": "  hello  "}"#))
+                .unwrap()
+        };
+
+        let id = "67E55044-10B1-426F-9247-BB680E5FE0C8";
+        let (status, body) = call(router.clone(), request(&format!("%20{}", id))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, "67e55044-10b1-426f-9247-bb680e5fe0c8:hello");
+
+        let (status, body) = call(router, request("42")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains(r#""id":["must be a UUID"]"#), "{}", body);
+    }
+
+    #[derive(Deserialize)]
+    struct Search {
+        q: String,
+        page: u32,
+    }
+
+    #[tokio::test]
+    async fn test_piped_query_keeps_types() {
+        async fn search(Piped(Query(search), _): Piped<Query<Search>, (TrimStrings,)>) -> String {
+            format!("{}@{}", search.q, search.page)
+        }
+        let router = Router::new().route("/search", get(search));
+        let request = Request::get("/search?q=%20rust%20&page=2")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            call(router, request).await,
+            (StatusCode::OK, "rust@2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_piped_query_keeps_repeated_keys() {
+        async fn tags(
+            Piped(Query(pairs), _): Piped<Query<Vec<(String, String)>>, (TrimStrings,)>,
+        ) -> String {
+            let tags: Vec<_> = pairs
+                .into_iter()
+                .filter(|(key, _)| key == "tag")
+                .map(|(_, value)| value)
+                .collect();
+            tags.join(",")
+        }
+        let router = Router::new().route("/tags", get(tags));
+        let request = Request::get("/tags?tag=%20a&q=x&tag=b%20&tag=c")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            call(router, request).await,
+            (StatusCode::OK, "a,b,c".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_piped_path_reports_the_reason() {
+        async fn item(Piped(Path(id), _): Piped<Path<u32>, (TrimStrings,)>) -> String {
+            id.to_string()
+        }
+        let router = Router::new().route("/items/{id}", get(item));
+        let request = Request::get("/items/abc").body(Body::empty()).unwrap();
+        let (status, body) = call(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.contains("Invalid path parameters: invalid type: string"),
+            "{}",
+            body
+        );
+    }
+
+    mod attribute {
+        use super::*;
+        use crate::{controller, get};
+
+        #[get("/search")]
+        async fn search(#[pipe(TrimStrings)] Query(search): Query<Search>) -> String {
+            format!("{}@{}", search.q, search.page)
+        }
+
+        struct Users;
+
+        #[controller("/users")]
+        impl Users {
+            #[get("/{id}")]
+            async fn show(&self, #[pipe(TrimStrings, ParseUuid)] Path(id): Path<String>) -> String {
+                id
+            }
+        }
+
+        #[tokio::test]
+        async fn test_pipe_attribute() {
+            let router = Router::new()
+                .route(__search_route, axum::routing::get(search))
+                .merge(std::sync::Arc::new(Users).router());
+            let request = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+            let (status, body) = call(router.clone(), request("/search?q=+api+&page=1")).await;
+            assert_eq!((status, body.as_str()), (StatusCode::OK, "api@1"));
+
+            let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+            let (status, body) = call(router.clone(), request(&format!("/users/{}", id))).await;
+            assert_eq!((status, body.as_str()), (StatusCode::OK, id));
+            let (status, _) = call(router, request("/users/7")).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+}
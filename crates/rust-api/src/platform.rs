@@ -0,0 +1,86 @@
+//! Hosting platform detection for RustAPI framework
+//!
+//! Platforms as a service tell the application where to listen through
+//! environment variables and collect its logs from stdout. `Platform`
+//! recognizes the common ones from the variables they set, so
+//! `RustAPI::from_env` can bind and log the way each expects.
+
+use crate::profile::LogFormat;
+
+/// Platform-as-a-service the process runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// Google Cloud Run (`K_SERVICE` is set)
+    CloudRun,
+    /// Heroku (`DYNO` is set)
+    Heroku,
+    /// Railway (`RAILWAY_ENVIRONMENT` is set)
+    Railway,
+}
+
+impl Platform {
+    /// The platform the process runs on, if it is a known one
+    pub fn detect() -> Option<Self> {
+        Self::detect_with(|name| std::env::var(name).ok())
+    }
+
+    // detect the platform from variables read through `var`
+    pub(crate) fn detect_with(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        if var("K_SERVICE").is_some() {
+            Some(Platform::CloudRun)
+        } else if var("DYNO").is_some() {
+            Some(Platform::Heroku)
+        } else if var("RAILWAY_ENVIRONMENT").is_some() {
+            Some(Platform::Railway)
+        } else {
+            None
+        }
+    }
+
+    /// Lowercase name, as logged at startup
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::CloudRun => "cloud-run",
+            Platform::Heroku => "heroku",
+            Platform::Railway => "railway",
+        }
+    }
+
+    /// Log format the platform's log collector handles best
+    ///
+    /// Cloud Run and Railway parse JSON lines into structured entries, Cloud
+    /// Run with Cloud Logging's field names; Heroku's router-style logs read
+    /// best as compact lines.
+    pub fn log_format(&self) -> LogFormat {
+        match self {
+            Platform::CloudRun => LogFormat::CloudLogging,
+            Platform::Railway => LogFormat::Json,
+            Platform::Heroku => LogFormat::Compact,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let vars = |set: &'static [&'static str]| {
+            move |name: &str| set.contains(&name).then(|| "x".to_string())
+        };
+        assert_eq!(
+            Platform::detect_with(vars(&["K_SERVICE", "PORT"])),
+            Some(Platform::CloudRun)
+        );
+        assert_eq!(
+            Platform::detect_with(vars(&["DYNO"])),
+            Some(Platform::Heroku)
+        );
+        assert_eq!(
+            Platform::detect_with(vars(&["RAILWAY_ENVIRONMENT"])),
+            Some(Platform::Railway)
+        );
+        assert_eq!(Platform::detect_with(vars(&["PORT"])), None);
+    }
+}
@@ -0,0 +1,647 @@
+//! Environment profiles for RustAPI framework
+//!
+//! `App::with_profile` picks defaults suited to an environment: developer
+//! conveniences in `Dev`, and in `Prod` only what is safe to expose. Each
+//! default can still be overridden with the matching `App` method,
+//! regardless of the order of the calls.
+//!
+//! | Setting          | Dev              | Test    | Prod         |
+//! |------------------|------------------|---------|--------------|
+//! | Log format       | pretty           | compact | JSON         |
+//! | 5xx error detail | in body          | in body | logged only  |
+//! | 5xx cause chain, backtrace | in body | logged | logged       |
+//! | Docs UI          | at `/docs`       | off     | off          |
+//! | CORS             | any origin       | off     | off (strict) |
+//!
+//! `APP_ENV` selects the profile in `Profile::from_env`; when it is unset or
+//! not recognised the profile is `Prod`, so a deployment that forgets it
+//! does not expose developer conveniences.
+
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    context::REQUEST_ID_HEADER,
+    error::{self, ApiError},
+    seed,
+};
+
+/// Default mount point of the docs UI
+pub const DEFAULT_DOCS_PATH: &str = "/docs";
+
+/// Swagger UI release loaded by `DocsAssets::cdn`
+pub const SWAGGER_UI_VERSION: &str = "5.17.14";
+
+/// Environment an application runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Local development
+    Dev,
+    /// Automated tests
+    Test,
+    /// Production and production-like deployments
+    Prod,
+}
+
+impl Profile {
+    /// The profile named by `APP_ENV`
+    ///
+    /// `development`/`dev`/`local` map to `Dev`, `test`/`testing` to `Test`
+    /// and `production`/`prod`/`staging` to `Prod`. An unset variable is
+    /// `Prod`, and so is an unknown name, with a warning.
+    pub fn from_env() -> Self {
+        let name = seed::environment();
+        Self::parse(&name).unwrap_or_else(|| {
+            tracing::warn!(
+                "Unknown {} value {:?}; using the Prod profile",
+                seed::ENVIRONMENT_VAR,
+                name
+            );
+            Profile::Prod
+        })
+    }
+
+    // map an environment name to a profile
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "production" | "prod" | "staging" => Some(Profile::Prod),
+            "test" | "testing" => Some(Profile::Test),
+            "development" | "dev" | "local" => Some(Profile::Dev),
+            _ => None,
+        }
+    }
+
+    /// Log format used by `init_logging` for this profile
+    pub fn log_format(self) -> LogFormat {
+        match self {
+            Profile::Dev => LogFormat::Pretty,
+            Profile::Test => LogFormat::Compact,
+            Profile::Prod => LogFormat::Json,
+        }
+    }
+
+    /// Whether 5xx responses include the error message and details
+    pub fn error_details(self) -> bool {
+        self != Profile::Prod
+    }
+
+    /// Whether 5xx responses include the cause chain and backtrace
+    pub fn debug_errors(self) -> bool {
+        self == Profile::Dev
+    }
+
+    /// Where the docs UI is mounted, if anywhere
+    pub fn docs_path(self) -> Option<&'static str> {
+        (self == Profile::Dev).then_some(DEFAULT_DOCS_PATH)
+    }
+
+    /// CORS policy; `None` rejects cross-origin browser requests
+    pub fn cors(self) -> Option<CorsLayer> {
+        (self == Profile::Dev).then(CorsLayer::very_permissive)
+    }
+}
+
+/// Output format of the log subscriber installed by `init_logging`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Multi-line, colored, human-oriented output
+    Pretty,
+    /// Single-line human-readable output
+    Compact,
+    /// One JSON object per event, for log aggregation
+    Json,
+    /// One JSON object per event with the field names Google Cloud Logging
+    /// reads (`severity`, `message`, `timestamp`), e.g. on Cloud Run
+    CloudLogging,
+}
+
+/// Install a global log subscriber writing in `format`
+///
+/// The level comes from `RUST_LOG`, defaulting to `info`. Returns false,
+/// leaving it in place, if a subscriber is already installed.
+///
+/// With the `console` feature and `RuntimeConfig::console` enabled, the
+/// subscriber also feeds tokio-console.
+pub fn init_logging(format: LogFormat) -> bool {
+    use tracing_subscriber::{
+        fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+    };
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let output = match format {
+        LogFormat::Pretty => fmt::layer().pretty().boxed(),
+        LogFormat::Compact => fmt::layer().compact().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+        LogFormat::CloudLogging => fmt::layer()
+            .event_format(CloudLogging)
+            .fmt_fields(fmt::format::JsonFields::new())
+            .boxed(),
+    };
+    // the filter only applies to the log output, as the console needs the
+    // runtime's trace-level events
+    let subscriber = tracing_subscriber::registry().with(output.with_filter(filter));
+    #[cfg(feature = "console")]
+    let subscriber = subscriber.with(crate::runtime::console_enabled().then(|| {
+        console_subscriber::ConsoleLayer::builder()
+            .with_default_env()
+            .spawn()
+    }));
+    if subscriber.try_init().is_err() {
+        return false;
+    }
+    #[cfg(feature = "console")]
+    crate::runtime::announce_console();
+    true
+}
+
+// formats events as Cloud Logging structured entries: the event's fields,
+// plus `severity`, `message`, `timestamp`, `target` and the fields of the
+// enclosing spans under `spans`; span fields must be recorded as JSON
+struct CloudLogging;
+
+impl<S, N> tracing_subscriber::fmt::FormatEvent<S, N> for CloudLogging
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    N: for<'a> tracing_subscriber::fmt::FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &tracing_subscriber::fmt::FmtContext<'_, S, N>,
+        mut writer: tracing_subscriber::fmt::format::Writer<'_>,
+        event: &tracing::Event<'_>,
+    ) -> std::fmt::Result {
+        use tracing::Level;
+        use tracing_subscriber::fmt::FormattedFields;
+
+        let mut fields = JsonFieldMap::default();
+        event.record(&mut fields);
+        let mut entry = fields.0;
+        let metadata = event.metadata();
+        let severity = match *metadata.level() {
+            Level::ERROR => "ERROR",
+            Level::WARN => "WARNING",
+            Level::INFO => "INFO",
+            Level::DEBUG | Level::TRACE => "DEBUG",
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let message = entry.remove("message").unwrap_or_default();
+        entry.insert("severity".into(), severity.into());
+        entry.insert("message".into(), message);
+        entry.insert(
+            "timestamp".into(),
+            json!({ "seconds": now.as_secs(), "nanos": now.subsec_nanos() }),
+        );
+        entry.insert("target".into(), metadata.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<_> = scope
+                .from_root()
+                .map(|span| {
+                    let mut fields = span
+                        .extensions()
+                        .get::<FormattedFields<N>>()
+                        .and_then(|fields| serde_json::from_str(fields).ok())
+                        .unwrap_or_else(|| json!({}));
+                    fields["name"] = span.name().into();
+                    fields
+                })
+                .collect();
+            entry.insert("spans".into(), spans.into());
+        }
+        writeln!(writer, "{}", serde_json::Value::Object(entry))
+    }
+}
+
+// the fields of an event, as JSON values
+#[derive(Default)]
+struct JsonFieldMap(serde_json::Map<String, serde_json::Value>);
+
+impl tracing::field::Visit for JsonFieldMap {
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+// how 5xx `ApiError`s are rendered and logged
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ErrorReporting {
+    // keep the message and details in the body
+    pub details: bool,
+    // add the cause chain and backtrace to the body
+    pub debug: bool,
+}
+
+tokio::task_local! {
+    // reporting of the request being handled, for errors rendered inside a
+    // response body, such as the items of a `BulkResponse`
+    pub(crate) static REPORTING: ErrorReporting;
+}
+
+// log 5xx errors with their cause chain and backtrace, and redact or extend
+// the body according to `reporting`
+pub(crate) async fn report_server_errors(
+    State(reporting): State<ErrorReporting>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // for errors sent outside a response, such as WebSocket error frames
+    req.extensions_mut().insert(reporting);
+    let response = REPORTING
+        .scope(
+            reporting,
+            error::FORCE_BACKTRACES.scope(reporting.debug, next.run(req)),
+        )
+        .await;
+    if !response.status().is_server_error() {
+        return response;
+    }
+    let Some(error) = response.extensions().get::<ApiError>().cloned() else {
+        return response;
+    };
+    let chain = error.chain();
+    let backtrace = error.backtrace().map(ToString::to_string);
+    if reporting.details && reporting.debug {
+        if chain.is_empty() && backtrace.is_none() {
+            return response;
+        }
+        let mut body = error.to_json();
+        body["error"]["debug"] = json!({
+            "chain": chain,
+            "backtrace": backtrace.as_deref().map(|trace| trace.lines().map(str::trim).collect::<Vec<_>>()),
+        });
+        return replace_body(response, Json(body).into_response());
+    }
+
+    // the context is gone by now, but its id is on the response
+    let request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok());
+    tracing::error!(
+        status = error.status().as_u16(),
+        request_id,
+        code = error.code(),
+        details = ?error.details(),
+        chain = ?chain,
+        backtrace = backtrace.as_deref().unwrap_or("disabled"),
+        "{}",
+        error.message()
+    );
+    if reporting.details {
+        return response;
+    }
+    replace_body(response, redacted(&error).into_response())
+}
+
+// a 5xx error with only its status reason and code
+pub(crate) fn redacted(error: &ApiError) -> ApiError {
+    let status = error.status();
+    let reason = status.canonical_reason().unwrap_or("Server error");
+    ApiError::new(status, reason).with_code(error.code())
+}
+
+// `replacement` with the status and headers of `original`, except its body
+// headers
+fn replace_body(original: Response, replacement: Response) -> Response {
+    let (mut parts, _) = original.into_parts();
+    let (new_parts, body) = replacement.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    if let Some(content_type) = new_parts.headers.get(header::CONTENT_TYPE) {
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, content_type.clone());
+    }
+    Response::from_parts(parts, body)
+}
+
+/// Where the docs UI loads Swagger UI from
+///
+/// Browsers only run the files when they match the integrity hashes, if
+/// set, so a compromised CDN cannot inject code into the page.
+///
+/// # Example
+///
+/// ```ignore
+/// // swagger-ui-dist's files served by the application under /assets
+/// let app = App::new()
+///     .docs("/docs")
+///     .docs_assets(DocsAssets::self_hosted("/assets/swagger-ui"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocsAssets {
+    script: String,
+    stylesheet: String,
+    integrity: Option<(String, String)>,
+}
+
+impl DocsAssets {
+    /// Swagger UI `SWAGGER_UI_VERSION` from the unpkg CDN (the default)
+    pub fn cdn() -> Self {
+        let base = format!("https://unpkg.com/swagger-ui-dist@{}", SWAGGER_UI_VERSION);
+        Self::self_hosted(&base)
+    }
+
+    /// `swagger-ui-bundle.js` and `swagger-ui.css` under `base`
+    pub fn self_hosted(base: &str) -> Self {
+        let base = base.trim_end_matches('/');
+        Self {
+            script: format!("{}/swagger-ui-bundle.js", base),
+            stylesheet: format!("{}/swagger-ui.css", base),
+            integrity: None,
+        }
+    }
+
+    /// Require these subresource integrity hashes (e.g. `sha384-...`) of
+    /// the script and the stylesheet
+    pub fn integrity(mut self, script: impl Into<String>, stylesheet: impl Into<String>) -> Self {
+        self.integrity = Some((script.into(), stylesheet.into()));
+        self
+    }
+
+    // whether the files come from another origin without integrity checks
+    pub(crate) fn unverified_cdn(&self) -> bool {
+        self.integrity.is_none() && self.script.starts_with("https://")
+    }
+}
+
+impl Default for DocsAssets {
+    fn default() -> Self {
+        Self::cdn()
+    }
+}
+
+// Swagger UI page loading the document at `spec`
+pub(crate) fn docs_page(spec: &str, assets: &DocsAssets) -> String {
+    let attribute = |name: &str, value: &str| format!(" {}=\"{}\"", name, escape(value));
+    let (script_integrity, stylesheet_integrity) = match &assets.integrity {
+        Some((script, stylesheet)) => (
+            attribute("integrity", script) + " crossorigin=\"anonymous\"",
+            attribute("integrity", stylesheet) + " crossorigin=\"anonymous\"",
+        ),
+        None => (String::new(), String::new()),
+    };
+    format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>API documentation</title>
+<link rel="stylesheet"{}{}>
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script{}{}></script>
+<script>SwaggerUIBundle({{ url: "{}", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        attribute("href", &assets.stylesheet),
+        stylesheet_integrity,
+        attribute("src", &assets.script),
+        script_integrity,
+        spec
+    )
+}
+
+// escape a value for an HTML attribute
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::app::App;
+
+    #[test]
+    fn test_profile_from_name() {
+        assert_eq!(Profile::parse("production"), Some(Profile::Prod));
+        assert_eq!(Profile::parse("Staging"), Some(Profile::Prod));
+        assert_eq!(Profile::parse("test"), Some(Profile::Test));
+        assert_eq!(Profile::parse("development"), Some(Profile::Dev));
+        assert_eq!(Profile::parse("prodution"), None);
+        assert_eq!(Profile::Prod.log_format(), LogFormat::Json);
+        assert!(Profile::Prod.cors().is_none());
+        assert_eq!(Profile::Dev.docs_path(), Some("/docs"));
+    }
+
+    #[test]
+    fn test_cloud_logging_format() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing_subscriber::{fmt, layer::SubscriberExt};
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .event_format(CloudLogging)
+                .fmt_fields(fmt::format::JsonFields::new())
+                .with_writer(move || WriterGuard(writer.clone())),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "req-1");
+            let _entered = span.enter();
+            tracing::warn!(duration_ms = 1200u64, "Slow request");
+        });
+
+        let line = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(entry["severity"], "WARNING");
+        assert_eq!(entry["message"], "Slow request");
+        assert_eq!(entry["duration_ms"], 1200);
+        assert!(entry["timestamp"]["seconds"].as_u64().unwrap() > 0);
+        assert_eq!(entry["spans"][0]["name"], "request");
+        assert_eq!(entry["spans"][0]["request_id"], "req-1");
+        assert!(entry.get("level").is_none());
+    }
+
+    // appends log output to a shared buffer
+    struct WriterGuard(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for WriterGuard {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_docs_page_assets() {
+        let page = docs_page("/docs/openapi.json", &DocsAssets::cdn());
+        assert!(page.contains(&format!("swagger-ui-dist@{}/", SWAGGER_UI_VERSION)));
+        assert!(!page.contains("integrity"));
+        assert!(DocsAssets::cdn().unverified_cdn());
+
+        let assets =
+            DocsAssets::self_hosted("/assets/swagger/").integrity("sha384-js", "sha384-css");
+        let page = docs_page("/docs/openapi.json", &assets);
+        assert!(page.contains(
+            "src=\"/assets/swagger/swagger-ui-bundle.js\" integrity=\"sha384-js\" crossorigin=\"anonymous\""
+        ));
+        assert!(page.contains("href=\"/assets/swagger/swagger-ui.css\" integrity=\"sha384-css\""));
+        assert!(!assets.unverified_cdn());
+    }
+
+    async fn call(app: App, uri: &str, origin: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(origin) = origin {
+            request = request.header("origin", origin);
+        }
+        app.build()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn failing() -> App {
+        App::new().route(
+            "/fail",
+            get(|| async { ApiError::internal("connection to db-7 refused") }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_prod_redacts_server_errors() {
+        let response = call(failing().with_profile(Profile::Prod), "/fail", None).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = body(response).await;
+        assert!(body.contains("Internal Server Error"), "{}", body);
+        assert!(!body.contains("db-7"));
+
+        let response = call(failing().with_profile(Profile::Dev), "/fail", None).await;
+        assert!(body_contains(response, "db-7").await);
+
+        let app = failing().error_details(true).with_profile(Profile::Prod);
+        assert!(body_contains(call(app, "/fail", None).await, "db-7").await);
+    }
+
+    #[tokio::test]
+    async fn test_dev_includes_cause_chain() {
+        let app = || {
+            App::new().route(
+                "/fail",
+                get(|| async {
+                    let cause = std::io::Error::other("refused by db-7");
+                    ApiError::internal("Lookup failed").with_source(cause)
+                }),
+            )
+        };
+
+        let response = call(app().with_profile(Profile::Dev), "/fail", None).await;
+        let json: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(json["error"]["message"], "Lookup failed");
+        assert_eq!(json["error"]["debug"]["chain"][0], "refused by db-7");
+        assert!(json["error"]["debug"]["backtrace"].is_array());
+
+        // the Dev app does not force backtraces anywhere else
+        let backtrace_env = ["RUST_BACKTRACE", "RUST_LIB_BACKTRACE"]
+            .iter()
+            .any(|name| std::env::var_os(name).is_some());
+        if !backtrace_env {
+            let error = ApiError::internal("x").with_source(std::io::Error::other("y"));
+            assert!(error.backtrace().is_none());
+        }
+
+        let response = call(app().with_profile(Profile::Prod), "/fail", None).await;
+        let text = body(response).await;
+        assert!(
+            !text.contains("db-7") && !text.contains("debug"),
+            "{}",
+            text
+        );
+
+        let response = call(app().with_profile(Profile::Test), "/fail", None).await;
+        assert!(!body_contains(response, "debug").await);
+    }
+
+    #[tokio::test]
+    async fn test_core_error_handlers() {
+        async fn handler() -> crate::Result<String> {
+            Err(crate::Error::other("mailer offline"))
+        }
+
+        let app = || App::new().route("/fail", get(handler));
+        let response = call(app(), "/fail", None).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body_contains(response, "mailer offline").await);
+        let response = call(app().with_profile(Profile::Dev), "/fail", None).await;
+        assert!(body_contains(response, "mailer offline").await);
+    }
+
+    async fn body_contains(response: Response, text: &str) -> bool {
+        body(response).await.contains(text)
+    }
+
+    #[tokio::test]
+    async fn test_docs_and_cors_follow_profile() {
+        let dev = || App::new().with_profile(Profile::Dev);
+        let response = call(dev(), "/docs", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body_contains(response, "/docs/openapi.json").await);
+        let response = call(dev(), "/docs/openapi.json", None).await;
+        assert!(body_contains(response, "\"openapi\"").await);
+        let response = call(dev(), "/docs", Some("https://example.com")).await;
+        assert!(response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+
+        let prod = || App::new().with_profile(Profile::Prod);
+        let response = call(prod(), "/docs", Some("https://example.com")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+
+        let response = call(prod().docs("/api-docs"), "/api-docs", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(dev().without_docs(), "/docs", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,207 @@
+//! CPU profiling for RustAPI framework
+//!
+//! With the `profiling` feature, the admin group gains a `GET /pprof`
+//! endpoint that samples the process's stacks for a given duration and
+//! returns the result as a flamegraph SVG or a pprof protobuf, so hot paths
+//! can be profiled in a running dev or staging environment without
+//! restarting it under a profiler.
+//!
+//! ```text
+//! curl -H "Authorization: Bearer $ADMIN_TOKEN" \
+//!     "localhost:3000/._admin/pprof?seconds=0.5" > flamegraph.svg
+//! curl -H "Authorization: Bearer $ADMIN_TOKEN" \
+//!     "localhost:3000/._admin/pprof?seconds=10&format=pprof" > cpu.pb
+//! go tool pprof -http=:8080 cpu.pb
+//! ```
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use axum::{
+    extract::Query,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use pprof::protos::Message;
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+/// Default sampling duration
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// Longest sampling duration accepted
+pub const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// Default sampling frequency, in samples per second
+pub const DEFAULT_FREQUENCY: i32 = 99;
+
+// the profiler is process-wide, so only one profile runs at a time
+static PROFILING: AtomicBool = AtomicBool::new(false);
+
+// the profile being taken, released on drop
+struct Profiling;
+
+impl Drop for Profiling {
+    fn drop(&mut self) {
+        PROFILING.store(false, Ordering::Release);
+    }
+}
+
+// frames from these libraries are dropped, as unwinding through them can crash
+const BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+/// Output of a profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// Interactive flamegraph SVG, rendered with inferno
+    #[default]
+    Flamegraph,
+    /// pprof protobuf, for `go tool pprof` and compatible viewers
+    Pprof,
+}
+
+/// Query parameters of the profiling endpoint
+#[derive(Debug, Deserialize)]
+pub(crate) struct ProfileQuery {
+    // fractional, so sub-second profiles can be taken
+    seconds: Option<f64>,
+    frequency: Option<i32>,
+    #[serde(default)]
+    format: ProfileFormat,
+}
+
+/// Sample the process's stacks for `duration` and render the result
+///
+/// Fails with 409 while another profile is being taken. A profile whose
+/// caller goes away keeps sampling until `duration` is up.
+pub async fn profile(
+    duration: Duration,
+    frequency: i32,
+    format: ProfileFormat,
+) -> Result<Vec<u8>, ApiError> {
+    if PROFILING.swap(true, Ordering::AcqRel) {
+        return Err(
+            ApiError::conflict("A profile is already being taken").with_code("profile_running")
+        );
+    }
+    // held by the sampling thread, which outlives a dropped caller
+    let running = Profiling;
+    tokio::task::spawn_blocking(move || {
+        let _running = running;
+        sample(duration, frequency, format)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Profiling failed: {}", e)))?
+}
+
+// run the profiler on this thread for `duration`
+fn sample(duration: Duration, frequency: i32, format: ProfileFormat) -> Result<Vec<u8>, ApiError> {
+    let failed = |e: pprof::Error| ApiError::internal(format!("Profiling failed: {}", e));
+    let guard = pprof::ProfilerGuardBuilder::default()
+        .frequency(frequency)
+        .blocklist(BLOCKLIST)
+        .build()
+        .map_err(failed)?;
+    std::thread::sleep(duration);
+    let report = guard.report().build().map_err(failed)?;
+
+    let mut output = Vec::new();
+    match format {
+        ProfileFormat::Flamegraph => report.flamegraph(&mut output).map_err(failed)?,
+        ProfileFormat::Pprof => report
+            .pprof()
+            .map_err(failed)?
+            .encode(&mut output)
+            .map_err(|e| ApiError::internal(format!("Encoding the profile failed: {}", e)))?,
+    }
+    Ok(output)
+}
+
+// `GET /pprof` in the admin group
+pub(crate) async fn handler(Query(query): Query<ProfileQuery>) -> Result<Response, ApiError> {
+    let duration = match query.seconds {
+        None => DEFAULT_DURATION,
+        Some(seconds) if seconds > 0.0 && seconds <= MAX_DURATION.as_secs_f64() => {
+            Duration::from_secs_f64(seconds)
+        }
+        Some(_) => {
+            return Err(ApiError::bad_request(format!(
+                "seconds must be greater than 0 and at most {}",
+                MAX_DURATION.as_secs()
+            )))
+        }
+    };
+    let frequency = query.frequency.unwrap_or(DEFAULT_FREQUENCY);
+    if !(1..=1000).contains(&frequency) {
+        return Err(ApiError::bad_request(
+            "frequency must be between 1 and 1000",
+        ));
+    }
+
+    tracing::info!(
+        "Profiling for {:?} at {} Hz ({:?})",
+        duration,
+        frequency,
+        query.format
+    );
+    let output = profile(duration, frequency, query.format).await?;
+    let response = match query.format {
+        ProfileFormat::Flamegraph => {
+            ([(header::CONTENT_TYPE, "image/svg+xml")], output).into_response()
+        }
+        ProfileFormat::Pprof => (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"cpu.pb\"",
+                ),
+            ],
+            output,
+        )
+            .into_response(),
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one test, as the profiler is process-wide
+    #[tokio::test]
+    async fn test_profile() {
+        let taking = tokio::spawn(profile(
+            Duration::from_millis(200),
+            DEFAULT_FREQUENCY,
+            ProfileFormat::Flamegraph,
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let error = profile(Duration::from_millis(1), 99, ProfileFormat::Pprof)
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "profile_running");
+        taking.await.unwrap().unwrap();
+
+        // a caller going away doesn't leave the profiler taken
+        let dropped = tokio::spawn(profile(Duration::from_millis(50), 99, ProfileFormat::Pprof));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        dropped.abort();
+        for _ in 0..500 {
+            if !PROFILING.load(Ordering::Acquire) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let pb = profile(Duration::from_millis(50), 99, ProfileFormat::Pprof)
+            .await
+            .unwrap();
+        let decoded = pprof::protos::Profile::decode(pb.as_slice()).unwrap();
+        assert!(!decoded.sample_type.is_empty());
+    }
+}
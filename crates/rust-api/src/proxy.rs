@@ -0,0 +1,672 @@
+//! Reverse proxy routes for RustAPI framework
+//!
+//! `Proxy` forwards requests to an upstream service through the app's
+//! `HttpClient`, for gateways that expose other services' routes next to
+//! their own. Hop-by-hop headers are dropped in both directions and the
+//! original host is passed on as `X-Forwarded-Host`. Responses are buffered,
+//! up to `Proxy::max_response`; larger ones fail with 502.
+//!
+//! # Tail latency
+//!
+//! For idempotent methods (GET, HEAD, OPTIONS, PUT, DELETE), a proxy can
+//! retry failed attempts and hedge slow ones: when the first attempt has not
+//! answered after a delay, typically the upstream's p95 latency, a second one
+//! is sent and the first answer wins. Both draw from a retry budget that
+//! grows with the traffic, so a struggling upstream sees at most a small,
+//! fixed share of extra load instead of a retry storm.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{any, MethodRouter},
+};
+use tokio::time::Instant;
+
+use crate::{
+    client::{self, ClientError, ClientRequest, ClientResponse, HttpClient, ResponseLimit},
+    error::ApiError,
+    metrics::Metrics,
+};
+
+/// Default largest request body forwarded, in bytes
+pub const DEFAULT_MAX_BODY: usize = 10 * 1024 * 1024;
+
+/// Default largest upstream response body returned, in bytes
+pub const DEFAULT_MAX_RESPONSE: usize = 32 * 1024 * 1024;
+
+/// Most attempts a request may make, whatever the policy says
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Latency samples kept for hedging percentiles
+const LATENCY_SAMPLES: usize = 512;
+
+/// Samples needed before a percentile is trusted
+const MIN_LATENCY_SAMPLES: usize = 20;
+
+// headers that only apply to one connection
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Retries of failed idempotent requests
+///
+/// An attempt fails when the upstream cannot be reached, times out, or
+/// answers 502, 503 or 504. Retries wait `backoff`, doubling each time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retries {
+    max_attempts: u32,
+    backoff: Duration,
+}
+
+impl Retries {
+    /// Make up to `max_attempts` attempts in total, capped at 5
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.clamp(1, MAX_ATTEMPTS),
+            backoff: Duration::from_millis(25),
+        }
+    }
+
+    /// Wait `backoff` before the first retry (default: 25ms)
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+/// When to send a second, hedged attempt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hedge {
+    /// After a fixed delay
+    After(Duration),
+    /// After the given latency percentile of recent upstream responses
+    /// (e.g. `0.95`), or `fallback` until enough responses were seen
+    Percentile {
+        /// Percentile, between 0 and 1
+        percentile: f64,
+        /// Delay used until the percentile is known
+        fallback: Duration,
+    },
+}
+
+/// Extra attempts allowed, as a share of requests
+///
+/// Every request adds `ratio` to the balance, every retry or hedge takes one
+/// away; the balance never exceeds `reserve`, which also allows small bursts
+/// at low traffic.
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    reserve: f64,
+    balance: Mutex<f64>,
+}
+
+impl RetryBudget {
+    /// Allow extra attempts for `ratio` of requests, plus bursts of `reserve`
+    pub fn new(ratio: f64, reserve: u32) -> Self {
+        let reserve = f64::from(reserve.max(1));
+        Self {
+            ratio: ratio.clamp(0.0, 1.0),
+            reserve,
+            balance: Mutex::new(reserve),
+        }
+    }
+
+    // credit a new request
+    fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap_or_else(|e| e.into_inner());
+        *balance = (*balance + self.ratio).min(self.reserve);
+    }
+
+    // take one extra attempt, if the budget allows it
+    fn withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap_or_else(|e| e.into_inner());
+        if *balance >= 1.0 {
+            *balance -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for RetryBudget {
+    /// 10% of requests, bursts of 10
+    fn default() -> Self {
+        Self::new(0.1, 10)
+    }
+}
+
+/// Forwards requests to an upstream service
+///
+/// Cheap to clone; clones share the retry budget and latency samples.
+///
+/// # Example
+///
+/// ```ignore
+/// let client = app.container().resolve::<dyn HttpClient>().unwrap();
+/// let billing = Proxy::new("http://billing:8080", client)
+///     .retries(Retries::new(3))
+///     .hedge(Hedge::Percentile { percentile: 0.95, fallback: Duration::from_millis(200) })
+///     .metrics(metrics.clone());
+///
+/// let app = app.route("/invoices/{*rest}", billing.route());
+/// ```
+#[derive(Clone)]
+pub struct Proxy {
+    upstream: String,
+    client: Arc<dyn HttpClient>,
+    max_body: usize,
+    max_response: usize,
+    retries: Option<Retries>,
+    hedge: Option<Hedge>,
+    budget: Arc<RetryBudget>,
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+    metrics: Option<Metrics>,
+}
+
+impl Proxy {
+    /// Forward to `upstream` (scheme, host and optional path prefix)
+    pub fn new(upstream: &str, client: Arc<dyn HttpClient>) -> Self {
+        Self {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            client,
+            max_body: DEFAULT_MAX_BODY,
+            max_response: DEFAULT_MAX_RESPONSE,
+            retries: None,
+            hedge: None,
+            budget: Arc::default(),
+            latencies: Arc::default(),
+            metrics: None,
+        }
+    }
+
+    /// Reject request bodies larger than `bytes` with 413
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Fail upstream responses larger than `bytes` with 502
+    /// `upstream_response_too_large`
+    ///
+    /// The client stops reading the response at the limit when it honours
+    /// `ResponseLimit`.
+    pub fn max_response(mut self, bytes: usize) -> Self {
+        self.max_response = bytes;
+        self
+    }
+
+    /// Retry failed idempotent requests
+    pub fn retries(mut self, retries: Retries) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Hedge slow idempotent requests
+    pub fn hedge(mut self, hedge: Hedge) -> Self {
+        self.hedge = Some(hedge);
+        self
+    }
+
+    /// Share of extra attempts (default: 10% of requests, bursts of 10)
+    pub fn budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Arc::new(budget);
+        self
+    }
+
+    /// Count `proxy_requests_total`, `proxy_retries_total`,
+    /// `proxy_hedges_total`, `proxy_hedge_wins_total` and
+    /// `proxy_budget_exhausted_total`, labelled with the upstream
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Route handler forwarding every method
+    pub fn route(self) -> MethodRouter {
+        any(move |req: Request| {
+            let proxy = self.clone();
+            async move { proxy.forward(req).await }
+        })
+    }
+
+    /// Forward `req` and return the upstream's response
+    ///
+    /// The request's path and query are appended to the upstream URL, and
+    /// its id is sent in `x-request-id`.
+    /// Fails with 502 when the upstream cannot be reached or its response is
+    /// larger than `max_response`, and 504 when it times out.
+    pub async fn forward(&self, req: Request) -> Response {
+        let (parts, body) = req.into_parts();
+        let body = match axum::body::to_bytes(body, self.max_body).await {
+            Ok(body) => body,
+            Err(_) => {
+                return ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large")
+                    .into_response()
+            }
+        };
+        let target = match self.target(&parts.uri) {
+            Ok(target) => target,
+            Err(error) => return error.into_response(),
+        };
+        let mut headers = parts.headers;
+        strip_hop_by_hop(&mut headers);
+        client::propagate_request_id(&mut headers);
+        if let Some(host) = headers.remove(header::HOST) {
+            headers.insert(HeaderName::from_static("x-forwarded-host"), host);
+        }
+        let outbound = Outbound {
+            method: parts.method,
+            uri: target,
+            headers,
+            body,
+            max_response: self.max_response,
+        };
+
+        self.count("proxy_requests_total");
+        self.budget.deposit();
+        match self.send(&outbound).await {
+            Ok(response) => {
+                let (mut parts, body) = response.into_parts();
+                strip_hop_by_hop(&mut parts.headers);
+                Response::from_parts(parts, Body::from(body))
+            }
+            Err(error) => ApiError::from(error).into_response(),
+        }
+    }
+
+    // the upstream URL of a request
+    fn target(&self, uri: &Uri) -> Result<Uri, ApiError> {
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        format!("{}{}", self.upstream, path)
+            .parse()
+            .map_err(|_| ApiError::internal("Invalid upstream URL"))
+    }
+
+    // send with the retries and hedging allowed for the method
+    async fn send(&self, outbound: &Outbound) -> Result<ClientResponse, ClientError> {
+        if !is_idempotent(&outbound.method) {
+            return self.client.send(outbound.request()).await;
+        }
+        let attempts = self.retries.map_or(1, |retries| retries.max_attempts);
+        let mut backoff = self
+            .retries
+            .map_or(Duration::ZERO, |retries| retries.backoff);
+        let mut attempt = 1;
+        loop {
+            let result = self.hedged(outbound).await;
+            if is_success(&result) || attempt >= attempts {
+                return result;
+            }
+            if !self.budget.withdraw() {
+                self.count("proxy_budget_exhausted_total");
+                return result;
+            }
+            self.count("proxy_retries_total");
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    // one attempt, plus a hedged one if the first is slow
+    async fn hedged(&self, outbound: &Outbound) -> Result<ClientResponse, ClientError> {
+        let Some(delay) = self.hedge_delay() else {
+            return self.timed(outbound).await;
+        };
+        let first = self.timed(outbound);
+        tokio::pin!(first);
+        tokio::select! {
+            result = &mut first => return result,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        if !self.budget.withdraw() {
+            self.count("proxy_budget_exhausted_total");
+            return first.await;
+        }
+        self.count("proxy_hedges_total");
+
+        // the first good answer wins; a failure waits for the other attempt
+        let second = self.timed(outbound);
+        tokio::pin!(second);
+        tokio::select! {
+            result = &mut first => {
+                if is_success(&result) { result } else { second.await }
+            }
+            result = &mut second => {
+                if is_success(&result) {
+                    self.count("proxy_hedge_wins_total");
+                    result
+                } else {
+                    first.await
+                }
+            }
+        }
+    }
+
+    // send one attempt, recording its latency
+    async fn timed(&self, outbound: &Outbound) -> Result<ClientResponse, ClientError> {
+        let started = Instant::now();
+        let result = self
+            .client
+            .send(outbound.request())
+            .await
+            // for clients that ignore the limit
+            .and_then(|response| match response.body().len() > self.max_response {
+                true => Err(ClientError::TooLarge(self.max_response)),
+                false => Ok(response),
+            });
+        if result.is_ok() {
+            let mut latencies = self.latencies.lock().unwrap_or_else(|e| e.into_inner());
+            if latencies.len() == LATENCY_SAMPLES {
+                latencies.pop_front();
+            }
+            latencies.push_back(started.elapsed());
+        }
+        result
+    }
+
+    // how long to wait before hedging, if hedging is on
+    fn hedge_delay(&self) -> Option<Duration> {
+        match self.hedge? {
+            Hedge::After(delay) => Some(delay),
+            Hedge::Percentile {
+                percentile,
+                fallback,
+            } => {
+                let mut samples: Vec<_> = self
+                    .latencies
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .iter()
+                    .copied()
+                    .collect();
+                if samples.len() < MIN_LATENCY_SAMPLES {
+                    return Some(fallback);
+                }
+                samples.sort_unstable();
+                let rank = (percentile.clamp(0.0, 1.0) * (samples.len() - 1) as f64).round();
+                Some(samples[rank as usize])
+            }
+        }
+    }
+
+    // count an event when metrics are enabled
+    fn count(&self, name: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(name, &[("upstream", &self.upstream)]);
+        }
+    }
+}
+
+// a buffered outbound request, rebuilt for every attempt
+struct Outbound {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+    max_response: usize,
+}
+
+impl Outbound {
+    // a fresh copy of the request
+    fn request(&self) -> ClientRequest {
+        let mut request = ClientRequest::new(self.body.clone());
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.headers_mut() = self.headers.clone();
+        request
+            .extensions_mut()
+            .insert(ResponseLimit(self.max_response));
+        request
+    }
+}
+
+// methods that may be sent more than once
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    )
+}
+
+// whether an attempt needs no retry: anything but transport failures and
+// 502, 503 or 504; a response too large would be as large again
+fn is_success(result: &Result<ClientResponse, ClientError>) -> bool {
+    match result {
+        Ok(response) => !matches!(
+            response.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        ),
+        Err(error) => matches!(error, ClientError::TooLarge(_)),
+    }
+}
+
+// drop connection-level headers, including those named by `Connection`
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in named.iter().chain(&HOP_BY_HOP.map(HeaderName::from_static)) {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::lifecycle::BoxFuture;
+
+    // answers attempt `n` with `script[n]`: a delay and a status, 0 failing
+    // the connection; later attempts repeat the last entry
+    struct Upstream {
+        script: Vec<(u64, u16)>,
+        calls: AtomicUsize,
+        seen: Mutex<Vec<ClientRequest>>,
+    }
+
+    impl Upstream {
+        fn new(script: &[(u64, u16)]) -> Arc<Self> {
+            Arc::new(Self {
+                script: script.to_vec(),
+                calls: AtomicUsize::new(0),
+                seen: Mutex::default(),
+            })
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    impl HttpClient for Upstream {
+        fn send(
+            &self,
+            request: ClientRequest,
+        ) -> BoxFuture<'_, Result<ClientResponse, ClientError>> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let (delay, status) = self.script[call.min(self.script.len() - 1)];
+            self.seen.lock().unwrap().push(request);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                if status == 0 {
+                    return Err(ClientError::Connect("refused".into()));
+                }
+                let mut response = ClientResponse::new(Bytes::from(format!("attempt {}", call)));
+                *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                Ok(response)
+            })
+        }
+    }
+
+    async fn call(proxy: &Proxy, method: Method) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri("/orders/7?full=1")
+            .header("host", "gateway.local")
+            .header("connection", "x-trace")
+            .header("x-trace", "1")
+            .body(Body::from("{}"))
+            .unwrap();
+        let response = proxy.forward(request).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_forwards_path_and_headers() {
+        let upstream = Upstream::new(&[(0, 200)]);
+        let proxy = Proxy::new("http://orders:8080/api/", upstream.clone());
+        assert_eq!(call(&proxy, Method::GET).await.0, StatusCode::OK);
+
+        let seen = upstream.seen.lock().unwrap();
+        assert_eq!(seen[0].uri(), "http://orders:8080/api/orders/7?full=1");
+        assert_eq!(seen[0].headers()["x-forwarded-host"], "gateway.local");
+        assert!(seen[0].headers().get("host").is_none());
+        assert!(seen[0].headers().get("x-trace").is_none());
+        assert_eq!(seen[0].body(), "{}");
+    }
+
+    #[tokio::test]
+    async fn test_large_responses_fail() {
+        let upstream = Upstream::new(&[(0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .max_response(4)
+            .retries(Retries::new(3).backoff(Duration::ZERO));
+        let response = proxy
+            .forward(Request::get("/orders").body(Body::empty()).unwrap())
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "upstream_response_too_large");
+        // not retried
+        assert_eq!(upstream.calls(), 1);
+        let seen = upstream.seen.lock().unwrap();
+        assert_eq!(seen[0].extensions().get(), Some(&ResponseLimit(4)));
+    }
+
+    #[tokio::test]
+    async fn test_retries_idempotent_requests() {
+        let metrics = Metrics::new();
+        let upstream = Upstream::new(&[(0, 503), (0, 0), (0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .retries(Retries::new(3).backoff(Duration::from_millis(1)))
+            .metrics(metrics.clone());
+
+        assert_eq!(
+            call(&proxy, Method::GET).await,
+            (StatusCode::OK, "attempt 2".to_string())
+        );
+        let labels = [("upstream", "http://orders")];
+        assert_eq!(metrics.counter("proxy_retries_total", &labels), 2);
+
+        // never for POST
+        let upstream = Upstream::new(&[(0, 0), (0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream.clone()).retries(Retries::new(3));
+        assert_eq!(call(&proxy, Method::POST).await.0, StatusCode::BAD_GATEWAY);
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_budget_caps_retries() {
+        let metrics = Metrics::new();
+        let upstream = Upstream::new(&[(0, 503)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .retries(Retries::new(10).backoff(Duration::ZERO))
+            .budget(RetryBudget::new(0.0, 3))
+            .metrics(metrics.clone());
+
+        assert_eq!(
+            call(&proxy, Method::GET).await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(upstream.calls(), 4);
+        assert_eq!(
+            call(&proxy, Method::GET).await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(upstream.calls(), 5);
+        let labels = [("upstream", "http://orders")];
+        assert_eq!(metrics.counter("proxy_budget_exhausted_total", &labels), 2);
+    }
+
+    #[tokio::test]
+    async fn test_attempts_are_capped() {
+        let upstream = Upstream::new(&[(0, 0)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .retries(Retries::new(100).backoff(Duration::ZERO));
+        assert_eq!(call(&proxy, Method::GET).await.0, StatusCode::BAD_GATEWAY);
+        assert_eq!(upstream.calls(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn test_hedge_wins_over_slow_attempt() {
+        let metrics = Metrics::new();
+        let upstream = Upstream::new(&[(1000, 200), (0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .hedge(Hedge::After(Duration::from_millis(20)))
+            .metrics(metrics.clone());
+
+        let started = Instant::now();
+        assert_eq!(
+            call(&proxy, Method::GET).await,
+            (StatusCode::OK, "attempt 1".to_string())
+        );
+        assert!(started.elapsed() < Duration::from_millis(500));
+        let labels = [("upstream", "http://orders")];
+        assert_eq!(metrics.counter("proxy_hedges_total", &labels), 1);
+        assert_eq!(metrics.counter("proxy_hedge_wins_total", &labels), 1);
+
+        // fast answers are not hedged
+        let upstream = Upstream::new(&[(0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream.clone())
+            .hedge(Hedge::After(Duration::from_millis(20)));
+        call(&proxy, Method::GET).await;
+        assert_eq!(upstream.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hedge_delay_follows_percentile() {
+        let upstream = Upstream::new(&[(0, 200)]);
+        let proxy = Proxy::new("http://orders", upstream).hedge(Hedge::Percentile {
+            percentile: 0.9,
+            fallback: Duration::from_millis(300),
+        });
+        assert_eq!(proxy.hedge_delay(), Some(Duration::from_millis(300)));
+
+        proxy
+            .latencies
+            .lock()
+            .unwrap()
+            .extend((1..=100).map(Duration::from_millis));
+        assert_eq!(proxy.hedge_delay(), Some(Duration::from_millis(90)));
+    }
+}
@@ -0,0 +1,337 @@
+//! PROXY protocol support for RustAPI framework
+//!
+//! Parses HAProxy PROXY protocol headers (v1 text and v2 binary) on accepted
+//! connections, so the real client address is available when running behind a
+//! TCP load balancer (HAProxy, AWS NLB, etc.).
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+/// Signature that starts every v2 header
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+/// Maximum length of a v1 header, including the trailing CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// Default time allowed for a client to send its PROXY header
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Addresses carried by a PROXY protocol header
+///
+/// Both addresses are `None` for `LOCAL` (v2) and `UNKNOWN` (v1) headers, in
+/// which case the peer address of the connection should be used as-is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// Original client address
+    pub source: Option<SocketAddr>,
+    /// Original destination address
+    pub destination: Option<SocketAddr>,
+}
+
+/// Read and parse a PROXY protocol header (v1 or v2) from the given stream
+///
+/// Consumes exactly the header bytes, leaving the rest of the stream
+/// untouched for the HTTP layer.
+pub async fn read_header<R: AsyncRead + Unpin>(io: &mut R) -> io::Result<ProxyHeader> {
+    // both versions are at least 12 bytes long ("PROXY UNKNOWN\r\n" is 15)
+    let mut prefix = [0u8; 12];
+    io.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        read_v2(io).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(io, &prefix).await
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+// read the remainder of a v1 text header and parse it
+async fn read_v1<R: AsyncRead + Unpin>(io: &mut R, prefix: &[u8]) -> io::Result<ProxyHeader> {
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("PROXY v1 header too long"));
+        }
+        line.push(io.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("PROXY v1 header is not valid UTF-8"))?;
+    parse_v1_line(line)
+}
+
+// parse a v1 header line without its trailing CRLF
+fn parse_v1_line(line: &str) -> io::Result<ProxyHeader> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        ["PROXY", "TCP4" | "TCP6", src, dst, src_port, dst_port] => Ok(ProxyHeader {
+            source: Some(parse_v1_addr(src, src_port)?),
+            destination: Some(parse_v1_addr(dst, dst_port)?),
+        }),
+        _ => Err(invalid("malformed PROXY v1 header")),
+    }
+}
+
+// parse one v1 address/port pair
+fn parse_v1_addr(ip: &str, port: &str) -> io::Result<SocketAddr> {
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| invalid("invalid PROXY v1 address"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("invalid PROXY v1 port"))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+// read the remainder of a v2 binary header and parse it
+async fn read_v2<R: AsyncRead + Unpin>(io: &mut R) -> io::Result<ProxyHeader> {
+    let version_command = io.read_u8().await?;
+    let family = io.read_u8().await?;
+    let len = io.read_u16().await? as usize;
+
+    let mut payload = vec![0u8; len];
+    io.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0x0F {
+        // LOCAL: health checks from the proxy itself, keep the peer address
+        0x0 => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        0x1 => parse_v2_addresses(family, &payload),
+        _ => Err(invalid("unsupported PROXY v2 command")),
+    }
+}
+
+// parse the address block of a v2 PROXY command
+fn parse_v2_addresses(family: u8, payload: &[u8]) -> io::Result<ProxyHeader> {
+    match family >> 4 {
+        // AF_INET
+        0x1 if payload.len() >= 12 => {
+            let src = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let dst = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src.into(), be_u16(&payload[8..10]))),
+                destination: Some(SocketAddr::new(dst.into(), be_u16(&payload[10..12]))),
+            })
+        }
+        // AF_INET6
+        0x2 if payload.len() >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[0..16]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[16..32]).unwrap());
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src.into(), be_u16(&payload[32..34]))),
+                destination: Some(SocketAddr::new(dst.into(), be_u16(&payload[34..36]))),
+            })
+        }
+        0x1 | 0x2 => Err(invalid("truncated PROXY v2 address block")),
+        // AF_UNSPEC, AF_UNIX and unknown families carry no usable IP
+        // address; like a LOCAL command, the peer address is kept
+        _ => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+    }
+}
+
+// decode a big-endian u16 from a two-byte slice
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+// build an InvalidData error with the given message
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Listener that requires a PROXY protocol header on every connection
+///
+/// Connections are accepted and their headers parsed in background tasks, so
+/// a slow or misbehaving client cannot stall the accept loop. The address
+/// reported to the HTTP layer is the original client address from the header.
+/// Connections with a missing or malformed header are dropped.
+///
+/// # Example
+///
+/// ```ignore
+/// let tcp = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+/// let listener = ProxyProtocolListener::new(tcp)?;
+/// ```
+pub struct ProxyProtocolListener {
+    incoming: mpsc::Receiver<(TcpStream, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl ProxyProtocolListener {
+    /// Wrap a bound TCP listener, using the default 5 second header timeout
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(listener: TcpListener) -> io::Result<Self> {
+        Self::with_header_timeout(listener, DEFAULT_HEADER_TIMEOUT)
+    }
+
+    /// Wrap a bound TCP listener with a custom header timeout
+    pub fn with_header_timeout(listener: TcpListener, timeout: Duration) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(128);
+        tokio::spawn(Self::accept_loop(listener, tx, timeout));
+
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+
+    // accept raw connections and hand each one off for header parsing
+    async fn accept_loop(
+        listener: TcpListener,
+        tx: mpsc::Sender<(TcpStream, SocketAddr)>,
+        timeout: Duration,
+    ) {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::error!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+            };
+
+            if tx.is_closed() {
+                return;
+            }
+            tokio::spawn(Self::handshake(stream, peer, tx.clone(), timeout));
+        }
+    }
+
+    // read the PROXY header of a single connection and forward it
+    async fn handshake(
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        tx: mpsc::Sender<(TcpStream, SocketAddr)>,
+        timeout: Duration,
+    ) {
+        let header = match tokio::time::timeout(timeout, read_header(&mut stream)).await {
+            Ok(Ok(header)) => header,
+            Ok(Err(e)) => {
+                tracing::warn!("Dropping connection from {}: {}", peer, e);
+                return;
+            }
+            Err(_) => {
+                tracing::warn!("Dropping connection from {}: PROXY header timeout", peer);
+                return;
+            }
+        };
+
+        let client = header.source.unwrap_or(peer);
+        tracing::debug!("Accepted proxied connection from {} via {}", client, peer);
+        let _ = tx.send((stream, client)).await;
+    }
+}
+
+impl axum::serve::Listener for ProxyProtocolListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // the accept loop only exits once this receiver is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_v1_tcp4() {
+        let mut input: &[u8] = b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 443\r\nGET / HTTP/1.1";
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(header.destination, Some("10.0.0.1:443".parse().unwrap()));
+        assert_eq!(input, b"GET / HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn test_read_v1_unknown() {
+        let mut input: &[u8] = b"PROXY UNKNOWN\r\n";
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, None);
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_tcp4() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        bytes.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1]);
+        bytes.extend_from_slice(&8080u16.to_be_bytes());
+        bytes.extend_from_slice(&443u16.to_be_bytes());
+        bytes.extend_from_slice(b"rest");
+
+        let mut input: &[u8] = &bytes;
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, Some("203.0.113.7:8080".parse().unwrap()));
+        assert_eq!(input, b"rest");
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_local() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        let mut input: &[u8] = &bytes;
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, None);
+    }
+
+    #[tokio::test]
+    async fn test_read_v2_address_families() {
+        // an unknown family is ignored, keeping the peer address
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x41, 0x00, 0x04]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        let mut input: &[u8] = &bytes;
+        let header = read_header(&mut input).await.unwrap();
+        assert_eq!(header.source, None);
+
+        // a short IPv4 block is still malformed
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend_from_slice(&[0x21, 0x11, 0x00, 0x04]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        let mut input: &[u8] = &bytes;
+        let err = read_header(&mut input).await.unwrap_err();
+        assert_eq!(err.to_string(), "truncated PROXY v2 address block");
+    }
+
+    #[tokio::test]
+    async fn test_read_missing_header() {
+        let mut input: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+        let err = read_header(&mut input).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,547 @@
+//! Principal quotas for RustAPI framework
+//!
+//! Request throttling keyed by the authenticated principal (user or API key)
+//! rather than the client IP, with per-principal tiers. Requests without an
+//! authenticated principal are counted per client IP on the default tier,
+//! and those without a known IP share one anonymous window.
+//! Over-quota requests get 429 Too Many Requests with `X-RateLimit-*` and
+//! `Retry-After` headers.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::time::Instant;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    context::RequestContext,
+    error::ApiError,
+    lifecycle::BoxFuture,
+    metrics::Metrics,
+};
+
+// how often expired windows are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// key and principal label of requests without a principal
+const ANONYMOUS: &str = "anonymous";
+
+/// A named request allowance per time window
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaTier {
+    name: String,
+    limit: u32,
+    window: Duration,
+}
+
+impl QuotaTier {
+    /// Allow `limit` requests per `window`
+    pub fn new(name: impl Into<String>, limit: u32, window: Duration) -> Self {
+        Self {
+            name: name.into(),
+            limit,
+            window,
+        }
+    }
+
+    /// Name of the tier, e.g. `free` or `pro`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Requests allowed per window
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Length of the window
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// Where the tier of a principal comes from (config, database, billing
+/// service, ...)
+///
+/// Returning `None` applies the default tier.
+pub trait QuotaSource: Send + Sync + 'static {
+    /// Look up the tier of a principal
+    fn tier_for<'a>(&'a self, principal: &'a str) -> BoxFuture<'a, Option<QuotaTier>>;
+}
+
+impl<F> QuotaSource for F
+where
+    F: Fn(&str) -> Option<QuotaTier> + Send + Sync + 'static,
+{
+    fn tier_for<'a>(&'a self, principal: &'a str) -> BoxFuture<'a, Option<QuotaTier>> {
+        let tier = self(principal);
+        Box::pin(async move { tier })
+    }
+}
+
+/// Quota enforcement state shared by every request
+///
+/// The principal is the one recorded in `RequestContext` by authentication
+/// middleware; unverified credentials such as a raw `X-API-Key` header are
+/// never used, since a client could rotate them to escape its quota.
+/// Requests without a principal are counted per client IP (from
+/// `ConnectInfo`) on the default tier; when the IP is unknown too, they all
+/// share a single anonymous window. Counting uses fixed windows per
+/// principal; windows are dropped once they expire.
+///
+/// Quotas are configured with a `QuotasBuilder` and shared once built. Add
+/// the middleware where the principal is already known, i.e. inside the
+/// authentication layer:
+///
+/// ```ignore
+/// let quotas = Quotas::builder(QuotaTier::new("free", 100, Duration::from_secs(60)))
+///     .tier("pro", QuotaTier::new("pro", 10_000, Duration::from_secs(60)))
+///     .assign("key-123", "pro")
+///     .metrics(metrics.clone())
+///     .build();
+///
+/// let app = App::new().group("/api", |g| {
+///     g.layer(middleware::from_fn_with_state(quotas, quota::enforce))
+///         .layer(authenticate)
+///         .route("/items", routing::get(list_items))
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Quotas {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    default_tier: QuotaTier,
+    tiers: HashMap<String, QuotaTier>,
+    assignments: HashMap<String, String>,
+    source: Option<Box<dyn QuotaSource>>,
+    metrics: Option<Metrics>,
+    label_principals: bool,
+    clock: Arc<dyn Clock>,
+    windows: Mutex<Windows>,
+}
+
+/// Configuration of `Quotas`, turned into shared quotas by `build`
+pub struct QuotasBuilder {
+    default_tier: QuotaTier,
+    tiers: HashMap<String, QuotaTier>,
+    assignments: HashMap<String, String>,
+    source: Option<Box<dyn QuotaSource>>,
+    metrics: Option<Metrics>,
+    label_principals: bool,
+    clock: Arc<dyn Clock>,
+}
+
+// current windows by quota key
+struct Windows {
+    by_key: HashMap<String, Window>,
+    swept: Option<Instant>,
+}
+
+// requests counted in the current window of one principal
+struct Window {
+    started: Instant,
+    length: Duration,
+    count: u32,
+}
+
+/// Outcome of counting a request against a quota
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaDecision {
+    /// Tier that was applied
+    pub tier: QuotaTier,
+    /// Whether the request is within the quota
+    pub allowed: bool,
+    /// Requests left in the current window
+    pub remaining: u32,
+    /// Time until the window resets
+    pub reset: Duration,
+}
+
+impl QuotasBuilder {
+    /// Define a named tier that principals can be assigned to
+    pub fn tier(mut self, name: impl Into<String>, tier: QuotaTier) -> Self {
+        self.tiers.insert(name.into(), tier);
+        self
+    }
+
+    /// Assign a principal to a named tier, e.g. from configuration
+    pub fn assign(mut self, principal: impl Into<String>, tier: impl Into<String>) -> Self {
+        self.assignments.insert(principal.into(), tier.into());
+        self
+    }
+
+    /// Look up tiers from a service; static assignments take precedence
+    pub fn source(mut self, source: impl QuotaSource) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Count allowed and throttled requests per tier into `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Also label the counters with the principal (`anonymous` for
+    /// unauthenticated requests)
+    ///
+    /// Off by default, since every principal adds its own series.
+    pub fn label_principals(mut self) -> Self {
+        self.label_principals = true;
+        self
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Build the quotas, ready to be shared by every request
+    pub fn build(self) -> Quotas {
+        Quotas {
+            inner: Arc::new(Inner {
+                default_tier: self.default_tier,
+                tiers: self.tiers,
+                assignments: self.assignments,
+                source: self.source,
+                metrics: self.metrics,
+                label_principals: self.label_principals,
+                clock: self.clock,
+                windows: Mutex::new(Windows {
+                    by_key: HashMap::new(),
+                    swept: None,
+                }),
+            }),
+        }
+    }
+}
+
+impl Quotas {
+    /// Create quotas where every principal gets `default_tier`
+    pub fn new(default_tier: QuotaTier) -> Self {
+        Self::builder(default_tier).build()
+    }
+
+    /// Configure quotas with `default_tier` for principals without a tier
+    pub fn builder(default_tier: QuotaTier) -> QuotasBuilder {
+        QuotasBuilder {
+            default_tier,
+            tiers: HashMap::new(),
+            assignments: HashMap::new(),
+            source: None,
+            metrics: None,
+            label_principals: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Resolve the tier of a principal
+    pub async fn tier_for(&self, principal: &str) -> QuotaTier {
+        let inner = &self.inner;
+        if let Some(tier) = inner
+            .assignments
+            .get(principal)
+            .and_then(|name| inner.tiers.get(name))
+        {
+            return tier.clone();
+        }
+        if let Some(source) = &inner.source {
+            if let Some(tier) = source.tier_for(principal).await {
+                return tier;
+            }
+        }
+        inner.default_tier.clone()
+    }
+
+    /// Count one request for an authenticated principal
+    pub async fn check(&self, principal: &str) -> QuotaDecision {
+        let tier = self.tier_for(principal).await;
+        self.count(format!("principal:{}", principal), tier, principal)
+    }
+
+    /// Count one request from an unauthenticated client, on the default tier
+    pub fn check_ip(&self, ip: std::net::IpAddr) -> QuotaDecision {
+        self.count(
+            format!("ip:{}", ip),
+            self.inner.default_tier.clone(),
+            ANONYMOUS,
+        )
+    }
+
+    /// Count one request with neither a principal nor a client IP
+    ///
+    /// All such requests share one window on the default tier.
+    pub fn check_anonymous(&self) -> QuotaDecision {
+        self.count(
+            ANONYMOUS.to_string(),
+            self.inner.default_tier.clone(),
+            ANONYMOUS,
+        )
+    }
+
+    // count a request against the window of `key`
+    fn count(&self, key: String, tier: QuotaTier, principal: &str) -> QuotaDecision {
+        let now = self.inner.clock.now();
+
+        let mut windows = self.inner.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let swept = *windows.swept.get_or_insert(now);
+        if now.duration_since(swept) >= SWEEP_INTERVAL {
+            windows
+                .by_key
+                .retain(|_, window| now.duration_since(window.started) < window.length);
+            windows.swept = Some(now);
+        }
+        let window = windows.by_key.entry(key).or_insert(Window {
+            started: now,
+            length: tier.window,
+            count: 0,
+        });
+        if now.duration_since(window.started) >= tier.window {
+            window.started = now;
+            window.count = 0;
+        }
+        window.length = tier.window;
+
+        let allowed = window.count < tier.limit;
+        if allowed {
+            window.count += 1;
+        }
+        let decision = QuotaDecision {
+            remaining: tier.limit.saturating_sub(window.count),
+            reset: tier
+                .window
+                .saturating_sub(now.duration_since(window.started)),
+            allowed,
+            tier,
+        };
+        drop(windows);
+
+        if let Some(metrics) = &self.inner.metrics {
+            let outcome = if allowed { "allowed" } else { "throttled" };
+            let mut labels = vec![("tier", decision.tier.name()), ("outcome", outcome)];
+            if self.inner.label_principals {
+                labels.push(("principal", principal));
+            }
+            metrics.increment("quota_requests_total", &labels);
+        }
+        decision
+    }
+
+    /// Number of quota windows currently held
+    pub fn tracked(&self) -> usize {
+        self.inner
+            .windows
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .by_key
+            .len()
+    }
+}
+
+// X-RateLimit-* headers describing a decision
+fn quota_headers(decision: &QuotaDecision) -> [(&'static str, HeaderValue); 3] {
+    // round the reset up so clients never retry too early
+    let reset = decision.reset.as_secs() + u64::from(decision.reset.subsec_nanos() > 0);
+    [
+        (
+            "x-ratelimit-limit",
+            HeaderValue::from(decision.tier.limit()),
+        ),
+        (
+            "x-ratelimit-remaining",
+            HeaderValue::from(decision.remaining),
+        ),
+        ("x-ratelimit-reset", HeaderValue::from(reset)),
+    ]
+}
+
+/// Middleware enforcing principal quotas
+pub async fn enforce(State(quotas): State<Quotas>, req: Request, next: Next) -> Response {
+    let principal = RequestContext::current().and_then(|ctx| ctx.principal());
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let decision = match (principal, client) {
+        (Some(principal), _) => quotas.check(&principal).await,
+        (None, Some(ip)) => quotas.check_ip(ip),
+        (None, None) => quotas.check_anonymous(),
+    };
+    let headers = quota_headers(&decision);
+    if !decision.allowed {
+        let retry_after = headers[2].1.clone();
+        let error = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Quota exceeded")
+            .with_code("quota_exceeded");
+        return (headers, [("retry-after", retry_after)], error).into_response();
+    }
+
+    let mut response = next.run(req).await;
+    response.headers_mut().extend(
+        headers
+            .into_iter()
+            .map(|(name, value)| (HeaderName::from_static(name), value)),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn quotas() -> QuotasBuilder {
+        Quotas::builder(QuotaTier::new("free", 2, Duration::from_secs(60)))
+            .tier("pro", QuotaTier::new("pro", 5, Duration::from_secs(60)))
+            .assign("key-pro", "pro")
+    }
+
+    #[tokio::test]
+    async fn test_tier_resolution() {
+        let quotas = quotas()
+            .source(|principal: &str| {
+                (principal == "key-svc").then(|| QuotaTier::new("svc", 9, Duration::from_secs(1)))
+            })
+            .build();
+        assert_eq!(quotas.tier_for("key-pro").await.name(), "pro");
+        assert_eq!(quotas.tier_for("key-svc").await.limit(), 9);
+        assert_eq!(quotas.tier_for("anyone").await.name(), "free");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_window_resets() {
+        let quotas = quotas().build();
+        assert!(quotas.check("a").await.allowed);
+        assert_eq!(quotas.check("a").await.remaining, 0);
+        assert!(!quotas.check("a").await.allowed);
+        assert!(quotas.check("b").await.allowed);
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(quotas.check("a").await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_window_uses_clock() {
+        let clock = crate::clock::TestClock::new();
+        let quotas = quotas().clock(Arc::new(clock.clone())).build();
+        quotas.check("a").await;
+        quotas.check("a").await;
+        assert!(!quotas.check("a").await.allowed);
+
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(quotas.check("a").await.reset, Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert!(quotas.check("a").await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_expired_windows_are_evicted() {
+        let clock = crate::clock::TestClock::new();
+        let quotas = quotas().clock(Arc::new(clock.clone())).build();
+        quotas.check("a").await;
+        quotas.check("b").await;
+        assert_eq!(quotas.tracked(), 2);
+
+        clock.advance(Duration::from_secs(60));
+        quotas.check("c").await;
+        assert_eq!(quotas.tracked(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_enforce_returns_429_with_headers() {
+        let metrics = Metrics::new();
+        let router =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(
+                    quotas().metrics(metrics.clone()).build(),
+                    enforce,
+                ));
+        // a fresh, unverified API key per request does not escape the quota
+        let keys = std::sync::atomic::AtomicU32::new(0);
+        let request = || {
+            let key = keys.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let mut request = Request::builder()
+                .uri("/")
+                .header("x-api-key", format!("key-{}", key))
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+            request
+        };
+
+        let response = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-ratelimit-limit"], "2");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "1");
+
+        router.clone().oneshot(request()).await.unwrap();
+        let response = router.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+
+        let labels = [("tier", "free"), ("outcome", "throttled")];
+        assert_eq!(metrics.counter("quota_requests_total", &labels), 1);
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_principal_or_ip_share_a_window() {
+        let metrics = Metrics::new();
+        let router =
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(
+                    quotas().metrics(metrics.clone()).label_principals().build(),
+                    enforce,
+                ));
+        let anonymous = || Request::builder().uri("/").body(Body::empty()).unwrap();
+
+        let response = router.clone().oneshot(anonymous()).await.unwrap();
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "1");
+        router.clone().oneshot(anonymous()).await.unwrap();
+        let response = router.oneshot(anonymous()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let labels = [
+            ("tier", "free"),
+            ("outcome", "throttled"),
+            ("principal", "anonymous"),
+        ];
+        assert_eq!(metrics.counter("quota_requests_total", &labels), 1);
+    }
+
+    #[tokio::test]
+    async fn test_principal_label_is_opt_in() {
+        let metrics = Metrics::new();
+        let labelled = quotas().metrics(metrics.clone()).label_principals().build();
+        labelled.check("key-pro").await;
+        let labels = [
+            ("tier", "pro"),
+            ("outcome", "allowed"),
+            ("principal", "key-pro"),
+        ];
+        assert_eq!(metrics.counter("quota_requests_total", &labels), 1);
+
+        let metrics = Metrics::new();
+        let unlabelled = quotas().metrics(metrics.clone()).build();
+        unlabelled.check("key-pro").await;
+        let labels = [("tier", "pro"), ("outcome", "allowed")];
+        assert_eq!(metrics.counter("quota_requests_total", &labels), 1);
+    }
+}
@@ -0,0 +1,293 @@
+//! Range requests for RustAPI framework
+//!
+//! `Range` / `Content-Range` negotiation over any seekable reader, so large
+//! resources (files, database blobs, media) can be fetched in parts and
+//! downloads can be resumed.
+
+use std::{io::SeekFrom, ops::RangeInclusive};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+// bytes read from the underlying reader per body chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How to answer a request asking for several ranges at once
+///
+/// `multipart/byteranges` responses are not produced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MultiRange {
+    /// Ignore the `Range` header and send the whole resource (default)
+    #[default]
+    Full,
+    /// Send only the first requested range
+    First,
+    /// Answer 416 Range Not Satisfiable
+    Reject,
+}
+
+/// Result of interpreting a `Range` header against a resource length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Send the whole resource with 200
+    Full,
+    /// Send one byte range (inclusive) with 206
+    Partial(RangeInclusive<u64>),
+    /// Answer 416
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Interpret a `Range` header value for a resource of `len` bytes
+    ///
+    /// Malformed or non-byte ranges are ignored, as RFC 9110 allows.
+    pub fn parse(value: Option<&str>, len: u64, policy: MultiRange) -> Self {
+        let Some(spec) = value.and_then(|v| v.trim().strip_prefix("bytes=")) else {
+            return RangeRequest::Full;
+        };
+        let ranges: Option<Vec<_>> = spec.split(',').map(|r| parse_one(r.trim(), len)).collect();
+        let Some(ranges) = ranges else {
+            return RangeRequest::Full;
+        };
+
+        if ranges.len() > 1 {
+            match policy {
+                MultiRange::Full => return RangeRequest::Full,
+                MultiRange::Reject => return RangeRequest::Unsatisfiable,
+                MultiRange::First => {}
+            }
+        }
+        match ranges.into_iter().next().flatten() {
+            Some(range) => RangeRequest::Partial(range),
+            None => RangeRequest::Unsatisfiable,
+        }
+    }
+}
+
+// parse `a-b`, `a-` or `-n`; Some(None) means well-formed but unsatisfiable
+fn parse_one(range: &str, len: u64) -> Option<Option<RangeInclusive<u64>>> {
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // suffix range: the last n bytes
+        let n: u64 = end.parse().ok()?;
+        if n == 0 || len == 0 {
+            return Some(None);
+        }
+        return Some(Some(len.saturating_sub(n)..=len - 1));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end = match end {
+        "" => u64::MAX,
+        end => end.parse().ok()?,
+    };
+    if end < start {
+        return None;
+    }
+    if start >= len {
+        return Some(None);
+    }
+    Some(Some(start..=end.min(len - 1)))
+}
+
+/// Response body honoring the request's `Range` header
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/media/{id}")]
+/// async fn media(Path(id): Path<String>, headers: HeaderMap) -> Result<Response, ApiError> {
+///     let file = tokio::fs::File::open(path_for(&id)?).await?;
+///     let len = file.metadata().await?.len();
+///     Ok(RangeBody::new(file, len)
+///         .content_type("video/mp4")
+///         .range(&headers)
+///         .into_response())
+/// }
+/// ```
+pub struct RangeBody<R> {
+    reader: R,
+    len: u64,
+    content_type: Option<HeaderValue>,
+    range: Option<String>,
+    policy: MultiRange,
+}
+
+impl<R> RangeBody<R>
+where
+    R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+{
+    /// Serve `len` bytes from `reader`
+    pub fn new(reader: R, len: u64) -> Self {
+        Self {
+            reader,
+            len,
+            content_type: None,
+            range: None,
+            policy: MultiRange::default(),
+        }
+    }
+
+    /// Set the `Content-Type` of the resource
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = HeaderValue::from_str(content_type).ok();
+        self
+    }
+
+    /// Take the `Range` header from the request headers
+    pub fn range(mut self, headers: &HeaderMap) -> Self {
+        self.range = headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        self
+    }
+
+    /// Set how multi-range requests are answered
+    pub fn multi_range(mut self, policy: MultiRange) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+impl<R> IntoResponse for RangeBody<R>
+where
+    R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+{
+    fn into_response(self) -> Response {
+        let request = RangeRequest::parse(self.range.as_deref(), self.len, self.policy);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if let Some(content_type) = self.content_type {
+            headers.insert(header::CONTENT_TYPE, content_type);
+        }
+
+        let (status, range) = match request {
+            RangeRequest::Full => (StatusCode::OK, 0..=self.len.saturating_sub(1)),
+            RangeRequest::Partial(range) => {
+                let content_range = format!("bytes {}-{}/{}", range.start(), range.end(), self.len);
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).expect("content range is ASCII"),
+                );
+                (StatusCode::PARTIAL_CONTENT, range)
+            }
+            RangeRequest::Unsatisfiable => {
+                let content_range = format!("bytes */{}", self.len);
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).expect("content range is ASCII"),
+                );
+                return (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response();
+            }
+        };
+
+        let length = if self.len == 0 {
+            0
+        } else {
+            range.end() - range.start() + 1
+        };
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+
+        let start = *range.start();
+        let body = stream::unfold(
+            (self.reader, length, false),
+            move |(mut reader, left, seeked)| async move {
+                if left == 0 {
+                    return None;
+                }
+                // seek lazily, once the body is polled
+                if !seeked {
+                    if let Err(e) = reader.seek(SeekFrom::Start(start)).await {
+                        return Some((Err(e), (reader, 0, true)));
+                    }
+                }
+                let mut chunk = vec![0; CHUNK_SIZE.min(left as usize)];
+                match reader.read(&mut chunk).await {
+                    Ok(0) => None,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Some((Ok(chunk), (reader, left - n as u64, true)))
+                    }
+                    Err(e) => Some((Err(e), (reader, 0, true))),
+                }
+            },
+        );
+        (status, headers, Body::from_stream(body.boxed())).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const DATA: &[u8] = b"0123456789";
+
+    async fn respond(range: Option<&str>, policy: MultiRange) -> (StatusCode, HeaderMap, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(range) = range {
+            headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        }
+        let response = RangeBody::new(Cursor::new(DATA), DATA.len() as u64)
+            .range(&headers)
+            .multi_range(policy)
+            .into_response();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let parse = |v| RangeRequest::parse(Some(v), 10, MultiRange::Full);
+        assert_eq!(parse("bytes=0-4"), RangeRequest::Partial(0..=4));
+        assert_eq!(parse("bytes=7-"), RangeRequest::Partial(7..=9));
+        assert_eq!(parse("bytes=-3"), RangeRequest::Partial(7..=9));
+        assert_eq!(parse("bytes=5-100"), RangeRequest::Partial(5..=9));
+        assert_eq!(parse("bytes=10-"), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=4-2"), RangeRequest::Full);
+        assert_eq!(parse("items=0-1"), RangeRequest::Full);
+        assert_eq!(parse("bytes=0-1,4-5"), RangeRequest::Full);
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=0-1,4-5"), 10, MultiRange::First),
+            RangeRequest::Partial(0..=1)
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=0-1,4-5"), 10, MultiRange::Reject),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_response() {
+        let (status, headers, body) = respond(Some("bytes=2-5"), MultiRange::Full).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(headers[header::CONTENT_LENGTH], "4");
+        assert_eq!(body, "2345");
+    }
+
+    #[tokio::test]
+    async fn test_full_and_unsatisfiable_responses() {
+        let (status, headers, body) = respond(None, MultiRange::Full).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert_eq!(body, "0123456789");
+
+        let (status, headers, _) = respond(Some("bytes=20-"), MultiRange::Full).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+    }
+}
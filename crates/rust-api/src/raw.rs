@@ -0,0 +1,307 @@
+//! Raw binary responses for RustAPI framework
+//!
+//! `Raw` sends bytes, or a stream of bytes, as-is with an explicit content
+//! type, for generated PDFs, images and exports. It sets `Content-Length`
+//! when the size is known and `Content-Disposition` on request, so handlers
+//! do not build the response by hand. Files and other seekable sources
+//! answer `Range` requests through `RangeBody`.
+
+use std::{io, path::Path};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use futures_util::{Stream, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tokio::io::{AsyncRead, AsyncSeek};
+
+use crate::{error::ApiError, range::RangeBody};
+
+// RFC 8187 `attr-char`s are sent as they are
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Binary response with an explicit content type
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/invoices/{id}/pdf")]
+/// async fn invoice_pdf(Path(id): Path<u64>) -> ApiResult<Raw> {
+///     let pdf = render_invoice(id).await?;
+///     Ok(Raw::new(pdf, "application/pdf").attachment(format!("invoice-{}.pdf", id)))
+/// }
+///
+/// #[get("/exports/{id}")]
+/// async fn export(Path(id): Path<u64>, headers: HeaderMap) -> ApiResult<Raw> {
+///     let raw = Raw::file(path_of(id), "text/csv").await?;
+///     Ok(raw.range(&headers).attachment(format!("export-{}.csv", id)))
+/// }
+/// ```
+pub struct Raw {
+    body: Source,
+    content_type: String,
+    content_length: Option<u64>,
+    disposition: Option<String>,
+}
+
+// where the bytes of a `Raw` come from
+enum Source {
+    Body(Body),
+    Seekable(RangeBody<Box<dyn Seekable>>),
+}
+
+// readers `RangeBody` can serve, boxed so `Raw` stays a plain type
+trait Seekable: AsyncRead + AsyncSeek + Send + Unpin {}
+
+impl<R: AsyncRead + AsyncSeek + Send + Unpin> Seekable for R {}
+
+impl Raw {
+    /// Send `bytes` as `content_type`
+    pub fn new(bytes: impl Into<Bytes>, content_type: impl Into<String>) -> Self {
+        let bytes = bytes.into();
+        Self {
+            content_length: Some(bytes.len() as u64),
+            body: Source::Body(Body::from(bytes)),
+            content_type: content_type.into(),
+            disposition: None,
+        }
+    }
+
+    /// Send the chunks of `stream` as `content_type`, as they come
+    ///
+    /// The length is unknown, so the body is chunked unless
+    /// `content_length` is set. An error from the stream aborts the
+    /// response.
+    pub fn stream<S, E>(stream: S, content_type: impl Into<String>) -> Self
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        Self {
+            body: Source::Body(Body::from_stream(stream.map_err(Into::into))),
+            content_type: content_type.into(),
+            content_length: None,
+            disposition: None,
+        }
+    }
+
+    /// Send `len` bytes from a seekable `reader` as `content_type`
+    ///
+    /// Such responses honour the `Range` passed to `range`, answering 206
+    /// with only the requested bytes.
+    pub fn seekable<R>(reader: R, len: u64, content_type: impl Into<String>) -> Self
+    where
+        R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+    {
+        let reader: Box<dyn Seekable> = Box::new(reader);
+        Self {
+            body: Source::Seekable(RangeBody::new(reader, len)),
+            content_type: content_type.into(),
+            content_length: None,
+            disposition: None,
+        }
+    }
+
+    /// Send the file at `path` as `content_type`, answering `Range` requests
+    pub async fn file(path: impl AsRef<Path>, content_type: impl Into<String>) -> io::Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        Ok(Self::seekable(file, len, content_type))
+    }
+
+    /// Answer the `Range` header in the request `headers`
+    ///
+    /// Only seekable responses (`seekable`, `file`) can serve ranges; the
+    /// others always send the whole body.
+    pub fn range(mut self, headers: &HeaderMap) -> Self {
+        if let Source::Seekable(body) = self.body {
+            self.body = Source::Seekable(body.range(headers));
+        }
+        self
+    }
+
+    /// Announce a body of `len` bytes
+    ///
+    /// For streams whose size is known up front, such as files. The
+    /// connection fails if the stream ends up longer or shorter. Seekable
+    /// responses set their own length.
+    pub fn content_length(mut self, len: u64) -> Self {
+        self.content_length = Some(len);
+        self
+    }
+
+    /// Ask the client to download the body as `filename`
+    pub fn attachment(mut self, filename: impl AsRef<str>) -> Self {
+        self.disposition = Some(content_disposition("attachment", filename.as_ref()));
+        self
+    }
+
+    /// Ask the client to show the body, saving it as `filename` if asked
+    pub fn inline(mut self, filename: impl AsRef<str>) -> Self {
+        self.disposition = Some(content_disposition("inline", filename.as_ref()));
+        self
+    }
+}
+
+/// `Content-Disposition` value for `filename`
+///
+/// Non-ASCII names are sent as RFC 8187 `filename*`, with an ASCII
+/// `filename` fallback for older clients.
+pub fn content_disposition(kind: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    if fallback == filename {
+        format!("{}; filename=\"{}\"", kind, filename)
+    } else {
+        format!(
+            "{}; filename=\"{}\"; filename*=UTF-8''{}",
+            kind,
+            fallback,
+            utf8_percent_encode(filename, ATTR_CHAR)
+        )
+    }
+}
+
+impl IntoResponse for Raw {
+    fn into_response(self) -> Response {
+        let Ok(content_type) = HeaderValue::try_from(&self.content_type) else {
+            return ApiError::internal(format!("Invalid content type {:?}", self.content_type))
+                .into_response();
+        };
+        let mut response = match self.body {
+            Source::Body(body) => {
+                let mut response = Response::new(body);
+                if let Some(len) = self.content_length {
+                    let headers = response.headers_mut();
+                    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+                }
+                response
+            }
+            // sets the length and range headers itself
+            Source::Seekable(body) => body.into_response(),
+        };
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, content_type);
+        if let Some(disposition) = self.disposition {
+            let value = HeaderValue::try_from(disposition).expect("dispositions are ASCII");
+            headers.insert(header::CONTENT_DISPOSITION, value);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use axum::http::StatusCode;
+    use futures_util::stream;
+
+    use super::*;
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("attachment", "report.pdf"),
+            "attachment; filename=\"report.pdf\""
+        );
+        assert_eq!(
+            content_disposition("inline", "résumé \"v2\".pdf"),
+            "inline; filename=\"r_sum_ _v2_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%22v2%22.pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bytes_response() {
+        let response = Raw::new(&b"%PDF-1.7\x00\xff"[..], "application/pdf")
+            .attachment("invoice.pdf")
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(headers[header::CONTENT_LENGTH], "10");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"invoice.pdf\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"%PDF-1.7\x00\xff");
+
+        let response = Raw::new(Vec::new(), "bad\ntype").into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_stream_response() {
+        let chunks = stream::iter([
+            Ok::<_, io::Error>(Bytes::from_static(b"a,b\n")),
+            Ok(Bytes::from_static(b"1,2\n")),
+        ]);
+        let response = Raw::stream(chunks, "text/csv").into_response();
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"a,b\n1,2\n");
+
+        let chunks = stream::iter([Ok::<_, io::Error>(Bytes::from_static(b"GIF89a"))]);
+        let response = Raw::stream(chunks, "image/gif")
+            .content_length(6)
+            .inline("logo.gif")
+            .into_response();
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "6");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "inline; filename=\"logo.gif\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_response_serves_ranges() {
+        let path = std::env::temp_dir().join(format!("raw-test-{}.txt", std::process::id()));
+        tokio::fs::write(&path, b"0123456789").await.unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_static("bytes=2-5"));
+
+        let response = Raw::file(&path, "text/plain")
+            .await
+            .unwrap()
+            .range(&headers)
+            .attachment("digits.txt")
+            .into_response();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-5/10");
+        assert_eq!(headers[header::CONTENT_LENGTH], "4");
+        assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+        assert!(headers.contains_key(header::CONTENT_DISPOSITION));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"2345");
+    }
+}
@@ -0,0 +1,150 @@
+//! Redirect responses for RustAPI framework
+//!
+//! Provides the `Redirect` response type and the handler used by
+//! `App::redirect` to declare URL migrations without writing handlers.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::ApiError, router::url_for};
+
+/// Response that redirects the client to another location
+///
+/// # Example
+///
+/// ```ignore
+/// #[post("/users")]
+/// async fn create_user(Json(body): Json<NewUser>) -> Result<Redirect, ApiError> {
+///     let user = service.create(body)?;
+///     Redirect::see_other_to_route(__get_user_route, &[("id", &user.id)])
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    status: StatusCode,
+    location: String,
+}
+
+impl Redirect {
+    /// 308 Permanent Redirect: the resource moved for good, method preserved
+    pub fn permanent(location: impl Into<String>) -> Self {
+        Self::with_status(StatusCode::PERMANENT_REDIRECT, location)
+    }
+
+    /// 307 Temporary Redirect: the resource is elsewhere for now, method
+    /// preserved
+    pub fn temporary(location: impl Into<String>) -> Self {
+        Self::with_status(StatusCode::TEMPORARY_REDIRECT, location)
+    }
+
+    /// 303 See Other: fetch the location with GET, typically after a POST
+    pub fn see_other(location: impl Into<String>) -> Self {
+        Self::with_status(StatusCode::SEE_OTHER, location)
+    }
+
+    /// 308 Permanent Redirect to a route built with `url_for`
+    pub fn permanent_to_route(template: &str, params: &[(&str, &str)]) -> Result<Self, ApiError> {
+        Ok(Self::permanent(url_for(template, params)?))
+    }
+
+    /// 307 Temporary Redirect to a route built with `url_for`
+    pub fn temporary_to_route(template: &str, params: &[(&str, &str)]) -> Result<Self, ApiError> {
+        Ok(Self::temporary(url_for(template, params)?))
+    }
+
+    /// 303 See Other to a route built with `url_for`
+    pub fn see_other_to_route(template: &str, params: &[(&str, &str)]) -> Result<Self, ApiError> {
+        Ok(Self::see_other(url_for(template, params)?))
+    }
+
+    /// Redirect with an explicit 3xx status
+    ///
+    /// # Panics
+    ///
+    /// Panics if the status is not one of 301, 302, 303, 307 or 308.
+    pub fn with_status(status: StatusCode, location: impl Into<String>) -> Self {
+        assert!(
+            Self::is_redirect_status(status),
+            "{} is not a redirect status",
+            status
+        );
+        Self {
+            status,
+            location: location.into(),
+        }
+    }
+
+    /// Status code of the redirect
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Target location of the redirect
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    // statuses that carry a Location header
+    pub(crate) fn is_redirect_status(status: StatusCode) -> bool {
+        matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+    }
+}
+
+impl IntoResponse for Redirect {
+    fn into_response(self) -> Response {
+        match HeaderValue::try_from(self.location) {
+            Ok(location) => (self.status, [(header::LOCATION, location)]).into_response(),
+            Err(_) => ApiError::internal("Invalid redirect location").into_response(),
+        }
+    }
+}
+
+/// Handler redirecting every request to a fixed target, keeping the query
+pub(crate) async fn redirect_handler(status: StatusCode, target: String, req: Request) -> Redirect {
+    let location = match req.uri().query() {
+        Some(query) if !target.contains('?') => format!("{}?{}", target, query),
+        _ => target,
+    };
+    Redirect::with_status(status, location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_constructors() {
+        assert_eq!(
+            Redirect::permanent("/new").status(),
+            StatusCode::PERMANENT_REDIRECT
+        );
+        assert_eq!(
+            Redirect::temporary("/new").status(),
+            StatusCode::TEMPORARY_REDIRECT
+        );
+        assert_eq!(Redirect::see_other("/new").status(), StatusCode::SEE_OTHER);
+    }
+
+    #[test]
+    fn test_redirect_to_route() {
+        let redirect = Redirect::see_other_to_route("/users/{id}", &[("id", "7")]).unwrap();
+        assert_eq!(redirect.location(), "/users/7");
+        assert!(Redirect::permanent_to_route("/users/{id}", &[]).is_err());
+    }
+
+    #[test]
+    fn test_redirect_into_response() {
+        let response = Redirect::permanent("/new").into_response();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/new");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a redirect status")]
+    fn test_redirect_rejects_non_redirect_status() {
+        Redirect::with_status(StatusCode::OK, "/new");
+    }
+}
@@ -0,0 +1,752 @@
+//! Route registry for RustAPI framework
+//!
+//! Every handler annotated with a route macro (`#[get]`, `#[post]`, ...) is
+//! recorded here together with an open-ended metadata map. Other crates can
+//! attach their own per-route data from their attribute macros (auth roles,
+//! docs, rate limits) and read it back when building layers.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll, Waker},
+};
+
+use axum::{
+    body::Body,
+    extract::{MatchedPath, Request},
+    http::{header, request::Parts, StatusCode},
+    middleware::{self, Next},
+    response::IntoResponse,
+};
+#[doc(hidden)]
+pub use inventory;
+use tower::ServiceExt;
+
+use crate::{
+    host::HostPattern,
+    openapi,
+    router::{self, Router},
+};
+
+/// Route declared by a route macro
+///
+/// Submitted automatically by `#[get]`, `#[post]`, etc.; not usually built by
+/// hand.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteDef {
+    method: &'static str,
+    path: &'static str,
+    handler: &'static str,
+}
+
+impl RouteDef {
+    /// Declare a route; `handler` is the fully qualified handler path
+    pub const fn new(method: &'static str, path: &'static str, handler: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            handler,
+        }
+    }
+
+    /// HTTP method, uppercase (`GET`, `POST`, ...)
+    pub const fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Path template, e.g. `/users/{id}`
+    pub const fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// Fully qualified handler path
+    pub const fn handler(&self) -> &'static str {
+        self.handler
+    }
+}
+
+inventory::collect!(RouteDef);
+
+/// Static route table sorted by path, then method
+///
+/// Generated by `#[controller]` as `ROUTES`, so a controller's routes can be
+/// inspected without building the global registry. Lookups are `const fn`,
+/// which lets route checks run at compile time:
+///
+/// ```ignore
+/// const _: () = assert!(UserController::ROUTES.get("GET", "/users/{id}").is_some());
+/// ```
+///
+/// Tables cover a single controller and hold each route's method, path and
+/// handler name; metadata is only attached in [`RouteRegistry`], which is
+/// still collected at startup from every route macro in the binary.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteTable {
+    routes: &'static [RouteDef],
+}
+
+impl RouteTable {
+    /// Wrap a sorted slice of routes
+    ///
+    /// # Panics
+    ///
+    /// Panics (at compile time in a `const`) if the routes are not sorted by
+    /// path and method or contain duplicates.
+    pub const fn new(routes: &'static [RouteDef]) -> Self {
+        let mut i = 1;
+        while i < routes.len() {
+            if !matches!(
+                compare_route(&routes[i - 1], routes[i].method, routes[i].path),
+                std::cmp::Ordering::Less
+            ) {
+                panic!("route table must be sorted by path and method without duplicates");
+            }
+            i += 1;
+        }
+        Self { routes }
+    }
+
+    /// Look up a route by uppercase method and path template
+    pub const fn get(&self, method: &str, path: &str) -> Option<&'static RouteDef> {
+        let routes = self.routes;
+        let (mut low, mut high) = (0, routes.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match compare_route(&routes[mid], method, path) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(&routes[mid]),
+            }
+        }
+        None
+    }
+
+    /// Check whether the table contains a route
+    pub const fn contains(&self, method: &str, path: &str) -> bool {
+        self.get(method, path).is_some()
+    }
+
+    /// The routes, in table order
+    pub const fn routes(&self) -> &'static [RouteDef] {
+        self.routes
+    }
+
+    /// Number of routes
+    pub const fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Check whether the table is empty
+    pub const fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Iterate over the routes in table order
+    pub fn iter(&self) -> impl Iterator<Item = &'static RouteDef> {
+        self.routes.iter()
+    }
+}
+
+// order a route against a (method, path) key: path first, then method
+const fn compare_route(route: &RouteDef, method: &str, path: &str) -> std::cmp::Ordering {
+    match compare_str(route.path, path) {
+        std::cmp::Ordering::Equal => compare_str(route.method, method),
+        ordering => ordering,
+    }
+}
+
+// byte-wise string comparison usable in const contexts
+const fn compare_str(a: &str, b: &str) -> std::cmp::Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut i = 0;
+    while i < a.len() && i < b.len() {
+        if a[i] < b[i] {
+            return std::cmp::Ordering::Less;
+        }
+        if a[i] > b[i] {
+            return std::cmp::Ordering::Greater;
+        }
+        i += 1;
+    }
+    if a.len() < b.len() {
+        std::cmp::Ordering::Less
+    } else if a.len() > b.len() {
+        std::cmp::Ordering::Greater
+    } else {
+        std::cmp::Ordering::Equal
+    }
+}
+
+/// Metadata attached to a handler by an attribute macro
+///
+/// The handler is identified by its fully qualified path, i.e.
+/// `concat!(module_path!(), "::", "handler_name")`, which is what the route
+/// macros use.
+///
+/// # Example
+///
+/// Code generated by a third-party `#[require_role("admin")]` macro placed on
+/// `delete_user`:
+///
+/// ```ignore
+/// ::rust_api::registry::inventory::submit! {
+///     ::rust_api::registry::RouteMetadata::new(
+///         concat!(module_path!(), "::", "delete_user"),
+///         |meta| meta.insert(RequiredRole("admin")),
+///     )
+/// }
+/// ```
+pub struct RouteMetadata {
+    handler: &'static str,
+    attach: fn(&mut Metadata),
+}
+
+impl RouteMetadata {
+    /// Attach metadata to the handler at `handler`
+    pub const fn new(handler: &'static str, attach: fn(&mut Metadata)) -> Self {
+        Self { handler, attach }
+    }
+}
+
+inventory::collect!(RouteMetadata);
+
+/// Type-keyed map of per-route metadata
+///
+/// Each crate stores its own types, so entries from different crates never
+/// collide.
+#[derive(Default, Clone)]
+pub struct Metadata {
+    entries: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Metadata {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, replacing any previous value of the same type
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.entries.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// Get the value of type `T`, if present
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.entries
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Check whether a value of type `T` is present
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.entries.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metadata")
+            .field("len", &self.entries.len())
+            .finish()
+    }
+}
+
+/// A registered route and its metadata
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    /// HTTP method, uppercase (`GET`, `POST`, ...)
+    pub method: &'static str,
+    /// Path template, e.g. `/users/{id}`
+    pub path: &'static str,
+    /// Fully qualified handler path, e.g. `my_app::users::get_user`
+    pub handler: &'static str,
+    /// Metadata attached by attribute macros
+    pub metadata: Metadata,
+}
+
+/// All routes declared with route macros in the final binary
+///
+/// # Example
+///
+/// ```ignore
+/// for route in RouteRegistry::global().with::<RequiredRole>() {
+///     println!("{} {} requires {:?}", route.method, route.path, route.metadata.get::<RequiredRole>());
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct RouteRegistry {
+    routes: Vec<RouteInfo>,
+    // uppercase method -> path template -> first route declared there
+    by_route: HashMap<String, HashMap<&'static str, usize>>,
+    // handler -> its routes, one per method it is mounted under
+    by_handler: HashMap<&'static str, Vec<usize>>,
+}
+
+impl RouteRegistry {
+    /// The registry of every route submitted to the binary, built once
+    ///
+    /// Collected from the route macros' submissions on first use, not from
+    /// the controllers' static `ROUTES` tables.
+    pub fn global() -> &'static RouteRegistry {
+        static REGISTRY: OnceLock<RouteRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(Self::collect)
+    }
+
+    /// Build a registry from the submitted route definitions and metadata
+    pub fn collect() -> Self {
+        let mut registry = Self {
+            routes: Vec::with_capacity(inventory::iter::<RouteDef>.into_iter().count()),
+            ..Self::default()
+        };
+        for def in inventory::iter::<RouteDef> {
+            registry.register(def.method, def.path, def.handler);
+        }
+        for attachment in inventory::iter::<RouteMetadata> {
+            // a handler may be mounted under several methods
+            let Some(indexes) = registry.by_handler.get(attachment.handler) else {
+                continue;
+            };
+            for &index in indexes {
+                (attachment.attach)(&mut registry.routes[index].metadata);
+            }
+        }
+        registry
+    }
+
+    /// Register a route by hand, returning it so metadata can be attached
+    pub fn register(
+        &mut self,
+        method: &'static str,
+        path: &'static str,
+        handler: &'static str,
+    ) -> &mut RouteInfo {
+        let index = self.routes.len();
+        self.routes.push(RouteInfo {
+            method,
+            path,
+            handler,
+            metadata: Metadata::new(),
+        });
+        self.by_route
+            .entry(method.to_ascii_uppercase())
+            .or_default()
+            .entry(path)
+            .or_insert(index);
+        self.by_handler.entry(handler).or_default().push(index);
+        &mut self.routes[index]
+    }
+
+    /// Look up a route by method and path template
+    pub fn get(&self, method: &str, path: &str) -> Option<&RouteInfo> {
+        let paths = if method.bytes().any(|b| b.is_ascii_lowercase()) {
+            self.by_route.get(&method.to_ascii_uppercase())
+        } else {
+            self.by_route.get(method)
+        }?;
+        paths.get(path).map(|&index| &self.routes[index])
+    }
+
+    /// Look up the route that matched a request
+    ///
+    /// Only works in middleware added with `Router::layer` or
+    /// `Router::route_layer`, after routing has happened. In an `App`,
+    /// routes mounted under a prefix are resolved through the app's
+    /// `ServedRoutes`; elsewhere the matched path must be the declared one.
+    pub fn for_request(&self, parts: &Parts) -> Option<&RouteInfo> {
+        let matched = parts.extensions.get::<MatchedPath>()?;
+        let method = parts.method.as_str();
+        let served = parts
+            .extensions
+            .get::<Arc<ServedRoutes>>()
+            .and_then(|served| served.get(method, matched.as_str()))
+            .and_then(|served| {
+                self.handler_routes(served.route.handler)
+                    .find(|route| route.method == served.route.method)
+            });
+        served.or_else(|| self.get(method, matched.as_str()))
+    }
+
+    /// Look up a route by its fully qualified handler path
+    pub fn by_handler(&self, handler: &str) -> Option<&RouteInfo> {
+        self.handler_routes(handler).next()
+    }
+
+    // every route of a handler, in registration order
+    fn handler_routes<'a>(&'a self, handler: &str) -> impl Iterator<Item = &'a RouteInfo> {
+        self.by_handler
+            .get(handler)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.routes[index])
+    }
+
+    /// Routes carrying metadata of type `T`
+    pub fn with<T: Any + Send + Sync>(&self) -> impl Iterator<Item = &RouteInfo> {
+        self.routes
+            .iter()
+            .filter(|route| route.metadata.contains::<T>())
+    }
+
+    /// Iterate over all routes
+    pub fn iter(&self) -> impl Iterator<Item = &RouteInfo> {
+        self.routes.iter()
+    }
+
+    /// Number of registered routes
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Check whether no routes are registered
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+/// A registry route as an application serves it
+#[derive(Debug, Clone)]
+pub struct ServedRoute {
+    /// The route, with the path template declared by its route macro
+    pub route: RouteInfo,
+    /// Path the route is served at, including the prefix it is mounted under
+    pub path: String,
+    /// Hosts the route is served on, or empty when served on every host
+    pub hosts: Vec<HostPattern>,
+}
+
+/// Registry routes an application serves, by the path they are served at
+///
+/// A route macro declares its path relative to where the handler is
+/// mounted, so a route mounted with `App::nest` or `App::group` is served
+/// under the mount prefix, and one mounted with `App::host` only on that
+/// host. Built by `App::routes`, and available to middleware of a built
+/// `App` as `Extension<Arc<ServedRoutes>>`; routes mounted under prefixes
+/// the `App` does not know, such as a raw `Router::nest`, are missing.
+#[derive(Debug, Clone, Default)]
+pub struct ServedRoutes {
+    routes: Vec<ServedRoute>,
+    by_path: HashMap<String, Vec<usize>>,
+}
+
+impl ServedRoutes {
+    /// Find the routes of `registry` served by `default` or the host
+    /// routers, trying each mount prefix
+    ///
+    /// Each candidate is sent through the routers with a layer answering
+    /// before the handler, so no handler runs.
+    pub(crate) fn discover(
+        registry: &RouteRegistry,
+        prefixes: &[String],
+        default: &Router,
+        hosts: &[(HostPattern, Router)],
+    ) -> Self {
+        let default = probe(default);
+        let hosts: Vec<_> = hosts
+            .iter()
+            .filter_map(|(pattern, router)| Some((pattern, probe(router)?)))
+            .collect();
+        let mut served = Self::default();
+        for route in registry.iter() {
+            for prefix in std::iter::once("").chain(prefixes.iter().map(String::as_str)) {
+                let path = join(prefix, route.path);
+                if served.get(route.method, &path).is_some() {
+                    continue;
+                }
+                let matches = |router: &Router| {
+                    matched_path(router, route.method, &path)
+                        .is_some_and(|matched| matched.as_str() == path)
+                };
+                let on_default = default.as_ref().is_some_and(matches);
+                let on_hosts: Vec<HostPattern> = hosts
+                    .iter()
+                    .filter(|(_, router)| matches(router))
+                    .map(|(pattern, _)| (*pattern).clone())
+                    .collect();
+                if !on_default && on_hosts.is_empty() {
+                    continue;
+                }
+                served.insert(ServedRoute {
+                    route: route.clone(),
+                    path,
+                    hosts: if on_default { Vec::new() } else { on_hosts },
+                });
+            }
+        }
+        served
+    }
+
+    // add the routes of `registry` served nowhere, at their declared path
+    pub(crate) fn add_unserved(&mut self, registry: &RouteRegistry) {
+        for route in registry.iter() {
+            let served = self.routes.iter().any(|served| {
+                served.route.handler == route.handler && served.route.method == route.method
+            });
+            if !served && self.get(route.method, route.path).is_none() {
+                self.insert(ServedRoute {
+                    route: route.clone(),
+                    path: route.path.to_string(),
+                    hosts: Vec::new(),
+                });
+            }
+        }
+    }
+
+    // add a route to the table and its index
+    fn insert(&mut self, served: ServedRoute) {
+        self.by_path
+            .entry(served.path.clone())
+            .or_default()
+            .push(self.routes.len());
+        self.routes.push(served);
+    }
+
+    /// Look up a route by method and the path template it is served at
+    pub fn get(&self, method: &str, path: &str) -> Option<&ServedRoute> {
+        self.by_path
+            .get(path)?
+            .iter()
+            .map(|&index| &self.routes[index])
+            .find(|served| served.route.method.eq_ignore_ascii_case(method))
+    }
+
+    /// Iterate over the served routes
+    pub fn iter(&self) -> impl Iterator<Item = &ServedRoute> {
+        self.routes.iter()
+    }
+
+    /// Number of served routes
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Check whether no registry route is served
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+// path of a route template mounted under `prefix`
+pub(crate) fn join(prefix: &str, path: &str) -> String {
+    match (prefix.trim_end_matches('/'), path) {
+        ("", path) => path.to_string(),
+        (prefix, "/") => prefix.to_string(),
+        (prefix, path) => format!("{}{}", prefix, path),
+    }
+}
+
+// `router`, answering with the matched path of a request before any handler
+fn probe(router: &Router) -> Option<Router> {
+    let answer = |req: Request, _: Next| async move {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        if let Some(matched) = req.extensions().get::<MatchedPath>() {
+            response.extensions_mut().insert(matched.clone());
+        }
+        response
+    };
+    router
+        .has_routes()
+        .then(|| router.clone().route_layer(middleware::from_fn(answer)))
+}
+
+// matched path of a request for `template` sent through a probe router
+fn matched_path(probe: &Router, method: &str, template: &str) -> Option<MatchedPath> {
+    let params: Vec<(&str, &str)> = openapi::path_params(template)
+        .into_iter()
+        .map(|name| (name, "probe"))
+        .collect();
+    let uri = router::url_for(template, &params).ok()?;
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .ok()?;
+    // the probe layer answers without awaiting, so one poll is enough; it
+    // also wraps the method fallback, whose answers axum marks with `Allow`
+    let call = pin!(probe.clone().oneshot(request));
+    match call.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(Ok(response)) if !response.headers().contains_key(header::ALLOW) => {
+            response.extensions().get::<MatchedPath>().cloned()
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct RequiredRole(&'static str);
+
+    inventory::submit! {
+        RouteDef::new("DELETE", "/registry-test/{id}", concat!(module_path!(), "::", "delete_thing"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "delete_thing"), |meta| {
+            meta.insert(RequiredRole("admin"))
+        })
+    }
+
+    struct Reports;
+
+    impl Reports {
+        #[crate::get("/registry-test/reports")]
+        async fn list() -> &'static str {
+            "reports"
+        }
+    }
+
+    #[test]
+    fn test_route_macro_on_associated_fn() {
+        assert_eq!(Reports::__list_route, "/registry-test/reports");
+        let route = RouteRegistry::global()
+            .iter()
+            .find(|route| route.path == Reports::__list_route)
+            .unwrap();
+        assert_eq!(route.method, "GET");
+        assert!(route.handler.ends_with("::list"));
+        let _ = Reports::list;
+    }
+
+    #[test]
+    fn test_metadata_map() {
+        let mut meta = Metadata::new();
+        assert!(meta.is_empty());
+        meta.insert(RequiredRole("user"));
+        meta.insert(RequiredRole("admin"));
+        meta.insert(42u32);
+        assert_eq!(meta.len(), 2);
+        assert_eq!(meta.get::<RequiredRole>(), Some(&RequiredRole("admin")));
+        assert!(!meta.contains::<String>());
+    }
+
+    #[test]
+    fn test_registry_indexes() {
+        let mut registry = RouteRegistry::default();
+        registry.register("GET", "/items", "app::items");
+        registry.register("HEAD", "/items", "app::items");
+        registry.register("GET", "/items", "app::shadowed");
+        registry.register("POST", "/items", "app::create_item");
+
+        assert_eq!(registry.get("get", "/items").unwrap().handler, "app::items");
+        assert_eq!(
+            registry.get("POST", "/items").unwrap().handler,
+            "app::create_item"
+        );
+        assert!(registry.get("PUT", "/items").is_none());
+        assert_eq!(registry.by_handler("app::items").unwrap().method, "GET");
+        assert_eq!(registry.handler_routes("app::items").count(), 2);
+        assert_eq!(registry.len(), 4);
+    }
+
+    const TABLE: RouteTable = RouteTable::new(&[
+        RouteDef::new("GET", "/users", "app::list_users"),
+        RouteDef::new("POST", "/users", "app::create_user"),
+        RouteDef::new("DELETE", "/users/{id}", "app::delete_user"),
+        RouteDef::new("GET", "/users/{id}", "app::get_user"),
+    ]);
+
+    const _: () = assert!(TABLE.contains("POST", "/users"));
+
+    #[test]
+    fn test_route_table() {
+        assert_eq!(TABLE.len(), 4);
+        let route = TABLE.get("GET", "/users/{id}").unwrap();
+        assert_eq!(route.handler(), "app::get_user");
+        assert_eq!(TABLE.get("DELETE", "/users").map(RouteDef::handler), None);
+        assert_eq!(TABLE.get("GET", "/user").map(RouteDef::handler), None);
+        for route in TABLE.iter() {
+            assert_eq!(
+                TABLE.get(route.method(), route.path()).unwrap().handler(),
+                route.handler()
+            );
+        }
+        assert!(RouteTable::new(&[]).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be sorted")]
+    fn test_route_table_rejects_unsorted() {
+        static ROUTES: [RouteDef; 2] = [
+            RouteDef::new("GET", "/b", "app::b"),
+            RouteDef::new("GET", "/a", "app::a"),
+        ];
+        RouteTable::new(&ROUTES);
+    }
+
+    #[test]
+    fn test_collect_joins_metadata() {
+        let registry = RouteRegistry::global();
+        let route = registry.get("delete", "/registry-test/{id}").unwrap();
+        assert!(route.handler.ends_with("::delete_thing"));
+        assert_eq!(
+            route.metadata.get::<RequiredRole>(),
+            Some(&RequiredRole("admin"))
+        );
+        assert_eq!(registry.with::<RequiredRole>().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_for_request() {
+        use axum::{
+            body::Body,
+            extract::State,
+            middleware::{self, Next},
+            response::Response,
+            routing::get,
+            Router,
+        };
+        use tower::ServiceExt;
+
+        async fn role_header(
+            State(registry): State<Arc<RouteRegistry>>,
+            req: Request<Body>,
+            next: Next,
+        ) -> Response {
+            let (parts, body) = req.into_parts();
+            let role = registry
+                .for_request(&parts)
+                .and_then(|route| route.metadata.get::<RequiredRole>())
+                .map_or("none", |role| role.0);
+            let mut response = next.run(Request::from_parts(parts, body)).await;
+            response
+                .headers_mut()
+                .insert("x-role", role.parse().unwrap());
+            response
+        }
+
+        let mut registry = RouteRegistry::default();
+        registry
+            .register("GET", "/users/{id}", "app::get_user")
+            .metadata
+            .insert(RequiredRole("user"));
+        assert!(registry.by_handler("app::missing").is_none());
+
+        let router = Router::new()
+            .route("/users/{id}", get(|| async { "user" }))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::new(registry),
+                role_header,
+            ));
+        let request = Request::get("/users/7").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-role"], "user");
+    }
+}
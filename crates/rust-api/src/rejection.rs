@@ -0,0 +1,264 @@
+//! Extractor rejection rendering for RustAPI framework
+//!
+//! Axum renders each rejection type (`Json`, `Query`, `Path`, ...) as plain
+//! text, and framework extractors reject with an `ApiError` marked with
+//! `ApiError::rejection`. A handler registered with `App::on_rejection`
+//! receives every one of them as a `Rejection`, so a single function puts
+//! all client errors in the application's own envelope. Errors returned by
+//! handlers themselves are left alone, and the headers of the original
+//! response (`Allow`, `WWW-Authenticate`, rate limits, ...) are kept.
+
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, response::Parts, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+use crate::error::ApiError;
+
+/// Statuses whose responses are passed to the rejection handler
+pub const REJECTION_STATUSES: [StatusCode; 4] = [
+    StatusCode::BAD_REQUEST,
+    StatusCode::PAYLOAD_TOO_LARGE,
+    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+    StatusCode::UNPROCESSABLE_ENTITY,
+];
+
+// plain-text rejection bodies larger than this get a generic message
+const MAX_TEXT_BODY: usize = 64 * 1024;
+
+/// A client error about to be sent, as seen by the rejection handler
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    status: StatusCode,
+    code: String,
+    message: String,
+    details: Option<Value>,
+}
+
+impl Rejection {
+    /// HTTP status of the rejection
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Machine-readable code, e.g. `unsupported_media_type` for axum's
+    /// rejections
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Human-readable message
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Structured details, if the rejection was an `ApiError` with details
+    pub fn details(&self) -> Option<&Value> {
+        self.details.as_ref()
+    }
+
+    /// The rejection as an `ApiError`, to render the standard envelope
+    pub fn into_api_error(self) -> ApiError {
+        let error = ApiError::new(self.status, self.message).with_code(self.code);
+        match self.details {
+            Some(details) => error.with_details(details),
+            None => error,
+        }
+    }
+}
+
+impl From<&ApiError> for Rejection {
+    fn from(error: &ApiError) -> Self {
+        Self {
+            status: error.status(),
+            code: error.code().to_string(),
+            message: error.message().to_string(),
+            details: error.details().cloned(),
+        }
+    }
+}
+
+// renders rejections for `App::on_rejection`
+type RenderFn = dyn Fn(Rejection) -> Response + Send + Sync;
+
+/// Rejection handler installed with `App::on_rejection`
+#[derive(Clone)]
+pub(crate) struct RejectionHandler(Arc<RenderFn>);
+
+impl RejectionHandler {
+    pub(crate) fn new(render: impl Fn(Rejection) -> Response + Send + Sync + 'static) -> Self {
+        Self(Arc::new(render))
+    }
+}
+
+impl fmt::Debug for RejectionHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RejectionHandler")
+    }
+}
+
+// pass client error responses through the rejection handler
+pub(crate) async fn render_rejections(
+    State(handler): State<RejectionHandler>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(req).await;
+    if !REJECTION_STATUSES.contains(&response.status()) {
+        return response;
+    }
+    if let Some(error) = response.extensions().get::<ApiError>() {
+        if !error.is_rejection() {
+            return response;
+        }
+        let rendered = (handler.0)(Rejection::from(error));
+        return with_headers_of(response.into_parts().0, rendered);
+    }
+    let plain_text = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/plain"));
+    if !plain_text {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let message = match axum::body::to_bytes(body, MAX_TEXT_BODY).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => String::from("Request rejected"),
+    };
+    let error = ApiError::new(parts.status, message);
+    with_headers_of(parts, (handler.0)(Rejection::from(&error)))
+}
+
+// `rendered` with the headers of the original response it replaces, except
+// its body headers
+fn with_headers_of(original: Parts, mut rendered: Response) -> Response {
+    let mut headers = original.headers;
+    headers.remove(header::CONTENT_TYPE);
+    headers.remove(header::CONTENT_LENGTH);
+    for name in rendered.headers().keys() {
+        headers.remove(name);
+    }
+    rendered.headers_mut().extend(headers);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Query, response::IntoResponse, routing::post, Json};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::app::App;
+
+    #[derive(Deserialize)]
+    struct Params {
+        #[allow(dead_code)]
+        page: u32,
+    }
+
+    async fn call(request: Request) -> (StatusCode, String) {
+        let app = App::new()
+            .route(
+                "/items",
+                post(|_: Query<Params>, _: Json<Value>| async { "ok" }),
+            )
+            .route(
+                "/teapot",
+                post(|| async { ApiError::bad_request("No tea").with_code("no_tea") }),
+            )
+            .route(
+                "/marked",
+                post(|_: crate::json::Json<Value>| async { "ok" }),
+            )
+            .on_rejection(|rejection| {
+                let body = format!("{}|{}", rejection.code(), rejection.message());
+                (rejection.status(), body).into_response()
+            });
+        let response = app.build().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_rejections_use_the_handler() {
+        let request = |uri: &str, content_type: &str, body: &'static str| {
+            Request::post(uri)
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let (status, body) = call(request("/items?page=x", "application/json", "{}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body.starts_with("bad_request|Failed to deserialize query"),
+            "{}",
+            body
+        );
+
+        let (status, body) = call(request("/items?page=1", "text/plain", "{}")).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(body.starts_with("unsupported_media_type|"), "{}", body);
+
+        // a handler's own error is not a rejection
+        let (status, body) = call(request("/teapot", "text/plain", "")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains(r#""code":"no_tea""#), "{}", body);
+
+        let (status, body) = call(request("/marked", "application/json", "{")).await;
+        assert!(
+            status == StatusCode::BAD_REQUEST && body.starts_with("malformed_json|"),
+            "{} {}",
+            status,
+            body
+        );
+
+        let (status, body) = call(request("/items?page=1", "application/json", "{}")).await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "ok"));
+    }
+
+    #[tokio::test]
+    async fn test_rejections_keep_the_original_headers() {
+        let app = App::new()
+            .route(
+                "/items",
+                post(|_: Query<Params>| async { "ok" }).layer(axum::middleware::from_fn(
+                    |req: Request, next: Next| async move {
+                        let mut response = next.run(req).await;
+                        let headers = response.headers_mut();
+                        headers.insert("x-ratelimit-remaining", "9".parse().unwrap());
+                        headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
+                        response
+                    },
+                )),
+            )
+            .on_rejection(|rejection| rejection.into_api_error().into_response());
+        let request = Request::post("/items?page=x").body(Body::empty()).unwrap();
+        let response = app.build().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "9");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+
+    #[test]
+    fn test_rejection_into_api_error() {
+        let rejection = Rejection::from(&ApiError::unprocessable("Bad").with_code("invalid"));
+        let error = rejection.into_api_error();
+        assert_eq!(
+            (error.status(), error.code()),
+            (StatusCode::UNPROCESSABLE_ENTITY, "invalid")
+        );
+    }
+}
@@ -0,0 +1,638 @@
+//! Repositories for RustAPI framework
+//!
+//! The `Repository<T, Id>` trait describes the CRUD operations every entity
+//! service ends up writing, with list queries driven by the `Pagination`
+//! extractor and returning a `Page`. `MemoryRepository` backs it with a map
+//! for tests; with the `sqlx` feature, `#[derive(Repository)]` on an entity
+//! generates an sqlx-backed implementation.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{request::Parts, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlx")]
+#[doc(hidden)]
+pub use sqlx;
+use thiserror::Error;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    context,
+    delta::{Delta, DeltaRepository, Since, SyncToken},
+    error::ApiError,
+    lifecycle::BoxFuture,
+};
+
+/// Items per page when the request does not say
+pub const DEFAULT_PER_PAGE: u32 = 20;
+
+/// Largest page a client may ask for
+pub const MAX_PER_PAGE: u32 = 100;
+
+/// Extractor for `?page=` (1-based) and `?per_page=` query parameters
+///
+/// Missing parameters default to the first page of `DEFAULT_PER_PAGE`
+/// items. A page of zero, or more than `MAX_PER_PAGE` items, is rejected with
+/// 400 Bad Request.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/users")]
+/// async fn list_users(
+///     Inject(users): Inject<UserRepository>,
+///     page: Pagination,
+/// ) -> Result<Json<Page<User>>, ApiError> {
+///     Ok(Json(users.list(page).await?))
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pagination {
+    page: u32,
+    per_page: u32,
+}
+
+impl Pagination {
+    /// Page `page` (1-based) of `per_page` items, clamped to valid values
+    pub fn new(page: u32, per_page: u32) -> Self {
+        Self {
+            page: page.max(1),
+            per_page: per_page.clamp(1, MAX_PER_PAGE),
+        }
+    }
+
+    /// Page number, starting at 1
+    pub fn page(&self) -> u32 {
+        self.page
+    }
+
+    /// Items per page
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+    }
+
+    /// Number of items to skip, for `OFFSET`
+    pub fn offset(&self) -> u64 {
+        u64::from(self.page - 1) * u64::from(self.per_page)
+    }
+
+    /// Number of items to return, for `LIMIT`
+    pub fn limit(&self) -> u64 {
+        u64::from(self.per_page)
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self::new(1, DEFAULT_PER_PAGE)
+    }
+}
+
+// query parameters read by the `Pagination` extractor
+#[derive(Deserialize)]
+struct PaginationQuery {
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Pagination {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let invalid = |message: String| {
+            ApiError::new(StatusCode::BAD_REQUEST, message)
+                .with_code("invalid_pagination")
+                .rejection()
+        };
+        let Query(query) = Query::<PaginationQuery>::try_from_uri(&parts.uri)
+            .map_err(|e| invalid(format!("Invalid pagination: {}", e)))?;
+        let page = query.page.unwrap_or(1);
+        let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page == 0 {
+            return Err(invalid("page starts at 1".to_string()));
+        }
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(invalid(format!(
+                "per_page must be between 1 and {}",
+                MAX_PER_PAGE
+            )));
+        }
+        Ok(Self { page, per_page })
+    }
+}
+
+/// One page of a list query
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page<T> {
+    /// Items on this page
+    pub items: Vec<T>,
+    /// Page number, starting at 1
+    pub page: u32,
+    /// Items per page
+    pub per_page: u32,
+    /// Number of items across all pages
+    pub total: u64,
+    /// Number of pages
+    pub total_pages: u64,
+}
+
+impl<T> Page<T> {
+    /// Page of `items` out of `total`, as requested by `pagination`
+    pub fn new(items: Vec<T>, pagination: Pagination, total: u64) -> Self {
+        Self {
+            items,
+            page: pagination.page(),
+            per_page: pagination.per_page(),
+            total,
+            total_pages: total.div_ceil(pagination.limit()),
+        }
+    }
+
+    /// Convert the items, e.g. entities into response DTOs
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            page: self.page,
+            per_page: self.per_page,
+            total: self.total,
+            total_pages: self.total_pages,
+        }
+    }
+}
+
+/// Failure of a repository operation
+#[derive(Error, Debug)]
+pub enum RepositoryError {
+    /// The item conflicts with an existing one, e.g. a duplicate key
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// The storage backend failed
+    #[error("Database error: {0}")]
+    Database(String),
+
+    /// A delta query starts before the oldest change still recorded
+    #[error("Sync token expired")]
+    SyncExpired,
+
+    /// The request deadline passed before the operation finished
+    #[error("Request deadline exceeded")]
+    Timeout,
+}
+
+#[cfg(feature = "sqlx")]
+impl From<sqlx::Error> for RepositoryError {
+    fn from(error: sqlx::Error) -> Self {
+        match error.as_database_error() {
+            Some(db) if db.is_unique_violation() => RepositoryError::Conflict(db.to_string()),
+            _ => RepositoryError::Database(error.to_string()),
+        }
+    }
+}
+
+// 409 for conflicts, 410 for expired sync tokens, 504 past the request
+// deadline; database details stay in the logs
+impl From<RepositoryError> for ApiError {
+    fn from(error: RepositoryError) -> Self {
+        match error {
+            RepositoryError::Conflict(message) => ApiError::conflict(message),
+            RepositoryError::SyncExpired => ApiError::new(
+                StatusCode::GONE,
+                "Changes since this point are no longer recorded; sync from the start",
+            )
+            .with_code("sync_token_expired"),
+            RepositoryError::Timeout => context::deadline_exceeded(),
+            RepositoryError::Database(message) => {
+                tracing::error!("Repository failure: {}", message);
+                ApiError::internal("Database error")
+            }
+        }
+    }
+}
+
+/// Run a repository operation, failing it with `RepositoryError::Timeout`
+/// once the current request's deadline passes
+///
+/// Repositories generated by `#[derive(Repository)]` run every operation
+/// through it; hand-written implementations can do the same.
+pub fn within_deadline<'a, T: 'a>(
+    operation: impl Future<Output = Result<T, RepositoryError>> + Send + 'a,
+) -> BoxFuture<'a, Result<T, RepositoryError>> {
+    Box::pin(async move {
+        context::within_deadline(operation)
+            .await
+            .unwrap_or(Err(RepositoryError::Timeout))
+    })
+}
+
+/// CRUD operations on entities of type `T` identified by `Id`
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Clone, Serialize, sqlx::FromRow, Repository)]
+/// #[repository(table = "users")]
+/// pub struct User {
+///     #[repository(id, generated)]
+///     pub id: i64,
+///     pub name: String,
+/// }
+///
+/// // generated: `UserRepository`, implementing `Repository<User, i64>`
+/// let users = UserRepository::new(pool);
+/// let page = users.list(Pagination::new(1, 20)).await?;
+/// ```
+pub trait Repository<T, Id>: Send + Sync + 'static {
+    /// The item with id `id`, if any
+    fn find(&self, id: Id) -> BoxFuture<'_, Result<Option<T>, RepositoryError>>;
+
+    /// One page of items, ordered by id
+    fn list(&self, pagination: Pagination) -> BoxFuture<'_, Result<Page<T>, RepositoryError>>;
+
+    /// Store a new item, returning it as stored (with generated fields set)
+    fn create(&self, item: T) -> BoxFuture<'_, Result<T, RepositoryError>>;
+
+    /// Replace the item with id `id`, `None` if there is none
+    fn update(&self, id: Id, item: T) -> BoxFuture<'_, Result<Option<T>, RepositoryError>>;
+
+    /// Delete the item with id `id`, returning whether it existed
+    fn delete(&self, id: Id) -> BoxFuture<'_, Result<bool, RepositoryError>>;
+}
+
+/// In-memory `Repository` for tests and prototypes
+///
+/// Items are keyed by the id `key` extracts from them; `create` fails with a
+/// conflict if the id is taken, and `update` stores the item under `id`
+/// whatever id it carries. Clones share the same items.
+///
+/// Every change is numbered and deleted items leave a tombstone, so it also
+/// implements `DeltaRepository`; `purge_tombstones` forgets them. Changes
+/// are timestamped by the repository's `Clock`, never going back in time,
+/// so `Since::Time` queries do not miss changes when the wall clock steps
+/// back.
+///
+/// # Example
+///
+/// ```ignore
+/// let users = MemoryRepository::new(|user: &User| user.id);
+/// users.create(User { id: 1, name: "ada".into() }).await?;
+/// ```
+pub struct MemoryRepository<T, Id> {
+    store: Arc<Mutex<Store<T, Id>>>,
+    key: Arc<dyn Fn(&T) -> Id + Send + Sync>,
+    clock: Arc<dyn Clock>,
+}
+
+// items and tombstones by id, with the number and time of the last change
+struct Store<T, Id> {
+    entries: BTreeMap<Id, Entry<T>>,
+    version: u64,
+    changed_at: SystemTime,
+    purged: Option<(u64, SystemTime)>,
+}
+
+// an item, or the tombstone of a deleted one, as of change `version`
+struct Entry<T> {
+    item: Option<T>,
+    version: u64,
+    changed_at: SystemTime,
+}
+
+impl<T, Id: Ord> Store<T, Id> {
+    fn live(&self) -> impl Iterator<Item = (&Id, &T)> {
+        self.entries
+            .iter()
+            .filter_map(|(id, entry)| entry.item.as_ref().map(|item| (id, item)))
+    }
+
+    // record a change of `id` at `now`, or at the last change if the clock
+    // went back since
+    fn set(&mut self, id: Id, item: Option<T>, now: SystemTime) {
+        self.version += 1;
+        self.changed_at = self.changed_at.max(now);
+        let entry = Entry {
+            item,
+            version: self.version,
+            changed_at: self.changed_at,
+        };
+        self.entries.insert(id, entry);
+    }
+}
+
+impl<T, Id: Ord> MemoryRepository<T, Id> {
+    /// Create an empty repository identifying items with `key`
+    pub fn new(key: impl Fn(&T) -> Id + Send + Sync + 'static) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(Store {
+                entries: BTreeMap::new(),
+                version: 0,
+                changed_at: SystemTime::UNIX_EPOCH,
+                purged: None,
+            })),
+            key: Arc::new(key),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Timestamp changes by `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Number of stored items
+    pub fn len(&self) -> usize {
+        self.store().live().count()
+    }
+
+    /// Check whether the repository is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget the tombstones of deleted items
+    ///
+    /// Delta queries starting before now then fail with
+    /// `RepositoryError::SyncExpired`.
+    pub fn purge_tombstones(&self) {
+        let now = self.clock.system_time();
+        let mut store = self.store();
+        store.entries.retain(|_, entry| entry.item.is_some());
+        let purged_at = store.changed_at.max(now);
+        store.purged = Some((store.version, purged_at));
+    }
+
+    // lock the store
+    fn store(&self) -> std::sync::MutexGuard<'_, Store<T, Id>> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T, Id> Clone for MemoryRepository<T, Id> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            key: self.key.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl<T, Id> Repository<T, Id> for MemoryRepository<T, Id>
+where
+    T: Clone + Send + Sync + 'static,
+    Id: Ord + Send + Sync + 'static,
+{
+    fn find(&self, id: Id) -> BoxFuture<'_, Result<Option<T>, RepositoryError>> {
+        let item = self
+            .store()
+            .entries
+            .get(&id)
+            .and_then(|entry| entry.item.clone());
+        Box::pin(async move { Ok(item) })
+    }
+
+    fn list(&self, pagination: Pagination) -> BoxFuture<'_, Result<Page<T>, RepositoryError>> {
+        let store = self.store();
+        let page: Vec<T> = store
+            .live()
+            .skip(pagination.offset() as usize)
+            .take(pagination.limit() as usize)
+            .map(|(_, item)| item.clone())
+            .collect();
+        let page = Page::new(page, pagination, store.live().count() as u64);
+        Box::pin(async move { Ok(page) })
+    }
+
+    fn create(&self, item: T) -> BoxFuture<'_, Result<T, RepositoryError>> {
+        let id = (self.key)(&item);
+        let mut store = self.store();
+        let taken = store
+            .entries
+            .get(&id)
+            .is_some_and(|entry| entry.item.is_some());
+        let result = match taken {
+            true => Err(RepositoryError::Conflict("id already exists".to_string())),
+            false => {
+                store.set(id, Some(item.clone()), self.clock.system_time());
+                Ok(item)
+            }
+        };
+        Box::pin(async move { result })
+    }
+
+    fn update(&self, id: Id, item: T) -> BoxFuture<'_, Result<Option<T>, RepositoryError>> {
+        let mut store = self.store();
+        let exists = store
+            .entries
+            .get(&id)
+            .is_some_and(|entry| entry.item.is_some());
+        let updated = exists.then(|| {
+            store.set(id, Some(item.clone()), self.clock.system_time());
+            item
+        });
+        Box::pin(async move { Ok(updated) })
+    }
+
+    fn delete(&self, id: Id) -> BoxFuture<'_, Result<bool, RepositoryError>> {
+        let mut store = self.store();
+        let existed = store
+            .entries
+            .get(&id)
+            .is_some_and(|entry| entry.item.is_some());
+        if existed {
+            store.set(id, None, self.clock.system_time());
+        }
+        Box::pin(async move { Ok(existed) })
+    }
+}
+
+impl<T, Id> DeltaRepository<T, Id> for MemoryRepository<T, Id>
+where
+    T: Clone + Send + Sync + 'static,
+    Id: Ord + Clone + Send + Sync + 'static,
+{
+    fn changes(
+        &self,
+        since: Since,
+        limit: u32,
+    ) -> BoxFuture<'_, Result<Delta<T, Id>, RepositoryError>> {
+        let store = self.store();
+        let expired = match (since, store.purged) {
+            (Since::Start, _) | (_, None) => false,
+            (Since::Token(token), Some((version, _))) => token.version() < version,
+            (Since::Time(time), Some((_, purged_at))) => time < purged_at,
+        };
+        let result = if expired {
+            Err(RepositoryError::SyncExpired)
+        } else {
+            let mut changed: Vec<(&Id, &Entry<T>)> = store
+                .entries
+                .iter()
+                .filter(|(_, entry)| match since {
+                    // a fresh sync needs no tombstones
+                    Since::Start => entry.item.is_some(),
+                    Since::Token(token) => entry.version > token.version(),
+                    Since::Time(time) => entry.changed_at >= time,
+                })
+                .collect();
+            changed.sort_by_key(|(_, entry)| entry.version);
+            let has_more = changed.len() > limit as usize;
+            changed.truncate(limit as usize);
+            let version = match (has_more, changed.last()) {
+                (true, Some((_, entry))) => entry.version,
+                _ => store.version,
+            };
+            let mut delta = Delta::new(SyncToken::new(version));
+            for (id, entry) in changed {
+                match &entry.item {
+                    Some(item) => delta.items.push(item.clone()),
+                    None => delta.deleted.push(id.clone()),
+                }
+            }
+            Ok(delta.has_more(has_more))
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn extract(uri: &str) -> Result<Pagination, ApiError> {
+        let (mut parts, _) = Request::get(uri).body(()).unwrap().into_parts();
+        Pagination::from_request_parts(&mut parts, &()).await
+    }
+
+    #[tokio::test]
+    async fn test_pagination_extractor() {
+        assert_eq!(extract("/users").await.unwrap(), Pagination::default());
+        let page = extract("/users?page=3&per_page=10").await.unwrap();
+        assert_eq!((page.offset(), page.limit()), (20, 10));
+        for uri in ["/users?page=0", "/users?per_page=500", "/users?page=x"] {
+            let err = extract(uri).await.unwrap_err();
+            assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(err.code(), "invalid_pagination");
+        }
+        assert_eq!(Pagination::new(0, 1000), Pagination::new(1, MAX_PER_PAGE));
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct User {
+        id: u32,
+        name: &'static str,
+    }
+
+    #[tokio::test]
+    async fn test_memory_repository() {
+        let users = MemoryRepository::new(|user: &User| user.id);
+        for (id, name) in [(3, "c"), (1, "a"), (2, "b")] {
+            users.create(User { id, name }).await.unwrap();
+        }
+        assert!(matches!(
+            users.create(User { id: 1, name: "dup" }).await,
+            Err(RepositoryError::Conflict(_))
+        ));
+
+        let page = users.list(Pagination::new(1, 2)).await.unwrap();
+        assert_eq!(page.items.iter().map(|u| u.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!((page.total, page.total_pages), (3, 2));
+        let names = users
+            .list(Pagination::new(2, 2))
+            .await
+            .unwrap()
+            .map(|u| u.name);
+        assert_eq!(names.items, ["c"]);
+
+        let renamed = User { id: 2, name: "bee" };
+        assert_eq!(
+            users.update(2, renamed.clone()).await.unwrap(),
+            Some(renamed)
+        );
+        assert_eq!(
+            users.update(9, User { id: 9, name: "x" }).await.unwrap(),
+            None
+        );
+        assert!(users.delete(1).await.unwrap());
+        assert!(!users.delete(1).await.unwrap());
+        assert_eq!(users.find(2).await.unwrap().unwrap().name, "bee");
+        assert_eq!(users.len(), 2);
+    }
+
+    #[test]
+    fn test_errors_map_to_api_errors() {
+        let conflict = ApiError::from(RepositoryError::Conflict("taken".to_string()));
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+        let database = ApiError::from(RepositoryError::Database("secret dsn".to_string()));
+        assert_eq!(database.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!database.message().contains("secret"));
+    }
+
+    #[cfg(feature = "sqlx-sqlite")]
+    mod sqlite {
+        use super::*;
+
+        #[derive(Debug, Clone, PartialEq, sqlx::FromRow, crate::Repository)]
+        #[repository(table = "users", backend = "sqlite")]
+        struct User {
+            #[repository(id, generated)]
+            id: i64,
+            name: String,
+        }
+
+        #[tokio::test]
+        async fn test_derived_repository() {
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .max_connections(1)
+                .connect("sqlite::memory:")
+                .await
+                .unwrap();
+            sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT UNIQUE NOT NULL)")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let users = UserRepository::new(pool);
+
+            for name in ["ada", "grace", "alan"] {
+                let user = User {
+                    id: 0,
+                    name: name.to_string(),
+                };
+                assert!(users.create(user).await.unwrap().id > 0);
+            }
+            let duplicate = User {
+                id: 0,
+                name: "ada".to_string(),
+            };
+            assert!(matches!(
+                users.create(duplicate).await,
+                Err(RepositoryError::Conflict(_))
+            ));
+
+            let page = users.list(Pagination::new(2, 2)).await.unwrap();
+            assert_eq!(page.items[0].name, "alan");
+            assert_eq!((page.total, page.total_pages), (3, 2));
+
+            let renamed = User {
+                id: 0,
+                name: "hopper".to_string(),
+            };
+            let updated = users.update(2, renamed).await.unwrap().unwrap();
+            assert_eq!((updated.id, updated.name.as_str()), (2, "hopper"));
+            assert!(users.delete(1).await.unwrap());
+            assert_eq!(users.find(1).await.unwrap(), None);
+            assert!(!users.delete(1).await.unwrap());
+        }
+    }
+}
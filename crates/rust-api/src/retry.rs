@@ -0,0 +1,322 @@
+//! Retries for RustAPI framework
+//!
+//! `#[retry]` re-runs an async service method that failed with one of the
+//! transient errors it lists, following a `RetryPolicy`; `run` is what it
+//! expands to and can be called directly. Delays are jittered, so callers
+//! that failed together don't retry together, and never run past the
+//! current request's deadline: when the next attempt would start too late,
+//! the last error is returned instead.
+//!
+//! With `RetryPolicy::metrics`, every retry increments
+//! `retry_attempts_total{operation}` and every call that fails after its
+//! last attempt `retry_exhausted_total{operation}`.
+
+use std::{
+    future::Future,
+    hash::{BuildHasher, Hasher, RandomState},
+    time::Duration,
+};
+
+use crate::{context::RequestContext, metrics::Metrics};
+
+/// Default delay before the first retry
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest delay between two attempts
+pub const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// How the delay grows between attempts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay before every retry
+    Fixed,
+    /// The delay doubles after every retry
+    #[default]
+    Exponential,
+}
+
+/// How often and how patiently to retry
+#[derive(Clone)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Backoff,
+    delay: Duration,
+    jitter: bool,
+    metrics: Option<Metrics>,
+}
+
+impl RetryPolicy {
+    /// Make at most `attempts` attempts, including the first (at least 1)
+    pub const fn new(attempts: u32) -> Self {
+        Self {
+            attempts: if attempts == 0 { 1 } else { attempts },
+            backoff: Backoff::Exponential,
+            delay: DEFAULT_DELAY,
+            jitter: true,
+            metrics: None,
+        }
+    }
+
+    /// Grow the delay with `backoff` (default: exponential)
+    pub const fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Wait `delay` before the first retry (default: 100ms)
+    pub const fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Wait a random delay between half and all of the backoff delay
+    /// (default: true)
+    pub const fn jitter(mut self, enabled: bool) -> Self {
+        self.jitter = enabled;
+        self
+    }
+
+    /// Add the `retry_attempts_total` and `retry_exhausted_total` counters
+    /// to `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Most attempts made
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Delay before retry number `retry`, counting from 1, before jitter
+    pub fn delay_for(&self, retry: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Fixed => self.delay,
+            Backoff::Exponential => self
+                .delay
+                .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1))),
+        };
+        delay.min(MAX_DELAY)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl RetryPolicy {
+    // the delay before retry number `retry`, jittered
+    fn wait_for(&self, retry: u32) -> Duration {
+        let delay = self.delay_for(retry);
+        match self.jitter {
+            true => delay.mul_f64(0.5 + random() / 2.0),
+            false => delay,
+        }
+    }
+
+    // increment a retry counter, if metrics are set
+    fn count(&self, name: &str, operation: &str) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(name, &[("operation", operation)]);
+        }
+    }
+}
+
+// a random fraction in [0, 1), from std's randomly keyed hasher
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Run `attempt` until it succeeds, fails with an error `retryable`
+/// rejects, or `policy` runs out of attempts
+///
+/// `operation` names the call in logs and metrics. Only errors `retryable`
+/// accepts are retried, so list the transient ones: retrying a write that
+/// failed after reaching the remote side can apply it twice.
+///
+/// # Example
+///
+/// ```ignore
+/// let user = retry::run(
+///     "users.find",
+///     RetryPolicy::new(3).backoff(Backoff::Fixed),
+///     || repo.find(id),
+///     |e| matches!(e, sqlx::Error::PoolTimedOut),
+/// )
+/// .await?;
+/// ```
+pub async fn run<T, E, Fut>(
+    operation: &str,
+    policy: RetryPolicy,
+    mut attempt: impl FnMut() -> Fut,
+    retryable: impl Fn(&E) -> bool,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut retry = 0;
+    loop {
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        retry += 1;
+        if !retryable(&error) {
+            return Err(error);
+        }
+        let delay = policy.wait_for(retry);
+        let remaining = RequestContext::with_current(RequestContext::remaining).flatten();
+        if retry >= policy.attempts || remaining.is_some_and(|left| left <= delay) {
+            policy.count("retry_exhausted_total", operation);
+            return Err(error);
+        }
+        tracing::warn!(
+            "Retrying {} in {:?} (attempt {} of {}): {}",
+            operation,
+            delay,
+            retry + 1,
+            policy.attempts,
+            error
+        );
+        policy.count("retry_attempts_total", operation);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_delay_for() {
+        let policy = RetryPolicy::new(5).delay(Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(400));
+        assert_eq!(policy.delay_for(30), MAX_DELAY);
+        let fixed = policy.backoff(Backoff::Fixed);
+        assert_eq!(fixed.delay_for(3), Duration::from_millis(100));
+        assert_eq!(RetryPolicy::new(0).attempts(), 1);
+    }
+
+    #[test]
+    fn test_delays_are_jittered() {
+        let policy = RetryPolicy::new(5).delay(Duration::from_millis(100));
+        let delays: Vec<_> = (0..20).map(|_| policy.wait_for(2)).collect();
+        assert!(delays.iter().all(|delay| (Duration::from_millis(100)
+            ..=Duration::from_millis(200))
+            .contains(delay)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert_eq!(policy.jitter(false).wait_for(2), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_retries_retryable_errors() {
+        let metrics = Metrics::new();
+        let calls = AtomicU32::new(0);
+        let policy = RetryPolicy::new(3).metrics(metrics.clone());
+
+        let attempt = || async {
+            match calls.fetch_add(1, Ordering::Relaxed) {
+                0 => Err("busy".to_string()),
+                n => Ok(n),
+            }
+        };
+        let result = run("flaky", policy.clone(), attempt, |e| e == "busy").await;
+        assert_eq!(result, Ok(1));
+
+        let result: Result<(), String> = run(
+            "down",
+            policy.clone(),
+            || async { Err("down".to_string()) },
+            |_| true,
+        )
+        .await;
+        assert_eq!(result, Err("down".to_string()));
+        assert_eq!(
+            metrics.counter("retry_attempts_total", &[("operation", "down")]),
+            2
+        );
+        assert_eq!(
+            metrics.counter("retry_exhausted_total", &[("operation", "down")]),
+            1
+        );
+
+        calls.store(0, Ordering::Relaxed);
+        let attempt = || async {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Err::<(), _>("fatal".to_string())
+        };
+        let result = run("fatal", policy, attempt, |e| e == "busy").await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum StoreError {
+        Busy,
+        Missing(String),
+    }
+
+    impl std::fmt::Display for StoreError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    struct Store {
+        calls: AtomicU32,
+        metrics: Metrics,
+    }
+
+    impl crate::di::Injectable for Store {}
+
+    impl Store {
+        #[crate::retry(
+            attempts = 3,
+            backoff = "fixed",
+            delay_ms = 10,
+            on = [StoreError::Busy],
+            name = "store.find",
+            metrics = self.metrics
+        )]
+        async fn find(&self, key: String) -> Result<String, StoreError> {
+            if self.calls.fetch_add(1, Ordering::Relaxed) < 2 {
+                return Err(StoreError::Busy);
+            }
+            if key.is_empty() {
+                return Err(StoreError::Missing(key));
+            }
+            Ok(key)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_macro_on_injected_service() {
+        let mut container = crate::di::Container::new();
+        container.register(std::sync::Arc::new(Store {
+            calls: AtomicU32::new(0),
+            metrics: Metrics::new(),
+        }));
+        let store: std::sync::Arc<Store> = container.resolve().unwrap();
+
+        assert_eq!(store.find("a".to_string()).await, Ok("a".to_string()));
+        assert_eq!(store.calls.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            store.find(String::new()).await,
+            Err(StoreError::Missing(String::new()))
+        );
+        assert_eq!(store.calls.load(Ordering::Relaxed), 4);
+        assert_eq!(
+            store
+                .metrics
+                .counter("retry_attempts_total", &[("operation", "store.find")]),
+            2
+        );
+    }
+}
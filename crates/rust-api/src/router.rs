@@ -4,6 +4,35 @@
 //! types. Users interact through the router module rather than importing Router
 //! directly.
 
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tower::ServiceExt;
+
+use crate::error::ApiError;
+
+/// Characters escaped when substituting a value into a path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Characters escaped in a catch-all value, which may span several segments
+const CATCH_ALL: &AsciiSet = &SEGMENT.remove(b'/');
+
 /// Re-export Axum's Router type
 ///
 /// Note: In Axum's type system, `Router<S>` means a router that "needs" state
@@ -53,10 +82,179 @@ impl<S> RouterExt<S> for Router<S> {
     }
 }
 
+/// Build a URL from a route template by substituting its parameters
+///
+/// Templates are the path constants generated by the route macros (e.g.
+/// `__get_user_route`), so links follow the route definitions instead of
+/// being assembled by string concatenation. Values are percent-encoded; a
+/// catch-all `{*name}` value may contain slashes.
+///
+/// # Example
+///
+/// ```ignore
+/// let url = url_for(__get_user_route, &[("id", "42")])?; // "/users/42"
+/// ```
+pub fn url_for(template: &str, params: &[(&str, &str)]) -> Result<String, ApiError> {
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        // escaped braces are literal characters
+        if rest[open + 1..].starts_with('{') {
+            url.push_str(&rest[..=open].replace("}}", "}"));
+            rest = &rest[open + 2..];
+            continue;
+        }
+
+        let close = open
+            + rest[open..].find('}').ok_or_else(|| {
+                ApiError::internal(format!("Invalid route template: {}", template))
+            })?;
+        let name = &rest[open + 1..close];
+        let (name, set) = match name.strip_prefix('*') {
+            Some(name) => (name, CATCH_ALL),
+            None => (name, SEGMENT),
+        };
+        let value = params
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                ApiError::internal(format!(
+                    "Missing parameter `{}` for route {}",
+                    name, template
+                ))
+            })?;
+
+        url.push_str(&rest[..open].replace("}}", "}"));
+        url.extend(utf8_percent_encode(value, set));
+        rest = &rest[close + 1..];
+    }
+
+    url.push_str(&rest.replace("}}", "}"));
+    Ok(url)
+}
+
+/// How requests whose path ends in a trailing slash are handled
+///
+/// The canonical form of a path has no trailing slash, so routes should be
+/// declared without one (`/users`, not `/users/`). The policy only applies
+/// to requests that did not match any route as written, and whose path
+/// matches one without the slash; the others reach the router's own
+/// fallback unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// Exact matching only: `/users/` does not match `/users` (default)
+    #[default]
+    Strict,
+    /// Respond with 308 Permanent Redirect to the path without the slash
+    Redirect,
+    /// Route the request as if it had been sent without the slash
+    Rewrite,
+}
+
+/// Apply a trailing slash policy to a router
+///
+/// `App::trailing_slash` does this automatically; use this function when
+/// building a raw router for `RustAPI::new`.
+///
+/// # Example
+///
+/// ```ignore
+/// let app = router::normalize_trailing_slash(app, TrailingSlash::Redirect);
+/// ```
+pub fn normalize_trailing_slash(router: Router, policy: TrailingSlash) -> Router {
+    if policy == TrailingSlash::Strict {
+        return router;
+    }
+
+    // unmatched requests land here; the original router keeps its fallback
+    let inner = router.clone();
+    // tells whether a route matches a request, without running it
+    let probe = router.has_routes().then(|| {
+        router
+            .clone()
+            .route_layer(middleware::from_fn(|_: Request, _: Next| async {
+                let mut response = StatusCode::NO_CONTENT.into_response();
+                response.extensions_mut().insert(Matched);
+                response
+            }))
+            .fallback(|| async { StatusCode::NOT_FOUND })
+    });
+    router.fallback_service(tower::service_fn(move |req: Request| {
+        let inner = inner.clone();
+        let probe = probe.clone();
+        async move {
+            let Some(trimmed) = trim_trailing_slash(req.uri()) else {
+                return inner.oneshot(req).await;
+            };
+            let Some(probe) = probe else {
+                return inner.oneshot(req).await;
+            };
+            let mut candidate = Request::new(Body::empty());
+            *candidate.method_mut() = req.method().clone();
+            *candidate.uri_mut() = trimmed.clone();
+            *candidate.headers_mut() = req.headers().clone();
+            let matched = probe.oneshot(candidate).await?;
+            if matched.extensions().get::<Matched>().is_none() {
+                return inner.oneshot(req).await;
+            }
+            match policy {
+                TrailingSlash::Redirect => Ok(redirect_to(&trimmed)),
+                _ => {
+                    let (mut parts, body) = req.into_parts();
+                    parts.uri = trimmed;
+                    inner.oneshot(Request::from_parts(parts, body)).await
+                }
+            }
+        }
+    }))
+}
+
+// marks a probe response for a request some route matched
+#[derive(Clone)]
+struct Matched;
+
+// strip trailing slashes from the path, keeping the query string
+fn trim_trailing_slash(uri: &Uri) -> Option<Uri> {
+    let path = uri.path();
+    if path.len() <= 1 || !path.ends_with('/') {
+        return None;
+    }
+
+    let trimmed = path.trim_end_matches('/');
+    let trimmed = if trimmed.is_empty() { "/" } else { trimmed };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", trimmed, query),
+        None => trimmed.to_string(),
+    };
+    path_and_query.parse().ok()
+}
+
+// permanent redirect that preserves the request method
+fn redirect_to(uri: &Uri) -> Response {
+    (
+        StatusCode::PERMANENT_REDIRECT,
+        [(header::LOCATION, uri.to_string())],
+    )
+        .into_response()
+}
+
 #[cfg(test)]
 mod tests {
+    use axum::{body::Body, routing::get};
+
     use super::*;
 
+    fn users_router(policy: TrailingSlash) -> Router {
+        normalize_trailing_slash(build().route("/users", get(|| async { "users" })), policy)
+    }
+
+    async fn send(router: Router, path: &str) -> Response {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        router.oneshot(request).await.unwrap()
+    }
+
     #[test]
     fn test_router_creation() {
         let _router = build();
@@ -66,4 +264,87 @@ mod tests {
     fn test_router_finish() {
         let _router = build().finish();
     }
+
+    #[test]
+    fn test_url_for() {
+        assert_eq!(
+            url_for("/users/{id}", &[("id", "42")]).unwrap(),
+            "/users/42"
+        );
+        assert_eq!(
+            url_for(
+                "/users/{id}/posts/{post}",
+                &[("id", "a b"), ("post", "x/y")]
+            )
+            .unwrap(),
+            "/users/a%20b/posts/x%2Fy"
+        );
+        assert_eq!(
+            url_for("/files/{*path}", &[("path", "docs/guide.md")]).unwrap(),
+            "/files/docs/guide.md"
+        );
+        assert!(url_for("/users/{id}", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_strict() {
+        let response = send(users_router(TrailingSlash::Strict), "/users/").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_redirect() {
+        let response = send(users_router(TrailingSlash::Redirect), "/users/?page=2").await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/users?page=2");
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_rewrite() {
+        let router = users_router(TrailingSlash::Rewrite);
+        assert_eq!(
+            send(router.clone(), "/users/").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(router.clone(), "/users").await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(router, "/missing/").await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_without_routes() {
+        let router = build().fallback(|| async { "fallback" });
+        let response = send(
+            normalize_trailing_slash(router, TrailingSlash::Rewrite),
+            "/a/",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_keeps_user_fallback() {
+        let router = build()
+            .route("/users", get(|| async { "users" }))
+            .nest("/api", build().route("/orders", get(|| async { "orders" })))
+            .fallback(|uri: Uri| async move { format!("spa {}", uri) });
+        let router = normalize_trailing_slash(router, TrailingSlash::Redirect);
+
+        let response = send(router.clone(), "/api/orders/").await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[header::LOCATION], "/api/orders");
+        for path in ["/missing/", "/missing"] {
+            let response = send(router.clone(), path).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, format!("spa {}", path));
+        }
+    }
 }
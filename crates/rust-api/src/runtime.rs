@@ -0,0 +1,199 @@
+//! Tokio runtime configuration for RustAPI framework
+//!
+//! Provides `RuntimeConfig`, used by `RustAPI::runtime` and the
+//! `#[rust_api::main]` entrypoint to tune the Tokio runtime without writing
+//! runtime setup code by hand.
+//!
+//! With the `console` feature, the runtime can also be inspected with
+//! [tokio-console](https://github.com/tokio-rs/console): enable it with
+//! `RuntimeConfig::console`, `#[rust_api::main(console = true)]` or the
+//! `TOKIO_CONSOLE` environment variable, and build with
+//! `RUSTFLAGS="--cfg tokio_unstable"` so Tokio emits task instrumentation.
+//! The console then listens on `TOKIO_CONSOLE_BIND` (default
+//! `127.0.0.1:6669`) once the framework installs its log subscriber, which
+//! it does when the app has a log format or profile.
+
+#[cfg(feature = "console")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{Error, Result};
+
+// set once a runtime was built with the console enabled
+#[cfg(feature = "console")]
+static CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Builder for the multi-threaded Tokio runtime that runs the server
+///
+/// Every setting is optional; unset values keep Tokio's defaults.
+///
+/// # Example
+///
+/// ```ignore
+/// RustAPI::new(app)
+///     .runtime(|rt| rt.worker_threads(4).thread_name("api-worker"))
+///     .run()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeConfig {
+    worker_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    thread_name: Option<String>,
+    thread_stack_size: Option<usize>,
+    console: bool,
+}
+
+impl RuntimeConfig {
+    /// Create a runtime configuration using Tokio's defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of async worker threads (default: number of CPU cores)
+    pub fn worker_threads(mut self, count: usize) -> Self {
+        self.worker_threads = Some(count);
+        self
+    }
+
+    /// Set the maximum size of the blocking thread pool (default: 512)
+    pub fn max_blocking_threads(mut self, count: usize) -> Self {
+        self.max_blocking_threads = Some(count);
+        self
+    }
+
+    /// Set the name given to runtime threads (default: "tokio-runtime-worker")
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    /// Set the stack size in bytes for runtime threads
+    pub fn thread_stack_size(mut self, bytes: usize) -> Self {
+        self.thread_stack_size = Some(bytes);
+        self
+    }
+
+    /// Enable tokio-console instrumentation (requires the `console` feature)
+    ///
+    /// `TOKIO_CONSOLE=1` or `TOKIO_CONSOLE=0` in the environment overrides
+    /// this, so staging can turn the console on without a code change.
+    /// Building the runtime fails if the console is enabled in code but the
+    /// feature is off.
+    pub fn console(mut self, enabled: bool) -> Self {
+        self.console = enabled;
+        self
+    }
+
+    /// Build the configured runtime
+    pub fn build(&self) -> Result<tokio::runtime::Runtime> {
+        #[cfg(feature = "console")]
+        if console_requested(self.console, std::env::var("TOKIO_CONSOLE").ok().as_deref()) {
+            CONSOLE.store(true, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "console"))]
+        if self.console {
+            return Err(Error::server_error(
+                "tokio-console was enabled, but the `console` feature is off",
+            ));
+        }
+
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all();
+        self.apply_to(&mut builder);
+
+        builder
+            .build()
+            .map_err(|e| Error::server_error(format!("Failed to build Tokio runtime: {}", e)))
+    }
+
+    // copy the configured values onto a Tokio runtime builder
+    fn apply_to(&self, builder: &mut tokio::runtime::Builder) {
+        if let Some(count) = self.worker_threads {
+            builder.worker_threads(count);
+        }
+        if let Some(count) = self.max_blocking_threads {
+            builder.max_blocking_threads(count);
+        }
+        if let Some(name) = &self.thread_name {
+            builder.thread_name(name.clone());
+        }
+        if let Some(bytes) = self.thread_stack_size {
+            builder.thread_stack_size(bytes);
+        }
+    }
+}
+
+// whether the console is on, given the configured flag and `TOKIO_CONSOLE`
+#[cfg(feature = "console")]
+fn console_requested(configured: bool, env: Option<&str>) -> bool {
+    match env.map(str::trim) {
+        Some("1" | "true" | "on") => true,
+        Some("0" | "false" | "off") => false,
+        _ => configured,
+    }
+}
+
+// whether a runtime was built with the console enabled
+#[cfg(feature = "console")]
+pub(crate) fn console_enabled() -> bool {
+    CONSOLE.load(Ordering::Relaxed)
+}
+
+// log where the console listens, once the log subscriber is installed
+#[cfg(feature = "console")]
+pub(crate) fn announce_console() {
+    if !console_enabled() {
+        return;
+    }
+    let bind = std::env::var("TOKIO_CONSOLE_BIND").unwrap_or_else(|_| "127.0.0.1:6669".into());
+    tracing::info!("Tokio console listening on {}", bind);
+    if !cfg!(tokio_unstable) {
+        tracing::warn!(
+            "Tokio console enabled without `--cfg tokio_unstable`; no tasks will be shown"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_config_defaults() {
+        let config = RuntimeConfig::new();
+        assert_eq!(config.worker_threads, None);
+        assert_eq!(config.max_blocking_threads, None);
+        assert_eq!(config.thread_name, None);
+    }
+
+    #[test]
+    fn test_runtime_config_build() {
+        let runtime = RuntimeConfig::new()
+            .worker_threads(2)
+            .max_blocking_threads(4)
+            .thread_name("test-worker")
+            .build()
+            .unwrap();
+
+        let name = runtime.block_on(async {
+            tokio::spawn(async { std::thread::current().name().map(str::to_string) })
+                .await
+                .unwrap()
+        });
+        assert_eq!(name.as_deref(), Some("test-worker"));
+    }
+
+    #[cfg(not(feature = "console"))]
+    #[test]
+    fn test_console_requires_feature() {
+        assert!(RuntimeConfig::new().console(true).build().is_err());
+    }
+
+    #[cfg(feature = "console")]
+    #[test]
+    fn test_console_env_override() {
+        assert!(console_requested(true, None));
+        assert!(!console_requested(true, Some("0")));
+        assert!(console_requested(false, Some("1")));
+        assert!(!console_requested(false, Some("")));
+    }
+}
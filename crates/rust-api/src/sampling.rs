@@ -0,0 +1,487 @@
+//! Request trace sampling for RustAPI framework
+//!
+//! Every request runs in a `request` span (method, route, request id, trace
+//! id, status, latency) that a tracing exporter such as
+//! `tracing-opentelemetry` turns into the root span of the request's trace.
+//! Exporting every trace of a high-traffic service can overwhelm the
+//! collector, so with `App::trace_requests` a `Sampler` decides up front,
+//! when the request arrives, whether its trace is kept: by ratio, per
+//! route, and following the caller's decision in the W3C `traceparent`
+//! header. Like
+//! OpenTelemetry's ratio sampler, the decision is read from the low 64 bits
+//! of the trace id, so every service sampling at the same ratio keeps the
+//! same traces.
+//!
+//! The decision is stored in the `RequestContext`. Spans and events of
+//! unsampled requests are still created, so logs are unaffected; wrapping
+//! the exporter layer in `sampling::layer` keeps them from it. A request
+//! that was not sampled but fails with a 5xx still has its `request` span
+//! exported, with its full duration and the error recorded on it, so errors
+//! are always captured; its child spans and events are not.
+//!
+//! # Example
+//!
+//! ```ignore
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(sampling::layer(tracing_opentelemetry::layer().with_tracer(tracer)))
+//!     .init();
+//!
+//! let app = App::new().trace_requests(
+//!     Sampler::new(0.05)
+//!         .route("/health", 0.0)
+//!         .route("/checkout", 1.0),
+//! );
+//! ```
+
+use std::{any::TypeId, cell::Cell, collections::HashMap};
+
+use axum::{http::HeaderMap, response::Response};
+use tokio::time::Instant;
+use tracing::{field, span, subscriber::Interest, Event, Metadata, Span, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{context::RequestContext, metrics};
+
+thread_local! {
+    // set while creating the `request` span of an unsampled request that is
+    // exported if it fails
+    static FORCED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Head-based sampling policy for request traces
+///
+/// A request's trace is sampled when the caller sampled it (with
+/// `parent_based`, the default), otherwise with the probability configured
+/// for its route, falling back to the default ratio. The decision is
+/// derived from the low 64 bits of the trace id, as OpenTelemetry's
+/// `TraceIdRatioBased` sampler does, or from a stable hash of the request id
+/// without one, so it is the same for a given trace in every service.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    ratio: f64,
+    routes: HashMap<String, f64>,
+    parent_based: bool,
+    always_on_error: bool,
+}
+
+impl Sampler {
+    /// Sample the given fraction of requests, from 0.0 (none) to 1.0 (all)
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio: ratio.clamp(0.0, 1.0),
+            routes: HashMap::new(),
+            parent_based: true,
+            always_on_error: true,
+        }
+    }
+
+    /// Sample every request
+    pub fn always() -> Self {
+        Self::new(1.0)
+    }
+
+    /// Sample the given fraction of requests to `route` instead
+    ///
+    /// `route` is the route template, e.g. `/users/{id}`.
+    pub fn route(mut self, route: impl Into<String>, ratio: f64) -> Self {
+        self.routes.insert(route.into(), ratio.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Follow the sampled flag of an incoming `traceparent` (default: true)
+    pub fn parent_based(mut self, enabled: bool) -> Self {
+        self.parent_based = enabled;
+        self
+    }
+
+    /// Export the `request` span of unsampled requests that fail with a 5xx
+    /// (default: true)
+    pub fn always_on_error(mut self, enabled: bool) -> Self {
+        self.always_on_error = enabled;
+        self
+    }
+
+    /// Whether to sample a request to `route`
+    ///
+    /// `key` identifies the trace; `parent` is the caller's decision, if it
+    /// sent one.
+    pub fn should_sample(&self, route: Option<&str>, key: &str, parent: Option<bool>) -> bool {
+        if let Some(parent) = parent.filter(|_| self.parent_based) {
+            return parent;
+        }
+        let ratio = route
+            .and_then(|route| self.routes.get(route))
+            .copied()
+            .unwrap_or(self.ratio);
+        ratio >= 1.0 || (ratio > 0.0 && fraction(key) < ratio)
+    }
+}
+
+/// Wrap a trace exporter layer so it only sees sampled requests
+///
+/// Spans and events of unsampled requests don't reach `inner`, except for
+/// the `request` span of a request that fails with a 5xx when the sampler
+/// exports those. That span reaches `inner` from the start but is only
+/// closed, which is when exporters such as `tracing-opentelemetry` send a
+/// span, once the request has failed. Wrap the exporter layer only, so logs
+/// keep every request.
+pub fn layer<L>(inner: L) -> Sampled<L> {
+    Sampled { inner }
+}
+
+/// Exporter layer seeing only sampled requests, created with `layer`
+#[derive(Debug, Clone)]
+pub struct Sampled<L> {
+    inner: L,
+}
+
+// marks the spans passed to the inner layer
+struct Forwarded {
+    // closed only once the request failed
+    withheld: bool,
+    failed: bool,
+}
+
+// whether `span` was passed to the inner layer, and whether it failed
+fn forwarded<S>(ctx: &Context<'_, S>, span: &span::Id) -> Option<(bool, bool)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let span = ctx.span(span)?;
+    let extensions = span.extensions();
+    let forwarded = extensions.get::<Forwarded>()?;
+    Some((forwarded.withheld, forwarded.failed))
+}
+
+// finds `otel.status_code = "ERROR"`, recorded on failed `request` spans
+struct ErrorVisitor(bool);
+
+impl field::Visit for ErrorVisitor {
+    fn record_str(&mut self, field: &field::Field, value: &str) {
+        if field.name() == "otel.status_code" && value == "ERROR" {
+            self.0 = true;
+        }
+    }
+
+    fn record_debug(&mut self, _: &field::Field, _: &dyn std::fmt::Debug) {}
+}
+
+impl<S, L> Layer<S> for Sampled<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &tracing::Dispatch) {
+        self.inner.on_register_dispatch(subscriber);
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let withheld = FORCED.with(Cell::get);
+        if !withheld && !is_sampled() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Forwarded {
+                withheld,
+                failed: false,
+            });
+        }
+        self.inner.on_new_span(attrs, id, ctx);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some((withheld, _)) = forwarded(&ctx, id) else {
+            return;
+        };
+        if withheld {
+            let mut visitor = ErrorVisitor(false);
+            values.record(&mut visitor);
+            if visitor.0 {
+                if let Some(span) = ctx.span(id) {
+                    if let Some(forwarded) = span.extensions_mut().get_mut::<Forwarded>() {
+                        forwarded.failed = true;
+                    }
+                }
+            }
+        }
+        self.inner.on_record(id, values, ctx);
+    }
+
+    fn on_follows_from(&self, id: &span::Id, follows: &span::Id, ctx: Context<'_, S>) {
+        if forwarded(&ctx, id).is_some() && forwarded(&ctx, follows).is_some() {
+            self.inner.on_follows_from(id, follows, ctx);
+        }
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if is_sampled() {
+            self.inner.on_event(event, ctx);
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if forwarded(&ctx, id).is_some() {
+            self.inner.on_enter(id, ctx);
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if forwarded(&ctx, id).is_some() {
+            self.inner.on_exit(id, ctx);
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        // an unsampled request that didn't fail is dropped unexported
+        if let Some((false, _) | (true, true)) = forwarded(&ctx, &id) {
+            self.inner.on_close(id, ctx);
+        }
+    }
+
+    fn on_id_change(&self, old: &span::Id, new: &span::Id, ctx: Context<'_, S>) {
+        if forwarded(&ctx, old).is_some() {
+            self.inner.on_id_change(old, new, ctx);
+        }
+    }
+
+    // lets `tracing-opentelemetry` find its layer behind this one
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            // SAFETY: forwarded to the inner layer, which upholds the contract
+            unsafe { self.inner.downcast_raw(id) }
+        }
+    }
+}
+
+// whether the current request, if any, is sampled
+fn is_sampled() -> bool {
+    RequestContext::with_current(RequestContext::is_sampled).unwrap_or(true)
+}
+
+// map a key to [0, 1): the low 64 bits of a hex trace id, which are what
+// OpenTelemetry's ratio sampler compares, or a stable hash of any other key
+fn fraction(key: &str) -> f64 {
+    let low = key
+        .len()
+        .checked_sub(16)
+        .and_then(|at| key.get(at..))
+        .filter(|low| low.bytes().all(|b| b.is_ascii_hexdigit()))
+        .and_then(|low| u64::from_str_radix(low, 16).ok());
+    let bits = low.unwrap_or_else(|| hash(key));
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// FNV-1a with a splitmix64 finalizer, fixed so decisions don't change
+// between builds
+fn hash(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+// open the `request` span of the current request, sampling it with
+// `sampler`; called within the request's context
+pub(crate) fn request_span(
+    ctx: &RequestContext,
+    headers: &HeaderMap,
+    sampler: Option<&Sampler>,
+) -> Span {
+    let parent = metrics::traceparent(headers);
+    let trace_id = parent.map(|(trace_id, _)| trace_id);
+    let mut forced = false;
+    if let Some(sampler) = sampler {
+        let sampled = sampler.should_sample(
+            ctx.route(),
+            trace_id.unwrap_or(ctx.request_id()),
+            parent.map(|(_, sampled)| sampled),
+        );
+        ctx.set_sampled(sampled);
+        // the head decision dropped the trace; export its root span if it fails
+        forced = !sampled && sampler.always_on_error;
+    }
+
+    FORCED.with(|cell| cell.set(forced));
+    let span = tracing::info_span!(
+        "request",
+        method = %ctx.method(),
+        route = ctx.route().unwrap_or("unmatched"),
+        request_id = ctx.request_id(),
+        trace_id,
+        status = field::Empty,
+        latency_ms = field::Empty,
+        otel.status_code = field::Empty,
+    );
+    FORCED.with(|cell| cell.set(false));
+    span
+}
+
+// record the outcome of the request on its `request` span
+pub(crate) fn finish(span: &Span, response: &Response, started: Instant) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+    if response.status().is_server_error() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{body::Body, extract::Request, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+    use crate::app::App;
+
+    // collects the names of spans and events as they are exported
+    #[derive(Clone, Default)]
+    struct Exported {
+        routes: Arc<Mutex<HashMap<span::Id, (String, Instant)>>>,
+        closed: Arc<Mutex<Vec<(String, Duration)>>>,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct RouteVisitor<'a>(&'a mut String);
+
+    impl field::Visit for RouteVisitor<'_> {
+        fn record_str(&mut self, field: &field::Field, value: &str) {
+            if field.name() == "route" {
+                self.0.push_str(value);
+            }
+        }
+
+        fn record_debug(&mut self, _: &field::Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Exported {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, _: Context<'_, S>) {
+            let mut route = String::new();
+            attrs.record(&mut RouteVisitor(&mut route));
+            let name = match attrs.metadata().name() {
+                "request" => route,
+                name => name.to_string(),
+            };
+            self.routes
+                .lock()
+                .unwrap()
+                .insert(id.clone(), (name, Instant::now()));
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            // the handlers' own events
+            if event.metadata().target() == module_path!() {
+                let name = event.metadata().name().to_string();
+                self.events.lock().unwrap().push(name);
+            }
+        }
+
+        fn on_close(&self, id: span::Id, _: Context<'_, S>) {
+            if let Some((name, opened)) = self.routes.lock().unwrap().remove(&id) {
+                self.closed.lock().unwrap().push((name, opened.elapsed()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_should_sample() {
+        let sampler = Sampler::new(0.0).route("/checkout", 1.0);
+        assert!(!sampler.should_sample(Some("/users"), "a", None));
+        assert!(sampler.should_sample(Some("/checkout"), "a", None));
+        assert!(sampler.should_sample(Some("/users"), "a", Some(true)));
+        assert!(!sampler
+            .parent_based(false)
+            .should_sample(Some("/users"), "a", Some(true)));
+        assert!(!Sampler::always().should_sample(None, "a", Some(false)));
+
+        let half = Sampler::new(0.5);
+        let sampled = (0..1000)
+            .filter(|i| half.should_sample(None, &i.to_string(), None))
+            .count();
+        assert!((400..600).contains(&sampled), "{}", sampled);
+        assert_eq!(
+            half.should_sample(None, "trace", None),
+            half.should_sample(None, "trace", None)
+        );
+    }
+
+    #[test]
+    fn test_trace_ids_sample_on_their_low_bits() {
+        let sampler = Sampler::new(0.25).parent_based(false);
+        // the high half doesn't matter, the low half is compared to the ratio
+        assert!(sampler.should_sample(None, "ffffffffffffffff0fffffffffffffff", None));
+        assert!(!sampler.should_sample(None, "000000000000000040000000000000ff", None));
+        assert_eq!(fraction("00000000000000008000000000000000"), 0.5);
+        // other keys hash the same in every build
+        assert_eq!(hash("request"), 0xc639_82fc_1a87_2558);
+    }
+
+    #[tokio::test]
+    async fn test_unsampled_requests_are_exported_on_error() {
+        let exported = Exported::default();
+        let subscriber = tracing_subscriber::registry().with(layer(exported.clone()));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let failing = || async {
+            tracing::info_span!("query").in_scope(|| tracing::info!("querying"));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        let router = App::new()
+            .trace_requests(Sampler::new(0.0).route("/sampled", 1.0))
+            .merge(
+                Router::new()
+                    .route(
+                        "/sampled",
+                        get(|| async {
+                            tracing::info_span!("query").in_scope(|| tracing::info!("querying"));
+                            "ok"
+                        }),
+                    )
+                    .route("/dropped", get(|| async { "ok" }))
+                    .route("/failing", get(failing)),
+            )
+            .build();
+        for path in ["/sampled", "/dropped", "/failing"] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        let closed = exported.closed.lock().unwrap();
+        let names: Vec<_> = closed.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["query", "/sampled", "/failing"]);
+        // the failed request's own span, covering the whole request
+        assert!(
+            closed[2].1 >= Duration::from_millis(20),
+            "{:?}",
+            closed[2].1
+        );
+        assert_eq!(exported.events.lock().unwrap().len(), 1);
+    }
+}
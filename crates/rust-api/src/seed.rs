@@ -0,0 +1,322 @@
+//! Database seeding for RustAPI framework
+//!
+//! Seeders registered with `App::seeder` load reference data and fixtures
+//! when `App::seed` runs, typically right after migrations. Each seeder is
+//! gated on the environment named by `APP_ENV` and runs at most once: before
+//! running, a seeder is claimed by inserting a marker under its name into a
+//! `SeedMarkers` store only if none exists, so of several replicas starting
+//! together exactly one runs it. The marker is confirmed once the seeder
+//! succeeds and removed again when it fails.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::{Arc, Mutex},
+};
+
+use crate::{di::Container, error::Result, lifecycle::BoxFuture};
+
+/// Environment variable naming the current environment
+pub const ENVIRONMENT_VAR: &str = "APP_ENV";
+
+/// Environment assumed when `APP_ENV` is not set
+///
+/// Production, so a deployment that does not set it never gets development
+/// fixtures or the `Dev` profile.
+pub const DEFAULT_ENVIRONMENT: &str = "production";
+
+/// The current environment, from `APP_ENV`
+pub fn environment() -> String {
+    std::env::var(ENVIRONMENT_VAR)
+        .ok()
+        .filter(|env| !env.is_empty())
+        .unwrap_or_else(|| DEFAULT_ENVIRONMENT.to_string())
+}
+
+/// Loads a set of rows into the database
+///
+/// Seeders resolve their database connection and services from the
+/// application container.
+///
+/// # Example
+///
+/// ```ignore
+/// struct Countries;
+///
+/// impl Seeder for Countries {
+///     fn name(&self) -> &str {
+///         "countries-v1"
+///     }
+///
+///     fn environments(&self) -> &[&str] {
+///         &["development", "test", "production"]
+///     }
+///
+///     fn run<'a>(&'a self, container: &'a Container) -> BoxFuture<'a, Result<()>> {
+///         Box::pin(async move {
+///             let db = container.resolve::<DatabaseConnection>().unwrap();
+///             insert_countries(&db).await
+///         })
+///     }
+/// }
+/// ```
+pub trait Seeder: Send + Sync + 'static {
+    /// Unique name recorded once the seeder ran; rename it to run it again
+    fn name(&self) -> &str;
+
+    /// Environments the seeder runs in, `development` and `test` by default
+    fn environments(&self) -> &[&str] {
+        &["development", "test"]
+    }
+
+    /// Load the data; an error aborts seeding and records no marker
+    fn run<'a>(&'a self, container: &'a Container) -> BoxFuture<'a, Result<()>>;
+}
+
+/// State of a seeder's marker, as seen by `SeedMarkers::claim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// There was no marker; this process recorded one and runs the seeder
+    Claimed,
+    /// The seeder already ran
+    Applied,
+    /// Another process claimed the seeder and has not finished it, or
+    /// crashed while running it
+    Pending,
+}
+
+/// Store of the names of the seeders that ran or are running
+pub trait SeedMarkers: Send + Sync + 'static {
+    /// Record an unconfirmed marker for the seeder `name` unless it has one
+    ///
+    /// Must be atomic (an insert-if-absent), so only one of several
+    /// concurrent callers gets `Claim::Claimed`.
+    fn claim<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Claim>>;
+
+    /// Confirm the claimed marker once the seeder `name` ran
+    fn mark_applied<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// Remove the claimed marker after the seeder `name` failed
+    fn release<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<()>>;
+}
+
+/// In-memory `SeedMarkers`, forgotten when the process exits
+///
+/// The default store; use a persistent one such as `orm::SeedTable` to keep
+/// seeders from running again on every start.
+#[derive(Clone, Default)]
+pub struct MemoryMarkers {
+    // name -> whether the seeder finished
+    markers: Arc<Mutex<BTreeMap<String, bool>>>,
+}
+
+impl MemoryMarkers {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether the seeder `name` ran
+    pub fn is_applied(&self, name: &str) -> bool {
+        self.markers().get(name).copied().unwrap_or(false)
+    }
+
+    // lock the markers
+    fn markers(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, bool>> {
+        self.markers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SeedMarkers for MemoryMarkers {
+    fn claim<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Claim>> {
+        let claim = match self.markers().entry(name.to_string()) {
+            Entry::Occupied(marker) if *marker.get() => Claim::Applied,
+            Entry::Occupied(_) => Claim::Pending,
+            Entry::Vacant(marker) => {
+                marker.insert(false);
+                Claim::Claimed
+            }
+        };
+        Box::pin(async move { Ok(claim) })
+    }
+
+    fn mark_applied<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.markers().insert(name.to_string(), true);
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn release<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.markers().remove(name);
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+/// Seeders registered on an `App`
+pub(crate) struct Seeds {
+    seeders: Vec<Box<dyn Seeder>>,
+    markers: Arc<dyn SeedMarkers>,
+    environment: Option<String>,
+}
+
+impl Default for Seeds {
+    fn default() -> Self {
+        Self {
+            seeders: Vec::new(),
+            markers: Arc::new(MemoryMarkers::new()),
+            environment: None,
+        }
+    }
+}
+
+impl Seeds {
+    pub(crate) fn add(&mut self, seeder: impl Seeder) {
+        self.seeders.push(Box::new(seeder));
+    }
+
+    pub(crate) fn set_markers(&mut self, markers: Arc<dyn SeedMarkers>) {
+        self.markers = markers;
+    }
+
+    pub(crate) fn set_environment(&mut self, environment: String) {
+        self.environment = Some(environment);
+    }
+
+    /// Run the pending seeders of the current environment in registration
+    /// order, returning the names of those that ran
+    pub(crate) async fn run(&self, container: &Container) -> Result<Vec<String>> {
+        let environment = self.environment.clone().unwrap_or_else(environment);
+        let mut applied = Vec::new();
+        for seeder in &self.seeders {
+            let name = seeder.name();
+            if !seeder.environments().contains(&environment.as_str()) {
+                tracing::debug!("Skipping seeder {} in {}", name, environment);
+                continue;
+            }
+            match self.markers.claim(name).await? {
+                Claim::Claimed => {}
+                Claim::Applied => {
+                    tracing::debug!("Seeder {} already applied", name);
+                    continue;
+                }
+                Claim::Pending => {
+                    tracing::warn!(
+                        "Seeder {} is claimed by another instance; remove its marker if that \
+                         instance crashed while seeding",
+                        name
+                    );
+                    continue;
+                }
+            }
+            if let Err(e) = seeder.run(container).await {
+                if let Err(release) = self.markers.release(name).await {
+                    tracing::error!("Failed to release seeder {}: {}", name, release);
+                }
+                return Err(e);
+            }
+            self.markers.mark_applied(name).await?;
+            tracing::info!("Applied seeder {}", name);
+            applied.push(name.to_string());
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{app::App, di::Injectable, error::Error};
+
+    #[derive(Default)]
+    struct Rows(AtomicUsize);
+
+    impl Injectable for Rows {}
+
+    struct Fixture {
+        name: &'static str,
+        environments: &'static [&'static str],
+        fail: bool,
+    }
+
+    impl Seeder for Fixture {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn environments(&self) -> &[&str] {
+            self.environments
+        }
+
+        fn run<'a>(&'a self, container: &'a Container) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                if self.fail {
+                    return Err(Error::other("constraint violated"));
+                }
+                container
+                    .resolve::<Rows>()
+                    .unwrap()
+                    .0
+                    .fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    fn fixture(name: &'static str, environments: &'static [&'static str]) -> Fixture {
+        Fixture {
+            name,
+            environments,
+            fail: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_gates_on_environment_and_runs_once() {
+        let rows = Arc::new(Rows::default());
+        let mut app = App::new()
+            .seeder(fixture("countries", &["test", "production"]))
+            .seeder(fixture("demo-users", &["development"]))
+            .seed_environment("test");
+        app.container_mut().register(rows.clone());
+
+        assert_eq!(app.seed().await.unwrap(), ["countries"]);
+        assert!(app.seed().await.unwrap().is_empty());
+        assert_eq!(rows.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_seeder_is_not_marked() {
+        let markers = MemoryMarkers::new();
+        let app = App::new()
+            .seeder(Fixture {
+                name: "broken",
+                environments: &["development"],
+                fail: true,
+            })
+            .seed_markers(Arc::new(markers.clone()))
+            .seed_environment("development");
+
+        assert!(app.seed().await.is_err());
+        assert!(!markers.is_applied("broken"));
+        assert_eq!(markers.claim("broken").await.unwrap(), Claim::Claimed);
+    }
+
+    #[tokio::test]
+    async fn test_claimed_seeder_runs_once() {
+        let markers = MemoryMarkers::new();
+        assert_eq!(markers.claim("countries").await.unwrap(), Claim::Claimed);
+
+        // a replica starting meanwhile skips it
+        let rows = Arc::new(Rows::default());
+        let mut app = App::new()
+            .seeder(fixture("countries", &["test"]))
+            .seed_markers(Arc::new(markers.clone()))
+            .seed_environment("test");
+        app.container_mut().register(rows.clone());
+        assert!(app.seed().await.unwrap().is_empty());
+        assert_eq!(rows.0.load(Ordering::SeqCst), 0);
+
+        markers.mark_applied("countries").await.unwrap();
+        assert_eq!(markers.claim("countries").await.unwrap(), Claim::Applied);
+    }
+}
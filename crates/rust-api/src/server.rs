@@ -3,9 +3,30 @@
 //! Provides the main `RustAPI` struct for configuring and running the HTTP
 //! server.
 
-use std::net::SocketAddr;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::{error::Result, router::Router};
+use axum::{middleware, serve::ListenerExt};
+use tokio::sync::oneshot;
+
+use crate::{
+    boot,
+    buffer::BufferPool,
+    error::{Error, Result},
+    health::{self, HealthChecks, Readiness},
+    lifecycle::{self, OnStart},
+    platform::Platform,
+    profile::{self, LogFormat},
+    proxy_protocol::ProxyProtocolListener,
+    router::Router,
+    runtime::RuntimeConfig,
+    shutdown::{self, Handoff, RequestTracker, ShutdownSignal},
+};
 
 /// Main RustAPI server struct with builder pattern for configuration
 ///
@@ -23,6 +44,23 @@ pub struct RustAPI {
     router: Router,
     port: u16,
     host: String,
+    proxy_protocol: bool,
+    reuse_port: bool,
+    handoff_file: Option<PathBuf>,
+    runtime: RuntimeConfig,
+    shutdown_signal: Option<ShutdownSignal>,
+    drain_report_interval: Duration,
+    drain_deadline: Option<Duration>,
+    shutdown_delay: Duration,
+    start_hooks: Vec<Box<dyn OnStart>>,
+    readiness: Readiness,
+    health_probes: bool,
+    health_checks: HealthChecks,
+    buffer_pool: Option<BufferPool>,
+    platform: Option<Platform>,
+    log_format: Option<LogFormat>,
+    #[cfg(feature = "ws")]
+    websockets: crate::ws::ServedSockets,
 }
 
 impl RustAPI {
@@ -34,7 +72,73 @@ impl RustAPI {
             router,
             port: 3000,
             host: "0.0.0.0".to_string(),
+            proxy_protocol: false,
+            reuse_port: false,
+            handoff_file: None,
+            runtime: RuntimeConfig::new(),
+            shutdown_signal: None,
+            drain_report_interval: shutdown::DEFAULT_DRAIN_REPORT_INTERVAL,
+            drain_deadline: None,
+            shutdown_delay: Duration::ZERO,
+            start_hooks: Vec::new(),
+            readiness: Readiness::new(),
+            health_probes: false,
+            health_checks: HealthChecks::new(),
+            buffer_pool: None,
+            platform: None,
+            log_format: None,
+            #[cfg(feature = "ws")]
+            websockets: Default::default(),
+        }
+    }
+
+    /// Create a server configured from the environment
+    ///
+    /// Listens on `PORT` (default 3000) and `HOST` (default `0.0.0.0`).
+    /// On a detected platform (Cloud Run, Heroku, Railway), a loopback
+    /// `HOST` is ignored, since the platform's router reaches the process
+    /// from outside, and logs are written in the format its collector
+    /// expects. Shuttle binds the listener itself; hand it the router
+    /// instead.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// RustAPI::from_env(app.build()).serve().await?;
+    /// ```
+    pub fn from_env(router: Router) -> Self {
+        Self::new(router).configure_from(|name| std::env::var(name).ok())
+    }
+
+    // apply `PORT`, `HOST` and platform conventions read through `var`
+    fn configure_from(mut self, var: impl Fn(&str) -> Option<String>) -> Self {
+        let platform = Platform::detect_with(&var);
+        if let Some(port) = var("PORT") {
+            match port.trim().parse() {
+                Ok(port) => self.port = port,
+                Err(_) => tracing::warn!("Ignoring invalid PORT {:?}, using {}", port, self.port),
+            }
         }
+        if let Some(host) = var("HOST").filter(|host| !host.trim().is_empty()) {
+            let loopback = matches!(host.trim(), "127.0.0.1" | "localhost" | "::1");
+            if platform.is_some() && loopback {
+                tracing::warn!("Ignoring loopback HOST {:?} on {:?}", host, platform);
+            } else {
+                self.host = host.trim().to_string();
+            }
+        }
+        self.platform = platform;
+        self.log_format = platform.map(|platform| platform.log_format());
+        self
+    }
+
+    /// Install a log subscriber writing in `format` when serving
+    ///
+    /// `from_env` picks the format of the detected platform. Nothing is
+    /// installed if a subscriber already is.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
     }
 
     /// Set the port to listen on (default: 3000)
@@ -49,34 +153,385 @@ impl RustAPI {
         self
     }
 
+    /// Require a PROXY protocol (v1 or v2) header on every connection
+    /// (default: false)
+    ///
+    /// Enable this when running behind HAProxy or a network load balancer in
+    /// TCP mode, so `ClientIp` reports the original client address.
+    /// Connections without a valid header are dropped.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Bind with `SO_REUSEPORT`, so a new process can listen on the port
+    /// while this one still does (default: false)
+    ///
+    /// The kernel spreads new connections over every process listening on
+    /// the port. Combined with `handoff_file`, this restarts a server
+    /// without a load balancer and without refusing connections:
+    ///
+    /// 1. start the new version with the same port and handoff file;
+    /// 2. once its start hooks succeed, it claims the handoff file;
+    /// 3. the old process sees the claim, stops accepting connections and
+    ///    drains like on SIGTERM, honoring the shutdown delay and drain
+    ///    deadline.
+    ///
+    /// Only available on Unix platforms; `serve` fails elsewhere.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// RustAPI::new(app)
+    ///     .port(8080)
+    ///     .reuse_port(true)
+    ///     .handoff_file("/run/my-api/handoff")
+    ///     .serve()
+    ///     .await?;
+    /// ```
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.reuse_port = enabled;
+        self
+    }
+
+    /// Coordinate restarts through the file at `path`
+    ///
+    /// Once ready, the server writes a token unique to the process to the
+    /// file, and it shuts down gracefully as soon as another process writes
+    /// its own. See `reuse_port` for the restart sequence.
+    pub fn handoff_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.handoff_file = Some(path.into());
+        self
+    }
+
+    /// Set the future that triggers graceful shutdown
+    ///
+    /// Defaults to Ctrl+C, or SIGTERM on Unix platforms.
+    pub fn shutdown_signal(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown_signal = Some(Box::pin(signal));
+        self
+    }
+
+    /// Set how often in-flight requests are logged while draining (default: 5
+    /// seconds)
+    pub fn drain_report_interval(mut self, interval: Duration) -> Self {
+        self.drain_report_interval = interval;
+        self
+    }
+
+    /// Cancel requests still running this long after shutdown begins
+    /// (default: wait indefinitely)
+    ///
+    /// Cancelled requests are answered with 503 Service Unavailable.
+    pub fn drain_deadline(mut self, deadline: Duration) -> Self {
+        self.drain_deadline = Some(deadline);
+        self
+    }
+
+    /// Keep serving for this long after shutdown begins, with readiness
+    /// reporting not ready, before the listener closes (default: none)
+    ///
+    /// Gives load balancers time to observe the failing readiness probe and
+    /// stop routing new traffic to this instance.
+    pub fn shutdown_delay(mut self, delay: Duration) -> Self {
+        self.shutdown_delay = delay;
+        self
+    }
+
+    /// Register a hook to run during startup
+    ///
+    /// Hooks run in registration order after the listener is bound. The
+    /// readiness probe reports not ready until every hook has finished; if a
+    /// hook fails, the server shuts down and `serve()` returns its error.
+    pub fn on_start(mut self, hook: impl OnStart) -> Self {
+        self.start_hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Serve `/healthz` and `/readyz` probes (default: false)
+    ///
+    /// Like `App::priority_lane` routes, the probes skip the app's
+    /// middleware, so load shedding and timeouts never fail them.
+    pub fn health_probes(mut self, enabled: bool) -> Self {
+        self.health_probes = enabled;
+        self
+    }
+
+    /// Serve the probes, reporting the dependency `checks`
+    ///
+    /// Enables `health_probes`. See `HealthChecks` for how each probe
+    /// treats failing checks.
+    pub fn health_checks(mut self, checks: HealthChecks) -> Self {
+        self.health_probes = true;
+        self.health_checks = checks;
+        self
+    }
+
+    /// Pool the buffers `Json` responses are serialized into
+    ///
+    /// Installed as the process-wide `BufferPool::global()` when the server
+    /// starts. Pass `BufferPool::disabled()` to allocate per response.
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Get a handle to the readiness flag reported by `/readyz`
+    ///
+    /// Useful for taking the instance out of rotation manually, e.g. during
+    /// maintenance.
+    pub fn readiness(&self) -> Readiness {
+        self.readiness.clone()
+    }
+
+    /// Tune the Tokio runtime used by `run()`
+    ///
+    /// Has no effect on `serve()`, which runs on the caller's runtime.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// RustAPI::new(app)
+    ///     .runtime(|rt| rt.worker_threads(4).max_blocking_threads(64))
+    ///     .run()?;
+    /// ```
+    pub fn runtime(mut self, configure: impl FnOnce(RuntimeConfig) -> RuntimeConfig) -> Self {
+        self.runtime = configure(self.runtime);
+        self
+    }
+
+    /// Build the configured Tokio runtime and block on the server
+    ///
+    /// Use this from a plain `fn main()` instead of `#[tokio::main]` when
+    /// the runtime should be tuned through `runtime()`.
+    pub fn run(self) -> Result<()> {
+        let runtime = self.runtime.build()?;
+        runtime.block_on(self.serve())
+    }
+
     /// Start the HTTP server
     ///
     /// This will bind to the configured host and port, and start serving
-    /// requests.
+    /// requests. Start hooks run once the listener is bound; the readiness
+    /// flag is set when they all succeed and cleared as soon as graceful
+    /// shutdown begins.
     pub async fn serve(self) -> Result<()> {
+        // before binding, so bind errors are logged in the configured format
+        if let Some(format) = self.log_format {
+            profile::init_logging(format);
+        }
         let addr = format!("{}:{}", self.host, self.port);
         let socket_addr: SocketAddr = addr.parse().map_err(|e| {
+            tracing::error!("Invalid address {}: {}", addr, e);
             crate::error::Error::server_error(format!("Invalid address {}: {}", addr, e))
         })?;
 
-        let listener = tokio::net::TcpListener::bind(socket_addr)
+        let listener = boot::time_async("listener", bind(socket_addr, self.reuse_port))
             .await
             .map_err(|e| {
+                tracing::error!("Failed to bind to {}: {}", socket_addr, e);
                 crate::error::Error::server_error(format!(
                     "Failed to bind to {}: {}",
                     socket_addr, e
                 ))
             })?;
 
-        tracing::info!("Server running on http://{}", socket_addr);
+        self.serve_with_listener(listener).await
+    }
 
-        // Router is already Axum's router (type alias), serve it directly
-        axum::serve(listener, self.router)
-            .await
-            .map_err(|e| crate::error::Error::server_error(format!("Server error: {}", e)))
+    /// Start the HTTP server on an already bound listener
+    ///
+    /// The configured host and port are ignored. Useful when the port is
+    /// chosen by the OS (port 0) and must be known before serving.
+    pub async fn serve_with_listener(mut self, listener: tokio::net::TcpListener) -> Result<()> {
+        let socket_addr = listener.local_addr().map_err(|e| {
+            crate::error::Error::server_error(format!("Failed to read local address: {}", e))
+        })?;
+        if let Some(format) = self.log_format {
+            profile::init_logging(format);
+        }
+        match self.platform {
+            Some(platform) => tracing::info!(
+                platform = platform.as_str(),
+                "Server running on http://{}",
+                socket_addr
+            ),
+            None => tracing::info!("Server running on http://{}", socket_addr),
+        }
+
+        if let Some(pool) = self.buffer_pool.take() {
+            BufferPool::set_global(pool);
+        }
+
+        let tracker = RequestTracker::new();
+        let handoff = self.handoff_file.take().map(Handoff::new);
+        let startup_error = Arc::new(Mutex::new(None));
+        let startup_failed = self.start_up(startup_error.clone(), handoff.clone());
+        let shutdown = self.drain_on_shutdown(tracker.clone(), startup_failed, handoff);
+
+        let mut router = self.router;
+        if self.health_probes {
+            router = router.merge(health::routes_with(
+                self.readiness.clone(),
+                self.health_checks.clone(),
+            ));
+        }
+
+        // upgrades record their `WebSockets` here for the drain
+        #[cfg(feature = "ws")]
+        {
+            router = router.layer(axum::Extension(self.websockets.clone()));
+        }
+
+        // connect info makes the client address available to extractors
+        let service = router
+            .layer(middleware::from_fn_with_state(
+                tracker,
+                shutdown::track_requests,
+            ))
+            .into_make_service_with_connect_info::<SocketAddr>();
+
+        let result = if self.proxy_protocol {
+            tracing::info!("PROXY protocol enabled");
+            let listener = ProxyProtocolListener::new(listener).map_err(|e| {
+                crate::error::Error::server_error(format!("Failed to start listener: {}", e))
+            })?;
+            // tap_io lets axum derive SocketAddr connect info from our listener
+            axum::serve(listener.tap_io(|_| {}), service)
+                .with_graceful_shutdown(shutdown)
+                .await
+        } else {
+            axum::serve(listener, service)
+                .with_graceful_shutdown(shutdown)
+                .await
+        };
+
+        // upgraded connections outlive the HTTP drain, so wait for them here
+        #[cfg(feature = "ws")]
+        self.websockets.drain().await;
+
+        if let Some(e) = startup_error.lock().unwrap().take() {
+            return Err(e);
+        }
+        result.map_err(|e| crate::error::Error::server_error(format!("Server error: {}", e)))
+    }
+
+    // wrap the router, e.g. with test instrumentation
+    pub(crate) fn map_router(mut self, f: impl FnOnce(Router) -> Router) -> Self {
+        self.router = f(self.router);
+        self
+    }
+
+    // run start hooks in the background, marking the app ready on success
+    fn start_up(
+        &mut self,
+        error_slot: Arc<Mutex<Option<Error>>>,
+        handoff: Option<Handoff>,
+    ) -> oneshot::Receiver<()> {
+        let hooks = std::mem::take(&mut self.start_hooks);
+        let readiness = self.readiness.clone();
+        let (failed_tx, failed_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            match boot::time_async("start_hooks", lifecycle::run_start_hooks(&hooks)).await {
+                Ok(()) => {
+                    readiness.set_ready(true);
+                    tracing::info!("Application ready");
+                    boot::emit();
+                    // ask the process we replace to drain
+                    if let Some(handoff) = handoff {
+                        if let Err(e) = handoff.claim().await {
+                            tracing::error!("Failed to claim handoff file: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Start hook failed: {}", e);
+                    *error_slot.lock().unwrap() = Some(e);
+                    let _ = failed_tx.send(());
+                }
+            }
+        });
+
+        failed_rx
+    }
+
+    // wait for the shutdown signal, then start reporting on the drain
+    fn drain_on_shutdown(
+        &mut self,
+        tracker: RequestTracker,
+        startup_failed: oneshot::Receiver<()>,
+        handoff: Option<Handoff>,
+    ) -> impl Future<Output = ()> {
+        let signal = self
+            .shutdown_signal
+            .take()
+            .unwrap_or_else(|| Box::pin(shutdown::default_signal()));
+        let readiness = self.readiness.clone();
+        let delay = self.shutdown_delay;
+        let interval = self.drain_report_interval;
+        let deadline = self.drain_deadline;
+        #[cfg(feature = "ws")]
+        let sockets = self.websockets.clone();
+
+        async move {
+            let taken_over = async {
+                match &handoff {
+                    Some(handoff) => handoff.taken_over().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = signal => {},
+                Ok(()) = startup_failed => {},
+                _ = taken_over => {},
+            }
+
+            // stop advertising readiness before the listener closes
+            readiness.set_ready(false);
+            if !delay.is_zero() {
+                tracing::info!("Not ready, waiting {:?} before closing listener", delay);
+                tokio::time::sleep(delay).await;
+            }
+
+            tracing::info!("Shutdown signal received, draining connections");
+            #[cfg(feature = "ws")]
+            sockets.close_all();
+            tokio::spawn(async move { tracker.report_drain(interval, deadline).await });
+        }
     }
 }
 
+// bind a listener, optionally with SO_REUSEPORT
+async fn bind(addr: SocketAddr, reuse_port: bool) -> std::io::Result<tokio::net::TcpListener> {
+    if !reuse_port {
+        return tokio::net::TcpListener::bind(addr).await;
+    }
+    #[cfg(all(
+        unix,
+        not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+    ))]
+    {
+        let socket = if addr.is_ipv4() {
+            tokio::net::TcpSocket::new_v4()?
+        } else {
+            tokio::net::TcpSocket::new_v6()?
+        };
+        socket.set_reuseaddr(true)?;
+        socket.set_reuseport(true)?;
+        socket.bind(addr)?;
+        socket.listen(1024)
+    }
+    #[cfg(not(all(
+        unix,
+        not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+    )))]
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,13 +542,160 @@ mod tests {
         let server = RustAPI::new(router);
         assert_eq!(server.port, 3000);
         assert_eq!(server.host, "0.0.0.0");
+        assert!(!server.proxy_protocol);
+        assert_eq!(server.drain_deadline, None);
+        assert!(!server.health_probes);
+        assert!(!server.readiness().is_ready());
+    }
+
+    #[test]
+    fn test_configure_from_environment() {
+        let vars = |set: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                set.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let server = RustAPI::new(crate::router::build())
+            .configure_from(vars(&[("PORT", "8080"), ("HOST", "127.0.0.1")]));
+        assert_eq!((server.port, server.host.as_str()), (8080, "127.0.0.1"));
+        assert_eq!(server.log_format, None);
+
+        let server = RustAPI::new(crate::router::build()).configure_from(vars(&[
+            ("K_SERVICE", "api"),
+            ("PORT", "8081"),
+            ("HOST", "localhost"),
+        ]));
+        assert_eq!((server.port, server.host.as_str()), (8081, "0.0.0.0"));
+        assert_eq!(server.platform, Some(Platform::CloudRun));
+        assert_eq!(server.log_format, Some(LogFormat::CloudLogging));
+
+        let server = RustAPI::new(crate::router::build()).configure_from(vars(&[("PORT", "http")]));
+        assert_eq!(server.port, 3000);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handoff_with_reuse_port() {
+        let first = bind("127.0.0.1:0".parse().unwrap(), true).await.unwrap();
+        let addr = first.local_addr().unwrap();
+        // a second process would bind the same port
+        let second = bind(addr, true).await.unwrap();
+        drop(second);
+        assert!(tokio::net::TcpListener::bind(addr).await.is_err());
+
+        let path = std::env::temp_dir().join(format!("rust-api-handoff-{}", std::process::id()));
+        let server = RustAPI::new(crate::router::build())
+            .handoff_file(&path)
+            .shutdown_signal(std::future::pending());
+        let readiness = server.readiness();
+        let handle = tokio::spawn(server.serve_with_listener(first));
+        while !readiness.is_ready() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let claimed = std::fs::read_to_string(&path).unwrap();
+        assert!(claimed.starts_with(&format!("{}-", std::process::id())));
+
+        std::fs::write(&path, "new-process").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("server did not hand off")
+            .unwrap()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_stale_handoff_token_is_ignored_while_starting() {
+        let path =
+            std::env::temp_dir().join(format!("rust-api-stale-handoff-{}", std::process::id()));
+        std::fs::write(&path, "previous-process").unwrap();
+        let server = RustAPI::new(crate::router::build())
+            .handoff_file(&path)
+            .on_start(|| async {
+                tokio::time::sleep(Duration::from_millis(600)).await;
+                Ok(())
+            })
+            .shutdown_signal(std::future::pending());
+        let readiness = server.readiness();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = tokio::spawn(server.serve_with_listener(listener));
+
+        // several polls pass before the start hook finishes
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!handle.is_finished());
+        while !readiness.is_ready() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!handle.is_finished());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .starts_with(&format!("{}-", std::process::id())));
+        handle.abort();
+        let _ = std::fs::remove_file(&path);
     }
 
     #[test]
     fn test_rust_api_builder() {
         let router = crate::router::build();
-        let server = RustAPI::new(router).port(8080).host("127.0.0.1");
+        let server = RustAPI::new(router)
+            .port(8080)
+            .host("127.0.0.1")
+            .proxy_protocol(true)
+            .drain_report_interval(Duration::from_secs(1))
+            .drain_deadline(Duration::from_secs(30));
         assert_eq!(server.port, 8080);
         assert_eq!(server.host, "127.0.0.1");
+        assert!(server.proxy_protocol);
+        assert_eq!(server.drain_report_interval, Duration::from_secs(1));
+        assert_eq!(server.drain_deadline, Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn test_serve_readiness_lifecycle() {
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let server = RustAPI::new(crate::router::build())
+            .host("127.0.0.1")
+            .port(0)
+            .health_probes(true)
+            .on_start(|| async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(())
+            })
+            .shutdown_signal(async {
+                let _ = stop_rx.await;
+            });
+        let readiness = server.readiness();
+
+        let handle = tokio::spawn(server.serve());
+        assert!(!readiness.is_ready());
+        while !readiness.is_ready() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        stop_tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!readiness.is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_serve_fails_when_start_hook_fails() {
+        let server = RustAPI::new(crate::router::build())
+            .host("127.0.0.1")
+            .port(0)
+            .on_start(|| async { Err(Error::other("migration failed")) })
+            .shutdown_signal(std::future::pending());
+
+        let err = server.serve().await.unwrap_err();
+        assert!(err.to_string().contains("migration failed"));
+    }
+
+    #[test]
+    fn test_rust_api_runtime() {
+        let router = crate::router::build();
+        let server = RustAPI::new(router).runtime(|rt| rt.worker_threads(2).thread_name("api"));
+        assert!(server.runtime.build().is_ok());
     }
 }
@@ -0,0 +1,508 @@
+//! Load shedding for RustAPI framework
+//!
+//! An overloaded server that keeps accepting everything answers everything
+//! late. `LoadShedder` watches how saturated the process is, from the
+//! number of requests in flight and from how late Tokio's scheduler runs
+//! tasks, and rejects the least important routes with 503 and `Retry-After`
+//! while it is, so the rest keep their latency.
+//!
+//! Routes are `Priority::Normal` unless marked with `#[priority(..)]`. Low
+//! priority routes are shed as soon as the server is saturated, normal ones
+//! at twice the saturation threshold, and high and critical ones never.
+//! Watched `Bulkhead`s count towards saturation too, so requests are shed
+//! before they pile up behind a saturated dependency.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::time::Instant;
+
+use crate::{
+    bulkhead::Bulkhead, di::Injectable, error::ApiError, metrics::Metrics, registry::RouteRegistry,
+};
+
+/// Default scheduler delay at which the server counts as saturated
+pub const DEFAULT_MAX_SCHEDULER_DELAY: Duration = Duration::from_millis(50);
+
+/// Default interval between scheduler delay probes
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How important a route is to keep serving under load
+///
+/// Route metadata attached by `#[priority(..)]`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Shed as soon as the server is saturated (reports, exports, ...)
+    Low,
+    /// Shed when the server is twice as saturated
+    #[default]
+    Normal,
+    /// Never shed
+    High,
+    /// Never shed; for probes and operational endpoints
+    Critical,
+}
+
+impl Priority {
+    /// Lowercase name, as used in metrics labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Critical => "critical",
+        }
+    }
+
+    // pressure at which requests of this priority are rejected
+    fn shed_at(&self) -> f64 {
+        match self {
+            Priority::Low => 1.0,
+            Priority::Normal => 2.0,
+            Priority::High | Priority::Critical => f64::INFINITY,
+        }
+    }
+}
+
+/// Rejects low priority requests while the server is saturated
+///
+/// Cheap to clone; clones share the load readings. Install with
+/// `App::load_shedding`.
+///
+/// # Example
+///
+/// ```ignore
+/// let shedder = LoadShedder::new()
+///     .max_in_flight(512)
+///     .max_scheduler_delay(Duration::from_millis(20))
+///     .metrics(metrics.clone());
+/// let app = App::new().load_shedding(shedder);
+///
+/// #[priority(low)]
+/// #[get("/reports/yearly")]
+/// async fn yearly_report() -> Json<Report> { ... }
+/// ```
+#[derive(Clone)]
+pub struct LoadShedder {
+    max_in_flight: Option<usize>,
+    max_scheduler_delay: Option<Duration>,
+    probe_interval: Duration,
+    retry_after: Duration,
+    metrics: Option<Metrics>,
+    watched: Vec<Bulkhead>,
+    load: Arc<Load>,
+}
+
+// readings shared by the middleware and the probe task
+#[derive(Debug, Default)]
+struct Load {
+    in_flight: AtomicUsize,
+    scheduler_delay_us: AtomicU64,
+    probing: AtomicBool,
+}
+
+impl LoadShedder {
+    /// Shed when the scheduler runs tasks 50ms late, with no in-flight limit
+    pub fn new() -> Self {
+        Self {
+            max_in_flight: None,
+            max_scheduler_delay: Some(DEFAULT_MAX_SCHEDULER_DELAY),
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            retry_after: Duration::from_secs(1),
+            metrics: None,
+            watched: Vec::new(),
+            load: Arc::default(),
+        }
+    }
+
+    /// Count the server as saturated with `max` requests in flight
+    pub fn max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = Some(max.max(1));
+        self
+    }
+
+    /// Count the server as saturated when tasks run `delay` late, or never
+    /// with `None`
+    pub fn max_scheduler_delay(mut self, delay: impl Into<Option<Duration>>) -> Self {
+        self.max_scheduler_delay = delay.into();
+        self
+    }
+
+    /// Measure the scheduler delay every `interval` (default: 100ms)
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Ask rejected clients to retry after `delay` (default: 1 second)
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = delay;
+        self
+    }
+
+    /// Count the server as saturated while `bulkhead` is
+    pub fn watch(mut self, bulkhead: Bulkhead) -> Self {
+        self.watched.push(bulkhead);
+        self
+    }
+
+    /// Report `load_shed_in_flight` and `load_shed_scheduler_delay_ms`
+    /// gauges and the `load_shed_rejected_total` counter, labelled with the
+    /// priority, to `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        let load = Arc::clone(&self.load);
+        metrics.gauge("load_shed_in_flight", &[], move || {
+            load.in_flight.load(Ordering::Relaxed) as f64
+        });
+        let load = Arc::clone(&self.load);
+        metrics.gauge("load_shed_scheduler_delay_ms", &[], move || {
+            load.scheduler_delay_us.load(Ordering::Relaxed) as f64 / 1000.0
+        });
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Requests in flight through the shedder
+    pub fn in_flight(&self) -> usize {
+        self.load.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Recent scheduler delay
+    ///
+    /// Rises to a late probe at once and decays slowly, so a single stall
+    /// keeps shedding on for a few probes.
+    pub fn scheduler_delay(&self) -> Duration {
+        Duration::from_micros(self.load.scheduler_delay_us.load(Ordering::Relaxed))
+    }
+
+    /// Current load relative to the thresholds; 1.0 or more is saturated
+    pub fn pressure(&self) -> f64 {
+        let in_flight = self
+            .max_in_flight
+            .map_or(0.0, |max| self.in_flight() as f64 / max as f64);
+        let delay = self.max_scheduler_delay.map_or(0.0, |max| {
+            self.scheduler_delay().as_secs_f64() / max.as_secs_f64().max(1e-6)
+        });
+        let bulkheads = self
+            .watched
+            .iter()
+            .map(Bulkhead::saturation)
+            .fold(0.0, f64::max);
+        in_flight.max(delay).max(bulkheads)
+    }
+
+    /// Whether a request of `priority` would be rejected now
+    pub fn should_shed(&self, priority: Priority) -> bool {
+        self.pressure() >= priority.shed_at()
+    }
+
+    // start measuring the scheduler delay, unless a probe is running
+    fn ensure_probe(&self) {
+        if self.max_scheduler_delay.is_none() || self.load.probing.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let load = Arc::downgrade(&self.load);
+        tokio::spawn(probe(load, self.probe_interval));
+    }
+
+    // the 503 sent to shed requests
+    fn reject(&self, priority: Priority) -> Response {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(
+                "load_shed_rejected_total",
+                &[("priority", priority.as_str())],
+            );
+        }
+        let seconds = self.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        let error = ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is overloaded, try again later",
+        )
+        .with_code("overloaded");
+        ([("retry-after", HeaderValue::from(seconds))], error).into_response()
+    }
+}
+
+impl Default for LoadShedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Injectable for LoadShedder {}
+
+// sleep for `interval` and record how late the wakeup is, until the
+// shedder is dropped
+async fn probe(load: Weak<Load>, interval: Duration) {
+    // the next request starts a new probe if this one stops, e.g. with
+    // the runtime it was spawned on
+    let _probing = Probing(load.clone());
+    loop {
+        let started = Instant::now();
+        tokio::time::sleep(interval).await;
+        let Some(load) = load.upgrade() else {
+            return;
+        };
+        let late = started.elapsed().saturating_sub(interval).as_micros() as u64;
+        let previous = load.scheduler_delay_us.load(Ordering::Relaxed);
+        let delay = if late > previous {
+            late
+        } else {
+            (previous * 4 + late) / 5
+        };
+        load.scheduler_delay_us.store(delay, Ordering::Relaxed);
+    }
+}
+
+/// Middleware rejecting requests the shedder decides to shed
+///
+/// Must run after routing so the matched route's priority is known; routes
+/// mounted under a prefix are resolved through the app's `ServedRoutes`.
+pub(crate) async fn shed_load(
+    State(shedder): State<LoadShedder>,
+    req: Request,
+    next: Next,
+) -> Response {
+    shedder.ensure_probe();
+    let (parts, body) = req.into_parts();
+    let priority = RouteRegistry::global()
+        .for_request(&parts)
+        .and_then(|route| route.metadata.get::<Priority>().copied())
+        .unwrap_or_default();
+    if shedder.should_shed(priority) {
+        tracing::debug!(
+            priority = priority.as_str(),
+            pressure = shedder.pressure(),
+            "Shedding request"
+        );
+        return shedder.reject(priority);
+    }
+
+    shedder.load.in_flight.fetch_add(1, Ordering::Relaxed);
+    let _in_flight = InFlight(&shedder.load);
+    next.run(Request::from_parts(parts, body)).await
+}
+
+// a running probe, cleared on drop
+struct Probing(Weak<Load>);
+
+impl Drop for Probing {
+    fn drop(&mut self) {
+        if let Some(load) = self.0.upgrade() {
+            load.probing.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+// a request in flight, released on drop
+struct InFlight<'a>(&'a Load);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::registry::{RouteDef, RouteMetadata};
+
+    inventory::submit! {
+        RouteDef::new("GET", "/shed-test/report", concat!(module_path!(), "::", "report"))
+    }
+
+    inventory::submit! {
+        RouteMetadata::new(concat!(module_path!(), "::", "report"), |meta| {
+            meta.insert(Priority::Low)
+        })
+    }
+
+    fn request(path: &str) -> Request {
+        Request::get(path).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sheds_low_priority_when_saturated() {
+        let metrics = Metrics::new();
+        let shedder = LoadShedder::new()
+            .max_in_flight(1)
+            .max_scheduler_delay(None)
+            .retry_after(Duration::from_millis(2500))
+            .metrics(metrics.clone());
+        let (release, released) = oneshot::channel::<()>();
+        let released = Arc::new(tokio::sync::Mutex::new(Some(released)));
+        let router = Router::new()
+            .route(
+                "/shed-test/slow",
+                get(move || async move {
+                    if let Some(released) = released.lock().await.take() {
+                        let _ = released.await;
+                    }
+                    "slow"
+                }),
+            )
+            .route("/shed-test/report", get(|| async { "report" }))
+            .route("/shed-test/orders", get(|| async { "orders" }))
+            .layer(middleware::from_fn_with_state(shedder.clone(), shed_load));
+
+        let slow = tokio::spawn(router.clone().oneshot(request("/shed-test/slow")));
+        while shedder.in_flight() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(metrics.gauge_value("load_shed_in_flight", &[]), Some(1.0));
+
+        let response = router
+            .clone()
+            .oneshot(request("/shed-test/report"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "3");
+
+        // normal routes are shed at twice the threshold
+        let response = router
+            .clone()
+            .oneshot(request("/shed-test/orders"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        release.send(()).unwrap();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        assert_eq!(shedder.in_flight(), 0);
+        let response = router.oneshot(request("/shed-test/report")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            metrics.counter("load_shed_rejected_total", &[("priority", "low")]),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_priority_lane_is_never_shed() {
+        let shedder = LoadShedder::new()
+            .max_in_flight(1)
+            .max_scheduler_delay(None);
+        shedder.load.in_flight.store(5, Ordering::Relaxed);
+        let app = crate::app::App::new()
+            .load_shedding(shedder)
+            .route("/shed-test/orders", get(|| async { "orders" }))
+            .priority_lane(crate::health::routes(crate::health::Readiness::new()))
+            .build();
+
+        let response = app
+            .clone()
+            .oneshot(request("/shed-test/orders"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = app.oneshot(request("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_mounted_routes_keep_their_priority() {
+        let shedder = LoadShedder::new()
+            .max_in_flight(1)
+            .max_scheduler_delay(None);
+        shedder.load.in_flight.store(1, Ordering::Relaxed);
+        let child = crate::app::App::new()
+            .route("/shed-test/report", get(|| async { "report" }))
+            .route("/shed-test/orders", get(|| async { "orders" }));
+        let app = crate::app::App::new()
+            .load_shedding(shedder)
+            .nest("/v1", child)
+            .group("/v2", |group| {
+                group.route("/shed-test/report", get(|| async { "report" }))
+            })
+            .build();
+
+        for (path, status) in [
+            ("/v1/shed-test/report", StatusCode::SERVICE_UNAVAILABLE),
+            ("/v2/shed-test/report", StatusCode::SERVICE_UNAVAILABLE),
+            ("/v1/shed-test/orders", StatusCode::OK),
+        ] {
+            let response = app.clone().oneshot(request(path)).await.unwrap();
+            assert_eq!(response.status(), status, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_probe_restarts_after_its_runtime_stops() {
+        let shedder = LoadShedder::new().probe_interval(Duration::from_millis(5));
+        let runtime = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap()
+        };
+        let first = runtime();
+        first.block_on(async {
+            shedder.ensure_probe();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        });
+        assert!(shedder.load.probing.load(Ordering::Relaxed));
+        drop(first);
+        assert!(!shedder.load.probing.load(Ordering::Relaxed));
+
+        runtime().block_on(async {
+            shedder.ensure_probe();
+            assert!(shedder.load.probing.load(Ordering::Relaxed));
+        });
+    }
+
+    #[test]
+    fn test_priority_thresholds() {
+        let shedder = LoadShedder::new().max_in_flight(2);
+        shedder.load.in_flight.store(4, Ordering::Relaxed);
+        assert!(shedder.should_shed(Priority::Low));
+        assert!(shedder.should_shed(Priority::Normal));
+        assert!(!shedder.should_shed(Priority::High));
+        assert!(!shedder.should_shed(Priority::Critical));
+    }
+
+    #[tokio::test]
+    async fn test_watched_bulkhead_saturation() {
+        let bulkhead = Bulkhead::new("payments", 1);
+        let shedder = LoadShedder::new()
+            .max_scheduler_delay(None)
+            .watch(bulkhead.clone());
+        assert!(!shedder.should_shed(Priority::Low));
+
+        let _held = bulkhead.acquire().await.unwrap();
+        assert!(shedder.should_shed(Priority::Low));
+        assert!(!shedder.should_shed(Priority::Normal));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_delay_is_measured() {
+        let shedder = LoadShedder::new()
+            .max_scheduler_delay(Duration::from_millis(50))
+            .probe_interval(Duration::from_millis(5));
+        shedder.ensure_probe();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!shedder.should_shed(Priority::Low));
+
+        // block the only worker thread
+        std::thread::sleep(Duration::from_millis(200));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(shedder.scheduler_delay() >= Duration::from_millis(100));
+        assert!(shedder.should_shed(Priority::Low));
+    }
+}
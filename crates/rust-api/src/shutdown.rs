@@ -0,0 +1,397 @@
+//! Graceful shutdown support for RustAPI framework
+//!
+//! Tracks in-flight requests so that, once shutdown begins, the server can
+//! periodically report what is still running and optionally cancel requests
+//! that outlive the drain deadline.
+//!
+//! For restarts without a load balancer, a handoff file lets the process
+//! replacing this one ask it to drain: the new process, listening on the
+//! same port with `SO_REUSEPORT`, writes its token to the file once ready,
+//! and the old one shuts down when it sees a token other than its own. A
+//! process only watches the file after writing its own token, so a token
+//! left by an earlier process does not stop it while it starts.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::watch;
+
+/// Boxed future used as a shutdown signal
+pub type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Default interval between drain reports
+pub const DEFAULT_DRAIN_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+// how often the handoff file is checked
+const HANDOFF_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Wait for Ctrl+C, or SIGTERM on Unix platforms
+///
+/// This is the default shutdown signal used by `RustAPI::serve`.
+pub async fn default_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// A process's claim on a handoff file
+#[derive(Debug, Clone)]
+pub(crate) struct Handoff {
+    path: PathBuf,
+    token: String,
+    // set once our token is in the file
+    claimed: Arc<watch::Sender<bool>>,
+}
+
+impl Handoff {
+    // a claim with a token unique to this process
+    pub fn new(path: PathBuf) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_nanos());
+        Self {
+            path,
+            token: format!("{}-{}", std::process::id(), started),
+            claimed: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    // write our token, asking the previous owner to drain
+    pub async fn claim(&self) -> std::io::Result<()> {
+        tokio::fs::write(&self.path, &self.token).await?;
+        self.claimed.send_replace(true);
+        Ok(())
+    }
+
+    // wait until another process claims the file after we did; never
+    // returns if we did not claim it
+    pub async fn taken_over(&self) {
+        let _ = self.claimed.subscribe().wait_for(|claimed| *claimed).await;
+        loop {
+            tokio::time::sleep(HANDOFF_POLL_INTERVAL).await;
+            if read_token(&self.path)
+                .await
+                .is_some_and(|token| token != self.token)
+            {
+                tracing::info!("Handoff file claimed by a new process");
+                return;
+            }
+        }
+    }
+}
+
+// the token in a handoff file, if it can be read
+async fn read_token(path: &Path) -> Option<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()
+        .map(|token| token.trim().to_string())
+}
+
+/// A request that is currently being handled
+#[derive(Debug, Clone)]
+pub struct InFlightRequest {
+    /// HTTP method of the request
+    pub method: Method,
+    /// Matched route template, or the raw path if no route matched
+    pub route: String,
+    /// When the request started
+    pub started: Instant,
+}
+
+impl InFlightRequest {
+    /// Time elapsed since the request started
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Registry of in-flight requests
+///
+/// Cheap to clone; all clones share the same registry.
+#[derive(Clone)]
+pub struct RequestTracker {
+    inner: Arc<TrackerInner>,
+}
+
+struct TrackerInner {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, InFlightRequest>>,
+    cancel: watch::Sender<bool>,
+}
+
+impl RequestTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        let (cancel, _) = watch::channel(false);
+        Self {
+            inner: Arc::new(TrackerInner {
+                next_id: AtomicU64::new(0),
+                requests: Mutex::new(HashMap::new()),
+                cancel,
+            }),
+        }
+    }
+
+    /// Number of requests currently in flight
+    pub fn len(&self) -> usize {
+        self.inner.requests.lock().unwrap().len()
+    }
+
+    /// Check if no requests are in flight
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Snapshot of the in-flight requests, oldest first
+    pub fn snapshot(&self) -> Vec<InFlightRequest> {
+        let mut requests: Vec<_> = self
+            .inner
+            .requests
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        requests.sort_by_key(|r| r.started);
+        requests
+    }
+
+    /// Cancel every in-flight request, answering each with 503
+    pub fn cancel_all(&self) {
+        self.inner.cancel.send_replace(true);
+    }
+
+    // record a new request, returning a guard that removes it on drop
+    fn start(&self, method: Method, route: String) -> TrackedRequest {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = InFlightRequest {
+            method,
+            route,
+            started: Instant::now(),
+        };
+        self.inner.requests.lock().unwrap().insert(id, request);
+
+        TrackedRequest {
+            tracker: self.clone(),
+            id,
+        }
+    }
+
+    /// Log the in-flight requests at regular intervals until none remain
+    ///
+    /// When a deadline is given, requests still running once it passes are
+    /// cancelled.
+    pub async fn report_drain(&self, interval: Duration, deadline: Option<Duration>) {
+        let started = Instant::now();
+        let mut cancelled = false;
+
+        loop {
+            let remaining = self.snapshot();
+            if remaining.is_empty() {
+                tracing::info!("All in-flight requests drained");
+                return;
+            }
+
+            Self::log_remaining(&remaining);
+
+            if !cancelled && deadline.is_some_and(|d| started.elapsed() >= d) {
+                tracing::warn!(
+                    "Drain deadline exceeded, cancelling {} request(s)",
+                    remaining.len()
+                );
+                self.cancel_all();
+                cancelled = true;
+            }
+
+            tokio::time::sleep(Self::next_tick(interval, deadline, started, cancelled)).await;
+        }
+    }
+
+    // log a summary of the requests still running
+    fn log_remaining(remaining: &[InFlightRequest]) {
+        tracing::info!("Draining {} in-flight request(s)", remaining.len());
+        for request in remaining {
+            tracing::info!(
+                method = %request.method,
+                route = %request.route,
+                elapsed_ms = request.elapsed().as_millis() as u64,
+                "Request still in flight"
+            );
+        }
+    }
+
+    // sleep until the next report, waking early to enforce the deadline
+    fn next_tick(
+        interval: Duration,
+        deadline: Option<Duration>,
+        started: Instant,
+        cancelled: bool,
+    ) -> Duration {
+        match deadline {
+            Some(deadline) if !cancelled => {
+                interval.min(deadline.saturating_sub(started.elapsed()))
+            }
+            _ => interval,
+        }
+    }
+}
+
+impl Default for RequestTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// removes a request from the tracker when the handler completes or is dropped
+struct TrackedRequest {
+    tracker: RequestTracker,
+    id: u64,
+}
+
+impl Drop for TrackedRequest {
+    fn drop(&mut self) {
+        self.tracker.inner.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Middleware that records each request in the tracker
+///
+/// Install with `axum::middleware::from_fn_with_state`; `RustAPI::serve`
+/// does this automatically.
+pub async fn track_requests(
+    State(tracker): State<RequestTracker>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| req.uri().path().to_string());
+    let _guard = tracker.start(req.method().clone(), route);
+
+    let mut cancel = tracker.inner.cancel.subscribe();
+    if *cancel.borrow() {
+        return shutting_down();
+    }
+
+    tokio::select! {
+        response = next.run(req) => response,
+        _ = cancel.wait_for(|cancelled| *cancelled) => shutting_down(),
+    }
+}
+
+// response sent for requests cancelled by the drain deadline
+fn shutting_down() -> Response {
+    (StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn app(tracker: RequestTracker) -> Router {
+        Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(tracker, track_requests))
+    }
+
+    fn get_request(path: &str) -> Request {
+        Request::builder().uri(path).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_completed_requests_are_removed() {
+        let tracker = RequestTracker::new();
+        let response = app(tracker.clone())
+            .oneshot(get_request("/fast"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(tracker.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_request_is_tracked_and_cancelled() {
+        let tracker = RequestTracker::new();
+        let handle = tokio::spawn(app(tracker.clone()).oneshot(get_request("/slow")));
+
+        while tracker.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot[0].route, "/slow");
+        assert_eq!(snapshot[0].method, Method::GET);
+
+        tracker.cancel_all();
+        let response = handle.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(tracker.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_drain_enforces_deadline() {
+        let tracker = RequestTracker::new();
+        let handle = tokio::spawn(app(tracker.clone()).oneshot(get_request("/slow")));
+        while tracker.is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        tracker
+            .report_drain(Duration::from_millis(10), Some(Duration::from_millis(20)))
+            .await;
+
+        let response = handle.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
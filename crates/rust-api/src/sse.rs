@@ -0,0 +1,501 @@
+//! Server-sent events for RustAPI framework
+//!
+//! `SseBroadcaster` fans events out to every client streaming a topic. Each
+//! event gets an id, increasing per topic, and the most recent events of a
+//! topic are kept in a bounded replay buffer: a client that reconnects with
+//! the `Last-Event-ID` header (browsers' `EventSource` does this on its own)
+//! first receives the events it missed, then the live ones.
+//!
+//! Ids are prefixed with an id of the broadcaster instance (`<instance>-<n>`),
+//! so an id issued before a restart or by another replica is not mistaken for
+//! one of this instance: such clients start with the live events.
+//!
+//! A client that falls too far behind the live events is disconnected, so it
+//! reconnects and catches up from the replay buffer. Events older than the
+//! buffer are lost to it. Topics without clients or events for
+//! `SseBroadcaster::idle_timeout` are dropped with their buffer.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::FromRequestParts,
+    http::request::Parts,
+    response::sse::{Event, KeepAlive, KeepAliveStream, Sse},
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::{sync::broadcast, time::Instant};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    di::Injectable,
+    error::{Error, Result},
+};
+
+/// Default number of events kept per topic for reconnecting clients
+pub const DEFAULT_REPLAY: usize = 256;
+
+/// Default time after which a topic without clients or events is dropped
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+// how often idle topics are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stream of events sent to one client
+pub type EventStream = Pin<Box<dyn Stream<Item = std::result::Result<Event, Infallible>> + Send>>;
+
+/// Response streaming a topic, returned by `SseBroadcaster::subscribe`
+pub type SseResponse = Sse<KeepAliveStream<EventStream>>;
+
+// an event as published, shared by the replay buffer and the subscribers
+#[derive(Debug)]
+struct Published {
+    id: String,
+    seq: u64,
+    name: Option<String>,
+    data: String,
+}
+
+impl Published {
+    // the event as sent on the wire
+    fn to_event(&self) -> Event {
+        let event = Event::default().id(&self.id).data(&self.data);
+        match &self.name {
+            Some(name) => event.event(name),
+            None => event,
+        }
+    }
+}
+
+// the topics of a broadcaster, sharing one sequence so that ids stay
+// increasing when an idle topic is dropped and comes back
+#[derive(Default)]
+struct Topics {
+    by_name: HashMap<String, Topic>,
+    last_seq: u64,
+    swept: Option<Instant>,
+}
+
+// replay buffer and live channel of one topic
+struct Topic {
+    replay: VecDeque<Arc<Published>>,
+    // the last event dropped from the replay buffer
+    dropped: u64,
+    active: Instant,
+    live: broadcast::Sender<Arc<Published>>,
+}
+
+/// Publishes events to the clients streaming their topic
+///
+/// Cheap to clone; clones share topics. Register it in the container and
+/// inject it into handlers and services.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/orders/events")]
+/// async fn order_events(
+///     Inject(events): Inject<SseBroadcaster>,
+///     LastEventId(last): LastEventId,
+/// ) -> SseResponse {
+///     events.subscribe("orders", last.as_deref())
+/// }
+///
+/// events.publish("orders", &OrderShipped { id })?;
+/// ```
+#[derive(Clone)]
+pub struct SseBroadcaster {
+    instance: Arc<str>,
+    replay: usize,
+    retry: Option<Duration>,
+    idle_timeout: Duration,
+    clock: Arc<dyn Clock>,
+    topics: Arc<Mutex<Topics>>,
+}
+
+impl Injectable for SseBroadcaster {}
+
+impl Default for SseBroadcaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SseBroadcaster {
+    /// Create a broadcaster keeping `DEFAULT_REPLAY` events per topic
+    pub fn new() -> Self {
+        Self {
+            instance: uuid::Uuid::now_v7().simple().to_string().into(),
+            replay: DEFAULT_REPLAY,
+            retry: None,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            clock: Arc::new(SystemClock),
+            topics: Arc::default(),
+        }
+    }
+
+    /// Keep the last `events` events of each topic for reconnecting clients
+    pub fn replay_buffer(mut self, events: usize) -> Self {
+        self.replay = events;
+        self
+    }
+
+    /// Tell clients to wait `delay` before reconnecting
+    ///
+    /// Sent as the `retry` field at the start of every stream; without it,
+    /// clients use their default (about 3 seconds in browsers).
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = Some(delay);
+        self
+    }
+
+    /// Drop topics that had no clients and no events for `timeout`
+    ///
+    /// Their buffered events go with them.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Publish `data`, serialized as JSON, to the clients of `topic`
+    ///
+    /// Returns the id of the event.
+    pub fn publish<T: Serialize>(&self, topic: &str, data: &T) -> Result<String> {
+        self.send(topic, None, data)
+    }
+
+    /// Publish `data` as an event named `name`, for `addEventListener(name)`
+    pub fn publish_named<T: Serialize>(&self, topic: &str, name: &str, data: &T) -> Result<String> {
+        self.send(topic, Some(name.to_string()), data)
+    }
+
+    /// Number of topics currently held
+    pub fn tracked(&self) -> usize {
+        self.topics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .by_name
+            .len()
+    }
+
+    // assign the next id, buffer the event and send it to the live clients
+    fn send<T: Serialize>(&self, topic: &str, name: Option<String>, data: &T) -> Result<String> {
+        let data = serde_json::to_string(data)
+            .map_err(|e| Error::other(format!("Failed to serialize {} event: {}", topic, e)))?;
+        let mut topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+        topics.last_seq += 1;
+        let seq = topics.last_seq;
+        let topic = self.topic(&mut topics, topic);
+        let event = Arc::new(Published {
+            id: format!("{}-{}", self.instance, seq),
+            seq,
+            name,
+            data,
+        });
+        if self.replay > 0 {
+            if topic.replay.len() == self.replay {
+                if let Some(dropped) = topic.replay.pop_front() {
+                    topic.dropped = dropped.seq;
+                }
+            }
+            topic.replay.push_back(event.clone());
+        } else {
+            topic.dropped = seq;
+        }
+        // no clients is fine
+        let _ = topic.live.send(event.clone());
+        Ok(event.id.clone())
+    }
+
+    // the state of `name`, marked active, after dropping the idle topics
+    fn topic<'a>(&self, topics: &'a mut Topics, name: &str) -> &'a mut Topic {
+        let now = self.clock.now();
+        let swept = *topics.swept.get_or_insert(now);
+        if now.duration_since(swept) >= SWEEP_INTERVAL.min(self.idle_timeout) {
+            let idle_timeout = self.idle_timeout;
+            topics.by_name.retain(|_, topic| {
+                topic.live.receiver_count() > 0 || now.duration_since(topic.active) < idle_timeout
+            });
+            topics.swept = Some(now);
+        }
+        let topic = topics
+            .by_name
+            .entry(name.to_string())
+            .or_insert_with(|| Topic {
+                replay: VecDeque::with_capacity(self.replay),
+                dropped: 0,
+                active: now,
+                live: broadcast::channel(self.replay.max(16)).0,
+            });
+        topic.active = now;
+        topic
+    }
+
+    // the sequence number of an id this instance issued
+    fn seq(&self, id: &str) -> Option<u64> {
+        let (instance, seq) = id.trim().rsplit_once('-')?;
+        if instance != &*self.instance {
+            tracing::debug!("Event id {} was issued by another instance", id);
+            return None;
+        }
+        seq.parse().ok()
+    }
+
+    /// Stream `topic` to a client, starting after `last_event_id`
+    ///
+    /// With an id, the buffered events published after it are sent first;
+    /// without one, or with an id this broadcaster never issued (including
+    /// ids from before a restart or from another replica), only new events
+    /// are sent. Comments keep the connection alive while the topic is
+    /// quiet.
+    pub fn subscribe(&self, topic: &str, last_event_id: Option<&str>) -> SseResponse {
+        Sse::new(self.events(topic, last_event_id)).keep_alive(KeepAlive::default())
+    }
+
+    /// The events of `topic` after `last_event_id`, as in `subscribe`
+    pub fn events(&self, topic: &str, last_event_id: Option<&str>) -> EventStream {
+        let last_seq = last_event_id.and_then(|id| self.seq(id));
+        let mut topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+        let issued = topics.last_seq;
+        let state = self.topic(&mut topics, topic);
+
+        // taken under the lock, so no event is missed or sent twice
+        let missed: Vec<_> = match last_seq {
+            Some(last_seq) if last_seq <= issued => {
+                if last_seq < state.dropped {
+                    tracing::debug!(
+                        "Events of {} after {} are no longer buffered",
+                        topic,
+                        last_seq
+                    );
+                }
+                state
+                    .replay
+                    .iter()
+                    .filter(|event| event.seq > last_seq)
+                    .map(|event| Ok(event.to_event()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let receiver = state.live.subscribe();
+        drop(topics);
+
+        let retry = self.retry.map(|delay| Ok(Event::default().retry(delay)));
+        let topic = topic.to_string();
+        let live = stream::unfold(receiver, move |mut receiver| {
+            let topic = topic.clone();
+            async move {
+                match receiver.recv().await {
+                    Ok(event) => Some((Ok(event.to_event()), receiver)),
+                    // end the stream; the client resumes from the replay buffer
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::debug!(
+                            "SSE client of {} skipped {} events, disconnecting",
+                            topic,
+                            skipped
+                        );
+                        None
+                    }
+                    Err(broadcast::error::RecvError::Closed) => None,
+                }
+            }
+        });
+        stream::iter(retry.into_iter().chain(missed))
+            .chain(live)
+            .boxed()
+    }
+}
+
+/// The `Last-Event-ID` header of a reconnecting SSE client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LastEventId(pub Option<String>);
+
+impl<S: Send + Sync> FromRequestParts<S> for LastEventId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Infallible> {
+        Ok(Self(
+            parts
+                .headers
+                .get("last-event-id")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        extract::{Request, State},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn stream(
+        State(events): State<SseBroadcaster>,
+        LastEventId(last): LastEventId,
+    ) -> SseResponse {
+        events.subscribe("orders", last.as_deref())
+    }
+
+    // the body of a stream request, read until it contains `until`
+    async fn read_until(events: &SseBroadcaster, last: Option<&str>, until: &str) -> String {
+        let app = Router::new()
+            .route("/events", get(stream))
+            .with_state(events.clone());
+        let mut request = Request::get("/events");
+        if let Some(last) = last {
+            request = request.header("last-event-id", last);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+        let mut text = String::new();
+        while !text.contains(until) {
+            let chunk = tokio::time::timeout(Duration::from_secs(1), body.next())
+                .await
+                .expect("event not received")
+                .unwrap()
+                .unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        text
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_last_event_id() {
+        let events = SseBroadcaster::new().retry(Duration::from_millis(1500));
+        let ids: Vec<_> = (1..=3)
+            .map(|n| {
+                events
+                    .publish("orders", &serde_json::json!({ "n": n }))
+                    .unwrap()
+            })
+            .collect();
+        events.publish("users", &"ignored").unwrap();
+
+        let text = read_until(&events, Some(&ids[0]), &ids[2]).await;
+        assert!(text.starts_with("retry: 1500\n"), "{}", text);
+        assert!(!text.contains(&format!("id: {}\n", ids[0])), "{}", text);
+        assert!(
+            text.contains(&format!("id: {}\ndata: {{\"n\":2}}\n", ids[1])),
+            "{}",
+            text
+        );
+        assert!(!text.contains("ignored"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_live_events_follow_replay() {
+        let events = SseBroadcaster::new();
+        let first = events.publish("orders", &1).unwrap();
+        let (instance, _) = first.rsplit_once('-').unwrap();
+        let publisher = events.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            publisher.publish_named("orders", "shipped", &2).unwrap();
+        });
+
+        let start = format!("{}-0", instance);
+        let text = read_until(&events, Some(&start), "shipped").await;
+        assert!(
+            text.contains(&format!("id: {}\ndata: 1\n", first)),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains(&format!("id: {}-2\ndata: 2\nevent: shipped\n", instance)),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_buffer_is_bounded() {
+        let events = SseBroadcaster::new().replay_buffer(2);
+        let ids: Vec<_> = (1..=5)
+            .map(|n| events.publish("orders", &n).unwrap())
+            .collect();
+        assert!(ids[4].ends_with("-5"), "{}", ids[4]);
+        let text = read_until(&events, Some(&ids[0]), &ids[4]).await;
+        assert!(!text.contains(&ids[2]), "{}", text);
+        assert!(text.contains(&ids[3]), "{}", text);
+
+        // unknown ids start with the live events
+        let publisher = events.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            publisher.publish("orders", &6).unwrap();
+        });
+        let (instance, _) = ids[0].rsplit_once('-').unwrap();
+        let text = read_until(&events, Some(&format!("{}-99", instance)), "data: 6").await;
+        assert!(!text.contains(&ids[4]), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_ids_of_other_instances_are_not_replayed() {
+        // a restarted process or another replica numbers its events from 1 too
+        let before = SseBroadcaster::new();
+        let stale = before.publish("orders", &"old").unwrap();
+        let events = SseBroadcaster::new();
+        for n in 1..=3 {
+            events.publish("orders", &n).unwrap();
+        }
+        assert_ne!(stale, events.publish("users", &0).unwrap());
+
+        let publisher = events.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            publisher.publish("orders", &"live").unwrap();
+        });
+        let text = read_until(&events, Some(&stale), "live").await;
+        assert!(!text.contains("data: 2\n"), "{}", text);
+        assert!(!text.contains("data: 3\n"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_idle_topics_are_dropped() {
+        let clock = crate::clock::TestClock::new();
+        let events = SseBroadcaster::new()
+            .idle_timeout(Duration::from_secs(60))
+            .clock(Arc::new(clock.clone()));
+        events.publish("a", &1).unwrap();
+        let streaming = events.events("b", None);
+        assert_eq!(events.tracked(), 2);
+
+        clock.advance(Duration::from_secs(60));
+        let last = events.publish("c", &1).unwrap();
+        // "b" still has a client
+        assert_eq!(events.tracked(), 2);
+        assert!(last.ends_with("-2"), "{}", last);
+
+        drop(streaming);
+        clock.advance(Duration::from_secs(60));
+        events.publish("c", &2).unwrap();
+        assert_eq!(events.tracked(), 1);
+    }
+}
@@ -0,0 +1,641 @@
+//! Long-running tasks for RustAPI framework
+//!
+//! Operations that take too long for one request follow the asynchronous
+//! request pattern: the handler starts a task and answers 202 Accepted with
+//! the task's status URL, and clients poll that URL for progress until the
+//! task has succeeded or failed.
+//!
+//! `TaskTracker` records the tasks. It runs work itself with `spawn`, or
+//! hands out a `TaskHandle` with `create` for work that runs elsewhere, such
+//! as a queue consumer, which reports progress and the outcome through it.
+//! `TaskTracker::routes` serves the status endpoint.
+//!
+//! A task created during an authenticated request belongs to its principal,
+//! and only that principal can read its status.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::time::Instant;
+
+use crate::{
+    auth::Principal,
+    clock::{Clock, SystemClock},
+    context::RequestContext,
+    di::Injectable,
+    error::ApiError,
+    ids::{IdGenerator, UuidV7},
+    json::Json,
+    profile,
+    router::Router,
+};
+
+/// Default number of finished tasks kept for status requests
+pub const DEFAULT_RETAIN: usize = 1000;
+
+/// Default time after which an unfinished task without updates is failed
+pub const DEFAULT_ABANDON_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+// how often abandoned tasks are looked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stage of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    /// Accepted, not started yet
+    Pending,
+    /// Being worked on
+    Running,
+    /// Finished with a result
+    Succeeded,
+    /// Finished with an error
+    Failed,
+}
+
+impl TaskState {
+    /// Check whether the task has finished
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed)
+    }
+}
+
+/// Status of a task, as served by the status endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskStatus {
+    /// Id of the task
+    pub id: String,
+    /// Stage of the task
+    #[serde(rename = "status")]
+    pub state: TaskState,
+    /// Percentage done, if reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u8>,
+    /// What the task is doing, if reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Result of a succeeded task
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Error of a failed task, as in error responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+// the tasks and the order in which they finished
+#[derive(Default)]
+struct Tasks {
+    entries: HashMap<String, Entry>,
+    finished: VecDeque<String>,
+    swept: Option<Instant>,
+}
+
+// one task, with who may read it and when it last changed
+struct Entry {
+    status: TaskStatus,
+    owner: Option<String>,
+    updated: Instant,
+}
+
+impl Tasks {
+    // record that `id` finished, dropping the oldest finished tasks beyond
+    // `retain`
+    fn finished(&mut self, id: &str, retain: usize) {
+        self.finished.push_back(id.to_string());
+        while self.finished.len() > retain {
+            if let Some(expired) = self.finished.pop_front() {
+                self.entries.remove(&expired);
+            }
+        }
+    }
+}
+
+/// Registry of long-running tasks
+///
+/// Cheap to clone; clones share the tasks. Register it in the container
+/// and merge its `routes` into the app. Tasks are kept in memory: the most
+/// recent `retain` finished tasks stay available, unfinished tasks until
+/// they finish or go without updates for `abandon_after`, which fails them.
+///
+/// # Example
+///
+/// ```ignore
+/// let tasks = TaskTracker::new("/tasks");
+///
+/// #[post("/reports")]
+/// async fn create_report(Inject(tasks): Inject<TaskTracker>, Json(spec): Json<ReportSpec>) -> TaskAccepted {
+///     tasks.spawn(|task| async move {
+///         task.progress(10, "collecting data");
+///         let rows = collect(&spec).await?;
+///         task.progress(80, "rendering");
+///         Ok(render(rows).await?)
+///     })
+/// }
+///
+/// let app = App::new().merge(tasks.routes()).route(__create_report_route, post(create_report));
+/// ```
+#[derive(Clone)]
+pub struct TaskTracker {
+    path: String,
+    retain: usize,
+    abandon_after: Duration,
+    ids: Arc<dyn IdGenerator>,
+    clock: Arc<dyn Clock>,
+    tasks: Arc<Mutex<Tasks>>,
+}
+
+impl Injectable for TaskTracker {}
+
+impl TaskTracker {
+    /// Create a tracker whose status endpoint is `{path}/{id}`
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.trim_end_matches('/').to_string(),
+            retain: DEFAULT_RETAIN,
+            abandon_after: DEFAULT_ABANDON_AFTER,
+            ids: Arc::new(UuidV7),
+            clock: Arc::new(SystemClock),
+            tasks: Arc::default(),
+        }
+    }
+
+    /// Keep the `count` most recently finished tasks
+    pub fn retain(mut self, count: usize) -> Self {
+        self.retain = count;
+        self
+    }
+
+    /// Fail unfinished tasks that had no update for `timeout`
+    ///
+    /// Covers work that died without reporting, such as a crashed consumer.
+    pub fn abandon_after(mut self, timeout: Duration) -> Self {
+        self.abandon_after = timeout;
+        self
+    }
+
+    /// Generate task ids with `generator` instead of UUID v7
+    pub fn id_generator(mut self, generator: Arc<dyn IdGenerator>) -> Self {
+        self.ids = generator;
+        self
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Status URL of the task `id`
+    pub fn status_url(&self, id: &str) -> String {
+        format!("{}/{}", self.path, id)
+    }
+
+    /// Record a pending task, returning the handle to report on it with
+    ///
+    /// The task belongs to the principal of the current request, if any.
+    pub fn create(&self) -> TaskHandle {
+        let id = self.ids.generate();
+        let status = TaskStatus {
+            id: id.clone(),
+            state: TaskState::Pending,
+            progress: None,
+            message: None,
+            result: None,
+            error: None,
+        };
+        let entry = Entry {
+            status,
+            owner: RequestContext::current().and_then(|ctx| ctx.principal()),
+            updated: self.clock.now(),
+        };
+        let mut tasks = self.lock();
+        self.abandon_stale(&mut tasks);
+        tasks.entries.insert(id.clone(), entry);
+        TaskHandle {
+            tracker: self.clone(),
+            id,
+        }
+    }
+
+    /// Handle of an existing task, e.g. in the consumer running it
+    pub fn handle(&self, id: &str) -> Option<TaskHandle> {
+        self.lock().entries.contains_key(id).then(|| TaskHandle {
+            tracker: self.clone(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Run `work` in the background as a new task
+    ///
+    /// The task is running while `work` runs and finishes with its outcome,
+    /// or fails if `work` panics; return the `TaskAccepted` from the handler.
+    pub fn spawn<F, Fut, T>(&self, work: F) -> TaskAccepted
+    where
+        F: FnOnce(TaskHandle) -> Fut,
+        Fut: Future<Output = Result<T, ApiError>> + Send + 'static,
+        T: Serialize + Send + 'static,
+    {
+        let task = self.create();
+        let accepted = task.accepted();
+        task.start();
+        // run apart from the task reporting the outcome, so a panic is seen
+        let running = tokio::spawn(work(task.clone()));
+        tokio::spawn(async move {
+            match running.await {
+                Ok(Ok(result)) => task.succeed(&result),
+                Ok(Err(error)) => task.fail(&error),
+                Err(_) => {
+                    task.fail(&ApiError::internal("Task panicked").with_code("task_panicked"))
+                }
+            }
+        });
+        accepted
+    }
+
+    /// Current status of the task `id`
+    pub fn status(&self, id: &str) -> Option<TaskStatus> {
+        self.lock()
+            .entries
+            .get(id)
+            .map(|entry| entry.status.clone())
+    }
+
+    /// Router serving `GET {path}/{id}` with the task's status, or 404
+    /// `task_not_found`
+    ///
+    /// A task that belongs to a principal is only served to that principal;
+    /// for anyone else it is not found.
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route(&format!("{}/{{id}}", self.path), get(status))
+            .with_state(self.clone())
+    }
+
+    // change a task that has not finished yet
+    fn update(&self, id: &str, change: impl FnOnce(&mut TaskStatus)) {
+        let now = self.clock.now();
+        let mut tasks = self.lock();
+        let Some(entry) = tasks.entries.get_mut(id) else {
+            return;
+        };
+        if entry.status.state.is_finished() {
+            tracing::warn!(task = id, "Ignoring update of a finished task");
+            return;
+        }
+        change(&mut entry.status);
+        entry.updated = now;
+        if entry.status.state.is_finished() {
+            tasks.finished(id, self.retain);
+        }
+    }
+
+    // fail the unfinished tasks without updates for `abandon_after`, at
+    // most once per sweep interval
+    fn abandon_stale(&self, tasks: &mut Tasks) {
+        let now = self.clock.now();
+        let swept = *tasks.swept.get_or_insert(now);
+        if now.duration_since(swept) < SWEEP_INTERVAL.min(self.abandon_after) {
+            return;
+        }
+        tasks.swept = Some(now);
+        let stale: Vec<_> = tasks
+            .entries
+            .values()
+            .filter(|entry| {
+                !entry.status.state.is_finished()
+                    && now.duration_since(entry.updated) >= self.abandon_after
+            })
+            .map(|entry| entry.status.id.clone())
+            .collect();
+        let error = ApiError::internal("Task abandoned").with_code("task_abandoned");
+        for id in stale {
+            tracing::warn!(
+                task = id,
+                "Failing task without updates for {:?}",
+                self.abandon_after
+            );
+            if let Some(entry) = tasks.entries.get_mut(&id) {
+                entry.status.state = TaskState::Failed;
+                entry.status.error = Some(profile::redacted(&error).to_json()["error"].take());
+                entry.updated = now;
+            }
+            tasks.finished(&id, self.retain);
+        }
+    }
+
+    // the status of `id`, if `principal` may read it
+    fn status_for(&self, id: &str, principal: Option<&str>) -> Option<TaskStatus> {
+        let tasks = self.lock();
+        let entry = tasks.entries.get(id)?;
+        match &entry.owner {
+            Some(owner) if Some(owner.as_str()) != principal => None,
+            _ => Some(entry.status.clone()),
+        }
+    }
+
+    // the tasks, whether or not a holder panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_, Tasks> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// status endpoint
+async fn status(
+    State(tracker): State<TaskTracker>,
+    principal: Option<Principal>,
+    Path(id): Path<String>,
+) -> Result<Json<TaskStatus>, ApiError> {
+    let principal = principal
+        .map(|principal| principal.subject().to_string())
+        .or_else(|| RequestContext::current().and_then(|ctx| ctx.principal()));
+    tracker
+        .status_for(&id, principal.as_deref())
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::not_found(format!("Task {} not found", id)).with_code("task_not_found")
+        })
+}
+
+/// Reports the progress and outcome of one task
+///
+/// Cheap to clone. Updates after the task finished are ignored.
+#[derive(Clone)]
+pub struct TaskHandle {
+    tracker: TaskTracker,
+    id: String,
+}
+
+impl TaskHandle {
+    /// Id of the task
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// 202 response pointing the client at the task's status
+    pub fn accepted(&self) -> TaskAccepted {
+        TaskAccepted {
+            id: self.id.clone(),
+            status_url: self.tracker.status_url(&self.id),
+        }
+    }
+
+    /// Mark the task running
+    pub fn start(&self) {
+        self.tracker
+            .update(&self.id, |status| status.state = TaskState::Running);
+    }
+
+    /// Report the percentage done (capped at 100) and what is being done
+    pub fn progress(&self, percent: u8, message: impl Into<String>) {
+        let message = message.into();
+        self.tracker.update(&self.id, |status| {
+            status.state = TaskState::Running;
+            status.progress = Some(percent.min(100));
+            status.message = Some(message);
+        });
+    }
+
+    /// Finish the task with `result`, serialized as JSON
+    pub fn succeed<T: Serialize>(&self, result: &T) {
+        match serde_json::to_value(result) {
+            Ok(result) => self.tracker.update(&self.id, |status| {
+                status.state = TaskState::Succeeded;
+                status.progress = Some(100);
+                status.result = Some(result);
+            }),
+            Err(e) => self.fail(&ApiError::internal(format!(
+                "Failed to serialize task result: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Finish the task with `error`
+    ///
+    /// 5xx errors are logged, and stored redacted to their status and code,
+    /// since they reach the client through the status endpoint.
+    pub fn fail(&self, error: &ApiError) {
+        let error = match error.status().is_server_error() {
+            true => {
+                tracing::error!(
+                    task = self.id,
+                    status = error.status().as_u16(),
+                    code = error.code(),
+                    chain = ?error.chain(),
+                    "{}",
+                    error.message()
+                );
+                profile::redacted(error).to_json()["error"].take()
+            }
+            false => error.to_json()["error"].take(),
+        };
+        self.tracker.update(&self.id, |status| {
+            status.state = TaskState::Failed;
+            status.error = Some(error);
+        });
+    }
+
+    /// Current status of the task
+    pub fn status(&self) -> Option<TaskStatus> {
+        self.tracker.status(&self.id)
+    }
+}
+
+/// 202 Accepted response for a started task
+///
+/// Carries the status URL in the `Location` header and the body
+/// `{"id": ..., "status_url": ...}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TaskAccepted {
+    /// Id of the task
+    pub id: String,
+    /// Where the task's status is served
+    pub status_url: String,
+}
+
+impl IntoResponse for TaskAccepted {
+    fn into_response(self) -> Response {
+        let location = HeaderValue::from_str(&self.status_url);
+        let mut response = (StatusCode::ACCEPTED, Json(self)).into_response();
+        if let Ok(location) = location {
+            response.headers_mut().insert(header::LOCATION, location);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{body::Body, extract::Request};
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::ids::SequentialIds;
+
+    async fn get_json(router: &Router, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_spawned_task_reports_progress_and_result() {
+        let tasks = TaskTracker::new("/tasks/").id_generator(Arc::new(SequentialIds::new("t")));
+        let (resume, resumed) = oneshot::channel::<()>();
+        let accepted = tasks.spawn(|task| async move {
+            task.progress(40, "rendering");
+            resumed.await.ok();
+            Ok(serde_json::json!({ "pages": 3 }))
+        });
+
+        let response = accepted.clone().into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers()[header::LOCATION], accepted.status_url);
+
+        let routes = tasks.routes();
+        let url = accepted.status_url.clone();
+        let (status, body) = loop {
+            let (status, body) = get_json(&routes, &url).await;
+            if body["progress"] == 40 {
+                break (status, body);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "id": accepted.id,
+                "status": "running",
+                "progress": 40,
+                "message": "rendering",
+            })
+        );
+
+        resume.send(()).unwrap();
+        while !tasks.status(&accepted.id).unwrap().state.is_finished() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let (_, body) = get_json(&routes, &url).await;
+        assert_eq!(body["status"], "succeeded");
+        assert_eq!(body["result"]["pages"], 3);
+
+        let (status, body) = get_json(&routes, "/tasks/nope").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "task_not_found");
+    }
+
+    #[test]
+    fn test_handles_report_failures_and_finished_tasks_expire() {
+        let tasks = TaskTracker::new("/tasks").retain(1);
+        let first = tasks.create();
+        assert_eq!(first.status().unwrap().state, TaskState::Pending);
+
+        // e.g. a queue consumer picking the task up by id
+        let consumer = tasks.handle(first.id()).unwrap();
+        consumer.start();
+        consumer.fail(&ApiError::bad_request("Unknown format").with_code("unknown_format"));
+        consumer.succeed(&"ignored");
+        let status = first.status().unwrap();
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.error.unwrap()["code"], "unknown_format");
+        assert_eq!(status.result, None);
+
+        let second = tasks.create();
+        second.succeed(&1);
+        assert!(tasks.status(first.id()).is_none());
+        assert_eq!(second.status().unwrap().state, TaskState::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn test_panicking_task_fails() {
+        let tasks = TaskTracker::new("/tasks");
+        let accepted = tasks.spawn(|_| async move {
+            if true {
+                panic!("boom");
+            }
+            Ok(())
+        });
+        let status = loop {
+            let status = tasks.status(&accepted.id).unwrap();
+            if status.state.is_finished() {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.error.unwrap()["code"], "task_panicked");
+    }
+
+    #[test]
+    fn test_server_errors_are_redacted() {
+        let tasks = TaskTracker::new("/tasks");
+        let task = tasks.create();
+        task.fail(&ApiError::internal("connection to 10.0.0.7 refused"));
+        let error = task.status().unwrap().error.unwrap();
+        assert_eq!(error["message"], "Internal Server Error");
+        assert!(!error.to_string().contains("10.0.0.7"), "{}", error);
+    }
+
+    #[test]
+    fn test_abandoned_tasks_fail() {
+        let clock = crate::clock::TestClock::new();
+        let tasks = TaskTracker::new("/tasks")
+            .abandon_after(Duration::from_secs(300))
+            .clock(Arc::new(clock.clone()));
+        let lost = tasks.create();
+        let active = tasks.create();
+
+        clock.advance(Duration::from_secs(200));
+        active.progress(50, "halfway");
+        clock.advance(Duration::from_secs(100));
+        tasks.create();
+
+        let status = lost.status().unwrap();
+        assert_eq!(status.state, TaskState::Failed);
+        assert_eq!(status.error.unwrap()["code"], "task_abandoned");
+        assert_eq!(active.status().unwrap().state, TaskState::Running);
+    }
+
+    #[tokio::test]
+    async fn test_status_is_scoped_to_the_principal() {
+        let tasks = TaskTracker::new("/tasks");
+        let ctx = RequestContext::new("req-1");
+        ctx.set_principal("alice");
+        let task = ctx.sync_scope(|| tasks.create());
+
+        let read = |principal: Option<&str>| {
+            let mut request = Request::get(tasks.status_url(task.id()));
+            if let Some(principal) = principal {
+                request = request.extension(Principal::new(principal));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            tasks.routes().oneshot(request)
+        };
+        assert_eq!(read(Some("alice")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            read(Some("bob")).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(read(None).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+}
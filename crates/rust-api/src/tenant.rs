@@ -0,0 +1,382 @@
+//! Multi-tenancy for RustAPI framework
+//!
+//! A `TenantResolver` installed with `App::tenancy` identifies the tenant of
+//! every request from a header, the subdomain or a custom function and
+//! records it in the `RequestContext`. The tenant claim of the authenticated
+//! principal, when configured, takes precedence: a header or subdomain naming
+//! another tenant is rejected instead of honoured. Services registered with
+//! `Container::register_for_tenant` then take precedence for that tenant, so
+//! `Inject<T>` resolves each tenant's own database pool or configuration.
+
+use std::{collections::HashSet, fmt, sync::Arc};
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{context::RequestContext, error::ApiError, host};
+
+/// Longest tenant id accepted from a request
+pub const MAX_TENANT_LEN: usize = 64;
+
+// custom tenant lookup registered with `from_fn`
+type ResolveFn = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+// a place to look for the tenant id
+#[derive(Clone)]
+enum Source {
+    Header(String),
+    Subdomain(String),
+    Custom(ResolveFn),
+}
+
+impl Source {
+    // the tenant id this source finds in a request
+    fn find(&self, parts: &Parts) -> Option<String> {
+        match self {
+            Source::Header(name) => parts
+                .headers
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            Source::Subdomain(domain) => {
+                let host = host::strip_port(host::request_host(parts)?);
+                let label = host.strip_suffix(domain.as_str())?.strip_suffix('.')?;
+                (!label.contains('.')).then(|| label.to_ascii_lowercase())
+            }
+            Source::Custom(resolve) => resolve(parts),
+        }
+    }
+}
+
+/// How requests are mapped to tenants
+///
+/// The tenant claim set with `claim` wins; a request whose other sources
+/// name a different tenant is rejected with 403 `tenant_mismatch`. Without a
+/// claim, the other sources are tried in the order they were added and the
+/// first that finds a tenant id wins. Tenant ids are at most
+/// `MAX_TENANT_LEN` ASCII letters, digits, `-` or `_`. Requests without a
+/// tenant are rejected with 400 unless the tenant is optional; ids outside
+/// the allow-list, if one is set, are rejected with 404.
+///
+/// # Example
+///
+/// ```ignore
+/// let resolver = TenantResolver::new()
+///     .claim(|parts| parts.extensions.get::<Claims>().map(|c| c.tenant.clone()))
+///     .header("x-tenant-id")
+///     .subdomain("api.example.com")
+///     .allow(["acme", "globex"]);
+///
+/// let mut app = App::new().tenancy(resolver);
+/// app.container_mut().register(Arc::new(shared_pool));
+/// app.container_mut().register_for_tenant("acme", Arc::new(acme_pool));
+/// ```
+#[derive(Clone, Default)]
+pub struct TenantResolver {
+    claim: Option<ResolveFn>,
+    sources: Vec<Source>,
+    optional: bool,
+    allowed: Option<Arc<HashSet<String>>>,
+}
+
+impl TenantResolver {
+    /// Create a resolver with no sources
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the tenant id from the request header `name`
+    pub fn header(mut self, name: impl Into<String>) -> Self {
+        self.sources.push(Source::Header(name.into()));
+        self
+    }
+
+    /// Use the subdomain of `domain` the request was sent to, so that
+    /// `acme.example.com` is tenant `acme` for `subdomain("example.com")`
+    pub fn subdomain(mut self, domain: impl Into<String>) -> Self {
+        self.sources
+            .push(Source::Subdomain(domain.into().to_ascii_lowercase()));
+        self
+    }
+
+    /// Take the tenant id from the authenticated principal with `resolve`
+    ///
+    /// Authentication middleware that runs first verifies the token and
+    /// stores its claims in the request extensions, for `resolve` to read.
+    /// The claim takes precedence over every other source.
+    pub fn claim(
+        mut self,
+        resolve: impl Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.claim = Some(Arc::new(resolve));
+        self
+    }
+
+    /// Find the tenant id with `resolve`
+    ///
+    /// Use `claim` instead for tenant ids of authenticated principals.
+    pub fn from_fn(
+        mut self,
+        resolve: impl Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.sources.push(Source::Custom(Arc::new(resolve)));
+        self
+    }
+
+    /// Let requests without a tenant through
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Only accept these tenant ids
+    pub fn allow<I, T>(mut self, tenants: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.allowed = Some(Arc::new(tenants.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// The tenant of a request, `Ok(None)` if it has none
+    pub fn resolve(&self, parts: &Parts) -> Result<Option<String>, ApiError> {
+        let requested = self.sources.iter().find_map(|source| source.find(parts));
+        let claimed = self.claim.as_ref().and_then(|claim| claim(parts));
+        let tenant = match (claimed, requested) {
+            (Some(claimed), Some(requested)) if claimed != requested => {
+                return Err(
+                    ApiError::new(StatusCode::FORBIDDEN, "Tenant not accessible")
+                        .with_code("tenant_mismatch"),
+                );
+            }
+            (Some(tenant), _) | (None, Some(tenant)) => tenant,
+            (None, None) => return Ok(None),
+        };
+        let valid = !tenant.is_empty()
+            && tenant.len() <= MAX_TENANT_LEN
+            && tenant
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "Invalid tenant")
+                .with_code("invalid_tenant"));
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.contains(&tenant) {
+                return Err(ApiError::new(StatusCode::NOT_FOUND, "Unknown tenant")
+                    .with_code("unknown_tenant"));
+            }
+        }
+        Ok(Some(tenant))
+    }
+}
+
+impl fmt::Debug for TenantResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantResolver")
+            .field("claim", &self.claim.is_some())
+            .field("sources", &self.sources.len())
+            .field("optional", &self.optional)
+            .field("allowed", &self.allowed)
+            .finish()
+    }
+}
+
+// record the tenant of the request in its context
+pub(crate) async fn resolve_tenant(
+    State(resolver): State<TenantResolver>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    match resolver.resolve(&parts) {
+        Ok(Some(tenant)) => {
+            if let Some(ctx) = RequestContext::current() {
+                ctx.set_tenant(tenant);
+            }
+        }
+        Ok(None) if resolver.optional => {}
+        Ok(None) => {
+            return ApiError::new(StatusCode::BAD_REQUEST, "Tenant required")
+                .with_code("tenant_required")
+                .into_response()
+        }
+        Err(e) => return e.into_response(),
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Extractor for the tenant of the current request
+///
+/// Rejects with 400 Bad Request when the request has no tenant.
+///
+/// # Example
+///
+/// ```ignore
+/// #[get("/settings")]
+/// async fn settings(Tenant(tenant): Tenant) -> String {
+///     format!("settings of {}", tenant)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    /// The tenant of the request being handled by the current task
+    pub fn current() -> Option<Self> {
+        RequestContext::with_current(|ctx| ctx.tenant())
+            .flatten()
+            .map(Tenant)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(_parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Tenant::current().ok_or_else(|| {
+            ApiError::new(StatusCode::BAD_REQUEST, "Tenant required")
+                .with_code("tenant_required")
+                .rejection()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app::App, di::Injectable, extract::Inject};
+
+    fn parts(host: &str, header: Option<&str>) -> Parts {
+        let mut request = Request::get("/").header("host", host);
+        if let Some(tenant) = header {
+            request = request.header("x-tenant-id", tenant);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_resolve_sources_in_order() {
+        let resolver = TenantResolver::new()
+            .header("x-tenant-id")
+            .subdomain("example.com");
+        let resolve = |host, header| resolver.resolve(&parts(host, header)).unwrap();
+        assert_eq!(resolve("Acme.example.com:8080", None).unwrap(), "acme");
+        assert_eq!(
+            resolve("acme.example.com", Some("globex")).unwrap(),
+            "globex"
+        );
+        assert_eq!(resolve("example.com", None), None);
+        assert_eq!(resolve("a.b.example.com", None), None);
+        assert_eq!(resolve("acme.other.com", None), None);
+
+        let custom = TenantResolver::new().from_fn(|_| Some("initech".to_string()));
+        assert_eq!(
+            custom.resolve(&parts("x", None)).unwrap().unwrap(),
+            "initech"
+        );
+    }
+
+    #[test]
+    fn test_claim_takes_precedence() {
+        let resolver = TenantResolver::new()
+            .claim(|parts| {
+                parts
+                    .headers
+                    .get("x-verified-tenant")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            })
+            .header("x-tenant-id")
+            .subdomain("example.com");
+        let claimed = |host: &str, header: Option<&str>| {
+            let mut parts = parts(host, header);
+            parts
+                .headers
+                .insert("x-verified-tenant", "acme".parse().unwrap());
+            resolver.resolve(&parts)
+        };
+        assert_eq!(claimed("x", None).unwrap().unwrap(), "acme");
+        assert_eq!(claimed("x", Some("acme")).unwrap().unwrap(), "acme");
+        let error = claimed("x", Some("globex")).unwrap_err();
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        assert_eq!(error.code(), "tenant_mismatch");
+        assert!(claimed("globex.example.com", None).is_err());
+
+        // anonymous requests fall back to the other sources
+        let anonymous = resolver.resolve(&parts("x", Some("globex"))).unwrap();
+        assert_eq!(anonymous.unwrap(), "globex");
+    }
+
+    #[test]
+    fn test_resolve_rejects_invalid_and_unknown_tenants() {
+        let resolver = TenantResolver::new().header("x-tenant-id").allow(["acme"]);
+        let error = resolver.resolve(&parts("x", Some("../etc"))).unwrap_err();
+        assert_eq!(error.code(), "invalid_tenant");
+        let error = resolver.resolve(&parts("x", Some("globex"))).unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+
+    struct Pool(&'static str);
+
+    impl Injectable for Pool {}
+
+    async fn call(router: &Router, tenant: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::get("/pool");
+        if let Some(tenant) = tenant {
+            request = request.header("x-tenant-id", tenant);
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scoped_injection() {
+        async fn pool(Tenant(tenant): Tenant, Inject(pool): Inject<Pool>) -> String {
+            format!("{}:{}", tenant, pool.0)
+        }
+
+        let mut app = App::new()
+            .route("/pool", get(pool))
+            .tenancy(TenantResolver::new().header("x-tenant-id"));
+        app.container_mut().register(Arc::new(Pool("shared")));
+        app.container_mut()
+            .register_for_tenant("acme", Arc::new(Pool("acme-db")));
+        let router = app.build();
+
+        assert_eq!(call(&router, Some("acme")).await.1, "acme:acme-db");
+        assert_eq!(call(&router, Some("globex")).await.1, "globex:shared");
+        assert_eq!(call(&router, None).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_optional_tenant() {
+        let router = App::new()
+            .route(
+                "/pool",
+                get(|| async { format!("{:?}", Tenant::current()) }),
+            )
+            .tenancy(TenantResolver::new().header("x-tenant-id").optional())
+            .build();
+        assert_eq!(
+            call(&router, None).await,
+            (StatusCode::OK, "None".to_string())
+        );
+    }
+}
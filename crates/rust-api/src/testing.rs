@@ -0,0 +1,1464 @@
+//! Test utilities for RustAPI framework
+//!
+//! Helpers for black-box tests: `spawn` boots the real server on an
+//! ephemeral port, `TestClient` calls an app in-process, and both take the
+//! same fluent `Request` builder;
+//! `TestContainer` swaps services for `#[mockable]` test doubles,
+//! `MockHttpClient` scripts third-party APIs, and `assert_openapi_snapshot`
+//! guards the public API surface.
+
+use std::{
+    fmt, fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request as HttpRequest, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{sync::oneshot, task::JoinHandle};
+use tower::ServiceExt;
+use tracing::{
+    field::{Field, Visit},
+    instrument::WithSubscriber,
+    span, Dispatch, Event, Level, Metadata, Subscriber,
+};
+
+use crate::{
+    app::App,
+    client::{ClientError, ClientRequest, ClientResponse, HttpClient},
+    di::{Container, Injectable},
+    error::{Error, Result},
+    lifecycle::BoxFuture,
+    server::RustAPI,
+};
+
+/// How long `spawn` waits for the server to become ready
+pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set to `1` to rewrite snapshots instead of comparing against them
+pub const UPDATE_SNAPSHOTS_ENV: &str = "UPDATE_SNAPSHOTS";
+
+/// A server running in the background for the duration of a test
+///
+/// Call `shutdown()` at the end of the test: it stops the server and fails
+/// the test if the server returned an error or logged at ERROR level while
+/// handling requests. Dropping the handle stops the server without checks.
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: Option<oneshot::Sender<()>>,
+    handle: Option<JoinHandle<Result<()>>>,
+    errors: ErrorLog,
+}
+
+impl TestServer {
+    /// Address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL, e.g. `http://127.0.0.1:49152`
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Absolute URL for a path
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url(), path)
+    }
+
+    /// Send a request to the server over a fresh connection
+    ///
+    /// Needs the `testing` feature, outside this crate's own tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server cannot be reached.
+    #[cfg(any(test, feature = "testing"))]
+    pub async fn send(&self, request: Request) -> TestResponse {
+        let stream = tokio::net::TcpStream::connect(self.addr)
+            .await
+            .expect("test server is reachable");
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+                .await
+                .expect("HTTP handshake with test server");
+        tokio::spawn(connection);
+
+        let mut request = request.into_http();
+        let authority = self.addr.to_string();
+        request
+            .headers_mut()
+            .entry(header::HOST)
+            .or_insert(HeaderValue::try_from(authority).expect("valid host"));
+        let response = sender
+            .send_request(request)
+            .await
+            .expect("test server answered");
+        TestResponse::read(response.map(Body::new)).await
+    }
+
+    /// ERROR-level log messages emitted while handling requests so far
+    pub fn error_logs(&self) -> Vec<String> {
+        self.errors.messages()
+    }
+
+    /// Stop the server and wait for it to drain
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server failed or logged errors while handling requests.
+    pub async fn shutdown(mut self) {
+        let result = self.stop_and_wait().await;
+        if let Err(e) = result {
+            panic!("test server failed: {}", e);
+        }
+        let errors = self.errors.messages();
+        assert!(
+            errors.is_empty(),
+            "test server logged {} error(s):\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+
+    // signal shutdown and wait for the serve task
+    async fn stop_and_wait(&mut self) -> Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        match self.handle.take() {
+            Some(handle) => handle
+                .await
+                .map_err(|e| Error::server_error(format!("Server task panicked: {}", e)))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for TestServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+}
+
+/// Boot an application on `127.0.0.1` with an OS-assigned port
+///
+/// # Example
+///
+/// ```ignore
+/// #[tokio::test]
+/// async fn creates_users() {
+///     let server = testing::spawn(build_app()).await;
+///     let response = reqwest::Client::new()
+///         .post(server.url("/users"))
+///         .json(&json!({ "name": "Ada" }))
+///         .send()
+///         .await
+///         .unwrap();
+///     assert_eq!(response.status(), 201);
+///     server.shutdown().await;
+/// }
+/// ```
+pub async fn spawn(app: App) -> TestServer {
+    spawn_server(RustAPI::new(app.build())).await
+}
+
+/// Boot a configured server (start hooks, health probes, ...) for a test
+///
+/// The host, port and shutdown signal of `server` are replaced. Waits until
+/// every start hook has succeeded.
+///
+/// # Panics
+///
+/// Panics if the server cannot bind, fails during start-up, or is not ready
+/// within `READY_TIMEOUT`.
+pub async fn spawn_server(server: RustAPI) -> TestServer {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("failed to bind test server");
+    let addr = listener.local_addr().expect("test server has no address");
+
+    let errors = ErrorLog::default();
+    let dispatch = Dispatch::new(errors.clone());
+    let (stop, stopped) = oneshot::channel();
+    let server = server
+        .map_router(|router| {
+            router.layer(middleware::from_fn_with_state(
+                dispatch.clone(),
+                capture_logs,
+            ))
+        })
+        .shutdown_signal(async {
+            let _ = stopped.await;
+        });
+    let readiness = server.readiness();
+    let mut handle = tokio::spawn(
+        server
+            .serve_with_listener(listener)
+            .with_subscriber(dispatch),
+    );
+
+    let ready = tokio::time::timeout(READY_TIMEOUT, async {
+        while !readiness.is_ready() {
+            if handle.is_finished() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await;
+
+    if handle.is_finished() {
+        match (&mut handle).await {
+            Ok(Err(e)) => panic!("test server failed to start: {}", e),
+            Ok(Ok(())) => panic!("test server stopped during start-up"),
+            Err(e) => panic!("test server panicked during start-up: {}", e),
+        }
+    }
+    assert!(
+        ready.is_ok(),
+        "test server not ready within {:?}",
+        READY_TIMEOUT
+    );
+
+    TestServer {
+        addr,
+        stop: Some(stop),
+        handle: Some(handle),
+        errors,
+    }
+}
+
+/// Fluent request builder for `TestClient` and `TestServer`
+///
+/// # Example
+///
+/// ```ignore
+/// let response = client
+///     .send(Request::post("/users").bearer(&token).json(&json!({ "name": "Ada" })))
+///     .await;
+/// assert_eq!(response.status(), StatusCode::CREATED);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: Method,
+    uri: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Request {
+    /// Request with the given method and path (plus optional query)
+    pub fn new(method: Method, uri: impl Into<String>) -> Self {
+        Self {
+            method,
+            uri: uri.into(),
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// GET request
+    pub fn get(uri: impl Into<String>) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    /// POST request
+    pub fn post(uri: impl Into<String>) -> Self {
+        Self::new(Method::POST, uri)
+    }
+
+    /// PUT request
+    pub fn put(uri: impl Into<String>) -> Self {
+        Self::new(Method::PUT, uri)
+    }
+
+    /// PATCH request
+    pub fn patch(uri: impl Into<String>) -> Self {
+        Self::new(Method::PATCH, uri)
+    }
+
+    /// DELETE request
+    pub fn delete(uri: impl Into<String>) -> Self {
+        Self::new(Method::DELETE, uri)
+    }
+
+    /// Add a header
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or value is not a valid header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(
+            HeaderName::try_from(name).expect("valid header name"),
+            HeaderValue::try_from(value).expect("valid header value"),
+        );
+        self
+    }
+
+    /// Authenticate with a bearer token
+    pub fn bearer(self, token: &str) -> Self {
+        self.header("authorization", &format!("Bearer {}", token))
+    }
+
+    /// Authenticate with HTTP Basic credentials
+    pub fn basic_auth(self, user: &str, password: &str) -> Self {
+        let credentials = base64_encode(format!("{}:{}", user, password).as_bytes());
+        self.header("authorization", &format!("Basic {}", credentials))
+    }
+
+    /// Send an API key in the `X-API-Key` header
+    pub fn api_key(self, key: &str) -> Self {
+        self.header("x-api-key", key)
+    }
+
+    /// Add a cookie; repeated calls share one `Cookie` header
+    pub fn cookie(mut self, name: &str, value: &str) -> Self {
+        let cookie = match self.headers.get(header::COOKIE) {
+            Some(existing) => format!(
+                "{}; {}={}",
+                existing.to_str().unwrap_or_default(),
+                name,
+                value
+            ),
+            None => format!("{}={}", name, value),
+        };
+        self.headers.insert(
+            header::COOKIE,
+            HeaderValue::try_from(cookie).expect("valid cookie"),
+        );
+        self
+    }
+
+    /// Set a raw body
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set a JSON body and content type
+    ///
+    /// # Panics
+    ///
+    /// Panics if `body` cannot be serialized.
+    pub fn json(self, body: &impl Serialize) -> Self {
+        let body = serde_json::to_vec(body).expect("request body serializes to JSON");
+        self.content_type("application/json").body(body)
+    }
+
+    /// Set a `multipart/form-data` body
+    pub fn multipart(self, form: Multipart) -> Self {
+        let content_type = form.content_type();
+        self.content_type(&content_type).body(form.into_bytes())
+    }
+
+    // replace the content type
+    fn content_type(mut self, value: &str) -> Self {
+        self.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::try_from(value).expect("valid content type"),
+        );
+        self
+    }
+
+    /// Convert into an `http::Request`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the URI is invalid.
+    pub fn into_http(self) -> HttpRequest {
+        let mut request = HttpRequest::new(Body::from(self.body));
+        *request.method_mut() = self.method;
+        *request.uri_mut() = self.uri.parse().expect("valid request URI");
+        *request.headers_mut() = self.headers;
+        request
+    }
+}
+
+/// `multipart/form-data` body for `Request::multipart`
+#[derive(Debug, Clone)]
+pub struct Multipart {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl Multipart {
+    /// Create an empty form
+    pub fn new() -> Self {
+        Self {
+            boundary: "rust-api-test-boundary".to_string(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Add a text field
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.part(
+            &format!("form-data; name=\"{}\"", name),
+            None,
+            value.as_bytes(),
+        );
+        self
+    }
+
+    /// Add a file field
+    pub fn file(mut self, name: &str, filename: &str, content_type: &str, data: &[u8]) -> Self {
+        self.part(
+            &format!("form-data; name=\"{}\"; filename=\"{}\"", name, filename),
+            Some(content_type),
+            data,
+        );
+        self
+    }
+
+    /// Content type including the boundary
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Encoded body, closing boundary included
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.body
+            .extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        self.body
+    }
+
+    // append one part
+    fn part(&mut self, disposition: &str, content_type: Option<&str>, data: &[u8]) {
+        let mut head = format!(
+            "--{}\r\ncontent-disposition: {}\r\n",
+            self.boundary, disposition
+        );
+        if let Some(content_type) = content_type {
+            head.push_str(&format!("content-type: {}\r\n", content_type));
+        }
+        head.push_str("\r\n");
+        self.body.extend_from_slice(head.as_bytes());
+        self.body.extend_from_slice(data);
+        self.body.extend_from_slice(b"\r\n");
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// standard base64 with padding
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Buffered response returned by `TestClient` and `TestServer`
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    // buffer a response
+    async fn read(response: Response) -> Self {
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .expect("response body is readable");
+        Self {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+
+    /// Response status
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Response headers
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// A header value as text, if present and valid
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Raw body
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Body as text (lossy)
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Body parsed as JSON
+    ///
+    /// # Panics
+    ///
+    /// Panics with the body text if it is not valid JSON for `T`.
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            panic!("response is not the expected JSON ({}): {}", e, self.text())
+        })
+    }
+}
+
+/// In-process client calling a router without opening a socket
+///
+/// # Example
+///
+/// ```ignore
+/// let client = TestClient::new(build_app());
+/// let response = client.send(Request::get("/users/1").bearer(&token)).await;
+/// let user: User = response.json();
+/// ```
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    /// Build the application and call it in-process
+    pub fn new(app: App) -> Self {
+        Self::from_router(app.build())
+    }
+
+    /// Call an already built router
+    pub fn from_router(router: Router) -> Self {
+        Self { router }
+    }
+
+    /// Send a request and buffer the response
+    pub async fn send(&self, request: Request) -> TestResponse {
+        let response = self
+            .router
+            .clone()
+            .oneshot(request.into_http())
+            .await
+            .unwrap_or_else(|never| match never {});
+        TestResponse::read(response).await
+    }
+}
+
+/// Record of the calls a mock received, in order
+///
+/// Used by the mocks generated with `#[mockable]`. Arguments are recorded
+/// in their `Debug` form, or as `_` for types without `Debug`.
+#[derive(Debug, Default)]
+pub struct CallLog {
+    calls: Mutex<Vec<(&'static str, Vec<String>)>>,
+}
+
+impl CallLog {
+    /// Record a call to `method` with its formatted arguments
+    pub fn record(&self, method: &'static str, args: Vec<String>) {
+        self.calls
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((method, args));
+    }
+
+    /// Names of the methods called, in call order
+    pub fn all(&self) -> Vec<&'static str> {
+        let calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        calls.iter().map(|(method, _)| *method).collect()
+    }
+
+    /// Arguments of each call to `method`, in call order
+    pub fn args(&self, method: &str) -> Vec<Vec<String>> {
+        let calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        calls
+            .iter()
+            .filter(|(called, _)| *called == method)
+            .map(|(_, args)| args.clone())
+            .collect()
+    }
+
+    /// Whether `method` was called with arguments formatting as `args`
+    pub fn called_with(&self, method: &str, args: &[&str]) -> bool {
+        self.args(method).iter().any(|call| call == args)
+    }
+
+    /// Number of calls to `method`
+    pub fn count(&self, method: &str) -> usize {
+        self.all().iter().filter(|m| **m == method).count()
+    }
+
+    /// Whether `method` was called at least once
+    pub fn called(&self, method: &str) -> bool {
+        self.count(method) > 0
+    }
+
+    /// Total number of calls
+    pub fn len(&self) -> usize {
+        self.all().len()
+    }
+
+    /// Check if no calls were received
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Argument of a mocked call, formatted for the `CallLog`
+///
+/// Used by the mocks generated with `#[mockable]`: `(&&MockArg(&arg))
+/// .describe()` picks `Debug` formatting when the type has it.
+#[doc(hidden)]
+pub struct MockArg<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait DescribeDebug {
+    fn describe(&self) -> String;
+}
+
+impl<T: fmt::Debug + ?Sized> DescribeDebug for &MockArg<'_, T> {
+    fn describe(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+#[doc(hidden)]
+pub trait DescribeOpaque {
+    fn describe(&self) -> String;
+}
+
+impl<T: ?Sized> DescribeOpaque for MockArg<'_, T> {
+    fn describe(&self) -> String {
+        "_".to_string()
+    }
+}
+
+/// Container builder for tests that swaps services for mocks
+///
+/// # Example
+///
+/// ```ignore
+/// let repo = Arc::new(MockUserRepo::new().on_find(|_| None));
+/// let app = TestContainer::new()
+///     .with_mock::<dyn UserRepo>(repo.clone())
+///     .install(build_app());
+/// let server = testing::spawn(app).await;
+/// ```
+#[derive(Clone, Default)]
+pub struct TestContainer {
+    container: Container,
+}
+
+impl TestContainer {
+    /// Create an empty test container
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a real service
+    pub fn with<T: Injectable + ?Sized>(mut self, service: Arc<T>) -> Self {
+        self.container.register(service);
+        self
+    }
+
+    /// Register a mock as the implementation of `T`
+    ///
+    /// `T` is usually the trait object the application resolves, e.g.
+    /// `with_mock::<dyn UserRepo>(mock)`. Keep a clone of the `Arc` to
+    /// inspect the recorded calls afterwards.
+    pub fn with_mock<T: Injectable + ?Sized>(self, mock: Arc<T>) -> Self {
+        self.with(mock)
+    }
+
+    /// Get a reference to the underlying container
+    pub fn container(&self) -> &Container {
+        &self.container
+    }
+
+    /// Unwrap the underlying container
+    pub fn into_container(self) -> Container {
+        self.container
+    }
+
+    /// Install the registered services into an application
+    ///
+    /// Services registered here replace the application's; the rest of the
+    /// application's services are kept.
+    pub fn install(self, mut app: App) -> App {
+        let mut container = self.container;
+        container.inherit_from(app.container());
+        *app.container_mut() = container;
+        app
+    }
+}
+
+/// Expected outbound request and the response scripted for it
+///
+/// Matches on method and full URL (including the query string), plus any
+/// headers and body given.
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    method: Method,
+    url: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Option<Bytes>,
+    times: Option<usize>,
+    response: MockResponse,
+}
+
+impl Expectation {
+    /// Expect a request with the given method and URL, answered with 200
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method,
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+            times: None,
+            response: MockResponse::new(StatusCode::OK),
+        }
+    }
+
+    /// Expect a GET request
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    /// Expect a POST request
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    /// Expect a PUT request
+    pub fn put(url: impl Into<String>) -> Self {
+        Self::new(Method::PUT, url)
+    }
+
+    /// Expect a DELETE request
+    pub fn delete(url: impl Into<String>) -> Self {
+        Self::new(Method::DELETE, url)
+    }
+
+    /// Only match requests carrying this header value
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or value is not a valid header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("valid header name"),
+            HeaderValue::try_from(value).expect("valid header value"),
+        ));
+        self
+    }
+
+    /// Only match requests with exactly this body
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Only match requests whose body is this JSON value
+    pub fn json_body(self, body: &serde_json::Value) -> Self {
+        self.body(body.to_string())
+    }
+
+    /// Expect exactly `n` matching requests; later ones fall through
+    pub fn times(mut self, n: usize) -> Self {
+        self.times = Some(n);
+        self
+    }
+
+    /// Answer matching requests with `response`
+    pub fn respond(mut self, response: MockResponse) -> Self {
+        self.response = response;
+        self
+    }
+
+    // whether a request satisfies this expectation
+    fn matches(&self, request: &ClientRequest) -> bool {
+        request.method() == self.method
+            && request.uri().to_string() == self.url
+            && self
+                .headers
+                .iter()
+                .all(|(name, value)| request.headers().get(name) == Some(value))
+            && self.body.as_ref().is_none_or(|body| {
+                match (
+                    serde_json::from_slice::<serde_json::Value>(body),
+                    serde_json::from_slice::<serde_json::Value>(request.body()),
+                ) {
+                    // JSON bodies compare structurally, ignoring formatting
+                    (Ok(expected), Ok(actual)) => expected == actual,
+                    _ => body == request.body(),
+                }
+            })
+    }
+}
+
+/// Scripted response of a `MockHttpClient`
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Bytes,
+}
+
+impl MockResponse {
+    /// Empty response with the given status
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Add a response header
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or value is not a valid header.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("valid header name"),
+            HeaderValue::try_from(value).expect("valid header value"),
+        ));
+        self
+    }
+
+    /// Set the response body
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set a JSON response body and content type
+    pub fn json(self, body: &serde_json::Value) -> Self {
+        self.header("content-type", "application/json")
+            .body(body.to_string())
+    }
+
+    // build the response handed to the caller
+    fn to_response(&self) -> ClientResponse {
+        let mut response = ClientResponse::new(self.body.clone());
+        *response.status_mut() = self.status;
+        for (name, value) in &self.headers {
+            response.headers_mut().append(name, value.clone());
+        }
+        response
+    }
+}
+
+/// `HttpClient` fake answering from scripted expectations
+///
+/// Every request is recorded. Requests matching no expectation fail with
+/// `ClientError::Other`; `verify()` reports them along with expectations
+/// that were not met. Clones share expectations and recordings.
+///
+/// # Example
+///
+/// ```ignore
+/// let client = MockHttpClient::new().expect(
+///     Expectation::post("https://payments.example.com/charges")
+///         .header("authorization", "Bearer test")
+///         .json_body(&json!({ "amount": 500 }))
+///         .times(1)
+///         .respond(MockResponse::new(StatusCode::CREATED).json(&json!({ "id": "ch_1" }))),
+/// );
+/// container.register::<dyn HttpClient>(Arc::new(client.clone()));
+/// // ... exercise the service ...
+/// client.verify();
+/// ```
+#[derive(Clone, Default)]
+pub struct MockHttpClient {
+    inner: Arc<Mutex<MockState>>,
+}
+
+// expectations with their hit counts, and everything received
+#[derive(Default)]
+struct MockState {
+    expectations: Vec<(Expectation, usize)>,
+    requests: Vec<ClientRequest>,
+    unmatched: Vec<String>,
+}
+
+impl MockHttpClient {
+    /// Create a client with no expectations
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an expectation; earlier expectations are tried first
+    pub fn expect(self, expectation: Expectation) -> Self {
+        self.state().expectations.push((expectation, 0));
+        self
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<ClientRequest> {
+        self.state().requests.clone()
+    }
+
+    /// Assert every expectation was met and no request went unmatched
+    ///
+    /// # Panics
+    ///
+    /// Panics listing the unmet expectations and unmatched requests.
+    pub fn verify(&self) {
+        let state = self.state();
+        let mut problems: Vec<String> = state
+            .expectations
+            .iter()
+            .filter(|(e, hits)| e.times.is_some_and(|n| n != *hits))
+            .map(|(e, hits)| {
+                format!(
+                    "expected {} {} {} time(s), got {}",
+                    e.method,
+                    e.url,
+                    e.times.unwrap_or_default(),
+                    hits
+                )
+            })
+            .collect();
+        problems.extend(
+            state
+                .unmatched
+                .iter()
+                .map(|request| format!("unexpected request {}", request)),
+        );
+        assert!(
+            problems.is_empty(),
+            "MockHttpClient verification failed:\n{}",
+            problems.join("\n")
+        );
+    }
+
+    // lock the shared state
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl HttpClient for MockHttpClient {
+    fn send(
+        &self,
+        request: ClientRequest,
+    ) -> BoxFuture<'_, std::result::Result<ClientResponse, ClientError>> {
+        let mut state = self.state();
+        let matched = state
+            .expectations
+            .iter_mut()
+            .find(|(e, hits)| e.times.is_none_or(|n| *hits < n) && e.matches(&request))
+            .map(|(e, hits)| {
+                *hits += 1;
+                e.response.to_response()
+            });
+        let result = matched.ok_or_else(|| {
+            let description = format!("{} {}", request.method(), request.uri());
+            state.unmatched.push(description.clone());
+            ClientError::Other(format!("no expectation matches {}", description))
+        });
+        state.requests.push(request);
+        drop(state);
+        Box::pin(async move { result })
+    }
+}
+
+/// Compare the application's OpenAPI document with a committed snapshot
+///
+/// Only the routes `app` serves are documented, as `App::openapi_spec`
+/// does. The snapshot is pretty-printed JSON at `path`, relative to the
+/// working directory (the package root under `cargo test`). With
+/// `UPDATE_SNAPSHOTS=1` the snapshot is written or rewritten. Otherwise a
+/// missing snapshot fails the test, and so does any change to the document,
+/// with a line diff, so API changes show up in review.
+///
+/// # Example
+///
+/// ```ignore
+/// #[test]
+/// fn openapi_is_unchanged() {
+///     testing::assert_openapi_snapshot(&build_app(), "snapshots/openapi.json");
+/// }
+/// ```
+///
+/// # Panics
+///
+/// Panics if the document differs from the snapshot or the snapshot is
+/// missing or cannot be read or written.
+pub fn assert_openapi_snapshot(app: &App, path: impl AsRef<Path>) {
+    let update = std::env::var(UPDATE_SNAPSHOTS_ENV).is_ok_and(|v| v == "1");
+    compare_snapshot(app, path.as_ref(), update);
+}
+
+// compare the document of `app` with the snapshot, or write it on `update`
+fn compare_snapshot(app: &App, path: &Path, update: bool) {
+    let mut actual =
+        serde_json::to_string_pretty(&app.openapi_spec()).expect("OpenAPI document serializes");
+    actual.push('\n');
+
+    if !update && !path.exists() {
+        panic!(
+            "OpenAPI snapshot {} is missing; run with {}=1 to create it",
+            path.display(),
+            UPDATE_SNAPSHOTS_ENV
+        );
+    }
+    if update {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .unwrap_or_else(|e| panic!("cannot create {}: {}", dir.display(), e));
+        }
+        fs::write(path, &actual)
+            .unwrap_or_else(|e| panic!("cannot write snapshot {}: {}", path.display(), e));
+        return;
+    }
+
+    let expected = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("cannot read snapshot {}: {}", path.display(), e));
+    if expected != actual {
+        panic!(
+            "OpenAPI document differs from snapshot {}\n\n{}\nRe-run with {}=1 to accept the change.",
+            path.display(),
+            line_diff(&expected, &actual),
+            UPDATE_SNAPSHOTS_ENV
+        );
+    }
+}
+
+// unified-style diff of two texts; `-` lines are expected, `+` lines actual
+fn line_diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    // the unchanged head and tail are skipped, the middle is diffed by LCS
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = format!("@@ line {} @@\n", prefix + 1);
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            out.push_str(&format!("  {}\n", old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if j < new_mid.len() && (i == old_mid.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push_str(&format!("+ {}\n", new_mid[j]));
+            j += 1;
+        } else {
+            out.push_str(&format!("- {}\n", old_mid[i]));
+            i += 1;
+        }
+    }
+    out
+}
+
+// run each request with the test server's log capture installed
+async fn capture_logs(State(dispatch): State<Dispatch>, req: HttpRequest, next: Next) -> Response {
+    next.run(req).with_subscriber(dispatch).await
+}
+
+/// Subscriber recording ERROR-level events
+#[derive(Clone, Default)]
+struct ErrorLog {
+    messages: Arc<Mutex<Vec<String>>>,
+    next_span: Arc<AtomicU64>,
+}
+
+impl ErrorLog {
+    // snapshot of the recorded messages
+    fn messages(&self) -> Vec<String> {
+        self.messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// collects an event's fields into one line
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            self.0.push_str(&format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+impl Subscriber for ErrorLog {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() || *metadata.level() == Level::ERROR
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        let message = format!("{}: {}", event.metadata().target(), visitor.0);
+        self.messages
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(message);
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{extract::Inject, mockable};
+
+    #[mockable]
+    trait Greeter: Send + Sync {
+        fn greet(&self, name: &str) -> String;
+    }
+
+    #[mockable]
+    trait Counter {
+        async fn count(&self) -> usize;
+    }
+
+    // an argument type without `Debug`
+    struct Opaque;
+
+    #[mockable]
+    trait Store {
+        fn put(&self, id: u64, value: Opaque);
+    }
+
+    impl Injectable for dyn Greeter {}
+
+    // minimal HTTP/1.1 GET, returning the raw response
+    async fn http_get(server: &TestServer, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_spawn_serves_requests() {
+        let app = App::new().route("/hello", get(|| async { "hello" }));
+        let server = spawn(app).await;
+        assert!(server.base_url().starts_with("http://127.0.0.1:"));
+        assert_ne!(server.addr().port(), 0);
+
+        let response = http_get(&server, "/hello").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("hello"));
+        server.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_captures_error_logs() {
+        let app = App::new().route(
+            "/fail",
+            get(|| async {
+                tracing::error!("database unreachable");
+                "degraded"
+            }),
+        );
+        let server = spawn(app).await;
+        http_get(&server, "/fail").await;
+        assert_eq!(server.error_logs().len(), 1);
+        assert!(server.error_logs()[0].contains("database unreachable"));
+
+        let teardown = tokio::spawn(server.shutdown()).await;
+        assert!(teardown.unwrap_err().is_panic());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "failed to start")]
+    async fn test_spawn_reports_start_failure() {
+        let server = RustAPI::new(App::new().build())
+            .on_start(|| async { Err(Error::other("migration failed")) });
+        spawn_server(server).await;
+    }
+
+    #[tokio::test]
+    async fn test_mock_records_calls() {
+        let mock = MockGreeter::new().on_greet(|name| format!("hi {}", name));
+        assert_eq!(mock.greet("ada"), "hi ada");
+        assert_eq!(mock.greet("bob"), "hi bob");
+        assert_eq!(mock.calls().all(), vec!["greet", "greet"]);
+        assert_eq!(mock.calls().count("greet"), 2);
+        assert!(mock.calls().called_with("greet", &["\"bob\""]));
+        assert_eq!(mock.calls().args("greet")[0], vec!["\"ada\""]);
+
+        let store = MockStore::new().on_put(|_, _| ());
+        store.put(7, Opaque);
+        assert!(store.calls().called_with("put", &["7", "_"]));
+
+        let counter = MockCounter::new().on_count(|| 3);
+        assert_eq!(counter.count().await, 3);
+        assert!(counter.calls().called("count"));
+        assert!(!MockGreeter::new().calls().called("greet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "MockGreeter::greet called without a stub")]
+    fn test_mock_panics_without_stub() {
+        MockGreeter::new().greet("ada");
+    }
+
+    #[tokio::test]
+    async fn test_with_mock_replaces_service() {
+        let mock = Arc::new(MockGreeter::new().on_greet(|name| format!("mock {}", name)));
+        let app = App::new().route(
+            "/greet",
+            get(|Inject(greeter): Inject<dyn Greeter>| async move { greeter.greet("ada") }),
+        );
+        let app = TestContainer::new()
+            .with_mock::<dyn Greeter>(mock.clone())
+            .install(app);
+
+        let server = spawn(app).await;
+        let response = http_get(&server, "/greet").await;
+        assert!(response.ends_with("mock ada"));
+        assert_eq!(mock.calls().count("greet"), 1);
+        server.shutdown().await;
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let diff = line_diff("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+        assert_eq!(diff, "@@ line 2 @@\n+ B\n- b\n  c\n  d\n+ e\n");
+    }
+
+    #[test]
+    fn test_openapi_snapshot() {
+        let dir = std::env::temp_dir().join(format!("rust-api-snapshot-{}", std::process::id()));
+        let path = dir.join("openapi.json");
+        let app = App::new();
+
+        let missing = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            compare_snapshot(&app, &path, false)
+        }));
+        let message = *missing.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("is missing"));
+        assert!(!path.exists());
+
+        compare_snapshot(&app, &path, true);
+        assert!(path.exists());
+        assert_openapi_snapshot(&app, &path);
+
+        fs::write(&path, "{}\n").unwrap();
+        let changed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_openapi_snapshot(&app, &path)
+        }));
+        let message = *changed.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("differs from snapshot"));
+        assert!(message.contains("- {}"));
+        assert!(message.contains("+   \"openapi\": \"3.1.0\""));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn outbound(method: Method, url: &str, body: &str) -> ClientRequest {
+        HttpRequest::builder()
+            .method(method)
+            .uri(url)
+            .header("authorization", "Bearer test")
+            .body(Bytes::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_http_client() {
+        let client = MockHttpClient::new()
+            .expect(
+                Expectation::post("https://pay.example.com/charges")
+                    .header("authorization", "Bearer test")
+                    .json_body(&serde_json::json!({ "amount": 500 }))
+                    .times(1)
+                    .respond(
+                        MockResponse::new(StatusCode::CREATED)
+                            .json(&serde_json::json!({ "id": "ch_1" })),
+                    ),
+            )
+            .expect(Expectation::get("https://pay.example.com/charges/ch_1"));
+        let shared: Arc<dyn HttpClient> = Arc::new(client.clone());
+
+        let request = outbound(
+            Method::POST,
+            "https://pay.example.com/charges",
+            r#"{ "amount": 500 }"#,
+        );
+        let response = shared.send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.body().as_ref(), br#"{"id":"ch_1"}"#);
+
+        let request = outbound(Method::GET, "https://pay.example.com/charges/ch_1", "");
+        assert_eq!(shared.send(request).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(client.requests().len(), 2);
+        client.verify();
+    }
+
+    #[tokio::test]
+    async fn test_mock_http_client_verify_fails() {
+        let client = MockHttpClient::new()
+            .expect(Expectation::delete("https://pay.example.com/charges/ch_1").times(1));
+        let request = outbound(Method::GET, "https://pay.example.com/other", "");
+        let error = client.send(request).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("GET https://pay.example.com/other"));
+
+        let verify = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| client.verify()));
+        let message = *verify.unwrap_err().downcast::<String>().unwrap();
+        assert!(message
+            .contains("expected DELETE https://pay.example.com/charges/ch_1 1 time(s), got 0"));
+        assert!(message.contains("unexpected request GET https://pay.example.com/other"));
+    }
+
+    #[test]
+    fn test_request_builder() {
+        let request = Request::post("/users?notify=1")
+            .bearer("t0k3n")
+            .cookie("session", "abc")
+            .cookie("theme", "dark")
+            .json(&serde_json::json!({ "name": "Ada" }))
+            .into_http();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri().query(), Some("notify=1"));
+        assert_eq!(request.headers()["authorization"], "Bearer t0k3n");
+        assert_eq!(request.headers()["cookie"], "session=abc; theme=dark");
+        assert_eq!(request.headers()["content-type"], "application/json");
+
+        let request = Request::get("/")
+            .basic_auth("Aladdin", "open sesame")
+            .into_http();
+        assert_eq!(
+            request.headers()["authorization"],
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn test_multipart() {
+        let form =
+            Multipart::new()
+                .text("title", "cat")
+                .file("photo", "cat.png", "image/png", b"PNG");
+        assert_eq!(
+            form.content_type(),
+            "multipart/form-data; boundary=rust-api-test-boundary"
+        );
+        let body = String::from_utf8(form.into_bytes()).unwrap();
+        assert!(body.contains("content-disposition: form-data; name=\"title\"\r\n\r\ncat\r\n"));
+        assert!(body.contains("filename=\"cat.png\"\r\ncontent-type: image/png\r\n\r\nPNG\r\n"));
+        assert!(body.ends_with("--rust-api-test-boundary--\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_client_and_server_share_requests() {
+        let app = || {
+            App::new().route(
+                "/echo",
+                axum::routing::post(|headers: HeaderMap, body: String| async move {
+                    let auth = headers["authorization"].to_str().unwrap().to_string();
+                    axum::Json(serde_json::json!({ "auth": auth, "body": body }))
+                }),
+            )
+        };
+        let request = Request::post("/echo").bearer("abc").body("hi");
+
+        let response = TestClient::new(app()).send(request.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<serde_json::Value>()["auth"], "Bearer abc");
+
+        let server = spawn(app()).await;
+        let response = server.send(request).await;
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.json::<serde_json::Value>()["body"], "hi");
+        server.shutdown().await;
+    }
+}
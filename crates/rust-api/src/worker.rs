@@ -0,0 +1,270 @@
+//! Background workers for RustAPI framework
+//!
+//! Workers are long-running tasks, such as event bus subscribers, queue
+//! consumers or periodic jobs, wired up with the same `App` as the HTTP
+//! routes. `App::run_workers_only` runs them without binding a port, so one
+//! codebase can ship separate web and worker deployments: the web
+//! deployment calls `serve`, which leaves workers out, and the worker
+//! deployment calls `run_workers_only`.
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::{sync::watch, task::JoinSet};
+
+use crate::{
+    di::Container,
+    error::{Error, Result},
+    lifecycle::BoxFuture,
+};
+
+/// Default time workers get to finish once asked to stop
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tells a worker when to stop
+///
+/// Workers should finish their current item and return once `stopped`
+/// completes.
+#[derive(Debug, Clone)]
+pub struct StopSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl StopSignal {
+    /// Whether the worker was asked to stop
+    pub fn is_stopped(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Wait until the worker is asked to stop
+    pub async fn stopped(&mut self) {
+        // an error means the runner is gone, which also means stop
+        let _ = self.receiver.wait_for(|stopped| *stopped).await;
+    }
+}
+
+/// A long-running background task
+///
+/// Implemented for async closures taking the app's container and the stop
+/// signal. Returning ends the worker; an error is logged, asks the other
+/// workers to stop and makes `run_workers_only` fail once they have, so the
+/// process exits and its supervisor can restart it.
+///
+/// # Example
+///
+/// ```ignore
+/// app.worker("welcome-emails", |container: Arc<Container>, mut stop: StopSignal| async move {
+///     let bus = container.resolve_or_panic::<EventBus>();
+///     let mut created = bus.subscribe::<UserCreated>();
+///     loop {
+///         tokio::select! {
+///             _ = stop.stopped() => return Ok(()),
+///             Some(user) = created.recv() => send_welcome(&user).await?,
+///         }
+///     }
+/// })
+/// ```
+pub trait Worker: Send + Sync + 'static {
+    /// Run until done or asked to stop
+    fn run(&self, container: Arc<Container>, stop: StopSignal) -> BoxFuture<'_, Result<()>>;
+}
+
+impl<F, Fut> Worker for F
+where
+    F: Fn(Arc<Container>, StopSignal) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn run(&self, container: Arc<Container>, stop: StopSignal) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self(container, stop))
+    }
+}
+
+/// Named workers registered with `App::worker`
+#[derive(Default)]
+pub(crate) struct Workers {
+    workers: Vec<(String, Arc<dyn Worker>)>,
+}
+
+impl Workers {
+    // register a worker
+    pub fn add(&mut self, name: &str, worker: impl Worker) {
+        self.workers.push((name.to_string(), Arc::new(worker)));
+    }
+
+    // number of registered workers
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    // run every worker until `shutdown` completes, one of them fails or all
+    // of them return, then give the rest `stop_timeout` to finish
+    pub async fn run(
+        self,
+        container: Arc<Container>,
+        shutdown: impl Future<Output = ()>,
+        stop_timeout: Duration,
+    ) -> Result<()> {
+        let (stop, receiver) = watch::channel(false);
+        let mut running = JoinSet::new();
+        for (name, worker) in self.workers {
+            let container = container.clone();
+            let signal = StopSignal {
+                receiver: receiver.clone(),
+            };
+            tracing::info!(worker = %name, "Starting worker");
+            running.spawn(async move {
+                let result = worker.run(container, signal).await;
+                (name, result)
+            });
+        }
+
+        let mut failed = None;
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                finished = running.join_next() => match finished {
+                    Some(finished) => {
+                        record(finished, &mut failed);
+                        if failed.is_some() {
+                            break;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        if !running.is_empty() {
+            tracing::info!("Stopping {} workers", running.len());
+        }
+        let _ = stop.send(true);
+        let drained = tokio::time::timeout(stop_timeout, async {
+            while let Some(finished) = running.join_next().await {
+                record(finished, &mut failed);
+            }
+        })
+        .await;
+        if drained.is_err() {
+            tracing::warn!(
+                "{} workers did not stop within {:?}, aborting them",
+                running.len(),
+                stop_timeout
+            );
+            running.abort_all();
+        }
+        failed.map_or(Ok(()), Err)
+    }
+}
+
+// log how a worker ended, keeping the first failure
+fn record(
+    finished: std::result::Result<(String, Result<()>), tokio::task::JoinError>,
+    failed: &mut Option<Error>,
+) {
+    let error = match finished {
+        Ok((name, Ok(()))) => {
+            tracing::info!(worker = %name, "Worker stopped");
+            return;
+        }
+        Ok((name, Err(e))) => {
+            tracing::error!(worker = %name, "Worker failed: {}", e);
+            Error::other(format!("Worker {} failed: {}", name, e))
+        }
+        Err(e) => {
+            tracing::error!("Worker panicked: {}", e);
+            Error::other(format!("Worker panicked: {}", e))
+        }
+    };
+    failed.get_or_insert(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_workers_stop_on_shutdown() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut workers = Workers::default();
+        for name in ["a", "b"] {
+            let stopped = stopped.clone();
+            workers.add(name, move |_: Arc<Container>, mut stop: StopSignal| {
+                let stopped = stopped.clone();
+                async move {
+                    stop.stopped().await;
+                    assert!(stop.is_stopped());
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+        }
+        assert_eq!(workers.len(), 2);
+
+        let shutdown = tokio::time::sleep(Duration::from_millis(20));
+        workers
+            .run(Arc::new(Container::new()), shutdown, DEFAULT_STOP_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(stopped.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_reported_and_stuck_workers_aborted() {
+        let mut workers = Workers::default();
+        workers.add("broken", |_: Arc<Container>, _: StopSignal| async {
+            Err(Error::other("queue unreachable"))
+        });
+        workers.add("stuck", |_: Arc<Container>, _: StopSignal| async {
+            std::future::pending::<()>().await;
+            Ok(())
+        });
+
+        let error = workers
+            .run(
+                Arc::new(Container::new()),
+                tokio::time::sleep(Duration::from_millis(20)),
+                Duration::from_millis(20),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("broken"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_failure_stops_the_other_workers() {
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut workers = Workers::default();
+        workers.add("broken", |_: Arc<Container>, _: StopSignal| async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err(Error::other("queue unreachable"))
+        });
+        let counter = stopped.clone();
+        workers.add(
+            "consumer",
+            move |_: Arc<Container>, mut stop: StopSignal| {
+                let stopped = counter.clone();
+                async move {
+                    stop.stopped().await;
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            },
+        );
+
+        // no shutdown signal: the failure alone ends the run
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            workers.run(
+                Arc::new(Container::new()),
+                std::future::pending(),
+                DEFAULT_STOP_TIMEOUT,
+            ),
+        )
+        .await
+        .expect("workers kept running after a failure");
+        assert!(result.is_err());
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,1313 @@
+//! WebSocket gateways for RustAPI framework
+//!
+//! A gateway handles the lifetime of WebSocket connections: it is told when
+//! a client connects, receives its messages and is told when it leaves.
+//! Gateways exchanging typed messages implement `TypedGateway` instead.
+//! Routes are declared with `#[websocket("/path")]` on a function returning
+//! the gateway; guards given with `#[guard(...)]` run on the HTTP upgrade
+//! request, so an unauthenticated client is rejected with a normal status
+//! before any upgrade, and the `Principal` a guard attached is available on
+//! the connection's `Session`. Heartbeats, idle eviction and per-client
+//! connection limits are configured for the whole app with `WebSockets`.
+//!
+//! ```ignore
+//! struct Chat;
+//!
+//! impl Gateway for Chat {
+//!     fn on_connect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+//!         let name = session.principal().map(Principal::subject).unwrap_or("guest");
+//!         session.send(format!("welcome {}", name));
+//!         Box::pin(async {})
+//!     }
+//!
+//!     fn on_message<'a>(&'a self, session: &'a Session, message: Message) -> BoxFuture<'a, ()> {
+//!         session.send(message);
+//!         Box::pin(async {})
+//!     }
+//! }
+//!
+//! #[guard(TokenAuth::new(verify_token))]
+//! #[websocket("/chat")]
+//! async fn chat() -> Chat {
+//!     Chat
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    net::SocketAddr,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+pub use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    sync::{mpsc, watch, Notify},
+    time::{Instant, Interval},
+};
+
+use crate::{
+    auth::Principal,
+    di::{Container, Injectable},
+    error::ApiError,
+    lifecycle::BoxFuture,
+    metrics::Metrics,
+    profile::{self, ErrorReporting},
+};
+
+/// Messages a connection queues for writing by default
+pub const SEND_BUFFER: usize = 256;
+
+/// Connection-limit key of clients with neither a `Principal` nor a known
+/// IP address; they share one limit
+pub const UNIDENTIFIED_CLIENT: &str = "unidentified";
+
+// ids of connections, unique within the process
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+/// Handler of the connections of a WebSocket route
+pub trait Gateway: Send + Sync + 'static {
+    /// A client connected
+    fn on_connect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+
+    /// A text or binary message arrived
+    fn on_message<'a>(&'a self, session: &'a Session, message: Message) -> BoxFuture<'a, ()>;
+
+    /// The server is shutting down; the connection is closed once this
+    /// returns
+    fn on_shutdown<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+
+    /// The connection closed, by either side
+    fn on_disconnect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+}
+
+/// One WebSocket connection, as seen by its gateway
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+    principal: Option<Principal>,
+    outgoing: mpsc::Sender<Message>,
+    closing: AtomicBool,
+    // signalled when a message did not fit in the outgoing buffer
+    overflow: Notify,
+    // send the messages of 5xx errors, as in HTTP responses
+    error_details: bool,
+}
+
+impl Session {
+    /// Process-unique id of the connection
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Principal attached by the guards of the upgrade request
+    pub fn principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+
+    /// Queue a message for the client; `false` once the connection is gone
+    ///
+    /// A client that does not read fast enough to keep its buffer
+    /// (`WebSockets::send_buffer`) from filling up is disconnected.
+    pub fn send(&self, message: impl Into<Message>) -> bool {
+        match self.outgoing.try_send(message.into()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflow.notify_one();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Close the connection with a close frame
+    pub fn close(&self, code: u16, reason: &str) -> bool {
+        self.closing.store(true, Ordering::Relaxed);
+        self.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+    }
+
+    /// Handle for sending to this connection from other tasks
+    ///
+    /// Shares the connection's buffer; its `send().await` waits for room
+    /// instead of disconnecting the client.
+    pub fn sender(&self) -> mpsc::Sender<Message> {
+        self.outgoing.clone()
+    }
+}
+
+/// Wire format of typed messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// JSON in text frames
+    #[default]
+    Json,
+    /// MessagePack (with field names) in binary frames
+    MessagePack,
+}
+
+/// Frame carrying a typed message
+///
+/// Encodes and decodes `T` with a `Codec`; a `TypedGateway` uses it for
+/// every message, so gateways only see their own types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsMessage<T>(pub T);
+
+impl<T: Serialize> WsMessage<T> {
+    /// Encode the message as a frame
+    pub fn encode(&self, codec: Codec) -> Result<Message, ApiError> {
+        let failed = || ApiError::internal("Failed to encode message");
+        match codec {
+            Codec::Json => serde_json::to_string(&self.0)
+                .map(|text| Message::Text(text.into()))
+                .map_err(|e| failed().with_source(e)),
+            Codec::MessagePack => rmp_serde::to_vec_named(&self.0)
+                .map(|bytes| Message::Binary(bytes.into()))
+                .map_err(|e| failed().with_source(e)),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> WsMessage<T> {
+    /// Decode a text or binary frame
+    ///
+    /// JSON is accepted in text and binary frames, MessagePack only in
+    /// binary frames. Failures are 422 `invalid_message` errors.
+    pub fn decode(message: &Message, codec: Codec) -> Result<Self, ApiError> {
+        let invalid = |detail: String| {
+            ApiError::unprocessable(format!("Invalid message: {}", detail))
+                .with_code("invalid_message")
+        };
+        let decoded = match (codec, message) {
+            (Codec::Json, Message::Text(text)) => {
+                serde_json::from_str(text.as_str()).map_err(|e| invalid(e.to_string()))
+            }
+            (Codec::Json, Message::Binary(bytes)) => {
+                serde_json::from_slice(bytes).map_err(|e| invalid(e.to_string()))
+            }
+            (Codec::MessagePack, Message::Binary(bytes)) => {
+                rmp_serde::from_slice(bytes).map_err(|e| invalid(e.to_string()))
+            }
+            _ => Err(invalid(String::from("unexpected frame type"))),
+        };
+        decoded.map(WsMessage)
+    }
+}
+
+/// Gateway exchanging typed messages
+///
+/// Incoming frames are decoded into `In`, usually an enum with one variant
+/// per message type, and dispatched to `on_message`; outgoing `Out`
+/// messages are encoded with the gateway's codec. A frame that cannot be
+/// decoded, and any error `on_message` returns, is answered with an error
+/// frame holding the standard error envelope,
+/// `{"error": {"code": ..., "message": ...}}`, and the connection stays
+/// open. Every `TypedGateway` is a `Gateway`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// #[serde(tag = "type", rename_all = "snake_case")]
+/// enum ClientMessage {
+///     Join { room: String },
+///     Say { text: String },
+/// }
+///
+/// impl TypedGateway for Chat {
+///     type In = ClientMessage;
+///     type Out = ServerMessage;
+///
+///     fn on_message<'a>(
+///         &'a self,
+///         session: &'a TypedSession<'a, ServerMessage>,
+///         message: ClientMessage,
+///     ) -> BoxFuture<'a, Result<(), ApiError>> {
+///         Box::pin(async move {
+///             match message {
+///                 ClientMessage::Join { room } => self.join(session, room).await,
+///                 ClientMessage::Say { text } => self.say(session, text).await,
+///             }
+///         })
+///     }
+/// }
+/// ```
+pub trait TypedGateway: Send + Sync + 'static {
+    /// Messages sent by clients
+    type In: DeserializeOwned + Send;
+    /// Messages sent to clients
+    type Out: Serialize + Send + Sync;
+
+    /// Wire format of the messages
+    fn codec(&self) -> Codec {
+        Codec::Json
+    }
+
+    /// A client connected
+    fn on_connect<'a>(&'a self, session: &'a TypedSession<'a, Self::Out>) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+
+    /// A message arrived; an error is sent back as an error frame
+    fn on_message<'a>(
+        &'a self,
+        session: &'a TypedSession<'a, Self::Out>,
+        message: Self::In,
+    ) -> BoxFuture<'a, Result<(), ApiError>>;
+
+    /// The server is shutting down; the connection is closed once this
+    /// returns
+    fn on_shutdown<'a>(&'a self, session: &'a TypedSession<'a, Self::Out>) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+
+    /// The connection closed, by either side
+    fn on_disconnect<'a>(&'a self, session: &'a TypedSession<'a, Self::Out>) -> BoxFuture<'a, ()> {
+        let _ = session;
+        Box::pin(async {})
+    }
+}
+
+/// Connection of a `TypedGateway`, sending `Out` messages
+///
+/// Dereferences to the underlying `Session` for its id and principal.
+#[derive(Debug)]
+pub struct TypedSession<'a, Out> {
+    session: &'a Session,
+    codec: Codec,
+    out: PhantomData<fn(&Out)>,
+}
+
+impl<Out: Serialize> TypedSession<'_, Out> {
+    /// Encode and queue a message; `false` once the connection is gone
+    ///
+    /// A message that cannot be encoded is logged and not sent.
+    pub fn send(&self, message: &Out) -> bool {
+        match WsMessage(message).encode(self.codec) {
+            Ok(frame) => self.session.send(frame),
+            Err(error) => {
+                tracing::error!(session = self.session.id(), error = %error, "websocket message not sent");
+                false
+            }
+        }
+    }
+
+    /// Send an error frame
+    ///
+    /// 5xx errors are logged, and redacted to their status and code like
+    /// HTTP responses when the app does not send error details.
+    pub fn send_error(&self, error: &ApiError) -> bool {
+        let body = match error.status().is_server_error() {
+            true => {
+                tracing::error!(
+                    session = self.session.id(),
+                    status = error.status().as_u16(),
+                    code = error.code(),
+                    chain = ?error.chain(),
+                    "{}",
+                    error.message()
+                );
+                match self.session.error_details {
+                    true => error.to_json(),
+                    false => profile::redacted(error).to_json(),
+                }
+            }
+            false => error.to_json(),
+        };
+        match WsMessage(body).encode(self.codec) {
+            Ok(frame) => self.session.send(frame),
+            Err(_) => false,
+        }
+    }
+}
+
+impl<Out> Deref for TypedSession<'_, Out> {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        self.session
+    }
+}
+
+impl<G: TypedGateway> Gateway for G {
+    fn on_connect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let typed = typed_session(self, session);
+            TypedGateway::on_connect(self, &typed).await;
+        })
+    }
+
+    fn on_message<'a>(&'a self, session: &'a Session, message: Message) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let typed = typed_session(self, session);
+            let result = match WsMessage::<G::In>::decode(&message, typed.codec) {
+                Ok(WsMessage(message)) => TypedGateway::on_message(self, &typed, message).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                typed.send_error(&error);
+            }
+        })
+    }
+
+    fn on_shutdown<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let typed = typed_session(self, session);
+            TypedGateway::on_shutdown(self, &typed).await;
+        })
+    }
+
+    fn on_disconnect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let typed = typed_session(self, session);
+            TypedGateway::on_disconnect(self, &typed).await;
+        })
+    }
+}
+
+// the typed view of a session for `gateway`
+fn typed_session<'a, G: TypedGateway>(
+    gateway: &G,
+    session: &'a Session,
+) -> TypedSession<'a, G::Out> {
+    TypedSession {
+        session,
+        codec: gateway.codec(),
+        out: PhantomData,
+    }
+}
+
+/// Why a connection ended, as counted in `websocket_disconnects_total`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The client closed the connection or went away
+    Client,
+    /// The server closed it with `Session::close`
+    Server,
+    /// Nothing was received within the idle timeout
+    Idle,
+    /// Reading from the connection failed
+    Error,
+    /// The server shut down
+    Shutdown,
+    /// The client did not read its messages fast enough
+    SlowConsumer,
+}
+
+impl DisconnectReason {
+    /// Label value used in metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+            Self::Idle => "idle",
+            Self::Error => "error",
+            Self::Shutdown => "shutdown",
+            Self::SlowConsumer => "slow_consumer",
+        }
+    }
+}
+
+/// Settings and live connections of an app's WebSocket routes
+///
+/// Install with `App::websockets`; routes of an app without it use the
+/// defaults: a ping every 30 seconds, eviction after 60 seconds without
+/// any frame from the client (pongs included), and no per-client limit.
+///
+/// `App::serve` and `RustAPI` drain the connections on graceful shutdown:
+/// each gateway's `on_shutdown` runs, the client gets a close frame (1001
+/// "server shutting down" by default) and the server waits up to the drain
+/// timeout for the connections to close.
+///
+/// # Example
+///
+/// ```ignore
+/// let app = App::new()
+///     .websockets(
+///         WebSockets::new()
+///             .ping_interval(Duration::from_secs(15))
+///             .idle_timeout(Duration::from_secs(45))
+///             .max_connections_per_client(5)
+///             .metrics(metrics.clone()),
+///     )
+///     .route(__chat_route, routing::get(chat));
+/// ```
+#[derive(Clone)]
+pub struct WebSockets {
+    ping_interval: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_per_client: Option<usize>,
+    send_buffer: usize,
+    close_code: u16,
+    close_reason: String,
+    drain_timeout: Duration,
+    metrics: Option<Metrics>,
+    live: Arc<Live>,
+}
+
+// connections admitted and not yet closed
+#[derive(Debug, Default)]
+struct Live {
+    active: AtomicUsize,
+    clients: Mutex<HashMap<String, usize>>,
+    shutdown: watch::Sender<bool>,
+    closed: Notify,
+}
+
+impl WebSockets {
+    /// Default settings
+    pub fn new() -> Self {
+        Self {
+            ping_interval: Some(Duration::from_secs(30)),
+            idle_timeout: Some(Duration::from_secs(60)),
+            max_per_client: None,
+            send_buffer: SEND_BUFFER,
+            close_code: close_code::AWAY,
+            close_reason: String::from("server shutting down"),
+            drain_timeout: Duration::from_secs(10),
+            metrics: None,
+            live: Arc::default(),
+        }
+    }
+
+    /// Ping every connection this often; `None` disables pings
+    pub fn ping_interval(mut self, interval: impl Into<Option<Duration>>) -> Self {
+        self.ping_interval = interval.into();
+        self
+    }
+
+    /// Close connections silent for this long; `None` never evicts
+    pub fn idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.idle_timeout = timeout.into();
+        self
+    }
+
+    /// Reject upgrades with 429 beyond `max` open connections per client
+    ///
+    /// Clients are told apart by the subject of their `Principal`, or by
+    /// IP address when the server was started with connect info (as
+    /// `App::serve` and `RustAPI` do). Any other client counts as
+    /// `UNIDENTIFIED_CLIENT`, with a warning.
+    pub fn max_connections_per_client(mut self, max: usize) -> Self {
+        self.max_per_client = Some(max);
+        self
+    }
+
+    /// Queue at most `capacity` outgoing messages per connection (default:
+    /// `SEND_BUFFER`)
+    pub fn send_buffer(mut self, capacity: usize) -> Self {
+        self.send_buffer = capacity.max(1);
+        self
+    }
+
+    /// Close frame sent to clients on shutdown
+    pub fn shutdown_close(mut self, code: u16, reason: impl Into<String>) -> Self {
+        self.close_code = code;
+        self.close_reason = reason.into();
+        self
+    }
+
+    /// Wait this long for clients to close on shutdown (default: 10 seconds)
+    ///
+    /// Connections still open afterwards are dropped.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Report `websocket_connections_active` and
+    /// `websocket_disconnects_total{reason}` to `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        let live = Arc::clone(&self.live);
+        metrics.gauge("websocket_connections_active", &[], move || {
+            live.active.load(Ordering::Relaxed) as f64
+        });
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Number of open connections
+    pub fn active(&self) -> usize {
+        self.live.active.load(Ordering::Relaxed)
+    }
+
+    /// Start closing every connection and refuse new ones with 503
+    ///
+    /// Returns immediately; use `drain` to also wait for the connections.
+    pub fn close_all(&self) {
+        self.live.shutdown.send_replace(true);
+    }
+
+    /// Close every connection and wait, up to the drain timeout, until they
+    /// are gone; returns the number still open
+    pub async fn drain(&self) -> usize {
+        self.close_all();
+        let closed = async {
+            loop {
+                let notified = self.live.closed.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.active() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        if tokio::time::timeout(self.drain_timeout, closed)
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "Drain timeout exceeded, dropping {} websocket connection(s)",
+                self.active()
+            );
+        }
+        self.active()
+    }
+
+    // count a new connection of `client`, unless it is over its limit
+    fn admit(&self, client: String) -> Result<Slot, ApiError> {
+        if *self.live.shutdown.borrow() {
+            return Err(
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down")
+                    .with_code("shutting_down"),
+            );
+        }
+        {
+            let mut clients = self.live.clients.lock().unwrap_or_else(|e| e.into_inner());
+            let open = clients.get(&client).copied().unwrap_or(0);
+            if self.max_per_client.is_some_and(|max| open >= max) {
+                return Err(ApiError::new(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Too many open connections",
+                )
+                .with_code("too_many_connections"));
+            }
+            clients.insert(client.clone(), open + 1);
+        }
+        self.live.active.fetch_add(1, Ordering::Relaxed);
+        Ok(Slot {
+            live: Arc::clone(&self.live),
+            client,
+        })
+    }
+
+    // count a finished connection
+    fn record_disconnect(&self, reason: DisconnectReason) {
+        if let Some(metrics) = &self.metrics {
+            metrics.increment(
+                "websocket_disconnects_total",
+                &[("reason", reason.as_str())],
+            );
+        }
+    }
+}
+
+impl Default for WebSockets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for WebSockets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSockets")
+            .field("ping_interval", &self.ping_interval)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("max_per_client", &self.max_per_client)
+            .field("send_buffer", &self.send_buffer)
+            .field("close_code", &self.close_code)
+            .field("close_reason", &self.close_reason)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("active", &self.active())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Injectable for WebSockets {}
+
+// WebSockets registries with connections on one server, closed by the
+// server on graceful shutdown
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ServedSockets(Arc<Mutex<ServedState>>);
+
+#[derive(Debug, Default)]
+struct ServedState {
+    registries: Vec<WebSockets>,
+    closing: bool,
+}
+
+impl ServedSockets {
+    // remember the registry of an upgrade; closed at once while shutting down
+    fn track(&self, sockets: &WebSockets) {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if state.closing {
+            sockets.close_all();
+        }
+        if !state
+            .registries
+            .iter()
+            .any(|known| Arc::ptr_eq(&known.live, &sockets.live))
+        {
+            state.registries.push(sockets.clone());
+        }
+    }
+
+    // close every connection and refuse new upgrades
+    pub(crate) fn close_all(&self) {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.closing = true;
+        state.registries.iter().for_each(WebSockets::close_all);
+    }
+
+    // close every connection and wait for each registry to drain
+    pub(crate) async fn drain(&self) {
+        self.close_all();
+        let registries = self
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .registries
+            .clone();
+        futures_util::future::join_all(registries.iter().map(WebSockets::drain)).await;
+    }
+}
+
+// an admitted connection, released when dropped
+#[derive(Debug)]
+struct Slot {
+    live: Arc<Live>,
+    client: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.live.active.fetch_sub(1, Ordering::Relaxed);
+        let mut clients = self.live.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(open) = clients.get_mut(&self.client) {
+            *open -= 1;
+            if *open == 0 {
+                clients.remove(&self.client);
+            }
+        }
+        drop(clients);
+        self.live.closed.notify_waiters();
+    }
+}
+
+/// Upgrade of a WebSocket route's request
+///
+/// Extracted by the handlers `#[websocket]` generates; carries the
+/// `Principal` the route's guards attached. Rejects the request with 429
+/// `too_many_connections` when the client is at its connection limit.
+#[derive(Debug)]
+pub struct Upgrade {
+    upgrade: WebSocketUpgrade,
+    principal: Option<Principal>,
+    error_details: bool,
+    sockets: WebSockets,
+    slot: Slot,
+}
+
+impl Upgrade {
+    /// Principal attached by the guards, if any
+    pub fn principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+
+    /// Accept the upgrade and serve the connection with `gateway`
+    pub fn accept(self, gateway: impl Gateway) -> Response {
+        let Upgrade {
+            upgrade,
+            principal,
+            error_details,
+            sockets,
+            slot,
+        } = self;
+        upgrade
+            .on_upgrade(move |socket| async move {
+                let reason = serve(socket, &gateway, principal, error_details, &sockets).await;
+                sockets.record_disconnect(reason);
+                drop(slot);
+            })
+            .into_response()
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Upgrade {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let upgrade = WebSocketUpgrade::from_request_parts(parts, state)
+            .await
+            .map_err(|e| {
+                ApiError::new(e.status(), e.body_text())
+                    .with_code("websocket_required")
+                    .rejection()
+            })?;
+        let sockets = parts
+            .extensions
+            .get::<Arc<Container>>()
+            .and_then(|container| container.resolve::<WebSockets>())
+            .map(|sockets| (*sockets).clone())
+            .unwrap_or_default();
+        if let Some(served) = parts.extensions.get::<ServedSockets>() {
+            served.track(&sockets);
+        }
+        let principal = parts.extensions.get::<Principal>().cloned();
+        let client = match &principal {
+            Some(principal) => format!("principal:{}", principal.subject()),
+            None => match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+                Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+                None => {
+                    if sockets.max_per_client.is_some() {
+                        static WARNED: AtomicBool = AtomicBool::new(false);
+                        if !WARNED.swap(true, Ordering::Relaxed) {
+                            tracing::warn!(
+                                "WebSocket client without a principal or connect info; \
+                                 such clients share one connection limit"
+                            );
+                        }
+                    }
+                    UNIDENTIFIED_CLIENT.to_string()
+                }
+            },
+        };
+        let slot = sockets.admit(client)?;
+        let error_details = parts
+            .extensions
+            .get::<ErrorReporting>()
+            .is_none_or(|reporting| reporting.details);
+        Ok(Self {
+            upgrade,
+            principal,
+            error_details,
+            sockets,
+            slot,
+        })
+    }
+}
+
+// wait for the next ping, forever when pings are off
+async fn next_ping(ping: &mut Option<Interval>) {
+    match ping {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// wait until the deadline, forever without one
+async fn until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+// wait until the server starts shutting down
+async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|shutdown| *shutdown).await.is_err() {
+        std::future::pending().await
+    }
+}
+
+// serve an upgraded connection with `gateway` until it ends
+async fn serve<G: Gateway + ?Sized>(
+    socket: WebSocket,
+    gateway: &G,
+    principal: Option<Principal>,
+    error_details: bool,
+    sockets: &WebSockets,
+) -> DisconnectReason {
+    let (mut sink, mut stream) = socket.split();
+    let (outgoing, mut queue) = mpsc::channel(sockets.send_buffer);
+    let session = Session {
+        id: NEXT_SESSION.fetch_add(1, Ordering::Relaxed),
+        principal,
+        outgoing,
+        closing: AtomicBool::new(false),
+        overflow: Notify::new(),
+        error_details,
+    };
+
+    // the writer ends after a close frame or when every sender is gone
+    let writer = tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let closing = matches!(message, Message::Close(_));
+            if sink.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+    });
+
+    let mut ping = sockets
+        .ping_interval
+        .map(|period| tokio::time::interval_at(Instant::now() + period, period));
+    let mut last_seen = Instant::now();
+    let mut shutdown = sockets.live.shutdown.subscribe();
+    let mut drain_deadline = None;
+    gateway.on_connect(&session).await;
+    let reason = loop {
+        let idle_deadline = sockets.idle_timeout.map(|timeout| last_seen + timeout);
+        tokio::select! {
+            frame = stream.next() => {
+                last_seen = Instant::now();
+                match frame {
+                    None | Some(Ok(Message::Close(_))) => break DisconnectReason::Client,
+                    Some(Err(_)) => break DisconnectReason::Error,
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                    Some(Ok(message)) => gateway.on_message(&session, message).await,
+                }
+            }
+            _ = next_ping(&mut ping) => {
+                session.outgoing.try_send(Message::Ping(Default::default())).ok();
+            }
+            _ = until(idle_deadline), if drain_deadline.is_none() => {
+                session.close(close_code::AWAY, "idle timeout");
+                break DisconnectReason::Idle;
+            }
+            // keep reading after the close frame, for the client's reply
+            _ = shutting_down(&mut shutdown), if drain_deadline.is_none() => {
+                gateway.on_shutdown(&session).await;
+                session.close(sockets.close_code, &sockets.close_reason);
+                drain_deadline = Some(Instant::now() + sockets.drain_timeout);
+            }
+            _ = until(drain_deadline) => break DisconnectReason::Shutdown,
+            _ = session.overflow.notified() => break DisconnectReason::SlowConsumer,
+        }
+    };
+    gateway.on_disconnect(&session).await;
+
+    let reason = match reason {
+        _ if drain_deadline.is_some() => DisconnectReason::Shutdown,
+        DisconnectReason::Client if session.closing.load(Ordering::Relaxed) => {
+            DisconnectReason::Server
+        }
+        reason => reason,
+    };
+    drop(session);
+    if reason == DisconnectReason::SlowConsumer {
+        // its queue is full; drop the connection instead of flushing it
+        writer.abort();
+    }
+    let _ = writer.await;
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite;
+
+    use super::*;
+    use crate::{app::App, auth::TokenAuth, guard, profile::Profile, routing::get, websocket};
+
+    struct Echo;
+
+    impl Gateway for Echo {
+        fn on_connect<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+            let name = session.principal().map_or("guest", Principal::subject);
+            session.send(format!("welcome {}", name));
+            Box::pin(async {})
+        }
+
+        fn on_message<'a>(&'a self, session: &'a Session, message: Message) -> BoxFuture<'a, ()> {
+            let copies = match message.to_text() {
+                Ok("flood") => 10,
+                _ => 1,
+            };
+            for _ in 0..copies {
+                session.send(message.clone());
+            }
+            Box::pin(async {})
+        }
+    }
+
+    fn auth() -> TokenAuth {
+        TokenAuth::new(|token, _| match token {
+            "secret" => Ok(Principal::new("ada")),
+            _ => Err(ApiError::unauthorized("Invalid token")),
+        })
+    }
+
+    #[guard(auth())]
+    #[websocket("/echo")]
+    async fn echo() -> Echo {
+        Echo
+    }
+
+    async fn serve_app() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = App::new().route(__echo_route, get(echo)).build();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("ws://{}/echo", addr)
+    }
+
+    #[tokio::test]
+    async fn test_guards_run_before_upgrade() {
+        let url = serve_app().await;
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 401),
+            other => panic!("expected a 401, got {:?}", other.map(|_| ())),
+        }
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("{}?access_token=secret", url))
+                .await
+                .unwrap();
+        let welcome = socket.next().await.unwrap().unwrap();
+        assert_eq!(welcome.into_text().unwrap().as_str(), "welcome ada");
+        socket
+            .send(tungstenite::Message::text("hello"))
+            .await
+            .unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        assert_eq!(reply.into_text().unwrap().as_str(), "hello");
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ChatIn {
+        Say { text: String },
+    }
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ChatOut {
+        Said { from: String, text: String },
+    }
+
+    struct Chat;
+
+    impl TypedGateway for Chat {
+        type In = ChatIn;
+        type Out = ChatOut;
+
+        fn on_message<'a>(
+            &'a self,
+            session: &'a TypedSession<'a, ChatOut>,
+            message: ChatIn,
+        ) -> BoxFuture<'a, Result<(), ApiError>> {
+            Box::pin(async move {
+                let ChatIn::Say { text } = message;
+                if text.is_empty() {
+                    return Err(ApiError::unprocessable("Empty message").with_code("empty"));
+                }
+                if text == "crash" {
+                    return Err(ApiError::internal("pool db-7 exhausted"));
+                }
+                let from = session.principal().map_or("guest", Principal::subject);
+                session.send(&ChatOut::Said {
+                    from: from.to_string(),
+                    text,
+                });
+                Ok(())
+            })
+        }
+    }
+
+    #[websocket("/chat")]
+    async fn chat() -> Chat {
+        Chat
+    }
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let message = ChatIn::Say {
+            text: String::from("hi"),
+        };
+        for codec in [Codec::Json, Codec::MessagePack] {
+            let frame = WsMessage(&message).encode(codec).unwrap();
+            assert_eq!(
+                WsMessage::<ChatIn>::decode(&frame, codec).unwrap().0,
+                message
+            );
+        }
+        let frame = Message::Text(r#"{"type":"say","text":"hi"}"#.into());
+        assert!(WsMessage::<ChatIn>::decode(&frame, Codec::MessagePack).is_err());
+    }
+
+    type Client = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    // send a text frame and parse the JSON reply
+    async fn exchange(socket: &mut Client, frame: &str) -> serde_json::Value {
+        socket
+            .send(tungstenite::Message::text(frame))
+            .await
+            .unwrap();
+        let reply = socket.next().await.unwrap().unwrap().into_text().unwrap();
+        serde_json::from_str(reply.as_str()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_typed_messages_and_error_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = App::new().route(__chat_route, get(chat)).build();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/chat", addr))
+            .await
+            .unwrap();
+
+        let reply = exchange(&mut socket, r#"{"type":"say","text":"hi"}"#).await;
+        assert_eq!(
+            reply,
+            serde_json::json!({ "type": "said", "from": "guest", "text": "hi" })
+        );
+        let reply = exchange(&mut socket, r#"{"type":"shout"}"#).await;
+        assert_eq!(reply["error"]["code"], "invalid_message");
+        let reply = exchange(&mut socket, r#"{"type":"say","text":""}"#).await;
+        assert_eq!(reply["error"]["code"], "empty");
+    }
+
+    #[tokio::test]
+    async fn test_server_error_frames_follow_the_profile() {
+        for (profile, message) in [
+            (Profile::Prod, "Internal Server Error"),
+            (Profile::Dev, "pool db-7 exhausted"),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = App::new()
+                .route(__chat_route, get(chat))
+                .with_profile(profile)
+                .build();
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/chat", addr))
+                .await
+                .unwrap();
+
+            let reply = exchange(&mut socket, r#"{"type":"say","text":"crash"}"#).await;
+            assert_eq!(reply["error"]["status"], 500);
+            assert_eq!(reply["error"]["message"], message, "{:?}", profile);
+        }
+    }
+
+    // an app serving `echo` with `sockets`, and the URL of the route
+    async fn serve_with(sockets: WebSockets) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = App::new()
+            .websockets(sockets)
+            .route(__echo_route, get(echo))
+            .build();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("ws://{}/echo?access_token=secret", addr)
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_keeps_connection_alive() {
+        let sockets = WebSockets::new()
+            .ping_interval(Duration::from_millis(40))
+            .idle_timeout(Duration::from_millis(200));
+        let url = serve_with(sockets.clone()).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+
+        // reading answers the pings, which keeps the connection alive
+        let mut pings = 0;
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(400) {
+            if let tungstenite::Message::Ping(_) = socket.next().await.unwrap().unwrap() {
+                pings += 1;
+            }
+        }
+        assert!(pings >= 5, "{} pings", pings);
+        assert_eq!(sockets.active(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_eviction() {
+        let sockets = WebSockets::new()
+            .ping_interval(None)
+            .idle_timeout(Duration::from_millis(100));
+        let url = serve_with(sockets.clone()).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        socket.next().await.unwrap().unwrap();
+
+        let close = match socket.next().await {
+            Some(Ok(tungstenite::Message::Close(frame))) => frame.unwrap(),
+            other => panic!("expected a close frame, got {:?}", other),
+        };
+        assert_eq!(u16::from(close.code), close_code::AWAY);
+        assert_eq!(close.reason.as_str(), "idle timeout");
+        while sockets.active() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumers_are_disconnected() {
+        let metrics = Metrics::new();
+        let sockets = WebSockets::new().send_buffer(1).metrics(metrics.clone());
+        let url = serve_with(sockets.clone()).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        socket.next().await.unwrap().unwrap();
+        socket
+            .send(tungstenite::Message::text("flood"))
+            .await
+            .unwrap();
+        while let Some(Ok(_)) = socket.next().await {}
+        while sockets.active() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            metrics.counter(
+                "websocket_disconnects_total",
+                &[("reason", "slow_consumer")]
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn test_unidentified_clients_share_a_limit() {
+        let sockets = WebSockets::new().max_connections_per_client(1);
+        let _slot = sockets.admit(UNIDENTIFIED_CLIENT.to_string()).unwrap();
+        let error = sockets.admit(UNIDENTIFIED_CLIENT.to_string()).unwrap_err();
+        assert_eq!(error.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_and_metrics() {
+        let metrics = Metrics::new();
+        let sockets = WebSockets::new()
+            .max_connections_per_client(1)
+            .metrics(metrics.clone());
+        let url = serve_with(sockets.clone()).await;
+
+        let (mut first, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        first.next().await.unwrap().unwrap();
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), 429),
+            other => panic!("expected a 429, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(
+            metrics.gauge_value("websocket_connections_active", &[]),
+            Some(1.0)
+        );
+
+        // closing frees the slot for the client's next connection
+        first.close(None).await.unwrap();
+        while first.next().await.is_some() {}
+        while sockets.active() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let (mut second, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        second.next().await.unwrap().unwrap();
+        assert_eq!(
+            metrics.counter("websocket_disconnects_total", &[("reason", "client")]),
+            1
+        );
+    }
+
+    struct Farewell;
+
+    impl Gateway for Farewell {
+        fn on_message<'a>(&'a self, _: &'a Session, _: Message) -> BoxFuture<'a, ()> {
+            Box::pin(async {})
+        }
+
+        fn on_shutdown<'a>(&'a self, session: &'a Session) -> BoxFuture<'a, ()> {
+            session.send("bye");
+            Box::pin(async {})
+        }
+    }
+
+    #[websocket("/farewell")]
+    async fn farewell() -> Farewell {
+        Farewell
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let metrics = Metrics::new();
+        let sockets = WebSockets::new()
+            .shutdown_close(close_code::RESTART, "restarting")
+            .drain_timeout(Duration::from_secs(5))
+            .metrics(metrics.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/farewell", listener.local_addr().unwrap());
+        let app = App::new()
+            .websockets(sockets.clone())
+            .route(__farewell_route, get(farewell))
+            .build();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = crate::server::RustAPI::new(app).shutdown_signal(async {
+            let _ = stop_rx.await;
+        });
+        let handle = tokio::spawn(server.serve_with_listener(listener));
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        while sockets.active() == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        stop_tx.send(()).unwrap();
+
+        let bye = socket.next().await.unwrap().unwrap();
+        assert_eq!(bye.into_text().unwrap().as_str(), "bye");
+        match socket.next().await {
+            Some(Ok(tungstenite::Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), close_code::RESTART);
+                assert_eq!(frame.reason.as_str(), "restarting");
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+        // the server returns once the client has answered the close frame
+        while socket.next().await.is_some() {}
+        handle.await.unwrap().unwrap();
+        assert_eq!(sockets.active(), 0);
+        assert_eq!(
+            metrics.counter("websocket_disconnects_total", &[("reason", "shutdown")]),
+            1
+        );
+
+        // new upgrades are refused once shutdown began
+        let error = sockets.admit(UNIDENTIFIED_CLIENT.to_string()).unwrap_err();
+        assert_eq!(error.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
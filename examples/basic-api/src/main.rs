@@ -20,7 +20,7 @@ async fn root() -> &'static str {
 /// Main entry point for the rust_api REST API server.
 /// Demonstrates FastAPI-style routing with decorator macros and dependency
 /// injection.
-#[tokio::main]
+#[rust_api::main]
 async fn main() {
     initialize_tracing();
     let container = setup_container();